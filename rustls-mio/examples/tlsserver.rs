@@ -577,7 +577,11 @@ fn make_config(args: &Args) -> Arc<rustls::ServerConfig> {
     let suites = if !args.flag_suite.is_empty() {
         lookup_suites(&args.flag_suite)
     } else {
-        rustls::ALL_CIPHERSUITES.to_vec()
+        rustls::ALL_CIPHERSUITES
+            .iter()
+            .copied()
+            .filter(|suite| suite.supported_by(&rustls::crypto::ring::RING))
+            .collect()
     };
 
     let versions = if !args.flag_protover.is_empty() {
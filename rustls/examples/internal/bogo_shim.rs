@@ -7,6 +7,8 @@
 use base64;
 use env_logger;
 use rustls;
+use rustls::crypto::ring::RING;
+use rustls::crypto::CryptoProvider;
 
 use rustls::internal::msgs::enums::ProtocolVersion;
 use rustls::quic;
@@ -49,9 +51,11 @@ struct Options {
     only_write_one_byte_after_handshake: bool,
     only_write_one_byte_after_handshake_on_resume: bool,
     shut_down_after_handshake: bool,
+    key_update: bool,
     check_close_notify: bool,
     host_name: String,
     use_sni: bool,
+    enable_grease: bool,
     send_sct: bool,
     key_file: String,
     cert_file: String,
@@ -69,14 +73,17 @@ struct Options {
     export_keying_material_label: String,
     export_keying_material_context: String,
     export_keying_material_context_used: bool,
+    export_early_keying_material: usize,
     read_size: usize,
     quic_transport_params: Vec<u8>,
     expect_quic_transport_params: Vec<u8>,
     enable_early_data: bool,
+    enable_ed25519: bool,
     expect_ticket_supports_early_data: bool,
     expect_accept_early_data: bool,
     expect_reject_early_data: bool,
     expect_version: u16,
+    install_cert_compression_algs: bool,
 }
 
 impl Options {
@@ -91,12 +98,14 @@ impl Options {
             resume_with_tickets_disabled: false,
             host_name: "example.com".to_string(),
             use_sni: false,
+            enable_grease: false,
             send_sct: false,
             queue_data: false,
             queue_data_on_resume: false,
             only_write_one_byte_after_handshake: false,
             only_write_one_byte_after_handshake_on_resume: false,
             shut_down_after_handshake: false,
+            key_update: false,
             check_close_notify: false,
             require_any_client_cert: false,
             offer_no_client_cas: false,
@@ -116,14 +125,17 @@ impl Options {
             export_keying_material_label: "".to_string(),
             export_keying_material_context: "".to_string(),
             export_keying_material_context_used: false,
+            export_early_keying_material: 0,
             read_size: 512,
             quic_transport_params: vec![],
             expect_quic_transport_params: vec![],
             enable_early_data: false,
+            enable_ed25519: false,
             expect_ticket_supports_early_data: false,
             expect_accept_early_data: false,
             expect_reject_early_data: false,
             expect_version: 0,
+            install_cert_compression_algs: false,
         }
     }
 
@@ -217,6 +229,76 @@ impl rustls::ClientCertVerifier for DummyClientAuth {
     }
 }
 
+// The test-only certificate compression algorithms installed by
+// `-install-cert-compression-algs`.  These must match the runner's.
+struct ExpandingCertCompression;
+
+const EXPANDING_PREFIX: [u8; 4] = [1, 2, 3, 4];
+
+impl rustls::CertificateCompressor for ExpandingCertCompression {
+    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+        rustls::CertificateCompressionAlgorithm::Unknown(0xff02)
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, rustls::CompressionFailed> {
+        let mut out = EXPANDING_PREFIX.to_vec();
+        out.extend_from_slice(input);
+        Ok(out)
+    }
+}
+
+impl rustls::CertificateDecompressor for ExpandingCertCompression {
+    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+        rustls::CertificateCompressionAlgorithm::Unknown(0xff02)
+    }
+
+    fn decompress(
+        &self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), rustls::DecompressionFailed> {
+        if !input.starts_with(&EXPANDING_PREFIX) || input.len() - 4 != output.len() {
+            return Err(rustls::DecompressionFailed);
+        }
+        output.copy_from_slice(&input[4..]);
+        Ok(())
+    }
+}
+
+struct ShrinkingCertCompression;
+
+impl rustls::CertificateCompressor for ShrinkingCertCompression {
+    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+        rustls::CertificateCompressionAlgorithm::Unknown(0xff01)
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, rustls::CompressionFailed> {
+        if !input.starts_with(&[0, 0]) {
+            return Err(rustls::CompressionFailed);
+        }
+        Ok(input[2..].to_vec())
+    }
+}
+
+impl rustls::CertificateDecompressor for ShrinkingCertCompression {
+    fn algorithm(&self) -> rustls::CertificateCompressionAlgorithm {
+        rustls::CertificateCompressionAlgorithm::Unknown(0xff01)
+    }
+
+    fn decompress(
+        &self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), rustls::DecompressionFailed> {
+        if input.len() + 2 != output.len() {
+            return Err(rustls::DecompressionFailed);
+        }
+        output[..2].copy_from_slice(&[0, 0]);
+        output[2..].copy_from_slice(input);
+        Ok(())
+    }
+}
+
 struct DummyServerAuth {
     send_sct: bool,
 }
@@ -316,8 +398,8 @@ fn lookup_scheme(scheme: u16) -> rustls::SignatureScheme {
         0x0805 => rustls::SignatureScheme::RSA_PSS_SHA384,
         0x0806 => rustls::SignatureScheme::RSA_PSS_SHA512,
         0x0807 => rustls::SignatureScheme::ED25519,
-        // TODO: add support for Ed448
-        // 0x0808 => rustls::SignatureScheme::ED448,
+        #[cfg(feature = "curve448")]
+        0x0808 => rustls::SignatureScheme::ED448,
         _ => {
             println_err!("Unsupported signature scheme {:04x}", scheme);
             process::exit(BOGO_NACK);
@@ -326,15 +408,31 @@ fn lookup_scheme(scheme: u16) -> rustls::SignatureScheme {
 }
 
 fn lookup_kx_group(group: u16) -> &'static rustls::SupportedKxGroup {
-    match group {
+    let found: &'static rustls::SupportedKxGroup = match group {
         0x001d => &rustls::kx_group::X25519,
         0x0017 => &rustls::kx_group::SECP256R1,
         0x0018 => &rustls::kx_group::SECP384R1,
+        #[cfg(feature = "secp521r1")]
+        0x0019 => &rustls::kx_group::SECP521R1,
+        #[cfg(feature = "mlkem")]
+        0x11ec => &rustls::kx_group::X25519MLKEM768,
+        #[cfg(feature = "curve448")]
+        0x001e => &rustls::kx_group::X448,
         _ => {
             println_err!("Unsupported kx group {:04x}", group);
             process::exit(BOGO_NACK);
         }
+    };
+
+    if !RING
+        .kx_groups()
+        .iter()
+        .any(|g| g.name == found.name)
+    {
+        println_err!("Unsupported kx group {:04x}", group);
+        process::exit(BOGO_NACK);
     }
+    found
 }
 
 fn make_server_cfg(opts: &Options) -> Arc<rustls::ServerConfig> {
@@ -356,7 +454,7 @@ fn make_server_cfg(opts: &Options) -> Arc<rustls::ServerConfig> {
             .map(|curveid| lookup_kx_group(*curveid))
             .collect()
     } else {
-        rustls::ALL_KX_GROUPS.to_vec()
+        RING.kx_groups().to_vec()
     };
 
     let mut cfg = rustls::config_builder()
@@ -377,6 +475,17 @@ fn make_server_cfg(opts: &Options) -> Arc<rustls::ServerConfig> {
     cfg.session_storage = rustls::ServerSessionMemoryCache::new(32);
     cfg.max_fragment_size = opts.max_fragment;
 
+    if opts.install_cert_compression_algs {
+        cfg.cert_compressors = vec![
+            Arc::new(ExpandingCertCompression),
+            Arc::new(ShrinkingCertCompression),
+        ];
+        cfg.cert_decompressors = vec![
+            Arc::new(ExpandingCertCompression),
+            Arc::new(ShrinkingCertCompression),
+        ];
+    }
+
     if opts.use_signing_scheme > 0 {
         let scheme = lookup_scheme(opts.use_signing_scheme);
         cfg.cert_resolver = Arc::new(FixedSignatureSchemeServerCertResolver {
@@ -433,13 +542,27 @@ fn make_client_cfg(opts: &Options) -> Arc<rustls::ClientConfig> {
             .map(|curveid| lookup_kx_group(*curveid))
             .collect()
     } else {
-        rustls::ALL_KX_GROUPS.to_vec()
+        RING.kx_groups().to_vec()
     };
 
+    // BoringSSL only accepts Ed25519 signatures when asked to, but will
+    // always sign with an Ed25519 key.  We have one list for both.
+    let signature_schemes = RING
+        .signature_schemes()
+        .iter()
+        .filter(|scheme| {
+            opts.enable_ed25519
+                || !opts.key_file.is_empty()
+                || **scheme != rustls::SignatureScheme::ED25519
+        })
+        .copied()
+        .collect::<Vec<_>>();
+
     let cfg = rustls::config_builder()
         .with_safe_default_cipher_suites()
         .with_kx_groups(&kx_groups)
         .with_protocol_versions(&opts.supported_versions())
+        .with_signature_schemes(&signature_schemes)
         .for_client()
         .expect("inconsistent settings")
         .with_custom_certificate_verifier(Arc::new(DummyServerAuth {
@@ -465,8 +588,20 @@ fn make_client_cfg(opts: &Options) -> Arc<rustls::ClientConfig> {
     let persist = ClientCacheWithoutKxHints::new();
     cfg.session_storage = persist;
     cfg.enable_sni = opts.use_sni;
+    cfg.enable_grease = opts.enable_grease;
     cfg.max_fragment_size = opts.max_fragment;
 
+    if opts.install_cert_compression_algs {
+        cfg.cert_compressors = vec![
+            Arc::new(ExpandingCertCompression),
+            Arc::new(ShrinkingCertCompression),
+        ];
+        cfg.cert_decompressors = vec![
+            Arc::new(ExpandingCertCompression),
+            Arc::new(ShrinkingCertCompression),
+        ];
+    }
+
     if !opts.protocols.is_empty() {
         cfg.alpn_protocols = opts
             .protocols
@@ -535,6 +670,10 @@ fn handle_err(err: rustls::Error) -> ! {
             quit(":WRONG_SIGNATURE_TYPE:")
         }
         Error::PeerSentOversizedRecord => quit(":DATA_LENGTH_TOO_LONG:"),
+        Error::PeerSentTooManyKeyUpdates => quit(":TOO_MANY_KEY_UPDATES:"),
+        Error::PeerSentTooManyWarningAlerts => quit(":TOO_MANY_WARNING_ALERTS:"),
+        Error::PeerSentTooManyEmptyRecords => quit(":TOO_MANY_EMPTY_FRAGMENTS:"),
+        Error::CertificateDecompressionFailed => quit(":CERT_DECOMPRESSION_FAILED:"),
         _ => {
             println_err!("unhandled error: {:?}", err);
             quit(":FIXME:")
@@ -592,6 +731,30 @@ impl ClientOrServer {
 fn exec(opts: &Options, mut sess: ClientOrServer, count: usize) {
     let mut sent_message = false;
 
+    if opts.export_early_keying_material > 0 && count > 0 {
+        let mut export = vec![0u8; opts.export_early_keying_material];
+        sess.client()
+            .export_early_keying_material(
+                &mut export,
+                opts.export_keying_material_label
+                    .as_bytes(),
+                if opts.export_keying_material_context_used {
+                    Some(
+                        opts.export_keying_material_context
+                            .as_bytes(),
+                    )
+                } else {
+                    None
+                },
+            )
+            .unwrap();
+        sess.client()
+            .early_data()
+            .expect("0rtt not available")
+            .write_all(&export)
+            .unwrap();
+    }
+
     if opts.queue_data || (opts.queue_data_on_resume && count > 0) {
         if count > 0 && opts.enable_early_data {
             let len = sess
@@ -616,6 +779,7 @@ fn exec(opts: &Options, mut sess: ClientOrServer, count: usize) {
     ];
     let mut conn = net::TcpStream::connect(&addrs[..]).expect("cannot connect");
     let mut sent_shutdown = false;
+    let mut sent_key_update = false;
     let mut seen_eof = false;
     let mut sent_exporter = false;
     let mut quench_writes = false;
@@ -733,6 +897,12 @@ fn exec(opts: &Options, mut sess: ClientOrServer, count: usize) {
             Err(err) => panic!("unhandled read error {:?}", err),
         };
 
+        if opts.key_update && !sent_key_update && !sess.is_handshaking() {
+            sess.refresh_traffic_keys(false)
+                .unwrap();
+            sent_key_update = true;
+        }
+
         if opts.shut_down_after_handshake && !sent_shutdown && !sess.is_handshaking() {
             sess.send_close_notify();
             sent_shutdown = true;
@@ -814,6 +984,9 @@ fn main() {
                     process::exit(BOGO_NACK);
                 }
             }
+            "-enable-ed25519" => {
+                opts.enable_ed25519 = true;
+            }
             "-no-ticket" => {
                 opts.tickets = false;
             }
@@ -853,11 +1026,9 @@ fn main() {
 
             "-expect-secure-renegotiation" |
             "-expect-no-session-id" |
-            "-enable-ed25519" |
             "-expect-hrr" |
             "-expect-no-hrr" |
             "-on-resume-expect-no-offer-early-data" |
-            "-key-update" | //< we could implement an API for this
             "-expect-tls13-downgrade" |
             "-expect-session-id" => {
                 println!("not checking {}; NYI", arg);
@@ -866,13 +1037,20 @@ fn main() {
             "-export-keying-material" => {
                 opts.export_keying_material = args.remove(0).parse::<usize>().unwrap();
             }
-            "-export-label" => {
+            "-export-early-keying-material" |
+            "-on-resume-export-early-keying-material" => {
+                opts.export_early_keying_material = args.remove(0).parse::<usize>().unwrap();
+            }
+            "-export-label" |
+            "-on-resume-export-label" => {
                 opts.export_keying_material_label = args.remove(0);
             }
-            "-export-context" => {
+            "-export-context" |
+            "-on-resume-export-context" => {
                 opts.export_keying_material_context = args.remove(0);
             }
-            "-use-export-context" => {
+            "-use-export-context" |
+            "-on-resume-use-export-context" => {
                 opts.export_keying_material_context_used = true;
             }
             "-quic-transport-params" => {
@@ -914,6 +1092,9 @@ fn main() {
                 opts.queue_data = true;
                 opts.only_write_one_byte_after_handshake = true;
             }
+            "-key-update" => {
+                opts.key_update = true;
+            }
             "-shim-shuts-down" => {
                 opts.shut_down_after_handshake = true;
             }
@@ -971,6 +1152,12 @@ fn main() {
             "-expect-version" => {
                 opts.expect_version = args.remove(0).parse::<u16>().unwrap();
             }
+            "-install-cert-compression-algs" => {
+                opts.install_cert_compression_algs = true;
+            }
+            "-enable-grease" => {
+                opts.enable_grease = true;
+            }
             "-curves" => {
                 let curve = args.remove(0).parse::<u16>().unwrap();
                 if let Some(mut curves) = opts.curves.take() {
@@ -1024,7 +1211,6 @@ fn main() {
             "-enable-client-custom-extension" |
             "-expect-dhe-group-size" |
             "-use-ticket-callback" |
-            "-enable-grease" |
             "-enable-channel-id" |
             "-resumption-delay" |
             "-expect-early-data-info" |
@@ -1035,8 +1221,6 @@ fn main() {
             "-allow-unknown-alpn-protos" |
             "-on-initial-tls13-variant" |
             "-on-initial-expect-curve-id" |
-            "-on-resume-export-early-keying-material" |
-            "-export-early-keying-material" |
             "-handshake-twice" |
             "-on-resume-verify-fail" |
             "-reverify-on-resume" |
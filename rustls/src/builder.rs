@@ -1,8 +1,12 @@
 use crate::client::builder::ConfigWantsServerVerifier;
+use crate::crypto::{self, CryptoProvider};
 use crate::error::Error;
-use crate::kx::{SupportedKxGroup, ALL_KX_GROUPS};
+use crate::kx::SupportedKxGroup;
+use crate::msgs::enums::SignatureScheme;
 use crate::server::builder::ConfigWantsClientVerifier;
+use crate::sign;
 use crate::suites::{SupportedCipherSuite, DEFAULT_CIPHERSUITES};
+use crate::verify::{RevocationPolicy, DEFAULT_OCSP_MAX_AGE};
 use crate::versions;
 
 /// Building a [`ServerConfig`] or [`ClientConfig`] in a linker-friendly way.
@@ -82,13 +86,33 @@ use crate::versions;
 ///    [`ConfigWantsVersions::with_safe_default_protocol_versions()`].
 /// 4. You now need to indicate whether to make a [`ServerConfig`] or [`ClientConfig`],
 ///    by calling [`ConfigWantsPeerType::for_server()`]
-///    or [`ConfigWantsPeerType::for_client()`] respectively.
+///    or [`ConfigWantsPeerType::for_client()`] respectively.  Before this,
+///    you may restrict the signature schemes in use by calling
+///    [`ConfigWantsPeerType::with_signature_schemes()`].
 /// 5. Now see [`ConfigWantsServerVerifier`] or [`ConfigWantsClientVerifier`] for further steps.
 ///
+/// All cryptography is done by the default provider, [`crypto::ring::RING`].
+/// Use [`config_builder_with_provider()`] to choose another.
+///
 /// [`ServerConfig`]: crate::ServerConfig
 /// [`ClientConfig`]: crate::ClientConfig
 pub fn config_builder() -> ConfigWantsCipherSuites {
-    ConfigWantsCipherSuites {}
+    config_builder_with_provider(&crypto::ring::RING)
+}
+
+/// Start building a [`ServerConfig`] or [`ClientConfig`] which does
+/// its cryptography with `provider`.
+///
+/// This is otherwise the same as [`config_builder()`].  The defaults
+/// chosen later are limited to what `provider` supports, and choosing
+/// anything it does not support is an error.
+///
+/// [`ServerConfig`]: crate::ServerConfig
+/// [`ClientConfig`]: crate::ClientConfig
+pub fn config_builder_with_provider(
+    provider: &'static dyn CryptoProvider,
+) -> ConfigWantsCipherSuites {
+    ConfigWantsCipherSuites { provider }
 }
 
 fn config_builder_with_safe_defaults() -> ConfigWantsPeerType {
@@ -127,7 +151,9 @@ pub fn server_config_builder_with_safe_defaults() -> ConfigWantsClientVerifier {
 }
 
 /// A config builder where we want to know the cipher suites.
-pub struct ConfigWantsCipherSuites;
+pub struct ConfigWantsCipherSuites {
+    provider: &'static dyn CryptoProvider,
+}
 
 impl ConfigWantsCipherSuites {
     /// Choose a specific set of cipher suites.
@@ -136,6 +162,7 @@ impl ConfigWantsCipherSuites {
         cipher_suites: &[SupportedCipherSuite],
     ) -> ConfigWantsKxGroups {
         ConfigWantsKxGroups {
+            provider: self.provider,
             cipher_suites: cipher_suites.to_vec(),
         }
     }
@@ -144,14 +171,20 @@ impl ConfigWantsCipherSuites {
     ///
     /// Note that this default provides only high-quality suites: there is no need
     /// to filter out low-, export- or NULL-strength cipher suites: rustls does not
-    /// implement these.
+    /// implement these.  Suites the crypto provider cannot do are left out.
     pub fn with_safe_default_cipher_suites(&self) -> ConfigWantsKxGroups {
-        self.with_cipher_suites(DEFAULT_CIPHERSUITES)
+        let cipher_suites = DEFAULT_CIPHERSUITES
+            .iter()
+            .filter(|suite| suite.supported_by(self.provider))
+            .copied()
+            .collect::<Vec<_>>();
+        self.with_cipher_suites(&cipher_suites)
     }
 }
 
 /// A config builder where we want to know which key exchange groups to use.
 pub struct ConfigWantsKxGroups {
+    provider: &'static dyn CryptoProvider,
     cipher_suites: Vec<SupportedCipherSuite>,
 }
 
@@ -159,6 +192,7 @@ impl ConfigWantsKxGroups {
     /// Choose a specific set of key exchange groups.
     pub fn with_kx_groups(self, kx_groups: &[&'static SupportedKxGroup]) -> ConfigWantsVersions {
         ConfigWantsVersions {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: kx_groups.to_vec(),
         }
@@ -167,13 +201,16 @@ impl ConfigWantsKxGroups {
     /// Choose the default set of key exchange groups.
     ///
     /// This is a safe default: rustls doesn't implement any poor-quality groups.
+    /// It is every group the crypto provider supports.
     pub fn with_safe_default_kx_groups(self) -> ConfigWantsVersions {
-        self.with_kx_groups(&ALL_KX_GROUPS)
+        let provider = self.provider;
+        self.with_kx_groups(provider.kx_groups())
     }
 }
 
 /// A config builder where we want to know the TLS versions.
 pub struct ConfigWantsVersions {
+    provider: &'static dyn CryptoProvider,
     cipher_suites: Vec<SupportedCipherSuite>,
     kx_groups: Vec<&'static SupportedKxGroup>,
 }
@@ -190,21 +227,41 @@ impl ConfigWantsVersions {
         versions: &[&'static versions::SupportedProtocolVersion],
     ) -> ConfigWantsPeerType {
         ConfigWantsPeerType {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             versions: versions::EnabledVersions::new(versions),
+            signature_schemes: self
+                .provider
+                .signature_schemes()
+                .to_vec(),
         }
     }
 }
 
 /// A config builder where we want to know whether this will be a client or a server.
 pub struct ConfigWantsPeerType {
+    provider: &'static dyn CryptoProvider,
     cipher_suites: Vec<SupportedCipherSuite>,
     kx_groups: Vec<&'static SupportedKxGroup>,
     versions: versions::EnabledVersions,
+    signature_schemes: Vec<SignatureScheme>,
 }
 
 impl ConfigWantsPeerType {
+    /// Choose the signature schemes to use, in preference order.  These
+    /// are the only schemes advertised to the peer, accepted in its
+    /// signatures, and used to make our own signatures.
+    ///
+    /// The default is every scheme the crypto provider supports, and
+    /// only those can be chosen.  Every enabled cipher suite must be
+    /// usable with at least one of these schemes, which is checked by
+    /// `for_client()` and `for_server()`.
+    pub fn with_signature_schemes(mut self, signature_schemes: &[SignatureScheme]) -> Self {
+        self.signature_schemes = signature_schemes.to_vec();
+        self
+    }
+
     fn validate(&self) -> Result<(), Error> {
         let mut any_usable_suite = false;
         for suite in &self.cipher_suites {
@@ -225,6 +282,65 @@ impl ConfigWantsPeerType {
             return Err(Error::General("no kx groups configured".into()));
         }
 
+        if let Some(suite) = self
+            .cipher_suites
+            .iter()
+            .find(|suite| !suite.supported_by(self.provider))
+        {
+            return Err(Error::General(format!(
+                "{:?} not supported by the crypto provider",
+                suite.suite()
+            )));
+        }
+
+        let provider_groups = self.provider.kx_groups();
+        if let Some(group) = self.kx_groups.iter().find(|group| {
+            !provider_groups
+                .iter()
+                .any(|g| g.name == group.name)
+        }) {
+            return Err(Error::General(format!(
+                "{:?} not supported by the crypto provider",
+                group.name
+            )));
+        }
+
+        if self.signature_schemes.is_empty() {
+            return Err(Error::General("no signature schemes configured".into()));
+        }
+
+        if let Some(scheme) = self
+            .signature_schemes
+            .iter()
+            .find(|scheme| {
+                !self
+                    .provider
+                    .signature_schemes()
+                    .contains(scheme)
+            })
+        {
+            return Err(Error::General(format!(
+                "{:?} not supported by the crypto provider",
+                scheme
+            )));
+        }
+
+        for suite in &self.cipher_suites {
+            if !self
+                .versions
+                .contains(suite.version().version)
+            {
+                continue;
+            }
+
+            if usable_signature_schemes(*suite, &self.signature_schemes).is_empty() {
+                return Err(Error::General(format!(
+                    "no signature schemes configured for {:?}",
+                    suite.suite()
+                )));
+            }
+        }
+
         Ok(())
     }
 
@@ -235,9 +351,13 @@ impl ConfigWantsPeerType {
     pub fn for_client(self) -> Result<ConfigWantsServerVerifier, Error> {
         self.validate()?;
         Ok(ConfigWantsServerVerifier {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             versions: self.versions,
+            signature_schemes: self.signature_schemes,
+            revocation_policy: RevocationPolicy::default(),
+            ocsp_max_age: DEFAULT_OCSP_MAX_AGE,
         })
     }
 
@@ -248,9 +368,53 @@ impl ConfigWantsPeerType {
     pub fn for_server(self) -> Result<ConfigWantsClientVerifier, Error> {
         self.validate()?;
         Ok(ConfigWantsClientVerifier {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             versions: self.versions,
+            signature_schemes: self.signature_schemes,
         })
     }
 }
+
+/// Return the members of `signature_schemes` which can authenticate a
+/// handshake using `suite`.
+fn usable_signature_schemes(
+    suite: SupportedCipherSuite,
+    signature_schemes: &[SignatureScheme],
+) -> Vec<SignatureScheme> {
+    let allowed = match suite {
+        SupportedCipherSuite::Tls12(inner) => inner.sign,
+        SupportedCipherSuite::Tls13(_) => sign::supported_sign_tls13(),
+    };
+
+    signature_schemes
+        .iter()
+        .filter(|scheme| allowed.contains(scheme))
+        .copied()
+        .collect()
+}
+
+/// Check that `key` can make a signature with one of the
+/// `signature_schemes`, with at least one of the enabled `cipher_suites`.
+pub(crate) fn validate_signing_key(
+    key: &dyn sign::SigningKey,
+    cipher_suites: &[SupportedCipherSuite],
+    versions: &versions::EnabledVersions,
+    signature_schemes: &[SignatureScheme],
+) -> Result<(), Error> {
+    let usable = cipher_suites
+        .iter()
+        .filter(|suite| versions.contains(suite.version().version))
+        .any(|suite| {
+            key.choose_scheme(&usable_signature_schemes(*suite, signature_schemes))
+                .is_some()
+        });
+
+    match usable {
+        true => Ok(()),
+        false => Err(Error::General(
+            "private key cannot sign with any enabled signature scheme".into(),
+        )),
+    }
+}
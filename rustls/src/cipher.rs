@@ -1,15 +1,17 @@
 use crate::conn::ConnectionSecrets;
+use crate::crypto::{AeadKey, CryptoProvider, NONCE_LEN};
 use crate::error::Error;
-use crate::key_schedule::{derive_traffic_iv, derive_traffic_key};
+use crate::key_schedule::{derive_traffic_iv, derive_traffic_key, Prk};
 use crate::msgs::base::Payload;
 use crate::msgs::codec;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::{ContentType, ProtocolVersion};
 use crate::msgs::fragmenter::MAX_FRAGMENT_LEN;
 use crate::msgs::message::{BorrowedPlainMessage, OpaqueMessage, PlainMessage};
-use crate::suites::Tls13CipherSuite;
+use crate::suites::{BulkAlgorithm, Tls13CipherSuite};
 
-use ring::{aead, hkdf};
+#[cfg(feature = "quic")]
+use ring::aead;
 
 /// Objects with this trait can decrypt TLS messages.
 pub(crate) trait MessageDecrypter: Send + Sync {
@@ -41,13 +43,13 @@ fn make_tls12_aad(
     typ: ContentType,
     vers: ProtocolVersion,
     len: usize,
-) -> ring::aead::Aad<[u8; TLS12_AAD_SIZE]> {
+) -> [u8; TLS12_AAD_SIZE] {
     let mut out = [0; TLS12_AAD_SIZE];
     codec::put_u64(seq, &mut out[0..]);
     out[8] = typ.get_u8();
     codec::put_u16(vers.get_u16(), &mut out[9..]);
     codec::put_u16(len as u16, &mut out[11..]);
-    ring::aead::Aad::from(out)
+    out
 }
 
 fn make_tls12_gcm_nonce(write_iv: &[u8], explicit: &[u8]) -> Iv {
@@ -70,13 +72,13 @@ fn make_tls12_gcm_nonce(write_iv: &[u8], explicit: &[u8]) -> Iv {
 pub(crate) struct AesGcm;
 
 impl Tls12AeadAlgorithm for AesGcm {
-    fn decrypter(&self, key: aead::LessSafeKey, iv: &[u8]) -> Box<dyn MessageDecrypter> {
+    fn decrypter(&self, key: Box<dyn AeadKey>, iv: &[u8]) -> Box<dyn MessageDecrypter> {
         Box::new(GcmMessageDecrypter::new(key, iv))
     }
 
     fn encrypter(
         &self,
-        key: aead::LessSafeKey,
+        key: Box<dyn AeadKey>,
         iv: &[u8],
         extra: &[u8],
     ) -> Box<dyn MessageEncrypter> {
@@ -85,23 +87,42 @@ impl Tls12AeadAlgorithm for AesGcm {
     }
 }
 
+/// AES-CCM records are laid out, and their nonces made, just as for
+/// AES-GCM (RFC6655 section 3); only the tag length can differ.
+pub(crate) struct AesCcm;
+
+impl Tls12AeadAlgorithm for AesCcm {
+    fn decrypter(&self, key: Box<dyn AeadKey>, iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        AesGcm.decrypter(key, iv)
+    }
+
+    fn encrypter(
+        &self,
+        key: Box<dyn AeadKey>,
+        iv: &[u8],
+        extra: &[u8],
+    ) -> Box<dyn MessageEncrypter> {
+        AesGcm.encrypter(key, iv, extra)
+    }
+}
+
 pub(crate) struct ChaCha20Poly1305;
 
 impl Tls12AeadAlgorithm for ChaCha20Poly1305 {
-    fn decrypter(&self, key: aead::LessSafeKey, iv: &[u8]) -> Box<dyn MessageDecrypter> {
+    fn decrypter(&self, key: Box<dyn AeadKey>, iv: &[u8]) -> Box<dyn MessageDecrypter> {
         Box::new(ChaCha20Poly1305MessageDecrypter::new(key, Iv::copy(iv)))
     }
 
-    fn encrypter(&self, key: aead::LessSafeKey, iv: &[u8], _: &[u8]) -> Box<dyn MessageEncrypter> {
+    fn encrypter(&self, key: Box<dyn AeadKey>, iv: &[u8], _: &[u8]) -> Box<dyn MessageEncrypter> {
         Box::new(ChaCha20Poly1305MessageEncrypter::new(key, Iv::copy(iv)))
     }
 }
 
 pub(crate) trait Tls12AeadAlgorithm: Send + Sync + 'static {
-    fn decrypter(&self, key: aead::LessSafeKey, iv: &[u8]) -> Box<dyn MessageDecrypter>;
+    fn decrypter(&self, key: Box<dyn AeadKey>, iv: &[u8]) -> Box<dyn MessageDecrypter>;
     fn encrypter(
         &self,
-        key: aead::LessSafeKey,
+        key: Box<dyn AeadKey>,
         iv: &[u8],
         extra: &[u8],
     ) -> Box<dyn MessageEncrypter>;
@@ -109,16 +130,15 @@ pub(crate) trait Tls12AeadAlgorithm: Send + Sync + 'static {
 
 /// Make a `MessageCipherPair` based on the given supported ciphersuite `scs`,
 /// and the session's `secrets`.
-pub(crate) fn new_tls12(secrets: &ConnectionSecrets) -> MessageCipherPair {
+pub(crate) fn new_tls12(secrets: &ConnectionSecrets) -> Result<MessageCipherPair, Error> {
     fn split_key<'a>(
         key_block: &'a [u8],
-        alg: &'static aead::Algorithm,
-    ) -> (aead::LessSafeKey, &'a [u8]) {
+        provider: &dyn CryptoProvider,
+        alg: &BulkAlgorithm,
+    ) -> Result<(Box<dyn AeadKey>, &'a [u8]), Error> {
         // Might panic if the key block is too small.
         let (key, rest) = key_block.split_at(alg.key_len());
-        // Won't panic because its only prerequisite is that `key` is `alg.key_len()` bytes long.
-        let key = aead::UnboundKey::new(alg, key).unwrap();
-        (aead::LessSafeKey::new(key), rest)
+        Ok((provider.aead_key(alg, key)?, rest))
     }
 
     // Make a key block, and chop it up.
@@ -126,10 +146,10 @@ pub(crate) fn new_tls12(secrets: &ConnectionSecrets) -> MessageCipherPair {
     let key_block = secrets.make_key_block();
 
     let suite = secrets.suite();
-    let scs = &suite.common;
+    let provider = secrets.provider();
 
-    let (client_write_key, key_block) = split_key(&key_block, scs.aead_algorithm);
-    let (server_write_key, key_block) = split_key(key_block, scs.aead_algorithm);
+    let (client_write_key, key_block) = split_key(&key_block, provider, &suite.bulk)?;
+    let (server_write_key, key_block) = split_key(key_block, provider, &suite.bulk)?;
     let (client_write_iv, key_block) = key_block.split_at(suite.fixed_iv_len);
     let (server_write_iv, extra) = key_block.split_at(suite.fixed_iv_len);
 
@@ -149,72 +169,67 @@ pub(crate) fn new_tls12(secrets: &ConnectionSecrets) -> MessageCipherPair {
         )
     };
 
-    (
+    Ok((
         suite
             .aead_alg
             .decrypter(read_key, read_iv),
         suite
             .aead_alg
             .encrypter(write_key, write_iv, extra),
-    )
+    ))
 }
 
 pub(crate) fn new_tls13_read(
     scs: &'static Tls13CipherSuite,
-    secret: &hkdf::Prk,
-) -> Box<dyn MessageDecrypter> {
-    let key = derive_traffic_key(secret, scs.common.aead_algorithm);
+    secret: &Prk,
+) -> Result<Box<dyn MessageDecrypter>, Error> {
+    let key = derive_traffic_key(secret, scs.aead_alg)?;
     let iv = derive_traffic_iv(secret);
 
-    Box::new(Tls13MessageDecrypter::new(key, iv))
+    Ok(Box::new(Tls13MessageDecrypter::new(key, iv)))
 }
 
 pub(crate) fn new_tls13_write(
     scs: &'static Tls13CipherSuite,
-    secret: &hkdf::Prk,
-) -> Box<dyn MessageEncrypter> {
-    let key = derive_traffic_key(secret, scs.common.aead_algorithm);
+    secret: &Prk,
+) -> Result<Box<dyn MessageEncrypter>, Error> {
+    let key = derive_traffic_key(secret, scs.aead_alg)?;
     let iv = derive_traffic_iv(secret);
 
-    Box::new(Tls13MessageEncrypter::new(key, iv))
+    Ok(Box::new(Tls13MessageEncrypter::new(key, iv)))
 }
 
-/// A `MessageEncrypter` for AES-GCM AEAD ciphersuites. TLS 1.2 only.
+/// A `MessageEncrypter` for AES-GCM and AES-CCM AEAD ciphersuites. TLS 1.2 only.
 struct GcmMessageEncrypter {
-    enc_key: aead::LessSafeKey,
+    enc_key: Box<dyn AeadKey>,
     iv: Iv,
 }
 
-/// A `MessageDecrypter` for AES-GCM AEAD ciphersuites.  TLS1.2 only.
+/// A `MessageDecrypter` for AES-GCM and AES-CCM AEAD ciphersuites.  TLS1.2 only.
 struct GcmMessageDecrypter {
-    dec_key: aead::LessSafeKey,
+    dec_key: Box<dyn AeadKey>,
     dec_salt: [u8; 4],
 }
 
 const GCM_EXPLICIT_NONCE_LEN: usize = 8;
-const GCM_OVERHEAD: usize = GCM_EXPLICIT_NONCE_LEN + 16;
 
 impl MessageDecrypter for GcmMessageDecrypter {
     fn decrypt(&self, mut msg: OpaqueMessage, seq: u64) -> Result<PlainMessage, Error> {
         let payload = &mut msg.payload.0;
-        if payload.len() < GCM_OVERHEAD {
+        let overhead = GCM_EXPLICIT_NONCE_LEN + self.dec_key.tag_len();
+        if payload.len() < overhead {
             return Err(Error::DecryptError);
         }
 
-        let nonce = {
-            let mut nonce = [0u8; 12];
-            nonce[..4].copy_from_slice(&self.dec_salt);
-            nonce[4..].copy_from_slice(&payload[..8]);
-            aead::Nonce::assume_unique_for_key(nonce)
-        };
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..4].copy_from_slice(&self.dec_salt);
+        nonce[4..].copy_from_slice(&payload[..GCM_EXPLICIT_NONCE_LEN]);
 
-        let aad = make_tls12_aad(seq, msg.typ, msg.version, payload.len() - GCM_OVERHEAD);
+        let aad = make_tls12_aad(seq, msg.typ, msg.version, payload.len() - overhead);
 
         let plain_len = self
             .dec_key
-            .open_within(nonce, aad, payload, GCM_EXPLICIT_NONCE_LEN..)
-            .map_err(|_| Error::DecryptError)?
-            .len();
+            .open_in_place(nonce, &aad, payload, GCM_EXPLICIT_NONCE_LEN)?;
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(Error::PeerSentOversizedRecord);
@@ -230,15 +245,13 @@ impl MessageEncrypter for GcmMessageEncrypter {
         let nonce = make_tls13_nonce(&self.iv, seq);
         let aad = make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
 
-        let total_len = msg.payload.len() + self.enc_key.algorithm().tag_len();
+        let total_len = msg.payload.len() + self.enc_key.tag_len();
         let mut payload = Vec::with_capacity(GCM_EXPLICIT_NONCE_LEN + total_len);
-        payload.extend_from_slice(&nonce.as_ref()[4..]);
+        payload.extend_from_slice(&nonce[4..]);
         payload.extend_from_slice(msg.payload);
 
         self.enc_key
-            .seal_in_place_separate_tag(nonce, aad, &mut payload[GCM_EXPLICIT_NONCE_LEN..])
-            .map(|tag| payload.extend(tag.as_ref()))
-            .map_err(|_| Error::General("encrypt failed".to_string()))?;
+            .seal_in_place(nonce, &aad, &mut payload, GCM_EXPLICIT_NONCE_LEN)?;
 
         Ok(OpaqueMessage {
             typ: msg.typ,
@@ -249,13 +262,13 @@ impl MessageEncrypter for GcmMessageEncrypter {
 }
 
 impl GcmMessageEncrypter {
-    fn new(enc_key: aead::LessSafeKey, iv: Iv) -> Self {
+    fn new(enc_key: Box<dyn AeadKey>, iv: Iv) -> Self {
         Self { enc_key, iv }
     }
 }
 
 impl GcmMessageDecrypter {
-    fn new(dec_key: aead::LessSafeKey, dec_iv: &[u8]) -> Self {
+    fn new(dec_key: Box<dyn AeadKey>, dec_iv: &[u8]) -> Self {
         let mut ret = Self {
             dec_key,
             dec_salt: [0u8; 4],
@@ -269,17 +282,17 @@ impl GcmMessageDecrypter {
 
 /// A TLS 1.3 write or read IV.
 #[allow(unreachable_pub)] // Only exposed as part of `crate::quic`
-pub struct Iv([u8; ring::aead::NONCE_LEN]);
+pub struct Iv([u8; NONCE_LEN]);
 
 impl Iv {
-    pub(crate) fn new(value: [u8; ring::aead::NONCE_LEN]) -> Self {
+    pub(crate) fn new(value: [u8; NONCE_LEN]) -> Self {
         Self(value)
     }
 
     /// Compute the nonce to use for encrypting or decrypting `packet_number`
     #[cfg(feature = "quic")]
-    pub fn nonce_for(&self, packet_number: u64) -> ring::aead::Nonce {
-        let mut out = [0; aead::NONCE_LEN];
+    pub fn nonce_for(&self, packet_number: u64) -> aead::Nonce {
+        let mut out = [0; NONCE_LEN];
         out[4..].copy_from_slice(&packet_number.to_be_bytes());
         for (out, inp) in out.iter_mut().zip(self.0.iter()) {
             *out ^= inp;
@@ -288,41 +301,24 @@ impl Iv {
     }
 
     fn copy(value: &[u8]) -> Self {
-        debug_assert_eq!(value.len(), ring::aead::NONCE_LEN);
+        debug_assert_eq!(value.len(), NONCE_LEN);
         let mut iv = Self::new(Default::default());
         iv.0.copy_from_slice(value);
         iv
     }
 
-    #[cfg(test)]
     pub(crate) fn value(&self) -> &[u8; 12] {
         &self.0
     }
 }
 
-pub(crate) struct IvLen;
-
-impl hkdf::KeyType for IvLen {
-    fn len(&self) -> usize {
-        aead::NONCE_LEN
-    }
-}
-
-impl From<hkdf::Okm<'_, IvLen>> for Iv {
-    fn from(okm: hkdf::Okm<IvLen>) -> Self {
-        let mut r = Self(Default::default());
-        okm.fill(&mut r.0[..]).unwrap();
-        r
-    }
-}
-
 struct Tls13MessageEncrypter {
-    enc_key: aead::LessSafeKey,
+    enc_key: Box<dyn AeadKey>,
     iv: Iv,
 }
 
 struct Tls13MessageDecrypter {
-    dec_key: aead::LessSafeKey,
+    dec_key: Box<dyn AeadKey>,
     iv: Iv,
 }
 
@@ -336,8 +332,8 @@ fn unpad_tls13(v: &mut Vec<u8>) -> ContentType {
     }
 }
 
-fn make_tls13_nonce(iv: &Iv, seq: u64) -> ring::aead::Nonce {
-    let mut nonce = [0u8; ring::aead::NONCE_LEN];
+fn make_tls13_nonce(iv: &Iv, seq: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
     codec::put_u64(seq, &mut nonce[4..]);
 
     nonce
@@ -347,24 +343,24 @@ fn make_tls13_nonce(iv: &Iv, seq: u64) -> ring::aead::Nonce {
             *nonce ^= *iv;
         });
 
-    aead::Nonce::assume_unique_for_key(nonce)
+    nonce
 }
 
 // https://datatracker.ietf.org/doc/html/rfc8446#section-5.2
 const TLS13_AAD_SIZE: usize = 1 + 2 + 2;
-fn make_tls13_aad(len: usize) -> ring::aead::Aad<[u8; TLS13_AAD_SIZE]> {
-    ring::aead::Aad::from([
+fn make_tls13_aad(len: usize) -> [u8; TLS13_AAD_SIZE] {
+    [
         0x17, // ContentType::ApplicationData
         0x3,  // ProtocolVersion (major)
         0x3,  // ProtocolVersion (minor)
         (len >> 8) as u8,
         len as u8,
-    ])
+    ]
 }
 
 impl MessageEncrypter for Tls13MessageEncrypter {
     fn encrypt(&self, msg: BorrowedPlainMessage, seq: u64) -> Result<OpaqueMessage, Error> {
-        let total_len = msg.payload.len() + 1 + self.enc_key.algorithm().tag_len();
+        let total_len = msg.payload.len() + 1 + self.enc_key.tag_len();
         let mut payload = Vec::with_capacity(total_len);
         payload.extend_from_slice(msg.payload);
         msg.typ.encode(&mut payload);
@@ -373,8 +369,7 @@ impl MessageEncrypter for Tls13MessageEncrypter {
         let aad = make_tls13_aad(total_len);
 
         self.enc_key
-            .seal_in_place_append_tag(nonce, aad, &mut payload)
-            .map_err(|_| Error::General("encrypt failed".to_string()))?;
+            .seal_in_place(nonce, &aad, &mut payload, 0)?;
 
         Ok(OpaqueMessage {
             typ: ContentType::ApplicationData,
@@ -387,7 +382,7 @@ impl MessageEncrypter for Tls13MessageEncrypter {
 impl MessageDecrypter for Tls13MessageDecrypter {
     fn decrypt(&self, mut msg: OpaqueMessage, seq: u64) -> Result<PlainMessage, Error> {
         let mut payload = &mut msg.payload.0;
-        if payload.len() < self.dec_key.algorithm().tag_len() {
+        if payload.len() < self.dec_key.tag_len() {
             return Err(Error::DecryptError);
         }
 
@@ -395,9 +390,7 @@ impl MessageDecrypter for Tls13MessageDecrypter {
         let aad = make_tls13_aad(payload.len());
         let plain_len = self
             .dec_key
-            .open_in_place(nonce, aad, &mut payload)
-            .map_err(|_| Error::DecryptError)?
-            .len();
+            .open_in_place(nonce, &aad, &mut payload, 0)?;
 
         payload.truncate(plain_len);
 
@@ -421,18 +414,18 @@ impl MessageDecrypter for Tls13MessageDecrypter {
 }
 
 impl Tls13MessageEncrypter {
-    fn new(key: aead::UnboundKey, enc_iv: Iv) -> Self {
+    fn new(enc_key: Box<dyn AeadKey>, enc_iv: Iv) -> Self {
         Self {
-            enc_key: aead::LessSafeKey::new(key),
+            enc_key,
             iv: enc_iv,
         }
     }
 }
 
 impl Tls13MessageDecrypter {
-    fn new(key: aead::UnboundKey, dec_iv: Iv) -> Self {
+    fn new(dec_key: Box<dyn AeadKey>, dec_iv: Iv) -> Self {
         Self {
-            dec_key: aead::LessSafeKey::new(key),
+            dec_key,
             iv: dec_iv,
         }
     }
@@ -442,7 +435,7 @@ impl Tls13MessageDecrypter {
 /// This implementation does the AAD construction required in TLS1.2.
 /// TLS1.3 uses `TLS13MessageEncrypter`.
 struct ChaCha20Poly1305MessageEncrypter {
-    enc_key: aead::LessSafeKey,
+    enc_key: Box<dyn AeadKey>,
     enc_offset: Iv,
 }
 
@@ -450,12 +443,12 @@ struct ChaCha20Poly1305MessageEncrypter {
 /// This implementation does the AAD construction required in TLS1.2.
 /// TLS1.3 uses `TLS13MessageDecrypter`.
 struct ChaCha20Poly1305MessageDecrypter {
-    dec_key: aead::LessSafeKey,
+    dec_key: Box<dyn AeadKey>,
     dec_offset: Iv,
 }
 
 impl ChaCha20Poly1305MessageEncrypter {
-    fn new(enc_key: aead::LessSafeKey, enc_iv: Iv) -> Self {
+    fn new(enc_key: Box<dyn AeadKey>, enc_iv: Iv) -> Self {
         Self {
             enc_key,
             enc_offset: enc_iv,
@@ -464,7 +457,7 @@ impl ChaCha20Poly1305MessageEncrypter {
 }
 
 impl ChaCha20Poly1305MessageDecrypter {
-    fn new(dec_key: aead::LessSafeKey, dec_iv: Iv) -> Self {
+    fn new(dec_key: Box<dyn AeadKey>, dec_iv: Iv) -> Self {
         Self {
             dec_key,
             dec_offset: dec_iv,
@@ -492,9 +485,7 @@ impl MessageDecrypter for ChaCha20Poly1305MessageDecrypter {
 
         let plain_len = self
             .dec_key
-            .open_in_place(nonce, aad, &mut payload)
-            .map_err(|_| Error::DecryptError)?
-            .len();
+            .open_in_place(nonce, &aad, &mut payload, 0)?;
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(Error::PeerSentOversizedRecord);
@@ -510,13 +501,12 @@ impl MessageEncrypter for ChaCha20Poly1305MessageEncrypter {
         let nonce = make_tls13_nonce(&self.enc_offset, seq);
         let aad = make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
 
-        let total_len = msg.payload.len() + self.enc_key.algorithm().tag_len();
+        let total_len = msg.payload.len() + self.enc_key.tag_len();
         let mut buf = Vec::with_capacity(total_len);
         buf.extend_from_slice(msg.payload);
 
         self.enc_key
-            .seal_in_place_append_tag(nonce, aad, &mut buf)
-            .map_err(|_| Error::General("encrypt failed".to_string()))?;
+            .seal_in_place(nonce, &aad, &mut buf, 0)?;
 
         Ok(OpaqueMessage {
             typ: msg.typ,
@@ -1,51 +1,111 @@
 use crate::anchors;
+use crate::builder;
 use crate::client::handy;
 use crate::client::{ClientConfig, ResolvesClientCert};
+use crate::compress;
+use crate::conn::PeerMessageLimits;
+use crate::crypto::CryptoProvider;
 use crate::error::Error;
 use crate::key;
 use crate::keylog::NoKeyLog;
 use crate::kx::SupportedKxGroup;
+use crate::msgs::enums::{PSKKeyExchangeMode, SignatureScheme};
 use crate::suites::SupportedCipherSuite;
 use crate::verify;
 use crate::versions;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 /// A client config in progress, where the next step is to configure how
 /// to validate server certificates (typically with a set root certificates).
 pub struct ConfigWantsServerVerifier {
+    pub(crate) provider: &'static dyn CryptoProvider,
     pub(crate) cipher_suites: Vec<SupportedCipherSuite>,
     pub(crate) kx_groups: Vec<&'static SupportedKxGroup>,
     pub(crate) versions: versions::EnabledVersions,
+    pub(crate) signature_schemes: Vec<SignatureScheme>,
+    pub(crate) revocation_policy: verify::RevocationPolicy,
+    pub(crate) ocsp_max_age: Duration,
 }
 
 impl ConfigWantsServerVerifier {
+    /// Choose how the revocation status of server certificates is checked
+    /// when they are verified using `with_root_certificates`.
+    ///
+    /// The default is `RevocationPolicy::Ignore`.  Other verifiers ignore
+    /// this: a verifier passed to `with_custom_certificate_verifier` must
+    /// check revocation itself (a `WebPkiVerifier` can be given a policy
+    /// with `WebPkiVerifier::with_revocation_policy`).
+    pub fn with_revocation_policy(mut self, policy: verify::RevocationPolicy) -> Self {
+        self.revocation_policy = policy;
+        self
+    }
+
+    /// Choose how long a stapled OCSP response without a nextUpdate
+    /// time is trusted for, when revocation is checked.
+    ///
+    /// The default is `DEFAULT_OCSP_MAX_AGE`.  Like `with_revocation_policy`,
+    /// this only applies to `with_root_certificates`.
+    pub fn with_ocsp_max_age(mut self, max_age: Duration) -> Self {
+        self.ocsp_max_age = max_age;
+        self
+    }
+
     /// Choose how to verify client certificates.
     pub fn with_root_certificates(
         self,
         root_store: anchors::RootCertStore,
         ct_logs: &'static [&'static sct::Log],
     ) -> ConfigWantsClientCert {
-        let verifier = Arc::new(verify::WebPkiVerifier::new(root_store, ct_logs));
+        let verifier = Arc::new(
+            verify::WebPkiVerifier::new(root_store, ct_logs)
+                .with_revocation_policy(self.revocation_policy)
+                .with_ocsp_max_age(self.ocsp_max_age)
+                .with_crypto_provider(self.provider),
+        );
 
         ConfigWantsClientCert {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             versions: self.versions,
+            signature_schemes: self.signature_schemes,
             verifier,
         }
     }
 
+    /// Trust servers which present one of a fixed set of raw public keys
+    /// (RFC7250), rather than a certificate chain.
+    ///
+    /// `spkis` are the DER-encoded SubjectPublicKeyInfos of the keys.  Only
+    /// TLS1.3 servers which support raw public keys can be verified.
+    pub fn with_pinned_public_keys(self, spkis: Vec<Vec<u8>>) -> ConfigWantsClientCert {
+        ConfigWantsClientCert {
+            provider: self.provider,
+            cipher_suites: self.cipher_suites,
+            kx_groups: self.kx_groups,
+            versions: self.versions,
+            signature_schemes: self.signature_schemes,
+            verifier: verify::PinnedPublicKeys::new(spkis),
+        }
+    }
+
     #[cfg(feature = "dangerous_configuration")]
     /// Set a custom certificate verifier.
+    ///
+    /// Any `with_revocation_policy` or `with_ocsp_max_age` settings are
+    /// not used: `verifier` is responsible for checking revocation.
     pub fn with_custom_certificate_verifier(
         self,
         verifier: Arc<dyn verify::ServerCertVerifier>,
     ) -> ConfigWantsClientCert {
         ConfigWantsClientCert {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             versions: self.versions,
+            signature_schemes: self.signature_schemes,
             verifier,
         }
     }
@@ -54,9 +114,11 @@ impl ConfigWantsServerVerifier {
 /// A config builder for a client, where we want to know whether and how a
 /// client certificate should be provided.
 pub struct ConfigWantsClientCert {
+    provider: &'static dyn CryptoProvider,
     cipher_suites: Vec<SupportedCipherSuite>,
     kx_groups: Vec<&'static SupportedKxGroup>,
     versions: versions::EnabledVersions,
+    signature_schemes: Vec<SignatureScheme>,
     verifier: Arc<dyn verify::ServerCertVerifier>,
 }
 
@@ -67,16 +129,50 @@ impl ConfigWantsClientCert {
     /// `cert_chain` is a vector of DER-encoded certificates.
     /// `key_der` is a DER-encoded RSA, ECDSA, or Ed25519 private key.
     ///
-    /// This function fails if `key_der` is invalid.
+    /// This function fails if `key_der` is invalid, or cannot sign with
+    /// any of the enabled signature schemes.
     pub fn with_single_cert(
         self,
         cert_chain: Vec<key::Certificate>,
         key_der: key::PrivateKey,
     ) -> Result<ClientConfig, Error> {
-        let resolver = handy::AlwaysResolvesClientCert::new(cert_chain, &key_der)?;
+        let resolver = handy::AlwaysResolvesClientCert::new(self.provider, cert_chain, &key_der)?;
+        self.validate_signing_key(&resolver)?;
         Ok(self.with_client_cert_resolver(Arc::new(resolver)))
     }
 
+    /// Sets a raw public key (RFC7250) and matching private key for use
+    /// in client authentication, with TLS1.3 servers which support raw
+    /// public keys.
+    ///
+    /// `spki` is the DER-encoded SubjectPublicKeyInfo of the key.
+    /// `key_der` is a DER-encoded RSA, ECDSA, or Ed25519 private key.
+    ///
+    /// This function fails if `key_der` is invalid, or cannot sign with
+    /// any of the enabled signature schemes.
+    pub fn with_raw_public_key(
+        self,
+        spki: Vec<u8>,
+        key_der: key::PrivateKey,
+    ) -> Result<ClientConfig, Error> {
+        let resolver =
+            handy::AlwaysResolvesClientCert::new_raw_public_key(self.provider, spki, &key_der)?;
+        self.validate_signing_key(&resolver)?;
+        Ok(self.with_client_cert_resolver(Arc::new(resolver)))
+    }
+
+    fn validate_signing_key(
+        &self,
+        resolver: &handy::AlwaysResolvesClientCert,
+    ) -> Result<(), Error> {
+        builder::validate_signing_key(
+            resolver.0.key.as_ref(),
+            &self.cipher_suites,
+            &self.versions,
+            &self.signature_schemes,
+        )
+    }
+
     /// Do not support client auth.
     pub fn with_no_client_auth(self) -> ClientConfig {
         self.with_client_cert_resolver(Arc::new(handy::FailResolveClientCert {}))
@@ -88,6 +184,7 @@ impl ConfigWantsClientCert {
         client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
     ) -> ClientConfig {
         ClientConfig {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             alpn_protocols: Vec::new(),
@@ -97,9 +194,19 @@ impl ConfigWantsClientCert {
             enable_tickets: true,
             versions: self.versions,
             enable_sni: true,
+            enable_grease: false,
+            enable_delegated_credentials: false,
             verifier: self.verifier,
             key_log: Arc::new(NoKeyLog {}),
             enable_early_data: false,
+            peer_message_limits: PeerMessageLimits::default(),
+            cert_compressors: Vec::new(),
+            cert_decompressors: Vec::new(),
+            max_decompressed_cert_size: compress::DEFAULT_MAX_DECOMPRESSED_CERT_SIZE,
+            ech_config: None,
+            psk_store: None,
+            psk_key_exchange_modes: vec![PSKKeyExchangeMode::PSK_DHE_KE],
+            signature_schemes: self.signature_schemes,
         }
     }
 }
@@ -1,6 +1,8 @@
+use crate::compress::CertificateCompressor;
+use crate::grease::Grease;
 #[cfg(feature = "logging")]
 use crate::log::trace;
-use crate::msgs::enums::ExtensionType;
+use crate::msgs::enums::{CertificateType, ExtensionType};
 use crate::msgs::handshake::CertificatePayload;
 use crate::msgs::handshake::DigitallySignedStruct;
 use crate::msgs::handshake::SCTList;
@@ -53,12 +55,14 @@ impl ServerKxDetails {
 
 pub(super) struct ClientHelloDetails {
     pub(super) sent_extensions: Vec<ExtensionType>,
+    pub(super) grease: Option<Grease>,
 }
 
 impl ClientHelloDetails {
-    pub(super) fn new() -> Self {
+    pub(super) fn new(grease: Option<Grease>) -> Self {
         Self {
             sent_extensions: Vec::new(),
+            grease,
         }
     }
 
@@ -107,6 +111,8 @@ pub(super) struct ClientAuthDetails {
     pub(super) certkey: Option<Arc<sign::CertifiedKey>>,
     pub(super) signer: Option<Box<dyn sign::Signer>>,
     pub(super) auth_context: Option<Vec<u8>>,
+    pub(super) compressor: Option<Arc<dyn CertificateCompressor>>,
+    pub(super) cert_type: CertificateType,
 }
 
 impl ClientAuthDetails {
@@ -115,6 +121,8 @@ impl ClientAuthDetails {
             certkey: None,
             signer: None,
             auth_context: None,
+            compressor: None,
+            cert_type: CertificateType::X509,
         }
     }
 }
@@ -1,4 +1,5 @@
 use crate::client;
+use crate::crypto::CryptoProvider;
 use crate::error::Error;
 use crate::key;
 use crate::limited_cache;
@@ -72,17 +73,32 @@ impl client::ResolvesClientCert for FailResolveClientCert {
     }
 }
 
-pub(super) struct AlwaysResolvesClientCert(Arc<sign::CertifiedKey>);
+pub(super) struct AlwaysResolvesClientCert(pub(super) Arc<sign::CertifiedKey>);
 
 impl AlwaysResolvesClientCert {
     pub(super) fn new(
+        provider: &dyn CryptoProvider,
         chain: Vec<key::Certificate>,
         priv_key: &key::PrivateKey,
     ) -> Result<Self, Error> {
-        let key = sign::any_supported_type(priv_key)
+        let key = provider
+            .load_private_key(priv_key)
             .map_err(|_| Error::General("invalid private key".into()))?;
         Ok(Self(Arc::new(sign::CertifiedKey::new(chain, key))))
     }
+
+    pub(super) fn new_raw_public_key(
+        provider: &dyn CryptoProvider,
+        spki: Vec<u8>,
+        priv_key: &key::PrivateKey,
+    ) -> Result<Self, Error> {
+        let key = provider
+            .load_private_key(priv_key)
+            .map_err(|_| Error::General("invalid private key".into()))?;
+        Ok(Self(Arc::new(sign::CertifiedKey::from_raw_public_key(
+            spki, key,
+        ))))
+    }
 }
 
 impl client::ResolvesClientCert for AlwaysResolvesClientCert {
@@ -97,6 +113,10 @@ impl client::ResolvesClientCert for AlwaysResolvesClientCert {
     fn has_certs(&self) -> bool {
         true
     }
+
+    fn has_raw_public_keys(&self) -> bool {
+        self.0.raw_public_key.is_some()
+    }
 }
 
 #[cfg(test)]
@@ -1,8 +1,13 @@
 #[cfg(feature = "logging")]
 use crate::bs_debug;
 use crate::check::check_message;
+use crate::compress;
 use crate::conn::{ConnectionCommon, ConnectionRandoms};
-use crate::error::{Error, WebPkiError};
+use crate::crypto::ActiveKeyExchange;
+use crate::delegated;
+use crate::ech::{EchOffer, EchStatus};
+use crate::error::{Error, OcspError, WebPkiError};
+use crate::grease::{self, Grease};
 use crate::hash_hs::HandshakeHashBuffer;
 use crate::key_schedule::KeyScheduleEarly;
 use crate::kx;
@@ -12,18 +17,20 @@ use crate::msgs::base::Payload;
 #[cfg(feature = "quic")]
 use crate::msgs::base::PayloadU16;
 use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::ECPointFormat;
 use crate::msgs::enums::{AlertDescription, CipherSuite, Compression, ProtocolVersion};
-use crate::msgs::enums::{ContentType, ExtensionType, HandshakeType};
-use crate::msgs::enums::{ECPointFormat, PSKKeyExchangeMode};
+use crate::msgs::enums::{CertificateType, ContentType, ExtensionType, HandshakeType};
 use crate::msgs::handshake::{CertificateStatusRequest, SCTList};
 use crate::msgs::handshake::{ClientExtension, HasServerExtensions};
 use crate::msgs::handshake::{ClientHelloPayload, HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{ConvertProtocolNameList, ProtocolNameList};
-use crate::msgs::handshake::{ECPointFormatList, SupportedPointFormats};
+use crate::msgs::handshake::{ECPointFormatList, EncryptedClientHello, SupportedPointFormats};
 use crate::msgs::handshake::{HelloRetryRequest, KeyShareEntry};
 use crate::msgs::handshake::{Random, SessionID};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::psk::{self, PskOffer};
+use crate::suites::PartiallyExtractedSecrets;
 use crate::ticketer::TimeBase;
 use crate::SupportedCipherSuite;
 
@@ -49,7 +56,21 @@ pub(super) trait State: Send + Sync {
         Err(Error::HandshakeNotComplete)
     }
 
-    fn perhaps_write_key_update(&mut self, _common: &mut ConnectionCommon) {}
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
+    fn perhaps_write_key_update(&mut self, _common: &mut ConnectionCommon) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        _common: &mut ConnectionCommon,
+        _request_peer_update: bool,
+    ) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
 }
 
 impl crate::conn::HandleState for Box<dyn State> {
@@ -140,13 +161,30 @@ pub(super) fn start_handshake(
         None
     };
 
+    let ech = match &config.ech_config {
+        Some(ech_config) if support_tls13 => {
+            cx.data.ech_status = EchStatus::Offered;
+            Some(EchOffer::new(
+                config.provider,
+                ech_config,
+                HandshakeHashBuffer::new(),
+            )?)
+        }
+        Some(_) => {
+            return Err(Error::General(
+                "Encrypted Client Hello requires TLS1.3".to_string(),
+            ));
+        }
+        None => None,
+    };
+
     if let Some(resuming) = &mut resuming_session {
         if resuming.version == ProtocolVersion::TLSv1_2 {
             // If we have a ticket, we use the sessionid as a signal that
             // we're  doing an abbreviated handshake.  See section 3.4 in
             // RFC5077.
             if !resuming.ticket.0.is_empty() {
-                resuming.set_session_id(SessionID::random()?);
+                resuming.set_session_id(SessionID::random(config.provider)?);
             }
             session_id = Some(resuming.session_id);
         }
@@ -159,14 +197,18 @@ pub(super) fn start_handshake(
     // https://tools.ietf.org/html/rfc8446#appendix-D.4
     // https://tools.ietf.org/html/draft-ietf-quic-tls-34#section-8.4
     if session_id.is_none() && !cx.common.is_quic() {
-        session_id = Some(SessionID::random()?);
+        session_id = Some(SessionID::random(config.provider)?);
     }
 
-    let random = Random::new()?;
-    let hello_details = ClientHelloDetails::new();
+    let random = Random::new(config.provider)?;
+    let grease = match config.enable_grease {
+        true => Some(Grease::new(config.provider)?),
+        false => None,
+    };
+    let hello_details = ClientHelloDetails::new(grease);
     let sent_tls13_fake_ccs = false;
     let may_send_sct_list = config.verifier.request_scts();
-    Ok(emit_client_hello_for_retry(
+    emit_client_hello_for_retry(
         config,
         cx,
         resuming_session,
@@ -182,7 +224,8 @@ pub(super) fn start_handshake(
         extra_exts,
         may_send_sct_list,
         None,
-    ))
+        ech,
+    )
 }
 
 struct ExpectServerHello {
@@ -193,11 +236,13 @@ struct ExpectServerHello {
     using_ems: bool,
     transcript_buffer: HandshakeHashBuffer,
     early_key_schedule: Option<KeyScheduleEarly>,
+    psk_offers: Vec<PskOffer>,
     hello: ClientHelloDetails,
-    offered_key_share: Option<kx::KeyExchange>,
+    offered_key_share: Option<Box<dyn ActiveKeyExchange>>,
     session_id: SessionID,
     sent_tls13_fake_ccs: bool,
     suite: Option<SupportedCipherSuite>,
+    ech: Option<EchOffer>,
 }
 
 struct ExpectServerHelloOrHelloRetryRequest {
@@ -217,11 +262,12 @@ fn emit_client_hello_for_retry(
     session_id: Option<SessionID>,
     retryreq: Option<&HelloRetryRequest>,
     server_name: ServerName,
-    key_share: Option<kx::KeyExchange>,
+    key_share: Option<Box<dyn ActiveKeyExchange>>,
     extra_exts: Vec<ClientExtension>,
     may_send_sct_list: bool,
     suite: Option<SupportedCipherSuite>,
-) -> NextState {
+    mut ech: Option<EchOffer>,
+) -> NextStateOrError {
     // Do we have a SessionID or ticket cached for this host?
     let (ticket, resume_version) = if let Some(resuming) = &resuming_session {
         (resuming.ticket.0.clone(), resuming.version)
@@ -229,10 +275,18 @@ fn emit_client_hello_for_retry(
         (Vec::new(), ProtocolVersion::Unknown(0))
     };
 
-    let support_tls12 = config.supports_version(ProtocolVersion::TLSv1_2) && !cx.common.is_quic();
+    // The inner hello of an ECH offer must only offer TLS1.3, and
+    // we don't expect the outer one to be accepted with anything else.
+    let support_tls12 =
+        config.supports_version(ProtocolVersion::TLSv1_2) && !cx.common.is_quic() && ech.is_none();
     let support_tls13 = config.supports_version(ProtocolVersion::TLSv1_3);
 
+    let grease = hello.grease;
+
     let mut supported_versions = Vec::new();
+    if let Some(grease) = &grease {
+        supported_versions.push(grease.version());
+    }
     if support_tls13 {
         supported_versions.push(ProtocolVersion::TLSv1_3);
     }
@@ -242,6 +296,9 @@ fn emit_client_hello_for_retry(
     }
 
     let mut exts = Vec::new();
+    if let Some(grease) = &grease {
+        exts.push(grease.first_extension());
+    }
     if !supported_versions.is_empty() {
         exts.push(ClientExtension::SupportedVersions(supported_versions));
     }
@@ -252,16 +309,34 @@ fn emit_client_hello_for_retry(
         ECPointFormatList::supported(),
     ));
     exts.push(ClientExtension::NamedGroups(
-        config
-            .kx_groups
+        grease
             .iter()
-            .map(|skxg| skxg.name)
+            .map(Grease::named_group)
+            .chain(
+                config
+                    .kx_groups
+                    .iter()
+                    .map(|skxg| skxg.name),
+            )
             .collect(),
     ));
+    // Offer our schemes in our preference order, leaving out those
+    // the verifier cannot check.
+    let verify_schemes = config
+        .verifier
+        .supported_verify_schemes();
     exts.push(ClientExtension::SignatureAlgorithms(
-        config
-            .verifier
-            .supported_verify_schemes(),
+        grease
+            .iter()
+            .map(Grease::signature_scheme)
+            .chain(
+                config
+                    .signature_schemes
+                    .iter()
+                    .filter(|scheme| verify_schemes.contains(scheme))
+                    .copied(),
+            )
+            .collect(),
     ));
     exts.push(ClientExtension::ExtendedMasterSecretRequest);
     exts.push(ClientExtension::CertificateStatusRequest(
@@ -274,31 +349,110 @@ fn emit_client_hello_for_retry(
 
     if let Some(key_share) = &key_share {
         debug_assert!(support_tls13);
-        let key_share = KeyShareEntry::new(key_share.group(), key_share.pubkey.as_ref());
-        exts.push(ClientExtension::KeyShare(vec![key_share]));
+        let mut shares = Vec::new();
+
+        // A second ClientHello may only offer the group the server asked for.
+        if let (Some(grease), None) = (&grease, retryreq) {
+            shares.push(KeyShareEntry::new(grease.named_group(), &[0]));
+        }
+        shares.push(KeyShareEntry::new(key_share.group(), key_share.pub_key()));
+        exts.push(ClientExtension::KeyShare(shares));
     }
 
     if let Some(cookie) = retryreq.and_then(HelloRetryRequest::get_cookie) {
         exts.push(ClientExtension::Cookie(cookie.clone()));
     }
 
-    if support_tls13 && config.enable_tickets {
-        // We could support PSK_KE here too. Such connections don't
-        // have forward secrecy, and are similar to TLS1.2 resumption.
-        let psk_modes = vec![PSKKeyExchangeMode::PSK_DHE_KE];
+    // External PSKs are offered for the hashes of the suites we might
+    // use: after a retry request, that's only the one the server chose.
+    let psk_offers = match (&config.psk_store, support_tls13) {
+        (Some(psk_store), true) => {
+            let suites: Vec<_> = match suite {
+                Some(suite) => suite.tls13().into_iter().collect(),
+                None => config
+                    .cipher_suites
+                    .iter()
+                    .filter_map(SupportedCipherSuite::tls13)
+                    .collect(),
+            };
+            psk::client_offers(config.provider, psk_store.as_ref(), &server_name, &suites)
+        }
+        _ => Vec::new(),
+    };
+
+    if support_tls13 && (config.enable_tickets || !psk_offers.is_empty()) {
+        let psk_modes = grease
+            .iter()
+            .map(Grease::psk_mode)
+            .chain(
+                config
+                    .psk_key_exchange_modes
+                    .iter()
+                    .cloned(),
+            )
+            .collect();
         exts.push(ClientExtension::PresharedKeyModes(psk_modes));
     }
 
     if !config.alpn_protocols.is_empty() {
+        let grease_protocol = grease.as_ref().map(Grease::protocol);
         exts.push(ClientExtension::Protocols(ProtocolNameList::from_slices(
-            &config
-                .alpn_protocols
+            &grease_protocol
                 .iter()
+                .chain(config.alpn_protocols.iter())
                 .map(|proto| &proto[..])
                 .collect::<Vec<_>>(),
         )));
     }
 
+    if support_tls13 && !config.cert_decompressors.is_empty() {
+        exts.push(ClientExtension::CertificateCompressionAlgorithms(
+            compress::offered_algorithms(&config.cert_decompressors),
+        ));
+    }
+
+    if support_tls13 && config.enable_delegated_credentials {
+        exts.push(ClientExtension::DelegatedCredentialRequest(
+            delegated::SUPPORTED_SCHEMES
+                .iter()
+                .filter(|scheme| {
+                    config
+                        .signature_schemes
+                        .contains(scheme)
+                })
+                .copied()
+                .collect(),
+        ));
+    }
+
+    // Raw public keys (RFC7250) are only offered for TLS1.3.  X.509 is
+    // the default, so we only say anything if we support something else.
+    let server_cert_types = config.verifier.certificate_types();
+    if support_tls13 && server_cert_types != [CertificateType::X509] {
+        exts.push(ClientExtension::ServerCertTypes(server_cert_types));
+    }
+
+    if support_tls13
+        && config
+            .client_auth_cert_resolver
+            .has_raw_public_keys()
+    {
+        exts.push(ClientExtension::ClientCertTypes(vec![
+            CertificateType::RawPublicKey,
+            CertificateType::X509,
+        ]));
+    }
+
+    if ech.is_some() {
+        exts.push(ClientExtension::EncryptedClientHello(
+            EncryptedClientHello::Inner,
+        ));
+    }
+
+    if let Some(grease) = &grease {
+        exts.push(grease.second_extension());
+    }
+
     // Extra extensions must be placed before the PSK extension
     exts.extend(extra_exts.iter().cloned());
 
@@ -332,7 +486,9 @@ fn emit_client_hello_for_retry(
                     resuming,
                     resuming_suite,
                     &mut exts,
-                    retryreq.is_some(),
+                    // Early data is sent under the outer hello's keys,
+                    // so can't be used with ECH.
+                    retryreq.is_some() || ech.is_some(),
                 );
                 (resuming, resuming_suite)
             })
@@ -348,10 +504,16 @@ fn emit_client_hello_for_retry(
         None
     };
 
-    // Note what extensions we sent.
+    // Our external PSKs go after any ticket, in the same extension.
+    if !psk_offers.is_empty() {
+        tls13::prepare_external_psks(&psk_offers, &mut exts);
+    }
+
+    // Note what extensions we sent.  The server must not echo GREASE ones.
     hello.sent_extensions = exts
         .iter()
         .map(ClientExtension::get_type)
+        .filter(|typ| !grease::is_grease(typ.get_u16()))
         .collect();
 
     let session_id = session_id.unwrap_or_else(SessionID::empty);
@@ -360,6 +522,9 @@ fn emit_client_hello_for_retry(
         .iter()
         .map(|cs| cs.suite())
         .collect();
+    if let Some(grease) = &grease {
+        cipher_suites.insert(0, grease.cipher_suite());
+    }
     // We don't do renegotiation at all, in fact.
     cipher_suites.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
 
@@ -375,15 +540,34 @@ fn emit_client_hello_for_retry(
         }),
     };
 
+    // An ECH inner hello is padded differently, when it is sealed.
+    if let (HandshakePayload::ClientHello(ch), None) = (&mut chp.payload, &ech) {
+        ch.add_padding();
+    }
+
+    // Binders for our external PSKs follow that for any ticket.
+    tls13::fill_in_external_psk_binders(
+        &config,
+        &psk_offers,
+        usize::from(fill_in_binder.is_some()),
+        &transcript_buffer,
+        &mut chp,
+    );
+
     let early_key_schedule = if let Some((resuming, resuming_suite)) = fill_in_binder {
-        let schedule =
-            tls13::fill_in_psk_binder(resuming, resuming_suite, &transcript_buffer, &mut chp);
+        let schedule = tls13::fill_in_psk_binder(
+            &config,
+            resuming,
+            resuming_suite,
+            &transcript_buffer,
+            &mut chp,
+        );
         Some((resuming_suite, schedule))
     } else {
         None
     };
 
-    let ch = Message {
+    let mut ch = Message {
         // "This value MUST be set to 0x0303 for all records generated
         //  by a TLS 1.3 implementation other than an initial ClientHello
         //  (i.e., one not generated after a HelloRetryRequest)"
@@ -401,28 +585,40 @@ fn emit_client_hello_for_retry(
         tls13::emit_fake_ccs(&mut sent_tls13_fake_ccs, cx.common);
     }
 
-    trace!("Sending ClientHello {:#?}", ch);
-
     transcript_buffer.add_message(&ch);
+
+    // With ECH, our transcript continues with the inner hello, but
+    // what we send is the outer hello.
+    if let Some(ech) = &mut ech {
+        let inner = match ch.payload {
+            MessagePayload::Handshake(ref inner) => inner,
+            _ => unreachable!(),
+        };
+        ch.payload = MessagePayload::Handshake(ech.seal_outer(inner, retryreq.is_some())?);
+        ech.outer_transcript.add_message(&ch);
+    }
+
+    trace!("Sending ClientHello {:#?}", ch);
     cx.common.send_msg(ch, false);
 
     // Calculate the hash of ClientHello and use it to derive EarlyTrafficSecret
-    let early_key_schedule = early_key_schedule.map(|(resuming_suite, schedule)| {
-        if !cx.data.early_data.is_enabled() {
-            return schedule;
+    let early_key_schedule = match early_key_schedule {
+        Some((resuming_suite, schedule)) => {
+            if cx.data.early_data.is_enabled() {
+                tls13::derive_early_traffic_secret(
+                    &config,
+                    cx,
+                    resuming_suite,
+                    &schedule,
+                    &mut sent_tls13_fake_ccs,
+                    &transcript_buffer,
+                    &random.0,
+                )?;
+            }
+            Some(schedule)
         }
-
-        tls13::derive_early_traffic_secret(
-            &*config.key_log,
-            cx,
-            resuming_suite,
-            &schedule,
-            &mut sent_tls13_fake_ccs,
-            &transcript_buffer,
-            &random.0,
-        );
-        schedule
-    });
+        None => None,
+    };
 
     let next = ExpectServerHello {
         config,
@@ -432,18 +628,20 @@ fn emit_client_hello_for_retry(
         using_ems,
         transcript_buffer,
         early_key_schedule,
+        psk_offers,
         hello,
         offered_key_share: key_share,
         session_id,
         sent_tls13_fake_ccs,
         suite,
+        ech,
     };
 
-    if support_tls13 && retryreq.is_none() {
+    Ok(if support_tls13 && retryreq.is_none() {
         Box::new(ExpectServerHelloOrHelloRetryRequest { next, extra_exts })
     } else {
         Box::new(next)
-    }
+    })
 }
 
 pub(super) fn process_alpn_protocol(
@@ -599,10 +797,46 @@ impl State for ExpectServerHello {
             }
         }
 
+        // If we offered ECH, did the server accept it?  If not, we
+        // continue with the outer hello, and will fail the handshake
+        // once the server has authenticated as its public name.
+        if let Some(ech) = self.ech.take() {
+            let accepted = match suite.tls13() {
+                Some(suite) => ech.accepted(suite, &self.transcript_buffer, &self.random, &m),
+                None => {
+                    return Err(cx
+                        .common
+                        .illegal_param("server chose TLS1.2 in response to ECH"));
+                }
+            };
+
+            match (accepted, ech.hrr_accepted) {
+                (true, Some(false)) | (false, Some(true)) => {
+                    return Err(cx
+                        .common
+                        .illegal_param("server changed ECH decision after hrr"));
+                }
+                (true, _) => {
+                    debug!("Server accepted ECH");
+                    cx.data.ech_status = EchStatus::Accepted;
+                }
+                (false, _) => {
+                    debug!("Server rejected ECH");
+                    cx.data.ech_status = EchStatus::Rejected;
+                    self.server_name = ech.public_name();
+                    self.random = ech.outer_random;
+                    self.transcript_buffer = ech.outer_transcript;
+                    self.resuming_session = None;
+                    self.early_key_schedule = None;
+                    self.psk_offers.clear();
+                }
+            }
+        }
+
         // Start our handshake hash, and input the server-hello.
         let mut transcript = self
             .transcript_buffer
-            .start_hash(suite.get_hash());
+            .start_hash(self.config.provider, suite.get_hash());
         transcript.add_message(&m);
 
         let randoms = ConnectionRandoms::new(self.random, server_hello.random, true);
@@ -620,6 +854,7 @@ impl State for ExpectServerHello {
                     suite,
                     transcript,
                     self.early_key_schedule,
+                    self.psk_offers,
                     self.hello,
                     // We always send a key share when TLS 1.3 is enabled.
                     self.offered_key_share.unwrap(),
@@ -682,8 +917,10 @@ impl ExpectServerHelloOrHelloRetryRequest {
             }
         }
 
-        // Or has something unrecognised
-        if hrr.has_unknown_extension() {
+        // Or has something unrecognised, or confirms an ECH offer we didn't make
+        if hrr.has_unknown_extension()
+            || (hrr.get_ech_confirmation().is_some() && self.next.ech.is_none())
+        {
             cx.common
                 .send_fatal_alert(AlertDescription::UnsupportedExtension);
             return Err(Error::PeerIncompatibleError(
@@ -738,8 +975,12 @@ impl ExpectServerHelloOrHelloRetryRequest {
         let transcript = self
             .next
             .transcript_buffer
-            .start_hash(cs.get_hash());
+            .start_hash(self.next.config.provider, cs.get_hash());
         let mut transcript_buffer = transcript.into_hrr_buffer();
+        let mut ech = self.next.ech;
+        if let Some(ech) = &mut ech {
+            ech.handle_hello_retry(cs, &transcript_buffer, &self.next.random, &m);
+        }
         transcript_buffer.add_message(&m);
 
         // Early data is not allowed after HelloRetryrequest
@@ -759,12 +1000,16 @@ impl ExpectServerHelloOrHelloRetryRequest {
                         cx.common
                             .illegal_param("server requested hrr with bad group")
                     })?;
-                kx::KeyExchange::start(group).ok_or(Error::FailedToGetRandomBytes)?
+                self.next
+                    .config
+                    .provider
+                    .start_key_exchange(group)
+                    .ok_or(Error::FailedToGetRandomBytes)?
             }
             _ => offered_key_share,
         };
 
-        Ok(emit_client_hello_for_retry(
+        emit_client_hello_for_retry(
             self.next.config,
             cx,
             self.next.resuming_session,
@@ -780,7 +1025,8 @@ impl ExpectServerHelloOrHelloRetryRequest {
             self.extra_exts,
             may_send_sct_list,
             Some(cs),
-        ))
+            ech,
+        )
     }
 }
 
@@ -805,9 +1051,15 @@ pub(super) fn send_cert_error_alert(common: &mut ConnectionCommon, err: Error) -
         Error::WebPkiError(WebPkiError::BadEncoding, _) => {
             common.send_fatal_alert(AlertDescription::DecodeError);
         }
-        Error::PeerMisbehavedError(_) => {
+        Error::PeerMisbehavedError(_) | Error::InvalidDelegatedCredential => {
             common.send_fatal_alert(AlertDescription::IllegalParameter);
         }
+        Error::OcspError(OcspError::CertRevoked) => {
+            common.send_fatal_alert(AlertDescription::CertificateRevoked);
+        }
+        Error::OcspError(_) => {
+            common.send_fatal_alert(AlertDescription::BadCertificateStatusResponse);
+        }
         _ => {
             common.send_fatal_alert(AlertDescription::BadCertificate);
         }
@@ -1,6 +1,13 @@
-use crate::conn::{Connection, ConnectionCommon, IoState, PlaintextSink, Protocol, Reader, Writer};
+use crate::compress::{CertificateCompressor, CertificateDecompressor};
+use crate::conn::{
+    Connection, ConnectionCommon, IoState, PeerMessageLimits, PlaintextSink, Protocol, Reader,
+    Writer,
+};
+use crate::crypto::CryptoProvider;
+use crate::ech::{EchConfig, EchStatus};
 use crate::error::Error;
 use crate::key;
+use crate::key_schedule::EarlyExporter;
 use crate::keylog::KeyLog;
 use crate::kx::SupportedKxGroup;
 #[cfg(feature = "logging")]
@@ -8,11 +15,13 @@ use crate::log::trace;
 #[cfg(feature = "quic")]
 use crate::msgs::enums::AlertDescription;
 use crate::msgs::enums::CipherSuite;
+use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::msgs::enums::ProtocolVersion;
 use crate::msgs::enums::SignatureScheme;
 use crate::msgs::handshake::{CertificatePayload, ClientExtension};
+use crate::psk::PskIdentityStore;
 use crate::sign;
-use crate::suites::SupportedCipherSuite;
+use crate::suites::{ExtractedSecrets, SupportedCipherSuite};
 use crate::verify;
 use crate::versions;
 
@@ -76,6 +85,15 @@ pub trait ResolvesClientCert: Send + Sync {
 
     /// Return true if any certificates at all are available.
     fn has_certs(&self) -> bool;
+
+    /// Return true if this resolver can provide raw public keys
+    /// (RFC7250), in `CertifiedKey::raw_public_key`.  If so, TLS1.3
+    /// servers may ask for one instead of a certificate chain.
+    ///
+    /// The default implementation returns false.
+    fn has_raw_public_keys(&self) -> bool {
+        false
+    }
 }
 
 /// Common configuration for (typically) all connections made by
@@ -92,8 +110,19 @@ pub trait ResolvesClientCert: Send + Sync {
 /// * [`ClientConfig::session_storage`]: the default stores 256 sessions in memory.
 /// * [`ClientConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
 /// * [`ClientConfig::key_log`]: key material is not logged.
+/// * [`ClientConfig::peer_message_limits`]: see [`PeerMessageLimits`] for the defaults.
+/// * [`ClientConfig::cert_compressors`] and [`ClientConfig::cert_decompressors`]: the default
+///   is empty -- certificate compression is not used.
+/// * [`ClientConfig::ech_config`]: the default is `None` -- Encrypted Client Hello is not used.
+/// * [`ClientConfig::psk_store`]: the default is `None` -- external PSKs are not offered.
+/// * [`ClientConfig::psk_key_exchange_modes`]: the default is `[PSK_DHE_KE]`.
+/// * [`ClientConfig::signature_schemes`]: the default is every scheme the crypto provider
+///   supports.
 #[derive(Clone)]
 pub struct ClientConfig {
+    /// Where all our cryptography comes from.
+    provider: &'static dyn CryptoProvider,
+
     /// List of ciphersuites, in preference order.
     cipher_suites: Vec<SupportedCipherSuite>,
 
@@ -140,6 +169,26 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_sni: bool,
 
+    /// Whether to send GREASE ([RFC8701](https://tools.ietf.org/html/rfc8701))
+    /// values in our ClientHello: reserved cipher suites, extensions, named
+    /// groups, signature schemes, versions and ALPN protocols, which servers
+    /// must ignore.  This helps keep servers and middleboxes from choking on
+    /// values they don't know.
+    ///
+    /// The default is false.
+    pub enable_grease: bool,
+
+    /// Whether to accept delegated credentials
+    /// ([RFC9345](https://tools.ietf.org/html/rfc9345)) from TLS1.3 servers.
+    /// These let a server sign the handshake with a short-lived key,
+    /// authorised by its certificate, instead of the certified key itself.
+    ///
+    /// The server's certificate is still verified by the configured
+    /// verifier, which also checks the signature on the credential.
+    ///
+    /// The default is false.
+    pub enable_delegated_credentials: bool,
+
     /// How to verify the server certificate chain.
     verifier: Arc<dyn verify::ServerCertVerifier>,
 
@@ -152,6 +201,67 @@ pub struct ClientConfig {
     ///
     /// The default is false.
     pub enable_early_data: bool,
+    /// Limits on how many `KeyUpdate` and `NewSessionTicket` messages,
+    /// warning alerts and empty records a server may send in a row.
+    pub peer_message_limits: PeerMessageLimits,
+
+    /// Certificate compression algorithms (RFC8879) we can use to
+    /// compress our certificate chain, in preference order.  We
+    /// compress with the first of these which the server supports.
+    ///
+    /// The default is empty: certificates are never compressed.
+    pub cert_compressors: Vec<Arc<dyn CertificateCompressor>>,
+
+    /// Certificate compression algorithms (RFC8879) we offer the
+    /// server for its certificate chain.
+    ///
+    /// The default is empty: compressed certificates are not accepted.
+    pub cert_decompressors: Vec<Arc<dyn CertificateDecompressor>>,
+
+    /// The largest certificate chain, in bytes, we are prepared to
+    /// decompress.  The default is 64KB, the same limit that applies
+    /// to uncompressed certificate chains.
+    pub max_decompressed_cert_size: usize,
+
+    /// If set, the server's Encrypted Client Hello configuration.  Our
+    /// real ClientHello, including the server name, is then encrypted
+    /// to the server, and only TLS1.3 is offered.
+    ///
+    /// If the server rejects ECH, the handshake fails with
+    /// [`Error::EncryptedClientHelloRejected`].
+    ///
+    /// The default is `None`.
+    pub ech_config: Option<EchConfig>,
+
+    /// If set, where to find the external pre-shared keys we offer to
+    /// TLS1.3 servers.  If the server chooses one, it authenticates
+    /// the handshake in place of the server's certificate.
+    ///
+    /// A client which only uses PSKs can be built with an empty
+    /// [`RootCertStore`](crate::RootCertStore), so that no server
+    /// certificate is ever trusted.
+    ///
+    /// The default is `None`.
+    pub psk_store: Option<Arc<dyn PskIdentityStore>>,
+
+    /// The TLS1.3 PSK key exchange modes we offer, for resumption and
+    /// external PSKs.  `PSK_DHE_KE` also does a key exchange, so gives
+    /// forward secrecy; `PSK_KE` does not.
+    ///
+    /// Servers using rustls only resume sessions with `PSK_DHE_KE`.
+    ///
+    /// The default is `[PSK_DHE_KE]`.
+    pub psk_key_exchange_modes: Vec<PSKKeyExchangeMode>,
+
+    /// The signature schemes we use, in preference order.  Only these
+    /// are advertised to the server and accepted in its signatures, and
+    /// they are used to choose how to sign for client authentication.
+    ///
+    /// Set this with [`ConfigWantsPeerType::with_signature_schemes`],
+    /// which checks it against the cipher suites and certificate.
+    ///
+    /// [`ConfigWantsPeerType::with_signature_schemes`]: crate::ConfigWantsPeerType::with_signature_schemes
+    pub signature_schemes: Vec<SignatureScheme>,
 }
 
 impl ClientConfig {
@@ -186,8 +296,10 @@ impl ClientConfig {
 ///
 /// This currently covers knowing the DNS name of the server, but
 /// will be extended in the future to knowing the IP address of the
-/// server, as well as supporting privacy-preserving names for the
-/// server ("ECH").  For this reason this enum is `non_exhaustive`.
+/// server.  For this reason this enum is `non_exhaustive`.
+///
+/// To keep the name private from observers, see
+/// [`ClientConfig::ech_config`].
 ///
 /// # Making one
 ///
@@ -416,7 +528,11 @@ impl ClientConnection {
         proto: Protocol,
     ) -> Result<Self, Error> {
         let mut new = Self {
-            common: ConnectionCommon::new(config.max_fragment_size, true)?,
+            common: ConnectionCommon::new(
+                config.max_fragment_size,
+                config.peer_message_limits,
+                true,
+            )?,
             state: None,
             data: ClientConnectionData::new(),
         };
@@ -466,6 +582,43 @@ impl ClientConnection {
         self.data.early_data.is_accepted()
     }
 
+    /// Derives key material from the TLS1.3 early exporter secret, as
+    /// described in RFC8446 section 7.5.  This is for binding early data
+    /// to the connection it was sent on; otherwise, use
+    /// [`Connection::export_keying_material`].
+    ///
+    /// This works once early data has been offered, and stays available
+    /// unless the server rejects it.
+    pub fn export_early_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        match &self.data.early_exporter {
+            Some(exporter)
+                if self.data.early_data.is_enabled() || self.is_early_data_accepted() =>
+            {
+                exporter.export_keying_material(output, label, context)
+            }
+            _ => Err(Error::General("early data is not in use".into())),
+        }
+    }
+
+    /// Returns whether Encrypted Client Hello was offered, and whether
+    /// the server accepted it.
+    pub fn ech_status(&self) -> EchStatus {
+        self.data.ech_status
+    }
+
+    /// Returns the identity of the external PSK the server chose, if
+    /// the handshake was authenticated with one.
+    pub fn external_psk_identity(&self) -> Option<&[u8]> {
+        self.data
+            .external_psk_identity
+            .as_deref()
+    }
+
     fn write_early_data(&mut self, data: &[u8]) -> io::Result<usize> {
         self.data
             .early_data
@@ -476,14 +629,19 @@ impl ClientConnection {
             })
     }
 
-    fn send_some_plaintext(&mut self, buf: &[u8]) -> usize {
+    fn send_some_plaintext(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut st = self.state.take();
-        if let Some(st) = st.as_mut() {
-            st.perhaps_write_key_update(&mut self.common);
-        }
+        let mut key_update = |common: &mut ConnectionCommon| match st.as_mut() {
+            Some(st) => st.perhaps_write_key_update(common),
+            None => Ok(()),
+        };
+        let sent = key_update(&mut self.common)
+            .and_then(|()| {
+                self.common
+                    .send_some_plaintext(buf, &mut key_update)
+            });
         self.state = st;
-
-        self.common.send_some_plaintext(buf)
+        sent.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 }
 
@@ -550,6 +708,22 @@ impl Connection for ClientConnection {
             .and_then(|st| st.export_keying_material(output, label, context))
     }
 
+    fn extract_secrets(self) -> Result<ExtractedSecrets, Error> {
+        let secrets = self
+            .state
+            .as_ref()
+            .ok_or(Error::HandshakeNotComplete)?
+            .extract_secrets()?;
+        self.common.extract_secrets(secrets)
+    }
+
+    fn refresh_traffic_keys(&mut self, request_peer_update: bool) -> Result<(), Error> {
+        match self.state.as_mut() {
+            Some(st) => st.refresh_traffic_keys(&mut self.common, request_peer_update),
+            None => Err(Error::HandshakeNotComplete),
+        }
+    }
+
     fn negotiated_cipher_suite(&self) -> Option<SupportedCipherSuite> {
         self.common
             .get_suite()
@@ -567,13 +741,19 @@ impl Connection for ClientConnection {
 
 impl PlaintextSink for ClientConnection {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        Ok(self.send_some_plaintext(buf))
+        self.send_some_plaintext(buf)
     }
 
     fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
         let mut sz = 0;
         for buf in bufs {
-            sz += self.send_some_plaintext(buf);
+            match self.send_some_plaintext(buf) {
+                Ok(len) => sz += len,
+                // Report what was already queued; the error recurs on
+                // the next write.
+                Err(_) if sz > 0 => break,
+                Err(err) => return Err(err),
+            }
         }
         Ok(sz)
     }
@@ -587,6 +767,10 @@ struct ClientConnectionData {
     server_cert_chain: CertificatePayload,
     early_data: EarlyData,
     resumption_ciphersuite: Option<SupportedCipherSuite>,
+    ech_status: EchStatus,
+    ech_retry_configs: Option<Vec<u8>>,
+    external_psk_identity: Option<Vec<u8>>,
+    early_exporter: Option<Box<EarlyExporter>>,
 }
 
 impl ClientConnectionData {
@@ -595,6 +779,10 @@ impl ClientConnectionData {
             server_cert_chain: Vec::new(),
             early_data: EarlyData::new(),
             resumption_ciphersuite: None,
+            ech_status: EchStatus::NotOffered,
+            ech_retry_configs: None,
+            external_psk_identity: None,
+            early_exporter: None,
         }
     }
 }
@@ -655,6 +843,8 @@ pub trait ClientQuicExt {
             ));
         }
 
+        quic::check_suites(&config.cipher_suites)?;
+
         let ext = match quic_version {
             quic::Version::V1Draft => ClientExtension::TransportParametersDraft(params),
             quic::Version::V1 => ClientExtension::TransportParameters(params),
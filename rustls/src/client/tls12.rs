@@ -1,20 +1,26 @@
 use crate::check::{check_message, inappropriate_message};
 use crate::conn::{ConnectionCommon, ConnectionRandoms, ConnectionSecrets};
+use crate::crypto::KeyExchangeResult;
 use crate::error::Error;
+#[cfg(feature = "ffdhe")]
+use crate::ffdhe::FfdheGroup;
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
-use crate::msgs::base::{Payload, PayloadU8};
+use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
-use crate::msgs::enums::{AlertDescription, ProtocolVersion};
+use crate::msgs::enums::{AlertDescription, ProtocolVersion, SignatureScheme};
 use crate::msgs::enums::{ContentType, HandshakeType};
 use crate::msgs::handshake::{CertificatePayload, DecomposedSignatureScheme, SCTList, SessionID};
-use crate::msgs::handshake::{DigitallySignedStruct, ServerECDHParams};
+use crate::msgs::handshake::{DigitallySignedStruct, KeyExchangeAlgorithm};
 use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
+#[cfg(feature = "ffdhe")]
+use crate::msgs::handshake::ServerDHParams;
+use crate::msgs::handshake::{ServerECDHParams, ServerKeyExchangePayload};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
-use crate::suites::{SupportedCipherSuite, Tls12CipherSuite};
+use crate::suites::{PartiallyExtractedSecrets, SupportedCipherSuite, Tls12CipherSuite};
 use crate::ticketer::TimeBase;
 use crate::verify;
 use crate::{kx, tls12};
@@ -127,6 +133,7 @@ mod server_hello {
                     }
 
                     let secrets = ConnectionSecrets::new_resume(
+                        self.config.provider,
                         &self.randoms,
                         suite,
                         &resuming.master_secret.0,
@@ -137,7 +144,7 @@ mod server_hello {
                         &secrets.master_secret,
                     );
                     cx.common
-                        .start_encryption_tls12(&secrets);
+                        .start_encryption_tls12(&secrets)?;
 
                     // Since we're resuming, we verified the certificate and
                     // proof of possession in the prior session.
@@ -392,23 +399,22 @@ impl hs::State for ExpectServerKx {
         )?;
         self.transcript.add_message(&m);
 
-        let ecdhe = opaque_kx
-            .unwrap_given_kxa(&self.suite.kx)
-            .ok_or_else(|| {
+        // Save the signature and signed parameters for later verification.
+        let server_kx = match opaque_kx.unwrap_given_kxa(&self.suite.kx) {
+            Some(ServerKeyExchangePayload::ECDHE(ecdhe)) => {
+                debug!("ECDHE curve is {:?}", ecdhe.params.curve_params);
+                ServerKxDetails::new(ecdhe.params.get_encoding(), ecdhe.dss)
+            }
+            Some(ServerKeyExchangePayload::DHE(dhe)) => {
+                debug!("DHE prime is {} bytes", dhe.params.dh_p.0.len());
+                ServerKxDetails::new(dhe.params.get_encoding(), dhe.dss)
+            }
+            _ => {
                 cx.common
                     .send_fatal_alert(AlertDescription::DecodeError);
-                Error::CorruptMessagePayload(ContentType::Handshake)
-            })?;
-
-        // Save the signature and signed parameters for later verification.
-        let mut kx_params = Vec::new();
-        ecdhe.params.encode(&mut kx_params);
-        let server_kx = ServerKxDetails::new(kx_params, ecdhe.dss);
-
-        #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
-        {
-            debug!("ECDHE curve is {:?}", ecdhe.params.curve_params);
-        }
+                return Err(Error::CorruptMessagePayload(ContentType::Handshake));
+            }
+        };
 
         Ok(Box::new(ExpectServerDoneOrCertReq {
             config: self.config,
@@ -446,11 +452,14 @@ fn emit_certificate(
 fn emit_clientkx(
     transcript: &mut HandshakeHash,
     common: &mut ConnectionCommon,
-    kxd: &kx::KeyExchangeResult,
+    kxa: &KeyExchangeAlgorithm,
+    kxd: &KeyExchangeResult,
 ) {
     let mut buf = Vec::new();
-    let ecpoint = PayloadU8::new(Vec::from(kxd.pubkey.as_ref()));
-    ecpoint.encode(&mut buf);
+    match *kxa {
+        KeyExchangeAlgorithm::DHE => PayloadU16::new(kxd.pubkey.clone()).encode(&mut buf),
+        _ => PayloadU8::new(kxd.pubkey.clone()).encode(&mut buf),
+    }
     let pubkey = Payload::new(buf);
 
     let ckx = Message {
@@ -633,15 +642,23 @@ impl hs::State for ExpectCertificateRequest {
             .iter()
             .map(|p| p.0.as_slice())
             .collect::<Vec<&[u8]>>();
+        let sigschemes = certreq
+            .sigschemes
+            .iter()
+            .filter(|scheme| {
+                self.config
+                    .signature_schemes
+                    .contains(scheme)
+            })
+            .copied()
+            .collect::<Vec<SignatureScheme>>();
         let maybe_certkey = self
             .config
             .client_auth_cert_resolver
-            .resolve(&canames, &certreq.sigschemes);
+            .resolve(&canames, &sigschemes);
 
         if let Some(certkey) = maybe_certkey {
-            let maybe_signer = certkey
-                .key
-                .choose_scheme(&certreq.sigschemes);
+            let maybe_signer = certkey.key.choose_scheme(&sigschemes);
 
             if maybe_signer.is_some() {
                 debug!("Attempting client auth");
@@ -753,9 +770,17 @@ impl hs::State for ExpectServerDone {
                 return Err(Error::PeerMisbehavedError(error_message));
             }
 
-            st.config
-                .verifier
-                .verify_tls12_signature(&message, &st.server_cert.cert_chain[0], sig)
+            verify::check_signature_scheme(&st.config.signature_schemes, sig)
+                .and_then(|()| {
+                    st.config
+                        .verifier
+                        .verify_tls12_signature(
+                            st.config.provider,
+                            &message,
+                            &st.server_cert.cert_chain[0],
+                            sig,
+                        )
+                })
                 .map_err(|err| hs::send_cert_error_alert(cx.common, err))?
         };
         cx.data.server_cert_chain = st.server_cert.cert_chain;
@@ -770,18 +795,51 @@ impl hs::State for ExpectServerDone {
         }
 
         // 5a.
-        let ecdh_params =
-            tls12::decode_ecdh_params::<ServerECDHParams>(cx.common, &st.server_kx.kx_params)?;
-        let group =
-            kx::KeyExchange::choose(ecdh_params.curve_params.named_group, &st.config.kx_groups)
+        let kxd = match suite.kx {
+            #[cfg(feature = "ffdhe")]
+            KeyExchangeAlgorithm::DHE => {
+                let dh_params =
+                    tls12::decode_kx_params::<ServerDHParams>(cx.common, &st.server_kx.kx_params)?;
+                // We only do DHE in the RFC7919 groups.
+                let kx_groups = &st.config.kx_groups;
+                let group = FfdheGroup::from_params(&dh_params.dh_p.0, &dh_params.dh_g.0)
+                    .and_then(|group| kx::KeyExchange::choose(group.named_group(), kx_groups))
+                    .ok_or_else(|| {
+                        Error::PeerMisbehavedError(
+                            "peer chose unsupported DHE parameters".to_string(),
+                        )
+                    })?;
+                let kx = st
+                    .config
+                    .provider
+                    .start_key_exchange(group)
+                    .ok_or(Error::FailedToGetRandomBytes)?;
+                tls12::complete_dhe(kx, &dh_params.dh_ys.0)?
+            }
+            _ => {
+                let ecdh_params = tls12::decode_kx_params::<ServerECDHParams>(
+                    cx.common,
+                    &st.server_kx.kx_params,
+                )?;
+                let group = kx::KeyExchange::choose(
+                    ecdh_params.curve_params.named_group,
+                    &st.config.kx_groups,
+                )
+                .filter(|skxg| skxg.usable_for_tls12(&suite.kx))
                 .ok_or_else(|| {
                     Error::PeerMisbehavedError("peer chose an unsupported group".to_string())
                 })?;
-        let kx = kx::KeyExchange::start(group).ok_or(Error::FailedToGetRandomBytes)?;
-        let kxd = tls12::complete_ecdh(kx, &ecdh_params.public.0)?;
+                let kx = st
+                    .config
+                    .provider
+                    .start_key_exchange(group)
+                    .ok_or(Error::FailedToGetRandomBytes)?;
+                tls12::complete_ecdh(kx, &ecdh_params.public.0)?
+            }
+        };
 
         // 5b.
-        emit_clientkx(&mut st.transcript, cx.common, &kxd);
+        emit_clientkx(&mut st.transcript, cx.common, &suite.kx, &kxd);
         // nb. EMS handshake hash only runs up to ClientKeyExchange.
         let handshake_hash = st.transcript.get_current_hash();
 
@@ -795,9 +853,15 @@ impl hs::State for ExpectServerDone {
 
         // 5e. Now commit secrets.
         let secrets = if st.using_ems {
-            ConnectionSecrets::new_ems(&st.randoms, &handshake_hash, suite, &kxd.shared_secret)
+            ConnectionSecrets::new_ems(
+                st.config.provider,
+                &st.randoms,
+                &handshake_hash,
+                suite,
+                &kxd.shared_secret,
+            )
         } else {
-            ConnectionSecrets::new(&st.randoms, suite, &kxd.shared_secret)
+            ConnectionSecrets::new(st.config.provider, &st.randoms, suite, &kxd.shared_secret)
         };
         st.config.key_log.log(
             "CLIENT_RANDOM",
@@ -805,7 +869,7 @@ impl hs::State for ExpectServerDone {
             &secrets.master_secret,
         );
         cx.common
-            .start_encryption_tls12(&secrets);
+            .start_encryption_tls12(&secrets)?;
         cx.common
             .record_layer
             .start_encrypting();
@@ -983,6 +1047,7 @@ impl ExpectFinished {
             time_now,
         );
         value.set_times(self.ticket.new_ticket_lifetime, 0);
+        value.set_alpn(cx.common.alpn_protocol.clone());
         if self.using_ems {
             value.set_extended_ms_used();
         }
@@ -1077,4 +1142,18 @@ impl hs::State for ExpectTraffic {
             .export_keying_material(output, label, context);
         Ok(())
     }
+
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        Ok(self.secrets.extract_secrets())
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        _common: &mut ConnectionCommon,
+        _request_peer_update: bool,
+    ) -> Result<(), Error> {
+        Err(Error::General(
+            "traffic key updates are not available in TLS1.2".to_string(),
+        ))
+    }
 }
@@ -1,10 +1,14 @@
 use crate::check::{check_message, inappropriate_handshake_message, inappropriate_message};
 use crate::cipher;
+use crate::compress;
 use crate::conn::{ConnectionCommon, ConnectionRandoms};
+use crate::crypto::{ActiveKeyExchange, Digest};
+use crate::delegated;
+use crate::ech::EchStatus;
 use crate::error::Error;
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 use crate::key_schedule::{
-    KeyScheduleEarly, KeyScheduleHandshake, KeyScheduleNonSecret, KeyScheduleTraffic,
+    KeyScheduleEarly, KeyScheduleHandshake, KeyScheduleNonSecret, KeyScheduleTraffic, PskKind,
 };
 use crate::kx;
 #[cfg(feature = "logging")]
@@ -12,24 +16,26 @@ use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
-use crate::msgs::enums::KeyUpdateRequest;
-use crate::msgs::enums::{AlertDescription, NamedGroup, ProtocolVersion};
+use crate::msgs::enums::{AlertDescription, CertificateType, NamedGroup, ProtocolVersion};
 use crate::msgs::enums::{ContentType, ExtensionType, HandshakeType, SignatureScheme};
+use crate::msgs::enums::{KeyUpdateRequest, PSKKeyExchangeMode};
 use crate::msgs::handshake::ClientExtension;
+use crate::msgs::handshake::DelegatedCredentialPayload;
 use crate::msgs::handshake::DigitallySignedStruct;
 use crate::msgs::handshake::EncryptedExtensions;
 use crate::msgs::handshake::NewSessionTicketPayloadTLS13;
 use crate::msgs::handshake::{CertificateEntry, CertificatePayloadTLS13};
 use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{HasServerExtensions, ServerHelloPayload, SessionID};
-use crate::msgs::handshake::{PresharedKeyIdentity, PresharedKeyOffer};
+use crate::msgs::handshake::{PresharedKeyBinder, PresharedKeyIdentity, PresharedKeyOffer};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
-use crate::suites::Tls13CipherSuite;
+use crate::psk::PskOffer;
+use crate::suites::{PartiallyExtractedSecrets, Tls13CipherSuite};
 use crate::verify;
 #[cfg(feature = "quic")]
 use crate::{conn::Protocol, msgs::base::PayloadU16, quic};
-use crate::{sign, KeyLog};
+use crate::{key, sign};
 
 use super::hs::ClientContext;
 use crate::client::common::ServerCertDetails;
@@ -67,31 +73,71 @@ pub(super) fn handle_server_hello(
     suite: &'static Tls13CipherSuite,
     transcript: HandshakeHash,
     early_key_schedule: Option<KeyScheduleEarly>,
+    psk_offers: Vec<PskOffer>,
     hello: ClientHelloDetails,
-    our_key_share: kx::KeyExchange,
+    our_key_share: Box<dyn ActiveKeyExchange>,
     mut sent_tls13_fake_ccs: bool,
 ) -> hs::NextStateOrError {
     validate_server_hello(cx.common, server_hello)?;
 
-    let their_key_share = server_hello
-        .get_key_share()
-        .ok_or_else(|| {
-            cx.common
-                .send_fatal_alert(AlertDescription::MissingExtension);
-            Error::PeerMisbehavedError("missing key share".to_string())
-        })?;
+    // Our external PSKs follow the ticket we're resuming with, if any.
+    let first_external = usize::from(early_key_schedule.is_some());
+    let external_psk = match server_hello.get_psk_index() {
+        Some(index) if usize::from(index) >= first_external => {
+            let offer = psk_offers
+                .into_iter()
+                .nth(usize::from(index) - first_external)
+                .ok_or_else(|| {
+                    Error::PeerMisbehavedError("server selected unoffered psk".to_string())
+                })?;
+            if offer.suite.get_hash() != suite.get_hash() {
+                return Err(cx
+                    .common
+                    .illegal_param("server selected psk with wrong hash"));
+            }
+            Some(offer)
+        }
+        _ => None,
+    };
 
-    if our_key_share.group() != their_key_share.group {
-        return Err(cx
-            .common
-            .illegal_param("wrong group for key share"));
-    }
+    let their_key_share = server_hello.get_key_share();
+    let shared = match their_key_share {
+        Some(their_key_share) => {
+            if our_key_share.group() != their_key_share.group {
+                return Err(cx
+                    .common
+                    .illegal_param("wrong group for key share"));
+            }
 
-    let shared = our_key_share
-        .complete(&their_key_share.payload.0)
-        .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?;
+            Some(
+                our_key_share
+                    .complete(&their_key_share.payload.0)
+                    .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?,
+            )
+        }
+        // In `psk_ke` mode, there is no key exchange.
+        None if external_psk.is_some()
+            && config
+                .psk_key_exchange_modes
+                .contains(&PSKKeyExchangeMode::PSK_KE) =>
+        {
+            None
+        }
+        None => {
+            cx.common
+                .send_fatal_alert(AlertDescription::MissingExtension);
+            return Err(Error::PeerMisbehavedError("missing key share".to_string()));
+        }
+    };
 
-    let key_schedule = if let (Some(selected_psk), Some(early_key_schedule)) =
+    let early_key_schedule = if let Some(offer) = &external_psk {
+        debug!("Using external PSK");
+        cx.data.early_data.rejected();
+        cx.common.early_traffic = false;
+        resuming_session.take();
+        cx.data.external_psk_identity = Some(offer.external_identity().to_vec());
+        Some(offer.key_schedule())
+    } else if let (Some(selected_psk), Some(early_key_schedule)) =
         (server_hello.get_psk_index(), early_key_schedule)
     {
         if let Some(ref resuming) = resuming_session {
@@ -125,18 +171,29 @@ pub(super) fn handle_server_hello(
                 "server selected unoffered psk".to_string(),
             ));
         }
-        early_key_schedule.into_handshake(&shared.shared_secret)
+        Some(early_key_schedule)
     } else {
         debug!("Not resuming");
         // Discard the early data key schedule.
         cx.data.early_data.rejected();
         cx.common.early_traffic = false;
         resuming_session.take();
-        KeyScheduleNonSecret::new(suite.hkdf_algorithm).into_handshake(&shared.shared_secret)
+        None
+    };
+
+    let key_schedule = match (early_key_schedule, shared) {
+        (Some(early_key_schedule), Some(shared)) => early_key_schedule.into_handshake(&shared),
+        (Some(early_key_schedule), None) => early_key_schedule.into_handshake_for_psk_ke(),
+        (None, Some(shared)) => {
+            KeyScheduleNonSecret::new(config.provider, suite.hash_algorithm).into_handshake(&shared)
+        }
+        (None, None) => unreachable!("psk_ke without a psk"),
     };
 
     // Remember what KX group the server liked for next time.
-    save_kx_hint(&config, &server_name, their_key_share.group);
+    if let Some(their_key_share) = their_key_share {
+        save_kx_hint(&config, &server_name, their_key_share.group);
+    }
 
     // If we change keying when a subsequent handshake message is being joined,
     // the two halves will have different record layer protections.  Disallow this.
@@ -153,21 +210,18 @@ pub(super) fn handle_server_hello(
     // Decrypt with the peer's key, encrypt with our own key
     cx.common
         .record_layer
-        .set_message_decrypter(cipher::new_tls13_read(suite, &server_key));
+        .set_message_decrypter(cipher::new_tls13_read(suite, &server_key)?);
 
     if !cx.data.early_data.is_enabled() {
         // Set the client encryption key for handshakes if early data is not used
         cx.common
             .record_layer
-            .set_message_encrypter(cipher::new_tls13_write(suite, &client_key));
+            .set_message_encrypter(cipher::new_tls13_write(suite, &client_key)?);
     }
 
     #[cfg(feature = "quic")]
     {
-        cx.common.quic.hs_secrets = Some(quic::Secrets {
-            server: server_key,
-            client: client_key,
-        });
+        cx.common.quic.hs_secrets = Some(quic::Secrets::new(&client_key, &server_key));
     }
 
     emit_fake_ccs(&mut sent_tls13_fake_ccs, cx.common);
@@ -203,7 +257,7 @@ fn validate_server_hello(
 pub(super) fn initial_key_share(
     config: &ClientConfig,
     server_name: &ServerName,
-) -> Result<kx::KeyExchange, Error> {
+) -> Result<Box<dyn ActiveKeyExchange>, Error> {
     let key = persist::ClientSessionKey::hint_for_server_name(server_name);
     let key_buf = key.get_encoding();
 
@@ -219,7 +273,10 @@ pub(super) fn initial_key_share(
                 .expect("No kx groups configured")
         });
 
-    kx::KeyExchange::start(group).ok_or(Error::FailedToGetRandomBytes)
+    config
+        .provider
+        .start_key_exchange(group)
+        .ok_or(Error::FailedToGetRandomBytes)
 }
 
 fn save_kx_hint(config: &ClientConfig, server_name: &ServerName, group: NamedGroup) {
@@ -233,32 +290,55 @@ fn save_kx_hint(config: &ClientConfig, server_name: &ServerName, group: NamedGro
 /// This implements the horrifying TLS1.3 hack where PSK binders have a
 /// data dependency on the message they are contained within.
 pub(super) fn fill_in_psk_binder(
+    config: &ClientConfig,
     resuming: &persist::ClientSessionValueWithResolvedCipherSuite,
     resuming_suite: &'static Tls13CipherSuite,
     transcript: &HandshakeHashBuffer,
     hmp: &mut HandshakeMessagePayload,
 ) -> KeyScheduleEarly {
     // We need to know the hash function of the suite we're trying to resume into.
-    let hkdf_alg = resuming_suite.hkdf_algorithm;
     let suite_hash = resuming_suite.get_hash();
 
     // The binder is calculated over the clienthello, but doesn't include itself or its
     // length, or the length of its container.
     let binder_plaintext = hmp.get_encoding_for_binder_signing();
-    let handshake_hash = transcript.get_hash_given(suite_hash, &binder_plaintext);
+    let handshake_hash = transcript.get_hash_given(config.provider, suite_hash, &binder_plaintext);
 
     // Run a fake key_schedule to simulate what the server will do if it chooses
     // to resume.
-    let key_schedule = KeyScheduleEarly::new(hkdf_alg, &resuming.master_secret.0);
-    let real_binder = key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+    let key_schedule =
+        KeyScheduleEarly::new(config.provider, suite_hash, &resuming.master_secret.0);
+    let real_binder =
+        key_schedule.psk_binder_key_and_sign_verify_data(PskKind::Resumption, &handshake_hash);
 
     if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
-        ch.set_psk_binder(real_binder.as_ref());
+        ch.set_psk_binder(0, real_binder.as_ref());
     };
 
     key_schedule
 }
 
+/// As `fill_in_psk_binder`, for our external PSKs: whose binders start
+/// at `first_index`.
+pub(super) fn fill_in_external_psk_binders(
+    config: &ClientConfig,
+    psks: &[PskOffer],
+    first_index: usize,
+    transcript: &HandshakeHashBuffer,
+    hmp: &mut HandshakeMessagePayload,
+) {
+    let binder_plaintext = hmp.get_encoding_for_binder_signing();
+    for (i, psk) in psks.iter().enumerate() {
+        let handshake_hash =
+            transcript.get_hash_given(config.provider, psk.suite.get_hash(), &binder_plaintext);
+        let real_binder = psk.binder(&handshake_hash);
+
+        if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
+            ch.set_psk_binder(first_index + i, real_binder.as_ref());
+        };
+    }
+}
+
 pub(super) fn prepare_resumption(
     config: &ClientConfig,
     cx: &mut ClientContext<'_>,
@@ -271,11 +351,22 @@ pub(super) fn prepare_resumption(
     cx.data.resumption_ciphersuite = Some(resuming_suite.into());
     // The EarlyData extension MUST be supplied together with the
     // PreSharedKey extension.
+    //
+    // Early data is sent under the ALPN protocol of the original session,
+    // so there's no point offering it if we no longer support that.
     let max_early_data_size = resuming_session.max_early_data_size;
-    if config.enable_early_data && max_early_data_size > 0 && !doing_retry {
+    let alpn_usable = match &resuming_session.alpn {
+        Some(alpn) => config.alpn_protocols.contains(&alpn.0),
+        None => true,
+    };
+    if config.enable_early_data && max_early_data_size > 0 && alpn_usable && !doing_retry {
         cx.data
             .early_data
             .enable(max_early_data_size as usize);
+        cx.common.alpn_protocol = resuming_session
+            .alpn
+            .as_ref()
+            .map(|alpn| alpn.0.clone());
         exts.push(ClientExtension::EarlyData);
     }
 
@@ -287,7 +378,7 @@ pub(super) fn prepare_resumption(
     let obfuscated_ticket_age =
         resuming_session.get_obfuscated_ticket_age(resuming_session.time_retrieved());
 
-    let binder_len = resuming_suite.get_hash().output_len;
+    let binder_len = resuming_suite.get_hash().output_len();
     let binder = vec![0u8; binder_len];
 
     let psk_identity = PresharedKeyIdentity::new(ticket, obfuscated_ticket_age);
@@ -295,37 +386,74 @@ pub(super) fn prepare_resumption(
     exts.push(ClientExtension::PresharedKey(psk_ext));
 }
 
+/// Offer our external PSKs after any ticket we're resuming with, with
+/// empty binders that are filled in later.
+pub(super) fn prepare_external_psks(psks: &[PskOffer], exts: &mut Vec<ClientExtension>) {
+    if !matches!(exts.last(), Some(ClientExtension::PresharedKey(_))) {
+        exts.push(ClientExtension::PresharedKey(PresharedKeyOffer {
+            identities: Vec::new(),
+            binders: Vec::new(),
+        }));
+    }
+
+    if let Some(ClientExtension::PresharedKey(offer)) = exts.last_mut() {
+        for psk in psks {
+            offer
+                .identities
+                .push(PresharedKeyIdentity::new(psk.identity.clone(), 0));
+            offer
+                .binders
+                .push(PresharedKeyBinder::new(vec![
+                    0u8;
+                    psk.suite
+                        .get_hash()
+                        .output_len()
+                ]));
+        }
+    }
+}
+
 pub(super) fn derive_early_traffic_secret(
-    key_log: &dyn KeyLog,
+    config: &ClientConfig,
     cx: &mut ClientContext<'_>,
     resuming_suite: &'static Tls13CipherSuite,
     early_key_schedule: &KeyScheduleEarly,
     sent_tls13_fake_ccs: &mut bool,
     transcript_buffer: &HandshakeHashBuffer,
     client_random: &[u8; 32],
-) {
+) -> Result<(), Error> {
     // For middlebox compatibility
     emit_fake_ccs(sent_tls13_fake_ccs, cx.common);
 
-    let client_hello_hash = transcript_buffer.get_hash_given(resuming_suite.get_hash(), &[]);
-    let client_early_traffic_secret =
-        early_key_schedule.client_early_traffic_secret(&client_hello_hash, key_log, client_random);
+    let client_hello_hash =
+        transcript_buffer.get_hash_given(config.provider, resuming_suite.get_hash(), &[]);
+    let client_early_traffic_secret = early_key_schedule.client_early_traffic_secret(
+        &client_hello_hash,
+        &*config.key_log,
+        client_random,
+    );
+    cx.data.early_exporter = Some(Box::new(early_key_schedule.early_exporter(
+        &client_hello_hash,
+        &*config.key_log,
+        client_random,
+    )));
     // Set early data encryption key
     cx.common
         .record_layer
         .set_message_encrypter(cipher::new_tls13_write(
             resuming_suite,
             &client_early_traffic_secret,
-        ));
+        )?);
 
     #[cfg(feature = "quic")]
     {
-        cx.common.quic.early_secret = Some(client_early_traffic_secret);
+        cx.common.quic.early_secret = Some(quic::ring_prk(&client_early_traffic_secret));
     }
 
     // Now the client can send encrypted early data
     cx.common.early_traffic = true;
     trace!("Starting early data traffic");
+    Ok(())
 }
 
 pub(super) fn emit_fake_ccs(sent_tls13_fake_ccs: &mut bool, common: &mut ConnectionCommon) {
@@ -375,6 +503,37 @@ fn validate_encrypted_extensions(
     Ok(())
 }
 
+/// The certificate types (RFC7250) the server chose in its encrypted
+/// extensions: first for its own certificate, then for ours.
+fn negotiated_cert_types(
+    common: &mut ConnectionCommon,
+    config: &ClientConfig,
+    exts: &EncryptedExtensions,
+) -> Result<(CertificateType, CertificateType), Error> {
+    let server_cert_type = exts.get_server_cert_type();
+    if let Some(typ) = server_cert_type {
+        if !config
+            .verifier
+            .certificate_types()
+            .contains(&typ)
+        {
+            return Err(common.illegal_param("server chose unoffered server certificate type"));
+        }
+    }
+
+    let client_cert_type = exts.get_client_cert_type();
+    if let Some(typ) = client_cert_type {
+        if typ != CertificateType::RawPublicKey && typ != CertificateType::X509 {
+            return Err(common.illegal_param("server chose unoffered client certificate type"));
+        }
+    }
+
+    Ok((
+        server_cert_type.unwrap_or(CertificateType::X509),
+        client_cert_type.unwrap_or(CertificateType::X509),
+    ))
+}
+
 struct ExpectEncryptedExtensions {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
@@ -399,6 +558,12 @@ impl hs::State for ExpectEncryptedExtensions {
         validate_encrypted_extensions(cx.common, &self.hello, exts)?;
         hs::process_alpn_protocol(cx, &self.config, exts.get_alpn_protocol())?;
 
+        if cx.data.ech_status == EchStatus::Rejected {
+            cx.data.ech_retry_configs = exts
+                .get_ech_retry_configs()
+                .map(Codec::get_encoding);
+        }
+
         #[cfg(feature = "quic")]
         {
             // QUIC transport parameters
@@ -418,6 +583,18 @@ impl hs::State for ExpectEncryptedExtensions {
             let was_early_traffic = cx.common.early_traffic;
             if was_early_traffic {
                 if exts.early_data_extension_offered() {
+                    // "If the server accepts early data, it MUST NOT
+                    // select a different ALPN protocol" -- RFC8446, 4.2.10
+                    if resuming_session
+                        .alpn
+                        .as_ref()
+                        .map(|alpn| &alpn.0)
+                        != cx.common.alpn_protocol.as_ref()
+                    {
+                        return Err(cx
+                            .common
+                            .illegal_param("server accepted early data with different ALPN"));
+                    }
                     cx.data.early_data.accepted();
                 } else {
                     cx.data.early_data.rejected();
@@ -432,7 +609,7 @@ impl hs::State for ExpectEncryptedExtensions {
                     .set_message_encrypter(cipher::new_tls13_write(
                         self.suite,
                         self.key_schedule.client_key(),
-                    ));
+                    )?);
             }
 
             cx.data.server_cert_chain = resuming_session
@@ -459,6 +636,26 @@ impl hs::State for ExpectEncryptedExtensions {
                 let msg = "server sent early data extension without resumption".to_string();
                 return Err(Error::PeerMisbehavedError(msg));
             }
+
+            // An external PSK authenticates the server, instead of a certificate.
+            if cx.data.external_psk_identity.is_some() {
+                let cert_verified = verify::ServerCertVerified::assertion();
+                let sig_verified = verify::HandshakeSignatureValid::assertion();
+                return Ok(Box::new(ExpectFinished {
+                    config: self.config,
+                    server_name: self.server_name,
+                    randoms: self.randoms,
+                    suite: self.suite,
+                    transcript: self.transcript,
+                    key_schedule: self.key_schedule,
+                    client_auth: None,
+                    cert_verified,
+                    sig_verified,
+                }));
+            }
+
+            let (server_cert_type, client_cert_type) =
+                negotiated_cert_types(cx.common, &self.config, exts)?;
             Ok(Box::new(ExpectCertificateOrCertReq {
                 config: self.config,
                 server_name: self.server_name,
@@ -467,6 +664,8 @@ impl hs::State for ExpectEncryptedExtensions {
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
                 may_send_sct_list: self.hello.server_may_send_sct_list(),
+                server_cert_type,
+                client_cert_type,
             }))
         }
     }
@@ -480,6 +679,8 @@ struct ExpectCertificateOrCertReq {
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     may_send_sct_list: bool,
+    server_cert_type: CertificateType,
+    client_cert_type: CertificateType,
 }
 
 impl hs::State for ExpectCertificateOrCertReq {
@@ -489,10 +690,11 @@ impl hs::State for ExpectCertificateOrCertReq {
             &[ContentType::Handshake],
             &[
                 HandshakeType::Certificate,
+                HandshakeType::CompressedCertificate,
                 HandshakeType::CertificateRequest,
             ],
         )?;
-        if m.is_handshake_type(HandshakeType::Certificate) {
+        if !m.is_handshake_type(HandshakeType::CertificateRequest) {
             Box::new(ExpectCertificate {
                 config: self.config,
                 server_name: self.server_name,
@@ -501,6 +703,7 @@ impl hs::State for ExpectCertificateOrCertReq {
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
                 may_send_sct_list: self.may_send_sct_list,
+                server_cert_type: self.server_cert_type,
                 client_auth: None,
             })
             .handle(cx, m)
@@ -513,6 +716,8 @@ impl hs::State for ExpectCertificateOrCertReq {
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
                 may_send_sct_list: self.may_send_sct_list,
+                server_cert_type: self.server_cert_type,
+                client_cert_type: self.client_cert_type,
             })
             .handle(cx, m)
         }
@@ -530,6 +735,8 @@ struct ExpectCertificateRequest {
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     may_send_sct_list: bool,
+    server_cert_type: CertificateType,
+    client_cert_type: CertificateType,
 }
 
 impl hs::State for ExpectCertificateRequest {
@@ -560,7 +767,13 @@ impl hs::State for ExpectCertificateRequest {
             .unwrap_or(&no_sigschemes)
             .iter()
             .cloned()
-            .filter(|scheme| tls13_sign_schemes.contains(scheme))
+            .filter(|scheme| {
+                tls13_sign_schemes.contains(scheme)
+                    && self
+                        .config
+                        .signature_schemes
+                        .contains(scheme)
+            })
             .collect::<Vec<SignatureScheme>>();
 
         if compat_sigschemes.is_empty() {
@@ -578,12 +791,21 @@ impl hs::State for ExpectCertificateRequest {
             .iter()
             .map(|p| p.0.as_slice())
             .collect::<Vec<&[u8]>>();
+        // We can only use a certkey which has the type of certificate
+        // the server chose.
+        let client_cert_type = self.client_cert_type;
         let maybe_certkey = self
             .config
             .client_auth_cert_resolver
-            .resolve(&canames, &compat_sigschemes);
+            .resolve(&canames, &compat_sigschemes)
+            .filter(|certkey| {
+                certkey
+                    .certificate_types()
+                    .contains(&client_cert_type)
+            });
 
         let mut client_auth = ClientAuthDetails::new();
+        client_auth.cert_type = client_cert_type;
         if let Some(certkey) = maybe_certkey {
             debug!("Attempting client auth");
             let maybe_signer = certkey
@@ -592,6 +814,11 @@ impl hs::State for ExpectCertificateRequest {
             client_auth.certkey = Some(certkey);
             client_auth.signer = maybe_signer;
             client_auth.auth_context = Some(certreq.context.0.clone());
+            client_auth.compressor = compress::choose_compressor(
+                &self.config.cert_compressors,
+                certreq.get_cert_compression_extension(),
+            )
+            .cloned();
         } else {
             debug!("Client auth requested but no cert selected");
         }
@@ -604,6 +831,7 @@ impl hs::State for ExpectCertificateRequest {
             transcript: self.transcript,
             key_schedule: self.key_schedule,
             may_send_sct_list: self.may_send_sct_list,
+            server_cert_type: self.server_cert_type,
             client_auth: Some(client_auth),
         }))
     }
@@ -617,16 +845,40 @@ struct ExpectCertificate {
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     may_send_sct_list: bool,
+    server_cert_type: CertificateType,
     client_auth: Option<ClientAuthDetails>,
 }
 
 impl hs::State for ExpectCertificate {
     fn handle(mut self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
-        let cert_chain = require_handshake_msg!(
-            m,
-            HandshakeType::Certificate,
-            HandshakePayload::CertificateTLS13
+        check_message(
+            &m,
+            &[ContentType::Handshake],
+            &[
+                HandshakeType::Certificate,
+                HandshakeType::CompressedCertificate,
+            ],
         )?;
+        let decompressed;
+        let cert_chain = match m.payload {
+            MessagePayload::Handshake(HandshakeMessagePayload {
+                payload: HandshakePayload::CompressedCertificate(ref compressed),
+                ..
+            }) => {
+                decompressed = compress::decompress_certificate(
+                    compressed,
+                    &self.config.cert_decompressors,
+                    self.config.max_decompressed_cert_size,
+                    cx.common,
+                )?;
+                &decompressed
+            }
+            _ => require_handshake_msg!(
+                m,
+                HandshakeType::Certificate,
+                HandshakePayload::CertificateTLS13
+            )?,
+        };
         self.transcript.add_message(&m);
 
         // This is only non-empty for client auth.
@@ -648,6 +900,28 @@ impl hs::State for ExpectCertificate {
             ));
         }
 
+        // A raw public key is sent alone, in place of the certificate chain.
+        let raw_public_key = self.server_cert_type == CertificateType::RawPublicKey;
+        if raw_public_key && cert_chain.entries.len() != 1 {
+            return Err(cx
+                .common
+                .illegal_param("server sent wrong number of raw public keys"));
+        }
+
+        let delegated_credential = cert_chain.get_end_entity_delegated_credential();
+        let delegated_credential_allowed =
+            self.config.enable_delegated_credentials && !raw_public_key;
+        if (delegated_credential.is_some() && !delegated_credential_allowed)
+            || cert_chain.any_intermediate_has_delegated_credential()
+        {
+            warn!("server sent unsolicited delegated credential");
+            cx.common
+                .send_fatal_alert(AlertDescription::UnexpectedMessage);
+            return Err(Error::PeerMisbehavedError(
+                "unsolicited delegated credential".to_string(),
+            ));
+        }
+
         let server_cert = ServerCertDetails::new(
             cert_chain.convert(),
             cert_chain.get_end_entity_ocsp(),
@@ -674,6 +948,8 @@ impl hs::State for ExpectCertificate {
             transcript: self.transcript,
             key_schedule: self.key_schedule,
             server_cert,
+            server_cert_type: self.server_cert_type,
+            delegated_credential,
             client_auth: self.client_auth,
         }))
     }
@@ -688,6 +964,8 @@ struct ExpectCertificateVerify {
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     server_cert: ServerCertDetails,
+    server_cert_type: CertificateType,
+    delegated_credential: Option<DelegatedCredentialPayload>,
     client_auth: Option<ClientAuthDetails>,
 }
 
@@ -701,17 +979,20 @@ impl hs::State for ExpectCertificateVerify {
 
         trace!("Server cert is {:?}", self.server_cert.cert_chain);
 
-        // 1. Verify the certificate chain.
+        // 1. Verify the certificate chain, or raw public key.
         let (end_entity, intermediates) = self
             .server_cert
             .cert_chain
             .split_first()
             .ok_or(Error::NoCertificatesPresented)?;
+        let raw_public_key = self.server_cert_type == CertificateType::RawPublicKey;
         let now = std::time::SystemTime::now();
-        let cert_verified = self
-            .config
-            .verifier
-            .verify_server_cert(
+        let cert_verified = if raw_public_key {
+            self.config
+                .verifier
+                .verify_raw_public_key(&end_entity.0, &self.server_name, now)
+        } else {
+            self.config.verifier.verify_server_cert(
                 end_entity,
                 intermediates,
                 &self.server_name,
@@ -719,19 +1000,38 @@ impl hs::State for ExpectCertificateVerify {
                 &self.server_cert.ocsp_response,
                 now,
             )
-            .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
+        }
+        .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
 
-        // 2. Verify their signature on the handshake.
+        // 2. Verify their signature on the handshake.  If they sent a
+        // delegated credential, check it and use its key instead.
         let handshake_hash = self.transcript.get_current_hash();
-        let sig_verified = self
-            .config
-            .verifier
-            .verify_tls13_signature(
-                &verify::construct_tls13_server_verify_message(&handshake_hash),
-                &self.server_cert.cert_chain[0],
+        let message = verify::construct_tls13_server_verify_message(&handshake_hash);
+        verify::check_signature_scheme(&self.config.signature_schemes, cert_verify)
+            .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
+        let sig_verified = match &self.delegated_credential {
+            _ if raw_public_key => verify::verify_tls13_spki(
+                self.config.provider,
+                &message,
+                &end_entity.0,
                 cert_verify,
+            ),
+            Some(dc) => delegated::verify(
+                self.config.provider,
+                dc,
+                end_entity,
+                self.config.verifier.as_ref(),
+                now,
             )
-            .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
+            .and_then(|()| {
+                delegated::verify_signature(self.config.provider, &message, dc, cert_verify)
+            }),
+            None => self
+                .config
+                .verifier
+                .verify_tls13_signature(self.config.provider, &message, end_entity, cert_verify),
+        }
+        .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
 
         cx.data.server_cert_chain = self.server_cert.cert_chain;
         self.transcript.add_message(&m);
@@ -766,19 +1066,28 @@ fn emit_certificate_tls13(
     };
 
     if let Some(cert_key) = &client_auth.certkey {
-        for cert in &cert_key.cert {
-            cert_payload
-                .entries
-                .push(CertificateEntry::new(cert.clone()));
+        match &cert_key.raw_public_key {
+            Some(spki) if client_auth.cert_type == CertificateType::RawPublicKey => {
+                cert_payload
+                    .entries
+                    .push(CertificateEntry::new(key::Certificate(spki.clone())));
+            }
+            _ => {
+                for cert in &cert_key.cert {
+                    cert_payload
+                        .entries
+                        .push(CertificateEntry::new(cert.clone()));
+                }
+            }
         }
     }
 
     let m = Message {
         version: ProtocolVersion::TLSv1_3,
-        payload: MessagePayload::Handshake(HandshakeMessagePayload {
-            typ: HandshakeType::Certificate,
-            payload: HandshakePayload::CertificateTLS13(cert_payload),
-        }),
+        payload: MessagePayload::Handshake(compress::certificate_message(
+            cert_payload,
+            client_auth.compressor.as_deref(),
+        )),
     };
     transcript.add_message(&m);
     common.send_msg(m, true);
@@ -818,7 +1127,7 @@ fn emit_certverify_tls13(
 
 fn emit_finished_tls13(
     transcript: &mut HandshakeHash,
-    verify_data: ring::hmac::Tag,
+    verify_data: Digest,
     common: &mut ConnectionCommon,
 ) {
     let verify_data_payload = Payload::new(verify_data.as_ref());
@@ -883,6 +1192,17 @@ impl hs::State for ExpectFinished {
             })
             .map(|_| verify::FinishedMessageVerified::assertion())?;
 
+        // If the server rejected ECH, it has now authenticated as its
+        // public name -- so we can trust its retry_configs -- but we
+        // mustn't continue with the connection.
+        if cx.data.ech_status == EchStatus::Rejected {
+            cx.common
+                .send_fatal_alert(AlertDescription::EncryptedClientHelloRequired);
+            return Err(Error::EncryptedClientHelloRejected {
+                retry_configs: cx.data.ech_retry_configs.take(),
+            });
+        }
+
         st.transcript.add_message(&m);
 
         let hash_after_handshake = st.transcript.get_current_hash();
@@ -897,7 +1217,7 @@ impl hs::State for ExpectFinished {
                 .set_message_encrypter(cipher::new_tls13_write(
                     st.suite,
                     st.key_schedule.client_key(),
-                ));
+                )?);
         }
 
         /* Send our authentication/finished messages.  These are still encrypted
@@ -924,11 +1244,14 @@ impl hs::State for ExpectFinished {
 
         cx.common
             .record_layer
-            .set_message_decrypter(cipher::new_tls13_read(st.suite, &server_key));
+            .set_message_decrypter(cipher::new_tls13_read(st.suite, &server_key)?);
 
         cx.common
             .record_layer
-            .set_message_encrypter(cipher::new_tls13_write(st.suite, &client_key));
+            .set_message_encrypter(cipher::new_tls13_write(st.suite, &client_key)?);
+        cx.common
+            .record_layer
+            .set_confidentiality_limit(st.suite.confidentiality_limit);
 
         cx.common.start_traffic();
 
@@ -947,10 +1270,7 @@ impl hs::State for ExpectFinished {
         #[cfg(feature = "quic")]
         {
             if cx.common.protocol == Protocol::Quic {
-                cx.common.quic.traffic_secrets = Some(quic::Secrets {
-                    client: client_key,
-                    server: server_key,
-                });
+                cx.common.quic.traffic_secrets = Some(quic::Secrets::new(&client_key, &server_key));
                 return Ok(Box::new(ExpectQuicTraffic(st)));
             }
         }
@@ -997,6 +1317,7 @@ impl ExpectTraffic {
             time_now,
         );
         value.set_times(nst.lifetime, nst.age_add);
+        value.set_alpn(cx.common.alpn_protocol.clone());
 
         if let Some(sz) = nst.get_max_early_data_size() {
             value.set_max_early_data_size(sz);
@@ -1053,6 +1374,7 @@ impl ExpectTraffic {
 
         // Mustn't be interleaved with other handshake messages.
         common.check_aligned_handshake()?;
+        common.count_key_update()?;
 
         match kur {
             KeyUpdateRequest::UpdateNotRequested => {}
@@ -1071,10 +1393,31 @@ impl ExpectTraffic {
             .next_server_application_traffic_secret();
         common
             .record_layer
-            .set_message_decrypter(cipher::new_tls13_read(self.suite, &new_read_key));
+            .set_message_decrypter(cipher::new_tls13_read(self.suite, &new_read_key)?);
 
         Ok(())
     }
+
+    /// Sends `msg` (a `KeyUpdate`) and then switches to our next write key.
+    ///
+    /// This also satisfies any pending request from the peer for us to update.
+    fn write_key_update(
+        &mut self,
+        common: &mut ConnectionCommon,
+        msg: Message,
+    ) -> Result<(), Error> {
+        let write_key = self
+            .key_schedule
+            .next_client_application_traffic_secret();
+        let encrypter = cipher::new_tls13_write(self.suite, &write_key)?;
+
+        self.want_write_key_update = false;
+        common.send_msg_encrypt(msg.into());
+        common
+            .record_layer
+            .set_message_encrypter(encrypter);
+        Ok(())
+    }
 }
 
 impl hs::State for ExpectTraffic {
@@ -1085,6 +1428,7 @@ impl hs::State for ExpectTraffic {
                 .take_received_plaintext(payload),
             MessagePayload::Handshake(payload) => match payload.payload {
                 HandshakePayload::NewSessionTicketTLS13(new_ticket) => {
+                    cx.common.count_new_session_ticket()?;
                     self.handle_new_ticket_tls13(cx, &new_ticket)?
                 }
                 HandshakePayload::KeyUpdate(key_update) => {
@@ -1118,18 +1462,29 @@ impl hs::State for ExpectTraffic {
             .export_keying_material(output, label, context)
     }
 
-    fn perhaps_write_key_update(&mut self, common: &mut ConnectionCommon) {
-        if self.want_write_key_update {
-            self.want_write_key_update = false;
-            common.send_msg_encrypt(Message::build_key_update_notify().into());
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        self.key_schedule
+            .extract_secrets(self.suite, true)
+    }
 
-            let write_key = self
-                .key_schedule
-                .next_client_application_traffic_secret();
-            common
-                .record_layer
-                .set_message_encrypter(cipher::new_tls13_write(self.suite, &write_key));
+    fn perhaps_write_key_update(&mut self, common: &mut ConnectionCommon) -> Result<(), Error> {
+        if self.want_write_key_update || common.record_layer.wants_key_update() {
+            self.write_key_update(common, Message::build_key_update_notify())?;
         }
+        Ok(())
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        common: &mut ConnectionCommon,
+        request_peer_update: bool,
+    ) -> Result<(), Error> {
+        let msg = if request_peer_update {
+            Message::build_key_update_request()
+        } else {
+            Message::build_key_update_notify()
+        };
+        self.write_key_update(common, msg)
     }
 }
 
@@ -1158,4 +1513,20 @@ impl hs::State for ExpectQuicTraffic {
         self.0
             .export_keying_material(output, label, context)
     }
+
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        Err(Error::General(
+            "traffic secrets are managed by QUIC, not TLS".to_string(),
+        ))
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        _common: &mut ConnectionCommon,
+        _request_peer_update: bool,
+    ) -> Result<(), Error> {
+        Err(Error::General(
+            "traffic key updates are managed by QUIC, not TLS".to_string(),
+        ))
+    }
 }
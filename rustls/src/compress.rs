@@ -0,0 +1,248 @@
+use crate::conn::ConnectionCommon;
+use crate::error::Error;
+#[cfg(feature = "logging")]
+use crate::log::{debug, warn};
+use crate::msgs::base::PayloadU24;
+use crate::msgs::codec::Codec;
+use crate::msgs::enums::{AlertDescription, CertificateCompressionAlgorithm};
+use crate::msgs::enums::{ContentType, HandshakeType};
+use crate::msgs::handshake::{CertificatePayloadTLS13, CompressedCertificatePayload};
+use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
+
+use std::sync::Arc;
+
+/// The default value of `max_decompressed_cert_size`: the same limit
+/// we apply to uncompressed handshake messages.
+pub(crate) const DEFAULT_MAX_DECOMPRESSED_CERT_SIZE: usize = 0xffff;
+
+/// The largest length expressible in a CompressedCertificate message.
+const MAX_U24: usize = 0xff_ffff;
+
+/// An implementation of a certificate compression algorithm, for
+/// use with RFC8879.
+///
+/// rustls does not include any compression algorithms itself: to
+/// send compressed certificates, supply implementations of this trait
+/// in `ClientConfig::cert_compressors` or `ServerConfig::cert_compressors`.
+pub trait CertificateCompressor: Send + Sync {
+    /// Which algorithm this is.
+    fn algorithm(&self) -> CertificateCompressionAlgorithm;
+
+    /// Compress `input`, returning the compressed form.
+    ///
+    /// If this fails, the certificate chain is sent uncompressed.
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, CompressionFailed>;
+}
+
+/// An implementation of a certificate decompression algorithm, for
+/// use with RFC8879.
+///
+/// Supply implementations of this trait in `ClientConfig::cert_decompressors`
+/// or `ServerConfig::cert_decompressors` to offer them to the peer.
+pub trait CertificateDecompressor: Send + Sync {
+    /// Which algorithm this is.
+    fn algorithm(&self) -> CertificateCompressionAlgorithm;
+
+    /// Decompress `input` into `output`.
+    ///
+    /// `output` is exactly as long as the peer says the decompressed
+    /// certificate chain is.  Implementations must fail if the decompressed
+    /// data does not exactly fill `output`.
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed>;
+}
+
+/// A `CertificateCompressor` could not compress its input.
+#[derive(Debug)]
+pub struct CompressionFailed;
+
+/// A `CertificateDecompressor` could not decompress its input.
+#[derive(Debug)]
+pub struct DecompressionFailed;
+
+/// The algorithms of `decompressors`, in order, for sending to the peer.
+pub(crate) fn offered_algorithms(
+    decompressors: &[Arc<dyn CertificateDecompressor>],
+) -> Vec<CertificateCompressionAlgorithm> {
+    decompressors
+        .iter()
+        .map(|d| d.algorithm())
+        .collect()
+}
+
+/// Choose the first of our `compressors` which the peer `offered`.
+pub(crate) fn choose_compressor<'a>(
+    compressors: &'a [Arc<dyn CertificateCompressor>],
+    offered: Option<&Vec<CertificateCompressionAlgorithm>>,
+) -> Option<&'a Arc<dyn CertificateCompressor>> {
+    let offered = offered?;
+    compressors
+        .iter()
+        .find(|c| offered.contains(&c.algorithm()))
+}
+
+/// Make a Certificate message carrying `cert_payload`, or a
+/// CompressedCertificate message if we have a `compressor` and
+/// it works.
+pub(crate) fn certificate_message(
+    cert_payload: CertificatePayloadTLS13,
+    compressor: Option<&dyn CertificateCompressor>,
+) -> HandshakeMessagePayload {
+    if let Some(compressor) = compressor {
+        let uncompressed = cert_payload.get_encoding();
+        match compressor.compress(&uncompressed) {
+            Ok(compressed) if uncompressed.len() <= MAX_U24 && compressed.len() <= MAX_U24 => {
+                debug!(
+                    "Compressed certificate with {:?}: {} -> {} bytes",
+                    compressor.algorithm(),
+                    uncompressed.len(),
+                    compressed.len()
+                );
+                return HandshakeMessagePayload {
+                    typ: HandshakeType::CompressedCertificate,
+                    payload: HandshakePayload::CompressedCertificate(
+                        CompressedCertificatePayload {
+                            alg: compressor.algorithm(),
+                            uncompressed_len: uncompressed.len() as u32,
+                            compressed: PayloadU24::new(compressed),
+                        },
+                    ),
+                };
+            }
+            _ => {
+                warn!(
+                    "Certificate compression with {:?} failed; sending uncompressed",
+                    compressor.algorithm()
+                );
+            }
+        }
+    }
+
+    HandshakeMessagePayload {
+        typ: HandshakeType::Certificate,
+        payload: HandshakePayload::CertificateTLS13(cert_payload),
+    }
+}
+
+/// Decompress and decode the peer's `compressed` certificate chain, using
+/// one of the `decompressors` we offered.
+pub(crate) fn decompress_certificate(
+    compressed: &CompressedCertificatePayload,
+    decompressors: &[Arc<dyn CertificateDecompressor>],
+    max_decompressed_size: usize,
+    common: &mut ConnectionCommon,
+) -> Result<CertificatePayloadTLS13, Error> {
+    let decompressor = match decompressors
+        .iter()
+        .find(|d| d.algorithm() == compressed.alg)
+    {
+        Some(decompressor) => decompressor,
+        None => {
+            common.send_fatal_alert(AlertDescription::IllegalParameter);
+            return Err(Error::PeerMisbehavedError(
+                "certificate compressed with unoffered algorithm".to_string(),
+            ));
+        }
+    };
+
+    let len = compressed.uncompressed_len as usize;
+    if len == 0 || len > max_decompressed_size {
+        warn!("Refusing to decompress certificate to {} bytes", len);
+        common.send_fatal_alert(AlertDescription::BadCertificate);
+        return Err(Error::CertificateDecompressionFailed);
+    }
+
+    let mut uncompressed = vec![0u8; len];
+    if decompressor
+        .decompress(&compressed.compressed.0, &mut uncompressed)
+        .is_err()
+    {
+        common.send_fatal_alert(AlertDescription::BadCertificate);
+        return Err(Error::CertificateDecompressionFailed);
+    }
+
+    CertificatePayloadTLS13::read_bytes(&uncompressed).ok_or_else(|| {
+        common.send_fatal_alert(AlertDescription::DecodeError);
+        Error::CorruptMessagePayload(ContentType::Handshake)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key::Certificate;
+    use crate::msgs::handshake::CertificateEntry;
+
+    struct Reverse;
+
+    impl CertificateCompressor for Reverse {
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Unknown(0xff00)
+        }
+
+        fn compress(&self, input: &[u8]) -> Result<Vec<u8>, CompressionFailed> {
+            Ok(input.iter().rev().cloned().collect())
+        }
+    }
+
+    struct Broken;
+
+    impl CertificateCompressor for Broken {
+        fn algorithm(&self) -> CertificateCompressionAlgorithm {
+            CertificateCompressionAlgorithm::Zlib
+        }
+
+        fn compress(&self, _input: &[u8]) -> Result<Vec<u8>, CompressionFailed> {
+            Err(CompressionFailed)
+        }
+    }
+
+    fn sample_payload() -> CertificatePayloadTLS13 {
+        CertificatePayloadTLS13::new(vec![CertificateEntry::new(Certificate(vec![1, 2, 3]))])
+    }
+
+    #[test]
+    fn chooses_first_offered_compressor() {
+        let compressors: Vec<Arc<dyn CertificateCompressor>> =
+            vec![Arc::new(Broken), Arc::new(Reverse)];
+
+        let chosen = choose_compressor(
+            &compressors,
+            Some(&vec![CertificateCompressionAlgorithm::Unknown(0xff00)]),
+        );
+        assert_eq!(
+            chosen.map(|c| c.algorithm()),
+            Some(CertificateCompressionAlgorithm::Unknown(0xff00))
+        );
+
+        let chosen = choose_compressor(
+            &compressors,
+            Some(&vec![CertificateCompressionAlgorithm::Zstd]),
+        );
+        assert!(chosen.is_none());
+        assert!(choose_compressor(&compressors, None).is_none());
+    }
+
+    #[test]
+    fn compresses_certificate() {
+        let m = certificate_message(sample_payload(), Some(&Reverse));
+        assert_eq!(m.typ, HandshakeType::CompressedCertificate);
+        match m.payload {
+            HandshakePayload::CompressedCertificate(cc) => {
+                let mut expected = sample_payload().get_encoding();
+                assert_eq!(cc.uncompressed_len as usize, expected.len());
+                expected.reverse();
+                assert_eq!(cc.compressed.0, expected);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn falls_back_to_uncompressed_certificate() {
+        let m = certificate_message(sample_payload(), Some(&Broken));
+        assert_eq!(m.typ, HandshakeType::Certificate);
+
+        let m = certificate_message(sample_payload(), None);
+        assert_eq!(m.typ, HandshakeType::Certificate);
+    }
+}
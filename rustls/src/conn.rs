@@ -1,4 +1,6 @@
+use crate::check;
 use crate::cipher;
+use crate::crypto::{CryptoProvider, Digest};
 use crate::error::Error;
 use crate::key;
 #[cfg(feature = "logging")]
@@ -18,11 +20,12 @@ use crate::msgs::message::{
 use crate::prf;
 use crate::quic;
 use crate::record_layer;
-use crate::suites::{SupportedCipherSuite, Tls12CipherSuite};
+use crate::suites::{
+    ConnectionTrafficSecrets, ExtractedSecrets, PartiallyExtractedSecrets, SupportedCipherSuite,
+    Tls12CipherSuite,
+};
 use crate::vecbuf::ChunkVecBuffer;
 
-use ring::digest::Digest;
-
 use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::io;
@@ -271,6 +274,9 @@ pub trait Connection: quic::QuicExt + Send + Sync {
     /// For servers, this is the certificate chain of the client,
     /// if client authentication was completed.
     ///
+    /// If the peer authenticated with a raw public key (RFC7250), this
+    /// instead holds only its DER-encoded SubjectPublicKeyInfo.
+    ///
     /// The return value is None until this value is available.
     fn peer_certificates(&self) -> Option<&[key::Certificate]>;
 
@@ -306,6 +312,35 @@ pub trait Connection: quic::QuicExt + Send + Sync {
         context: Option<&[u8]>,
     ) -> Result<(), Error>;
 
+    /// Consumes the connection, returning the keys, IVs and sequence
+    /// numbers needed to carry on its traffic elsewhere -- for example,
+    /// by handing the socket to kernel TLS.
+    ///
+    /// This function fails if called prior to the handshake completing,
+    /// for QUIC connections, and for TLS1.3 cipher suites whose AEAD is not
+    /// a [`BulkAlgorithm`](crate::BulkAlgorithm).  It also fails if TLS data is waiting to
+    /// be written, or received data is waiting to be read or processed:
+    /// call [`Connection::write_tls`] until [`Connection::wants_write`] is
+    /// false, and read all plaintext, first.
+    fn extract_secrets(self) -> Result<ExtractedSecrets, Error>
+    where
+        Self: Sized;
+
+    /// Replaces our traffic keys for data we send.
+    ///
+    /// This queues a TLS1.3 `KeyUpdate` message and switches to fresh
+    /// write keys for all subsequent data.  If `request_peer_update` is
+    /// true, the peer is also asked to update its keys in return.
+    ///
+    /// Applications only need this if they wish to limit the amount of
+    /// data protected by one key, beyond the limits rustls already
+    /// enforces.
+    ///
+    /// This function fails if called prior to the handshake completing,
+    /// for TLS1.2 connections (which have no equivalent mechanism), and
+    /// for QUIC connections (where key updates are handled by QUIC itself).
+    fn refresh_traffic_keys(&mut self, request_peer_update: bool) -> Result<(), Error>;
+
     /// Retrieves the ciphersuite agreed with the peer.
     ///
     /// This returns None until the ciphersuite is agreed.
@@ -436,18 +471,21 @@ fn join_randoms(first: &[u8; 32], second: &[u8; 32]) -> [u8; 64] {
 /// TLS1.2 per-connection keying material
 pub(crate) struct ConnectionSecrets {
     pub(crate) randoms: ConnectionRandoms,
+    provider: &'static dyn CryptoProvider,
     suite: &'static Tls12CipherSuite,
     pub(crate) master_secret: [u8; 48],
 }
 
 impl ConnectionSecrets {
     pub(crate) fn new(
+        provider: &'static dyn CryptoProvider,
         randoms: &ConnectionRandoms,
         suite: &'static Tls12CipherSuite,
         pms: &[u8],
     ) -> Self {
         let mut ret = Self {
             randoms: randoms.clone(),
+            provider,
             suite,
             master_secret: [0u8; 48],
         };
@@ -455,7 +493,8 @@ impl ConnectionSecrets {
         let randoms = join_randoms(&ret.randoms.client, &ret.randoms.server);
         prf::prf(
             &mut ret.master_secret,
-            suite.hmac_algorithm,
+            provider,
+            suite.hash_algorithm,
             pms,
             b"master secret",
             &randoms,
@@ -464,6 +503,7 @@ impl ConnectionSecrets {
     }
 
     pub(crate) fn new_ems(
+        provider: &'static dyn CryptoProvider,
         randoms: &ConnectionRandoms,
         hs_hash: &Digest,
         suite: &'static Tls12CipherSuite,
@@ -471,13 +511,15 @@ impl ConnectionSecrets {
     ) -> Self {
         let mut ret = Self {
             randoms: randoms.clone(),
+            provider,
             master_secret: [0u8; 48],
             suite,
         };
 
         prf::prf(
             &mut ret.master_secret,
-            suite.hmac_algorithm,
+            provider,
+            suite.hash_algorithm,
             pms,
             b"extended master secret",
             hs_hash.as_ref(),
@@ -486,12 +528,14 @@ impl ConnectionSecrets {
     }
 
     pub(crate) fn new_resume(
+        provider: &'static dyn CryptoProvider,
         randoms: &ConnectionRandoms,
         suite: &'static Tls12CipherSuite,
         master_secret: &[u8],
     ) -> Self {
         let mut ret = Self {
             randoms: randoms.clone(),
+            provider,
             suite,
             master_secret: [0u8; 48],
         };
@@ -502,10 +546,8 @@ impl ConnectionSecrets {
 
     pub(crate) fn make_key_block(&self) -> Vec<u8> {
         let suite = &self.suite;
-        let common = &self.suite.common;
 
-        let len =
-            (common.aead_algorithm.key_len() + suite.fixed_iv_len) * 2 + suite.explicit_nonce_len;
+        let len = (suite.bulk.key_len() + suite.fixed_iv_len) * 2 + suite.explicit_nonce_len;
 
         let mut out = Vec::new();
         out.resize(len, 0u8);
@@ -515,7 +557,8 @@ impl ConnectionSecrets {
         let randoms = join_randoms(&self.randoms.server, &self.randoms.client);
         prf::prf(
             &mut out,
-            self.suite.hmac_algorithm,
+            self.provider,
+            self.suite.hash_algorithm,
             &self.master_secret,
             b"key expansion",
             &randoms,
@@ -528,6 +571,41 @@ impl ConnectionSecrets {
         self.suite
     }
 
+    pub(crate) fn provider(&self) -> &'static dyn CryptoProvider {
+        self.provider
+    }
+
+    /// The keys and IVs made by `cipher::new_tls12`, in a form which can be
+    /// handed to another TLS implementation.
+    pub(crate) fn extract_secrets(&self) -> PartiallyExtractedSecrets {
+        let key_block = self.make_key_block();
+        let bulk = &self.suite.bulk;
+        let key_len = bulk.key_len();
+
+        let (client_key, key_block) = key_block.split_at(key_len);
+        let (server_key, key_block) = key_block.split_at(key_len);
+        let (client_iv, key_block) = key_block.split_at(self.suite.fixed_iv_len);
+        let (server_iv, extra) = key_block.split_at(self.suite.fixed_iv_len);
+
+        let (write_key, write_iv, read_key, read_iv) = match self.randoms.we_are_client {
+            true => (client_key, client_iv, server_key, server_iv),
+            false => (server_key, server_iv, client_key, client_iv),
+        };
+
+        // The fixed part and our explicit nonce offset make up the write
+        // nonce; the peer's explicit nonces come with each record.
+        let mut tx_iv = [0u8; 12];
+        tx_iv[..write_iv.len()].copy_from_slice(write_iv);
+        tx_iv[write_iv.len()..write_iv.len() + extra.len()].copy_from_slice(extra);
+        let mut rx_iv = [0u8; 12];
+        rx_iv[..read_iv.len()].copy_from_slice(read_iv);
+
+        PartiallyExtractedSecrets {
+            tx: ConnectionTrafficSecrets::new(bulk, write_key, &tx_iv),
+            rx: ConnectionTrafficSecrets::new(bulk, read_key, &rx_iv),
+        }
+    }
+
     pub(crate) fn get_master_secret(&self) -> Vec<u8> {
         let mut ret = Vec::new();
         ret.extend_from_slice(&self.master_secret);
@@ -540,7 +618,8 @@ impl ConnectionSecrets {
 
         prf::prf(
             &mut out,
-            self.suite.hmac_algorithm,
+            self.provider,
+            self.suite.hash_algorithm,
             &self.master_secret,
             label,
             handshake_hash.as_ref(),
@@ -573,7 +652,8 @@ impl ConnectionSecrets {
 
         prf::prf(
             output,
-            self.suite.hmac_algorithm,
+            self.provider,
+            self.suite.hash_algorithm,
             &self.master_secret,
             label,
             &randoms,
@@ -581,6 +661,62 @@ impl ConnectionSecrets {
     }
 }
 
+/// Limits on messages a peer may send which cost us effort, but do not
+/// advance the connection.
+///
+/// Each limit counts messages received in a row, without any application
+/// data in between.  A peer exceeding a limit is sent a fatal alert and
+/// the connection fails with the matching `Error`.
+///
+/// The defaults are generous for well-behaved peers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerMessageLimits {
+    /// How many TLS1.3 `KeyUpdate` messages the peer may send.
+    ///
+    /// Exceeding this gives `Error::PeerSentTooManyKeyUpdates`.  The
+    /// default is 32.
+    pub key_updates: usize,
+
+    /// How many TLS1.3 `NewSessionTicket` messages a server may send.
+    ///
+    /// Exceeding this gives `Error::PeerSentTooManyTickets`.  The
+    /// default is 32.
+    pub new_session_tickets: usize,
+
+    /// How many warning alerts (other than `close_notify`) the peer may send.
+    ///
+    /// Exceeding this gives `Error::PeerSentTooManyWarningAlerts`.  The
+    /// default is 4.
+    pub warning_alerts: usize,
+
+    /// How many records with no content the peer may send.
+    ///
+    /// Exceeding this gives `Error::PeerSentTooManyEmptyRecords`.  The
+    /// default is 32.
+    pub empty_records: usize,
+}
+
+impl Default for PeerMessageLimits {
+    fn default() -> Self {
+        Self {
+            key_updates: 32,
+            new_session_tickets: 32,
+            warning_alerts: 4,
+            empty_records: 32,
+        }
+    }
+}
+
+/// How many of each message limited by `PeerMessageLimits` we've
+/// received since the last application data.
+#[derive(Default)]
+struct PeerMessageCounts {
+    key_updates: usize,
+    new_session_tickets: usize,
+    warning_alerts: usize,
+    empty_records: usize,
+}
+
 // --- Common (to client and server) connection functions ---
 
 enum Limit {
@@ -588,6 +724,11 @@ enum Limit {
     No,
 }
 
+/// Sends a TLS1.3 `KeyUpdate` and switches to the next write key, if
+/// the connection's state wants one.  The key schedule lives in that
+/// state, so this is supplied by the connection.
+pub(crate) type KeyUpdater<'a> = &'a mut dyn FnMut(&mut ConnectionCommon) -> Result<(), Error>;
+
 pub(crate) struct ConnectionCommon {
     pub(crate) negotiated_version: Option<ProtocolVersion>,
     pub(crate) is_client: bool,
@@ -609,12 +750,18 @@ pub(crate) struct ConnectionCommon {
     #[allow(dead_code)] // only read for QUIC
     /// Protocol whose key schedule should be used. Unused for TLS < 1.3.
     pub(crate) protocol: Protocol,
+    peer_limits: PeerMessageLimits,
+    peer_counts: PeerMessageCounts,
     #[cfg(feature = "quic")]
     pub(crate) quic: Quic,
 }
 
 impl ConnectionCommon {
-    pub(crate) fn new(max_fragment_size: Option<usize>, client: bool) -> Result<Self, Error> {
+    pub(crate) fn new(
+        max_fragment_size: Option<usize>,
+        peer_limits: PeerMessageLimits,
+        client: bool,
+    ) -> Result<Self, Error> {
         Ok(Self {
             negotiated_version: None,
             is_client: client,
@@ -635,11 +782,26 @@ impl ConnectionCommon {
             sendable_plaintext: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
             sendable_tls: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
             protocol: Protocol::Tcp,
+            peer_limits,
+            peer_counts: PeerMessageCounts::default(),
             #[cfg(feature = "quic")]
             quic: Quic::new(),
         })
     }
 
+    /// Adopt the message size and peer message limits of a
+    /// configuration chosen after this connection started.
+    pub(crate) fn set_limits(
+        &mut self,
+        max_fragment_size: Option<usize>,
+        peer_limits: PeerMessageLimits,
+    ) -> Result<(), Error> {
+        self.message_fragmenter =
+            MessageFragmenter::new(max_fragment_size).map_err(|_| Error::BadMaxFragmentSize)?;
+        self.peer_limits = peer_limits;
+        Ok(())
+    }
+
     pub(crate) fn reader(&mut self) -> Reader {
         Reader { common: self }
     }
@@ -674,12 +836,30 @@ impl ConnectionCommon {
             }
         }
 
-        // Decrypt if demanded by current state.
-        let msg = match self.record_layer.is_decrypting() {
-            true => self.decrypt_incoming(msg)?,
-            false => msg.into_plain_message(),
+        // Decrypt if demanded by current state.  A server which rejected
+        // the client's early data may need to skip over it.
+        let msg = if self.record_layer.is_decrypting() {
+            match self.decrypt_incoming(msg)? {
+                Some(msg) => msg,
+                None => return Ok(None),
+            }
+        } else if msg.typ == ContentType::ApplicationData
+            && self
+                .record_layer
+                .doing_trial_decryption(msg.payload.0.len())
+        {
+            trace!("Dropping early_data after HelloRetryRequest");
+            return Ok(None);
+        } else {
+            msg.into_plain_message()
         };
 
+        // Empty records are legal, but also a cheap way for a peer
+        // to keep us busy.
+        if msg.payload.0.is_empty() {
+            self.count_empty_record()?;
+        }
+
         // For handshake messages, we need to join them before parsing
         // and processing.
         if self.handshake_joiner.want_message(&msg) {
@@ -716,6 +896,13 @@ impl ConnectionCommon {
             return Err(Error::CorruptMessage);
         }
 
+        // Handshake messages may already be joined if an `Acceptor`
+        // read the ClientHello before this connection was made.
+        if let Err(err) = self.process_new_handshake_messages(state, data) {
+            self.error = Some(err.clone());
+            return Err(err);
+        }
+
         while let Some(msg) = self.message_deframer.frames.pop_front() {
             let result = self
                 .process_msg(msg)
@@ -736,6 +923,45 @@ impl ConnectionCommon {
         Ok(self.current_io_state())
     }
 
+    /// Process received records only until the first handshake message
+    /// is complete, and return it.  Any later records are left for
+    /// `process_new_packets`.
+    pub(crate) fn first_handshake_message(&mut self) -> Result<Option<Message>, Error> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        if self.message_deframer.desynced {
+            return Err(Error::CorruptMessage);
+        }
+
+        while self.handshake_joiner.frames.is_empty() {
+            let msg = match self.message_deframer.frames.pop_front() {
+                Some(msg) => msg,
+                None => return Ok(None),
+            };
+
+            let result = match self.process_msg(msg) {
+                Ok(Some(MessageType::Data(msg))) => {
+                    self.send_fatal_alert(AlertDescription::UnexpectedMessage);
+                    Err(check::inappropriate_message(
+                        &msg,
+                        &[ContentType::Handshake],
+                    ))
+                }
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                self.error = Some(err.clone());
+                return Err(err);
+            }
+        }
+
+        Ok(self.handshake_joiner.frames.pop_front())
+    }
+
     pub(crate) fn process_new_handshake_messages<S: HandleState>(
         &mut self,
         state: &mut Option<S>,
@@ -809,13 +1035,48 @@ impl ConnectionCommon {
         self.suite
     }
 
+    /// Pair the traffic secrets from the handshake state with our
+    /// sequence numbers, provided nothing is left buffered which would
+    /// be lost when the connection is taken apart.
+    pub(crate) fn extract_secrets(
+        &self,
+        secrets: PartiallyExtractedSecrets,
+    ) -> Result<ExtractedSecrets, Error> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        if !self.sendable_tls.is_empty() {
+            return Err(Error::General(
+                "buffered TLS data must be written before extracting secrets".into(),
+            ));
+        }
+
+        if !self.received_plaintext.is_empty()
+            || self.message_deframer.has_pending()
+            || !self.handshake_joiner.is_empty()
+        {
+            return Err(Error::General(
+                "buffered received data must be read before extracting secrets".into(),
+            ));
+        }
+
+        Ok(ExtractedSecrets {
+            tx: (self.record_layer.write_seq(), secrets.tx),
+            rx: (self.record_layer.read_seq(), secrets.rx),
+        })
+    }
+
     pub(crate) fn get_alpn_protocol(&self) -> Option<&[u8]> {
         self.alpn_protocol
             .as_ref()
             .map(AsRef::as_ref)
     }
 
-    pub(crate) fn decrypt_incoming(&mut self, encr: OpaqueMessage) -> Result<PlainMessage, Error> {
+    pub(crate) fn decrypt_incoming(
+        &mut self,
+        encr: OpaqueMessage,
+    ) -> Result<Option<PlainMessage>, Error> {
         if self
             .record_layer
             .wants_close_before_decrypt()
@@ -867,7 +1128,7 @@ impl ConnectionCommon {
                 self.send_fatal_alert(AlertDescription::DecodeError);
             } else {
                 warn!("TLS alert warning received: {:#?}", alert);
-                return Ok(());
+                return self.count_warning_alert();
             }
         }
 
@@ -915,12 +1176,55 @@ impl ConnectionCommon {
         len
     }
 
+    /// Like send_appdata_encrypt, but `key_update` is called before any
+    /// fragment which would be encrypted under a write key nearing its
+    /// confidentiality limit, so a TLS1.3 connection can send a
+    /// `KeyUpdate` in time.
+    ///
+    /// If `key_update` fails after some fragments were queued, this
+    /// returns the number of bytes they hold.  The key still needs
+    /// updating, so the error recurs on the next write.
+    fn send_appdata_encrypt_with_key_updates(
+        &mut self,
+        payload: &[u8],
+        key_update: KeyUpdater,
+    ) -> Result<usize, Error> {
+        let len = self
+            .sendable_tls
+            .apply_limit(payload.len());
+
+        let mut plain_messages = VecDeque::new();
+        self.message_fragmenter.fragment_borrow(
+            ContentType::ApplicationData,
+            ProtocolVersion::TLSv1_2,
+            &payload[..len],
+            &mut plain_messages,
+        );
+
+        let mut sent = 0;
+        for m in plain_messages {
+            if self.record_layer.wants_key_update() {
+                if let Err(err) = key_update(self) {
+                    return if sent > 0 { Ok(sent) } else { Err(err) };
+                }
+            }
+            sent += m.payload.len();
+            self.send_single_fragment(m);
+        }
+
+        Ok(sent)
+    }
+
     fn send_single_fragment(&mut self, m: BorrowedPlainMessage) {
         // Close connection once we start to run out of
-        // sequence space.
-        if self
-            .record_layer
-            .wants_close_before_encrypt()
+        // sequence space, or our key nears its confidentiality
+        // limit.  TLS1.3 connections should have sent a KeyUpdate
+        // well before that.  The close_notify itself comes back
+        // through here, so alerts are exempt.
+        if m.typ != ContentType::Alert
+            && self
+                .record_layer
+                .wants_close_before_encrypt()
         {
             self.send_close_notify();
         }
@@ -957,9 +1261,20 @@ impl ConnectionCommon {
     /// encrypting it as it goes out.
     ///
     /// If internal buffers are too small, this function will not accept
-    /// all the data.
-    pub(crate) fn send_some_plaintext(&mut self, data: &[u8]) -> usize {
-        self.send_plain(data, Limit::Yes)
+    /// all the data.  `key_update` replaces the write key as it nears
+    /// its confidentiality limit; see
+    /// `send_appdata_encrypt_with_key_updates`.
+    pub(crate) fn send_some_plaintext(
+        &mut self,
+        data: &[u8],
+        key_update: KeyUpdater,
+    ) -> Result<usize, Error> {
+        if !self.traffic || data.is_empty() {
+            // Buffered until the handshake completes, or nothing to send.
+            return Ok(self.send_plain(data, Limit::Yes));
+        }
+
+        self.send_appdata_encrypt_with_key_updates(data, key_update)
     }
 
     pub(crate) fn send_early_plaintext(&mut self, data: &[u8]) -> usize {
@@ -1016,6 +1331,9 @@ impl ConnectionCommon {
             return;
         }
 
+        // The write key is fresh here, so this cannot need a key
+        // update without very large buffer limits; the record layer
+        // closes the connection if it does.
         while let Some(buf) = self.sendable_plaintext.pop() {
             self.send_plain(&buf, Limit::No);
         }
@@ -1060,9 +1378,60 @@ impl ConnectionCommon {
     }
 
     pub(crate) fn take_received_plaintext(&mut self, bytes: Payload) {
+        if !bytes.0.is_empty() {
+            self.peer_counts = PeerMessageCounts::default();
+        }
         self.received_plaintext.append(bytes.0);
     }
 
+    /// Account for a `KeyUpdate` received from the peer.
+    pub(crate) fn count_key_update(&mut self) -> Result<(), Error> {
+        if exceeds_limit(
+            &mut self.peer_counts.key_updates,
+            self.peer_limits.key_updates,
+        ) {
+            return Err(self.peer_limit_exceeded(Error::PeerSentTooManyKeyUpdates));
+        }
+        Ok(())
+    }
+
+    /// Account for a `NewSessionTicket` received from the peer.
+    pub(crate) fn count_new_session_ticket(&mut self) -> Result<(), Error> {
+        if exceeds_limit(
+            &mut self.peer_counts.new_session_tickets,
+            self.peer_limits.new_session_tickets,
+        ) {
+            return Err(self.peer_limit_exceeded(Error::PeerSentTooManyTickets));
+        }
+        Ok(())
+    }
+
+    fn count_warning_alert(&mut self) -> Result<(), Error> {
+        if exceeds_limit(
+            &mut self.peer_counts.warning_alerts,
+            self.peer_limits.warning_alerts,
+        ) {
+            return Err(self.peer_limit_exceeded(Error::PeerSentTooManyWarningAlerts));
+        }
+        Ok(())
+    }
+
+    fn count_empty_record(&mut self) -> Result<(), Error> {
+        if exceeds_limit(
+            &mut self.peer_counts.empty_records,
+            self.peer_limits.empty_records,
+        ) {
+            return Err(self.peer_limit_exceeded(Error::PeerSentTooManyEmptyRecords));
+        }
+        Ok(())
+    }
+
+    fn peer_limit_exceeded(&mut self, err: Error) -> Error {
+        warn!("{}", err);
+        self.send_fatal_alert(AlertDescription::UnexpectedMessage);
+        err
+    }
+
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let len = self.received_plaintext.read(buf)?;
 
@@ -1078,15 +1447,18 @@ impl ConnectionCommon {
         Ok(len)
     }
 
-    pub(crate) fn start_encryption_tls12(&mut self, secrets: &ConnectionSecrets) {
-        let (dec, enc) = cipher::new_tls12(secrets);
+    pub(crate) fn start_encryption_tls12(
+        &mut self,
+        secrets: &ConnectionSecrets,
+    ) -> Result<(), Error> {
+        let (dec, enc) = cipher::new_tls12(secrets)?;
         self.record_layer
             .prepare_message_encrypter(enc);
         self.record_layer
             .prepare_message_decrypter(dec);
+        Ok(())
     }
 
-    #[cfg(feature = "quic")]
     pub(crate) fn missing_extension(&mut self, why: &str) -> Error {
         self.send_fatal_alert(AlertDescription::MissingExtension);
         Error::PeerMisbehavedError(why.to_string())
@@ -1170,3 +1542,10 @@ impl Quic {
 }
 
 const DEFAULT_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// Count one more message against `limit`, returning true if this
+/// takes us over it.
+fn exceeds_limit(count: &mut usize, limit: usize) -> bool {
+    *count = count.saturating_add(1);
+    *count > limit
+}
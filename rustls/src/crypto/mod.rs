@@ -0,0 +1,475 @@
+//! Pluggable cryptography: see [`CryptoProvider`].
+//!
+//! The default provider, [`ring::RING`], uses *ring* -- and, for
+//! algorithms *ring* lacks, rustls's own code.  Some of those need a
+//! crate feature: `secp521r1` uses the `p521` crate, and `aes-ccm` the
+//! `ccm` crate.
+
+use crate::error::Error;
+use crate::key;
+use crate::kx::SupportedKxGroup;
+use crate::msgs::enums::{NamedGroup, SignatureScheme};
+use crate::sign::{SignError, SigningKey};
+use crate::suites::BulkAlgorithm;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// The default cryptography provider, using *ring*.
+pub mod ring;
+
+pub use crate::rand::GetRandomFailed;
+
+/// The length of the nonces given to an [`AeadKey`].
+pub const NONCE_LEN: usize = 12;
+
+/// The longest output of any [`HashAlgorithm`].
+const MAX_OUTPUT_LEN: usize = 64;
+
+/// A hash function used by rustls.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashAlgorithm {
+    /// SHA-1, from FIPS 180-4.  rustls only uses this to identify
+    /// certificates in OCSP responses, as RFC6960 requires.
+    SHA1,
+
+    /// SHA-256, from FIPS 180-4.
+    SHA256,
+
+    /// SHA-384, from FIPS 180-4.
+    SHA384,
+
+    /// SHA-512, from FIPS 180-4.
+    SHA512,
+}
+
+impl HashAlgorithm {
+    /// The length of this hash function's output, in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            Self::SHA1 => 20,
+            Self::SHA256 => 32,
+            Self::SHA384 => 48,
+            Self::SHA512 => 64,
+        }
+    }
+}
+
+/// The output of a hash function, or of HMAC.
+#[derive(Clone)]
+pub struct Digest {
+    buf: [u8; MAX_OUTPUT_LEN],
+    len: usize,
+}
+
+impl Digest {
+    /// Wrap `bytes`, which must be no longer than the output
+    /// of SHA-512.
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut buf = [0u8; MAX_OUTPUT_LEN];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Self {
+            buf,
+            len: bytes.len(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for Digest {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Digest")
+            .field(&self.as_ref())
+            .finish()
+    }
+}
+
+/// An in-progress hash computation.
+pub trait HashContext: Send + Sync {
+    /// Which hash function this is.
+    fn algorithm(&self) -> HashAlgorithm;
+
+    /// Add `data` to the input.
+    fn update(&mut self, data: &[u8]);
+
+    /// Make an independent copy of this computation, so the hash of
+    /// the input so far can be taken without ending it.
+    fn fork(&self) -> Box<dyn HashContext>;
+
+    /// Finish the computation, returning the hash of the input.
+    fn finish(self: Box<Self>) -> Digest;
+}
+
+/// A key for an AEAD algorithm.
+pub trait AeadKey: Send + Sync {
+    /// Encrypt `in_out[prefix_len..]` in place, appending the
+    /// authentication tag.  The first `prefix_len` bytes of `in_out`
+    /// are left alone.
+    fn seal_in_place(
+        &self,
+        nonce: [u8; NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+        prefix_len: usize,
+    ) -> Result<(), Error>;
+
+    /// Decrypt and authenticate `in_out[prefix_len..]`, which is a
+    /// ciphertext followed by its tag, in place.  On success the
+    /// plaintext is moved to the start of `in_out`, over the prefix
+    /// (like *ring*'s `open_within`), and its length is returned.
+    fn open_in_place(
+        &self,
+        nonce: [u8; NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut [u8],
+        prefix_len: usize,
+    ) -> Result<usize, Error>;
+
+    /// The length of the authentication tag.
+    fn tag_len(&self) -> usize;
+}
+
+/// Our half of an in-progress key exchange.
+pub trait ActiveKeyExchange: Send + Sync {
+    /// The group being used.
+    fn group(&self) -> NamedGroup;
+
+    /// Our public key, or key share, to send to the peer.
+    fn pub_key(&self) -> &[u8];
+
+    /// Complete the key exchange, given the peer's public key.
+    ///
+    /// Returns the shared secret, or `None` if `peer_pub_key` is invalid.
+    fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// A long-term X25519 private key, such as a server's Encrypted
+/// Client Hello key.
+///
+/// Unlike the ephemeral keys of [`ActiveKeyExchange`], this can be
+/// used for any number of key agreements.
+pub trait StaticX25519Key: Send + Sync {
+    /// Our public key.
+    fn public_key(&self) -> &[u8];
+
+    /// Do X25519 with the peer's public key, returning the shared secret.
+    ///
+    /// Returns `None` if `peer_pub_key` is invalid, or if the shared
+    /// secret is all zeroes (RFC7748 section 6.1).
+    fn agree(&self, peer_pub_key: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// The result of a key exchange.  This has our public key,
+/// and the agreed shared secret (also known as the "premaster secret"
+/// in TLS1.0-era protocols, and "Z" in TLS1.3).
+pub struct KeyExchangeResult {
+    /// Our public key, or key share, to send to the peer.
+    pub pubkey: Vec<u8>,
+
+    /// The shared secret.
+    pub shared_secret: Vec<u8>,
+}
+
+/// A source of cryptography for rustls.
+///
+/// Everything rustls needs for a TLS handshake and the records which
+/// follow it comes from here: randomness, hashing, HMAC and HKDF, bulk
+/// encryption, key exchange, loading private keys for signing and
+/// verifying handshake signatures.  Encrypted Client Hello servers
+/// also load their X25519 keys with it.  The default is [`ring::RING`];
+/// choose another with [`config_builder_with_provider`], for example to
+/// use a certified cryptographic module.  [`Ticketer::new_with_provider`]
+/// makes session tickets with a provider.
+///
+/// Two parts of rustls still use *ring* directly, whatever the provider:
+///
+/// - certificate chain validation by [`WebPkiVerifier`] and the other
+///   webpki-based verifiers.  Implement `ServerCertVerifier` or
+///   `ClientCertVerifier` to avoid this.
+/// - QUIC packet protection, whose interface uses *ring* types.
+///
+/// [`config_builder_with_provider`]: crate::config_builder_with_provider
+/// [`WebPkiVerifier`]: crate::WebPkiVerifier
+/// [`Ticketer::new_with_provider`]: crate::Ticketer::new_with_provider
+pub trait CryptoProvider: Send + Sync {
+    /// Fill `buf` with cryptographically secure random bytes.
+    fn fill_random(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed>;
+
+    /// Start computing a hash with `alg`.
+    fn start_hash(&self, alg: HashAlgorithm) -> Box<dyn HashContext>;
+
+    /// Hash `data` with `alg`.
+    fn hash(&self, alg: HashAlgorithm, data: &[u8]) -> Digest {
+        let mut ctx = self.start_hash(alg);
+        ctx.update(data);
+        ctx.finish()
+    }
+
+    /// Compute HMAC using `alg`, keyed with `key`, of the concatenation
+    /// of the slices in `data`.
+    fn hmac_sign(&self, alg: HashAlgorithm, key: &[u8], data: &[&[u8]]) -> Digest;
+
+    /// HKDF-Extract (RFC5869) using `alg`, returning the pseudorandom key.
+    ///
+    /// The default implementation uses `hmac_sign`.
+    fn hkdf_extract(&self, alg: HashAlgorithm, salt: &[u8], ikm: &[u8]) -> Digest {
+        self.hmac_sign(alg, salt, &[ikm])
+    }
+
+    /// HKDF-Expand (RFC5869) using `alg`, filling `out` from the
+    /// pseudorandom key `prk` and the concatenation of the slices in `info`.
+    ///
+    /// This fails if `out` is longer than 255 times the output of `alg`.
+    ///
+    /// The default implementation uses `hmac_sign`.
+    fn hkdf_expand(
+        &self,
+        alg: HashAlgorithm,
+        prk: &[u8],
+        info: &[&[u8]],
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        let hash_len = alg.output_len();
+        if out.len() > 255 * hash_len {
+            return Err(Error::General("HKDF output too long".to_string()));
+        }
+
+        let mut previous: Option<Digest> = None;
+        for (i, chunk) in out.chunks_mut(hash_len).enumerate() {
+            // T(i) = HMAC-Hash(PRK, T(i-1) | info | i)
+            let counter = [i as u8 + 1];
+            let mut data = Vec::with_capacity(info.len() + 2);
+            if let Some(previous) = &previous {
+                data.push(previous.as_ref());
+            }
+            data.extend_from_slice(info);
+            data.push(&counter[..]);
+
+            let block = self.hmac_sign(alg, prk, &data);
+            chunk.copy_from_slice(&block.as_ref()[..chunk.len()]);
+            previous = Some(block);
+        }
+
+        Ok(())
+    }
+
+    /// Can this provider encrypt with `alg`?
+    ///
+    /// Cipher suites using an unsupported algorithm cannot be configured.
+    fn aead_supported(&self, alg: &BulkAlgorithm) -> bool;
+
+    /// Make a key for `alg`, which is supported, from the bytes `key`.
+    ///
+    /// This fails if `key` is not the right length for `alg`.
+    fn aead_key(&self, alg: &BulkAlgorithm, key: &[u8]) -> Result<Box<dyn AeadKey>, Error>;
+
+    /// The key exchange groups this provider supports, in preference
+    /// order.  Only these can be configured; they are the default.
+    fn kx_groups(&self) -> &[&'static SupportedKxGroup];
+
+    /// Start a key exchange in `group`, which is one of `kx_groups()`,
+    /// by generating an ephemeral key pair.
+    ///
+    /// The provider generates the private key with whatever random
+    /// generator its key exchange implementation uses; `fill_random` is
+    /// not involved.
+    fn start_key_exchange(
+        &self,
+        group: &'static SupportedKxGroup,
+    ) -> Option<Box<dyn ActiveKeyExchange>>;
+
+    /// Do the server's half of a TLS1.3 key exchange in `group`, given
+    /// the client's key share `peer_pub_key`.
+    ///
+    /// The default implementation is `start_key_exchange` followed by
+    /// `complete`, which suits Diffie-Hellman groups.  Providers of
+    /// KEM-based groups must encapsulate to the client's share instead.
+    fn respond_key_exchange(
+        &self,
+        group: &'static SupportedKxGroup,
+        peer_pub_key: &[u8],
+    ) -> Result<KeyExchangeResult, Error> {
+        let ours = self
+            .start_key_exchange(group)
+            .ok_or(Error::FailedToGetRandomBytes)?;
+        let pubkey = ours.pub_key().to_vec();
+        let shared_secret = ours
+            .complete(peer_pub_key)
+            .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?;
+        Ok(KeyExchangeResult {
+            pubkey,
+            shared_secret,
+        })
+    }
+
+    /// Load a long-term X25519 private key from its 32 bytes, for
+    /// decrypting Encrypted Client Hello.
+    ///
+    /// The default implementation fails: a provider need not support
+    /// this unless it is used by servers offering ECH.
+    fn load_static_x25519_key(
+        &self,
+        _private_key: &[u8],
+    ) -> Result<Arc<dyn StaticX25519Key>, Error> {
+        Err(Error::General(
+            "static X25519 keys are not supported".to_string(),
+        ))
+    }
+
+    /// The signature schemes this provider can sign and verify with,
+    /// in preference order.  Only these can be configured; they are
+    /// the default.
+    fn signature_schemes(&self) -> &[SignatureScheme];
+
+    /// Load the DER-encoded private key `key`, for signing.
+    fn load_private_key(&self, key: &key::PrivateKey) -> Result<Arc<dyn SigningKey>, SignError>;
+
+    /// Verify that `signature` is a valid signature by the public key
+    /// `spki` over `message`, using `scheme`.
+    ///
+    /// `spki` is a DER-encoded SubjectPublicKeyInfo.  As in TLS1.3, ECDSA
+    /// keys must be on the curve named by `scheme`, and RSA signatures
+    /// must use PSS.
+    fn verify_signature(
+        &self,
+        scheme: SignatureScheme,
+        spki: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Like `verify_signature`, but with the looser rules of TLS1.2:
+    /// ECDSA keys may be on any curve, whatever `scheme` says, and RSA
+    /// PKCS#1 signatures are allowed.
+    ///
+    /// The default implementation is `verify_signature`.
+    fn verify_tls12_signature(
+        &self,
+        scheme: SignatureScheme,
+        spki: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error> {
+        self.verify_signature(scheme, spki, message, signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A provider which uses the default HKDF implementation.
+    struct DefaultHkdf;
+
+    impl CryptoProvider for DefaultHkdf {
+        fn fill_random(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+            ring::RING.fill_random(buf)
+        }
+
+        fn start_hash(&self, alg: HashAlgorithm) -> Box<dyn HashContext> {
+            ring::RING.start_hash(alg)
+        }
+
+        fn hmac_sign(&self, alg: HashAlgorithm, key: &[u8], data: &[&[u8]]) -> Digest {
+            ring::RING.hmac_sign(alg, key, data)
+        }
+
+        fn aead_supported(&self, alg: &BulkAlgorithm) -> bool {
+            ring::RING.aead_supported(alg)
+        }
+
+        fn aead_key(&self, alg: &BulkAlgorithm, key: &[u8]) -> Result<Box<dyn AeadKey>, Error> {
+            ring::RING.aead_key(alg, key)
+        }
+
+        fn kx_groups(&self) -> &[&'static SupportedKxGroup] {
+            ring::RING.kx_groups()
+        }
+
+        fn start_key_exchange(
+            &self,
+            group: &'static SupportedKxGroup,
+        ) -> Option<Box<dyn ActiveKeyExchange>> {
+            ring::RING.start_key_exchange(group)
+        }
+
+        fn signature_schemes(&self) -> &[SignatureScheme] {
+            ring::RING.signature_schemes()
+        }
+
+        fn load_private_key(
+            &self,
+            key: &key::PrivateKey,
+        ) -> Result<Arc<dyn SigningKey>, SignError> {
+            ring::RING.load_private_key(key)
+        }
+
+        fn verify_signature(
+            &self,
+            scheme: SignatureScheme,
+            spki: &[u8],
+            message: &[u8],
+            signature: &[u8],
+        ) -> Result<(), Error> {
+            ring::RING.verify_signature(scheme, spki, message, signature)
+        }
+    }
+
+    #[test]
+    fn default_hkdf_matches_rfc5869() {
+        // RFC5869 test case 1.
+        let ikm = [0x0b; 22];
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+
+        let prk = DefaultHkdf.hkdf_extract(HashAlgorithm::SHA256, &salt, &ikm);
+        assert_eq!(
+            prk.as_ref(),
+            &[
+                0x07, 0x77, 0x09, 0x36, 0x2c, 0x2e, 0x32, 0xdf, 0x0d, 0xdc, 0x3f, 0x0d, 0xc4, 0x7b,
+                0xba, 0x63, 0x90, 0xb6, 0xc7, 0x3b, 0xb5, 0x0f, 0x9c, 0x31, 0x22, 0xec, 0x84, 0x4a,
+                0xd7, 0xc2, 0xb3, 0xe5,
+            ][..]
+        );
+
+        let expected = [
+            0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36,
+            0x2f, 0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56,
+            0xec, 0xc4, 0xc5, 0xbf, 0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65,
+        ];
+        let mut okm = [0u8; 42];
+        DefaultHkdf
+            .hkdf_expand(HashAlgorithm::SHA256, prk.as_ref(), &[&info], &mut okm)
+            .unwrap();
+        assert_eq!(okm, expected);
+
+        // ring's implementation agrees, given the info in pieces.
+        let mut okm = [0u8; 42];
+        ring::RING
+            .hkdf_expand(
+                HashAlgorithm::SHA256,
+                prk.as_ref(),
+                &[&info[..3], &info[3..]],
+                &mut okm,
+            )
+            .unwrap();
+        assert_eq!(okm, expected);
+    }
+
+    #[test]
+    fn hkdf_expand_rejects_long_output() {
+        let mut okm = vec![0u8; 255 * 32 + 1];
+        assert!(DefaultHkdf
+            .hkdf_expand(HashAlgorithm::SHA256, &[0u8; 32], &[], &mut okm)
+            .is_err());
+        assert!(ring::RING
+            .hkdf_expand(HashAlgorithm::SHA256, &[0u8; 32], &[], &mut okm)
+            .is_err());
+    }
+}
@@ -0,0 +1,406 @@
+use super::{
+    ActiveKeyExchange, AeadKey, CryptoProvider, Digest, HashAlgorithm, HashContext,
+    KeyExchangeResult, NONCE_LEN,
+};
+#[cfg(feature = "static-x25519")]
+use super::StaticX25519Key;
+use crate::error::Error;
+use crate::key;
+use crate::kx::{self, SupportedKxGroup, ALL_KX_GROUPS};
+use crate::msgs::enums::SignatureScheme;
+use crate::rand::GetRandomFailed;
+use crate::sign::{self, SignError, SigningKey};
+use crate::suites::BulkAlgorithm;
+use crate::verify;
+
+#[cfg(feature = "aes-ccm")]
+use aes::{Aes128, Aes256};
+#[cfg(feature = "aes-ccm")]
+use ccm::aead::generic_array::typenum::Unsigned;
+#[cfg(feature = "aes-ccm")]
+use ccm::consts::{U12, U16, U8};
+#[cfg(feature = "aes-ccm")]
+use ccm::{AeadCore, AeadInPlace, KeyInit};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{aead, digest, hkdf, hmac};
+#[cfg(feature = "static-x25519")]
+use std::convert::TryFrom;
+
+use std::sync::Arc;
+
+/// The default [`CryptoProvider`], which uses *ring*.
+///
+/// It supports all the cipher suites and key exchange groups rustls
+/// defines.  Some algorithms need a crate feature:
+///
+/// - P-521 key exchange and ECDSA signatures need `secp521r1`, and use
+///   the `p521` crate.
+/// - AES-CCM cipher suites need `aes-ccm`, and use the `ccm` crate.
+/// - Finite-field Diffie-Hellman needs `ffdhe`, and uses the
+///   `crypto-bigint` crate.
+/// - X25519MLKEM768 key exchange needs `mlkem`, and uses ML-KEM from
+///   the `aws-lc-rs` crate.
+/// - X448 key exchange and Ed448 signatures need `curve448`, and use
+///   the system OpenSSL library through the `openssl` crate.  X448
+///   private keys are generated by OpenSSL's own random generator.
+/// - Long-term X25519 keys, for Encrypted Client Hello servers, need
+///   `static-x25519`, and use the `x25519-dalek` crate.
+///
+/// Its randomness, including that of ephemeral key exchange private
+/// keys, comes from *ring*'s `SystemRandom`: the operating system's
+/// generator.  The exceptions are the key exchange groups from other
+/// crates, which use their own generators.
+#[derive(Debug)]
+pub struct RingProvider;
+
+/// The default [`CryptoProvider`]: see [`RingProvider`].
+pub static RING: RingProvider = RingProvider;
+
+impl CryptoProvider for RingProvider {
+    fn fill_random(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+        SystemRandom::new()
+            .fill(buf)
+            .map_err(|_| GetRandomFailed)
+    }
+
+    fn start_hash(&self, alg: HashAlgorithm) -> Box<dyn HashContext> {
+        Box::new(Hash(alg, digest::Context::new(digest_algorithm(alg))))
+    }
+
+    fn hmac_sign(&self, alg: HashAlgorithm, key: &[u8], data: &[&[u8]]) -> Digest {
+        let key = hmac::Key::new(hmac_algorithm(alg), key);
+        let mut ctx = hmac::Context::with_key(&key);
+        for d in data {
+            ctx.update(d);
+        }
+        Digest::new(ctx.sign().as_ref())
+    }
+
+    fn hkdf_expand(
+        &self,
+        alg: HashAlgorithm,
+        prk: &[u8],
+        info: &[&[u8]],
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        hkdf::Prk::new_less_safe(hkdf_algorithm(alg), prk)
+            .expand(info, Len(out.len()))
+            .and_then(|okm| okm.fill(out))
+            .map_err(|_| Error::General("HKDF output too long".to_string()))
+    }
+
+    fn aead_supported(&self, alg: &BulkAlgorithm) -> bool {
+        match alg {
+            BulkAlgorithm::Aes128Gcm
+            | BulkAlgorithm::Aes256Gcm
+            | BulkAlgorithm::Chacha20Poly1305 => true,
+            BulkAlgorithm::Aes128Ccm
+            | BulkAlgorithm::Aes256Ccm
+            | BulkAlgorithm::Aes128Ccm8
+            | BulkAlgorithm::Aes256Ccm8 => cfg!(feature = "aes-ccm"),
+        }
+    }
+
+    fn aead_key(&self, alg: &BulkAlgorithm, key: &[u8]) -> Result<Box<dyn AeadKey>, Error> {
+        let alg = match alg {
+            BulkAlgorithm::Aes128Gcm => &aead::AES_128_GCM,
+            BulkAlgorithm::Aes256Gcm => &aead::AES_256_GCM,
+            BulkAlgorithm::Chacha20Poly1305 => &aead::CHACHA20_POLY1305,
+            #[cfg(feature = "aes-ccm")]
+            BulkAlgorithm::Aes128Ccm => return Ccm::<ccm::Ccm<Aes128, U16, U12>>::new(key),
+            #[cfg(feature = "aes-ccm")]
+            BulkAlgorithm::Aes256Ccm => return Ccm::<ccm::Ccm<Aes256, U16, U12>>::new(key),
+            #[cfg(feature = "aes-ccm")]
+            BulkAlgorithm::Aes128Ccm8 => return Ccm::<ccm::Ccm<Aes128, U8, U12>>::new(key),
+            #[cfg(feature = "aes-ccm")]
+            BulkAlgorithm::Aes256Ccm8 => return Ccm::<ccm::Ccm<Aes256, U8, U12>>::new(key),
+            #[cfg(not(feature = "aes-ccm"))]
+            _ => return Err(unsupported_aead(alg)),
+        };
+        let key = aead::UnboundKey::new(alg, key).map_err(|_| bad_aead_key())?;
+        Ok(Box::new(Aead(aead::LessSafeKey::new(key))))
+    }
+
+    fn kx_groups(&self) -> &[&'static SupportedKxGroup] {
+        ALL_KX_GROUPS
+    }
+
+    fn start_key_exchange(
+        &self,
+        group: &'static SupportedKxGroup,
+    ) -> Option<Box<dyn ActiveKeyExchange>> {
+        let kx: Box<dyn ActiveKeyExchange> = Box::new(kx::KeyExchange::start(group)?);
+        Some(kx)
+    }
+
+    fn respond_key_exchange(
+        &self,
+        group: &'static SupportedKxGroup,
+        peer_pub_key: &[u8],
+    ) -> Result<KeyExchangeResult, Error> {
+        kx::KeyExchange::respond(group, peer_pub_key)
+    }
+
+    #[cfg(feature = "static-x25519")]
+    fn load_static_x25519_key(
+        &self,
+        private_key: &[u8],
+    ) -> Result<Arc<dyn StaticX25519Key>, Error> {
+        let private_key = <[u8; 32]>::try_from(private_key)
+            .map_err(|_| Error::General("X25519 private key has the wrong length".to_string()))?;
+        let secret = x25519_dalek::StaticSecret::from(private_key);
+        let public = x25519_dalek::PublicKey::from(&secret);
+        Ok(Arc::new(StaticX25519(secret, public)))
+    }
+
+    fn signature_schemes(&self) -> &[SignatureScheme] {
+        SIGNATURE_SCHEMES
+    }
+
+    fn load_private_key(&self, key: &key::PrivateKey) -> Result<Arc<dyn SigningKey>, SignError> {
+        sign::any_supported_type(key)
+    }
+
+    fn verify_signature(
+        &self,
+        scheme: SignatureScheme,
+        spki: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error> {
+        verify::verify_spki_signature(scheme, spki, message, signature, false)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        scheme: SignatureScheme,
+        spki: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error> {
+        verify::verify_spki_signature(scheme, spki, message, signature, true)
+    }
+}
+
+/// The signature schemes we support, in preference order.
+static SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    #[cfg(feature = "secp521r1")]
+    SignatureScheme::ECDSA_NISTP521_SHA512,
+    SignatureScheme::ED25519,
+    #[cfg(feature = "curve448")]
+    SignatureScheme::ED448,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+fn encrypt_failed() -> Error {
+    Error::General("encrypt failed".to_string())
+}
+
+fn bad_aead_key() -> Error {
+    Error::General("AEAD key has the wrong length".to_string())
+}
+
+#[cfg(not(feature = "aes-ccm"))]
+fn unsupported_aead(alg: &BulkAlgorithm) -> Error {
+    Error::General(format!("{:?} is not supported", alg))
+}
+
+fn digest_algorithm(alg: HashAlgorithm) -> &'static digest::Algorithm {
+    match alg {
+        HashAlgorithm::SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        HashAlgorithm::SHA256 => &digest::SHA256,
+        HashAlgorithm::SHA384 => &digest::SHA384,
+        HashAlgorithm::SHA512 => &digest::SHA512,
+    }
+}
+
+fn hmac_algorithm(alg: HashAlgorithm) -> hmac::Algorithm {
+    match alg {
+        HashAlgorithm::SHA1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        HashAlgorithm::SHA256 => hmac::HMAC_SHA256,
+        HashAlgorithm::SHA384 => hmac::HMAC_SHA384,
+        HashAlgorithm::SHA512 => hmac::HMAC_SHA512,
+    }
+}
+
+/// The *ring* HKDF algorithm using `alg`.
+pub(crate) fn hkdf_algorithm(alg: HashAlgorithm) -> hkdf::Algorithm {
+    match alg {
+        HashAlgorithm::SHA1 => hkdf::HKDF_SHA1_FOR_LEGACY_USE_ONLY,
+        HashAlgorithm::SHA256 => hkdf::HKDF_SHA256,
+        HashAlgorithm::SHA384 => hkdf::HKDF_SHA384,
+        HashAlgorithm::SHA512 => hkdf::HKDF_SHA512,
+    }
+}
+
+struct Hash(HashAlgorithm, digest::Context);
+
+impl HashContext for Hash {
+    fn algorithm(&self) -> HashAlgorithm {
+        self.0
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.1.update(data);
+    }
+
+    fn fork(&self) -> Box<dyn HashContext> {
+        Box::new(Self(self.0, self.1.clone()))
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest::new(self.1.finish().as_ref())
+    }
+}
+
+struct Aead(aead::LessSafeKey);
+
+impl AeadKey for Aead {
+    fn seal_in_place(
+        &self,
+        nonce: [u8; NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+        prefix_len: usize,
+    ) -> Result<(), Error> {
+        let plain = in_out
+            .get_mut(prefix_len..)
+            .ok_or_else(encrypt_failed)?;
+        let tag = self
+            .0
+            .seal_in_place_separate_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(aad),
+                plain,
+            )
+            .map_err(|_| encrypt_failed())?;
+        in_out.extend_from_slice(tag.as_ref());
+        Ok(())
+    }
+
+    fn open_in_place(
+        &self,
+        nonce: [u8; NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut [u8],
+        prefix_len: usize,
+    ) -> Result<usize, Error> {
+        self.0
+            .open_within(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(aad),
+                in_out,
+                prefix_len..,
+            )
+            .map(|plain| plain.len())
+            .map_err(|_| Error::DecryptError)
+    }
+
+    fn tag_len(&self) -> usize {
+        self.0.algorithm().tag_len()
+    }
+}
+
+/// AES-CCM, which *ring* lacks, from the `ccm` crate.
+#[cfg(feature = "aes-ccm")]
+struct Ccm<A>(A);
+
+#[cfg(feature = "aes-ccm")]
+impl<A> Ccm<A>
+where
+    A: AeadInPlace<NonceSize = U12> + KeyInit + Send + Sync + 'static,
+{
+    fn new(key: &[u8]) -> Result<Box<dyn AeadKey>, Error> {
+        let key = A::new_from_slice(key).map_err(|_| bad_aead_key())?;
+        Ok(Box::new(Self(key)))
+    }
+}
+
+#[cfg(feature = "aes-ccm")]
+impl<A> AeadKey for Ccm<A>
+where
+    A: AeadInPlace<NonceSize = U12> + Send + Sync,
+{
+    fn seal_in_place(
+        &self,
+        nonce: [u8; NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+        prefix_len: usize,
+    ) -> Result<(), Error> {
+        let plain = in_out
+            .get_mut(prefix_len..)
+            .ok_or_else(encrypt_failed)?;
+        let tag = self
+            .0
+            .encrypt_in_place_detached(&nonce.into(), aad, plain)
+            .map_err(|_| encrypt_failed())?;
+        in_out.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    fn open_in_place(
+        &self,
+        nonce: [u8; NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut [u8],
+        prefix_len: usize,
+    ) -> Result<usize, Error> {
+        let plain_end = in_out
+            .len()
+            .checked_sub(self.tag_len())
+            .filter(|end| *end >= prefix_len)
+            .ok_or(Error::DecryptError)?;
+        let (plain, tag) = in_out.split_at_mut(plain_end);
+        let tag = &*tag;
+        self.0
+            .decrypt_in_place_detached(&nonce.into(), aad, &mut plain[prefix_len..], tag.into())
+            .map_err(|_| Error::DecryptError)?;
+        // The `ccm` crate has no equivalent of `open_within`.
+        in_out.copy_within(prefix_len..plain_end, 0);
+        Ok(plain_end - prefix_len)
+    }
+
+    fn tag_len(&self) -> usize {
+        <A as AeadCore>::TagSize::USIZE
+    }
+}
+
+/// A long-term X25519 key, which *ring* lacks, from the `x25519-dalek`
+/// crate.
+#[cfg(feature = "static-x25519")]
+struct StaticX25519(x25519_dalek::StaticSecret, x25519_dalek::PublicKey);
+
+#[cfg(feature = "static-x25519")]
+impl StaticX25519Key for StaticX25519 {
+    fn public_key(&self) -> &[u8] {
+        self.1.as_bytes()
+    }
+
+    fn agree(&self, peer_pub_key: &[u8]) -> Option<Vec<u8>> {
+        let peer_pub_key = <[u8; 32]>::try_from(peer_pub_key).ok()?;
+        let shared_secret = self
+            .0
+            .diffie_hellman(&peer_pub_key.into());
+        if !shared_secret.was_contributory() {
+            return None;
+        }
+        Some(shared_secret.as_bytes().to_vec())
+    }
+}
+
+/// An output length for `hkdf::Prk::expand`.
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
@@ -0,0 +1,376 @@
+//! Delegated credentials ([RFC9345](https://tools.ietf.org/html/rfc9345)):
+//! short-lived keys which a server's end-entity certificate authorises to
+//! sign TLS1.3 handshakes in its place.
+use crate::crypto::ring::RING;
+use crate::crypto::CryptoProvider;
+use crate::error::Error;
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::warn;
+use crate::msgs::base::{PayloadU16, PayloadU24};
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::SignatureScheme;
+use crate::msgs::handshake::{Credential, DelegatedCredentialPayload, DigitallySignedStruct};
+use crate::ocsp;
+use crate::sign;
+use crate::verify::{self, HandshakeSignatureValid, ServerCertVerifier};
+use crate::x509::DerReader;
+
+use ring::io::der;
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The longest a delegated credential may be valid for.
+const MAX_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The DelegationUsage certificate extension, 1.3.6.1.4.1.44363.44.
+const ID_PE_DELEGATION_USAGE: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xda, 0x4b, 0x2c];
+
+/// The KeyUsage certificate extension, 2.5.29.15.
+const ID_CE_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+
+/// The schemes we support for the key in a delegated credential,
+/// which clients offer in the `delegated_credential` extension.
+pub(crate) static SUPPORTED_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ED25519,
+];
+
+/// A delegated credential, and its private key.
+///
+/// A server presents this in place of its certificate's own key
+/// to clients which support delegated credentials: put it in
+/// [`CertifiedKey::delegated_credential`](crate::sign::CertifiedKey::delegated_credential).
+///
+/// The end-entity certificate must carry the DelegationUsage extension
+/// for clients to accept the credential.
+#[derive(Clone)]
+pub struct DelegatedCredential {
+    payload: DelegatedCredentialPayload,
+    key: Arc<dyn sign::SigningKey>,
+}
+
+impl DelegatedCredential {
+    /// Issue a new delegated credential for `key`, whose DER-encoded
+    /// SubjectPublicKeyInfo is `public_key`.  The credential is signed by
+    /// `end_entity_key`, the key certified by `end_entity`, and is valid
+    /// for `valid_for` from now.
+    ///
+    /// Both signature schemes (the credential's own, and the one
+    /// `end_entity_key` signs it with) are chosen from `signature_schemes`:
+    /// normally the server's [`ServerConfig::signature_schemes`].
+    ///
+    /// `key` must be an ECDSA P-256, ECDSA P-384 or Ed25519 key matching
+    /// `public_key`, and `valid_for` must be no more than seven days.
+    /// The default crypto provider checks that `key` and `public_key` match.
+    ///
+    /// [`ServerConfig::signature_schemes`]: crate::ServerConfig::signature_schemes
+    pub fn new(
+        end_entity: &Certificate,
+        end_entity_key: &dyn sign::SigningKey,
+        public_key: Vec<u8>,
+        key: Arc<dyn sign::SigningKey>,
+        valid_for: Duration,
+        signature_schemes: &[SignatureScheme],
+    ) -> Result<Self, Error> {
+        Self::new_with_provider(
+            &RING,
+            end_entity,
+            end_entity_key,
+            public_key,
+            key,
+            valid_for,
+            signature_schemes,
+        )
+    }
+
+    /// Like [`DelegatedCredential::new`], but `provider` rather than the
+    /// default crypto provider checks that `key` and `public_key` match.
+    pub fn new_with_provider(
+        provider: &dyn CryptoProvider,
+        end_entity: &Certificate,
+        end_entity_key: &dyn sign::SigningKey,
+        public_key: Vec<u8>,
+        key: Arc<dyn sign::SigningKey>,
+        valid_for: Duration,
+        signature_schemes: &[SignatureScheme],
+    ) -> Result<Self, Error> {
+        let not_before = delegation_start(end_entity)?;
+
+        if valid_for > MAX_VALIDITY {
+            warn!("Delegated credential validity {:?} is too long", valid_for);
+            return Err(Error::InvalidDelegatedCredential);
+        }
+
+        let dc_schemes = SUPPORTED_SCHEMES
+            .iter()
+            .filter(|scheme| signature_schemes.contains(scheme))
+            .cloned()
+            .collect::<Vec<_>>();
+        let scheme = key
+            .choose_scheme(&dc_schemes)
+            .ok_or(Error::InvalidDelegatedCredential)?
+            .get_scheme();
+        check_key_pair(provider, key.as_ref(), scheme, &public_key)?;
+
+        let expiry = unix_time(SystemTime::now())? + valid_for.as_secs();
+        let valid_time = expiry
+            .checked_sub(not_before)
+            .filter(|valid_time| *valid_time <= u64::from(u32::MAX))
+            .ok_or(Error::InvalidDelegatedCredential)?;
+
+        let cred = Credential {
+            valid_time: valid_time as u32,
+            dc_cert_verify_algorithm: scheme,
+            public_key: PayloadU24::new(public_key),
+        };
+
+        let tls13_schemes = sign::supported_sign_tls13();
+        let end_entity_schemes = signature_schemes
+            .iter()
+            .filter(|scheme| tls13_schemes.contains(scheme))
+            .cloned()
+            .collect::<Vec<_>>();
+        let signer = end_entity_key
+            .choose_scheme(&end_entity_schemes)
+            .ok_or(Error::InvalidDelegatedCredential)?;
+        let algorithm = signer.get_scheme();
+        let signature = signer.sign(&signed_message(end_entity, &cred, algorithm))?;
+
+        Ok(Self {
+            payload: DelegatedCredentialPayload {
+                cred,
+                algorithm,
+                signature: PayloadU16::new(signature),
+            },
+            key,
+        })
+    }
+
+    /// Load a delegated credential issued elsewhere, in its TLS
+    /// encoding (as returned by [`DelegatedCredential::encoding`]),
+    /// along with its private `key`.
+    pub fn from_encoding(encoding: &[u8], key: Arc<dyn sign::SigningKey>) -> Result<Self, Error> {
+        let mut rd = Reader::init(encoding);
+        let payload = DelegatedCredentialPayload::read(&mut rd)
+            .filter(|_| !rd.any_left())
+            .ok_or(Error::InvalidDelegatedCredential)?;
+
+        if key
+            .choose_scheme(&[payload.cred.dc_cert_verify_algorithm])
+            .is_none()
+        {
+            return Err(Error::InvalidDelegatedCredential);
+        }
+
+        Ok(Self { payload, key })
+    }
+
+    /// The TLS encoding of this delegated credential.
+    pub fn encoding(&self) -> Vec<u8> {
+        self.payload.get_encoding()
+    }
+
+    /// The signature scheme which this credential's key uses.
+    pub fn scheme(&self) -> SignatureScheme {
+        self.payload
+            .cred
+            .dc_cert_verify_algorithm
+    }
+
+    /// May we send this credential to a client which offered `dc_schemes`
+    /// in its `delegated_credential` extension and `sigschemes` in its
+    /// `signature_algorithms` extension?
+    pub(crate) fn usable_with(
+        &self,
+        dc_schemes: &[SignatureScheme],
+        sigschemes: &[SignatureScheme],
+    ) -> bool {
+        dc_schemes.contains(&self.scheme()) && sigschemes.contains(&self.payload.algorithm)
+    }
+
+    pub(crate) fn payload(&self) -> &DelegatedCredentialPayload {
+        &self.payload
+    }
+
+    pub(crate) fn signing_key(&self) -> &dyn sign::SigningKey {
+        &*self.key
+    }
+}
+
+/// Check a delegated credential `dc` which the server sent with `end_entity`.
+///
+/// `end_entity` must already have been verified by `verifier`, which is
+/// used with `provider` to check the credential's signature.
+pub(crate) fn verify(
+    provider: &dyn CryptoProvider,
+    dc: &DelegatedCredentialPayload,
+    end_entity: &Certificate,
+    verifier: &dyn ServerCertVerifier,
+    now: SystemTime,
+) -> Result<(), Error> {
+    if !SUPPORTED_SCHEMES.contains(&dc.cred.dc_cert_verify_algorithm) {
+        warn!(
+            "Delegated credential uses unoffered scheme {:?}",
+            dc.cred.dc_cert_verify_algorithm
+        );
+        return Err(Error::InvalidDelegatedCredential);
+    }
+
+    let not_before = delegation_start(end_entity)?;
+    let now = unix_time(now)?;
+    let expiry = not_before + u64::from(dc.cred.valid_time);
+    if expiry <= now || expiry > now + MAX_VALIDITY.as_secs() {
+        warn!("Delegated credential has expired, or is valid for too long");
+        return Err(Error::InvalidDelegatedCredential);
+    }
+
+    let dss = DigitallySignedStruct::new(dc.algorithm, dc.signature.0.clone());
+    verifier
+        .verify_tls13_signature(
+            provider,
+            &signed_message(end_entity, &dc.cred, dc.algorithm),
+            end_entity,
+            &dss,
+        )
+        .map_err(|_| Error::InvalidDelegatedCredential)?;
+
+    Ok(())
+}
+
+/// Verify the server's CertificateVerify signature `dss` over `message`,
+/// which must be made with the key from its delegated credential `dc`.
+pub(crate) fn verify_signature(
+    provider: &dyn CryptoProvider,
+    message: &[u8],
+    dc: &DelegatedCredentialPayload,
+    dss: &DigitallySignedStruct,
+) -> Result<HandshakeSignatureValid, Error> {
+    if dss.scheme != dc.cred.dc_cert_verify_algorithm {
+        return Err(Error::PeerMisbehavedError(
+            "CertificateVerify scheme does not match delegated credential".to_string(),
+        ));
+    }
+
+    verify::verify_tls13_spki(provider, message, &dc.cred.public_key.0, dss)
+}
+
+/// Check that `key` is the private half of `public_key`, by signing a
+/// test message with `scheme` and verifying it with `provider`.
+fn check_key_pair(
+    provider: &dyn CryptoProvider,
+    key: &dyn sign::SigningKey,
+    scheme: SignatureScheme,
+    public_key: &[u8],
+) -> Result<(), Error> {
+    let message = b"rustls delegated credential key check";
+    let signature = key
+        .choose_scheme(&[scheme])
+        .ok_or(Error::InvalidDelegatedCredential)?
+        .sign(message)?;
+
+    provider
+        .verify_signature(scheme, public_key, message, &signature)
+        .map_err(|_| {
+            warn!("Delegated credential key does not match its public key");
+            Error::InvalidDelegatedCredential
+        })
+}
+
+/// Check `end_entity` may issue delegated credentials, and return the
+/// start of its validity period (in seconds since the UNIX epoch), from
+/// which a credential's validity is measured.
+fn delegation_start(end_entity: &Certificate) -> Result<u64, Error> {
+    let cert = ocsp::Cert::parse_der(&end_entity.0).ok_or(Error::InvalidDelegatedCredential)?;
+
+    let delegation_usage = cert
+        .extension(ID_PE_DELEGATION_USAGE)
+        .map_err(|_| Error::InvalidDelegatedCredential)?;
+    let key_usage = cert
+        .extension(ID_CE_KEY_USAGE)
+        .map_err(|_| Error::InvalidDelegatedCredential)?;
+
+    if delegation_usage.is_none() {
+        warn!("Certificate lacks the DelegationUsage extension");
+        return Err(Error::InvalidDelegatedCredential);
+    }
+
+    if let Some(key_usage) = key_usage {
+        if !allows_digital_signature(key_usage) {
+            warn!("Certificate key usage does not allow signing delegated credentials");
+            return Err(Error::InvalidDelegatedCredential);
+        }
+    }
+
+    Ok(cert.not_before)
+}
+
+/// Does the KeyUsage extension value `key_usage` include
+/// digitalSignature?
+fn allows_digital_signature(key_usage: &[u8]) -> bool {
+    let bits = DerReader::new(key_usage).read(der::Tag::BitString as u8);
+    match bits {
+        // The first byte counts the unused bits of the last.
+        Some([_, first, ..]) => first & 0x80 != 0,
+        _ => false,
+    }
+}
+
+/// The message which the end-entity key signs to issue a credential.
+fn signed_message(
+    end_entity: &Certificate,
+    cred: &Credential,
+    algorithm: SignatureScheme,
+) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.resize(64, 0x20u8);
+    msg.extend_from_slice(b"TLS, server delegated credentials\x00");
+    msg.extend_from_slice(&end_entity.0);
+    cred.encode(&mut msg);
+    algorithm.encode(&mut msg);
+    msg
+}
+
+fn unix_time(now: SystemTime) -> Result<u64, Error> {
+    now.duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .map_err(|_| Error::FailedToGetCurrentTime)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checks_key_usage() {
+        // digitalSignature, nonRepudiation
+        assert!(allows_digital_signature(&[0x03, 0x02, 0x06, 0xc0]));
+        // keyCertSign, cRLSign
+        assert!(!allows_digital_signature(&[0x03, 0x02, 0x01, 0x06]));
+        assert!(!allows_digital_signature(&[0x03, 0x01, 0x00]));
+        assert!(!allows_digital_signature(&[0x04, 0x02, 0x06, 0xc0]));
+    }
+
+    #[test]
+    fn signed_message_layout() {
+        let cred = Credential {
+            valid_time: 0x0102_0304,
+            dc_cert_verify_algorithm: SignatureScheme::ED25519,
+            public_key: PayloadU24::new(vec![0xaa]),
+        };
+        let msg = signed_message(
+            &Certificate(vec![0x30, 0x00]),
+            &cred,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+        );
+
+        let mut expected = vec![0x20; 64];
+        expected.extend_from_slice(b"TLS, server delegated credentials\x00");
+        expected.extend_from_slice(&[0x30, 0x00]);
+        expected.extend_from_slice(&[0x01, 0x02, 0x03, 0x04, 0x08, 0x07, 0x00, 0x00, 0x01, 0xaa]);
+        expected.extend_from_slice(&[0x04, 0x03]);
+        assert_eq!(msg, expected);
+    }
+}
@@ -0,0 +1,899 @@
+//! Encrypted Client Hello (draft-ietf-tls-esni-18).
+//!
+//! The client sends its real ClientHello (the "inner" hello) encrypted
+//! to a key published by the server, inside an "outer" ClientHello
+//! which names only the server's `public_name`.
+
+use crate::client::ServerName;
+use crate::conn::{ConnectionCommon, ConnectionRandoms};
+use crate::crypto::ring::RING;
+use crate::crypto::{CryptoProvider, Digest, HashAlgorithm, StaticX25519Key};
+use crate::error::Error;
+use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
+use crate::hpke::{self, HpkeContext};
+use crate::key_schedule::{self, Prk};
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::{AlertDescription, ExtensionType, HandshakeType};
+use crate::msgs::enums::{HpkeAead, HpkeKdf, HpkeKem, ProtocolVersion};
+use crate::msgs::handshake::ConvertServerNameList;
+use crate::msgs::handshake::{ClientExtension, ClientHelloPayload, HelloRetryExtension};
+use crate::msgs::handshake::{EchConfigContents, EchConfigList, EchConfigPayload};
+use crate::msgs::handshake::{EchOuterPayload, EncryptedClientHello, HpkeKeyConfig};
+use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload, HelloRetryRequest};
+use crate::msgs::handshake::{HpkeSymmetricCipherSuite, Random, SessionID};
+use crate::msgs::message::{Message, MessagePayload};
+use crate::suites::{SupportedCipherSuite, Tls13CipherSuite};
+
+use ring::constant_time;
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+/// The length of the acceptance confirmation signal.
+const CONFIRMATION_LEN: usize = 8;
+
+/// The cipher suites we offer in our own `ECHConfig`s.
+static SERVER_SUITES: &[HpkeSymmetricCipherSuite] = &[
+    HpkeSymmetricCipherSuite {
+        kdf_id: HpkeKdf::HKDF_SHA256,
+        aead_id: HpkeAead::AES_128_GCM,
+    },
+    HpkeSymmetricCipherSuite {
+        kdf_id: HpkeKdf::HKDF_SHA256,
+        aead_id: HpkeAead::CHACHA20_POLY_1305,
+    },
+];
+
+/// Whether Encrypted Client Hello was used for a connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EchStatus {
+    /// ECH was not offered.
+    NotOffered,
+    /// ECH was offered, and the server has not yet responded.
+    Offered,
+    /// The server accepted our ECH offer: the server name we were
+    /// given was not revealed.
+    Accepted,
+    /// The server rejected our ECH offer.  The handshake fails
+    /// with [`Error::EncryptedClientHelloRejected`].
+    Rejected,
+}
+
+/// A client's Encrypted Client Hello configuration: one `ECHConfig`
+/// published by the server, and the HPKE cipher suite we will use
+/// with it.
+///
+/// Servers publish an `ECHConfigList` in the DNS, in their HTTPS
+/// resource record.  Make one of these from that with [`EchConfig::new`].
+#[derive(Clone, Debug)]
+pub struct EchConfig {
+    contents: EchConfigContents,
+    suite: HpkeSymmetricCipherSuite,
+    public_name: ServerName,
+    encoding: Vec<u8>,
+}
+
+impl EchConfig {
+    /// Choose the first configuration in the encoded `ech_config_list`
+    /// which we support.
+    ///
+    /// Fails with [`Error::InvalidEchConfig`] if `ech_config_list` cannot
+    /// be decoded, or contains no usable configuration.
+    pub fn new(ech_config_list: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::init(ech_config_list);
+        let list = match EchConfigList::read(&mut r) {
+            Some(list) if !r.any_left() => list,
+            _ => return Err(Error::InvalidEchConfig),
+        };
+
+        list.into_iter()
+            .find_map(Self::from_payload)
+            .ok_or(Error::InvalidEchConfig)
+    }
+
+    fn from_payload(payload: EchConfigPayload) -> Option<Self> {
+        let encoding = payload.get_encoding();
+        let contents = match payload {
+            EchConfigPayload::V18(contents) => contents,
+            EchConfigPayload::Unknown { .. } => return None,
+        };
+
+        let key_config = &contents.key_config;
+        if key_config.kem_id != HpkeKem::DHKEM_X25519_HKDF_SHA256
+            || key_config.public_key.0.len() != hpke::X25519_LEN
+            || contents
+                .extensions
+                .iter()
+                .any(|ext| ext.is_mandatory())
+        {
+            return None;
+        }
+
+        let public_name = std::str::from_utf8(&contents.public_name.0).ok()?;
+        let public_name = ServerName::try_from(public_name).ok()?;
+        let suite = *key_config
+            .symmetric_cipher_suites
+            .iter()
+            .find(|suite| hpke::is_supported(suite))?;
+
+        Some(Self {
+            contents,
+            suite,
+            public_name,
+            encoding,
+        })
+    }
+}
+
+/// A server's Encrypted Client Hello key, and the `ECHConfig` which
+/// publishes it.
+///
+/// The private key is an X25519 private key; clients use it with
+/// DHKEM(X25519, HKDF-SHA256).
+#[derive(Clone)]
+pub struct EchKey {
+    config: EchConfigPayload,
+    private_key: Arc<dyn StaticX25519Key>,
+}
+
+impl EchKey {
+    /// Make a key with identifier `config_id`, from the 32-byte X25519
+    /// `private_key`.  Clients which reject ECH will authenticate the
+    /// server as `public_name` instead.
+    ///
+    /// The key is loaded by the default crypto provider, which needs
+    /// the `static-x25519` crate feature for this.
+    pub fn new(config_id: u8, public_name: &str, private_key: &[u8]) -> Result<Self, Error> {
+        Self::new_with_provider(&RING, config_id, public_name, private_key)
+    }
+
+    /// Like [`EchKey::new`], but the key is loaded by `provider`: see
+    /// [`CryptoProvider::load_static_x25519_key`].
+    pub fn new_with_provider(
+        provider: &dyn CryptoProvider,
+        config_id: u8,
+        public_name: &str,
+        private_key: &[u8],
+    ) -> Result<Self, Error> {
+        if ServerName::try_from(public_name).is_err() || private_key.len() != hpke::X25519_LEN {
+            return Err(Error::InvalidEchConfig);
+        }
+        let private_key = provider.load_static_x25519_key(private_key)?;
+
+        let config = EchConfigPayload::V18(EchConfigContents {
+            key_config: HpkeKeyConfig {
+                config_id,
+                kem_id: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+                public_key: PayloadU16::new(private_key.public_key().to_vec()),
+                symmetric_cipher_suites: SERVER_SUITES.to_vec(),
+            },
+            maximum_name_length: 0,
+            public_name: PayloadU8::new(public_name.as_bytes().to_vec()),
+            extensions: Vec::new(),
+        });
+
+        Ok(Self {
+            config,
+            private_key,
+        })
+    }
+
+    /// The encoded `ECHConfigList` containing just this key's
+    /// configuration, for publishing to clients.
+    pub fn config_list(&self) -> Vec<u8> {
+        vec![self.config.clone()].get_encoding()
+    }
+
+    fn contents(&self) -> &EchConfigContents {
+        match self.config {
+            EchConfigPayload::V18(ref contents) => contents,
+            EchConfigPayload::Unknown { .. } => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for EchKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EchKey")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// The `info` parameter for HPKE: "tls ech", a zero byte, and the
+/// encoded `ECHConfig`.
+fn hpke_info(config_encoding: &[u8]) -> Vec<u8> {
+    let mut info = b"tls ech\0".to_vec();
+    info.extend_from_slice(config_encoding);
+    info
+}
+
+/// Compute the acceptance confirmation signal, given the hash of the
+/// transcript up to and including the (modified) ServerHello or
+/// HelloRetryRequest.
+fn confirmation(
+    provider: &'static dyn CryptoProvider,
+    alg: HashAlgorithm,
+    inner_random: &Random,
+    transcript_hash: &Digest,
+    hrr: bool,
+) -> [u8; CONFIRMATION_LEN] {
+    let zeroes = [0u8; key_schedule::MAX_HASH_LEN];
+    let prk = Prk::extract(provider, alg, &zeroes[..alg.output_len()], &inner_random.0);
+    let label: &[u8] = if hrr {
+        b"hrr ech accept confirmation"
+    } else {
+        b"ech accept confirmation"
+    };
+    let mut out = [0u8; CONFIRMATION_LEN];
+    // Won't fail: CONFIRMATION_LEN is far below the HKDF output limit.
+    key_schedule::hkdf_expand_label(&prk, label, transcript_hash.as_ref(), &mut out).unwrap();
+    out
+}
+
+/// The encoding of the ServerHello `m`, with the last bytes of its
+/// random replaced with zeroes, as input to the confirmation.
+fn server_hello_for_confirmation(m: &Message) -> Vec<u8> {
+    // type (1), length (3) and legacy_version (2), then the random.
+    const RANDOM_END: usize = 4 + 2 + 32;
+    let mut encoding = match m.payload {
+        MessagePayload::Handshake(ref hs) => hs.get_encoding(),
+        _ => unreachable!(),
+    };
+    for b in &mut encoding[RANDOM_END - CONFIRMATION_LEN..RANDOM_END] {
+        *b = 0;
+    }
+    encoding
+}
+
+/// The encoding of `hrr`, with its confirmation replaced by `confirmation`.
+fn hello_retry_for_confirmation(hrr: &HelloRetryRequest, confirmation: &[u8]) -> Vec<u8> {
+    let mut hrr = hrr.clone();
+    for ext in &mut hrr.extensions {
+        if let HelloRetryExtension::EncryptedClientHello(ref mut payload) = *ext {
+            *payload = Payload::new(confirmation);
+        }
+    }
+
+    HandshakeMessagePayload {
+        typ: HandshakeType::HelloRetryRequest,
+        payload: HandshakePayload::HelloRetryRequest(hrr),
+    }
+    .get_encoding()
+}
+
+/// The client's state for an ECH offer, for the duration of the
+/// handshake up to the ServerHello.
+///
+/// The transcript of the inner hello is the one kept by the handshake
+/// state; this keeps the transcript of the outer hello, in case the
+/// server rejects ECH.
+pub(crate) struct EchOffer {
+    provider: &'static dyn CryptoProvider,
+    config: EchConfig,
+    hpke: HpkeContext,
+    enc: Vec<u8>,
+    pub(crate) outer_random: Random,
+    pub(crate) outer_transcript: HandshakeHashBuffer,
+    /// Whether the server accepted our offer in its HelloRetryRequest,
+    /// if it sent one.
+    pub(crate) hrr_accepted: Option<bool>,
+}
+
+impl EchOffer {
+    pub(crate) fn new(
+        provider: &'static dyn CryptoProvider,
+        config: &EchConfig,
+        outer_transcript: HandshakeHashBuffer,
+    ) -> Result<Self, Error> {
+        let (enc, hpke) = HpkeContext::setup_sender(
+            provider,
+            &config.suite,
+            &config.contents.key_config.public_key.0,
+            &hpke_info(&config.encoding),
+        )
+        .ok_or(Error::FailedToGetRandomBytes)?;
+
+        Ok(Self {
+            provider,
+            config: config.clone(),
+            hpke,
+            enc,
+            outer_random: Random::new(provider)?,
+            outer_transcript,
+            hrr_accepted: None,
+        })
+    }
+
+    /// The name which the outer hello is sent to, and which the
+    /// server authenticates as if it rejects ECH.
+    pub(crate) fn public_name(&self) -> ServerName {
+        self.config.public_name.clone()
+    }
+
+    /// Make the outer ClientHello to send, carrying the encrypted
+    /// `inner` ClientHello.
+    pub(crate) fn seal_outer(
+        &mut self,
+        inner: &HandshakeMessagePayload,
+        retry: bool,
+    ) -> Result<HandshakeMessagePayload, Error> {
+        let inner = match inner.payload {
+            HandshakePayload::ClientHello(ref ch) => ch,
+            _ => unreachable!(),
+        };
+        let encoded_inner = encode_inner(inner, self.config.contents.maximum_name_length);
+
+        // The outer hello carries everything in the inner one, except
+        // the real server name, and anything to do with resumption.
+        let mut extensions: Vec<ClientExtension> = inner
+            .extensions
+            .iter()
+            .filter(|ext| {
+                !matches!(
+                    ext.get_type(),
+                    ExtensionType::ServerName
+                        | ExtensionType::PreSharedKey
+                        | ExtensionType::EarlyData
+                        | ExtensionType::EncryptedClientHello
+                )
+            })
+            .cloned()
+            .collect();
+        if let Some(public_name) = self.config.public_name.for_sni() {
+            extensions.push(ClientExtension::make_sni(public_name));
+        }
+
+        // The enc is only sent in the first ClientHello.
+        let enc = if retry { Vec::new() } else { self.enc.clone() };
+        let payload_len = self
+            .hpke
+            .sealed_len(encoded_inner.len());
+        extensions.push(ClientExtension::EncryptedClientHello(
+            EncryptedClientHello::Outer(EchOuterPayload {
+                cipher_suite: self.config.suite,
+                config_id: self
+                    .config
+                    .contents
+                    .key_config
+                    .config_id,
+                enc: PayloadU16::new(enc),
+                payload: PayloadU16::new(vec![0; payload_len]),
+            }),
+        ));
+
+        let mut outer = ClientHelloPayload {
+            client_version: inner.client_version,
+            random: self.outer_random,
+            session_id: inner.session_id,
+            cipher_suites: inner.cipher_suites.clone(),
+            compression_methods: inner.compression_methods.clone(),
+            extensions,
+        };
+
+        // The outer hello, with a zeroed payload, is the AAD.
+        outer.add_padding();
+        let sealed = self
+            .hpke
+            .seal(&outer.get_encoding(), &encoded_inner)
+            .ok_or_else(|| Error::General("ECH encryption failed".to_string()))?;
+        for ext in outer.extensions.iter_mut() {
+            if let ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(offer)) = ext {
+                offer.payload = PayloadU16::new(sealed);
+                break;
+            }
+        }
+
+        Ok(HandshakeMessagePayload {
+            typ: HandshakeType::ClientHello,
+            payload: HandshakePayload::ClientHello(outer),
+        })
+    }
+
+    /// Did the server accept ECH in ServerHello `m`?  `inner_transcript`
+    /// covers the inner ClientHello(s), whose random was `inner_random`.
+    pub(crate) fn accepted(
+        &self,
+        suite: &Tls13CipherSuite,
+        inner_transcript: &HandshakeHashBuffer,
+        inner_random: &Random,
+        m: &Message,
+    ) -> bool {
+        let server_random = match m.payload {
+            MessagePayload::Handshake(HandshakeMessagePayload {
+                payload: HandshakePayload::ServerHello(ref sh),
+                ..
+            }) => sh.random,
+            _ => return false,
+        };
+
+        let hash = inner_transcript.get_hash_given(
+            self.provider,
+            suite.get_hash(),
+            &server_hello_for_confirmation(m),
+        );
+        let expected = confirmation(
+            self.provider,
+            suite.hash_algorithm,
+            inner_random,
+            &hash,
+            false,
+        );
+        constant_time::verify_slices_are_equal(&expected, &server_random.0[32 - CONFIRMATION_LEN..])
+            .is_ok()
+    }
+
+    /// Process the HelloRetryRequest `m`: note whether the server accepted
+    /// our offer, and add it to the outer transcript.  `inner_transcript`
+    /// covers the inner ClientHello, and is already rolled up for `m`.
+    pub(crate) fn handle_hello_retry(
+        &mut self,
+        suite: SupportedCipherSuite,
+        inner_transcript: &HandshakeHashBuffer,
+        inner_random: &Random,
+        m: &Message,
+    ) {
+        let hrr = match m.payload {
+            MessagePayload::Handshake(HandshakeMessagePayload {
+                payload: HandshakePayload::HelloRetryRequest(ref hrr),
+                ..
+            }) => hrr,
+            _ => unreachable!(),
+        };
+
+        let accepted = match (suite.tls13(), hrr.get_ech_confirmation()) {
+            (Some(suite), Some(received)) => {
+                let zeroes = [0u8; CONFIRMATION_LEN];
+                let hash = inner_transcript.get_hash_given(
+                    self.provider,
+                    suite.get_hash(),
+                    &hello_retry_for_confirmation(hrr, &zeroes),
+                );
+                let expected = confirmation(
+                    self.provider,
+                    suite.hash_algorithm,
+                    inner_random,
+                    &hash,
+                    true,
+                );
+                constant_time::verify_slices_are_equal(&expected, &received.0).is_ok()
+            }
+            _ => false,
+        };
+        self.hrr_accepted = Some(accepted);
+
+        let outer_transcript = mem::replace(&mut self.outer_transcript, HandshakeHashBuffer::new());
+        self.outer_transcript = outer_transcript
+            .start_hash(self.provider, suite.get_hash())
+            .into_hrr_buffer();
+        self.outer_transcript.add_message(m);
+    }
+}
+
+/// Encode `inner` for encryption: this is the inner ClientHello, without
+/// its session id (which the server copies from the outer one), and
+/// padded to obscure the length of the server name.
+fn encode_inner(inner: &ClientHelloPayload, maximum_name_length: u8) -> Vec<u8> {
+    let mut encoding = ClientHelloPayload {
+        client_version: inner.client_version,
+        random: inner.random,
+        session_id: SessionID::empty(),
+        cipher_suites: inner.cipher_suites.clone(),
+        compression_methods: inner.compression_methods.clone(),
+        extensions: inner.extensions.clone(),
+    }
+    .get_encoding();
+
+    let maximum_name_length = usize::from(maximum_name_length);
+    let name_len = inner
+        .get_sni_extension()
+        .and_then(|sni| sni.get_single_hostname())
+        .map(|name| {
+            let name: &str = name.into();
+            name.len()
+        });
+    let mut padding = match name_len {
+        Some(len) => maximum_name_length.saturating_sub(len),
+        // The length of a server_name extension with a name
+        // of `maximum_name_length`.
+        None => maximum_name_length + 9,
+    };
+    padding += 31 - ((encoding.len() + padding + 31) % 32);
+
+    encoding.resize(encoding.len() + padding, 0);
+    encoding
+}
+
+/// The server's state once it has accepted ECH, for decrypting the
+/// second inner ClientHello after a HelloRetryRequest.
+pub(crate) struct EchAccepted {
+    hpke: HpkeContext,
+    config_id: u8,
+    suite: HpkeSymmetricCipherSuite,
+}
+
+/// Try to decrypt the inner ClientHello carried in the ClientHello `m`,
+/// using one of our `keys`.
+///
+/// `accepted` is our state if we accepted ECH in an earlier ClientHello;
+/// this is then the second ClientHello, and must carry an inner one.
+///
+/// Returns the inner ClientHello, or `None` if we reject ECH or the
+/// client did not offer it.
+pub(crate) fn server_open(
+    provider: &dyn CryptoProvider,
+    keys: &[EchKey],
+    m: &Message,
+    accepted: Option<EchAccepted>,
+    common: &mut ConnectionCommon,
+) -> Result<Option<(Message, EchAccepted)>, Error> {
+    let outer = match m.payload {
+        MessagePayload::Handshake(HandshakeMessagePayload {
+            payload: HandshakePayload::ClientHello(ref ch),
+            ..
+        }) => ch,
+        _ => unreachable!(),
+    };
+
+    let offer = match outer.get_ech_extension() {
+        Some(EncryptedClientHello::Outer(offer)) => offer,
+        Some(EncryptedClientHello::Inner) => {
+            return Err(common.illegal_param("inner ECH extension in outer ClientHello"));
+        }
+        None if accepted.is_some() => {
+            return Err(common.missing_extension("ECH not offered on retry"));
+        }
+        None => return Ok(None),
+    };
+
+    let retry = accepted.is_some();
+    let mut state = match accepted {
+        Some(state) => {
+            if state.config_id != offer.config_id
+                || state.suite != offer.cipher_suite
+                || !offer.enc.0.is_empty()
+            {
+                return Err(common.illegal_param("ECH offer changed on retry"));
+            }
+            state
+        }
+        None => match setup_receiver(provider, keys, offer) {
+            Some(state) => state,
+            None => {
+                debug!("Rejecting ECH: no usable key");
+                return Ok(None);
+            }
+        },
+    };
+
+    let plaintext = match state
+        .hpke
+        .open(&outer_aad(outer), &offer.payload.0)
+    {
+        Some(plaintext) => plaintext,
+        None if retry => {
+            common.send_fatal_alert(AlertDescription::DecryptError);
+            return Err(Error::DecryptError);
+        }
+        None => {
+            debug!("Rejecting ECH: decryption failed");
+            return Ok(None);
+        }
+    };
+
+    let inner = decode_inner(&plaintext, outer)
+        .ok_or_else(|| common.illegal_param("invalid ECH inner ClientHello"))?;
+    debug!("Accepted ECH");
+
+    let inner = Message {
+        version: m.version,
+        payload: MessagePayload::Handshake(HandshakeMessagePayload {
+            typ: HandshakeType::ClientHello,
+            payload: HandshakePayload::ClientHello(inner),
+        }),
+    };
+    Ok(Some((inner, state)))
+}
+
+fn setup_receiver(
+    provider: &dyn CryptoProvider,
+    keys: &[EchKey],
+    offer: &EchOuterPayload,
+) -> Option<EchAccepted> {
+    if !SERVER_SUITES.contains(&offer.cipher_suite) {
+        return None;
+    }
+
+    keys.iter()
+        .filter(|key| key.contents().key_config.config_id == offer.config_id)
+        .find_map(|key| {
+            HpkeContext::setup_receiver(
+                provider,
+                &offer.cipher_suite,
+                &offer.enc.0,
+                key.private_key.as_ref(),
+                &hpke_info(&key.config.get_encoding()),
+            )
+        })
+        .map(|hpke| EchAccepted {
+            hpke,
+            config_id: offer.config_id,
+            suite: offer.cipher_suite,
+        })
+}
+
+/// The outer ClientHello `outer`, with its ECH payload zeroed.
+fn outer_aad(outer: &ClientHelloPayload) -> Vec<u8> {
+    let extensions = outer
+        .extensions
+        .iter()
+        .map(|ext| match ext {
+            ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(offer)) => {
+                let mut offer = offer.clone();
+                offer.payload = PayloadU16::new(vec![0; offer.payload.0.len()]);
+                ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(offer))
+            }
+            ext => ext.clone(),
+        })
+        .collect();
+
+    ClientHelloPayload {
+        client_version: outer.client_version,
+        random: outer.random,
+        session_id: outer.session_id,
+        cipher_suites: outer.cipher_suites.clone(),
+        compression_methods: outer.compression_methods.clone(),
+        extensions,
+    }
+    .get_encoding()
+}
+
+/// Decode the inner ClientHello from `plaintext`, restoring what the
+/// client omitted from its encoding using the `outer` ClientHello.
+fn decode_inner(plaintext: &[u8], outer: &ClientHelloPayload) -> Option<ClientHelloPayload> {
+    // Find where the ClientHello ends and the padding starts.
+    let mut r = Reader::init(plaintext);
+    ProtocolVersion::read(&mut r)?;
+    Random::read(&mut r)?;
+    SessionID::read(&mut r)?;
+    let len = u16::read(&mut r)?;
+    r.take(usize::from(len))?;
+    let len = u8::read(&mut r)?;
+    r.take(usize::from(len))?;
+    let len = u16::read(&mut r)?;
+    r.take(usize::from(len))?;
+
+    let (hello, padding) = plaintext.split_at(r.used());
+    if padding.iter().any(|b| *b != 0) {
+        return None;
+    }
+
+    let mut inner = ClientHelloPayload::read_bytes(hello)?;
+    if !inner.session_id.is_empty() {
+        return None;
+    }
+    inner.session_id = outer.session_id;
+
+    // Expand any ech_outer_extensions, which refer to extensions of the
+    // outer ClientHello, in order.
+    let mut outer_exts = outer.extensions.iter();
+    let mut extensions = Vec::with_capacity(inner.extensions.len());
+    for ext in inner.extensions {
+        match ext {
+            ClientExtension::EncryptedClientHelloOuterExtensions(types) => {
+                for typ in types {
+                    if typ == ExtensionType::EncryptedClientHello {
+                        return None;
+                    }
+                    let ext = outer_exts.find(|ext| ext.get_type() == typ)?;
+                    extensions.push(ext.clone());
+                }
+            }
+            ext => extensions.push(ext),
+        }
+    }
+    inner.extensions = extensions;
+
+    if inner.has_duplicate_extension()
+        || !matches!(inner.get_ech_extension(), Some(EncryptedClientHello::Inner))
+    {
+        return None;
+    }
+
+    // The inner ClientHello must offer only TLS1.3 or later.
+    let versions = inner.get_versions_extension()?;
+    if !versions.contains(&ProtocolVersion::TLSv1_3)
+        || versions
+            .iter()
+            .any(|v| v.get_u16() <= ProtocolVersion::TLSv1_2.get_u16())
+    {
+        return None;
+    }
+
+    Some(inner)
+}
+
+/// The server's `ECHConfigList` to send to a client whose ECH offer we
+/// rejected, as `retry_configs`.
+pub(crate) fn retry_configs(keys: &[EchKey]) -> EchConfigList {
+    keys.iter()
+        .map(|key| key.config.clone())
+        .collect()
+}
+
+/// Set the end of the server random in the ServerHello `sh`, and in
+/// `randoms`, to the confirmation that we accepted ECH.  `transcript`
+/// covers the inner ClientHello(s).
+pub(crate) fn confirm_server_hello(
+    suite: &Tls13CipherSuite,
+    transcript: &HandshakeHash,
+    randoms: &mut ConnectionRandoms,
+    sh: &mut Message,
+) {
+    let hash = transcript.get_hash_given(&server_hello_for_confirmation(sh));
+    let confirmation = confirmation(
+        transcript.provider(),
+        suite.hash_algorithm,
+        &Random::from(randoms.client),
+        &hash,
+        false,
+    );
+    randoms.server[32 - CONFIRMATION_LEN..].copy_from_slice(&confirmation);
+
+    if let MessagePayload::Handshake(HandshakeMessagePayload {
+        payload: HandshakePayload::ServerHello(ref mut sh),
+        ..
+    }) = sh.payload
+    {
+        sh.random = Random::from(randoms.server);
+    }
+}
+
+/// Add the confirmation that we accepted ECH to `hrr`.  `transcript`
+/// covers the inner ClientHello, and is already rolled up for `hrr`.
+pub(crate) fn confirm_hello_retry(
+    suite: &Tls13CipherSuite,
+    transcript: &HandshakeHash,
+    inner_random: &Random,
+    hrr: &mut HelloRetryRequest,
+) {
+    let zeroes = [0u8; CONFIRMATION_LEN];
+    hrr.extensions
+        .push(HelloRetryExtension::EncryptedClientHello(Payload::new(
+            &zeroes[..],
+        )));
+    let hash = transcript.get_hash_given(&hello_retry_for_confirmation(hrr, &zeroes));
+    let confirmation = confirmation(
+        transcript.provider(),
+        suite.hash_algorithm,
+        inner_random,
+        &hash,
+        true,
+    );
+    if let Some(HelloRetryExtension::EncryptedClientHello(payload)) = hrr.extensions.last_mut() {
+        *payload = Payload::new(&confirmation[..]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::msgs::enums::{CipherSuite, Compression, NamedGroup};
+
+    fn sample_hello(name: &str) -> ClientHelloPayload {
+        let name: webpki::DnsName = webpki::DnsNameRef::try_from_ascii_str(name)
+            .unwrap()
+            .into();
+        ClientHelloPayload {
+            client_version: ProtocolVersion::TLSv1_2,
+            random: Random::from([1u8; 32]),
+            session_id: SessionID::random(&RING).unwrap(),
+            cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
+            compression_methods: vec![Compression::Null],
+            extensions: vec![
+                ClientExtension::make_sni(name.as_ref()),
+                ClientExtension::NamedGroups(vec![NamedGroup::X25519]),
+                ClientExtension::SupportedVersions(vec![ProtocolVersion::TLSv1_3]),
+                ClientExtension::EncryptedClientHello(EncryptedClientHello::Inner),
+            ],
+        }
+    }
+
+    #[test]
+    fn inner_hello_is_padded() {
+        for name in &["a.com", "a-much-longer-server-name.example.com"] {
+            let hello = sample_hello(name);
+            let short = encode_inner(&hello, 0);
+            assert_eq!(short.len() % 32, 0);
+
+            let padded = encode_inner(&hello, 64);
+            assert_eq!(padded.len() % 32, 0);
+            assert!(padded.len() >= short.len() + 64 - name.len() - 31);
+        }
+
+        // Names up to the maximum length are indistinguishable.
+        assert_eq!(
+            encode_inner(&sample_hello("a.com"), 40).len(),
+            encode_inner(&sample_hello("abcdefghijklmnopqrst.com"), 40).len()
+        );
+    }
+
+    #[test]
+    fn can_decode_inner_hello() {
+        let outer = sample_hello("public.com");
+        let inner = sample_hello("private.com");
+
+        let decoded = decode_inner(&encode_inner(&inner, 32), &outer).unwrap();
+        assert_eq!(decoded.get_encoding(), {
+            let mut expected = inner;
+            expected.session_id = outer.session_id;
+            expected.get_encoding()
+        });
+    }
+
+    #[test]
+    fn decode_inner_expands_outer_extensions() {
+        let outer = sample_hello("public.com");
+        let mut inner = sample_hello("private.com");
+        inner.extensions[1] = ClientExtension::EncryptedClientHelloOuterExtensions(vec![
+            ExtensionType::EllipticCurves,
+        ]);
+
+        let decoded = decode_inner(&encode_inner(&inner, 0), &outer).unwrap();
+        assert_eq!(
+            decoded.extensions[1].get_type(),
+            ExtensionType::EllipticCurves
+        );
+
+        // The ECH extension itself cannot be referenced.
+        inner.extensions[1] = ClientExtension::EncryptedClientHelloOuterExtensions(vec![
+            ExtensionType::EncryptedClientHello,
+        ]);
+        assert!(decode_inner(&encode_inner(&inner, 0), &outer).is_none());
+
+        // Nor can extensions missing from the outer hello.
+        inner.extensions[1] = ClientExtension::EncryptedClientHelloOuterExtensions(vec![
+            ExtensionType::ALProtocolNegotiation,
+        ]);
+        assert!(decode_inner(&encode_inner(&inner, 0), &outer).is_none());
+    }
+
+    #[test]
+    fn decode_inner_rejects_bad_hellos() {
+        let outer = sample_hello("public.com");
+        let inner = sample_hello("private.com");
+
+        let mut encoded = encode_inner(&inner, 32);
+        *encoded.last_mut().unwrap() = 1;
+        assert!(decode_inner(&encoded, &outer).is_none());
+
+        let mut with_session_id = sample_hello("private.com");
+        with_session_id.session_id = SessionID::random(&RING).unwrap();
+        assert!(decode_inner(&with_session_id.get_encoding(), &outer).is_none());
+
+        let mut without_ech = sample_hello("private.com");
+        without_ech.extensions.pop();
+        assert!(decode_inner(&encode_inner(&without_ech, 0), &outer).is_none());
+
+        let mut with_tls12 = inner;
+        with_tls12.extensions[2] = ClientExtension::SupportedVersions(vec![
+            ProtocolVersion::TLSv1_3,
+            ProtocolVersion::TLSv1_2,
+        ]);
+        assert!(decode_inner(&encode_inner(&with_tls12, 0), &outer).is_none());
+    }
+
+    #[cfg(feature = "static-x25519")]
+    #[test]
+    fn echkey_config_is_usable_by_client() {
+        let key = EchKey::new(7, "public.com", &[3u8; 32]).unwrap();
+        let config = EchConfig::new(&key.config_list()).unwrap();
+        assert_eq!(config.contents.key_config.config_id, 7);
+        assert_eq!(config.public_name, ServerName::try_from("public.com").unwrap());
+        assert_eq!(retry_configs(&[key.clone(), key]).len(), 2);
+    }
+}
@@ -184,6 +184,77 @@ impl fmt::Display for WebPkiOp {
     }
 }
 
+/// Reasons for a stapled OCSP response to be refused, used in [`Error`].
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum OcspError {
+    /// Encountered an illegal encoding.
+    BadEncoding,
+
+    /// The responder did not successfully produce a response.
+    UnsuccessfulResponse,
+
+    /// The response was of a type other than the basic response type.
+    UnsupportedResponseType,
+
+    /// The issuer of the end-entity certificate could not be found
+    /// among the intermediates or trust anchors.
+    UnknownIssuer,
+
+    /// The response was not signed by the issuer, or by a responder
+    /// authorised by the issuer.
+    ResponderNotAuthorized,
+
+    /// The response was signed with an unsupported signature algorithm.
+    UnsupportedSignatureAlgorithm,
+
+    /// The response's signature was invalid.
+    InvalidSignature,
+
+    /// The response did not contain the status of the end-entity
+    /// certificate.
+    CertNotCovered,
+
+    /// The response is not valid yet.
+    NotValidYet,
+
+    /// The response has expired.
+    Expired,
+
+    /// The end-entity certificate has been revoked.
+    CertRevoked,
+
+    /// The responder does not know the end-entity certificate.
+    CertStatusUnknown,
+
+    /// No response was stapled, but one was required.
+    Missing,
+}
+
+impl fmt::Display for OcspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OcspError::BadEncoding => write!(f, "bad DER encoding"),
+            OcspError::UnsuccessfulResponse => write!(f, "responder reported an error"),
+            OcspError::UnsupportedResponseType => write!(f, "unsupported response type"),
+            OcspError::UnknownIssuer => write!(f, "issuer of certificate not found"),
+            OcspError::ResponderNotAuthorized => {
+                write!(f, "responder not authorized by certificate issuer")
+            }
+            OcspError::UnsupportedSignatureAlgorithm => {
+                write!(f, "unsupported signature algorithm")
+            }
+            OcspError::InvalidSignature => write!(f, "invalid signature"),
+            OcspError::CertNotCovered => write!(f, "response does not cover certificate"),
+            OcspError::NotValidYet => write!(f, "response not yet valid"),
+            OcspError::Expired => write!(f, "response expired"),
+            OcspError::CertRevoked => write!(f, "certificate revoked"),
+            OcspError::CertStatusUnknown => write!(f, "certificate status unknown"),
+            OcspError::Missing => write!(f, "no response stapled"),
+        }
+    }
+}
+
 /// rustls reports protocol errors using this type.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Error {
@@ -241,6 +312,38 @@ pub enum Error {
     /// The presented SCT(s) were invalid.
     InvalidSct(sct::Error),
 
+    /// The revocation status of the presented certificate could not
+    /// be established from its stapled OCSP response, or it was
+    /// revoked.
+    OcspError(OcspError),
+
+    /// The peer's compressed certificate chain could not be
+    /// decompressed, or was larger than we allow.
+    CertificateDecompressionFailed,
+
+    /// The server rejected our Encrypted Client Hello offer.  The
+    /// server authenticated itself as the `public_name` from our
+    /// [`EchConfig`](crate::EchConfig), but no data was exchanged.
+    ///
+    /// `retry_configs` is the `ECHConfigList` the server supplied to
+    /// use instead, if any: a new connection may use it with
+    /// [`EchConfig::new`](crate::EchConfig::new).
+    EncryptedClientHelloRejected {
+        /// The server's replacement `ECHConfigList`.
+        retry_configs: Option<Vec<u8>>,
+    },
+
+    /// An `ECHConfigList` or Encrypted Client Hello key supplied in
+    /// configuration could not be used.
+    InvalidEchConfig,
+
+    /// A delegated credential could not be issued or loaded, or the
+    /// server presented a delegated credential which was not valid.
+    InvalidDelegatedCredential,
+
+    /// The peer presented a raw public key (RFC7250) which we do not trust.
+    UntrustedRawPublicKey,
+
     /// A catch-all error for unlikely errors.
     General(String),
 
@@ -263,6 +366,21 @@ pub enum Error {
     /// The `max_fragment_size` value supplied in configuration was too small,
     /// or too large.
     BadMaxFragmentSize,
+    /// The peer sent more consecutive `KeyUpdate` messages than
+    /// `PeerMessageLimits::key_updates` allows.
+    PeerSentTooManyKeyUpdates,
+
+    /// The peer sent more consecutive `NewSessionTicket` messages than
+    /// `PeerMessageLimits::new_session_tickets` allows.
+    PeerSentTooManyTickets,
+
+    /// The peer sent more consecutive warning alerts than
+    /// `PeerMessageLimits::warning_alerts` allows.
+    PeerSentTooManyWarningAlerts,
+
+    /// The peer sent more consecutive empty records than
+    /// `PeerMessageLimits::empty_records` allows.
+    PeerSentTooManyEmptyRecords,
 }
 
 fn join<T: fmt::Debug>(items: &[T]) -> String {
@@ -311,9 +429,23 @@ impl fmt::Display for Error {
             Error::UnsupportedNameType => write!(f, "presented server name type wasn't supported"),
             Error::DecryptError => write!(f, "cannot decrypt peer's message"),
             Error::PeerSentOversizedRecord => write!(f, "peer sent excess record size"),
+            Error::PeerSentTooManyKeyUpdates => write!(f, "peer sent too many key updates"),
+            Error::PeerSentTooManyTickets => write!(f, "peer sent too many session tickets"),
+            Error::PeerSentTooManyWarningAlerts => write!(f, "peer sent too many warning alerts"),
+            Error::PeerSentTooManyEmptyRecords => write!(f, "peer sent too many empty records"),
             Error::HandshakeNotComplete => write!(f, "handshake not complete"),
             Error::NoApplicationProtocol => write!(f, "peer doesn't support any known protocol"),
             Error::InvalidSct(ref err) => write!(f, "invalid certificate timestamp: {:?}", err),
+            Error::OcspError(ref err) => write!(f, "OCSP check failed: {}", err),
+            Error::CertificateDecompressionFailed => {
+                write!(f, "peer's compressed certificate could not be decompressed")
+            }
+            Error::EncryptedClientHelloRejected { .. } => {
+                write!(f, "server rejected encrypted client hello")
+            }
+            Error::InvalidEchConfig => write!(f, "invalid encrypted client hello configuration"),
+            Error::InvalidDelegatedCredential => write!(f, "invalid delegated credential"),
+            Error::UntrustedRawPublicKey => write!(f, "peer presented an untrusted raw public key"),
             Error::FailedToGetCurrentTime => write!(f, "failed to get current time"),
             Error::FailedToGetRandomBytes => write!(f, "failed to get random bytes"),
             Error::BadMaxFragmentSize => {
@@ -342,7 +474,7 @@ impl From<rand::GetRandomFailed> for Error {
 #[cfg(test)]
 mod tests {
     use super::Error;
-    use super::{WebPkiError, WebPkiOp};
+    use super::{OcspError, WebPkiError, WebPkiOp};
 
     #[test]
     fn smoke() {
@@ -420,6 +552,26 @@ mod tests {
                 WebPkiOp::ParseEndEntity,
             ),
             Error::InvalidSct(sct::Error::MalformedSct),
+            Error::OcspError(OcspError::BadEncoding),
+            Error::OcspError(OcspError::UnsuccessfulResponse),
+            Error::OcspError(OcspError::UnsupportedResponseType),
+            Error::OcspError(OcspError::UnknownIssuer),
+            Error::OcspError(OcspError::ResponderNotAuthorized),
+            Error::OcspError(OcspError::UnsupportedSignatureAlgorithm),
+            Error::OcspError(OcspError::InvalidSignature),
+            Error::OcspError(OcspError::CertNotCovered),
+            Error::OcspError(OcspError::NotValidYet),
+            Error::OcspError(OcspError::Expired),
+            Error::OcspError(OcspError::CertRevoked),
+            Error::OcspError(OcspError::CertStatusUnknown),
+            Error::OcspError(OcspError::Missing),
+            Error::CertificateDecompressionFailed,
+            Error::EncryptedClientHelloRejected {
+                retry_configs: Some(vec![1, 2, 3]),
+            },
+            Error::InvalidEchConfig,
+            Error::InvalidDelegatedCredential,
+            Error::UntrustedRawPublicKey,
             Error::General("undocumented error".to_string()),
             Error::FailedToGetCurrentTime,
             Error::FailedToGetRandomBytes,
@@ -427,6 +579,10 @@ mod tests {
             Error::PeerSentOversizedRecord,
             Error::NoApplicationProtocol,
             Error::BadMaxFragmentSize,
+            Error::PeerSentTooManyKeyUpdates,
+            Error::PeerSentTooManyTickets,
+            Error::PeerSentTooManyWarningAlerts,
+            Error::PeerSentTooManyEmptyRecords,
         ];
 
         for err in all {
@@ -0,0 +1,283 @@
+//! Finite-field Diffie-Hellman in the RFC7919 groups, which ring
+//! doesn't support.
+//!
+//! Modular exponentiation is done by crypto-bigint, in constant time.
+//! Private exponents are short, as RFC7919 section 5.2 allows.
+
+use crate::msgs::enums::NamedGroup;
+
+use crypto_bigint::modular::constant_mod::{Residue, ResidueParams};
+use crypto_bigint::{Limb, Uint, U2048, U3072, U384, U4096};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Like crypto-bigint's `impl_modulus!`, but with R^2 and R^3 mod p
+/// precomputed: having the compiler work them out for moduli this large
+/// takes it far too long.
+macro_rules! ffdhe_modulus {
+    ($name:ident, $uint:ty, $p:expr, $r2:expr, $r3:expr) => {
+        #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+        struct $name;
+
+        impl ResidueParams<{ <$uint>::LIMBS }> for $name {
+            const LIMBS: usize = <$uint>::LIMBS;
+            const MODULUS: $uint = <$uint>::from_be_hex($p);
+            // p > 2^(n-1), so R = 2^n mod p is 2^n - p.
+            const R: $uint = <$uint>::ZERO.wrapping_sub(&Self::MODULUS);
+            const R2: $uint = <$uint>::from_be_hex($r2);
+            const R3: $uint = <$uint>::from_be_hex($r3);
+            // The low 64 bits of p are all set, so -(p^-1) is 1 modulo
+            // the limb size.
+            const MOD_NEG_INV: Limb = Limb::ONE;
+        }
+    };
+}
+
+// The primes from RFC7919 appendix A.
+ffdhe_modulus!(
+    Ffdhe2048Modulus,
+    U2048,
+    "ffffffffffffffffadf85458a2bb4a9aafdc5620273d3cf1d8b9c583ce2d3695a9e13641146433fbcc939dce249b3ef97d2fe363630c75d8f681b202aec4617ad3df1ed5d5fd65612433f51f5f066ed0856365553ded1af3b557135e7f57c935984f0c70e0e68b77e2a689daf3efe8721df158a136ade73530acca4f483a797abc0ab182b324fb61d108a94bb2c8e3fbb96adab760d7f4681d4f42a3de394df4ae56ede76372bb190b07a7c8ee0a6d709e02fce1cdf7e2ecc03404cd28342f619172fe9ce98583ff8e4f1232eef28183c3fe3b1b4c6fad733bb5fcbc2ec22005c58ef1837d1683b2c6f34a26c1b2effa886b423861285c97ffffffffffffffff",
+    "352bd399be84058efaff50d29d573457a57c73bddc70fb82bae7b0b36e362dc06a315604189cd76b06bdeac1f5500fa7e8c2954e4c1804ca5c6d1aebdb06f65b6a12fb707c8c0510b297a8236fa93d282674e1d64fbcbdc8e6678eeb375db18eae1302f2f6d4777e9b894b245f6b69a144f0c619ce34845885a997d50cd51aecdd24a12753c8f09d627588c49875d5a7748d407c3437b7a8f8a98014a12b74e4b14884d8a18af8cee3e768c860d10b8a5f57d037430ee91e561ab426d07137fd70acf2aa4cf36dddf92f8e9ab7e33fb0f6afebb76e589d6ce9fdac6acff4eaaa18af748291173f2a0570187ec422eeb70a152f396458f3b8187be36bd38a4fa1",
+    "310bcd4eb4f25995afbe83e5d6bfb232a0118228274cb3078b74d956fb463d9d22ffeb115da208e8062b38ebe0341715c30f518f604fc9dfa020285ee8d9aba7b56e73eec452e77677b0b74075d92f51c88382e4ec72f6978c6fb8b70484537d61408dcd8186784a09e8fc564d6964ea01d37e0b1673ca43948b1b9349e43e1fbf2caf99ec835b44a88f396f28dc4c3babf5412bfd79354ac4effefe1dfa5593232935f32436ebb9d658e79c56d53cf3d63716c23c284871ad4dbee5470d69f9b6231e74cd01c3552aefeaa415e365ca12f6c7efef488f1cdb2f0c0b0f1759e55e4651d8910c444f499d2da5edfb774934c9a214dad4a6191b79e5ab768174a0"
+);
+
+ffdhe_modulus!(
+    Ffdhe3072Modulus,
+    U3072,
+    "ffffffffffffffffadf85458a2bb4a9aafdc5620273d3cf1d8b9c583ce2d3695a9e13641146433fbcc939dce249b3ef97d2fe363630c75d8f681b202aec4617ad3df1ed5d5fd65612433f51f5f066ed0856365553ded1af3b557135e7f57c935984f0c70e0e68b77e2a689daf3efe8721df158a136ade73530acca4f483a797abc0ab182b324fb61d108a94bb2c8e3fbb96adab760d7f4681d4f42a3de394df4ae56ede76372bb190b07a7c8ee0a6d709e02fce1cdf7e2ecc03404cd28342f619172fe9ce98583ff8e4f1232eef28183c3fe3b1b4c6fad733bb5fcbc2ec22005c58ef1837d1683b2c6f34a26c1b2effa886b4238611fcfdcde355b3b6519035bbc34f4def99c023861b46fc9d6e6c9077ad91d2691f7f7ee598cb0fac186d91caefe130985139270b4130c93bc437944f4fd4452e2d74dd364f2e21e71f54bff5cae82ab9c9df69ee86d2bc522363a0dabc521979b0deada1dbf9a42d5c4484e0abcd06bfa53ddef3c1b20ee3fd59d7c25e41d2b66c62e37ffffffffffffffff",
+    "a15c076b8eba952bf128e8a3bc34b85a6ed9eeadf80f1d3b235844dcd86373c1cf12dfc2d3ce8737dc47aa6edd2410f77829cc5363f6c287b9631002bcd3a514b01833b509e7823d10ce037cd0d96e9f27dea14fe4181598bde2b9c392d11c5f06a7f1f979ddbc728e6698ac54503db2336add6a568174b67f3b09c2874c8bd6fba48a97194ac0c35cef7feb3065c063b957d0168aa30dbdd17f17645d31b3e1051b9e86af98b24047c2f1201f7e26bf9e1242144781117f73f3196878672689adad49e21e8ea35ab593a5a31dbb969ab1b2a765c8382b4227313949c5d2b6b9ca830fc7e9c9aacdf8fa1e549ede734f622011d2e7dee086d8bba3113b3c4f5dd4faa7c313aad0c376ffea53a3c753b3e8283c273157a6fc373694dcde5e69926e078202e05502db2e90cb1352f92170a793367b56dafd28817adcf831676817783b269a46a689ae9b87c4093cf55afa4a4d777d71fad32ad2ee9266736dc40384e19b8a4f2f19c729b38c9f17d3b9ee6d42cb5b17bc46dcfa1861ec14ba1560",
+    "25664f44f30d3359e677ede650f05728fdf9636801ee1eb9de8aa9be5c759cf8df54bc0919e1d91df8f1a613d29d05060b4b903fb6cc093863944230a15ce380c3e3cf6c17bf585ad46438f4f54e27b00f0beeb70a522896587d4d483d27072045fad7696c686ddfef965b31f346a6801e5d4f6903fadff81cf52f220abada0e896d62edca62b65e70b0be4f0cc020843ecfe244e1fc1f1902ec2253b176a44fce4b86fb9cbe8598b2e0e23d9dded4ae117536838579fcea44e75c009236dd8fcf84bec3961e47a16ce78ba6db22842ac8cf25e56ceeb439836b27248507335b41a67c99f39355944eb78656801685a6948f8cf2fa76ffc09dad4fc92179d850a29f37d8b962e20eaa0060634eef3335c47d25733ce4b1e76fdfa9070c43be254ad1bbbaf762636fe550494776f5ca3327ad871beb6f94ed5f8df8ac081bb36db452daf154ee157b87af6e157c07ce47f4979d27a84d56f945b586e72579adccf8c424885e957588600bcc59ba8fb1af3df45ed309d7e934a701596249a6ed3a"
+);
+
+ffdhe_modulus!(
+    Ffdhe4096Modulus,
+    U4096,
+    "ffffffffffffffffadf85458a2bb4a9aafdc5620273d3cf1d8b9c583ce2d3695a9e13641146433fbcc939dce249b3ef97d2fe363630c75d8f681b202aec4617ad3df1ed5d5fd65612433f51f5f066ed0856365553ded1af3b557135e7f57c935984f0c70e0e68b77e2a689daf3efe8721df158a136ade73530acca4f483a797abc0ab182b324fb61d108a94bb2c8e3fbb96adab760d7f4681d4f42a3de394df4ae56ede76372bb190b07a7c8ee0a6d709e02fce1cdf7e2ecc03404cd28342f619172fe9ce98583ff8e4f1232eef28183c3fe3b1b4c6fad733bb5fcbc2ec22005c58ef1837d1683b2c6f34a26c1b2effa886b4238611fcfdcde355b3b6519035bbc34f4def99c023861b46fc9d6e6c9077ad91d2691f7f7ee598cb0fac186d91caefe130985139270b4130c93bc437944f4fd4452e2d74dd364f2e21e71f54bff5cae82ab9c9df69ee86d2bc522363a0dabc521979b0deada1dbf9a42d5c4484e0abcd06bfa53ddef3c1b20ee3fd59d7c25e41d2b669e1ef16e6f52c3164df4fb7930e9e4e58857b6ac7d5f42d69f6d187763cf1d5503400487f55ba57e31cc7a7135c886efb4318aed6a1e012d9e6832a907600a918130c46dc778f971ad0038092999a333cb8b7a1a1db93d7140003c2a4ecea9f98d0acc0a8291cdcec97dcf8ec9b55a7f88a46b4db5a851f44182e1c68a007e5e655f6affffffffffffffff",
+    "9ce5b1970fd8c13a6fcaa672721afd71cc49ddbc0a74a9650e12a8d373dc214591b4755b94db499f24d6c8eef2b79c5d7369bc4dea70d999b56ea5b6b85bc3b1bdc4a37d887bebf60f1a8df669c89e344da9766c9ceb35485f59f6b0ab45f30badb09e22bd27eea412d20272c64244ca226a8a8e677d0ec7c9eb898763438ab157115408c29e4cf66aeb2e33b0b7a10209ce26fc63dcb6280b049bf047427b9b82b12e47abbcf4fc551f30b27152fd098cb8a1c246c53eccc9b6faba81d4e216de7a0666456b50ee119d4a453023a5bb7180442e3f18ff7140b6b57efa3a6fa36b89e3e91844ba5cbc4dd3102e6f5fbf713ce8a48fffbc8324deb0227d48ff6aff669cc30e05c9c8a2e0d202150e35d7aca0555a19b5facd2e2e3aa917df47704c3b00d98c45d734162f974111bf2792a878f4d49b5910f958d3eaef1c794a4e832c0e85f8357c2f6322ee9cc3967e50c94c3190f543c1c9111d16fa00c9a44921435670b591370e604ff365115b49c11216d38d5a710fefcaa445efe222f8a07b289a4f4cc0831b7edab7f61350180a039ea0b3417f652a602ee0776ef6e31634057f484c3d506f4b38dce2d458f61ce9e47fd2fb803a6581370e542c8f269a7ec0216ed3ae93502bcd0155dd2e3f311f41dc52ed9c5b4f5e5e28faaab1dd5d6eb26dc72abf5627126a70aaf62f758eec79158587b51100a7c622b7cfb2cc2d",
+    "3f1333df783a237d58dcc92229abdfc30624ad3de965af3b64111c379dd4c19d1a54bf491420ff36fb8ce4f99ece9bfcc6d5d2c98850bd8ab1b9ee64ef503aae39b22fb3b28c0ce1133a7bbc5d9fc7cfb57d2377d750242aa539a1665ae8e415a30e4bd52906823009dd8bd94b6f89ad8a1d76f5e8857da2964ccd084e77f8bc18b9ca18fa021296fa55874937f446b79c96a52607241131cba01a3f0c50fa5db588aebd3e0223a13cc565815659da18b21f53f2a7b03ade57be29e1113c27218e4763b8e6a2d46768cc359aa06dfc618c358b5d784f275e5b63c6b9b2e7d51d60b55b9123fb4f5390b4df6f390136fac4bb505845a97656fd8c1b54d5b17c8b81a14ac072783aa0195763fc1e685cc7a2f454364a40e887f86e25b5ef1b089825c40b81b62168c62b54f54ad8c741853c34a35a9c42392abe1770d61f0e57cb18089b98dd29398da5918b4105857e0842414df843a8cca93ef2f3ce8af1d6853fac81d72220b36aa7253fc3e1e998f6aeee9524e0573c69835f5db9cc6144abf8856138b5d841e2df632937f6632bc27b5c659e72b4b74478531431b782f1914565798cbe89e074413c157aed181cd23369125faab298a0b66a763f50e6f39f62ae59ec6af480723d2de288a216e69e9f7cfa37c1e5818c94fa150466aa66aaf8a4b5916c017968032c4ca2f424a46f3843cb0ff8b6436b03002481674997c6"
+);
+
+/// The generator of every RFC7919 group.
+const GENERATOR: &[u8] = &[2];
+
+/// One of the RFC7919 groups.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FfdheGroup {
+    Ffdhe2048,
+    Ffdhe3072,
+    Ffdhe4096,
+}
+
+/// A private exponent, which is at most 384 bits long.
+pub(crate) struct PrivateExponent(U384);
+
+impl FfdheGroup {
+    const ALL: [Self; 3] = [Self::Ffdhe2048, Self::Ffdhe3072, Self::Ffdhe4096];
+
+    /// Identify the group with prime `p` and generator `g`, as sent by
+    /// a TLS1.2 server.  Parameters which aren't one of our groups are
+    /// refused rather than validated.
+    pub(crate) fn from_params(p: &[u8], g: &[u8]) -> Option<Self> {
+        if strip_leading_zeros(g) != GENERATOR {
+            return None;
+        }
+
+        let p = strip_leading_zeros(p);
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|group| group.prime() == p)
+    }
+
+    pub(crate) fn named_group(self) -> NamedGroup {
+        match self {
+            Self::Ffdhe2048 => NamedGroup::FFDHE2048,
+            Self::Ffdhe3072 => NamedGroup::FFDHE3072,
+            Self::Ffdhe4096 => NamedGroup::FFDHE4096,
+        }
+    }
+
+    /// The prime p, big-endian.  Public values and shared secrets are
+    /// the same length.
+    pub(crate) fn prime(self) -> Vec<u8> {
+        match self {
+            Self::Ffdhe2048 => encode(&Ffdhe2048Modulus::MODULUS),
+            Self::Ffdhe3072 => encode(&Ffdhe3072Modulus::MODULUS),
+            Self::Ffdhe4096 => encode(&Ffdhe4096Modulus::MODULUS),
+        }
+    }
+
+    pub(crate) fn generator(self) -> &'static [u8] {
+        GENERATOR
+    }
+
+    /// The length of private exponents (RFC7919 section 5.2).
+    fn exponent_bits(self) -> usize {
+        match self {
+            Self::Ffdhe2048 => 225,
+            Self::Ffdhe3072 => 275,
+            Self::Ffdhe4096 => 325,
+        }
+    }
+
+    /// Generate a private exponent and its public value.
+    pub(crate) fn generate(self) -> Option<(PrivateExponent, Vec<u8>)> {
+        let mut random = [0u8; U384::BYTES];
+        SystemRandom::new()
+            .fill(&mut random)
+            .ok()?;
+
+        // Reduce to exactly `exponent_bits` bits.
+        let bits = self.exponent_bits();
+        let mask = U384::ONE
+            .shl_vartime(bits)
+            .wrapping_sub(&U384::ONE);
+        let top = U384::ONE.shl_vartime(bits - 1);
+        let ours = PrivateExponent(
+            U384::from_be_slice(&random)
+                .bitand(&mask)
+                .bitor(&top),
+        );
+
+        let pubkey = self.pow(GENERATOR, &ours)?;
+        Some((ours, pubkey))
+    }
+
+    /// Compute the shared secret given the peer's public value `peer`,
+    /// padded to the length of p.
+    ///
+    /// This fails if `peer` is longer than p, or isn't in the range
+    /// 1 < peer < p - 1 (RFC7919 section 5.1).
+    pub(crate) fn agree(self, ours: &PrivateExponent, peer: &[u8]) -> Option<Vec<u8>> {
+        self.pow(peer, ours)
+    }
+
+    fn pow(self, base: &[u8], exponent: &PrivateExponent) -> Option<Vec<u8>> {
+        let bits = self.exponent_bits();
+        match self {
+            Self::Ffdhe2048 => pow::<Ffdhe2048Modulus, { U2048::LIMBS }>(base, &exponent.0, bits),
+            Self::Ffdhe3072 => pow::<Ffdhe3072Modulus, { U3072::LIMBS }>(base, &exponent.0, bits),
+            Self::Ffdhe4096 => pow::<Ffdhe4096Modulus, { U4096::LIMBS }>(base, &exponent.0, bits),
+        }
+    }
+}
+
+/// `base ^ exponent mod p`, or `None` if `base` isn't in the range
+/// 1 < base < p - 1.
+fn pow<M, const LIMBS: usize>(base: &[u8], exponent: &U384, exponent_bits: usize) -> Option<Vec<u8>>
+where
+    M: ResidueParams<LIMBS>,
+{
+    let base = decode::<LIMBS>(base)?;
+    if base <= Uint::ONE || base >= M::MODULUS.wrapping_sub(&Uint::ONE) {
+        return None;
+    }
+
+    let result = Residue::<M, LIMBS>::new(&base)
+        .pow_bounded_exp(exponent, exponent_bits)
+        .retrieve();
+    Some(encode(&result))
+}
+
+/// Decode a big-endian value, which may be shorter than `LIMBS` but
+/// not longer.
+fn decode<const LIMBS: usize>(bytes: &[u8]) -> Option<Uint<LIMBS>> {
+    let padding = Uint::<LIMBS>::BYTES.checked_sub(bytes.len())?;
+    let mut padded = vec![0u8; Uint::<LIMBS>::BYTES];
+    padded[padding..].copy_from_slice(bytes);
+    Some(Uint::from_be_slice(&padded))
+}
+
+fn encode<const LIMBS: usize>(x: &Uint<LIMBS>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(Uint::<LIMBS>::BYTES);
+    for word in x.as_words().iter().rev() {
+        out.extend_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let zeros = bytes
+        .iter()
+        .take_while(|b| **b == 0)
+        .count();
+    &bytes[zeros..]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_agreement() {
+        for group in FfdheGroup::ALL.iter().copied() {
+            let (a, a_pub) = group.generate().unwrap();
+            let (b, b_pub) = group.generate().unwrap();
+            assert_eq!(a_pub.len(), group.prime().len());
+
+            let secret = group.agree(&a, &b_pub).unwrap();
+            assert_eq!(secret.len(), group.prime().len());
+            assert_eq!(secret, group.agree(&b, &a_pub).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_public_values() {
+        let group = FfdheGroup::Ffdhe2048;
+        let (ours, _) = group.generate().unwrap();
+        let p = group.prime();
+        let mut p_minus_1 = p.clone();
+        *p_minus_1.last_mut().unwrap() -= 1;
+        let mut too_long = vec![0u8];
+        too_long.extend_from_slice(&[2u8; 256]);
+
+        for peer in &[
+            vec![],
+            vec![0],
+            vec![1],
+            p_minus_1,
+            p,
+            vec![0xff; 256],
+            too_long,
+        ] {
+            assert!(group.agree(&ours, peer).is_none());
+        }
+
+        // Leading zeros may be omitted.
+        assert!(group.agree(&ours, &[2]).is_some());
+    }
+
+    #[test]
+    fn test_montgomery_parameters() {
+        fn check<M: ResidueParams<LIMBS>, const LIMBS: usize>() {
+            // these depend on R2 and R3 respectively
+            let two = Uint::<LIMBS>::from_u8(2);
+            assert_eq!(Residue::<M, LIMBS>::new(&two).retrieve(), two);
+            let (half, ok) = Residue::<M, LIMBS>::new(&two).invert();
+            assert!(bool::from(ok));
+            assert_eq!((half + half).retrieve(), Uint::ONE);
+        }
+
+        check::<Ffdhe2048Modulus, { U2048::LIMBS }>();
+        check::<Ffdhe3072Modulus, { U3072::LIMBS }>();
+        check::<Ffdhe4096Modulus, { U4096::LIMBS }>();
+    }
+
+    #[test]
+    fn test_from_params() {
+        for group in FfdheGroup::ALL.iter().copied() {
+            assert_eq!(
+                FfdheGroup::from_params(&group.prime(), group.generator()),
+                Some(group)
+            );
+        }
+
+        let p = FfdheGroup::Ffdhe2048.prime();
+        assert_eq!(
+            FfdheGroup::from_params(&p, &[0, 2]),
+            Some(FfdheGroup::Ffdhe2048)
+        );
+        assert_eq!(FfdheGroup::from_params(&p, &[5]), None);
+        assert_eq!(FfdheGroup::from_params(&p[1..], &[2]), None);
+    }
+}
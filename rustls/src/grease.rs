@@ -0,0 +1,133 @@
+//! GREASE ([RFC8701](https://tools.ietf.org/html/rfc8701)): reserved values
+//! which clients sprinkle through their ClientHello, so that peers which
+//! fail to ignore unknown values are found and fixed.
+use crate::crypto::CryptoProvider;
+use crate::msgs::base::Payload;
+use crate::msgs::enums::{CipherSuite, ExtensionType, NamedGroup};
+use crate::msgs::enums::{PSKKeyExchangeMode, ProtocolVersion, SignatureScheme};
+use crate::msgs::handshake::{ClientExtension, UnknownExtension};
+use crate::rand;
+
+/// Is `value` one of the reserved GREASE values for cipher suites,
+/// extensions, named groups, signature schemes, versions or ALPN
+/// protocols?
+pub(crate) fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// Is `protocol` one of the reserved GREASE ALPN protocol names?
+pub(crate) fn is_grease_protocol(protocol: &[u8]) -> bool {
+    match *protocol {
+        [a, b] => is_grease(u16::from_be_bytes([a, b])),
+        _ => false,
+    }
+}
+
+/// The GREASE values a client uses for one connection.
+///
+/// These are chosen at random, but stay the same in a second
+/// ClientHello sent after a HelloRetryRequest.
+#[derive(Clone, Copy)]
+pub(crate) struct Grease {
+    seed: [u8; 8],
+}
+
+impl Grease {
+    pub(crate) fn new(provider: &dyn CryptoProvider) -> Result<Self, rand::GetRandomFailed> {
+        let mut seed = [0u8; 8];
+        rand::fill_random(provider, &mut seed)?;
+        Ok(Self { seed })
+    }
+
+    fn value(&self, index: usize) -> u16 {
+        let byte = (self.seed[index] & 0xf0) | 0x0a;
+        u16::from_be_bytes([byte, byte])
+    }
+
+    pub(crate) fn cipher_suite(&self) -> CipherSuite {
+        CipherSuite::from(self.value(0))
+    }
+
+    /// The first of two GREASE extensions, which is empty.
+    pub(crate) fn first_extension(&self) -> ClientExtension {
+        ClientExtension::Unknown(UnknownExtension {
+            typ: ExtensionType::from(self.value(1)),
+            payload: Payload::empty(),
+        })
+    }
+
+    /// The second of two GREASE extensions.  This always has a different
+    /// type to the first, and is not empty.
+    pub(crate) fn second_extension(&self) -> ClientExtension {
+        let mut value = self.value(2);
+        if value == self.value(1) {
+            value ^= 0x1010;
+        }
+        ClientExtension::Unknown(UnknownExtension {
+            typ: ExtensionType::from(value),
+            payload: Payload::new(vec![0]),
+        })
+    }
+
+    pub(crate) fn named_group(&self) -> NamedGroup {
+        NamedGroup::from(self.value(3))
+    }
+
+    pub(crate) fn signature_scheme(&self) -> SignatureScheme {
+        SignatureScheme::from(self.value(4))
+    }
+
+    pub(crate) fn version(&self) -> ProtocolVersion {
+        ProtocolVersion::from(self.value(5))
+    }
+
+    pub(crate) fn protocol(&self) -> Vec<u8> {
+        self.value(6).to_be_bytes().to_vec()
+    }
+
+    /// PSK key exchange modes use their own GREASE values:
+    /// `0x0b + 0x1f * n`.
+    pub(crate) fn psk_mode(&self) -> PSKKeyExchangeMode {
+        PSKKeyExchangeMode::from(0x0b + 0x1f * (self.seed[7] % 8))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::ring::RING;
+
+    #[test]
+    fn recognises_grease() {
+        let all: Vec<u16> = (0..16)
+            .map(|n| 0x0a0a + 0x1010 * n)
+            .collect();
+        assert_eq!(all.last(), Some(&0xfafa));
+        for value in 0..=0xffff {
+            assert_eq!(is_grease(value), all.contains(&value));
+        }
+
+        assert!(is_grease_protocol(&[0x3a, 0x3a]));
+        assert!(!is_grease_protocol(&[0x3a, 0x4a]));
+        assert!(!is_grease_protocol(&[0x3a, 0x3a, 0x3a]));
+        assert!(!is_grease_protocol(b"h2"));
+    }
+
+    #[test]
+    fn chooses_grease() {
+        for _ in 0..100 {
+            let grease = Grease::new(&RING).unwrap();
+            assert!(is_grease(grease.cipher_suite().get_u16()));
+            let first = grease.first_extension().get_type();
+            let second = grease.second_extension().get_type();
+            assert!(is_grease(first.get_u16()));
+            assert!(is_grease(second.get_u16()));
+            assert_ne!(first, second);
+            assert!(is_grease(grease.named_group().get_u16()));
+            assert!(is_grease(grease.signature_scheme().get_u16()));
+            assert!(is_grease(grease.version().get_u16()));
+            assert!(is_grease_protocol(&grease.protocol()));
+            assert_eq!((grease.psk_mode().get_u8() - 0x0b) % 0x1f, 0);
+        }
+    }
+}
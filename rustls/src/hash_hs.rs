@@ -1,7 +1,7 @@
+use crate::crypto::{CryptoProvider, Digest, HashAlgorithm, HashContext};
 use crate::msgs::codec::Codec;
 use crate::msgs::handshake::HandshakeMessagePayload;
 use crate::msgs::message::{Message, MessagePayload};
-use ring::digest;
 use std::mem;
 
 /// Early stage buffering of handshake payloads.
@@ -45,20 +45,26 @@ impl HandshakeHashBuffer {
     /// Get the hash value if we were to hash `extra` too.
     pub(crate) fn get_hash_given(
         &self,
-        hash: &'static digest::Algorithm,
+        provider: &dyn CryptoProvider,
+        hash: HashAlgorithm,
         extra: &[u8],
-    ) -> digest::Digest {
-        let mut ctx = digest::Context::new(hash);
+    ) -> Digest {
+        let mut ctx = provider.start_hash(hash);
         ctx.update(&self.buffer);
         ctx.update(extra);
         ctx.finish()
     }
 
     /// We now know what hash function the verify_data will use.
-    pub(crate) fn start_hash(self, alg: &'static digest::Algorithm) -> HandshakeHash {
-        let mut ctx = digest::Context::new(alg);
+    pub(crate) fn start_hash(
+        self,
+        provider: &'static dyn CryptoProvider,
+        alg: HashAlgorithm,
+    ) -> HandshakeHash {
+        let mut ctx = provider.start_hash(alg);
         ctx.update(&self.buffer);
         HandshakeHash {
+            provider,
             ctx,
             client_auth: match self.client_auth_enabled {
                 true => Some(self.buffer),
@@ -76,8 +82,10 @@ impl HandshakeHashBuffer {
 /// For client auth, we also need to buffer all the messages.
 /// This is disabled in cases where client auth is not possible.
 pub(crate) struct HandshakeHash {
+    provider: &'static dyn CryptoProvider,
+
     /// None before we know what hash function we're using
-    ctx: digest::Context,
+    ctx: Box<dyn HashContext>,
 
     /// buffer for client-auth.
     client_auth: Option<Vec<u8>>,
@@ -112,8 +120,8 @@ impl HandshakeHash {
 
     /// Get the hash value if we were to hash `extra` too,
     /// using hash function `hash`.
-    pub(crate) fn get_hash_given(&self, extra: &[u8]) -> digest::Digest {
-        let mut ctx = self.ctx.clone();
+    pub(crate) fn get_hash_given(&self, extra: &[u8]) -> Digest {
+        let mut ctx = self.ctx.fork();
         ctx.update(extra);
         ctx.finish()
     }
@@ -135,7 +143,11 @@ impl HandshakeHash {
     pub(crate) fn rollup_for_hrr(&mut self) {
         let ctx = &mut self.ctx;
 
-        let old_ctx = mem::replace(ctx, digest::Context::new(ctx.algorithm()));
+        let old_ctx = mem::replace(
+            ctx,
+            self.provider
+                .start_hash(ctx.algorithm()),
+        );
         let old_hash = old_ctx.finish();
         let old_handshake_hash_msg =
             HandshakeMessagePayload::build_handshake_hash(old_hash.as_ref());
@@ -144,8 +156,8 @@ impl HandshakeHash {
     }
 
     /// Get the current hash value.
-    pub(crate) fn get_current_hash(&self) -> digest::Digest {
-        self.ctx.clone().finish()
+    pub(crate) fn get_current_hash(&self) -> Digest {
+        self.ctx.fork().finish()
     }
 
     /// Takes this object's buffer containing all handshake messages
@@ -155,8 +167,13 @@ impl HandshakeHash {
         self.client_auth.take()
     }
 
+    /// The crypto provider doing the hashing.
+    pub(crate) fn provider(&self) -> &'static dyn CryptoProvider {
+        self.provider
+    }
+
     /// The digest algorithm
-    pub(crate) fn algorithm(&self) -> &'static digest::Algorithm {
+    pub(crate) fn algorithm(&self) -> HashAlgorithm {
         self.ctx.algorithm()
     }
 }
@@ -164,14 +181,15 @@ impl HandshakeHash {
 #[cfg(test)]
 mod test {
     use super::HandshakeHashBuffer;
-    use ring::digest;
+    use crate::crypto::ring::RING;
+    use crate::crypto::HashAlgorithm;
 
     #[test]
     fn hashes_correctly() {
         let mut hhb = HandshakeHashBuffer::new();
         hhb.update_raw(b"hello");
         assert_eq!(hhb.buffer.len(), 5);
-        let mut hh = hhb.start_hash(&digest::SHA256);
+        let mut hh = hhb.start_hash(&RING, HashAlgorithm::SHA256);
         assert!(hh.client_auth.is_none());
         hh.update_raw(b"world");
         let h = hh.get_current_hash();
@@ -188,7 +206,7 @@ mod test {
         hhb.set_client_auth_enabled();
         hhb.update_raw(b"hello");
         assert_eq!(hhb.buffer.len(), 5);
-        let mut hh = hhb.start_hash(&digest::SHA256);
+        let mut hh = hhb.start_hash(&RING, HashAlgorithm::SHA256);
         assert_eq!(
             hh.client_auth
                 .as_ref()
@@ -218,7 +236,7 @@ mod test {
         hhb.set_client_auth_enabled();
         hhb.update_raw(b"hello");
         assert_eq!(hhb.buffer.len(), 5);
-        let mut hh = hhb.start_hash(&digest::SHA256);
+        let mut hh = hhb.start_hash(&RING, HashAlgorithm::SHA256);
         assert_eq!(
             hh.client_auth
                 .as_ref()
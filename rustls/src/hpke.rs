@@ -0,0 +1,344 @@
+//! A minimal implementation of the base mode of Hybrid Public Key
+//! Encryption (RFC9180), sufficient for Encrypted Client Hello.
+//!
+//! The only KEM supported is DHKEM(X25519, HKDF-SHA256).  X25519, the
+//! KDF and the AEAD all come from a `CryptoProvider`.
+
+use crate::crypto::{AeadKey, CryptoProvider, HashAlgorithm, StaticX25519Key, NONCE_LEN};
+use crate::kx::KeyExchange;
+use crate::msgs::enums::{HpkeAead, HpkeKdf, HpkeKem, NamedGroup};
+use crate::msgs::handshake::HpkeSymmetricCipherSuite;
+use crate::suites::BulkAlgorithm;
+
+use std::convert::TryFrom;
+
+/// The length of an X25519 public key, and therefore of `enc`.
+pub(crate) const X25519_LEN: usize = 32;
+
+/// Can we use `suite`?
+pub(crate) fn is_supported(suite: &HpkeSymmetricCipherSuite) -> bool {
+    kdf_algorithm(suite.kdf_id).is_some() && aead_algorithm(suite.aead_id).is_some()
+}
+
+fn kdf_algorithm(kdf: HpkeKdf) -> Option<HashAlgorithm> {
+    match kdf {
+        HpkeKdf::HKDF_SHA256 => Some(HashAlgorithm::SHA256),
+        HpkeKdf::HKDF_SHA384 => Some(HashAlgorithm::SHA384),
+        HpkeKdf::HKDF_SHA512 => Some(HashAlgorithm::SHA512),
+        _ => None,
+    }
+}
+
+fn aead_algorithm(aead: HpkeAead) -> Option<BulkAlgorithm> {
+    match aead {
+        HpkeAead::AES_128_GCM => Some(BulkAlgorithm::Aes128Gcm),
+        HpkeAead::AES_256_GCM => Some(BulkAlgorithm::Aes256Gcm),
+        HpkeAead::CHACHA20_POLY_1305 => Some(BulkAlgorithm::Chacha20Poly1305),
+        _ => None,
+    }
+}
+
+/// A KDF, bound to a particular `suite_id`.
+struct LabeledKdf<'a> {
+    provider: &'a dyn CryptoProvider,
+    alg: HashAlgorithm,
+    suite_id: &'a [u8],
+}
+
+impl<'a> LabeledKdf<'a> {
+    /// `LabeledExtract()` from RFC9180.
+    fn extract(&self, salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+        self.provider
+            .hmac_sign(self.alg, salt, &[b"HPKE-v1", self.suite_id, label, ikm])
+            .as_ref()
+            .to_vec()
+    }
+
+    /// `LabeledExpand()` from RFC9180.  This fails if `len` is too long
+    /// for the KDF.
+    fn expand(&self, prk: &[u8], label: &[u8], info: &[u8], len: usize) -> Option<Vec<u8>> {
+        let len_bytes = u16::try_from(len).ok()?.to_be_bytes();
+        let info = [&len_bytes[..], b"HPKE-v1", self.suite_id, label, info];
+        let mut out = vec![0u8; len];
+        self.provider
+            .hkdf_expand(self.alg, prk, &info, &mut out)
+            .ok()?;
+        Some(out)
+    }
+}
+
+/// DHKEM(X25519, HKDF-SHA256)'s `ExtractAndExpand()`.
+fn kem_shared_secret(
+    provider: &dyn CryptoProvider,
+    dh: &[u8],
+    enc: &[u8],
+    pk_r: &[u8],
+) -> Option<Vec<u8>> {
+    let suite_id = kem_suite_id(HpkeKem::DHKEM_X25519_HKDF_SHA256);
+    let kdf = LabeledKdf {
+        provider,
+        alg: HashAlgorithm::SHA256,
+        suite_id: &suite_id,
+    };
+    let eae_prk = kdf.extract(b"", b"eae_prk", dh);
+    let kem_context = [enc, pk_r].concat();
+    kdf.expand(&eae_prk, b"shared_secret", &kem_context, 32)
+}
+
+fn kem_suite_id(kem: HpkeKem) -> Vec<u8> {
+    let mut suite_id = b"KEM".to_vec();
+    suite_id.extend_from_slice(&kem.get_u16().to_be_bytes());
+    suite_id
+}
+
+/// An HPKE encryption context.  This is used in one direction only:
+/// either for `seal` (if made by `setup_sender`) or for `open` (if
+/// made by `setup_receiver`).
+pub(crate) struct HpkeContext {
+    key: Box<dyn AeadKey>,
+    base_nonce: [u8; NONCE_LEN],
+    seq: u64,
+}
+
+impl HpkeContext {
+    /// `SetupBaseS()`: encapsulate a fresh secret to `pk_r`.  Returns
+    /// `enc` (to send to the receiver) and the sending context.
+    pub(crate) fn setup_sender(
+        provider: &dyn CryptoProvider,
+        suite: &HpkeSymmetricCipherSuite,
+        pk_r: &[u8],
+        info: &[u8],
+    ) -> Option<(Vec<u8>, Self)> {
+        let x25519 = KeyExchange::choose(NamedGroup::X25519, provider.kx_groups())?;
+        let sk_e = provider.start_key_exchange(x25519)?;
+        let enc = sk_e.pub_key().to_vec();
+        // A small-order `pk_r` gives an all-zero result (RFC7748 section 6.1).
+        let dh = sk_e
+            .complete(pk_r)
+            .filter(|dh| dh.iter().any(|b| *b != 0))?;
+        let shared_secret = kem_shared_secret(provider, &dh, &enc, pk_r)?;
+        let ctx = Self::key_schedule(provider, suite, &shared_secret, info)?;
+        Some((enc, ctx))
+    }
+
+    /// `SetupBaseR()`: decapsulate `enc` using `sk_r`, and return the
+    /// receiving context.
+    pub(crate) fn setup_receiver(
+        provider: &dyn CryptoProvider,
+        suite: &HpkeSymmetricCipherSuite,
+        enc: &[u8],
+        sk_r: &dyn StaticX25519Key,
+        info: &[u8],
+    ) -> Option<Self> {
+        let dh = sk_r.agree(enc)?;
+        let shared_secret = kem_shared_secret(provider, &dh, enc, sk_r.public_key())?;
+        Self::key_schedule(provider, suite, &shared_secret, info)
+    }
+
+    /// `KeySchedule()` for mode_base.
+    fn key_schedule(
+        provider: &dyn CryptoProvider,
+        suite: &HpkeSymmetricCipherSuite,
+        shared_secret: &[u8],
+        info: &[u8],
+    ) -> Option<Self> {
+        let alg = kdf_algorithm(suite.kdf_id)?;
+        let aead_alg = aead_algorithm(suite.aead_id)?;
+        if !provider.aead_supported(&aead_alg) {
+            return None;
+        }
+
+        let mut suite_id = b"HPKE".to_vec();
+        suite_id.extend_from_slice(
+            &HpkeKem::DHKEM_X25519_HKDF_SHA256
+                .get_u16()
+                .to_be_bytes(),
+        );
+        suite_id.extend_from_slice(&suite.kdf_id.get_u16().to_be_bytes());
+        suite_id.extend_from_slice(&suite.aead_id.get_u16().to_be_bytes());
+        let kdf = LabeledKdf {
+            provider,
+            alg,
+            suite_id: &suite_id,
+        };
+
+        let mode_base = 0u8;
+        let psk_id_hash = kdf.extract(b"", b"psk_id_hash", b"");
+        let info_hash = kdf.extract(b"", b"info_hash", info);
+        let mut context = vec![mode_base];
+        context.extend_from_slice(&psk_id_hash);
+        context.extend_from_slice(&info_hash);
+
+        let secret = kdf.extract(shared_secret, b"secret", b"");
+        let key = kdf.expand(&secret, b"key", &context, aead_alg.key_len())?;
+        let nonce = kdf.expand(&secret, b"base_nonce", &context, NONCE_LEN)?;
+
+        let mut base_nonce = [0u8; NONCE_LEN];
+        base_nonce.copy_from_slice(&nonce);
+        Some(Self {
+            key: provider
+                .aead_key(&aead_alg, &key)
+                .ok()?,
+            base_nonce,
+            seq: 0,
+        })
+    }
+
+    fn next_nonce(&mut self) -> Option<[u8; NONCE_LEN]> {
+        let mut nonce = self.base_nonce;
+        for (n, s) in nonce[NONCE_LEN - 8..]
+            .iter_mut()
+            .zip(self.seq.to_be_bytes().iter())
+        {
+            *n ^= *s;
+        }
+        self.seq = self.seq.checked_add(1)?;
+        Some(nonce)
+    }
+
+    /// The length of the ciphertext for a `plaintext_len`-byte message.
+    pub(crate) fn sealed_len(&self, plaintext_len: usize) -> usize {
+        plaintext_len + self.key.tag_len()
+    }
+
+    /// `ContextS.Seal()`.
+    pub(crate) fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let mut buf = plaintext.to_vec();
+        self.key
+            .seal_in_place(nonce, aad, &mut buf, 0)
+            .ok()?;
+        Some(buf)
+    }
+
+    /// `ContextR.Open()`.
+    pub(crate) fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let mut buf = ciphertext.to_vec();
+        let len = self
+            .key
+            .open_in_place(nonce, aad, &mut buf, 0)
+            .ok()?;
+        buf.truncate(len);
+        Some(buf)
+    }
+}
+
+// Receiving needs a static X25519 key, which *ring* lacks.
+#[cfg(all(test, feature = "static-x25519"))]
+mod test {
+    use super::*;
+    use crate::crypto::ring::RING;
+
+    use std::sync::Arc;
+
+    fn suite(kdf_id: HpkeKdf, aead_id: HpkeAead) -> HpkeSymmetricCipherSuite {
+        HpkeSymmetricCipherSuite { kdf_id, aead_id }
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn test_key() -> Arc<dyn StaticX25519Key> {
+        RING.load_static_x25519_key(&(1..=32).collect::<Vec<u8>>())
+            .unwrap()
+    }
+
+    #[test]
+    fn public_key() {
+        assert_eq!(
+            test_key().public_key(),
+            unhex("07a37cbc142093c8b755dc1b10e86cb426374ad16aa853ed0bdfc0b2b86d1c7c")
+        );
+    }
+
+    // These vectors were produced by an independent HPKE implementation,
+    // sealing "hello ech" to `test_key()` with info "tls ech" and no aad.
+    fn check_vector(suite: HpkeSymmetricCipherSuite, vector: &str) {
+        let vector = unhex(vector);
+        let (enc, ciphertext) = vector.split_at(X25519_LEN);
+        let sk_r = test_key();
+
+        let mut ctx =
+            HpkeContext::setup_receiver(&RING, &suite, enc, sk_r.as_ref(), b"tls ech").unwrap();
+        assert_eq!(ctx.open(b"", ciphertext).unwrap(), b"hello ech");
+
+        let mut ctx =
+            HpkeContext::setup_receiver(&RING, &suite, enc, sk_r.as_ref(), b"tls").unwrap();
+        assert!(ctx.open(b"", ciphertext).is_none());
+    }
+
+    #[test]
+    fn open_sha256_aes128gcm() {
+        check_vector(
+            suite(HpkeKdf::HKDF_SHA256, HpkeAead::AES_128_GCM),
+            "bfd4ec4e572a5bccd4933f0b61d7288683450f16b65c87ca6bae114775928415\
+             c254227ef097f5a120c112874453d669711231656fb14261e2",
+        );
+    }
+
+    #[test]
+    fn open_sha384_chacha20poly1305() {
+        check_vector(
+            suite(HpkeKdf::HKDF_SHA384, HpkeAead::CHACHA20_POLY_1305),
+            "8bc5d9ef99ac2c3bcc109f26e3d5b9ea04dc363f1324beaea11993029176367c\
+             2832c79d9a2fc7a94ca93a01135bc86341d187e9116dd97beb",
+        );
+    }
+
+    #[test]
+    fn open_sha512_aes256gcm() {
+        check_vector(
+            suite(HpkeKdf::HKDF_SHA512, HpkeAead::AES_256_GCM),
+            "343e7080e552bd6d4354874efedde9de2904da4301849ff3af0639e71223d158\
+             8ea2cc2a3573a8fdcf32a5ecead18e5a231bbf4d56f16ba93c",
+        );
+    }
+
+    #[test]
+    fn seal_then_open() {
+        let suite = suite(HpkeKdf::HKDF_SHA256, HpkeAead::AES_128_GCM);
+        let sk_r = test_key();
+        let (enc, mut sender) =
+            HpkeContext::setup_sender(&RING, &suite, sk_r.public_key(), b"info").unwrap();
+        let mut receiver =
+            HpkeContext::setup_receiver(&RING, &suite, &enc, sk_r.as_ref(), b"info").unwrap();
+
+        for msg in &[&b"first"[..], b"second", b""] {
+            let ct = sender.seal(b"aad", msg).unwrap();
+            assert_eq!(ct.len(), sender.sealed_len(msg.len()));
+            assert_eq!(&receiver.open(b"aad", &ct).unwrap(), msg);
+        }
+
+        let ct = sender.seal(b"aad", b"third").unwrap();
+        assert!(receiver.open(b"other", &ct).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_inputs() {
+        let sk_r = test_key();
+        let good = suite(HpkeKdf::HKDF_SHA256, HpkeAead::AES_128_GCM);
+        assert!(is_supported(&good));
+        assert!(!is_supported(&suite(
+            HpkeKdf::HKDF_SHA256,
+            HpkeAead::EXPORT_ONLY
+        )));
+        assert!(!is_supported(&suite(
+            HpkeKdf::Unknown(4),
+            HpkeAead::AES_128_GCM
+        )));
+
+        let sk_r = sk_r.as_ref();
+        assert!(HpkeContext::setup_receiver(&RING, &good, &[0u8; 31], sk_r, b"").is_none());
+        // the all-zero point gives a non-contributory result
+        assert!(HpkeContext::setup_receiver(&RING, &good, &[0u8; 32], sk_r, b"").is_none());
+        assert!(HpkeContext::setup_sender(&RING, &good, &[0u8; 32], b"").is_none());
+        assert!(RING
+            .load_static_x25519_key(&[1u8; 31])
+            .is_err());
+    }
+}
@@ -1,21 +1,21 @@
-use crate::cipher::{Iv, IvLen};
+use crate::cipher::Iv;
+use crate::crypto::{AeadKey, CryptoProvider, Digest, HashAlgorithm, NONCE_LEN};
 use crate::error::Error;
-use crate::msgs::base::PayloadU8;
+use crate::suites::{
+    ConnectionTrafficSecrets, PartiallyExtractedSecrets, Tls13AeadAlgorithm, Tls13CipherSuite,
+};
 use crate::KeyLog;
 
-/// Key schedule maintenance for TLS1.3
-use ring::{
-    aead,
-    digest::{self, Digest},
-    hkdf::{self, KeyType as _},
-    hmac,
-};
+use std::fmt;
 
 /// The kinds of secret we can extract from `KeySchedule`.
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SecretKind {
     ResumptionPskBinderKey,
+    ExternalPskBinderKey,
+    ImportedPskBinderKey,
     ClientEarlyTrafficSecret,
+    EarlyExporterMasterSecret,
     ClientHandshakeTrafficSecret,
     ServerHandshakeTrafficSecret,
     ClientApplicationTrafficSecret,
@@ -29,7 +29,10 @@ impl SecretKind {
     fn to_bytes(self) -> &'static [u8] {
         match self {
             SecretKind::ResumptionPskBinderKey => b"res binder",
+            SecretKind::ExternalPskBinderKey => b"ext binder",
+            SecretKind::ImportedPskBinderKey => b"imp binder",
             SecretKind::ClientEarlyTrafficSecret => b"c e traffic",
+            SecretKind::EarlyExporterMasterSecret => b"e exp master",
             SecretKind::ClientHandshakeTrafficSecret => b"c hs traffic",
             SecretKind::ServerHandshakeTrafficSecret => b"s hs traffic",
             SecretKind::ClientApplicationTrafficSecret => b"c ap traffic",
@@ -44,6 +47,7 @@ impl SecretKind {
         use self::SecretKind::*;
         Some(match self {
             ClientEarlyTrafficSecret => "CLIENT_EARLY_TRAFFIC_SECRET",
+            EarlyExporterMasterSecret => "EARLY_EXPORTER_SECRET",
             ClientHandshakeTrafficSecret => "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
             ServerHandshakeTrafficSecret => "SERVER_HANDSHAKE_TRAFFIC_SECRET",
             ClientApplicationTrafficSecret => "CLIENT_TRAFFIC_SECRET_0",
@@ -56,12 +60,64 @@ impl SecretKind {
     }
 }
 
+/// Where a PSK came from.  This decides the label used for its binder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PskKind {
+    /// A ticket from an earlier connection.
+    Resumption,
+    /// An external PSK, used as-is.
+    External,
+    /// An external PSK, imported as described in RFC9258.
+    Imported,
+}
+
+/// A TLS1.3 secret: an HKDF pseudorandom key, with the crypto provider
+/// and hash function to expand it with.
+#[derive(Clone)]
+pub(crate) struct Prk {
+    provider: &'static dyn CryptoProvider,
+    algorithm: HashAlgorithm,
+    secret: Digest,
+}
+
+impl Prk {
+    /// HKDF-Extract from `ikm`, with `salt`.
+    pub(crate) fn extract(
+        provider: &'static dyn CryptoProvider,
+        algorithm: HashAlgorithm,
+        salt: &[u8],
+        ikm: &[u8],
+    ) -> Self {
+        Self {
+            provider,
+            algorithm,
+            secret: provider.hkdf_extract(algorithm, salt, ikm),
+        }
+    }
+
+    pub(crate) fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// The secret itself.
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.secret.as_ref()
+    }
+}
+
+impl fmt::Debug for Prk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Prk")
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
 /// This is the TLS1.3 key schedule.  It stores the current secret and
 /// the type of hash.  This isn't used directly; but only through the
 /// typestates.
 struct KeySchedule {
-    current: hkdf::Prk,
-    algorithm: ring::hkdf::Algorithm,
+    current: Prk,
 }
 
 // We express the state of a contained KeySchedule using these
@@ -76,9 +132,13 @@ pub(crate) struct KeyScheduleEarly {
 }
 
 impl KeyScheduleEarly {
-    pub(crate) fn new(algorithm: hkdf::Algorithm, secret: &[u8]) -> Self {
+    pub(crate) fn new(
+        provider: &'static dyn CryptoProvider,
+        algorithm: HashAlgorithm,
+        secret: &[u8],
+    ) -> Self {
         Self {
-            ks: KeySchedule::new(algorithm, secret),
+            ks: KeySchedule::new(provider, algorithm, secret),
         }
     }
 
@@ -87,7 +147,7 @@ impl KeyScheduleEarly {
         hs_hash: &Digest,
         key_log: &dyn KeyLog,
         client_random: &[u8; 32],
-    ) -> hkdf::Prk {
+    ) -> Prk {
         self.ks.derive_logged_secret(
             SecretKind::ClientEarlyTrafficSecret,
             hs_hash.as_ref(),
@@ -96,21 +156,49 @@ impl KeyScheduleEarly {
         )
     }
 
-    pub(crate) fn resumption_psk_binder_key_and_sign_verify_data(
+    pub(crate) fn early_exporter(
         &self,
         hs_hash: &Digest,
-    ) -> hmac::Tag {
-        let resumption_psk_binder_key = self
+        key_log: &dyn KeyLog,
+        client_random: &[u8; 32],
+    ) -> EarlyExporter {
+        EarlyExporter {
+            secret: self.ks.derive_logged_secret(
+                SecretKind::EarlyExporterMasterSecret,
+                hs_hash.as_ref(),
+                key_log,
+                client_random,
+            ),
+        }
+    }
+
+    pub(crate) fn psk_binder_key_and_sign_verify_data(
+        &self,
+        kind: PskKind,
+        hs_hash: &Digest,
+    ) -> Digest {
+        let secret_kind = match kind {
+            PskKind::Resumption => SecretKind::ResumptionPskBinderKey,
+            PskKind::External => SecretKind::ExternalPskBinderKey,
+            PskKind::Imported => SecretKind::ImportedPskBinderKey,
+        };
+        let psk_binder_key = self
             .ks
-            .derive_for_empty_hash(SecretKind::ResumptionPskBinderKey);
+            .derive_secret_for_empty_hash(secret_kind);
         self.ks
-            .sign_verify_data(&resumption_psk_binder_key, hs_hash)
+            .sign_verify_data(&psk_binder_key, hs_hash)
     }
 
     pub(crate) fn into_handshake(mut self, secret: &[u8]) -> KeyScheduleHandshakeStart {
         self.ks.input_secret(secret);
         KeyScheduleHandshakeStart { ks: self.ks }
     }
+
+    /// Continue without a key exchange, as in the `psk_ke` mode.
+    pub(crate) fn into_handshake_for_psk_ke(mut self) -> KeyScheduleHandshakeStart {
+        self.ks.input_empty();
+        KeyScheduleHandshakeStart { ks: self.ks }
+    }
 }
 
 /// KeySchedule for skipping early data stage.  No secrets can be extracted
@@ -120,9 +208,9 @@ pub(crate) struct KeyScheduleNonSecret {
 }
 
 impl KeyScheduleNonSecret {
-    pub(crate) fn new(algorithm: hkdf::Algorithm) -> Self {
+    pub(crate) fn new(provider: &'static dyn CryptoProvider, algorithm: HashAlgorithm) -> Self {
         Self {
-            ks: KeySchedule::new_with_empty_secret(algorithm),
+            ks: KeySchedule::new_with_empty_secret(provider, algorithm),
         }
     }
 
@@ -143,7 +231,7 @@ impl KeyScheduleHandshakeStart {
         hs_hash: Digest,
         key_log: &dyn KeyLog,
         client_random: &[u8; 32],
-    ) -> (KeyScheduleHandshake, hkdf::Prk, hkdf::Prk) {
+    ) -> (KeyScheduleHandshake, Prk, Prk) {
         // Use an empty handshake hash for the initial handshake.
         let client_secret = self.ks.derive_logged_secret(
             SecretKind::ClientHandshakeTrafficSecret,
@@ -171,17 +259,17 @@ impl KeyScheduleHandshakeStart {
 
 pub(crate) struct KeyScheduleHandshake {
     ks: KeySchedule,
-    client_handshake_traffic_secret: hkdf::Prk,
-    server_handshake_traffic_secret: hkdf::Prk,
+    client_handshake_traffic_secret: Prk,
+    server_handshake_traffic_secret: Prk,
 }
 
 impl KeyScheduleHandshake {
-    pub(crate) fn sign_server_finish(&self, hs_hash: &Digest) -> hmac::Tag {
+    pub(crate) fn sign_server_finish(&self, hs_hash: &Digest) -> Digest {
         self.ks
             .sign_finish(&self.server_handshake_traffic_secret, hs_hash)
     }
 
-    pub(crate) fn client_key(&self) -> &hkdf::Prk {
+    pub(crate) fn client_key(&self) -> &Prk {
         &self.client_handshake_traffic_secret
     }
 
@@ -190,11 +278,7 @@ impl KeyScheduleHandshake {
         hs_hash: Digest,
         key_log: &dyn KeyLog,
         client_random: &[u8; 32],
-    ) -> (
-        KeyScheduleTrafficWithClientFinishedPending,
-        hkdf::Prk,
-        hkdf::Prk,
-    ) {
+    ) -> (KeyScheduleTrafficWithClientFinishedPending, Prk, Prk) {
         let traffic = KeyScheduleTraffic::new(self.ks, hs_hash, key_log, client_random);
 
         let client_secret = traffic
@@ -217,15 +301,16 @@ impl KeyScheduleHandshake {
 /// finished verify_data. The traffic stage key schedule can be extracted from it
 /// through signing the client finished hash.
 pub(crate) struct KeyScheduleTrafficWithClientFinishedPending {
-    handshake_client_traffic_secret: hkdf::Prk,
+    handshake_client_traffic_secret: Prk,
     traffic: KeyScheduleTraffic,
 }
 
 impl KeyScheduleTrafficWithClientFinishedPending {
-    pub(crate) fn sign_client_finish(
-        self,
-        hs_hash: &Digest,
-    ) -> (KeyScheduleTraffic, hmac::Tag, hkdf::Prk) {
+    pub(crate) fn handshake_client_key(&self) -> &Prk {
+        &self.handshake_client_traffic_secret
+    }
+
+    pub(crate) fn sign_client_finish(self, hs_hash: &Digest) -> (KeyScheduleTraffic, Digest, Prk) {
         let tag = self
             .traffic
             .ks
@@ -240,13 +325,29 @@ impl KeyScheduleTrafficWithClientFinishedPending {
     }
 }
 
+/// The early exporter of RFC8446 section 7.5, for binding 0-RTT data.
+pub(crate) struct EarlyExporter {
+    secret: Prk,
+}
+
+impl EarlyExporter {
+    pub(crate) fn export_keying_material(
+        &self,
+        out: &mut [u8],
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        export_keying_material(&self.secret, out, label, context)
+    }
+}
+
 /// KeySchedule during traffic stage.  All traffic & exporter keys are guaranteed
 /// to be available.
 pub(crate) struct KeyScheduleTraffic {
     ks: KeySchedule,
-    current_client_traffic_secret: hkdf::Prk,
-    current_server_traffic_secret: hkdf::Prk,
-    current_exporter_secret: hkdf::Prk,
+    current_client_traffic_secret: Prk,
+    current_server_traffic_secret: Prk,
+    current_exporter_secret: Prk,
 }
 
 impl KeyScheduleTraffic {
@@ -287,7 +388,7 @@ impl KeyScheduleTraffic {
         }
     }
 
-    pub(crate) fn next_server_application_traffic_secret(&mut self) -> hkdf::Prk {
+    pub(crate) fn next_server_application_traffic_secret(&mut self) -> Prk {
         let secret = self
             .ks
             .derive_next(&self.current_server_traffic_secret);
@@ -295,7 +396,7 @@ impl KeyScheduleTraffic {
         secret
     }
 
-    pub(crate) fn next_client_application_traffic_secret(&mut self) -> hkdf::Prk {
+    pub(crate) fn next_client_application_traffic_secret(&mut self) -> Prk {
         let secret = self
             .ks
             .derive_next(&self.current_client_traffic_secret);
@@ -303,16 +404,38 @@ impl KeyScheduleTraffic {
         secret
     }
 
+    pub(crate) fn extract_secrets(
+        &self,
+        suite: &Tls13CipherSuite,
+        is_client: bool,
+    ) -> Result<PartiallyExtractedSecrets, Error> {
+        let bulk = suite.aead_alg.bulk().ok_or_else(|| {
+            Error::General("cannot extract secrets for this cipher suite".to_string())
+        })?;
+        let expand = |secret: &Prk| {
+            let key = hkdf_expand_label_vec(secret, bulk.key_len(), b"key", &[]);
+            let iv = derive_traffic_iv(secret);
+            ConnectionTrafficSecrets::new(bulk, &key, iv.value())
+        };
+
+        let client = expand(&self.current_client_traffic_secret);
+        let server = expand(&self.current_server_traffic_secret);
+        let (tx, rx) = match is_client {
+            true => (client, server),
+            false => (server, client),
+        };
+
+        Ok(PartiallyExtractedSecrets { tx, rx })
+    }
+
     pub(crate) fn resumption_master_secret_and_derive_ticket_psk(
         &self,
         hs_hash: &Digest,
         nonce: &[u8],
     ) -> Vec<u8> {
-        let resumption_master_secret = self.ks.derive(
-            self.ks.algorithm(),
-            SecretKind::ResumptionMasterSecret,
-            hs_hash.as_ref(),
-        );
+        let resumption_master_secret = self
+            .ks
+            .derive_secret(SecretKind::ResumptionMasterSecret, hs_hash.as_ref());
         self.ks
             .derive_ticket_psk(&resumption_master_secret, nonce)
     }
@@ -323,50 +446,56 @@ impl KeyScheduleTraffic {
         label: &[u8],
         context: Option<&[u8]>,
     ) -> Result<(), Error> {
-        self.ks
-            .export_keying_material(&self.current_exporter_secret, out, label, context)
+        export_keying_material(&self.current_exporter_secret, out, label, context)
     }
 }
 
 impl KeySchedule {
-    fn new(algorithm: hkdf::Algorithm, secret: &[u8]) -> Self {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-        let salt = hkdf::Salt::new(algorithm, &zeroes[..algorithm.len()]);
+    fn new(provider: &'static dyn CryptoProvider, algorithm: HashAlgorithm, secret: &[u8]) -> Self {
+        let zeroes = [0u8; MAX_HASH_LEN];
         Self {
-            current: salt.extract(secret),
-            algorithm,
+            current: Prk::extract(
+                provider,
+                algorithm,
+                &zeroes[..algorithm.output_len()],
+                secret,
+            ),
         }
     }
 
-    #[inline]
-    fn algorithm(&self) -> hkdf::Algorithm {
-        self.algorithm
+    fn new_with_empty_secret(
+        provider: &'static dyn CryptoProvider,
+        algorithm: HashAlgorithm,
+    ) -> Self {
+        let zeroes = [0u8; MAX_HASH_LEN];
+        Self::new(provider, algorithm, &zeroes[..algorithm.output_len()])
     }
 
-    fn new_with_empty_secret(algorithm: hkdf::Algorithm) -> Self {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-        Self::new(algorithm, &zeroes[..algorithm.len()])
+    #[inline]
+    fn algorithm(&self) -> HashAlgorithm {
+        self.current.algorithm()
     }
 
     /// Input the empty secret.
     fn input_empty(&mut self) {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-        self.input_secret(&zeroes[..self.algorithm.len()]);
+        let zeroes = [0u8; MAX_HASH_LEN];
+        self.input_secret(&zeroes[..self.algorithm().output_len()]);
     }
 
     /// Input the given secret.
     fn input_secret(&mut self, secret: &[u8]) {
-        let salt: hkdf::Salt = self.derive_for_empty_hash(SecretKind::DerivedSecret);
-        self.current = salt.extract(secret);
+        let salt = self.derive_secret_for_empty_hash(SecretKind::DerivedSecret);
+        self.current = Prk::extract(
+            self.current.provider,
+            self.algorithm(),
+            salt.as_bytes(),
+            secret,
+        );
     }
 
     /// Derive a secret of given `kind`, using current handshake hash `hs_hash`.
-    fn derive<T, L>(&self, key_type: L, kind: SecretKind, hs_hash: &[u8]) -> T
-    where
-        T: for<'a> From<hkdf::Okm<'a, L>>,
-        L: hkdf::KeyType,
-    {
-        hkdf_expand(&self.current, key_type, kind.to_bytes(), hs_hash)
+    fn derive_secret(&self, kind: SecretKind, hs_hash: &[u8]) -> Prk {
+        hkdf_expand_label_prk(&self.current, kind.to_bytes(), hs_hash)
     }
 
     fn derive_logged_secret(
@@ -375,122 +504,89 @@ impl KeySchedule {
         hs_hash: &[u8],
         key_log: &dyn KeyLog,
         client_random: &[u8; 32],
-    ) -> hkdf::Prk {
+    ) -> Prk {
         let log_label = kind
             .log_label()
             .expect("not a loggable secret");
+        let secret = self.derive_secret(kind, hs_hash);
         if key_log.will_log(log_label) {
-            let secret = self
-                .derive::<PayloadU8, _>(PayloadU8Len(self.algorithm.len()), kind, hs_hash)
-                .into_inner();
-            key_log.log(log_label, client_random, &secret);
+            key_log.log(log_label, client_random, secret.as_bytes());
         }
-        self.derive(self.algorithm, kind, hs_hash)
+        secret
     }
 
     /// Derive a secret of given `kind` using the hash of the empty string
     /// for the handshake hash.  Useful only for
     /// `SecretKind::ResumptionPSKBinderKey` and
     /// `SecretKind::DerivedSecret`.
-    fn derive_for_empty_hash<T>(&self, kind: SecretKind) -> T
-    where
-        T: for<'a> From<hkdf::Okm<'a, hkdf::Algorithm>>,
-    {
-        let digest_alg = self
-            .algorithm
-            .hmac_algorithm()
-            .digest_algorithm();
-        let empty_hash = digest::digest(digest_alg, &[]);
-        self.derive(self.algorithm, kind, empty_hash.as_ref())
+    fn derive_secret_for_empty_hash(&self, kind: SecretKind) -> Prk {
+        let empty_hash = self
+            .current
+            .provider
+            .hash(self.algorithm(), &[]);
+        self.derive_secret(kind, empty_hash.as_ref())
     }
 
     /// Sign the finished message consisting of `hs_hash` using a current
     /// traffic secret.
-    fn sign_finish(&self, base_key: &hkdf::Prk, hs_hash: &Digest) -> hmac::Tag {
+    fn sign_finish(&self, base_key: &Prk, hs_hash: &Digest) -> Digest {
         self.sign_verify_data(base_key, hs_hash)
     }
 
     /// Sign the finished message consisting of `hs_hash` using the key material
     /// `base_key`.
-    fn sign_verify_data(&self, base_key: &hkdf::Prk, hs_hash: &Digest) -> hmac::Tag {
-        let hmac_alg = self.algorithm.hmac_algorithm();
-        let hmac_key = hkdf_expand(base_key, hmac_alg, b"finished", &[]);
-        hmac::sign(&hmac_key, hs_hash.as_ref())
+    fn sign_verify_data(&self, base_key: &Prk, hs_hash: &Digest) -> Digest {
+        let hmac_key =
+            hkdf_expand_label_vec(base_key, self.algorithm().output_len(), b"finished", &[]);
+        self.current
+            .provider
+            .hmac_sign(self.algorithm(), &hmac_key, &[hs_hash.as_ref()])
     }
 
     /// Derive the next application traffic secret, returning it.
-    fn derive_next(&self, base_key: &hkdf::Prk) -> hkdf::Prk {
-        hkdf_expand(base_key, self.algorithm, b"traffic upd", &[])
+    fn derive_next(&self, base_key: &Prk) -> Prk {
+        hkdf_expand_label_prk(base_key, b"traffic upd", &[])
     }
 
     /// Derive the PSK to use given a resumption_master_secret and
     /// ticket_nonce.
-    fn derive_ticket_psk(&self, rms: &hkdf::Prk, nonce: &[u8]) -> Vec<u8> {
-        let payload: PayloadU8 = hkdf_expand(
-            rms,
-            PayloadU8Len(self.algorithm.len()),
-            b"resumption",
-            nonce,
-        );
-        payload.into_inner()
+    fn derive_ticket_psk(&self, rms: &Prk, nonce: &[u8]) -> Vec<u8> {
+        hkdf_expand_label_vec(rms, self.algorithm().output_len(), b"resumption", nonce)
     }
+}
 
-    fn export_keying_material(
-        &self,
-        current_exporter_secret: &hkdf::Prk,
-        out: &mut [u8],
-        label: &[u8],
-        context: Option<&[u8]>,
-    ) -> Result<(), Error> {
-        let digest_alg = self
-            .algorithm
-            .hmac_algorithm()
-            .digest_algorithm();
+/// The longest output of any hash function we use.
+pub(crate) const MAX_HASH_LEN: usize = 64;
 
-        let h_empty = digest::digest(digest_alg, &[]);
-        let secret: hkdf::Prk = hkdf_expand(
-            current_exporter_secret,
-            self.algorithm,
-            label,
-            h_empty.as_ref(),
-        );
+fn export_keying_material(
+    exporter_secret: &Prk,
+    out: &mut [u8],
+    label: &[u8],
+    context: Option<&[u8]>,
+) -> Result<(), Error> {
+    let provider = exporter_secret.provider;
+    let algorithm = exporter_secret.algorithm();
 
-        let h_context = digest::digest(digest_alg, context.unwrap_or(&[]));
+    let h_empty = provider.hash(algorithm, &[]);
+    let secret = hkdf_expand_label_prk(exporter_secret, label, h_empty.as_ref());
 
-        // TODO: Test what happens when this fails
-        hkdf_expand_info(
-            &secret,
-            PayloadU8Len(out.len()),
-            b"exporter",
-            h_context.as_ref(),
-            |okm| okm.fill(out),
-        )
-        .map_err(|_| Error::General("exporting too much".to_string()))
-    }
-}
+    let h_context = provider.hash(algorithm, context.unwrap_or(&[]));
 
-pub(crate) fn hkdf_expand<T, L>(secret: &hkdf::Prk, key_type: L, label: &[u8], context: &[u8]) -> T
-where
-    T: for<'a> From<hkdf::Okm<'a, L>>,
-    L: hkdf::KeyType,
-{
-    hkdf_expand_info(secret, key_type, label, context, |okm| okm.into())
+    // TODO: Test what happens when this fails
+    hkdf_expand_label(&secret, b"exporter", h_context.as_ref(), out)
+        .map_err(|_| Error::General("exporting too much".to_string()))
 }
 
-fn hkdf_expand_info<F, T, L>(
-    secret: &hkdf::Prk,
-    key_type: L,
+/// HKDF-Expand-Label (RFC8446 section 7.1), filling `out`.
+pub(crate) fn hkdf_expand_label(
+    secret: &Prk,
     label: &[u8],
     context: &[u8],
-    f: F,
-) -> T
-where
-    F: for<'b> FnOnce(hkdf::Okm<'b, L>) -> T,
-    L: hkdf::KeyType,
-{
+    out: &mut [u8],
+) -> Result<(), Error> {
     const LABEL_PREFIX: &[u8] = b"tls13 ";
 
-    let output_len = u16::to_be_bytes(key_type.len() as u16);
+    let output_len = u16::to_be_bytes(out.len() as u16);
     let label_len = u8::to_be_bytes((LABEL_PREFIX.len() + label.len()) as u8);
     let context_len = u8::to_be_bytes(context.len() as u8);
 
@@ -502,42 +598,76 @@ where
         &context_len[..],
         context,
     ];
-    let okm = secret.expand(info, key_type).unwrap();
+    secret
+        .provider
+        .hkdf_expand(secret.algorithm(), secret.as_bytes(), info, out)
+}
 
-    f(okm)
+/// HKDF-Expand-Label, returning `len` bytes.
+pub(crate) fn hkdf_expand_label_vec(
+    secret: &Prk,
+    len: usize,
+    label: &[u8],
+    context: &[u8],
+) -> Vec<u8> {
+    let mut out = vec![0u8; len];
+    // Won't fail, because `len` is at most a few hash lengths.
+    hkdf_expand_label(secret, label, context, &mut out).unwrap();
+    out
 }
 
-pub(crate) struct PayloadU8Len(pub(crate) usize);
-impl hkdf::KeyType for PayloadU8Len {
-    fn len(&self) -> usize {
-        self.0
+/// HKDF-Expand-Label, returning a secret as long as the hash output.
+fn hkdf_expand_label_prk(secret: &Prk, label: &[u8], context: &[u8]) -> Prk {
+    let okm = hkdf_expand_label_vec(secret, secret.algorithm().output_len(), label, context);
+    Prk {
+        provider: secret.provider,
+        algorithm: secret.algorithm(),
+        secret: Digest::new(&okm),
     }
 }
 
-impl From<hkdf::Okm<'_, PayloadU8Len>> for PayloadU8 {
-    fn from(okm: hkdf::Okm<PayloadU8Len>) -> Self {
-        let mut r = vec![0u8; okm.len().0];
-        okm.fill(&mut r[..]).unwrap();
-        Self::new(r)
-    }
+/// Derive the PSK imported (RFC9258) from the external PSK `epsk`, whose
+/// hash is `epsk_algorithm`, for use with a suite whose hash is `target`.
+/// `imported_identity` is the encoding of the identity it is imported as.
+pub(crate) fn derive_imported_psk(
+    provider: &'static dyn CryptoProvider,
+    epsk_algorithm: HashAlgorithm,
+    epsk: &[u8],
+    imported_identity: &[u8],
+    target: HashAlgorithm,
+) -> Vec<u8> {
+    let epskx = Prk::extract(provider, epsk_algorithm, &[], epsk);
+    let identity_hash = provider.hash(epsk_algorithm, imported_identity);
+    hkdf_expand_label_vec(
+        &epskx,
+        target.output_len(),
+        b"derived psk",
+        identity_hash.as_ref(),
+    )
 }
 
 pub(crate) fn derive_traffic_key(
-    secret: &hkdf::Prk,
-    aead_algorithm: &'static aead::Algorithm,
-) -> aead::UnboundKey {
-    hkdf_expand(secret, aead_algorithm, b"key", &[])
+    secret: &Prk,
+    aead_alg: &dyn Tls13AeadAlgorithm,
+) -> Result<Box<dyn AeadKey>, Error> {
+    let key = hkdf_expand_label_vec(secret, aead_alg.key_len(), b"key", &[]);
+    aead_alg.new_key(secret.provider, &key)
 }
 
-pub(crate) fn derive_traffic_iv(secret: &hkdf::Prk) -> Iv {
-    hkdf_expand(secret, IvLen, b"iv", &[])
+pub(crate) fn derive_traffic_iv(secret: &Prk) -> Iv {
+    let mut iv = [0u8; NONCE_LEN];
+    // Won't fail: this is much shorter than any hash output.
+    hkdf_expand_label(secret, b"iv", &[], &mut iv).unwrap();
+    Iv::new(iv)
 }
 
 #[cfg(test)]
 mod test {
     use super::{derive_traffic_iv, derive_traffic_key, KeySchedule, SecretKind};
+    use crate::crypto::ring::RING;
+    use crate::crypto::{AeadKey, CryptoProvider, HashAlgorithm};
+    use crate::suites::BulkAlgorithm;
     use crate::KeyLog;
-    use ring::{aead, hkdf};
 
     #[test]
     fn test_vectors() {
@@ -620,8 +750,7 @@ mod test {
             0x0d, 0xb2, 0x8f, 0x98, 0x85, 0x86, 0xa1, 0xb7, 0xe4, 0xd5, 0xc6, 0x9c,
         ];
 
-        let hkdf = hkdf::HKDF_SHA256;
-        let mut ks = KeySchedule::new_with_empty_secret(hkdf);
+        let mut ks = KeySchedule::new_with_empty_secret(&RING, HashAlgorithm::SHA256);
         ks.input_secret(&ecdhe_secret);
 
         assert_traffic_secret(
@@ -681,10 +810,12 @@ mod test {
         let traffic_secret = ks.derive_logged_secret(kind, &hash, &log, &[0; 32]);
 
         // Since we can't test key equality, we test the output of sealing with the key instead.
-        let aead_alg = &aead::AES_128_GCM;
-        let key = derive_traffic_key(&traffic_secret, aead_alg);
+        let bulk = &BulkAlgorithm::Aes128Gcm;
+        let key = derive_traffic_key(&traffic_secret, bulk).unwrap();
         let seal_output = seal_zeroes(key);
-        let expected_key = aead::UnboundKey::new(aead_alg, expected_key).unwrap();
+        let expected_key = RING
+            .aead_key(bulk, expected_key)
+            .unwrap();
         let expected_seal_output = seal_zeroes(expected_key);
         assert_eq!(seal_output, expected_seal_output);
         assert!(seal_output.len() >= 48); // Sanity check.
@@ -693,15 +824,10 @@ mod test {
         assert_eq!(iv.value(), expected_iv);
     }
 
-    fn seal_zeroes(key: aead::UnboundKey) -> Vec<u8> {
-        let key = aead::LessSafeKey::new(key);
+    fn seal_zeroes(key: Box<dyn AeadKey>) -> Vec<u8> {
         let mut seal_output = vec![0; 32];
-        key.seal_in_place_append_tag(
-            aead::Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
-            aead::Aad::empty(),
-            &mut seal_output,
-        )
-        .unwrap();
+        key.seal_in_place([0; 12], &[], &mut seal_output, 0)
+            .unwrap();
         seal_output
     }
 }
@@ -28,6 +28,8 @@ pub trait KeyLog: Send + Sync {
     /// - `CLIENT_RANDOM`: `secret` is the master secret for a TLSv1.2 session.
     /// - `CLIENT_EARLY_TRAFFIC_SECRET`: `secret` encrypts early data
     ///   transmitted by a client
+    /// - `EARLY_EXPORTER_SECRET`: `secret` is the early exporter secret
+    ///   in a TLSv1.3 session which uses 0-RTT.
     /// - `SERVER_HANDSHAKE_TRAFFIC_SECRET`: `secret` encrypts
     ///   handshake messages from the server during a TLSv1.3 handshake.
     /// - `CLIENT_HANDSHAKE_TRAFFIC_SECRET`: `secret` encrypts
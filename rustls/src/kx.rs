@@ -1,19 +1,43 @@
+use crate::crypto::{ActiveKeyExchange, KeyExchangeResult};
+use crate::error::Error;
+#[cfg(feature = "ffdhe")]
+use crate::ffdhe;
+#[cfg(feature = "mlkem")]
+use crate::mlkem;
 use crate::msgs::enums::NamedGroup;
+use crate::msgs::handshake::KeyExchangeAlgorithm;
 
-/// The result of a key exchange.  This has our public key,
-/// and the agreed shared secret (also known as the "premaster secret"
-/// in TLS1.0-era protocols, and "Z" in TLS1.3).
-pub(crate) struct KeyExchangeResult {
-    pub(crate) pubkey: ring::agreement::PublicKey,
-    pub(crate) shared_secret: Vec<u8>,
-}
+#[cfg(feature = "curve448")]
+use openssl::pkey::{Id, PKey, Private};
+#[cfg(feature = "secp521r1")]
+use p521::elliptic_curve::rand_core::OsRng;
+#[cfg(feature = "secp521r1")]
+use p521::elliptic_curve::sec1::ToEncodedPoint;
+use ring::rand::SystemRandom;
 
-/// An in-progress key exchange.  This has the algorithm,
-/// our private key, and our public key.
+/// An in-progress key exchange, as done by the default crypto provider.
+/// This has the algorithm, our private key, and our public key.
 pub(crate) struct KeyExchange {
     skxg: &'static SupportedKxGroup,
-    privkey: ring::agreement::EphemeralPrivateKey,
-    pub(crate) pubkey: ring::agreement::PublicKey,
+    privkey: PrivateKey,
+    pubkey: Vec<u8>,
+}
+
+/// Our ephemeral private key, for whichever implementation the
+/// group uses.
+enum PrivateKey {
+    Ring(ring::agreement::EphemeralPrivateKey),
+    #[cfg(feature = "secp521r1")]
+    P521(p521::SecretKey),
+    #[cfg(feature = "ffdhe")]
+    Ffdhe(ffdhe::PrivateExponent),
+    #[cfg(feature = "mlkem")]
+    X25519MlKem768(
+        mlkem::DecapsulationKey,
+        ring::agreement::EphemeralPrivateKey,
+    ),
+    #[cfg(feature = "curve448")]
+    X448(PKey<Private>),
 }
 
 impl KeyExchange {
@@ -31,70 +55,374 @@ impl KeyExchange {
     /// Start a key exchange, using the given SupportedKxGroup.
     ///
     /// This generates an ephemeral key pair and stores it in the returned KeyExchange object.
+    /// The randomness comes from *ring*'s `SystemRandom`, except that
+    /// the `p521` crate uses `getrandom` directly, and ML-KEM and X448
+    /// use the generators of aws-lc and OpenSSL.
     pub(crate) fn start(skxg: &'static SupportedKxGroup) -> Option<Self> {
-        let rng = ring::rand::SystemRandom::new();
-        let ours =
-            ring::agreement::EphemeralPrivateKey::generate(skxg.agreement_algorithm, &rng).ok()?;
-
-        let pubkey = ours.compute_public_key().ok()?;
+        let (privkey, pubkey) = match skxg.agreement_algorithm {
+            Agreement::Ring(alg) => {
+                let ours = ring_private_key(alg)?;
+                let pubkey = ours.compute_public_key().ok()?;
+                (PrivateKey::Ring(ours), pubkey.as_ref().to_vec())
+            }
+            #[cfg(feature = "secp521r1")]
+            Agreement::P521 => {
+                let ours = p521::SecretKey::random(&mut OsRng);
+                let pubkey = ours
+                    .public_key()
+                    .to_encoded_point(false);
+                (PrivateKey::P521(ours), pubkey.as_bytes().to_vec())
+            }
+            #[cfg(feature = "ffdhe")]
+            Agreement::Ffdhe(group) => {
+                let (ours, pubkey) = group.generate()?;
+                (PrivateKey::Ffdhe(ours), pubkey)
+            }
+            #[cfg(feature = "mlkem")]
+            Agreement::X25519MlKem768 => {
+                let (mlkem, mut pubkey) = mlkem::DecapsulationKey::generate()?;
+                let x25519 = ring_private_key(&ring::agreement::X25519)?;
+                pubkey.extend_from_slice(
+                    x25519
+                        .compute_public_key()
+                        .ok()?
+                        .as_ref(),
+                );
+                (PrivateKey::X25519MlKem768(mlkem, x25519), pubkey)
+            }
+            #[cfg(feature = "curve448")]
+            Agreement::X448 => {
+                let ours = PKey::generate_x448().ok()?;
+                let pubkey = ours.raw_public_key().ok()?;
+                (PrivateKey::X448(ours), pubkey)
+            }
+        };
 
         Some(Self {
             skxg,
-            privkey: ours,
+            privkey,
             pubkey,
         })
     }
 
-    /// Return the group being used.
-    pub(crate) fn group(&self) -> NamedGroup {
+    /// Completes the key exchange, given the peer's public key, returning
+    /// the shared secret.
+    fn agree(self, peer: &[u8]) -> Option<Vec<u8>> {
+        let shared_secret = match (self.privkey, self.skxg.agreement_algorithm) {
+            (PrivateKey::Ring(ours), Agreement::Ring(alg)) => {
+                let peer_key = ring::agreement::UnparsedPublicKey::new(alg, peer);
+                ring::agreement::agree_ephemeral(ours, &peer_key, (), |v| Ok(Vec::from(v))).ok()?
+            }
+            #[cfg(feature = "secp521r1")]
+            (PrivateKey::P521(ours), Agreement::P521) => {
+                // Only the uncompressed point format is allowed (RFC8446 section 4.2.8.2).
+                if peer.first() != Some(&0x04) {
+                    return None;
+                }
+                let peer_key = p521::PublicKey::from_sec1_bytes(peer).ok()?;
+                p521::ecdh::diffie_hellman(ours.to_nonzero_scalar(), peer_key.as_affine())
+                    .raw_secret_bytes()
+                    .to_vec()
+            }
+            #[cfg(feature = "ffdhe")]
+            (PrivateKey::Ffdhe(ours), Agreement::Ffdhe(group)) => group.agree(&ours, peer)?,
+            #[cfg(feature = "mlkem")]
+            (PrivateKey::X25519MlKem768(mlkem, x25519), Agreement::X25519MlKem768) => {
+                // The server's share is an ML-KEM ciphertext and an X25519 public key.
+                if peer.len() != mlkem::CIPHERTEXT_LEN + X25519_LEN {
+                    return None;
+                }
+                let (ciphertext, x25519_peer) = peer.split_at(mlkem::CIPHERTEXT_LEN);
+                let mut shared_secret = mlkem.decapsulate(ciphertext)?.to_vec();
+                shared_secret.extend_from_slice(&x25519_agree(x25519, x25519_peer)?);
+                shared_secret
+            }
+            #[cfg(feature = "curve448")]
+            (PrivateKey::X448(ours), Agreement::X448) => x448_agree(&ours, peer)?,
+            _ => return None,
+        };
+
+        Some(shared_secret)
+    }
+
+    /// Do the server's half of a TLS1.3 key exchange, given the client's
+    /// key share `peer`.
+    ///
+    /// For Diffie-Hellman groups this is `start` followed by `complete`.
+    /// For KEM-based groups it encapsulates to the client's share, and
+    /// the result's `pubkey` is the ciphertext.
+    pub(crate) fn respond(
+        skxg: &'static SupportedKxGroup,
+        peer: &[u8],
+    ) -> Result<KeyExchangeResult, Error> {
+        let failed = || Error::PeerMisbehavedError("key exchange failed".to_string());
+
+        match skxg.agreement_algorithm {
+            #[cfg(feature = "mlkem")]
+            Agreement::X25519MlKem768 => {
+                // The client's share is an ML-KEM encapsulation key and an
+                // X25519 public key.
+                if peer.len() != mlkem::ENCAPSULATION_KEY_LEN + X25519_LEN {
+                    return Err(failed());
+                }
+                let (encapsulation_key, x25519_peer) = peer.split_at(mlkem::ENCAPSULATION_KEY_LEN);
+
+                let x25519 = ring_private_key(&ring::agreement::X25519)
+                    .ok_or(Error::FailedToGetRandomBytes)?;
+                let x25519_pubkey = x25519
+                    .compute_public_key()
+                    .map_err(|_| Error::FailedToGetRandomBytes)?;
+
+                let (mut pubkey, mlkem_secret) =
+                    mlkem::encapsulate(encapsulation_key).ok_or_else(failed)?;
+                pubkey.extend_from_slice(x25519_pubkey.as_ref());
+                let mut shared_secret = mlkem_secret.to_vec();
+                shared_secret
+                    .extend_from_slice(&x25519_agree(x25519, x25519_peer).ok_or_else(failed)?);
+
+                Ok(KeyExchangeResult {
+                    pubkey,
+                    shared_secret,
+                })
+            }
+            _ => {
+                let ours = Self::start(skxg).ok_or(Error::FailedToGetRandomBytes)?;
+                let pubkey = ours.pubkey.clone();
+                let shared_secret = ours.agree(peer).ok_or_else(failed)?;
+                Ok(KeyExchangeResult {
+                    pubkey,
+                    shared_secret,
+                })
+            }
+        }
+    }
+}
+
+impl ActiveKeyExchange for KeyExchange {
+    fn group(&self) -> NamedGroup {
         self.skxg.name
     }
 
-    /// Completes the key exchange, given the peer's public key.  The shared
-    /// secret is returned as a KeyExchangeResult.
-    pub(crate) fn complete(self, peer: &[u8]) -> Option<KeyExchangeResult> {
-        let peer_key = ring::agreement::UnparsedPublicKey::new(self.skxg.agreement_algorithm, peer);
-        let pubkey = self.pubkey;
-        ring::agreement::agree_ephemeral(self.privkey, &peer_key, (), move |v| {
-            Ok(KeyExchangeResult {
-                pubkey,
-                shared_secret: Vec::from(v),
-            })
-        })
-        .ok()
+    fn pub_key(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Option<Vec<u8>> {
+        self.agree(peer_pub_key)
     }
 }
 
+/// The length of an X25519 public key.
+#[cfg(feature = "mlkem")]
+const X25519_LEN: usize = 32;
+
+/// Make a *ring* ephemeral private key for `alg`.
+fn ring_private_key(
+    alg: &'static ring::agreement::Algorithm,
+) -> Option<ring::agreement::EphemeralPrivateKey> {
+    ring::agreement::EphemeralPrivateKey::generate(alg, &SystemRandom::new()).ok()
+}
+
+#[cfg(feature = "mlkem")]
+fn x25519_agree(ours: ring::agreement::EphemeralPrivateKey, peer: &[u8]) -> Option<Vec<u8>> {
+    let peer_key = ring::agreement::UnparsedPublicKey::new(&ring::agreement::X25519, peer);
+    ring::agreement::agree_ephemeral(ours, &peer_key, (), |v| Ok(Vec::from(v))).ok()
+}
+
+/// Do X448 with our private key `ours` and the peer's public key `peer`,
+/// rejecting an all-zero result as RFC7748 section 6.2 requires.
+#[cfg(feature = "curve448")]
+fn x448_agree(ours: &PKey<Private>, peer: &[u8]) -> Option<Vec<u8>> {
+    let peer = PKey::public_key_from_raw_bytes(peer, Id::X448).ok()?;
+    let mut deriver = openssl::derive::Deriver::new(ours).ok()?;
+    deriver.set_peer(&peer).ok()?;
+    let shared_secret = deriver.derive_to_vec().ok()?;
+    if shared_secret.iter().all(|b| *b == 0) {
+        return None;
+    }
+    Some(shared_secret)
+}
+
+/// How a group's key agreement is implemented.
+#[derive(Clone, Copy, Debug)]
+enum Agreement {
+    Ring(&'static ring::agreement::Algorithm),
+    /// ring lacks P-521, so this uses the `p521` crate.
+    #[cfg(feature = "secp521r1")]
+    P521,
+    /// ring lacks finite-field Diffie-Hellman too; see the `ffdhe` module.
+    #[cfg(feature = "ffdhe")]
+    Ffdhe(ffdhe::FfdheGroup),
+    /// The hybrid of ML-KEM-768 (see the `mlkem` module) and X25519.
+    #[cfg(feature = "mlkem")]
+    X25519MlKem768,
+    /// ring lacks X448 as well, so this uses OpenSSL.
+    #[cfg(feature = "curve448")]
+    X448,
+}
+
 /// A key-exchange group supported by rustls.
 ///
 /// All possible instances of this class are provided by the library in
-/// the `ALL_KX_GROUPS` array.
+/// the `ALL_KX_GROUPS` slice.
 #[derive(Debug)]
 pub struct SupportedKxGroup {
     /// The IANA "TLS Supported Groups" name of the group
     pub name: NamedGroup,
 
-    /// The corresponding ring agreement::Algorithm
-    agreement_algorithm: &'static ring::agreement::Algorithm,
+    /// The implementation of the key agreement
+    agreement_algorithm: Agreement,
+}
+
+impl SupportedKxGroup {
+    /// Can this group be used for TLS1.2 key exchange `kxa`?  Elliptic
+    /// curve groups are for ECDHE and finite-field ones for DHE.  TLS1.2
+    /// only has Diffie-Hellman key exchange, so KEM-based groups are
+    /// TLS1.3-only.
+    pub(crate) fn usable_for_tls12(&self, kxa: &KeyExchangeAlgorithm) -> bool {
+        match self.agreement_algorithm {
+            #[cfg(feature = "mlkem")]
+            Agreement::X25519MlKem768 => false,
+            #[cfg(feature = "ffdhe")]
+            Agreement::Ffdhe(_) => *kxa == KeyExchangeAlgorithm::DHE,
+            _ => *kxa == KeyExchangeAlgorithm::ECDHE,
+        }
+    }
+
+    /// The prime and generator of a finite-field group, for a TLS1.2
+    /// ServerKeyExchange; or `None` for other groups.
+    pub(crate) fn dhe_params(&self) -> Option<(Vec<u8>, &'static [u8])> {
+        match self.agreement_algorithm {
+            #[cfg(feature = "ffdhe")]
+            Agreement::Ffdhe(group) => Some((group.prime(), group.generator())),
+            _ => None,
+        }
+    }
 }
 
 /// Ephemeral ECDH on curve25519 (see RFC7748)
 pub static X25519: SupportedKxGroup = SupportedKxGroup {
     name: NamedGroup::X25519,
-    agreement_algorithm: &ring::agreement::X25519,
+    agreement_algorithm: Agreement::Ring(&ring::agreement::X25519),
+};
+
+/// Ephemeral ECDH on curve448 (see RFC7748)
+///
+/// This needs the `curve448` crate feature.
+#[cfg(feature = "curve448")]
+pub static X448: SupportedKxGroup = SupportedKxGroup {
+    name: NamedGroup::X448,
+    agreement_algorithm: Agreement::X448,
+};
+
+/// Hybrid post-quantum key exchange, combining ML-KEM-768 with
+/// X25519 (see draft-kwiatkowski-tls-ecdhe-mlkem).
+///
+/// This is only usable in TLS1.3.  Its key shares are much larger than
+/// those of the other groups: 1216 bytes from the client and 1120
+/// bytes from the server.
+///
+/// This needs the `mlkem` crate feature.
+#[cfg(feature = "mlkem")]
+pub static X25519MLKEM768: SupportedKxGroup = SupportedKxGroup {
+    name: NamedGroup::X25519MLKEM768,
+    agreement_algorithm: Agreement::X25519MlKem768,
 };
 
 /// Ephemeral ECDH on secp256r1 (aka NIST-P256)
 pub static SECP256R1: SupportedKxGroup = SupportedKxGroup {
     name: NamedGroup::secp256r1,
-    agreement_algorithm: &ring::agreement::ECDH_P256,
+    agreement_algorithm: Agreement::Ring(&ring::agreement::ECDH_P256),
 };
 
 /// Ephemeral ECDH on secp384r1 (aka NIST-P384)
 pub static SECP384R1: SupportedKxGroup = SupportedKxGroup {
     name: NamedGroup::secp384r1,
-    agreement_algorithm: &ring::agreement::ECDH_P384,
+    agreement_algorithm: Agreement::Ring(&ring::agreement::ECDH_P384),
 };
 
-/// A list of all the key exchange groups supported by rustls.
-pub static ALL_KX_GROUPS: [&SupportedKxGroup; 3] = [&X25519, &SECP256R1, &SECP384R1];
+/// Ephemeral ECDH on secp521r1 (aka NIST-P521)
+///
+/// This needs the `secp521r1` crate feature.
+#[cfg(feature = "secp521r1")]
+pub static SECP521R1: SupportedKxGroup = SupportedKxGroup {
+    name: NamedGroup::secp521r1,
+    agreement_algorithm: Agreement::P521,
+};
+
+/// Ephemeral finite-field Diffie-Hellman with the 2048-bit group
+/// (see RFC7919)
+///
+/// This needs the `ffdhe` crate feature.
+#[cfg(feature = "ffdhe")]
+pub static FFDHE2048: SupportedKxGroup = SupportedKxGroup {
+    name: NamedGroup::FFDHE2048,
+    agreement_algorithm: Agreement::Ffdhe(ffdhe::FfdheGroup::Ffdhe2048),
+};
+
+/// Ephemeral finite-field Diffie-Hellman with the 3072-bit group
+/// (see RFC7919)
+///
+/// This needs the `ffdhe` crate feature.
+#[cfg(feature = "ffdhe")]
+pub static FFDHE3072: SupportedKxGroup = SupportedKxGroup {
+    name: NamedGroup::FFDHE3072,
+    agreement_algorithm: Agreement::Ffdhe(ffdhe::FfdheGroup::Ffdhe3072),
+};
+
+/// Ephemeral finite-field Diffie-Hellman with the 4096-bit group
+/// (see RFC7919)
+///
+/// This needs the `ffdhe` crate feature.
+#[cfg(feature = "ffdhe")]
+pub static FFDHE4096: SupportedKxGroup = SupportedKxGroup {
+    name: NamedGroup::FFDHE4096,
+    agreement_algorithm: Agreement::Ffdhe(ffdhe::FfdheGroup::Ffdhe4096),
+};
+
+/// A list of all the key exchange groups defined by rustls.  The
+/// default crypto provider does not support them all: see
+/// [`RingProvider`](crate::crypto::ring::RingProvider).
+///
+/// The finite-field groups come last: they are much slower, and
+/// their key shares much larger, than the elliptic curve groups.
+pub static ALL_KX_GROUPS: &[&SupportedKxGroup] = &[
+    &X25519,
+    &SECP256R1,
+    &SECP384R1,
+    #[cfg(feature = "secp521r1")]
+    &SECP521R1,
+    #[cfg(feature = "curve448")]
+    &X448,
+    #[cfg(feature = "mlkem")]
+    &X25519MLKEM768,
+    #[cfg(feature = "ffdhe")]
+    &FFDHE2048,
+    #[cfg(feature = "ffdhe")]
+    &FFDHE3072,
+    #[cfg(feature = "ffdhe")]
+    &FFDHE4096,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn responses_agree_with_key_shares() {
+        for skxg in ALL_KX_GROUPS {
+            let client = KeyExchange::start(skxg).unwrap();
+            let server = KeyExchange::respond(skxg, &client.pubkey).unwrap();
+            let shared_secret = client.agree(&server.pubkey).unwrap();
+            assert_eq!(shared_secret, server.shared_secret, "{:?}", skxg.name);
+        }
+    }
+
+    #[test]
+    fn key_shares_are_fresh() {
+        for skxg in ALL_KX_GROUPS {
+            let share = || KeyExchange::start(skxg).unwrap().pubkey;
+            assert_ne!(share(), share(), "{:?}", skxg.name);
+        }
+    }
+}
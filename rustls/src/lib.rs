@@ -9,8 +9,10 @@
 //! * ECDSA, Ed25519 or RSA server authentication by clients.
 //! * ECDSA, Ed25519 or RSA server authentication by servers.
 //! * Forward secrecy using ECDHE; with curve25519, nistp256 or nistp384 curves.
+//! * Finite-field DHE with the ffdhe2048, ffdhe3072 and ffdhe4096 groups ([RFC7919](https://tools.ietf.org/html/rfc7919)), with the `ffdhe` crate feature; DHE cipher suites are off by default.
 //! * AES128-GCM and AES256-GCM bulk encryption, with safe nonces.
 //! * ChaCha20-Poly1305 bulk encryption ([RFC7905](https://tools.ietf.org/html/rfc7905)).
+//! * AES-CCM and AES-CCM_8 bulk encryption ([RFC7251](https://tools.ietf.org/html/rfc7251)), off by default.
 //! * ALPN support.
 //! * SNI support.
 //! * Tunable fragment size to make TLS messages match size of underlying transport.
@@ -18,19 +20,26 @@
 //! * TLS1.2 session resumption.
 //! * TLS1.2 resumption via tickets ([RFC5077](https://tools.ietf.org/html/rfc5077)).
 //! * TLS1.3 resumption via tickets or session storage.
-//! * TLS1.3 0-RTT data for clients.
+//! * TLS1.3 0-RTT data for clients and servers.
 //! * Client authentication by clients.
 //! * Client authentication by servers.
 //! * Extended master secret support ([RFC7627](https://tools.ietf.org/html/rfc7627)).
 //! * Exporters ([RFC5705](https://tools.ietf.org/html/rfc5705)).
 //! * OCSP stapling by servers.
+//! * OCSP verification by clients.
 //! * SCT stapling by servers.
 //! * SCT verification by clients.
+//! * TLS1.3 certificate compression ([RFC8879](https://tools.ietf.org/html/rfc8879)), with user-supplied algorithms.
+//! * Encrypted Client Hello (draft-ietf-tls-esni-18) for clients and servers, using HPKE with X25519.
+//! * GREASE ([RFC8701](https://tools.ietf.org/html/rfc8701)) for clients.
+//! * TLS1.3 delegated credentials ([RFC9345](https://tools.ietf.org/html/rfc9345)) for clients and servers.
+//! * TLS1.3 raw public keys ([RFC7250](https://tools.ietf.org/html/rfc7250)) for clients and servers.
+//! * TLS1.3 external pre-shared keys, including imported PSKs ([RFC9258](https://tools.ietf.org/html/rfc9258)).
+//! * Pluggable cryptography via `CryptoProvider`, with *ring* as the default.
+//! * User-defined TLS1.3 cipher suites.
 //!
 //! ## Possible future features
 //!
-//! * PSK support.
-//! * OCSP verification by clients.
 //! * Certificate pinning.
 //!
 //! ## Non-features
@@ -47,7 +56,7 @@
 //! * Renegotiation.
 //! * Kerberos.
 //! * Compression.
-//! * Discrete-log Diffie-Hellman.
+//! * Discrete-log Diffie-Hellman with parameters other than the RFC7919 groups.
 //! * Automatic protocol version downgrade.
 //! * AES-GCM with unsafe nonces.
 //!
@@ -60,6 +69,8 @@
 //! cryptography in TLS. As a result, rustls only runs on platforms
 //! [supported by `ring`](https://github.com/briansmith/ring#online-automated-testing).
 //! At the time of writing this means x86, x86-64, armv7, and aarch64.
+//! Handshakes and records can use another `CryptoProvider`, but certificate
+//! chain validation and QUIC packet protection still use *ring* directly.
 //!
 //! ## Design Overview
 //! ### Rustls does not take care of network IO
@@ -202,6 +213,35 @@
 //!   details of these.  You will only need this if you're writing a QUIC
 //!   implementation.
 //!
+//! - `secp521r1`: this adds the P-521 key exchange group and ECDSA P-521
+//!   signatures, using the `p521` crate.  This feature needs Rust 1.65
+//!   or later.  It covers key exchange, handshake signatures and P-521
+//!   end-entity keys; certificate chains in which an issuer signs with a
+//!   P-521 key are still rejected by the webpki chain validation.
+//!
+//! - `aes-ccm`: this adds the AES-CCM cipher suites to the default crypto
+//!   provider, using the `aes` and `ccm` crates.  This feature needs Rust
+//!   1.56 or later.
+//!
+//! - `ffdhe`: this adds the finite-field Diffie-Hellman groups from RFC7919,
+//!   and the TLS1.2 DHE cipher suites, using the `crypto-bigint` crate.
+//!   This feature needs Rust 1.65 or later.
+//!
+//! - `mlkem`: this adds the hybrid post-quantum X25519MLKEM768 key exchange
+//!   group, using the ML-KEM implementation from the `aws-lc-rs` crate.
+//!   This feature needs Rust 1.71 or later, and a C compiler.
+//!
+//! - `static-x25519`: this lets the default crypto provider load
+//!   long-term X25519 keys, using the `x25519-dalek` crate.  Servers
+//!   need it to offer Encrypted Client Hello.  This feature needs Rust
+//!   1.60 or later.
+//!
+//! - `curve448`: this adds the X448 key exchange group and Ed448
+//!   signatures, using the system's OpenSSL library (1.1.1 or later)
+//!   through the `openssl` crate.  This feature needs its development
+//!   headers and `pkg-config` at build time.  As with `secp521r1`, chains
+//!   in which an issuer signs with an Ed448 key are still rejected.
+//!
 
 // Require docs for public APIs, deny unsafe code, etc.
 #![forbid(unsafe_code, unused_must_use, unstable_features)]
@@ -254,12 +294,23 @@ mod log {
 mod msgs;
 mod anchors;
 mod cipher;
+mod compress;
 mod conn;
+mod delegated;
+mod ech;
 mod error;
+#[cfg(feature = "ffdhe")]
+mod ffdhe;
+mod grease;
 mod hash_hs;
+mod hpke;
 mod key_schedule;
 mod limited_cache;
+#[cfg(feature = "mlkem")]
+mod mlkem;
+mod ocsp;
 mod prf;
+mod psk;
 mod rand;
 mod record_layer;
 mod stream;
@@ -304,31 +355,43 @@ pub use crate::client::ResolvesClientCert;
 pub use crate::client::ServerName;
 pub use crate::client::StoresClientSessions;
 pub use crate::client::{ClientConfig, ClientConnection, WriteEarlyData};
-pub use crate::conn::{IoState, Connection, Reader, Writer};
+pub use crate::compress::{
+    CertificateCompressor, CertificateDecompressor, CompressionFailed, DecompressionFailed,
+};
+pub use crate::conn::{Connection, IoState, PeerMessageLimits, Reader, Writer};
+pub use crate::delegated::DelegatedCredential;
+pub use crate::ech::{EchConfig, EchKey, EchStatus};
 pub use crate::error::Error;
+pub use crate::error::OcspError;
 pub use crate::error::WebPkiError;
 pub use crate::error::WebPkiOp;
 pub use crate::key::{Certificate, PrivateKey};
 pub use crate::keylog::{KeyLog, KeyLogFile, NoKeyLog};
 pub use crate::kx::{SupportedKxGroup, ALL_KX_GROUPS};
+pub use crate::msgs::enums::CertificateCompressionAlgorithm;
+pub use crate::msgs::enums::CertificateType;
 pub use crate::msgs::enums::CipherSuite;
+pub use crate::msgs::enums::PSKKeyExchangeMode;
 pub use crate::msgs::enums::ProtocolVersion;
 pub use crate::msgs::enums::SignatureScheme;
 pub use crate::msgs::handshake::DistinguishedNames;
+pub use crate::psk::{ExternalPsk, PskIdentityStore, PskMemoryStore};
 pub use crate::server::builder::{ConfigWantsClientVerifier, ConfigWantsServerCert};
 pub use crate::server::handy::ResolvesServerCertUsingSni;
 pub use crate::server::handy::{NoServerSessionStorage, ServerSessionMemoryCache};
 pub use crate::server::StoresServerSessions;
+pub use crate::server::{Accepted, Acceptor, ReadEarlyData, ServerConfig, ServerConnection};
 pub use crate::server::{ClientHello, ProducesTickets, ResolvesServerCert};
-pub use crate::server::{ServerConfig, ServerConnection};
 pub use crate::stream::{Stream, StreamOwned};
 pub use crate::suites::{
-    BulkAlgorithm, SupportedCipherSuite, Tls12CipherSuite, Tls13CipherSuite, ALL_CIPHERSUITES,
+    BulkAlgorithm, CipherSuiteCommon, ConnectionTrafficSecrets, ExtractedSecrets,
+    SupportedCipherSuite, Tls12CipherSuite, Tls13AeadAlgorithm, Tls13CipherSuite, ALL_CIPHERSUITES,
     DEFAULT_CIPHERSUITES,
 };
 pub use crate::ticketer::Ticketer;
 pub use crate::verify::{
     AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+    PinnedPublicKeys, RevocationPolicy, ALL_SIGNATURE_SCHEMES, DEFAULT_OCSP_MAX_AGE,
 };
 pub use crate::versions::{SupportedProtocolVersion, ALL_VERSIONS, DEFAULT_VERSIONS};
 
@@ -336,10 +399,20 @@ pub use crate::versions::{SupportedProtocolVersion, ALL_VERSIONS, DEFAULT_VERSIO
 ///
 /// ALL_CIPHERSUITES is provided as an array of all of these values.
 pub mod cipher_suite {
+    pub use crate::suites::TLS13_AES_128_CCM_8_SHA256;
+    pub use crate::suites::TLS13_AES_128_CCM_SHA256;
     pub use crate::suites::TLS13_AES_128_GCM_SHA256;
     pub use crate::suites::TLS13_AES_256_GCM_SHA384;
     pub use crate::suites::TLS13_CHACHA20_POLY1305_SHA256;
+    #[cfg(feature = "ffdhe")]
+    pub use crate::suites::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256;
+    #[cfg(feature = "ffdhe")]
+    pub use crate::suites::TLS_DHE_RSA_WITH_AES_256_GCM_SHA384;
+    pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_128_CCM;
+    pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8;
     pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256;
+    pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_256_CCM;
+    pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8;
     pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384;
     pub use crate::suites::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256;
     pub use crate::suites::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256;
@@ -357,16 +430,31 @@ pub mod version {
 
 /// All defined key exchange groups appear in this module.
 ///
-/// ALL_KX_GROUPS is provided as an array of all of these values.
+/// ALL_KX_GROUPS is provided as a slice of all of these values.
 pub mod kx_group {
+    #[cfg(feature = "ffdhe")]
+    pub use crate::kx::FFDHE2048;
+    #[cfg(feature = "ffdhe")]
+    pub use crate::kx::FFDHE3072;
+    #[cfg(feature = "ffdhe")]
+    pub use crate::kx::FFDHE4096;
     pub use crate::kx::SECP256R1;
     pub use crate::kx::SECP384R1;
+    #[cfg(feature = "secp521r1")]
+    pub use crate::kx::SECP521R1;
     pub use crate::kx::X25519;
+    #[cfg(feature = "mlkem")]
+    pub use crate::kx::X25519MLKEM768;
+    #[cfg(feature = "curve448")]
+    pub use crate::kx::X448;
 }
 
 /// Message signing interfaces and implementations.
 pub mod sign;
 
+pub mod crypto;
+pub use crate::builder::config_builder_with_provider;
+
 #[cfg(feature = "quic")]
 #[cfg_attr(docsrs, doc(cfg(feature = "quic")))]
 /// APIs for implementing QUIC TLS
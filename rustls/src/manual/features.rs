@@ -21,13 +21,18 @@
 * Extended master secret support (RFC7627).
 * Exporters (RFC5705).
 * OCSP stapling by servers.
+* OCSP verification by clients.
 * SCT stapling by servers.
 * SCT verification by clients.
+* TLS1.3 certificate compression ([RFC8879](https://tools.ietf.org/html/rfc8879)), with user-supplied algorithms.
+* Encrypted Client Hello (draft-ietf-tls-esni-18) for clients and servers, using HPKE with X25519.
+* GREASE ([RFC8701](https://tools.ietf.org/html/rfc8701)) for clients.
+* TLS1.3 delegated credentials ([RFC9345](https://tools.ietf.org/html/rfc9345)) for clients and servers.
+* TLS1.3 raw public keys ([RFC7250](https://tools.ietf.org/html/rfc7250)) for clients and servers.
+* TLS1.3 external pre-shared keys, including imported PSKs ([RFC9258](https://tools.ietf.org/html/rfc9258)).
 
 ## Possible future features
 
-* PSK support.
-* OCSP verification by clients.
 * Certificate pinning.
 
 ## Non-features
@@ -118,8 +118,10 @@ rustls naturally does not support SSLv2, but most importantly does not support R
 is an attack against CBC mode ciphersuites in SSLv3.  This was possible in most cases because some clients willingly
 downgraded to SSLv3 after failed handshakes for later versions.
 
-rustls does not support CBC mode ciphersuites, or SSLv3.  Note that rustls does not need to implement `TLS_FALLBACK_SCSV`
-introduced as a countermeasure because it contains no ability to downgrade to earlier protocol versions.
+rustls does not support CBC mode ciphersuites, or SSLv3.  rustls clients never downgrade to earlier protocol
+versions, so never need to send `TLS_FALLBACK_SCSV`, which was introduced as a countermeasure.  rustls servers
+do honour it from other clients: if they support TLS1.3, a client which falls back to TLS1.2 is refused with an
+`inappropriate_fallback` alert.
 
 ## GCM nonces
 
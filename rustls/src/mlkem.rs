@@ -0,0 +1,139 @@
+//! ML-KEM-768 (FIPS 203), for the hybrid X25519MLKEM768 key exchange.
+//!
+//! *ring* lacks ML-KEM, so this uses aws-lc's implementation, through
+//! the `aws-lc-rs` crate.  That draws its randomness from aws-lc's own
+//! generator, not the crypto provider.
+
+use aws_lc_rs::kem::{Ciphertext, DecapsulationKey as AwsDecapsulationKey, EncapsulationKey};
+use aws_lc_rs::kem::{AlgorithmId, ML_KEM_768};
+
+/// The length of an encoded encapsulation key.
+pub(crate) const ENCAPSULATION_KEY_LEN: usize = 1184;
+
+/// The length of a ciphertext.
+pub(crate) const CIPHERTEXT_LEN: usize = 1088;
+
+/// The length of a shared secret.
+pub(crate) const SHARED_SECRET_LEN: usize = 32;
+
+/// An ML-KEM-768 decapsulation key.
+pub(crate) struct DecapsulationKey(AwsDecapsulationKey<AlgorithmId>);
+
+impl DecapsulationKey {
+    /// Generate a new key pair, returning the decapsulation key and
+    /// the encapsulation key.
+    pub(crate) fn generate() -> Option<(Self, Vec<u8>)> {
+        let dk = AwsDecapsulationKey::generate(&ML_KEM_768).ok()?;
+        let ek = dk
+            .encapsulation_key()
+            .ok()?
+            .key_bytes()
+            .ok()?
+            .as_ref()
+            .to_vec();
+        Some((Self(dk), ek))
+    }
+
+    /// Recover the shared secret from ciphertext `c`.  This only fails
+    /// if `c` is the wrong length: an invalid ciphertext gives an
+    /// unpredictable shared secret ("implicit rejection").
+    pub(crate) fn decapsulate(&self, c: &[u8]) -> Option<[u8; SHARED_SECRET_LEN]> {
+        if c.len() != CIPHERTEXT_LEN {
+            return None;
+        }
+
+        let shared = self
+            .0
+            .decapsulate(Ciphertext::from(c))
+            .ok()?;
+        let mut out = [0u8; SHARED_SECRET_LEN];
+        out.copy_from_slice(shared.as_ref());
+        Some(out)
+    }
+}
+
+/// Encapsulate a fresh shared secret to `ek`, returning the ciphertext
+/// and the shared secret.  This fails if `ek` is not a valid
+/// encapsulation key.
+pub(crate) fn encapsulate(ek: &[u8]) -> Option<(Vec<u8>, [u8; SHARED_SECRET_LEN])> {
+    let ek = EncapsulationKey::new(&ML_KEM_768, ek).ok()?;
+    let (ct, shared) = ek.encapsulate().ok()?;
+    let mut out = [0u8; SHARED_SECRET_LEN];
+    out.copy_from_slice(shared.as_ref());
+    Some((ct.as_ref().to_vec(), out))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The test vectors come from OpenSSL's independent FIPS 203
+    // implementation: the key pair is derived from the seed 0, 1, ...,
+    // 63, and the ciphertext encapsulated to it.
+
+    /// The test key pair.
+    fn test_key() -> (DecapsulationKey, Vec<u8>) {
+        let dk = AwsDecapsulationKey::new(&ML_KEM_768, include_bytes!("testdata/mlkem768-dk.bin"))
+            .unwrap();
+        (
+            DecapsulationKey(dk),
+            include_bytes!("testdata/mlkem768-ek.bin").to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_decapsulation() {
+        let (dk, _) = test_key();
+        let ct = include_bytes!("testdata/mlkem768-ct.bin");
+        assert_eq!(
+            dk.decapsulate(ct).unwrap().to_vec(),
+            unhex("24665c4ac429cd90e873a9572b1f03779de3894559c8af53f24cf2e3de24bc88")
+        );
+    }
+
+    #[test]
+    fn test_implicit_rejection() {
+        let (dk, _) = test_key();
+        let mut ct = include_bytes!("testdata/mlkem768-ct.bin").to_vec();
+        ct[0] ^= 1;
+        assert_eq!(
+            dk.decapsulate(&ct).unwrap().to_vec(),
+            unhex("e1f131353d2594a393ec8ab8b6414b3a5c84568e7404aa8bbe593dc3200df237")
+        );
+        assert!(dk.decapsulate(&ct[1..]).is_none());
+    }
+
+    #[test]
+    fn test_encapsulation_to_test_key() {
+        let (dk, ek) = test_key();
+        let (ct, shared) = encapsulate(&ek).unwrap();
+        assert_eq!(ct.len(), CIPHERTEXT_LEN);
+        assert_eq!(dk.decapsulate(&ct), Some(shared));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (dk, ek) = DecapsulationKey::generate().unwrap();
+        assert_eq!(ek.len(), ENCAPSULATION_KEY_LEN);
+        let (ct, shared) = encapsulate(&ek).unwrap();
+        assert_eq!(dk.decapsulate(&ct), Some(shared));
+    }
+
+    #[test]
+    fn test_encapsulate_rejects_bad_keys() {
+        let (_, mut ek) = test_key();
+        assert!(encapsulate(&ek[1..]).is_none());
+
+        // make the first coefficient q
+        ek[0] = 0x01;
+        ek[1] = (ek[1] & 0xf0) | 0x0d;
+        assert!(encapsulate(&ek).is_none());
+    }
+}
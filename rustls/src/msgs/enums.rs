@@ -122,6 +122,7 @@ enum_builder! {
         CertificateURL => 0x15,
         CertificateStatus => 0x16,
         KeyUpdate => 0x18,
+        CompressedCertificate => 0x19,
         MessageHash => 0xfe
     }
 }
@@ -178,7 +179,8 @@ enum_builder! {
         BadCertificateHashValue => 0x72,
         UnknownPSKIdentity => 0x73,
         CertificateRequired => 0x74,
-        NoApplicationProtocol => 0x78
+        NoApplicationProtocol => 0x78,
+        EncryptedClientHelloRequired => 0x79
     }
 }
 
@@ -219,8 +221,12 @@ enum_builder! {
         Heartbeat => 0x000f,
         ALProtocolNegotiation => 0x0010,
         SCT => 0x0012,
+        ClientCertificateType => 0x0013,
+        ServerCertificateType => 0x0014,
         Padding => 0x0015,
         ExtendedMasterSecret => 0x0017,
+        CompressCertificate => 0x001b,
+        DelegatedCredential => 0x0022,
         SessionTicket => 0x0023,
         PreSharedKey => 0x0029,
         EarlyData => 0x002a,
@@ -236,6 +242,8 @@ enum_builder! {
         TransportParameters => 0x0039,
         NextProtocolNegotiation => 0x3374,
         ChannelId => 0x754f,
+        EncryptedClientHelloOuterExtensions => 0xfd00,
+        EncryptedClientHello => 0xfe0d,
         RenegotiationInfo => 0xff01,
         TransportParametersDraft => 0xffa5
     }
@@ -310,7 +318,8 @@ enum_builder! {
         FFDHE3072 => 0x0101,
         FFDHE4096 => 0x0102,
         FFDHE6144 => 0x0103,
-        FFDHE8192 => 0x0104
+        FFDHE8192 => 0x0104,
+        X25519MLKEM768 => 0x11ec
     }
 }
 
@@ -513,6 +522,7 @@ enum_builder! {
         TLS13_CHACHA20_POLY1305_SHA256 => 0x1303,
         TLS13_AES_128_CCM_SHA256 => 0x1304,
         TLS13_AES_128_CCM_8_SHA256 => 0x1305,
+        TLS_FALLBACK_SCSV => 0x5600,
         TLS_ECDH_ECDSA_WITH_NULL_SHA => 0xc001,
         TLS_ECDH_ECDSA_WITH_RC4_128_SHA => 0xc002,
         TLS_ECDH_ECDSA_WITH_3DES_EDE_CBC_SHA => 0xc003,
@@ -684,6 +694,10 @@ enum_builder! {
         TLS_PSK_WITH_AES_256_CCM_8 => 0xc0a9,
         TLS_PSK_DHE_WITH_AES_128_CCM_8 => 0xc0aa,
         TLS_PSK_DHE_WITH_AES_256_CCM_8 => 0xc0ab,
+        TLS_ECDHE_ECDSA_WITH_AES_128_CCM => 0xc0ac,
+        TLS_ECDHE_ECDSA_WITH_AES_256_CCM => 0xc0ad,
+        TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8 => 0xc0ae,
+        TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8 => 0xc0af,
         TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 => 0xcca8,
         TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256 => 0xcca9,
         TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256 => 0xccaa,
@@ -791,3 +805,109 @@ enum_builder! {
         OCSP => 0x01
     }
 }
+
+enum_builder! {
+    /// The `CertificateCompressionAlgorithm` TLS protocol enum.  Values in this enum are taken
+    /// from the various RFCs covering TLS, and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U16
+    EnumName: CertificateCompressionAlgorithm;
+    EnumVal{
+        Zlib => 0x0001,
+        Brotli => 0x0002,
+        Zstd => 0x0003
+    }
+}
+
+enum_builder! {
+    /// The `CertificateType` TLS protocol enum, used to negotiate raw
+    /// public keys (RFC7250).  Values in this enum are taken
+    /// from the various RFCs covering TLS, and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U8
+    EnumName: CertificateType;
+    EnumVal{
+        X509 => 0x00,
+        OpenPGP => 0x01,
+        RawPublicKey => 0x02
+    }
+}
+
+enum_builder! {
+    /// The `TlsKdf` enum, naming the KDF an imported PSK (RFC9258) is
+    /// for.  Values in this enum are taken from RFC9258, and are listed
+    /// by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U16
+    EnumName: TlsKdf;
+    EnumVal{
+        HKDF_SHA256 => 0x0001,
+        HKDF_SHA384 => 0x0002
+    }
+}
+
+enum_builder! {
+    /// The `EchVersion` TLS protocol enum.  Values in this enum are taken
+    /// from the ECH specification: the only version we support is the one
+    /// standardised, which shares its value with the extension type.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U16
+    EnumName: EchVersion;
+    EnumVal{
+        V18 => 0xfe0d
+    }
+}
+
+enum_builder! {
+    /// The `EchClientHelloType` TLS protocol enum.  Values in this enum are taken
+    /// from the ECH specification.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U8
+    EnumName: EchClientHelloType;
+    EnumVal{
+        ClientHelloOuter => 0x00,
+        ClientHelloInner => 0x01
+    }
+}
+
+enum_builder! {
+    /// The `HpkeKem` enum.  Values in this enum are taken from RFC9180,
+    /// and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U16
+    EnumName: HpkeKem;
+    EnumVal{
+        DHKEM_P256_HKDF_SHA256 => 0x0010,
+        DHKEM_P384_HKDF_SHA384 => 0x0011,
+        DHKEM_P521_HKDF_SHA512 => 0x0012,
+        DHKEM_X25519_HKDF_SHA256 => 0x0020,
+        DHKEM_X448_HKDF_SHA512 => 0x0021
+    }
+}
+
+enum_builder! {
+    /// The `HpkeKdf` enum.  Values in this enum are taken from RFC9180,
+    /// and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U16
+    EnumName: HpkeKdf;
+    EnumVal{
+        HKDF_SHA256 => 0x0001,
+        HKDF_SHA384 => 0x0002,
+        HKDF_SHA512 => 0x0003
+    }
+}
+
+enum_builder! {
+    /// The `HpkeAead` enum.  Values in this enum are taken from RFC9180,
+    /// and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U16
+    EnumName: HpkeAead;
+    EnumVal{
+        AES_128_GCM => 0x0001,
+        AES_256_GCM => 0x0002,
+        CHACHA20_POLY_1305 => 0x0003,
+        EXPORT_ONLY => 0xffff
+    }
+}
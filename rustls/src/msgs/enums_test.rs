@@ -58,7 +58,7 @@ fn test_enums() {
     test_enum8::<AlertLevel>(AlertLevel::Warning, AlertLevel::Fatal);
     test_enum8::<AlertDescription>(
         AlertDescription::CloseNotify,
-        AlertDescription::NoApplicationProtocol,
+        AlertDescription::EncryptedClientHelloRequired,
     );
     test_enum8::<HeartbeatMessageType>(
         HeartbeatMessageType::Request,
@@ -70,7 +70,7 @@ fn test_enums() {
         NamedCurve::sect163k1,
         NamedCurve::arbitrary_explicit_char2_curves,
     );
-    test_enum16::<NamedGroup>(NamedGroup::secp256r1, NamedGroup::FFDHE8192);
+    test_enum16::<NamedGroup>(NamedGroup::secp256r1, NamedGroup::X25519MLKEM768);
     test_enum16::<CipherSuite>(
         CipherSuite::TLS_NULL_WITH_NULL_NULL,
         CipherSuite::SSL_RSA_FIPS_WITH_3DES_EDE_CBC_SHA,
@@ -91,4 +91,21 @@ fn test_enums() {
         KeyUpdateRequest::UpdateRequested,
     );
     test_enum8::<CertificateStatusType>(CertificateStatusType::OCSP, CertificateStatusType::OCSP);
+    test_enum16::<CertificateCompressionAlgorithm>(
+        CertificateCompressionAlgorithm::Zlib,
+        CertificateCompressionAlgorithm::Zstd,
+    );
+    test_enum8::<CertificateType>(CertificateType::X509, CertificateType::RawPublicKey);
+    test_enum16::<TlsKdf>(TlsKdf::HKDF_SHA256, TlsKdf::HKDF_SHA384);
+    test_enum16::<EchVersion>(EchVersion::V18, EchVersion::V18);
+    test_enum8::<EchClientHelloType>(
+        EchClientHelloType::ClientHelloOuter,
+        EchClientHelloType::ClientHelloInner,
+    );
+    test_enum16::<HpkeKem>(
+        HpkeKem::DHKEM_P256_HKDF_SHA256,
+        HpkeKem::DHKEM_X448_HKDF_SHA512,
+    );
+    test_enum16::<HpkeKdf>(HpkeKdf::HKDF_SHA256, HpkeKdf::HKDF_SHA512);
+    test_enum16::<HpkeAead>(HpkeAead::AES_128_GCM, HpkeAead::EXPORT_ONLY);
 }
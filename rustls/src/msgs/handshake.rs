@@ -1,14 +1,17 @@
+use crate::crypto::CryptoProvider;
 use crate::key;
-use crate::msgs::base::{Payload, PayloadU8, PayloadU16, PayloadU24};
+use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
 use crate::msgs::codec;
 use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::CertificateCompressionAlgorithm;
 use crate::msgs::enums::ECCurveType;
 use crate::msgs::enums::PSKKeyExchangeMode;
-use crate::msgs::enums::{CertificateStatusType, ClientCertificateType};
+use crate::msgs::enums::{CertificateStatusType, CertificateType, ClientCertificateType};
 use crate::msgs::enums::{CipherSuite, Compression, ECPointFormat, ExtensionType};
+use crate::msgs::enums::{EchClientHelloType, EchVersion, HpkeAead, HpkeKdf, HpkeKem};
 use crate::msgs::enums::{HandshakeType, ProtocolVersion};
 use crate::msgs::enums::{HashAlgorithm, ServerNameType, SignatureAlgorithm};
-use crate::msgs::enums::{KeyUpdateRequest, NamedGroup, SignatureScheme};
+use crate::msgs::enums::{KeyUpdateRequest, NamedGroup, SignatureScheme, TlsKdf};
 use crate::rand;
 
 #[cfg(feature = "logging")]
@@ -77,9 +80,9 @@ impl Codec for Random {
 }
 
 impl Random {
-    pub fn new() -> Result<Self, rand::GetRandomFailed> {
+    pub fn new(provider: &dyn CryptoProvider) -> Result<Self, rand::GetRandomFailed> {
         let mut data = [0u8; 32];
-        rand::fill_random(&mut data)?;
+        rand::fill_random(provider, &mut data)?;
         Ok(Self(data))
     }
 
@@ -149,9 +152,9 @@ impl Codec for SessionID {
 }
 
 impl SessionID {
-    pub fn random() -> Result<Self, rand::GetRandomFailed> {
+    pub fn random(provider: &dyn CryptoProvider) -> Result<Self, rand::GetRandomFailed> {
         let mut data = [0u8; 32];
-        rand::fill_random(&mut data)?;
+        rand::fill_random(provider, &mut data)?;
         Ok(Self { data, len: 32 })
     }
 
@@ -470,6 +473,38 @@ impl Codec for PresharedKeyOffer {
     }
 }
 
+// --- RFC9258 imported PSK identity ---
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedIdentity {
+    pub external_identity: PayloadU16,
+    pub context: PayloadU16,
+    pub target_protocol: ProtocolVersion,
+    pub target_kdf: TlsKdf,
+}
+
+impl Codec for ImportedIdentity {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.external_identity.encode(bytes);
+        self.context.encode(bytes);
+        self.target_protocol.encode(bytes);
+        self.target_kdf.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        let external_identity = PayloadU16::read(r)?;
+        if external_identity.0.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            external_identity,
+            context: PayloadU16::read(r)?,
+            target_protocol: ProtocolVersion::read(r)?,
+            target_kdf: TlsKdf::read(r)?,
+        })
+    }
+}
+
 // --- RFC6066 certificate status request ---
 type ResponderIDs = VecU16OfPayloadU16;
 
@@ -547,6 +582,11 @@ pub type SCTList = VecU16OfPayloadU16;
 declare_u8_vec!(PSKKeyExchangeModes, PSKKeyExchangeMode);
 declare_u16_vec!(KeyShareEntries, KeyShareEntry);
 declare_u8_vec!(ProtocolVersions, ProtocolVersion);
+declare_u8_vec!(
+    CertificateCompressionAlgorithms,
+    CertificateCompressionAlgorithm
+);
+declare_u8_vec!(CertificateTypes, CertificateType);
 
 #[derive(Clone, Debug)]
 pub enum ClientExtension {
@@ -568,6 +608,13 @@ pub enum ClientExtension {
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    CertificateCompressionAlgorithms(CertificateCompressionAlgorithms),
+    DelegatedCredentialRequest(SupportedSignatureSchemes),
+    ClientCertTypes(CertificateTypes),
+    ServerCertTypes(CertificateTypes),
+    EncryptedClientHello(EncryptedClientHello),
+    EncryptedClientHelloOuterExtensions(EchOuterExtensions),
+    Padding(usize),
     Unknown(UnknownExtension),
 }
 
@@ -593,6 +640,15 @@ impl ClientExtension {
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::CertificateCompressionAlgorithms(_) => ExtensionType::CompressCertificate,
+            Self::DelegatedCredentialRequest(_) => ExtensionType::DelegatedCredential,
+            Self::ClientCertTypes(_) => ExtensionType::ClientCertificateType,
+            Self::ServerCertTypes(_) => ExtensionType::ServerCertificateType,
+            Self::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
+            Self::EncryptedClientHelloOuterExtensions(_) => {
+                ExtensionType::EncryptedClientHelloOuterExtensions
+            }
+            Self::Padding(_) => ExtensionType::Padding,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -623,6 +679,12 @@ impl Codec for ClientExtension {
             Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
                 sub.extend_from_slice(r)
             }
+            Self::CertificateCompressionAlgorithms(ref r) => r.encode(&mut sub),
+            Self::DelegatedCredentialRequest(ref r) => r.encode(&mut sub),
+            Self::ClientCertTypes(ref r) | Self::ServerCertTypes(ref r) => r.encode(&mut sub),
+            Self::EncryptedClientHello(ref r) => r.encode(&mut sub),
+            Self::EncryptedClientHelloOuterExtensions(ref r) => r.encode(&mut sub),
+            Self::Padding(len) => sub.resize(len, 0),
             Self::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -678,10 +740,35 @@ impl Codec for ClientExtension {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData if !sub.any_left() => Self::EarlyData,
+            ExtensionType::CompressCertificate => {
+                let algs = CertificateCompressionAlgorithms::read(&mut sub)?;
+                Self::CertificateCompressionAlgorithms(algs)
+            }
+            ExtensionType::DelegatedCredential => {
+                let schemes = SupportedSignatureSchemes::read(&mut sub)?;
+                Self::DelegatedCredentialRequest(schemes)
+            }
+            ExtensionType::ClientCertificateType => {
+                Self::ClientCertTypes(CertificateTypes::read(&mut sub)?)
+            }
+            ExtensionType::ServerCertificateType => {
+                Self::ServerCertTypes(CertificateTypes::read(&mut sub)?)
+            }
+            ExtensionType::EncryptedClientHello => {
+                Self::EncryptedClientHello(EncryptedClientHello::read(&mut sub)?)
+            }
+            ExtensionType::EncryptedClientHelloOuterExtensions => {
+                Self::EncryptedClientHelloOuterExtensions(EchOuterExtensions::read(&mut sub)?)
+            }
+            ExtensionType::Padding => Self::Padding(sub.rest().len()),
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
-        if sub.any_left() { None } else { Some(ext) }
+        if sub.any_left() {
+            None
+        } else {
+            Some(ext)
+        }
     }
 }
 
@@ -728,6 +815,9 @@ pub enum ServerExtension {
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    ClientCertType(CertificateType),
+    ServerCertType(CertificateType),
+    EncryptedClientHello(EchConfigList),
     Unknown(UnknownExtension),
 }
 
@@ -748,6 +838,9 @@ impl ServerExtension {
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::ClientCertType(_) => ExtensionType::ClientCertificateType,
+            Self::ServerCertType(_) => ExtensionType::ServerCertificateType,
+            Self::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -774,6 +867,8 @@ impl Codec for ServerExtension {
             Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
                 sub.extend_from_slice(r)
             }
+            Self::ClientCertType(ref r) | Self::ServerCertType(ref r) => r.encode(&mut sub),
+            Self::EncryptedClientHello(ref r) => r.encode(&mut sub),
             Self::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -812,10 +907,23 @@ impl Codec for ServerExtension {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData => Self::EarlyData,
+            ExtensionType::ClientCertificateType => {
+                Self::ClientCertType(CertificateType::read(&mut sub)?)
+            }
+            ExtensionType::ServerCertificateType => {
+                Self::ServerCertType(CertificateType::read(&mut sub)?)
+            }
+            ExtensionType::EncryptedClientHello => {
+                Self::EncryptedClientHello(EchConfigList::read(&mut sub)?)
+            }
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
-        if sub.any_left() { None } else { Some(ext) }
+        if sub.any_left() {
+            None
+        } else {
+            Some(ext)
+        }
     }
 }
 
@@ -1019,11 +1127,38 @@ impl ClientHelloPayload {
             .unwrap_or(false)
     }
 
+    /// Add a padding extension (RFC7685) if this hello, as a handshake
+    /// message, would otherwise be between 256 and 511 bytes long: some
+    /// middleboxes hang on hellos of that size.
+    ///
+    /// Any PSK binder must already be present, at its final length: the
+    /// padding goes before the PSK extension, which must be last.
+    pub fn add_padding(&mut self) {
+        // The handshake message header is four bytes.
+        let unpadded_len = 4 + self.get_encoding().len();
+        if !(0x100..0x200).contains(&unpadded_len) {
+            return;
+        }
+
+        // The extension's own type and length take four bytes.  If that
+        // leaves no room, send one byte of padding to get past 511.
+        let padding_len = match 0x200 - unpadded_len {
+            len if len > 4 => len - 4,
+            _ => 1,
+        };
 
-    pub fn set_psk_binder(&mut self, binder: impl Into<Vec<u8>>) {
+        let position = match self.extensions.last() {
+            Some(ClientExtension::PresharedKey(_)) => self.extensions.len() - 1,
+            _ => self.extensions.len(),
+        };
+        self.extensions
+            .insert(position, ClientExtension::Padding(padding_len));
+    }
+
+    pub fn set_psk_binder(&mut self, index: usize, binder: impl Into<Vec<u8>>) {
         let last_extension = self.extensions.last_mut();
         if let Some(ClientExtension::PresharedKey(ref mut offer)) = last_extension {
-            offer.binders[0] = PresharedKeyBinder::new(binder.into());
+            offer.binders[index] = PresharedKeyBinder::new(binder.into());
         }
     }
 
@@ -1036,13 +1171,54 @@ impl ClientHelloPayload {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
+
+    pub fn get_cert_compression_extension(&self) -> Option<&CertificateCompressionAlgorithms> {
+        let ext = self.find_extension(ExtensionType::CompressCertificate)?;
+        match *ext {
+            ClientExtension::CertificateCompressionAlgorithms(ref algs) => Some(algs),
+            _ => None,
+        }
+    }
+
+    pub fn get_delegated_credential_extension(&self) -> Option<&SupportedSignatureSchemes> {
+        let ext = self.find_extension(ExtensionType::DelegatedCredential)?;
+        match *ext {
+            ClientExtension::DelegatedCredentialRequest(ref schemes) => Some(schemes),
+            _ => None,
+        }
+    }
+
+    pub fn get_client_cert_types_extension(&self) -> Option<&CertificateTypes> {
+        let ext = self.find_extension(ExtensionType::ClientCertificateType)?;
+        match *ext {
+            ClientExtension::ClientCertTypes(ref types) => Some(types),
+            _ => None,
+        }
+    }
+
+    pub fn get_server_cert_types_extension(&self) -> Option<&CertificateTypes> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ClientExtension::ServerCertTypes(ref types) => Some(types),
+            _ => None,
+        }
+    }
+
+    pub fn get_ech_extension(&self) -> Option<&EncryptedClientHello> {
+        let ext = self.find_extension(ExtensionType::EncryptedClientHello)?;
+        match *ext {
+            ClientExtension::EncryptedClientHello(ref ech) => Some(ech),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum HelloRetryExtension {
     KeyShare(NamedGroup),
     Cookie(PayloadU16),
     SupportedVersions(ProtocolVersion),
+    EncryptedClientHello(Payload),
     Unknown(UnknownExtension),
 }
 
@@ -1052,6 +1228,7 @@ impl HelloRetryExtension {
             HelloRetryExtension::KeyShare(_) => ExtensionType::KeyShare,
             HelloRetryExtension::Cookie(_) => ExtensionType::Cookie,
             HelloRetryExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            HelloRetryExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
             HelloRetryExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1066,6 +1243,7 @@ impl Codec for HelloRetryExtension {
             HelloRetryExtension::KeyShare(ref r) => r.encode(&mut sub),
             HelloRetryExtension::Cookie(ref r) => r.encode(&mut sub),
             HelloRetryExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+            HelloRetryExtension::EncryptedClientHello(ref r) => r.encode(&mut sub),
             HelloRetryExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1084,14 +1262,21 @@ impl Codec for HelloRetryExtension {
             ExtensionType::SupportedVersions => {
                 Self::SupportedVersions(ProtocolVersion::read(&mut sub)?)
             }
+            ExtensionType::EncryptedClientHello => {
+                Self::EncryptedClientHello(Payload::new(sub.take(8)?))
+            }
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
-        if sub.any_left() { None } else { Some(ext) }
+        if sub.any_left() {
+            None
+        } else {
+            Some(ext)
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct HelloRetryRequest {
     pub legacy_version: ProtocolVersion,
     pub session_id: SessionID,
@@ -1150,6 +1335,7 @@ impl HelloRetryRequest {
             ext.get_type() != ExtensionType::KeyShare
                 && ext.get_type() != ExtensionType::SupportedVersions
                 && ext.get_type() != ExtensionType::Cookie
+                && ext.get_type() != ExtensionType::EncryptedClientHello
         })
     }
 
@@ -1182,6 +1368,14 @@ impl HelloRetryRequest {
             _ => None,
         }
     }
+
+    pub fn get_ech_confirmation(&self) -> Option<&Payload> {
+        let ext = self.find_extension(ExtensionType::EncryptedClientHello)?;
+        match *ext {
+            HelloRetryExtension::EncryptedClientHello(ref confirmation) => Some(confirmation),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1221,7 +1415,11 @@ impl Codec for ServerHelloPayload {
             extensions,
         };
 
-        if r.any_left() { None } else { Some(ret) }
+        if r.any_left() {
+            None
+        } else {
+            Some(ret)
+        }
     }
 }
 
@@ -1295,10 +1493,64 @@ impl Codec for CertificatePayload {
 // That's annoying. It means the parsing is not
 // context-free any more.
 
+/// The `Credential` structure of RFC9345: a public key, and how long
+/// (and with which scheme) it may be used to sign handshakes.
+#[derive(Clone, Debug)]
+pub struct Credential {
+    /// Seconds from the end-entity certificate's notBefore time until
+    /// the credential expires.
+    pub valid_time: u32,
+    pub dc_cert_verify_algorithm: SignatureScheme,
+    /// A DER-encoded SubjectPublicKeyInfo.
+    pub public_key: PayloadU24,
+}
+
+impl Codec for Credential {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.valid_time.encode(bytes);
+        self.dc_cert_verify_algorithm
+            .encode(bytes);
+        self.public_key.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            valid_time: u32::read(r)?,
+            dc_cert_verify_algorithm: SignatureScheme::read(r)?,
+            public_key: PayloadU24::read(r)?,
+        })
+    }
+}
+
+/// A `Credential` signed by the end-entity certificate's key (RFC9345).
+#[derive(Clone, Debug)]
+pub struct DelegatedCredentialPayload {
+    pub cred: Credential,
+    pub algorithm: SignatureScheme,
+    pub signature: PayloadU16,
+}
+
+impl Codec for DelegatedCredentialPayload {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.cred.encode(bytes);
+        self.algorithm.encode(bytes);
+        self.signature.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            cred: Credential::read(r)?,
+            algorithm: SignatureScheme::read(r)?,
+            signature: PayloadU16::read(r)?,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum CertificateExtension {
     CertificateStatus(CertificateStatus),
     SignedCertificateTimestamp(SCTList),
+    DelegatedCredential(DelegatedCredentialPayload),
     Unknown(UnknownExtension),
 }
 
@@ -1307,6 +1559,7 @@ impl CertificateExtension {
         match *self {
             CertificateExtension::CertificateStatus(_) => ExtensionType::StatusRequest,
             CertificateExtension::SignedCertificateTimestamp(_) => ExtensionType::SCT,
+            CertificateExtension::DelegatedCredential(_) => ExtensionType::DelegatedCredential,
             CertificateExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1329,6 +1582,13 @@ impl CertificateExtension {
             _ => None,
         }
     }
+
+    pub fn get_delegated_credential(&self) -> Option<&DelegatedCredentialPayload> {
+        match *self {
+            CertificateExtension::DelegatedCredential(ref dc) => Some(dc),
+            _ => None,
+        }
+    }
 }
 
 impl Codec for CertificateExtension {
@@ -1339,6 +1599,7 @@ impl Codec for CertificateExtension {
         match *self {
             CertificateExtension::CertificateStatus(ref r) => r.encode(&mut sub),
             CertificateExtension::SignedCertificateTimestamp(ref r) => r.encode(&mut sub),
+            CertificateExtension::DelegatedCredential(ref r) => r.encode(&mut sub),
             CertificateExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1360,10 +1621,18 @@ impl Codec for CertificateExtension {
                 let scts = SCTList::read(&mut sub)?;
                 Self::SignedCertificateTimestamp(scts)
             }
+            ExtensionType::DelegatedCredential => {
+                let dc = DelegatedCredentialPayload::read(&mut sub)?;
+                Self::DelegatedCredential(dc)
+            }
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
-        if sub.any_left() { None } else { Some(ext) }
+        if sub.any_left() {
+            None
+        } else {
+            Some(ext)
+        }
     }
 }
 
@@ -1414,7 +1683,9 @@ impl CertificateEntry {
 
     pub fn has_unknown_extension(&self) -> bool {
         self.exts.iter().any(|ext| {
-            ext.get_type() != ExtensionType::StatusRequest && ext.get_type() != ExtensionType::SCT
+            ext.get_type() != ExtensionType::StatusRequest
+                && ext.get_type() != ExtensionType::SCT
+                && ext.get_type() != ExtensionType::DelegatedCredential
         })
     }
 
@@ -1431,6 +1702,13 @@ impl CertificateEntry {
             .find(|ext| ext.get_type() == ExtensionType::SCT)
             .and_then(CertificateExtension::get_sct_list)
     }
+
+    pub fn get_delegated_credential(&self) -> Option<&DelegatedCredentialPayload> {
+        self.exts
+            .iter()
+            .find(|ext| ext.get_type() == ExtensionType::DelegatedCredential)
+            .and_then(CertificateExtension::get_delegated_credential)
+    }
 }
 
 #[derive(Debug)]
@@ -1506,6 +1784,24 @@ impl CertificatePayloadTLS13 {
             .cloned()
     }
 
+    pub fn get_end_entity_delegated_credential(&self) -> Option<DelegatedCredentialPayload> {
+        self.entries
+            .first()
+            .and_then(CertificateEntry::get_delegated_credential)
+            .cloned()
+    }
+
+    pub fn any_intermediate_has_delegated_credential(&self) -> bool {
+        self.entries
+            .iter()
+            .skip(1)
+            .any(|entry| {
+                entry
+                    .get_delegated_credential()
+                    .is_some()
+            })
+    }
+
     pub fn convert(&self) -> CertificatePayload {
         let mut ret = Vec::new();
         for entry in &self.entries {
@@ -1515,7 +1811,7 @@ impl CertificatePayloadTLS13 {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum KeyExchangeAlgorithm {
     BulkOnly,
     DH,
@@ -1636,6 +1932,75 @@ impl Codec for ServerECDHParams {
     }
 }
 
+#[derive(Debug)]
+pub struct ClientDHParams {
+    pub public: PayloadU16,
+}
+
+impl Codec for ClientDHParams {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.public.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        let pb = PayloadU16::read(r)?;
+        Some(Self { public: pb })
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerDHParams {
+    pub dh_p: PayloadU16,
+    pub dh_g: PayloadU16,
+    pub dh_ys: PayloadU16,
+}
+
+impl ServerDHParams {
+    pub fn new(p: &[u8], g: &[u8], pubkey: &[u8]) -> Self {
+        Self {
+            dh_p: PayloadU16::new(p.to_vec()),
+            dh_g: PayloadU16::new(g.to_vec()),
+            dh_ys: PayloadU16::new(pubkey.to_vec()),
+        }
+    }
+}
+
+impl Codec for ServerDHParams {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.dh_p.encode(bytes);
+        self.dh_g.encode(bytes);
+        self.dh_ys.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        let dh_p = PayloadU16::read(r)?;
+        let dh_g = PayloadU16::read(r)?;
+        let dh_ys = PayloadU16::read(r)?;
+
+        Some(Self { dh_p, dh_g, dh_ys })
+    }
+}
+
+#[derive(Debug)]
+pub struct DHEServerKeyExchange {
+    pub params: ServerDHParams,
+    pub dss: DigitallySignedStruct,
+}
+
+impl Codec for DHEServerKeyExchange {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.params.encode(bytes);
+        self.dss.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        let params = ServerDHParams::read(r)?;
+        let dss = DigitallySignedStruct::read(r)?;
+
+        Some(Self { params, dss })
+    }
+}
+
 #[derive(Debug)]
 pub struct ECDHEServerKeyExchange {
     pub params: ServerECDHParams,
@@ -1659,6 +2024,7 @@ impl Codec for ECDHEServerKeyExchange {
 #[derive(Debug)]
 pub enum ServerKeyExchangePayload {
     ECDHE(ECDHEServerKeyExchange),
+    DHE(DHEServerKeyExchange),
     Unknown(Payload),
 }
 
@@ -1666,6 +2032,7 @@ impl Codec for ServerKeyExchangePayload {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match *self {
             ServerKeyExchangePayload::ECDHE(ref x) => x.encode(bytes),
+            ServerKeyExchangePayload::DHE(ref x) => x.encode(bytes),
             ServerKeyExchangePayload::Unknown(ref x) => x.encode(bytes),
         }
     }
@@ -1678,12 +2045,15 @@ impl Codec for ServerKeyExchangePayload {
 }
 
 impl ServerKeyExchangePayload {
-    pub fn unwrap_given_kxa(&self, kxa: &KeyExchangeAlgorithm) -> Option<ECDHEServerKeyExchange> {
+    pub fn unwrap_given_kxa(&self, kxa: &KeyExchangeAlgorithm) -> Option<Self> {
         if let ServerKeyExchangePayload::Unknown(ref unk) = *self {
             let mut rd = Reader::init(&unk.0);
 
             let result = match *kxa {
-                KeyExchangeAlgorithm::ECDHE => ECDHEServerKeyExchange::read(&mut rd),
+                KeyExchangeAlgorithm::ECDHE => {
+                    ECDHEServerKeyExchange::read(&mut rd).map(Self::ECDHE)
+                }
+                KeyExchangeAlgorithm::DHE => DHEServerKeyExchange::read(&mut rd).map(Self::DHE),
                 _ => None,
             };
 
@@ -1748,6 +2118,30 @@ pub trait HasServerExtensions {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
+
+    fn get_ech_retry_configs(&self) -> Option<&EchConfigList> {
+        let ext = self.find_extension(ExtensionType::EncryptedClientHello)?;
+        match *ext {
+            ServerExtension::EncryptedClientHello(ref configs) => Some(configs),
+            _ => None,
+        }
+    }
+
+    fn get_client_cert_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ClientCertificateType)?;
+        match *ext {
+            ServerExtension::ClientCertType(typ) => Some(typ),
+            _ => None,
+        }
+    }
+
+    fn get_server_cert_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ServerExtension::ServerCertType(typ) => Some(typ),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for EncryptedExtensions {
@@ -1797,6 +2191,7 @@ impl Codec for CertificateRequestPayload {
 pub enum CertReqExtension {
     SignatureAlgorithms(SupportedSignatureSchemes),
     AuthorityNames(DistinguishedNames),
+    CertificateCompressionAlgorithms(CertificateCompressionAlgorithms),
     Unknown(UnknownExtension),
 }
 
@@ -1805,6 +2200,9 @@ impl CertReqExtension {
         match *self {
             CertReqExtension::SignatureAlgorithms(_) => ExtensionType::SignatureAlgorithms,
             CertReqExtension::AuthorityNames(_) => ExtensionType::CertificateAuthorities,
+            CertReqExtension::CertificateCompressionAlgorithms(_) => {
+                ExtensionType::CompressCertificate
+            }
             CertReqExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1818,6 +2216,7 @@ impl Codec for CertReqExtension {
         match *self {
             CertReqExtension::SignatureAlgorithms(ref r) => r.encode(&mut sub),
             CertReqExtension::AuthorityNames(ref r) => r.encode(&mut sub),
+            CertReqExtension::CertificateCompressionAlgorithms(ref r) => r.encode(&mut sub),
             CertReqExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1842,10 +2241,18 @@ impl Codec for CertReqExtension {
                 let cas = DistinguishedNames::read(&mut sub)?;
                 Self::AuthorityNames(cas)
             }
+            ExtensionType::CompressCertificate => {
+                let algs = CertificateCompressionAlgorithms::read(&mut sub)?;
+                Self::CertificateCompressionAlgorithms(algs)
+            }
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
-        if sub.any_left() { None } else { Some(ext) }
+        if sub.any_left() {
+            None
+        } else {
+            Some(ext)
+        }
     }
 }
 
@@ -1896,6 +2303,14 @@ impl CertificateRequestPayloadTLS13 {
             _ => None,
         }
     }
+
+    pub fn get_cert_compression_extension(&self) -> Option<&CertificateCompressionAlgorithms> {
+        let ext = self.find_extension(ExtensionType::CompressCertificate)?;
+        match *ext {
+            CertReqExtension::CertificateCompressionAlgorithms(ref algs) => Some(algs),
+            _ => None,
+        }
+    }
 }
 
 // -- NewSessionTicket --
@@ -1971,7 +2386,11 @@ impl Codec for NewSessionTicketExtension {
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
-        if sub.any_left() { None } else { Some(ext) }
+        if sub.any_left() {
+            None
+        } else {
+            Some(ext)
+        }
     }
 }
 
@@ -2076,6 +2495,244 @@ impl CertificateStatus {
     }
 }
 
+// -- CompressedCertificate (RFC8879) --
+#[derive(Debug)]
+pub struct CompressedCertificatePayload {
+    pub alg: CertificateCompressionAlgorithm,
+    pub uncompressed_len: u32,
+    pub compressed: PayloadU24,
+}
+
+impl Codec for CompressedCertificatePayload {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.alg.encode(bytes);
+        codec::u24(self.uncompressed_len).encode(bytes);
+        self.compressed.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            alg: CertificateCompressionAlgorithm::read(r)?,
+            uncompressed_len: codec::u24::read(r)?.0,
+            compressed: PayloadU24::read(r)?,
+        })
+    }
+}
+
+// -- Encrypted Client Hello --
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HpkeSymmetricCipherSuite {
+    pub kdf_id: HpkeKdf,
+    pub aead_id: HpkeAead,
+}
+
+impl Codec for HpkeSymmetricCipherSuite {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.kdf_id.encode(bytes);
+        self.aead_id.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            kdf_id: HpkeKdf::read(r)?,
+            aead_id: HpkeAead::read(r)?,
+        })
+    }
+}
+
+declare_u16_vec!(HpkeSymmetricCipherSuites, HpkeSymmetricCipherSuite);
+
+#[derive(Clone, Debug)]
+pub struct HpkeKeyConfig {
+    pub config_id: u8,
+    pub kem_id: HpkeKem,
+    pub public_key: PayloadU16,
+    pub symmetric_cipher_suites: HpkeSymmetricCipherSuites,
+}
+
+impl Codec for HpkeKeyConfig {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.config_id.encode(bytes);
+        self.kem_id.encode(bytes);
+        self.public_key.encode(bytes);
+        self.symmetric_cipher_suites
+            .encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            config_id: u8::read(r)?,
+            kem_id: HpkeKem::read(r)?,
+            public_key: PayloadU16::read(r)?,
+            symmetric_cipher_suites: HpkeSymmetricCipherSuites::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EchConfigExtension {
+    pub typ: u16,
+    pub payload: PayloadU16,
+}
+
+impl EchConfigExtension {
+    /// Clients must not use an ECHConfig with a mandatory
+    /// extension they don't understand; we understand none.
+    pub fn is_mandatory(&self) -> bool {
+        self.typ & 0x8000 != 0
+    }
+}
+
+impl Codec for EchConfigExtension {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.typ.encode(bytes);
+        self.payload.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            typ: u16::read(r)?,
+            payload: PayloadU16::read(r)?,
+        })
+    }
+}
+
+declare_u16_vec!(EchConfigExtensions, EchConfigExtension);
+
+#[derive(Clone, Debug)]
+pub struct EchConfigContents {
+    pub key_config: HpkeKeyConfig,
+    pub maximum_name_length: u8,
+    pub public_name: PayloadU8,
+    pub extensions: EchConfigExtensions,
+}
+
+impl Codec for EchConfigContents {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.key_config.encode(bytes);
+        self.maximum_name_length.encode(bytes);
+        self.public_name.encode(bytes);
+        self.extensions.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            key_config: HpkeKeyConfig::read(r)?,
+            maximum_name_length: u8::read(r)?,
+            public_name: PayloadU8::read(r)?,
+            extensions: EchConfigExtensions::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum EchConfigPayload {
+    V18(EchConfigContents),
+    Unknown {
+        version: EchVersion,
+        contents: Payload,
+    },
+}
+
+impl Codec for EchConfigPayload {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let mut sub: Vec<u8> = Vec::new();
+        let version = match *self {
+            Self::V18(ref r) => {
+                r.encode(&mut sub);
+                EchVersion::V18
+            }
+            Self::Unknown {
+                version,
+                ref contents,
+            } => {
+                contents.encode(&mut sub);
+                version
+            }
+        };
+
+        version.encode(bytes);
+        (sub.len() as u16).encode(bytes);
+        bytes.append(&mut sub);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        let version = EchVersion::read(r)?;
+        let len = u16::read(r)? as usize;
+        let mut sub = r.sub(len)?;
+
+        let config = match version {
+            EchVersion::V18 => Self::V18(EchConfigContents::read(&mut sub)?),
+            _ => Self::Unknown {
+                version,
+                contents: Payload::read(&mut sub),
+            },
+        };
+
+        if sub.any_left() {
+            None
+        } else {
+            Some(config)
+        }
+    }
+}
+
+declare_u16_vec!(EchConfigList, EchConfigPayload);
+
+#[derive(Clone, Debug)]
+pub struct EchOuterPayload {
+    pub cipher_suite: HpkeSymmetricCipherSuite,
+    pub config_id: u8,
+    pub enc: PayloadU16,
+    pub payload: PayloadU16,
+}
+
+impl Codec for EchOuterPayload {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.cipher_suite.encode(bytes);
+        self.config_id.encode(bytes);
+        self.enc.encode(bytes);
+        self.payload.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            cipher_suite: HpkeSymmetricCipherSuite::read(r)?,
+            config_id: u8::read(r)?,
+            enc: PayloadU16::read(r)?,
+            payload: PayloadU16::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum EncryptedClientHello {
+    Outer(EchOuterPayload),
+    Inner,
+}
+
+impl Codec for EncryptedClientHello {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            Self::Outer(ref r) => {
+                EchClientHelloType::ClientHelloOuter.encode(bytes);
+                r.encode(bytes);
+            }
+            Self::Inner => EchClientHelloType::ClientHelloInner.encode(bytes),
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        match EchClientHelloType::read(r)? {
+            EchClientHelloType::ClientHelloOuter => Some(Self::Outer(EchOuterPayload::read(r)?)),
+            EchClientHelloType::ClientHelloInner => Some(Self::Inner),
+            _ => None,
+        }
+    }
+}
+
+declare_u8_vec!(EchOuterExtensions, ExtensionType);
+
 #[derive(Debug)]
 pub enum HandshakePayload {
     HelloRequest,
@@ -2098,6 +2755,7 @@ pub enum HandshakePayload {
     KeyUpdate(KeyUpdateRequest),
     Finished(Payload),
     CertificateStatus(CertificateStatus),
+    CompressedCertificate(CompressedCertificatePayload),
     MessageHash(Payload),
     Unknown(Payload),
 }
@@ -2125,6 +2783,7 @@ impl HandshakePayload {
             HandshakePayload::KeyUpdate(ref x) => x.encode(bytes),
             HandshakePayload::Finished(ref x) => x.encode(bytes),
             HandshakePayload::CertificateStatus(ref x) => x.encode(bytes),
+            HandshakePayload::CompressedCertificate(ref x) => x.encode(bytes),
             HandshakePayload::MessageHash(ref x) => x.encode(bytes),
             HandshakePayload::Unknown(ref x) => x.encode(bytes),
         }
@@ -2205,6 +2864,12 @@ impl HandshakeMessagePayload {
             HandshakeType::ClientKeyExchange => {
                 HandshakePayload::ClientKeyExchange(Payload::read(&mut sub))
             }
+            HandshakeType::EndOfEarlyData if vers == ProtocolVersion::TLSv1_3 => {
+                if sub.any_left() {
+                    return None;
+                }
+                HandshakePayload::EndOfEarlyData
+            }
             HandshakeType::CertificateRequest if vers == ProtocolVersion::TLSv1_3 => {
                 let p = CertificateRequestPayloadTLS13::read(&mut sub)?;
                 HandshakePayload::CertificateRequestTLS13(p)
@@ -2234,6 +2899,10 @@ impl HandshakeMessagePayload {
             HandshakeType::CertificateStatus => {
                 HandshakePayload::CertificateStatus(CertificateStatus::read(&mut sub)?)
             }
+            HandshakeType::CompressedCertificate if vers == ProtocolVersion::TLSv1_3 => {
+                let p = CompressedCertificatePayload::read(&mut sub)?;
+                HandshakePayload::CompressedCertificate(p)
+            }
             HandshakeType::MessageHash => {
                 // does not appear on the wire
                 return None;
@@ -2259,6 +2928,13 @@ impl HandshakeMessagePayload {
         }
     }
 
+    pub fn build_key_update_request() -> Self {
+        Self {
+            typ: HandshakeType::KeyUpdate,
+            payload: HandshakePayload::KeyUpdate(KeyUpdateRequest::UpdateRequested),
+        }
+    }
+
     pub fn get_encoding_for_binder_signing(&self) -> Vec<u8> {
         let mut ret = self.get_encoding();
 
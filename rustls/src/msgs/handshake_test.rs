@@ -257,6 +257,23 @@ fn can_roundtrip_psk_offer() {
     assert_eq!(psko.get_encoding(), bytes.to_vec());
 }
 
+#[test]
+fn can_roundtrip_imported_identity() {
+    let bytes = [0, 2, 0x99, 0x98, 0, 1, 0x42, 0x03, 0x04, 0x00, 0x02];
+    let id = ImportedIdentity::read(&mut Reader::init(&bytes)).unwrap();
+    println!("{:?}", id);
+
+    assert_eq!(id.external_identity.0, vec![0x99, 0x98]);
+    assert_eq!(id.context.0, vec![0x42]);
+    assert_eq!(id.target_protocol, ProtocolVersion::TLSv1_3);
+    assert_eq!(id.target_kdf, TlsKdf::HKDF_SHA384);
+    assert_eq!(id.get_encoding(), bytes.to_vec());
+
+    // The external identity may not be empty.
+    let bytes = [0, 0, 0, 0, 0x03, 0x04, 0x00, 0x01];
+    assert!(ImportedIdentity::read(&mut Reader::init(&bytes)).is_none());
+}
+
 #[test]
 fn can_roundtrip_certstatusreq_for_ocsp() {
     let ext = ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp());
@@ -390,6 +407,29 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
             ClientExtension::SignedCertificateTimestampRequest,
             ClientExtension::TransportParameters(vec![1, 2, 3]),
+            ClientExtension::CertificateCompressionAlgorithms(vec![
+                CertificateCompressionAlgorithm::Zlib,
+            ]),
+            ClientExtension::DelegatedCredentialRequest(vec![SignatureScheme::ED25519]),
+            ClientExtension::ClientCertTypes(vec![CertificateType::RawPublicKey]),
+            ClientExtension::ServerCertTypes(vec![
+                CertificateType::RawPublicKey,
+                CertificateType::X509,
+            ]),
+            ClientExtension::EncryptedClientHello(EncryptedClientHello::Outer(EchOuterPayload {
+                cipher_suite: HpkeSymmetricCipherSuite {
+                    kdf_id: HpkeKdf::HKDF_SHA256,
+                    aead_id: HpkeAead::AES_128_GCM,
+                },
+                config_id: 1,
+                enc: PayloadU16(vec![1, 2, 3]),
+                payload: PayloadU16(vec![4, 5, 6]),
+            })),
+            ClientExtension::EncryptedClientHelloOuterExtensions(vec![
+                ExtensionType::KeyShare,
+                ExtensionType::SignatureAlgorithms,
+            ]),
+            ClientExtension::Padding(3),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -472,7 +512,9 @@ fn test_truncated_client_extension_is_detected() {
 
         // these extension types don't have any internal encoding that rustls validates:
         match ext.get_type() {
-            ExtensionType::TransportParameters | ExtensionType::Unknown(_) => {
+            ExtensionType::TransportParameters
+            | ExtensionType::Padding
+            | ExtensionType::Unknown(_) => {
                 continue;
             }
             _ => {}
@@ -575,6 +617,41 @@ fn client_get_psk_modes() {
     });
 }
 
+#[test]
+fn client_get_cert_compression_extension() {
+    test_client_extension_getter(ExtensionType::CompressCertificate, |chp| {
+        chp.get_cert_compression_extension()
+            .is_some()
+    });
+}
+
+#[test]
+fn client_get_ech_extension() {
+    test_client_extension_getter(ExtensionType::EncryptedClientHello, |chp| {
+        chp.get_ech_extension().is_some()
+    });
+}
+
+#[test]
+fn client_get_delegated_credential_extension() {
+    test_client_extension_getter(ExtensionType::DelegatedCredential, |chp| {
+        chp.get_delegated_credential_extension()
+            .is_some()
+    });
+}
+
+#[test]
+fn client_get_cert_types_extensions() {
+    test_client_extension_getter(ExtensionType::ClientCertificateType, |chp| {
+        chp.get_client_cert_types_extension()
+            .is_some()
+    });
+    test_client_extension_getter(ExtensionType::ServerCertificateType, |chp| {
+        chp.get_server_cert_types_extension()
+            .is_some()
+    });
+}
+
 #[test]
 fn test_truncated_helloretry_extension_is_detected() {
     let hrr = get_sample_helloretryrequest();
@@ -644,6 +721,13 @@ fn helloretry_get_supported_versions() {
     });
 }
 
+#[test]
+fn helloretry_get_ech_confirmation() {
+    test_helloretry_extension_getter(ExtensionType::EncryptedClientHello, |hrr| {
+        hrr.get_ech_confirmation().is_some()
+    });
+}
+
 #[test]
 fn test_truncated_server_extension_is_detected() {
     let shp = get_sample_serverhellopayload();
@@ -724,6 +808,23 @@ fn server_get_supported_versions() {
     });
 }
 
+#[test]
+fn server_get_ech_retry_configs() {
+    test_server_extension_getter(ExtensionType::EncryptedClientHello, |shp| {
+        shp.get_ech_retry_configs().is_some()
+    });
+}
+
+#[test]
+fn server_get_cert_types() {
+    test_server_extension_getter(ExtensionType::ClientCertificateType, |shp| {
+        shp.get_client_cert_type().is_some()
+    });
+    test_server_extension_getter(ExtensionType::ServerCertificateType, |shp| {
+        shp.get_server_cert_type().is_some()
+    });
+}
+
 fn test_cert_extension_getter(typ: ExtensionType, getter: fn(&CertificateEntry) -> bool) {
     let mut ce = get_sample_certificatepayloadtls13()
         .entries
@@ -755,6 +856,13 @@ fn certentry_get_scts() {
     test_cert_extension_getter(ExtensionType::SCT, |ce| ce.get_scts().is_some());
 }
 
+#[test]
+fn certentry_get_delegated_credential() {
+    test_cert_extension_getter(ExtensionType::DelegatedCredential, |ce| {
+        ce.get_delegated_credential().is_some()
+    });
+}
+
 fn get_sample_serverhellopayload() -> ServerHelloPayload {
     ServerHelloPayload {
         legacy_version: ProtocolVersion::TLSv1_2,
@@ -775,6 +883,9 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::SignedCertificateTimestamp(vec![PayloadU16(vec![0])]),
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
             ServerExtension::TransportParameters(vec![1, 2, 3]),
+            ServerExtension::ClientCertType(CertificateType::RawPublicKey),
+            ServerExtension::ServerCertType(CertificateType::X509),
+            ServerExtension::EncryptedClientHello(vec![get_sample_echconfig()]),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -795,6 +906,50 @@ fn can_clone_all_serverextensions() {
         .clone();
 }
 
+fn get_sample_echconfig() -> EchConfigPayload {
+    EchConfigPayload::V18(EchConfigContents {
+        key_config: HpkeKeyConfig {
+            config_id: 1,
+            kem_id: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+            public_key: PayloadU16(vec![1, 2, 3]),
+            symmetric_cipher_suites: vec![HpkeSymmetricCipherSuite {
+                kdf_id: HpkeKdf::HKDF_SHA256,
+                aead_id: HpkeAead::CHACHA20_POLY_1305,
+            }],
+        },
+        maximum_name_length: 0,
+        public_name: PayloadU8(b"example.com".to_vec()),
+        extensions: vec![],
+    })
+}
+
+#[test]
+fn can_roundtrip_echconfig_list() {
+    let list: EchConfigList = vec![
+        get_sample_echconfig(),
+        EchConfigPayload::Unknown {
+            version: EchVersion::Unknown(0xfe0a),
+            contents: Payload(vec![1, 2, 3]),
+        },
+    ];
+    let enc = list.get_encoding();
+    let read = EchConfigList::read_bytes(&enc).unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!(read.get_encoding(), enc);
+    assert!(EchConfigList::read_bytes(&enc[..enc.len() - 1]).is_none());
+}
+
+#[test]
+fn can_roundtrip_inner_ech_extension() {
+    let enc = EncryptedClientHello::Inner.get_encoding();
+    assert_eq!(enc, vec![0x01]);
+    assert!(matches!(
+        EncryptedClientHello::read_bytes(&enc),
+        Some(EncryptedClientHello::Inner)
+    ));
+    assert!(EncryptedClientHello::read_bytes(&[0x02]).is_none());
+}
+
 fn get_sample_helloretryrequest() -> HelloRetryRequest {
     HelloRetryRequest {
         legacy_version: ProtocolVersion::TLSv1_2,
@@ -804,6 +959,7 @@ fn get_sample_helloretryrequest() -> HelloRetryRequest {
             HelloRetryExtension::KeyShare(NamedGroup::X25519),
             HelloRetryExtension::Cookie(PayloadU16(vec![0])),
             HelloRetryExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            HelloRetryExtension::EncryptedClientHello(Payload(vec![0; 8])),
             HelloRetryExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -822,6 +978,15 @@ fn get_sample_certificatepayloadtls13() -> CertificatePayloadTLS13 {
                     ocsp_response: PayloadU24(vec![1, 2, 3]),
                 }),
                 CertificateExtension::SignedCertificateTimestamp(vec![PayloadU16(vec![0])]),
+                CertificateExtension::DelegatedCredential(DelegatedCredentialPayload {
+                    cred: Credential {
+                        valid_time: 1234,
+                        dc_cert_verify_algorithm: SignatureScheme::ED25519,
+                        public_key: PayloadU24(vec![1, 2, 3]),
+                    },
+                    algorithm: SignatureScheme::ECDSA_NISTP256_SHA256,
+                    signature: PayloadU16(vec![4, 5, 6]),
+                }),
                 CertificateExtension::Unknown(UnknownExtension {
                     typ: ExtensionType::Unknown(12345),
                     payload: Payload(vec![1, 2, 3]),
@@ -865,6 +1030,9 @@ fn get_sample_certificaterequestpayloadtls13() -> CertificateRequestPayloadTLS13
         extensions: vec![
             CertReqExtension::SignatureAlgorithms(vec![SignatureScheme::ECDSA_NISTP256_SHA256]),
             CertReqExtension::AuthorityNames(vec![PayloadU16(vec![1, 2, 3])]),
+            CertReqExtension::CertificateCompressionAlgorithms(vec![
+                CertificateCompressionAlgorithm::Brotli,
+            ]),
             CertReqExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -903,6 +1071,14 @@ fn get_sample_certificatestatus() -> CertificateStatus {
     }
 }
 
+fn get_sample_compressedcertificatepayload() -> CompressedCertificatePayload {
+    CompressedCertificatePayload {
+        alg: CertificateCompressionAlgorithm::Zstd,
+        uncompressed_len: 1234,
+        compressed: PayloadU24(vec![1, 2, 3]),
+    }
+}
+
 fn get_all_tls12_handshake_payloads() -> Vec<HandshakeMessagePayload> {
     vec![
         HandshakeMessagePayload {
@@ -1098,6 +1274,10 @@ fn get_all_tls13_handshake_payloads() -> Vec<HandshakeMessagePayload> {
             typ: HandshakeType::EncryptedExtensions,
             payload: HandshakePayload::EncryptedExtensions(get_sample_encryptedextensions()),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::EndOfEarlyData,
+            payload: HandshakePayload::EndOfEarlyData,
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::KeyUpdate,
             payload: HandshakePayload::KeyUpdate(KeyUpdateRequest::UpdateRequested),
@@ -1114,6 +1294,12 @@ fn get_all_tls13_handshake_payloads() -> Vec<HandshakeMessagePayload> {
             typ: HandshakeType::CertificateStatus,
             payload: HandshakePayload::CertificateStatus(get_sample_certificatestatus()),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::CompressedCertificate,
+            payload: HandshakePayload::CompressedCertificate(
+                get_sample_compressedcertificatepayload(),
+            ),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::Unknown(99),
             payload: HandshakePayload::Unknown(Payload(vec![1, 2, 3])),
@@ -1217,3 +1403,72 @@ fn cannot_decode_huge_certificate() {
     buf[6] = 0x01;
     assert!(HandshakeMessagePayload::read_bytes(&buf).is_none());
 }
+
+fn sample_hello_of_len(len: usize, psk: bool) -> ClientHelloPayload {
+    let mut chp = ClientHelloPayload {
+        client_version: ProtocolVersion::TLSv1_2,
+        random: Random::from([0; 32]),
+        session_id: SessionID::empty(),
+        cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
+        compression_methods: vec![Compression::Null],
+        extensions: vec![ClientExtension::ExtendedMasterSecretRequest],
+    };
+    if psk {
+        chp.extensions
+            .push(ClientExtension::PresharedKey(PresharedKeyOffer::new(
+                PresharedKeyIdentity::new(vec![1, 2, 3], 0),
+                vec![0; 32],
+            )));
+    }
+
+    let unknown_len = len - 4 - chp.get_encoding().len() - 4;
+    chp.extensions.insert(
+        0,
+        ClientExtension::Unknown(UnknownExtension {
+            typ: ExtensionType::Unknown(12345),
+            payload: Payload(vec![0; unknown_len]),
+        }),
+    );
+    assert_eq!(4 + chp.get_encoding().len(), len);
+    chp
+}
+
+#[test]
+fn client_hello_padding_avoids_problem_lengths() {
+    for psk in &[false, true] {
+        for len in 150..600 {
+            let mut chp = sample_hello_of_len(len, *psk);
+            chp.add_padding();
+            let padded_len = 4 + chp.get_encoding().len();
+            let padding = chp.find_extension(ExtensionType::Padding);
+
+            if (256..512).contains(&len) {
+                assert!(padding.is_some());
+                assert!(
+                    (512..=516).contains(&padded_len),
+                    "{} -> {}",
+                    len,
+                    padded_len
+                );
+            } else {
+                assert!(padding.is_none());
+                assert_eq!(padded_len, len);
+            }
+
+            if *psk {
+                assert!(chp.check_psk_ext_is_last());
+            }
+        }
+    }
+}
+
+#[test]
+fn can_roundtrip_padding_extension() {
+    let ext = ClientExtension::Padding(10);
+    let enc = ext.get_encoding();
+    assert_eq!(enc, [0x00, 0x15, 0x00, 0x0a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(
+        ClientExtension::read_bytes(&enc).map(|ext| ext.get_encoding()),
+        Some(enc)
+    );
+}
@@ -62,6 +62,7 @@ pub struct ClientSessionValue {
     pub extended_ms: bool,
     pub max_early_data_size: u32,
     pub server_cert_chain: CertificatePayload,
+    pub alpn: Option<PayloadU8>,
 }
 
 impl ClientSessionValue {
@@ -95,6 +96,12 @@ impl Codec for ClientSessionValue {
         (if self.extended_ms { 1u8 } else { 0u8 }).encode(bytes);
         self.max_early_data_size.encode(bytes);
         self.server_cert_chain.encode(bytes);
+        if let Some(ref alpn) = self.alpn {
+            1u8.encode(bytes);
+            alpn.encode(bytes);
+        } else {
+            0u8.encode(bytes);
+        }
     }
 
     fn read(r: &mut Reader) -> Option<Self> {
@@ -109,6 +116,13 @@ impl Codec for ClientSessionValue {
         let extended_ms = u8::read(r)?;
         let max_early_data_size = u32::read(r)?;
         let server_cert_chain = CertificatePayload::read(r)?;
+        // Values stored before the ALPN protocol was recorded end here.
+        let has_alpn = r.any_left() && u8::read(r)? == 1;
+        let alpn = if has_alpn {
+            Some(PayloadU8::read(r)?)
+        } else {
+            None
+        };
 
         Some(Self {
             version: v,
@@ -122,6 +136,7 @@ impl Codec for ClientSessionValue {
             extended_ms: extended_ms == 1u8,
             max_early_data_size,
             server_cert_chain,
+            alpn,
         })
     }
 }
@@ -165,6 +180,7 @@ impl ClientSessionValueWithResolvedCipherSuite {
                 extended_ms: false,
                 max_early_data_size: 0,
                 server_cert_chain: server_cert_chain.to_owned(),
+                alpn: None,
             },
             supported_cipher_suite: cipher_suite,
             time_retrieved: time_now,
@@ -212,6 +228,10 @@ impl ClientSessionValueWithResolvedCipherSuite {
         self.value.max_early_data_size = sz;
     }
 
+    pub fn set_alpn(&mut self, alpn: Option<Vec<u8>>) {
+        self.value.alpn = alpn.map(PayloadU8::new);
+    }
+
     #[inline]
     pub fn time_retrieved(&self) -> TimeBase {
         self.time_retrieved
@@ -3,6 +3,7 @@ use super::enums::*;
 use super::handshake::*;
 use super::persist::*;
 
+use crate::crypto::ring::RING;
 use crate::key::Certificate;
 use crate::suites::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256;
 use crate::ticketer::TimeBase;
@@ -28,7 +29,7 @@ fn clientsessionvalue_is_debug() {
     let csv = ClientSessionValueWithResolvedCipherSuite::new(
         ProtocolVersion::TLSv1_2,
         TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
-        &SessionID::random().unwrap(),
+        &SessionID::random(&RING).unwrap(),
         vec![],
         vec![1, 2, 3],
         &vec![Certificate(b"abc".to_vec()), Certificate(b"def".to_vec())],
@@ -37,6 +38,42 @@ fn clientsessionvalue_is_debug() {
     println!("{:?}", csv);
 }
 
+fn clientsessionvalue_for_test() -> ClientSessionValueWithResolvedCipherSuite {
+    ClientSessionValueWithResolvedCipherSuite::new(
+        ProtocolVersion::TLSv1_3,
+        TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        &SessionID::empty(),
+        vec![4, 5, 6],
+        vec![1, 2, 3],
+        &vec![Certificate(b"abc".to_vec())],
+        TimeBase::now().unwrap(),
+    )
+}
+
+#[test]
+fn clientsessionvalue_alpn_roundtrip() {
+    let mut csv = clientsessionvalue_for_test();
+    csv.set_alpn(Some(b"h2".to_vec()));
+    let bytes = csv.get_encoding();
+
+    let decoded = ClientSessionValue::read_bytes(&bytes).unwrap();
+    assert_eq!(decoded.alpn.unwrap().0, b"h2");
+}
+
+#[test]
+fn clientsessionvalue_reads_encoding_without_alpn() {
+    let csv = clientsessionvalue_for_test();
+    let mut bytes = csv.get_encoding();
+
+    // encodings written before the ALPN protocol was stored lack the
+    // final presence byte
+    assert_eq!(bytes.pop(), Some(0));
+    let decoded = ClientSessionValue::read_bytes(&bytes).unwrap();
+    assert!(decoded.alpn.is_none());
+    assert_eq!(decoded.ticket.0, vec![4, 5, 6]);
+    assert_eq!(decoded.master_secret.0, vec![1, 2, 3]);
+}
+
 #[test]
 fn serversessionvalue_is_debug() {
     let ssv = ServerSessionValue::new(
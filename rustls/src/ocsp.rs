@@ -0,0 +1,984 @@
+// Validation of stapled OCSP responses (RFC 6960), and detection of the
+// TLS Feature "must-staple" certificate extension (RFC 7633).
+//
+// webpki does not expose the certificate fields we need here, so this
+// has its own (deliberately minimal) certificate parsing.
+
+use crate::anchors::RootCertStore;
+use crate::crypto::{CryptoProvider, HashAlgorithm};
+use crate::error::{Error, OcspError, WebPkiError};
+use crate::key::Certificate;
+use crate::msgs::enums::SignatureScheme;
+use crate::x509::{bit_string_bytes, wrap_in_sequence, DerReader, ID_ED25519};
+
+use ring::io::der;
+
+const ENUMERATED: u8 = 0x0a;
+
+const fn explicit(number: u8) -> u8 {
+    der::CONTEXT_SPECIFIC | der::CONSTRUCTED | number
+}
+
+const fn implicit(number: u8) -> u8 {
+    der::CONTEXT_SPECIFIC | number
+}
+
+const BOOLEAN: u8 = der::Tag::Boolean as u8;
+const INTEGER: u8 = der::Tag::Integer as u8;
+const BIT_STRING: u8 = der::Tag::BitString as u8;
+const OCTET_STRING: u8 = der::Tag::OctetString as u8;
+const OID: u8 = der::Tag::OID as u8;
+const SEQUENCE: u8 = der::Tag::Sequence as u8;
+const UTC_TIME: u8 = der::Tag::UTCTime as u8;
+const GENERALIZED_TIME: u8 = der::Tag::GeneralizedTime as u8;
+
+// Object identifiers, as the contents of their DER encoding.
+const ID_PKIX_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const ID_KP_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+const ID_PE_TLSFEATURE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+const ID_CE_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+
+const ID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const ID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const ID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+const ID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+
+const SHA256_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const SHA384_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const SHA512_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+
+/// The TLS extension number of `status_request`, as it appears in a
+/// TLS Feature extension.
+const STATUS_REQUEST: &[u8] = &[0x05];
+
+/// Check `response`, a DER-encoded OCSPResponse stapled by the server,
+/// shows `end_entity` is not revoked at `now` (in seconds since the UNIX
+/// epoch).
+///
+/// The response must be signed by the issuer of `end_entity`, or by a
+/// responder holding a certificate for that purpose from the issuer.
+/// The issuer is found among `intermediates` and `roots`.  Signatures
+/// are checked, and certificate identifiers hashed, by `provider`.
+///
+/// A response without a nextUpdate time is accepted for `max_age`
+/// seconds after its thisUpdate time.
+pub(crate) fn verify_response(
+    provider: &dyn CryptoProvider,
+    response: &[u8],
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    roots: &RootCertStore,
+    now: u64,
+    max_age: u64,
+) -> Result<(), OcspError> {
+    let cert = Cert::parse(&end_entity.0)?;
+    let anchors = roots
+        .roots
+        .iter()
+        .map(|ota| ota.to_trust_anchor())
+        .collect::<Vec<_>>();
+    let issuer = find_issuer(provider, &cert, intermediates, &anchors)?;
+
+    let response = BasicResponse::parse(response)?;
+    let signer_spki = find_signer(provider, &response, &issuer, now)?;
+    verify_signed_data(
+        provider,
+        signer_spki,
+        response.signature_alg,
+        response.tbs,
+        response.signature,
+    )?;
+
+    let single = response.find(provider, &cert, &issuer)?;
+    check_freshness(single.this_update, single.next_update, now, max_age)?;
+
+    match single.status {
+        CertStatus::Good => Ok(()),
+        CertStatus::Revoked => Err(OcspError::CertRevoked),
+        CertStatus::Unknown => Err(OcspError::CertStatusUnknown),
+    }
+}
+
+/// Check a response produced at `this_update` is usable at `now`.
+///
+/// RFC6960 says a response without nextUpdate means newer information
+/// is always available; we accept those for `max_age` seconds rather
+/// than forever.
+fn check_freshness(
+    this_update: u64,
+    next_update: Option<u64>,
+    now: u64,
+    max_age: u64,
+) -> Result<(), OcspError> {
+    if this_update > now {
+        return Err(OcspError::NotValidYet);
+    }
+
+    let expiry = next_update.unwrap_or_else(|| this_update.saturating_add(max_age));
+    match now > expiry {
+        true => Err(OcspError::Expired),
+        false => Ok(()),
+    }
+}
+
+/// Does `end_entity` carry a TLS Feature extension demanding a stapled
+/// OCSP response?
+pub(crate) fn requires_stapled_response(end_entity: &Certificate) -> Result<bool, OcspError> {
+    let features = match Cert::parse(&end_entity.0)?.extension(ID_PE_TLSFEATURE)? {
+        Some(features) => features,
+        None => return Ok(false),
+    };
+
+    let mut rd = DerReader::new(features);
+    let mut features = DerReader::new(
+        rd.read(SEQUENCE)
+            .ok_or(OcspError::BadEncoding)?,
+    );
+    while !features.is_empty() {
+        if features
+            .read(INTEGER)
+            .ok_or(OcspError::BadEncoding)?
+            == STATUS_REQUEST
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// The parts of an X.509 certificate that revocation checking needs.
+/// This is also used to check certificates which issue delegated
+/// credentials.
+pub(crate) struct Cert<'a> {
+    /// The encoding of the tbsCertificate, which is what is signed.
+    tbs: &'a [u8],
+    signature_alg: &'a [u8],
+    signature: &'a [u8],
+    serial: &'a [u8],
+    /// The encoding of the issuer name, for CertID matching.
+    issuer_encoding: &'a [u8],
+    /// The contents of the issuer and subject names.  These match
+    /// the form used by `webpki::TrustAnchor`.
+    issuer: &'a [u8],
+    subject: &'a [u8],
+    pub(crate) not_before: u64,
+    not_after: u64,
+    /// The contents of the SubjectPublicKeyInfo.
+    pub(crate) spki: &'a [u8],
+    extensions: Option<&'a [u8]>,
+}
+
+impl<'a> Cert<'a> {
+    fn parse(encoding: &'a [u8]) -> Result<Self, OcspError> {
+        Self::parse_der(encoding).ok_or(OcspError::BadEncoding)
+    }
+
+    pub(crate) fn parse_der(encoding: &'a [u8]) -> Option<Self> {
+        let mut outer = DerReader::new(encoding);
+        let mut cert = DerReader::new(outer.read(SEQUENCE)?);
+        if !outer.is_empty() {
+            return None;
+        }
+
+        let (tbs_contents, tbs) = cert.read_with_encoding(SEQUENCE)?;
+        let signature_alg = cert.read(SEQUENCE)?;
+        let signature = bit_string_bytes(cert.read(BIT_STRING)?)?;
+        if !cert.is_empty() {
+            return None;
+        }
+
+        let mut tbs_rd = DerReader::new(tbs_contents);
+        tbs_rd.read_optional(explicit(0))?;
+        let serial = tbs_rd.read(INTEGER)?;
+        tbs_rd.read(SEQUENCE)?;
+        let (issuer, issuer_encoding) = tbs_rd.read_with_encoding(SEQUENCE)?;
+
+        let mut validity = DerReader::new(tbs_rd.read(SEQUENCE)?);
+        let not_before = read_time(&mut validity)?;
+        let not_after = read_time(&mut validity)?;
+
+        let subject = tbs_rd.read(SEQUENCE)?;
+        let spki = tbs_rd.read(SEQUENCE)?;
+        tbs_rd.read_optional(implicit(1))?;
+        tbs_rd.read_optional(implicit(2))?;
+        let extensions = match tbs_rd.read_optional(explicit(3))? {
+            Some(extensions) => Some(DerReader::new(extensions).read(SEQUENCE)?),
+            None => None,
+        };
+        if !tbs_rd.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            tbs,
+            signature_alg,
+            signature,
+            serial,
+            issuer_encoding,
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            spki,
+            extensions,
+        })
+    }
+
+    /// Return the value of the extension identified by `oid`, if present.
+    pub(crate) fn extension(&self, oid: &[u8]) -> Result<Option<&'a [u8]>, OcspError> {
+        let mut extensions = DerReader::new(self.extensions.unwrap_or_default());
+
+        while !extensions.is_empty() {
+            let mut ext = DerReader::new(
+                extensions
+                    .read(SEQUENCE)
+                    .ok_or(OcspError::BadEncoding)?,
+            );
+            let id = ext
+                .read(OID)
+                .ok_or(OcspError::BadEncoding)?;
+            ext.read_optional(BOOLEAN)
+                .ok_or(OcspError::BadEncoding)?;
+            let value = ext
+                .read(OCTET_STRING)
+                .ok_or(OcspError::BadEncoding)?;
+
+            if id == oid {
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Is this certificate marked for signing OCSP responses?
+    fn is_ocsp_signer(&self) -> Result<bool, OcspError> {
+        let usages = match self.extension(ID_CE_EXT_KEY_USAGE)? {
+            Some(usages) => usages,
+            None => return Ok(false),
+        };
+
+        let mut rd = DerReader::new(usages);
+        let mut usages = DerReader::new(
+            rd.read(SEQUENCE)
+                .ok_or(OcspError::BadEncoding)?,
+        );
+        while !usages.is_empty() {
+            if usages
+                .read(OID)
+                .ok_or(OcspError::BadEncoding)?
+                == ID_KP_OCSP_SIGNING
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+/// The name and key of the issuer of the end-entity certificate.
+struct Issuer<'a> {
+    name: &'a [u8],
+    spki: &'a [u8],
+}
+
+fn find_issuer<'a>(
+    provider: &dyn CryptoProvider,
+    cert: &Cert,
+    intermediates: &'a [Certificate],
+    anchors: &[webpki::TrustAnchor<'a>],
+) -> Result<Issuer<'a>, OcspError> {
+    let candidates = intermediates
+        .iter()
+        .filter_map(|inter| Cert::parse(&inter.0).ok())
+        .map(|inter| Issuer {
+            name: inter.subject,
+            spki: inter.spki,
+        })
+        .chain(anchors.iter().map(|ta| Issuer {
+            name: ta.subject,
+            spki: ta.spki,
+        }));
+
+    for candidate in candidates {
+        if candidate.name == cert.issuer
+            && verify_signed_data(
+                provider,
+                candidate.spki,
+                cert.signature_alg,
+                cert.tbs,
+                cert.signature,
+            )
+            .is_ok()
+        {
+            return Ok(candidate);
+        }
+    }
+
+    Err(OcspError::UnknownIssuer)
+}
+
+/// Find the key that should have signed `response`.  This is the key of
+/// `issuer`, or that of a delegated responder whose certificate is
+/// included in the response.
+fn find_signer<'a>(
+    provider: &dyn CryptoProvider,
+    response: &BasicResponse<'a>,
+    issuer: &Issuer<'a>,
+    now: u64,
+) -> Result<&'a [u8], OcspError> {
+    if response
+        .responder
+        .identifies(provider, issuer.name, issuer.spki)?
+    {
+        return Ok(issuer.spki);
+    }
+
+    let mut certs = DerReader::new(response.certs);
+    while !certs.is_empty() {
+        let (_, _, encoding) = certs
+            .read_any()
+            .ok_or(OcspError::BadEncoding)?;
+        let responder = Cert::parse(encoding)?;
+
+        if !response
+            .responder
+            .identifies(provider, responder.subject, responder.spki)?
+        {
+            continue;
+        }
+
+        let authorized = responder.issuer == issuer.name
+            && verify_signed_data(
+                provider,
+                issuer.spki,
+                responder.signature_alg,
+                responder.tbs,
+                responder.signature,
+            )
+            .is_ok()
+            && responder.is_ocsp_signer()?
+            && responder.not_before <= now
+            && now <= responder.not_after;
+
+        if authorized {
+            return Ok(responder.spki);
+        }
+    }
+
+    Err(OcspError::ResponderNotAuthorized)
+}
+
+enum ResponderId<'a> {
+    /// The contents of the responder's subject name.
+    ByName(&'a [u8]),
+    /// The SHA-1 hash of the responder's public key.
+    ByKey(&'a [u8]),
+}
+
+impl ResponderId<'_> {
+    fn identifies(
+        &self,
+        provider: &dyn CryptoProvider,
+        name: &[u8],
+        spki: &[u8],
+    ) -> Result<bool, OcspError> {
+        match self {
+            ResponderId::ByName(responder) => Ok(*responder == name),
+            ResponderId::ByKey(hash) => {
+                let key = public_key(spki).ok_or(OcspError::BadEncoding)?;
+                Ok(provider
+                    .hash(HashAlgorithm::SHA1, key)
+                    .as_ref()
+                    == *hash)
+            }
+        }
+    }
+}
+
+/// A BasicOCSPResponse.
+struct BasicResponse<'a> {
+    /// The encoding of the tbsResponseData, which is what is signed.
+    tbs: &'a [u8],
+    responder: ResponderId<'a>,
+    /// The contents of the sequence of SingleResponses.
+    responses: &'a [u8],
+    signature_alg: &'a [u8],
+    signature: &'a [u8],
+    /// The contents of the sequence of certificates, possibly empty.
+    certs: &'a [u8],
+}
+
+impl<'a> BasicResponse<'a> {
+    fn parse(encoding: &'a [u8]) -> Result<Self, OcspError> {
+        let mut outer = DerReader::new(encoding);
+        let mut response = DerReader::new(
+            outer
+                .read(SEQUENCE)
+                .ok_or(OcspError::BadEncoding)?,
+        );
+
+        match response.read(ENUMERATED) {
+            Some([0]) => {}
+            Some(_) => return Err(OcspError::UnsuccessfulResponse),
+            None => return Err(OcspError::BadEncoding),
+        }
+
+        let mut bytes = response
+            .read(explicit(0))
+            .map(DerReader::new)
+            .and_then(|mut rd| rd.read(SEQUENCE))
+            .map(DerReader::new)
+            .ok_or(OcspError::BadEncoding)?;
+        if bytes
+            .read(OID)
+            .ok_or(OcspError::BadEncoding)?
+            != ID_PKIX_OCSP_BASIC
+        {
+            return Err(OcspError::UnsupportedResponseType);
+        }
+
+        bytes
+            .read(OCTET_STRING)
+            .and_then(Self::parse_basic)
+            .ok_or(OcspError::BadEncoding)
+    }
+
+    fn parse_basic(encoding: &'a [u8]) -> Option<Self> {
+        let mut outer = DerReader::new(encoding);
+        let mut basic = DerReader::new(outer.read(SEQUENCE)?);
+        let (tbs_contents, tbs) = basic.read_with_encoding(SEQUENCE)?;
+        let signature_alg = basic.read(SEQUENCE)?;
+        let signature = bit_string_bytes(basic.read(BIT_STRING)?)?;
+        let certs = match basic.read_optional(explicit(0))? {
+            Some(certs) => DerReader::new(certs).read(SEQUENCE)?,
+            None => &[],
+        };
+
+        let mut tbs_rd = DerReader::new(tbs_contents);
+        if let Some(version) = tbs_rd.read_optional(explicit(0))? {
+            if DerReader::new(version).read(INTEGER)? != [0] {
+                return None;
+            }
+        }
+
+        let responder = if let Some(name) = tbs_rd.read_optional(explicit(1))? {
+            ResponderId::ByName(DerReader::new(name).read(SEQUENCE)?)
+        } else {
+            ResponderId::ByKey(DerReader::new(tbs_rd.read(explicit(2))?).read(OCTET_STRING)?)
+        };
+
+        tbs_rd.read(GENERALIZED_TIME)?;
+        let responses = tbs_rd.read(SEQUENCE)?;
+
+        Some(Self {
+            tbs,
+            responder,
+            responses,
+            signature_alg,
+            signature,
+            certs,
+        })
+    }
+
+    /// Find the SingleResponse about `cert`.
+    fn find(
+        &self,
+        provider: &dyn CryptoProvider,
+        cert: &Cert,
+        issuer: &Issuer,
+    ) -> Result<SingleResponse<'a>, OcspError> {
+        let mut responses = DerReader::new(self.responses);
+
+        while !responses.is_empty() {
+            let single = responses
+                .read(SEQUENCE)
+                .and_then(SingleResponse::parse)
+                .ok_or(OcspError::BadEncoding)?;
+
+            if single.is_for(provider, cert, issuer)? {
+                return Ok(single);
+            }
+        }
+
+        Err(OcspError::CertNotCovered)
+    }
+}
+
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+struct SingleResponse<'a> {
+    hash_alg: &'a [u8],
+    issuer_name_hash: &'a [u8],
+    issuer_key_hash: &'a [u8],
+    serial: &'a [u8],
+    status: CertStatus,
+    this_update: u64,
+    next_update: Option<u64>,
+}
+
+impl<'a> SingleResponse<'a> {
+    fn parse(contents: &'a [u8]) -> Option<Self> {
+        let mut single = DerReader::new(contents);
+
+        let mut cert_id = DerReader::new(single.read(SEQUENCE)?);
+        let hash_alg = DerReader::new(cert_id.read(SEQUENCE)?).read(OID)?;
+        let issuer_name_hash = cert_id.read(OCTET_STRING)?;
+        let issuer_key_hash = cert_id.read(OCTET_STRING)?;
+        let serial = cert_id.read(INTEGER)?;
+
+        let status = match single.read_any()? {
+            (tag, _, _) if tag == implicit(0) => CertStatus::Good,
+            (tag, _, _) if tag == explicit(1) => CertStatus::Revoked,
+            (tag, _, _) if tag == implicit(2) => CertStatus::Unknown,
+            _ => return None,
+        };
+
+        let this_update = read_time(&mut single)?;
+        let next_update = match single.read_optional(explicit(0))? {
+            Some(next_update) => Some(read_time(&mut DerReader::new(next_update))?),
+            None => None,
+        };
+
+        Some(Self {
+            hash_alg,
+            issuer_name_hash,
+            issuer_key_hash,
+            serial,
+            status,
+            this_update,
+            next_update,
+        })
+    }
+
+    /// Does the CertID of this response identify `cert`?
+    fn is_for(
+        &self,
+        provider: &dyn CryptoProvider,
+        cert: &Cert,
+        issuer: &Issuer,
+    ) -> Result<bool, OcspError> {
+        let alg = match self.hash_alg {
+            ID_SHA1 => HashAlgorithm::SHA1,
+            ID_SHA256 => HashAlgorithm::SHA256,
+            ID_SHA384 => HashAlgorithm::SHA384,
+            ID_SHA512 => HashAlgorithm::SHA512,
+            _ => return Ok(false),
+        };
+        let key = public_key(issuer.spki).ok_or(OcspError::BadEncoding)?;
+
+        Ok(self.serial == cert.serial
+            && provider
+                .hash(alg, cert.issuer_encoding)
+                .as_ref()
+                == self.issuer_name_hash
+            && provider.hash(alg, key).as_ref() == self.issuer_key_hash)
+    }
+}
+
+/// Return the subjectPublicKey bytes of a SubjectPublicKeyInfo.
+fn public_key(spki: &[u8]) -> Option<&[u8]> {
+    let mut rd = DerReader::new(spki);
+    rd.read(SEQUENCE)?;
+    bit_string_bytes(rd.read(BIT_STRING)?)
+}
+
+/// Verify that `signature` over `message` was made by the key in `spki`,
+/// using the algorithm described by the AlgorithmIdentifier `alg`.
+///
+/// `spki` is the contents of a SubjectPublicKeyInfo.
+fn verify_signed_data(
+    provider: &dyn CryptoProvider,
+    spki: &[u8],
+    alg: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), OcspError> {
+    let signature_type = DerReader::new(alg)
+        .read(OID)
+        .ok_or(OcspError::BadEncoding)?;
+
+    // As in TLS1.2, the ECDSA schemes here name only the hash function.
+    let scheme = match signature_type {
+        SHA256_WITH_RSA_ENCRYPTION => SignatureScheme::RSA_PKCS1_SHA256,
+        SHA384_WITH_RSA_ENCRYPTION => SignatureScheme::RSA_PKCS1_SHA384,
+        SHA512_WITH_RSA_ENCRYPTION => SignatureScheme::RSA_PKCS1_SHA512,
+        ECDSA_WITH_SHA256 => SignatureScheme::ECDSA_NISTP256_SHA256,
+        ECDSA_WITH_SHA384 => SignatureScheme::ECDSA_NISTP384_SHA384,
+        ID_ED25519 => SignatureScheme::ED25519,
+        _ => return Err(OcspError::UnsupportedSignatureAlgorithm),
+    };
+
+    let mut spki = spki.to_vec();
+    wrap_in_sequence(&mut spki);
+    provider
+        .verify_tls12_signature(scheme, &spki, message, signature)
+        .map_err(|err| match err {
+            Error::WebPkiError(WebPkiError::BadEncoding, _) => OcspError::BadEncoding,
+            Error::WebPkiError(WebPkiError::UnsupportedSignatureAlgorithmForPublicKey, _) => {
+                OcspError::UnsupportedSignatureAlgorithm
+            }
+            _ => OcspError::InvalidSignature,
+        })
+}
+
+/// Read a UTCTime or GeneralizedTime, returning seconds since the
+/// UNIX epoch.
+fn read_time(rd: &mut DerReader) -> Option<u64> {
+    let (year, rest) = match rd.read_any()? {
+        (UTC_TIME, value, _) => {
+            let year = digits(value.get(..2)?)?;
+            let year = if year >= 50 { 1900 + year } else { 2000 + year };
+            (year, &value[2..])
+        }
+        (GENERALIZED_TIME, value, _) => (digits(value.get(..4)?)?, &value[4..]),
+        _ => return None,
+    };
+
+    // We only accept the forms allowed by RFC 5280: MMDDHHMMSSZ
+    if rest.len() != 11 || rest[10] != b'Z' {
+        return None;
+    }
+
+    let month = digits(&rest[0..2])?;
+    let day = digits(&rest[2..4])?;
+    let hour = digits(&rest[4..6])?;
+    let minute = digits(&rest[6..8])?;
+    let second = digits(&rest[8..10])?;
+
+    if year < 1970
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    Some(days_since_epoch(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+fn digits(bytes: &[u8]) -> Option<u64> {
+    bytes
+        .iter()
+        .try_fold(0, |acc, b| match b {
+            b'0'..=b'9' => Some(acc * 10 + u64::from(b - b'0')),
+            _ => None,
+        })
+}
+
+/// Days from 1970-01-01 to the given date, which must not be earlier.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // Count years from March, so leap days fall at the end of the year.
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let days = year * 365 + year / 4 - year / 100 + year / 400 + day_of_year;
+
+    // That counted from 0000-03-01.
+    days - 719_468
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::ring::RING;
+    use crate::key::Certificate;
+    use std::fs;
+
+    fn load(kt: &str, name: &str) -> Vec<u8> {
+        fs::read(format!("../test-ca/{}/{}", kt, name)).unwrap()
+    }
+
+    fn load_cert(kt: &str, name: &str) -> Certificate {
+        let pem = fs::read(format!("../test-ca/{}/{}", kt, name)).unwrap();
+        let mut certs = rustls_pemfile::certs(&mut pem.as_slice()).unwrap();
+        assert_eq!(certs.len(), 1);
+        Certificate(certs.remove(0))
+    }
+
+    fn roots(kt: &str) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&Certificate(load(kt, "ca.der")))
+            .unwrap();
+        roots
+    }
+
+    // 2021-06-01, which is within the validity of all test responses.
+    const NOW: u64 = 1_622_505_600;
+
+    const MAX_AGE: u64 = 7 * 86400;
+
+    fn check(kt: &str, response: &str, now: u64) -> Result<(), OcspError> {
+        verify_response(
+            &RING,
+            &load(kt, response),
+            &load_cert(kt, "end.cert"),
+            &[load_cert(kt, "inter.cert")],
+            &roots(kt),
+            now,
+            MAX_AGE,
+        )
+    }
+
+    #[test]
+    fn accepts_good_responses() {
+        for kt in &["rsa", "ecdsa", "eddsa"] {
+            assert_eq!(check(kt, "end.ocsp", NOW), Ok(()));
+        }
+    }
+
+    #[test]
+    fn accepts_delegated_responders() {
+        for kt in &["rsa", "ecdsa", "eddsa"] {
+            assert_eq!(check(kt, "end.delegated.ocsp", NOW), Ok(()));
+        }
+    }
+
+    #[test]
+    fn identifies_responders_by_key() {
+        let ocsp_cert = load_cert("rsa", "ocsp.cert");
+        let responder = Cert::parse(&ocsp_cert.0).unwrap();
+        let other_cert = load_cert("rsa", "inter.cert");
+        let other = Cert::parse(&other_cert.0).unwrap();
+
+        // The SHA-1 hash of the responder's public key, which is also
+        // its subject key identifier.
+        let hash = [
+            0xed, 0x31, 0x33, 0xbf, 0x53, 0x1a, 0xc9, 0x36, 0xe7, 0x29, 0xd2, 0x15, 0x3c, 0x92,
+            0x45, 0x35, 0x47, 0xd8, 0x3e, 0xa1,
+        ];
+        let by_key = ResponderId::ByKey(&hash);
+        assert_eq!(
+            by_key.identifies(&RING, responder.subject, responder.spki),
+            Ok(true)
+        );
+        assert_eq!(
+            by_key.identifies(&RING, other.subject, other.spki),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn rejects_revoked_certs() {
+        for kt in &["rsa", "ecdsa", "eddsa"] {
+            assert_eq!(
+                check(kt, "end.revoked.ocsp", NOW),
+                Err(OcspError::CertRevoked)
+            );
+        }
+    }
+
+    #[test]
+    fn checks_freshness() {
+        assert_eq!(
+            check("rsa", "end.ocsp", NOW - 86400 * 365),
+            Err(OcspError::NotValidYet)
+        );
+        assert_eq!(
+            check("rsa", "end.ocsp", NOW + 86400 * 365 * 6),
+            Err(OcspError::Expired)
+        );
+    }
+
+    #[test]
+    fn limits_age_without_next_update() {
+        assert_eq!(check_freshness(NOW, None, NOW, MAX_AGE), Ok(()));
+        assert_eq!(check_freshness(NOW, None, NOW + MAX_AGE, MAX_AGE), Ok(()));
+        assert_eq!(
+            check_freshness(NOW, None, NOW + MAX_AGE + 1, MAX_AGE),
+            Err(OcspError::Expired)
+        );
+        assert_eq!(
+            check_freshness(NOW + 1, None, NOW, MAX_AGE),
+            Err(OcspError::NotValidYet)
+        );
+        assert_eq!(
+            check_freshness(NOW, Some(NOW + MAX_AGE * 2), NOW + MAX_AGE + 1, MAX_AGE),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn finds_issuer_in_roots() {
+        // here the intermediate is a "root", and is not sent in the chain
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&load_cert("ecdsa", "inter.cert"))
+            .unwrap();
+
+        assert_eq!(
+            verify_response(
+                &RING,
+                &load("ecdsa", "end.ocsp"),
+                &load_cert("ecdsa", "end.cert"),
+                &[],
+                &roots,
+                NOW,
+                MAX_AGE,
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_issuer() {
+        assert_eq!(
+            verify_response(
+                &RING,
+                &load("rsa", "end.ocsp"),
+                &load_cert("rsa", "end.cert"),
+                &[],
+                &roots("rsa"),
+                NOW,
+                MAX_AGE,
+            ),
+            Err(OcspError::UnknownIssuer)
+        );
+    }
+
+    #[test]
+    fn rejects_response_for_other_cert() {
+        assert_eq!(
+            verify_response(
+                &RING,
+                &load("rsa", "end.ocsp"),
+                &load_cert("rsa", "end.muststaple.cert"),
+                &[load_cert("rsa", "inter.cert")],
+                &roots("rsa"),
+                NOW,
+                MAX_AGE,
+            ),
+            Err(OcspError::CertNotCovered)
+        );
+    }
+
+    #[test]
+    fn rejects_response_from_other_pki() {
+        assert_eq!(
+            verify_response(
+                &RING,
+                &load("ecdsa", "end.ocsp"),
+                &load_cert("rsa", "end.cert"),
+                &[load_cert("rsa", "inter.cert")],
+                &roots("rsa"),
+                NOW,
+                MAX_AGE,
+            ),
+            Err(OcspError::ResponderNotAuthorized)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let mut response = load("rsa", "end.ocsp");
+        // the response ends with its signature, then the signer's
+        // certificate wrapped in a [0] and a SEQUENCE (4 header bytes each).
+        let signature_end = response.len() - load_cert("rsa", "inter.cert").0.len() - 8;
+        response[signature_end - 1] ^= 1;
+
+        assert_eq!(
+            verify_response(
+                &RING,
+                &response,
+                &load_cert("rsa", "end.cert"),
+                &[load_cert("rsa", "inter.cert")],
+                &roots("rsa"),
+                NOW,
+                MAX_AGE,
+            ),
+            Err(OcspError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let end = load_cert("rsa", "end.cert");
+        let inter = [load_cert("rsa", "inter.cert")];
+        let roots = roots("rsa");
+
+        assert_eq!(
+            verify_response(
+                &RING,
+                &[0x01, 0x02, 0x03],
+                &end,
+                &inter,
+                &roots,
+                NOW,
+                MAX_AGE
+            ),
+            Err(OcspError::BadEncoding)
+        );
+        // OCSPResponse { responseStatus: tryLater }
+        assert_eq!(
+            verify_response(
+                &RING,
+                &[0x30, 0x03, 0x0a, 0x01, 0x03],
+                &end,
+                &inter,
+                &roots,
+                NOW,
+                MAX_AGE
+            ),
+            Err(OcspError::UnsuccessfulResponse)
+        );
+
+        let response = load("rsa", "end.ocsp");
+        for len in 0..response.len() {
+            assert!(
+                verify_response(&RING, &response[..len], &end, &inter, &roots, NOW, MAX_AGE)
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn detects_must_staple() {
+        for kt in &["rsa", "ecdsa", "eddsa"] {
+            assert_eq!(
+                requires_stapled_response(&load_cert(kt, "end.muststaple.cert")),
+                Ok(true)
+            );
+            assert_eq!(
+                requires_stapled_response(&load_cert(kt, "end.cert")),
+                Ok(false)
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_time() {
+        fn time(tag: u8, value: &[u8]) -> Option<u64> {
+            let mut encoding = vec![tag, value.len() as u8];
+            encoding.extend_from_slice(value);
+            read_time(&mut DerReader::new(&encoding))
+        }
+
+        assert_eq!(time(UTC_TIME, b"700101000000Z"), Some(0));
+        assert_eq!(time(GENERALIZED_TIME, b"19700101000000Z"), Some(0));
+        assert_eq!(
+            time(GENERALIZED_TIME, b"20000229235959Z"),
+            Some(951_868_799)
+        );
+        assert_eq!(time(UTC_TIME, b"210601000000Z"), Some(NOW));
+        assert_eq!(time(UTC_TIME, b"490601000000Z"), Some(2_506_118_400));
+        assert_eq!(time(UTC_TIME, b"500101000000Z"), None);
+        assert_eq!(time(GENERALIZED_TIME, b"20211301000000Z"), None);
+        assert_eq!(time(GENERALIZED_TIME, b"20210601000000+0100"), None);
+        assert_eq!(time(GENERALIZED_TIME, b"2021060100000Z"), None);
+        assert_eq!(time(OCTET_STRING, b"20210601000000Z"), None);
+    }
+}
@@ -1,25 +1,22 @@
-use ring::hmac;
-
-fn concat_sign(key: &hmac::Key, a: &[u8], b: &[u8]) -> hmac::Tag {
-    let mut ctx = hmac::Context::with_key(key);
-    ctx.update(a);
-    ctx.update(b);
-    ctx.sign()
-}
-
-fn p(out: &mut [u8], alg: hmac::Algorithm, secret: &[u8], seed: &[u8]) {
-    let hmac_key = hmac::Key::new(alg, secret);
+use crate::crypto::{CryptoProvider, HashAlgorithm};
 
+fn p(
+    out: &mut [u8],
+    provider: &dyn CryptoProvider,
+    alg: HashAlgorithm,
+    secret: &[u8],
+    seed: &[u8],
+) {
     // A(1)
-    let mut current_a = hmac::sign(&hmac_key, seed);
-    let chunk_size = alg.digest_algorithm().output_len;
+    let mut current_a = provider.hmac_sign(alg, secret, &[seed]);
+    let chunk_size = alg.output_len();
     for chunk in out.chunks_mut(chunk_size) {
         // P_hash[i] = HMAC_hash(secret, A(i) + seed)
-        let p_term = concat_sign(&hmac_key, current_a.as_ref(), seed);
+        let p_term = provider.hmac_sign(alg, secret, &[current_a.as_ref(), seed]);
         chunk.copy_from_slice(&p_term.as_ref()[..chunk.len()]);
 
         // A(i+1) = HMAC_hash(secret, A(i))
-        current_a = hmac::sign(&hmac_key, current_a.as_ref());
+        current_a = provider.hmac_sign(alg, secret, &[current_a.as_ref()]);
     }
 }
 
@@ -30,14 +27,22 @@ fn concat(a: &[u8], b: &[u8]) -> Vec<u8> {
     ret
 }
 
-pub(crate) fn prf(out: &mut [u8], alg: hmac::Algorithm, secret: &[u8], label: &[u8], seed: &[u8]) {
+pub(crate) fn prf(
+    out: &mut [u8],
+    provider: &dyn CryptoProvider,
+    alg: HashAlgorithm,
+    secret: &[u8],
+    label: &[u8],
+    seed: &[u8],
+) {
     let joined_seed = concat(label, seed);
-    p(out, alg, secret, &joined_seed);
+    p(out, provider, alg, secret, &joined_seed);
 }
 
 #[cfg(test)]
 mod tests {
-    use ring::hmac::{HMAC_SHA256, HMAC_SHA512};
+    use crate::crypto::ring::RING;
+    use crate::crypto::HashAlgorithm;
 
     #[test]
     fn check_sha256() {
@@ -47,7 +52,14 @@ mod tests {
         let expect = include_bytes!("testdata/prf-result.1.bin");
         let mut output = [0u8; 100];
 
-        super::prf(&mut output, HMAC_SHA256, secret, label, seed);
+        super::prf(
+            &mut output,
+            &RING,
+            HashAlgorithm::SHA256,
+            secret,
+            label,
+            seed,
+        );
         assert_eq!(expect.len(), output.len());
         assert_eq!(expect.to_vec(), output.to_vec());
     }
@@ -60,7 +72,14 @@ mod tests {
         let expect = include_bytes!("testdata/prf-result.2.bin");
         let mut output = [0u8; 196];
 
-        super::prf(&mut output, HMAC_SHA512, secret, label, seed);
+        super::prf(
+            &mut output,
+            &RING,
+            HashAlgorithm::SHA512,
+            secret,
+            label,
+            seed,
+        );
         assert_eq!(expect.len(), output.len());
         assert_eq!(expect.to_vec(), output.to_vec());
     }
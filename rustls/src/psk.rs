@@ -0,0 +1,405 @@
+//! External pre-shared keys for TLS1.3: secrets agreed out-of-band,
+//! which authenticate both peers in place of certificates.
+//!
+//! These may be imported ([RFC9258](https://tools.ietf.org/html/rfc9258)),
+//! which derives a distinct PSK for each hash function it is used with.
+use crate::client::ServerName;
+use crate::crypto::{CryptoProvider, Digest, HashAlgorithm};
+use crate::error::Error;
+use crate::key_schedule::{self, KeyScheduleEarly, PskKind};
+use crate::msgs::base::PayloadU16;
+use crate::msgs::codec::Codec;
+use crate::msgs::enums::{ProtocolVersion, TlsKdf};
+use crate::msgs::handshake::ImportedIdentity;
+use crate::suites::Tls13CipherSuite;
+
+use std::sync::Arc;
+
+/// An external pre-shared key for TLS1.3, which both peers know by
+/// its identity.
+///
+/// A handshake using a PSK is authenticated by it: no certificates
+/// are sent or verified.
+pub struct ExternalPsk {
+    identity: Vec<u8>,
+    secret: Vec<u8>,
+    hash_algorithm: HashAlgorithm,
+    import_context: Option<Vec<u8>>,
+}
+
+impl ExternalPsk {
+    /// An external PSK `secret`, known by `identity`, for use with
+    /// TLS1.3 cipher suites which use SHA-256.  This is the hash
+    /// external PSKs are assumed to use unless agreed otherwise.
+    ///
+    /// This fails if `identity` or `secret` is empty, or `identity`
+    /// is too long to send.
+    pub fn new(identity: Vec<u8>, secret: Vec<u8>) -> Result<Self, Error> {
+        Self::build(identity, secret, HashAlgorithm::SHA256, None)
+    }
+
+    /// As [`ExternalPsk::new`], but for use with TLS1.3 cipher suites
+    /// which use SHA-384.
+    pub fn new_sha384(identity: Vec<u8>, secret: Vec<u8>) -> Result<Self, Error> {
+        Self::build(identity, secret, HashAlgorithm::SHA384, None)
+    }
+
+    /// An external PSK `secret`, known by `identity`, which is imported
+    /// as described in RFC9258.  Peers use a PSK derived from `secret`,
+    /// `identity`, `context` and the hash of the cipher suite, so an
+    /// imported PSK can be used with any TLS1.3 cipher suite.
+    ///
+    /// Both peers must import the PSK, with the same `context`, which
+    /// may be empty.  `secret` is hashed with SHA-256.
+    pub fn imported(identity: Vec<u8>, secret: Vec<u8>, context: Vec<u8>) -> Result<Self, Error> {
+        Self::build(identity, secret, HashAlgorithm::SHA256, Some(context))
+    }
+
+    fn build(
+        identity: Vec<u8>,
+        secret: Vec<u8>,
+        hash_algorithm: HashAlgorithm,
+        import_context: Option<Vec<u8>>,
+    ) -> Result<Self, Error> {
+        // An imported identity carries the identity, context, and
+        // eight bytes of lengths and algorithms.
+        let sent_len = match &import_context {
+            Some(context) => identity.len() + context.len() + 8,
+            None => identity.len(),
+        };
+
+        if identity.is_empty() || secret.is_empty() || sent_len > 0xffff {
+            return Err(Error::General("invalid external PSK".to_string()));
+        }
+
+        Ok(Self {
+            identity,
+            secret,
+            hash_algorithm,
+            import_context,
+        })
+    }
+
+    /// The identity which the peers know this PSK by.
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    fn get_hash(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+}
+
+/// A store of external PSKs, which both clients and servers use
+/// to find the PSKs they share with their peers.
+pub trait PskIdentityStore: Send + Sync {
+    /// Return the PSKs a client should offer when connecting to
+    /// `server_name`, most preferred first.
+    fn psks_for_server(&self, server_name: &ServerName) -> Vec<Arc<ExternalPsk>>;
+
+    /// Return the PSK a server knows by `identity`, or None.
+    ///
+    /// For an imported PSK, `identity` is the identity it was made
+    /// with, rather than the imported identity the client sends.
+    fn psk_for_identity(&self, identity: &[u8]) -> Option<Arc<ExternalPsk>>;
+}
+
+/// A [`PskIdentityStore`] holding a fixed set of PSKs in memory.
+///
+/// Clients offer every one of these to every server.
+pub struct PskMemoryStore {
+    psks: Vec<Arc<ExternalPsk>>,
+}
+
+impl PskMemoryStore {
+    /// Make a new PskMemoryStore, holding `psks`.
+    pub fn new(psks: Vec<ExternalPsk>) -> Arc<Self> {
+        Arc::new(Self {
+            psks: psks.into_iter().map(Arc::new).collect(),
+        })
+    }
+}
+
+impl PskIdentityStore for PskMemoryStore {
+    fn psks_for_server(&self, _server_name: &ServerName) -> Vec<Arc<ExternalPsk>> {
+        self.psks.clone()
+    }
+
+    fn psk_for_identity(&self, identity: &[u8]) -> Option<Arc<ExternalPsk>> {
+        self.psks
+            .iter()
+            .find(|psk| psk.identity == identity)
+            .cloned()
+    }
+}
+
+/// An external PSK, as offered in a ClientHello for use with the hash
+/// of a particular cipher suite.
+pub(crate) struct PskOffer {
+    provider: &'static dyn CryptoProvider,
+    psk: Arc<ExternalPsk>,
+    /// The identity sent in the ClientHello.
+    pub(crate) identity: Vec<u8>,
+    secret: Vec<u8>,
+    /// The cipher suite which the PSK was offered for.  It may be
+    /// used with any other which has the same hash.
+    pub(crate) suite: &'static Tls13CipherSuite,
+}
+
+impl PskOffer {
+    fn new(
+        provider: &'static dyn CryptoProvider,
+        psk: &Arc<ExternalPsk>,
+        suite: &'static Tls13CipherSuite,
+    ) -> Option<Self> {
+        let (identity, secret) = match &psk.import_context {
+            None if psk.get_hash() == suite.get_hash() => {
+                (psk.identity.clone(), psk.secret.clone())
+            }
+            None => return None,
+            Some(context) => {
+                let identity = ImportedIdentity {
+                    external_identity: PayloadU16::new(psk.identity.clone()),
+                    context: PayloadU16::new(context.clone()),
+                    target_protocol: ProtocolVersion::TLSv1_3,
+                    target_kdf: target_kdf(suite)?,
+                }
+                .get_encoding();
+                let secret = key_schedule::derive_imported_psk(
+                    provider,
+                    psk.hash_algorithm,
+                    &psk.secret,
+                    &identity,
+                    suite.hash_algorithm,
+                );
+                (identity, secret)
+            }
+        };
+
+        Some(Self {
+            provider,
+            psk: Arc::clone(psk),
+            identity,
+            secret,
+            suite,
+        })
+    }
+
+    /// The identity of the PSK, as it was made.
+    pub(crate) fn external_identity(&self) -> &[u8] {
+        &self.psk.identity
+    }
+
+    pub(crate) fn key_schedule(&self) -> KeyScheduleEarly {
+        KeyScheduleEarly::new(self.provider, self.suite.hash_algorithm, &self.secret)
+    }
+
+    /// The binder for this PSK, over a ClientHello whose (truncated)
+    /// transcript hash is `hs_hash`.
+    pub(crate) fn binder(&self, hs_hash: &Digest) -> Digest {
+        let kind = match self.psk.import_context {
+            Some(_) => PskKind::Imported,
+            None => PskKind::External,
+        };
+        self.key_schedule()
+            .psk_binder_key_and_sign_verify_data(kind, hs_hash)
+    }
+}
+
+/// The KDF which an imported PSK for `suite` targets.
+fn target_kdf(suite: &Tls13CipherSuite) -> Option<TlsKdf> {
+    match suite.get_hash() {
+        HashAlgorithm::SHA256 => Some(TlsKdf::HKDF_SHA256),
+        HashAlgorithm::SHA384 => Some(TlsKdf::HKDF_SHA384),
+        _ => None,
+    }
+}
+
+/// The PSKs a client offers to `server_name`, for the first of `suites`
+/// with each hash they can be used with.
+pub(crate) fn client_offers(
+    provider: &'static dyn CryptoProvider,
+    store: &dyn PskIdentityStore,
+    server_name: &ServerName,
+    suites: &[&'static Tls13CipherSuite],
+) -> Vec<PskOffer> {
+    let mut hash_suites: Vec<&'static Tls13CipherSuite> = Vec::new();
+    for suite in suites {
+        if !hash_suites
+            .iter()
+            .any(|prev| prev.get_hash() == suite.get_hash())
+        {
+            hash_suites.push(suite);
+        }
+    }
+
+    let mut offers = Vec::new();
+    for psk in store.psks_for_server(server_name) {
+        offers.extend(
+            hash_suites
+                .iter()
+                .filter_map(|suite| PskOffer::new(provider, &psk, suite)),
+        );
+    }
+    offers
+}
+
+/// Find the PSK a client offered as `identity`, for use with `suite`.
+pub(crate) fn server_find(
+    provider: &'static dyn CryptoProvider,
+    store: &dyn PskIdentityStore,
+    identity: &[u8],
+    suite: &'static Tls13CipherSuite,
+) -> Option<PskOffer> {
+    let direct = store
+        .psk_for_identity(identity)
+        .filter(|psk| psk.import_context.is_none())
+        .and_then(|psk| PskOffer::new(provider, &psk, suite));
+    if direct.is_some() {
+        return direct;
+    }
+
+    // Otherwise it might be imported: in which case everything in the
+    // identity must match what we would have sent.
+    let imported = ImportedIdentity::read_bytes(identity)?;
+    store
+        .psk_for_identity(&imported.external_identity.0)
+        .filter(|psk| psk.import_context.is_some())
+        .and_then(|psk| PskOffer::new(provider, &psk, suite))
+        .filter(|offer| offer.identity == identity)
+}
+
+/// The hash of the cipher suites which the PSK a client offered as
+/// `identity` can be used with, if we know it.
+pub(crate) fn server_hash(store: &dyn PskIdentityStore, identity: &[u8]) -> Option<HashAlgorithm> {
+    if let Some(psk) = store
+        .psk_for_identity(identity)
+        .filter(|psk| psk.import_context.is_none())
+    {
+        return Some(psk.get_hash());
+    }
+
+    let imported = ImportedIdentity::read_bytes(identity)?;
+    store
+        .psk_for_identity(&imported.external_identity.0)
+        .filter(|psk| psk.import_context.is_some())?;
+    match imported.target_kdf {
+        TlsKdf::HKDF_SHA256 => Some(HashAlgorithm::SHA256),
+        TlsKdf::HKDF_SHA384 => Some(HashAlgorithm::SHA384),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::ring::RING;
+    use crate::suites::TLS13_CHACHA20_POLY1305_SHA256;
+    use crate::suites::{TLS13_AES_128_GCM_SHA256, TLS13_AES_256_GCM_SHA384};
+    use std::convert::TryFrom;
+
+    fn suites() -> Vec<&'static Tls13CipherSuite> {
+        [
+            TLS13_AES_128_GCM_SHA256,
+            TLS13_CHACHA20_POLY1305_SHA256,
+            TLS13_AES_256_GCM_SHA384,
+        ]
+        .iter()
+        .map(|suite| suite.tls13().unwrap())
+        .collect()
+    }
+
+    fn store() -> Arc<PskMemoryStore> {
+        PskMemoryStore::new(vec![
+            ExternalPsk::new(b"plain".to_vec(), vec![1; 32]).unwrap(),
+            ExternalPsk::new_sha384(b"plain384".to_vec(), vec![2; 48]).unwrap(),
+            ExternalPsk::imported(b"imported".to_vec(), vec![3; 32], b"context".to_vec()).unwrap(),
+        ])
+    }
+
+    #[test]
+    fn rejects_bad_psks() {
+        assert!(ExternalPsk::new(Vec::new(), vec![1]).is_err());
+        assert!(ExternalPsk::new(vec![1], Vec::new()).is_err());
+        assert!(ExternalPsk::new(vec![1; 0x10000], vec![1]).is_err());
+        assert!(ExternalPsk::new(vec![1; 0xffff], vec![1]).is_ok());
+        assert!(ExternalPsk::imported(vec![1; 0xfff0], vec![1], vec![2; 0x10]).is_err());
+    }
+
+    #[test]
+    fn offers_psks_for_their_hashes() {
+        let server_name = ServerName::try_from("example.com").unwrap();
+        let suites = suites();
+        let offers = client_offers(&RING, &*store(), &server_name, &suites);
+
+        let offered: Vec<_> = offers
+            .iter()
+            .map(|offer| (offer.external_identity(), offer.suite.common.suite))
+            .collect();
+        assert_eq!(
+            offered,
+            vec![
+                (&b"plain"[..], suites[0].common.suite),
+                (&b"plain384"[..], suites[2].common.suite),
+                (&b"imported"[..], suites[0].common.suite),
+                (&b"imported"[..], suites[2].common.suite),
+            ]
+        );
+
+        assert_eq!(offers[0].identity, b"plain");
+        assert_eq!(offers[0].secret, vec![1; 32]);
+
+        // Each imported PSK has its own identity and secret.
+        assert_ne!(offers[2].identity, offers[3].identity);
+        assert_eq!(offers[2].secret.len(), 32);
+        assert_eq!(offers[3].secret.len(), 48);
+
+        let only_sha384 = client_offers(&RING, &*store(), &server_name, &suites[2..]);
+        assert_eq!(only_sha384.len(), 2);
+    }
+
+    #[test]
+    fn servers_find_offered_psks() {
+        let server_name = ServerName::try_from("example.com").unwrap();
+        let suites = suites();
+        let store = store();
+
+        for offer in client_offers(&RING, &*store, &server_name, &suites) {
+            let found = server_find(&RING, &*store, &offer.identity, offer.suite).unwrap();
+            assert_eq!(
+                server_hash(&*store, &offer.identity),
+                Some(offer.suite.get_hash())
+            );
+            assert_eq!(found.external_identity(), offer.external_identity());
+            assert_eq!(found.secret, offer.secret);
+
+            // A suite with another hash can't use it.
+            let other = match offer.suite.get_hash() == suites[0].get_hash() {
+                true => suites[2],
+                false => suites[0],
+            };
+            assert!(server_find(&RING, &*store, &offer.identity, other).is_none());
+        }
+
+        assert!(server_find(&RING, &*store, b"unknown", suites[0]).is_none());
+        assert!(server_hash(&*store, b"unknown").is_none());
+
+        // An imported identity must match exactly.
+        let wrong_context = ImportedIdentity {
+            external_identity: PayloadU16::new(b"imported".to_vec()),
+            context: PayloadU16::new(b"other".to_vec()),
+            target_protocol: ProtocolVersion::TLSv1_3,
+            target_kdf: TlsKdf::HKDF_SHA256,
+        };
+        assert!(server_find(&RING, &*store, &wrong_context.get_encoding(), suites[0]).is_none());
+
+        // And the PSK must have been made to be imported.
+        let not_imported = ImportedIdentity {
+            external_identity: PayloadU16::new(b"plain".to_vec()),
+            context: PayloadU16::new(Vec::new()),
+            target_protocol: ProtocolVersion::TLSv1_3,
+            target_kdf: TlsKdf::HKDF_SHA256,
+        };
+        assert!(server_find(&RING, &*store, &not_imported.get_encoding(), suites[0]).is_none());
+    }
+}
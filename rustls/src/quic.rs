@@ -1,15 +1,17 @@
 /// This module contains optional APIs for implementing QUIC TLS.
 pub use crate::cipher::Iv;
-use crate::cipher::IvLen;
 pub use crate::client::ClientQuicExt;
 use crate::conn::ConnectionCommon;
+use crate::crypto;
 use crate::error::Error;
-use crate::key_schedule::hkdf_expand;
+use crate::key_schedule::Prk;
 use crate::msgs::base::Payload;
 use crate::msgs::enums::{AlertDescription, ContentType, ProtocolVersion};
 use crate::msgs::message::PlainMessage;
 pub use crate::server::ServerQuicExt;
-use crate::suites::{BulkAlgorithm, Tls13CipherSuite, TLS13_AES_128_GCM_SHA256_INTERNAL};
+use crate::suites::{
+    BulkAlgorithm, SupportedCipherSuite, Tls13CipherSuite, TLS13_AES_128_GCM_SHA256_INTERNAL,
+};
 
 use ring::{aead, hkdf};
 
@@ -23,6 +25,13 @@ pub(crate) struct Secrets {
 }
 
 impl Secrets {
+    pub(crate) fn new(client: &Prk, server: &Prk) -> Self {
+        Self {
+            client: ring_prk(client),
+            server: ring_prk(server),
+        }
+    }
+
     fn local_remote(&self, is_client: bool) -> (&hkdf::Prk, &hkdf::Prk) {
         if is_client {
             (&self.client, &self.server)
@@ -77,11 +86,7 @@ pub struct DirectionalKeys {
 
 impl DirectionalKeys {
     pub(crate) fn new(suite: &'static Tls13CipherSuite, secret: &hkdf::Prk) -> Self {
-        let hp_alg = match suite.common.bulk {
-            BulkAlgorithm::Aes128Gcm => &aead::quic::AES_128,
-            BulkAlgorithm::Aes256Gcm => &aead::quic::AES_256,
-            BulkAlgorithm::Chacha20Poly1305 => &aead::quic::CHACHA20,
-        };
+        let (hp_alg, _) = algorithms(suite);
 
         Self {
             header: hkdf_expand(secret, hp_alg, b"quic hp", &[]),
@@ -96,20 +101,32 @@ pub struct PacketKey {
     pub key: aead::LessSafeKey,
     /// Computes unique nonces for each packet
     pub iv: Iv,
+    suite: &'static Tls13CipherSuite,
 }
 
 impl PacketKey {
     fn new(suite: &'static Tls13CipherSuite, secret: &hkdf::Prk) -> Self {
         Self {
-            key: aead::LessSafeKey::new(hkdf_expand(
-                secret,
-                suite.common.aead_algorithm,
-                b"quic key",
-                &[],
-            )),
+            suite,
+            key: aead::LessSafeKey::new(hkdf_expand(secret, algorithms(suite).1, b"quic key", &[])),
             iv: hkdf_expand(secret, IvLen, b"quic iv", &[]),
         }
     }
+    /// Number of packets which may be encrypted with this key before it
+    /// must be replaced by a key update.
+    ///
+    /// See RFC9001 section 6.6.
+    pub fn confidentiality_limit(&self) -> u64 {
+        self.suite.confidentiality_limit
+    }
+
+    /// Number of packets which may fail to decrypt with this key before
+    /// the connection must be closed.
+    ///
+    /// See RFC9001 section 6.6.
+    pub fn integrity_limit(&self) -> u64 {
+        self.suite.integrity_limit
+    }
 }
 
 /// Packet protection keys for bidirectional 1-RTT communication
@@ -204,7 +221,7 @@ pub(crate) fn next_1rtt_keys(this: &mut ConnectionCommon) -> Option<PacketKeySet
         .get_suite()
         .and_then(|suite| suite.tls13())?;
     let secrets = this.quic.traffic_secrets.as_ref()?;
-    let next = next_1rtt_secrets(suite.hkdf_algorithm, secrets);
+    let next = next_1rtt_secrets(crypto::ring::hkdf_algorithm(suite.hash_algorithm), secrets);
 
     let (local, remote) = next.local_remote(this.is_client);
     let keys = PacketKeySet {
@@ -223,6 +240,103 @@ fn next_1rtt_secrets(hkdf_alg: hkdf::Algorithm, prev: &Secrets) -> Secrets {
     }
 }
 
+/// QUIC packet protection uses *ring*, whatever the crypto provider, so
+/// secrets from the key schedule are converted into *ring* keys.
+pub(crate) fn ring_prk(secret: &Prk) -> hkdf::Prk {
+    hkdf::Prk::new_less_safe(
+        crypto::ring::hkdf_algorithm(secret.algorithm()),
+        secret.as_bytes(),
+    )
+}
+
+/// QUIC packet protection uses *ring*, so only cipher suites whose AEAD
+/// is one of the [`BulkAlgorithm`]s *ring* has can be used.
+pub(crate) fn check_suites(suites: &[SupportedCipherSuite]) -> Result<(), Error> {
+    match suites
+        .iter()
+        .filter_map(|suite| suite.tls13())
+        .find(|suite| {
+            suite
+                .aead_alg
+                .bulk()
+                .and_then(ring_algorithms)
+                .is_none()
+        }) {
+        Some(suite) => Err(Error::General(format!(
+            "{:?} cannot be used with QUIC",
+            suite.common.suite
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// The header and packet protection algorithms for `suite`.
+fn algorithms(
+    suite: &Tls13CipherSuite,
+) -> (&'static aead::quic::Algorithm, &'static aead::Algorithm) {
+    // Won't panic: `check_suites` rejects configs with any other suites.
+    suite
+        .aead_alg
+        .bulk()
+        .and_then(ring_algorithms)
+        .unwrap()
+}
+
+fn ring_algorithms(
+    bulk: &BulkAlgorithm,
+) -> Option<(&'static aead::quic::Algorithm, &'static aead::Algorithm)> {
+    match bulk {
+        BulkAlgorithm::Aes128Gcm => Some((&aead::quic::AES_128, &aead::AES_128_GCM)),
+        BulkAlgorithm::Aes256Gcm => Some((&aead::quic::AES_256, &aead::AES_256_GCM)),
+        BulkAlgorithm::Chacha20Poly1305 => Some((&aead::quic::CHACHA20, &aead::CHACHA20_POLY1305)),
+        BulkAlgorithm::Aes128Ccm
+        | BulkAlgorithm::Aes256Ccm
+        | BulkAlgorithm::Aes128Ccm8
+        | BulkAlgorithm::Aes256Ccm8 => None,
+    }
+}
+
+fn hkdf_expand<T, L>(secret: &hkdf::Prk, key_type: L, label: &[u8], context: &[u8]) -> T
+where
+    T: for<'a> From<hkdf::Okm<'a, L>>,
+    L: hkdf::KeyType,
+{
+    const LABEL_PREFIX: &[u8] = b"tls13 ";
+
+    let output_len = u16::to_be_bytes(key_type.len() as u16);
+    let label_len = u8::to_be_bytes((LABEL_PREFIX.len() + label.len()) as u8);
+    let context_len = u8::to_be_bytes(context.len() as u8);
+
+    let info = &[
+        &output_len[..],
+        &label_len[..],
+        LABEL_PREFIX,
+        label,
+        &context_len[..],
+        context,
+    ];
+    secret
+        .expand(info, key_type)
+        .unwrap()
+        .into()
+}
+
+struct IvLen;
+
+impl hkdf::KeyType for IvLen {
+    fn len(&self) -> usize {
+        aead::NONCE_LEN
+    }
+}
+
+impl From<hkdf::Okm<'_, IvLen>> for Iv {
+    fn from(okm: hkdf::Okm<IvLen>) -> Self {
+        let mut iv = [0u8; aead::NONCE_LEN];
+        okm.fill(&mut iv[..]).unwrap();
+        Self::new(iv)
+    }
+}
+
 /// QUIC protocol version
 ///
 /// Governs version-specific behavior in the TLS layer
@@ -400,6 +514,19 @@ mod test {
         );
     }
 
+    #[test]
+    fn initial_keys_have_aes_gcm_limits() {
+        let initial_salt = hkdf::Salt::new(hkdf::HKDF_SHA256, &[0u8; 20]);
+        let keys = Keys::initial(&initial_salt, &[1, 2, 3, 4], true);
+        assert_eq!(
+            keys.local
+                .packet
+                .confidentiality_limit(),
+            1 << 23
+        );
+        assert_eq!(keys.remote.packet.integrity_limit(), 1 << 52);
+    }
+
     #[test]
     fn key_update_test_vector() {
         fn equal_prk(x: &hkdf::Prk, y: &hkdf::Prk) -> bool {
@@ -1,10 +1,18 @@
 use crate::cipher::{MessageDecrypter, MessageEncrypter};
 use crate::error::Error;
+#[cfg(feature = "logging")]
+use crate::log::trace;
 use crate::msgs::message::{BorrowedPlainMessage, OpaqueMessage, PlainMessage};
 
 static SEQ_SOFT_LIMIT: u64 = 0xffff_ffff_ffff_0000u64;
 static SEQ_HARD_LIMIT: u64 = 0xffff_ffff_ffff_fffeu64;
 
+/// How far ahead of the confidentiality limit we start asking for a
+/// TLS1.3 KeyUpdate.  One can be sent before any record of application
+/// data, so this only needs to cover plaintext buffered during the
+/// handshake, which is sent without that chance.
+static KEY_UPDATE_MARGIN: u64 = 0x1_0000;
+
 #[derive(PartialEq)]
 enum DirectionState {
     /// No keying material.
@@ -24,6 +32,14 @@ pub(crate) struct RecordLayer {
     read_seq: u64,
     encrypt_state: DirectionState,
     decrypt_state: DirectionState,
+
+    /// How many messages we may encrypt under one key; see
+    /// `set_confidentiality_limit`.
+    confidentiality_limit: u64,
+
+    /// Bytes of undecryptable records we may still skip; see
+    /// `set_trial_decryption`.
+    trial_decryption_len: Option<usize>,
 }
 
 impl RecordLayer {
@@ -35,6 +51,8 @@ impl RecordLayer {
             read_seq: 0,
             encrypt_state: DirectionState::Invalid,
             decrypt_state: DirectionState::Invalid,
+            confidentiality_limit: u64::MAX,
+            trial_decryption_len: None,
         }
     }
 
@@ -46,6 +64,16 @@ impl RecordLayer {
         self.decrypt_state == DirectionState::Active
     }
 
+    /// The sequence number of the next record we encrypt.
+    pub(crate) fn write_seq(&self) -> u64 {
+        self.write_seq
+    }
+
+    /// The sequence number of the next record we decrypt.
+    pub(crate) fn read_seq(&self) -> u64 {
+        self.read_seq
+    }
+
     /// Prepare to use the given `MessageEncrypter` for future message encryption.
     /// It is not used until you call `start_encrypting`.
     pub(crate) fn prepare_message_encrypter(&mut self, cipher: Box<dyn MessageEncrypter>) {
@@ -60,6 +88,7 @@ impl RecordLayer {
         self.message_decrypter = cipher;
         self.read_seq = 0;
         self.decrypt_state = DirectionState::Prepared;
+        self.trial_decryption_len = None;
     }
 
     /// Start using the `MessageEncrypter` previously provided to the previous
@@ -90,6 +119,45 @@ impl RecordLayer {
         self.start_decrypting();
     }
 
+    /// Limit the number of messages we encrypt under one key to `limit`,
+    /// the confidentiality limit of the AEAD in use.
+    ///
+    /// This is for TLS1.3, where keys can be replaced before they wear
+    /// out: see `wants_key_update`.  Without it, only the sequence number
+    /// limits apply.
+    pub(crate) fn set_confidentiality_limit(&mut self, limit: u64) {
+        self.confidentiality_limit = limit;
+    }
+
+    /// Enable "trial decryption" mode, for when TLS1.3 0-RTT is attempted
+    /// but rejected by the server.
+    ///
+    /// Up to `max_length` bytes of records which fail to decrypt with the
+    /// current `MessageDecrypter` are then skipped, rather than treated as an
+    /// error.  If there is no `MessageDecrypter` yet (because the server sent
+    /// a HelloRetryRequest), application data records are skipped instead.
+    ///
+    /// This mode ends when a new `MessageDecrypter` is installed, or when
+    /// a record is successfully decrypted.
+    pub(crate) fn set_trial_decryption(&mut self, max_length: usize) {
+        self.trial_decryption_len = Some(max_length);
+    }
+
+    /// Return true if a record of `requested` bytes which could not be
+    /// processed may be skipped, and account for it.
+    pub(crate) fn doing_trial_decryption(&mut self, requested: usize) -> bool {
+        match self
+            .trial_decryption_len
+            .and_then(|value| value.checked_sub(requested))
+        {
+            Some(remaining) => {
+                self.trial_decryption_len = Some(remaining);
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Return true if the peer appears to getting close to encrypting
     /// too many messages with this key.
     ///
@@ -102,29 +170,62 @@ impl RecordLayer {
         self.read_seq == SEQ_SOFT_LIMIT
     }
 
+    /// Return true if we should replace our encryption key soon, as
+    /// it is nearing its confidentiality limit.
+    pub(crate) fn wants_key_update(&self) -> bool {
+        self.write_seq
+            >= self
+                .confidentiality_limit
+                .saturating_sub(KEY_UPDATE_MARGIN)
+    }
+
     /// Return true if we are getting close to encrypting too many
     /// messages with our encryption key.
+    ///
+    /// This leaves room for one more message, so we can send a
+    /// `close_notify` alert.
     pub(crate) fn wants_close_before_encrypt(&self) -> bool {
-        self.write_seq == SEQ_SOFT_LIMIT
+        self.write_seq
+            == SEQ_SOFT_LIMIT.min(
+                self.confidentiality_limit
+                    .saturating_sub(1),
+            )
     }
 
     /// Return true if we outright refuse to do anything with the
     /// encryption key.
     pub(crate) fn encrypt_exhausted(&self) -> bool {
-        self.write_seq >= SEQ_HARD_LIMIT
+        self.write_seq >= SEQ_HARD_LIMIT.min(self.confidentiality_limit)
     }
 
     /// Decrypt a TLS message.
     ///
     /// `encr` is a decoded message allegedly received from the peer.
-    /// If it can be decrypted, its decryption is returned.  Otherwise,
-    /// an error is returned.
-    pub(crate) fn decrypt_incoming(&mut self, encr: OpaqueMessage) -> Result<PlainMessage, Error> {
+    /// If it can be decrypted, its decryption is returned.  If it cannot
+    /// be decrypted but we are doing trial decryption, `None` is returned
+    /// and the message should be dropped.  Otherwise, an error is returned.
+    pub(crate) fn decrypt_incoming(
+        &mut self,
+        encr: OpaqueMessage,
+    ) -> Result<Option<PlainMessage>, Error> {
         debug_assert!(self.decrypt_state == DirectionState::Active);
         let seq = self.read_seq;
-        self.read_seq += 1;
-        self.message_decrypter
+        let encrypted_len = encr.payload.0.len();
+        match self
+            .message_decrypter
             .decrypt(encr, seq)
+        {
+            Ok(plaintext) => {
+                self.read_seq += 1;
+                self.trial_decryption_len = None;
+                Ok(Some(plaintext))
+            }
+            Err(Error::DecryptError) if self.doing_trial_decryption(encrypted_len) => {
+                trace!("Dropping undecryptable message after aborted early_data");
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
     }
 
     /// Encrypt a TLS message.
@@ -141,3 +242,62 @@ impl RecordLayer {
             .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_number_limits_apply_by_default() {
+        let mut record_layer = RecordLayer::new();
+        assert!(!record_layer.wants_key_update());
+        assert!(!record_layer.wants_close_before_encrypt());
+        assert!(!record_layer.encrypt_exhausted());
+
+        record_layer.write_seq = SEQ_SOFT_LIMIT;
+        assert!(record_layer.wants_close_before_encrypt());
+        assert!(!record_layer.encrypt_exhausted());
+
+        record_layer.write_seq = SEQ_HARD_LIMIT;
+        assert!(record_layer.encrypt_exhausted());
+    }
+
+    #[test]
+    fn confidentiality_limit_applies() {
+        let limit = 1 << 23;
+        let mut record_layer = RecordLayer::new();
+        record_layer.set_confidentiality_limit(limit);
+
+        record_layer.write_seq = limit - KEY_UPDATE_MARGIN - 1;
+        assert!(!record_layer.wants_key_update());
+
+        record_layer.write_seq = limit - KEY_UPDATE_MARGIN;
+        assert!(record_layer.wants_key_update());
+        assert!(!record_layer.wants_close_before_encrypt());
+
+        record_layer.write_seq = limit - 1;
+        assert!(record_layer.wants_close_before_encrypt());
+        assert!(!record_layer.encrypt_exhausted());
+
+        record_layer.write_seq = limit;
+        assert!(record_layer.encrypt_exhausted());
+
+        // a new key starts afresh
+        record_layer.set_message_encrypter(<dyn MessageEncrypter>::invalid());
+        assert!(!record_layer.wants_key_update());
+        assert!(!record_layer.encrypt_exhausted());
+    }
+
+    #[test]
+    fn confidentiality_limit_is_capped_by_sequence_numbers() {
+        let mut record_layer = RecordLayer::new();
+        record_layer.set_confidentiality_limit(u64::MAX);
+
+        record_layer.write_seq = SEQ_SOFT_LIMIT;
+        assert!(record_layer.wants_key_update());
+        assert!(record_layer.wants_close_before_encrypt());
+
+        record_layer.write_seq = SEQ_HARD_LIMIT;
+        assert!(record_layer.encrypt_exhausted());
+    }
+}
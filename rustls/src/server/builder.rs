@@ -1,7 +1,13 @@
+use crate::builder;
+use crate::compress;
+use crate::conn::PeerMessageLimits;
+use crate::crypto::CryptoProvider;
 use crate::error::Error;
 use crate::key;
 use crate::keylog::NoKeyLog;
 use crate::kx::SupportedKxGroup;
+use crate::msgs::enums::{PSKKeyExchangeMode, SignatureScheme};
+use crate::psk::PskIdentityStore;
 use crate::server::handy;
 use crate::server::{ResolvesServerCert, ServerConfig};
 use crate::suites::SupportedCipherSuite;
@@ -13,9 +19,11 @@ use std::sync::Arc;
 /// A server config in progress, where the next step is to configure whether
 /// and how to authenticate clients.
 pub struct ConfigWantsClientVerifier {
+    pub(crate) provider: &'static dyn CryptoProvider,
     pub(crate) cipher_suites: Vec<SupportedCipherSuite>,
     pub(crate) kx_groups: Vec<&'static SupportedKxGroup>,
     pub(crate) versions: versions::EnabledVersions,
+    pub(crate) signature_schemes: Vec<SignatureScheme>,
 }
 
 impl ConfigWantsClientVerifier {
@@ -25,9 +33,11 @@ impl ConfigWantsClientVerifier {
         client_cert_verifier: Arc<dyn verify::ClientCertVerifier>,
     ) -> ConfigWantsServerCert {
         ConfigWantsServerCert {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             versions: self.versions,
+            signature_schemes: self.signature_schemes,
             verifier: client_cert_verifier,
         }
     }
@@ -41,9 +51,11 @@ impl ConfigWantsClientVerifier {
 /// A config builder for a server, where we want to know how to provide a
 /// server certificate to a connecting peer.
 pub struct ConfigWantsServerCert {
+    provider: &'static dyn CryptoProvider,
     cipher_suites: Vec<SupportedCipherSuite>,
     kx_groups: Vec<&'static SupportedKxGroup>,
     versions: versions::EnabledVersions,
+    signature_schemes: Vec<SignatureScheme>,
     verifier: Arc<dyn verify::ClientCertVerifier>,
 }
 
@@ -60,13 +72,15 @@ impl ConfigWantsServerCert {
     /// `cert_chain` is a vector of DER-encoded certificates.
     /// `key_der` is a DER-encoded RSA, ECDSA, or Ed25519 private key.
     ///
-    /// This function fails if `key_der` is invalid.
+    /// This function fails if `key_der` is invalid, or cannot sign with
+    /// any of the enabled signature schemes.
     pub fn with_single_cert(
         self,
         cert_chain: Vec<key::Certificate>,
         key_der: key::PrivateKey,
     ) -> Result<ServerConfig, Error> {
-        let resolver = handy::AlwaysResolvesChain::new(cert_chain, &key_der)?;
+        let resolver = handy::AlwaysResolvesChain::new(self.provider, cert_chain, &key_der)?;
+        self.validate_signing_key(&resolver)?;
         Ok(self.with_cert_resolver(Arc::new(resolver)))
     }
 
@@ -80,22 +94,68 @@ impl ConfigWantsServerCert {
     /// `scts` is an `SignedCertificateTimestampList` encoding (see RFC6962)
     /// and is ignored if empty.
     ///
-    /// This function fails if `key_der` is invalid.
+    /// This function fails if `key_der` is invalid, or cannot sign with
+    /// any of the enabled signature schemes.
     pub fn with_single_cert_with_ocsp_and_sct(
         self,
         cert_chain: Vec<key::Certificate>,
         key_der: key::PrivateKey,
         ocsp: Vec<u8>,
         scts: Vec<u8>,
+    ) -> Result<ServerConfig, Error> {
+        let resolver = handy::AlwaysResolvesChain::new_with_extras(
+            self.provider,
+            cert_chain,
+            &key_der,
+            ocsp,
+            scts,
+        )?;
+        self.validate_signing_key(&resolver)?;
+        Ok(self.with_cert_resolver(Arc::new(resolver)))
+    }
+
+    /// Sets a raw public key (RFC7250) and matching private key, in
+    /// place of a certificate chain.  Only TLS1.3 clients which support
+    /// raw public keys can connect.
+    ///
+    /// `spki` is the DER-encoded SubjectPublicKeyInfo of the key.
+    /// `key_der` is a DER-encoded RSA, ECDSA, or Ed25519 private key.
+    ///
+    /// This function fails if `key_der` is invalid, or cannot sign with
+    /// any of the enabled signature schemes.
+    pub fn with_raw_public_key(
+        self,
+        spki: Vec<u8>,
+        key_der: key::PrivateKey,
     ) -> Result<ServerConfig, Error> {
         let resolver =
-            handy::AlwaysResolvesChain::new_with_extras(cert_chain, &key_der, ocsp, scts)?;
+            handy::AlwaysResolvesChain::new_raw_public_key(self.provider, spki, &key_der)?;
+        self.validate_signing_key(&resolver)?;
         Ok(self.with_cert_resolver(Arc::new(resolver)))
     }
 
+    fn validate_signing_key(&self, resolver: &handy::AlwaysResolvesChain) -> Result<(), Error> {
+        builder::validate_signing_key(
+            resolver.0.key.as_ref(),
+            &self.cipher_suites,
+            &self.versions,
+            &self.signature_schemes,
+        )
+    }
+
+    /// Authenticate only with the external pre-shared keys in `psk_store`,
+    /// and have no certificate.  Only TLS1.3 clients which offer one of
+    /// these PSKs can connect.
+    pub fn with_external_psks(self, psk_store: Arc<dyn PskIdentityStore>) -> ServerConfig {
+        let mut config = self.with_cert_resolver(Arc::new(handy::NeverResolvesChain {}));
+        config.psk_store = Some(psk_store);
+        config
+    }
+
     /// Sets a custom [`ResolvesServerCert`].
     pub fn with_cert_resolver(self, cert_resolver: Arc<dyn ResolvesServerCert>) -> ServerConfig {
         ServerConfig {
+            provider: self.provider,
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             verifier: self.verifier,
@@ -107,8 +167,15 @@ impl ConfigWantsServerCert {
             alpn_protocols: Vec::new(),
             versions: self.versions,
             key_log: Arc::new(NoKeyLog {}),
-            #[cfg(feature = "quic")]
             max_early_data_size: 0,
+            peer_message_limits: PeerMessageLimits::default(),
+            cert_compressors: Vec::new(),
+            cert_decompressors: Vec::new(),
+            max_decompressed_cert_size: compress::DEFAULT_MAX_DECOMPRESSED_CERT_SIZE,
+            ech_keys: Vec::new(),
+            psk_store: None,
+            psk_key_exchange_modes: vec![PSKKeyExchangeMode::PSK_DHE_KE],
+            signature_schemes: self.signature_schemes,
         }
     }
 }
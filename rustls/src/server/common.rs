@@ -1,3 +1,5 @@
+use crate::delegated::DelegatedCredential;
+use crate::msgs::enums::CertificateType;
 use crate::{key, sign};
 
 /// ActiveCertifiedKey wraps CertifiedKey and tracks OSCP and SCT state
@@ -38,4 +40,19 @@ impl<'a> ActiveCertifiedKey<'a> {
     pub(super) fn get_sct_list(&self) -> Option<&[u8]> {
         self.sct_list
     }
+
+    #[inline]
+    pub(super) fn get_delegated_credential(&self) -> Option<&DelegatedCredential> {
+        self.key.delegated_credential.as_ref()
+    }
+
+    #[inline]
+    pub(super) fn get_raw_public_key(&self) -> Option<&[u8]> {
+        self.key.raw_public_key.as_deref()
+    }
+
+    #[inline]
+    pub(super) fn certificate_types(&self) -> Vec<CertificateType> {
+        self.key.certificate_types()
+    }
 }
@@ -1,3 +1,4 @@
+use crate::crypto::CryptoProvider;
 use crate::error::Error;
 use crate::key;
 use crate::limited_cache;
@@ -81,17 +82,28 @@ impl server::ProducesTickets for NeverProducesTickets {
     }
 }
 
+/// Something which never resolves a cert chain.
+pub(super) struct NeverResolvesChain {}
+
+impl server::ResolvesServerCert for NeverResolvesChain {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        None
+    }
+}
+
 /// Something which always resolves to the same cert chain.
-pub(super) struct AlwaysResolvesChain(Arc<sign::CertifiedKey>);
+pub(super) struct AlwaysResolvesChain(pub(super) Arc<sign::CertifiedKey>);
 
 impl AlwaysResolvesChain {
     /// Creates an `AlwaysResolvesChain`, auto-detecting the underlying private
     /// key type and encoding.
     pub(super) fn new(
+        provider: &dyn CryptoProvider,
         chain: Vec<key::Certificate>,
         priv_key: &key::PrivateKey,
     ) -> Result<Self, Error> {
-        let key = sign::any_supported_type(priv_key)
+        let key = provider
+            .load_private_key(priv_key)
             .map_err(|_| Error::General("invalid private key".into()))?;
         Ok(Self(Arc::new(sign::CertifiedKey::new(chain, key))))
     }
@@ -101,12 +113,13 @@ impl AlwaysResolvesChain {
     ///
     /// If non-empty, the given OCSP response and SCTs are attached.
     pub(super) fn new_with_extras(
+        provider: &dyn CryptoProvider,
         chain: Vec<key::Certificate>,
         priv_key: &key::PrivateKey,
         ocsp: Vec<u8>,
         scts: Vec<u8>,
     ) -> Result<Self, Error> {
-        let mut r = Self::new(chain, priv_key)?;
+        let mut r = Self::new(provider, chain, priv_key)?;
 
         {
             let cert = Arc::make_mut(&mut r.0);
@@ -120,6 +133,21 @@ impl AlwaysResolvesChain {
 
         Ok(r)
     }
+
+    /// Creates an `AlwaysResolvesChain` which only has a raw public key,
+    /// `spki`, auto-detecting the underlying private key type and encoding.
+    pub(super) fn new_raw_public_key(
+        provider: &dyn CryptoProvider,
+        spki: Vec<u8>,
+        priv_key: &key::PrivateKey,
+    ) -> Result<Self, Error> {
+        let key = provider
+            .load_private_key(priv_key)
+            .map_err(|_| Error::General("invalid private key".into()))?;
+        Ok(Self(Arc::new(sign::CertifiedKey::from_raw_public_key(
+            spki, key,
+        ))))
+    }
 }
 
 impl server::ResolvesServerCert for AlwaysResolvesChain {
@@ -1,5 +1,7 @@
 use crate::conn::{ConnectionCommon, ConnectionRandoms};
+use crate::ech;
 use crate::error::Error;
+use crate::grease;
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
@@ -9,9 +11,10 @@ use crate::msgs::enums::{ContentType, HandshakeType, ProtocolVersion};
 use crate::msgs::handshake::SessionID;
 use crate::msgs::handshake::{ClientHelloPayload, Random, ServerExtension};
 use crate::msgs::handshake::{ConvertProtocolNameList, ConvertServerNameList};
-use crate::msgs::handshake::{HandshakePayload, SupportedSignatureSchemes};
+use crate::msgs::handshake::{HandshakePayload, KeyExchangeAlgorithm, SupportedSignatureSchemes};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::psk;
 use crate::server::{ClientHello, ServerConfig};
 use crate::suites;
 use crate::SupportedCipherSuite;
@@ -36,7 +39,21 @@ pub(super) trait State: Send + Sync {
         Err(Error::HandshakeNotComplete)
     }
 
-    fn perhaps_write_key_update(&mut self, _common: &mut ConnectionCommon) {}
+    fn extract_secrets(&self) -> Result<suites::PartiallyExtractedSecrets, Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
+    fn perhaps_write_key_update(&mut self, _common: &mut ConnectionCommon) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        _common: &mut ConnectionCommon,
+        _request_peer_update: bool,
+    ) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
 }
 
 impl<'a> crate::conn::HandleState for Box<dyn State> {
@@ -108,8 +125,6 @@ impl ExtensionProcessing {
         &mut self,
         config: &ServerConfig,
         cx: &mut ServerContext<'_>,
-        #[allow(unused_variables)] // #[cfg(feature = "quic")] only
-        suite: SupportedCipherSuite,
         ocsp_response: &mut Option<&[u8]>,
         sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
@@ -146,6 +161,15 @@ impl ExtensionProcessing {
             }
         }
 
+        // A resumed session may change protocol, but then early data sent
+        // under the old one cannot be accepted.
+        if let Some(resume) = resumedata {
+            if resume.alpn.as_ref().map(|x| &x.0) != cx.common.alpn_protocol.as_ref() {
+                debug!("ALPN protocol differs from resumed session");
+                cx.data.early_data.reject();
+            }
+        }
+
         #[cfg(feature = "quic")]
         {
             if cx.common.is_quic() {
@@ -157,22 +181,6 @@ impl ExtensionProcessing {
                             .missing_extension("QUIC transport parameters not found"));
                     }
                 }
-
-                if let Some(resume) = resumedata {
-                    if config.max_early_data_size > 0
-                        && hello.early_data_extension_offered()
-                        && resume.version == cx.common.negotiated_version.unwrap()
-                        && resume.cipher_suite == suite.suite()
-                        && resume.alpn.as_ref().map(|x| &x.0) == cx.common.alpn_protocol.as_ref()
-                        && !cx.data.reject_early_data
-                    {
-                        self.exts
-                            .push(ServerExtension::EarlyData);
-                    } else {
-                        // Clobber value set in tls13::emit_server_hello
-                        cx.common.quic.early_secret = None;
-                    }
-                }
             }
         }
 
@@ -273,6 +281,7 @@ pub(super) struct ExpectClientHello {
     pub(super) using_ems: bool,
     pub(super) done_retry: bool,
     pub(super) send_ticket: bool,
+    pub(super) ech: Option<ech::EchAccepted>,
 }
 
 impl ExpectClientHello {
@@ -291,17 +300,35 @@ impl ExpectClientHello {
             using_ems: false,
             done_retry: false,
             send_ticket: false,
+            ech: None,
         }
     }
 }
 
 impl State for ExpectClientHello {
-    fn handle(self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> NextStateOrError {
-        let client_hello =
-            require_handshake_msg!(m, HandshakeType::ClientHello, HandshakePayload::ClientHello)?;
+    fn handle(mut self: Box<Self>, cx: &mut ServerContext<'_>, mut m: Message) -> NextStateOrError {
+        require_handshake_msg!(m, HandshakeType::ClientHello, HandshakePayload::ClientHello)?;
         let tls13_enabled = self
             .config
             .supports_version(ProtocolVersion::TLSv1_3);
+
+        // If the client offered ECH and we can decrypt its inner
+        // ClientHello, carry on with that instead.
+        if tls13_enabled {
+            if let Some((inner, accepted)) = ech::server_open(
+                self.config.provider,
+                &self.config.ech_keys,
+                &m,
+                self.ech.take(),
+                cx.common,
+            )? {
+                m = inner;
+                self.ech = Some(accepted);
+            }
+        }
+
+        let client_hello =
+            require_handshake_msg!(m, HandshakeType::ClientHello, HandshakePayload::ClientHello)?;
         let tls12_enabled = self
             .config
             .supports_version(ProtocolVersion::TLSv1_2);
@@ -362,6 +389,22 @@ impl State for ExpectClientHello {
             ProtocolVersion::TLSv1_2
         };
 
+        // A client which has retried with a lower version sends
+        // TLS_FALLBACK_SCSV (RFC7507).  If we support something better,
+        // its earlier attempt was interfered with.
+        if version == ProtocolVersion::TLSv1_2
+            && tls13_enabled
+            && client_hello
+                .cipher_suites
+                .contains(&CipherSuite::TLS_FALLBACK_SCSV)
+        {
+            cx.common
+                .send_fatal_alert(AlertDescription::InappropriateFallback);
+            return Err(Error::PeerIncompatibleError(
+                "client fell back to TLS1.2, but TLS1.3 is available".to_string(),
+            ));
+        }
+
         cx.common.negotiated_version = Some(version);
 
         // --- Common to TLS1.2 and TLS1.3: ciphersuite and certificate selection.
@@ -419,12 +462,23 @@ impl State for ExpectClientHello {
             .get_sigalgs_extension()
             .cloned()
             .unwrap_or_else(SupportedSignatureSchemes::default);
-        sigschemes_ext
-            .retain(|scheme| suites::compatible_sigscheme_for_suites(*scheme, &common_suites));
+        // GREASE values are meaningless, so the cert resolver doesn't see them.
+        sigschemes_ext.retain(|scheme| {
+            !grease::is_grease(scheme.get_u16())
+                && self
+                    .config
+                    .signature_schemes
+                    .contains(scheme)
+                && suites::compatible_sigscheme_for_suites(*scheme, &common_suites)
+        });
 
         let alpn_protocols = client_hello
             .get_alpn_extension()
-            .map(|protos| protos.to_slices());
+            .map(|protos| {
+                let mut protos = protos.to_slices();
+                protos.retain(|proto| !grease::is_grease_protocol(proto));
+                protos
+            });
 
         // Choose a certificate.
         let certkey = {
@@ -435,29 +489,91 @@ impl State for ExpectClientHello {
             trace!("sig schemes {:?}", sigschemes_ext);
             trace!("alpn protocols {:?}", alpn_protocols);
 
-            let alpn_slices = alpn_protocols.as_deref();
-            let client_hello = ClientHello::new(sni_ref, &sigschemes_ext, alpn_slices);
+            let client_hello = ClientHello::new(sni_ref, &sigschemes_ext, alpn_protocols);
 
-            let certkey = self
+            match self
                 .config
                 .cert_resolver
-                .resolve(client_hello);
-            certkey.ok_or_else(|| {
-                cx.common
-                    .send_fatal_alert(AlertDescription::AccessDenied);
-                Error::General("no server certificate chain resolved".to_string())
-            })?
+                .resolve(client_hello)
+            {
+                Some(certkey) => Some(certkey),
+                // A TLS1.3 client might instead authenticate us with an
+                // external PSK: we'll find out when we look at its offer.
+                None if version == ProtocolVersion::TLSv1_3 && self.config.psk_store.is_some() => {
+                    None
+                }
+                None => {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::AccessDenied);
+                    return Err(Error::General(
+                        "no server certificate chain resolved".to_string(),
+                    ));
+                }
+            }
         };
-        let certkey = ActiveCertifiedKey::from_certified_key(&certkey);
+        let certkey = certkey
+            .as_deref()
+            .map(ActiveCertifiedKey::from_certified_key);
+
+        // Raw public keys are only supported in TLS1.3.
+        let has_chain = certkey
+            .as_ref()
+            .map(|certkey| !certkey.get_cert().is_empty());
+        if version == ProtocolVersion::TLSv1_2 && has_chain != Some(true) {
+            return Err(incompatible(
+                cx.common,
+                "no certificate chain for TLS1.2 client",
+            ));
+        }
 
         // Reduce our supported ciphersuites by the certificate.
         // (no-op for TLS1.3)
-        let suitable_suites =
-            suites::reduce_given_sigalg(&self.config.cipher_suites, certkey.get_key().algorithm());
+        let suitable_suites = match &certkey {
+            Some(certkey) => suites::reduce_given_sigalg(
+                &self.config.cipher_suites,
+                certkey.get_key().algorithm(),
+            ),
+            None => self.config.cipher_suites.clone(),
+        };
 
         // And version
         let suitable_suites = suites::reduce_given_version(&suitable_suites, version);
 
+        // And whether we have a group for DHE (RFC7919 section 4).
+        let client_groups = client_hello
+            .get_namedgroups_extension()
+            .map(|groups| groups.as_slice())
+            .unwrap_or_default();
+        let suitable_suites: Vec<_> = suitable_suites
+            .into_iter()
+            .filter(|scs| match scs {
+                SupportedCipherSuite::Tls12(suite) if suite.kx == KeyExchangeAlgorithm::DHE => {
+                    crate::tls12::choose_kx_group(&self.config.kx_groups, &suite.kx, client_groups)
+                        .is_some()
+                }
+                _ => true,
+            })
+            .collect();
+
+        // An external PSK the client offered can only be used with suites
+        // which have its hash, so we choose one of those if we can.
+        let psk_hash = match (&self.config.psk_store, client_hello.get_psk()) {
+            (Some(psk_store), Some(offer)) if version == ProtocolVersion::TLSv1_3 => offer
+                .identities
+                .iter()
+                .find_map(|id| psk::server_hash(psk_store.as_ref(), &id.identity.0)),
+            _ => None,
+        };
+        let psk_suites: Vec<_> = suitable_suites
+            .iter()
+            .filter(|scs| Some(scs.get_hash()) == psk_hash)
+            .cloned()
+            .collect();
+        let suitable_suites = match psk_suites.is_empty() {
+            true => suitable_suites,
+            false => psk_suites,
+        };
+
         let suite = if self.config.ignore_client_order {
             suites::choose_ciphersuite_preferring_server(
                 &client_hello.cipher_suites,
@@ -477,7 +593,9 @@ impl State for ExpectClientHello {
         // Start handshake hash.
         let starting_hash = suite.get_hash();
         let transcript = match self.transcript {
-            HandshakeHashOrBuffer::Buffer(inner) => inner.start_hash(starting_hash),
+            HandshakeHashOrBuffer::Buffer(inner) => {
+                inner.start_hash(self.config.provider, starting_hash)
+            }
             HandshakeHashOrBuffer::Hash(inner) if inner.algorithm() == starting_hash => inner,
             _ => {
                 return Err(cx
@@ -487,7 +605,11 @@ impl State for ExpectClientHello {
         };
 
         // Save their Random.
-        let randoms = ConnectionRandoms::new(client_hello.random, Random::new()?, false);
+        let randoms = ConnectionRandoms::new(
+            client_hello.random,
+            Random::new(self.config.provider)?,
+            false,
+        );
         match suite {
             SupportedCipherSuite::Tls13(suite) => tls13::CompleteClientHelloHandling {
                 config: self.config,
@@ -497,6 +619,7 @@ impl State for ExpectClientHello {
                 done_retry: self.done_retry,
                 send_ticket: self.send_ticket,
                 extra_exts: self.extra_exts,
+                ech: self.ech,
             }
             .handle_client_hello(cx, certkey, &m),
             SupportedCipherSuite::Tls12(suite) => tls12::CompleteClientHelloHandling {
@@ -511,7 +634,10 @@ impl State for ExpectClientHello {
             }
             .handle_client_hello(
                 cx,
-                certkey,
+                // We checked a TLS1.2 client has a certificate, above.
+                certkey.ok_or_else(|| {
+                    Error::General("no server certificate chain resolved".to_string())
+                })?,
                 &m,
                 client_hello,
                 sigschemes_ext,
@@ -1,15 +1,28 @@
-use crate::conn::{Connection, ConnectionCommon, IoState, PlaintextSink, Reader, Writer};
+use crate::check;
+use crate::compress::{CertificateCompressor, CertificateDecompressor};
+use crate::conn::{
+    Connection, ConnectionCommon, IoState, PeerMessageLimits, PlaintextSink, Reader, Writer,
+};
+use crate::crypto::CryptoProvider;
+use crate::ech::EchKey;
 use crate::error::Error;
+use crate::grease;
 use crate::key;
+use crate::key_schedule::EarlyExporter;
 use crate::keylog::KeyLog;
 use crate::kx::SupportedKxGroup;
-#[cfg(feature = "quic")]
-use crate::msgs::enums::AlertDescription;
+use crate::msgs::base::Payload;
+use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::msgs::enums::ProtocolVersion;
 use crate::msgs::enums::SignatureScheme;
-use crate::msgs::handshake::ServerExtension;
+use crate::msgs::enums::{AlertDescription, ContentType, HandshakeType};
+use crate::msgs::handshake::{ClientHelloPayload, HandshakePayload, ServerExtension};
+use crate::msgs::handshake::{ConvertProtocolNameList, ConvertServerNameList};
+use crate::msgs::message::{Message, MessagePayload};
+use crate::psk::PskIdentityStore;
 use crate::sign;
-use crate::suites::SupportedCipherSuite;
+use crate::suites::{ExtractedSecrets, SupportedCipherSuite};
+use crate::vecbuf::ChunkVecBuffer;
 use crate::verify;
 #[cfg(feature = "quic")]
 use crate::{conn::Protocol, quic};
@@ -106,7 +119,7 @@ pub trait ResolvesServerCert: Send + Sync {
 pub struct ClientHello<'a> {
     server_name: Option<webpki::DnsNameRef<'a>>,
     signature_schemes: &'a [SignatureScheme],
-    alpn: Option<&'a [&'a [u8]]>,
+    alpn: Option<Vec<&'a [u8]>>,
 }
 
 impl<'a> ClientHello<'a> {
@@ -114,7 +127,7 @@ impl<'a> ClientHello<'a> {
     fn new(
         server_name: Option<webpki::DnsNameRef<'a>>,
         signature_schemes: &'a [SignatureScheme],
-        alpn: Option<&'a [&'a [u8]]>,
+        alpn: Option<Vec<&'a [u8]>>,
     ) -> Self {
         ClientHello {
             server_name,
@@ -142,8 +155,8 @@ impl<'a> ClientHello<'a> {
     /// Get the alpn.
     ///
     /// Returns `None` if the client did not include an ALPN extension
-    pub fn alpn(&self) -> Option<&'a [&'a [u8]]> {
-        self.alpn
+    pub fn alpn(&self) -> Option<&[&'a [u8]]> {
+        self.alpn.as_deref()
     }
 }
 
@@ -160,8 +173,20 @@ impl<'a> ClientHello<'a> {
 /// * [`ServerConfig::session_storage`]: the default stores 256 sessions in memory.
 /// * [`ServerConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
 /// * [`ServerConfig::key_log`]: key material is not logged.
+/// * [`ServerConfig::max_early_data_size`]: the default is 0: early data is not accepted.
+/// * [`ServerConfig::peer_message_limits`]: see [`PeerMessageLimits`] for the defaults.
+/// * [`ServerConfig::cert_compressors`] and [`ServerConfig::cert_decompressors`]: the default
+///   is empty -- certificate compression is not used.
+/// * [`ServerConfig::ech_keys`]: the default is empty -- Encrypted Client Hello is not accepted.
+/// * [`ServerConfig::psk_store`]: the default is `None` -- external PSKs are not accepted.
+/// * [`ServerConfig::psk_key_exchange_modes`]: the default is `[PSK_DHE_KE]`.
+/// * [`ServerConfig::signature_schemes`]: the default is every scheme the crypto provider
+///   supports.
 #[derive(Clone)]
 pub struct ServerConfig {
+    /// Where all our cryptography comes from.
+    provider: &'static dyn CryptoProvider,
+
     /// List of ciphersuites, in preference order.
     cipher_suites: Vec<SupportedCipherSuite>,
 
@@ -209,10 +234,81 @@ pub struct ServerConfig {
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
-    /// Amount of early data to accept; 0 to disable.
-    #[cfg(feature = "quic")] // TLS support unimplemented
-    #[doc(hidden)]
+    /// Amount of TLS1.3 early data to accept for sessions created by
+    /// this config.  Specify 0 to disable early data.  The
+    /// default is 0.
+    ///
+    /// Read the early data via [`ServerConnection::early_data`].
+    ///
+    /// Over TCP, early data is only accepted when resuming a session
+    /// stored in `session_storage`, since those tickets can only be used
+    /// once.  Sessions resumed with tickets produced by `ticketer` could
+    /// be replayed, so they never get early data.  QUIC servers accept
+    /// early data with either kind of ticket.
+    ///
+    /// The units for this are _both_ plaintext bytes, _and_ ciphertext
+    /// bytes, depending on whether the server accepts a client's early_data
+    /// or not.  It is therefore recommended to include some slop in
+    /// this value to account for the unknown amount of ciphertext
+    /// expansion in the latter case.
     pub max_early_data_size: u32,
+    /// Limits on how many `KeyUpdate` messages, warning alerts and
+    /// empty records a client may send in a row.
+    pub peer_message_limits: PeerMessageLimits,
+
+    /// Certificate compression algorithms (RFC8879) we can use to
+    /// compress our certificate chain, in preference order.  We
+    /// compress with the first of these which the client supports.
+    ///
+    /// The default is empty: certificates are never compressed.
+    pub cert_compressors: Vec<Arc<dyn CertificateCompressor>>,
+
+    /// Certificate compression algorithms (RFC8879) we offer the
+    /// client for its certificate chain.
+    ///
+    /// The default is empty: compressed certificates are not accepted.
+    pub cert_decompressors: Vec<Arc<dyn CertificateDecompressor>>,
+
+    /// The largest certificate chain, in bytes, we are prepared to
+    /// decompress.  The default is 64KB, the same limit that applies
+    /// to uncompressed certificate chains.
+    pub max_decompressed_cert_size: usize,
+
+    /// Keys for decrypting TLS1.3 clients' Encrypted Client Hello
+    /// offers.  Publish their [`EchKey::config_list`] for clients
+    /// to use, for example in DNS.
+    ///
+    /// A client whose offer we cannot decrypt is sent all of these
+    /// configurations, so it can retry.
+    ///
+    /// The default is empty: ECH is never accepted.
+    pub ech_keys: Vec<EchKey>,
+
+    /// If set, where to find the external pre-shared keys which TLS1.3
+    /// clients may offer.  If we choose one, it authenticates the
+    /// handshake: we send no certificate, and ask for none.
+    ///
+    /// The default is `None`.
+    pub psk_store: Option<Arc<dyn PskIdentityStore>>,
+
+    /// The key exchange modes we accept external PSKs with, most
+    /// preferred first.  `PSK_DHE_KE` also does a key exchange, so
+    /// gives forward secrecy; `PSK_KE` does not.
+    ///
+    /// Resumption always uses `PSK_DHE_KE`.
+    ///
+    /// The default is `[PSK_DHE_KE]`.
+    pub psk_key_exchange_modes: Vec<PSKKeyExchangeMode>,
+
+    /// The signature schemes we use, in preference order.  Only these
+    /// are advertised to the client and accepted in its signatures, and
+    /// they are used to choose how to sign the handshake.
+    ///
+    /// Set this with [`ConfigWantsPeerType::with_signature_schemes`],
+    /// which checks it against the cipher suites and certificate.
+    ///
+    /// [`ConfigWantsPeerType::with_signature_schemes`]: crate::ConfigWantsPeerType::with_signature_schemes
+    pub signature_schemes: Vec<SignatureScheme>,
 }
 
 impl ServerConfig {
@@ -251,7 +347,11 @@ impl ServerConnection {
         extra_exts: Vec<ServerExtension>,
     ) -> Result<Self, Error> {
         Ok(Self {
-            common: ConnectionCommon::new(config.max_fragment_size, false)?,
+            common: ConnectionCommon::new(
+                config.max_fragment_size,
+                config.peer_message_limits,
+                false,
+            )?,
             state: Some(Box::new(hs::ExpectClientHello::new(config, extra_exts))),
             data: ServerConnectionData::default(),
         })
@@ -277,6 +377,14 @@ impl ServerConnection {
         self.data.get_sni_str()
     }
 
+    /// Returns the identity of the external PSK we chose, if the
+    /// handshake was authenticated with one.
+    pub fn external_psk_identity(&self) -> Option<&[u8]> {
+        self.data
+            .external_psk_identity
+            .as_deref()
+    }
+
     /// Application-controlled portion of the resumption ticket supplied by the client, if any.
     ///
     /// Recovered from the prior session's `set_resumption_data`. Integrity is guaranteed by rustls.
@@ -306,22 +414,69 @@ impl ServerConnection {
     ///
     /// Useful if invariants encoded in `received_resumption_data()` cannot be respected.
     ///
-    /// Must be called while `is_handshaking` is true.
+    /// Must be called while `is_handshaking` is true.  This has no effect
+    /// if the client's early data has already been accepted.
     pub fn reject_early_data(&mut self) {
         assert!(
             self.is_handshaking(),
             "cannot retroactively reject early data"
         );
-        self.data.reject_early_data = true;
+        self.data.early_data.reject();
     }
 
-    fn send_some_plaintext(&mut self, buf: &[u8]) -> usize {
-        let mut st = self.state.take();
-        if let Some(st) = st.as_mut() {
-            st.perhaps_write_key_update(&mut self.common);
+    /// Returns an `io::Read` implementer you can read bytes from that are
+    /// received from a client as TLS1.3 0RTT/"early" data, during the handshake.
+    ///
+    /// This returns `None` in many circumstances, such as :
+    ///
+    /// - Early data is disabled if [`ServerConfig::max_early_data_size`] is zero (the default).
+    /// - The session negotiated with the client is not TLS1.3.
+    /// - The client just doesn't support early data.
+    /// - The connection doesn't resume an existing session.
+    /// - The client hasn't sent a full ClientHello yet.
+    ///
+    /// Note that early data is not protected against replay in the same
+    /// way as the rest of the connection: the application should only act
+    /// on it if doing so twice would be harmless.
+    pub fn early_data(&mut self) -> Option<ReadEarlyData<'_>> {
+        if self.data.early_data.was_accepted() {
+            Some(ReadEarlyData::new(&mut self.data.early_data))
+        } else {
+            None
+        }
+    }
+
+    /// Derives key material from the TLS1.3 early exporter secret, as
+    /// described in RFC8446 section 7.5.  This is for binding early data
+    /// to the connection it was received on; otherwise, use
+    /// [`Connection::export_keying_material`].
+    ///
+    /// This works only once the client's early data has been accepted.
+    pub fn export_early_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        match &self.data.early_exporter {
+            Some(exporter) => exporter.export_keying_material(output, label, context),
+            None => Err(Error::General("early data is not in use".into())),
         }
+    }
+
+    fn send_some_plaintext(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut st = self.state.take();
+        let mut key_update = |common: &mut ConnectionCommon| match st.as_mut() {
+            Some(st) => st.perhaps_write_key_update(common),
+            None => Ok(()),
+        };
+        let sent = key_update(&mut self.common)
+            .and_then(|()| {
+                self.common
+                    .send_some_plaintext(buf, &mut key_update)
+            });
         self.state = st;
-        self.common.send_some_plaintext(buf)
+        sent.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 }
 
@@ -384,6 +539,22 @@ impl Connection for ServerConnection {
             .and_then(|st| st.export_keying_material(output, label, context))
     }
 
+    fn extract_secrets(self) -> Result<ExtractedSecrets, Error> {
+        let secrets = self
+            .state
+            .as_ref()
+            .ok_or(Error::HandshakeNotComplete)?
+            .extract_secrets()?;
+        self.common.extract_secrets(secrets)
+    }
+
+    fn refresh_traffic_keys(&mut self, request_peer_update: bool) -> Result<(), Error> {
+        match self.state.as_mut() {
+            Some(st) => st.refresh_traffic_keys(&mut self.common, request_peer_update),
+            None => Err(Error::HandshakeNotComplete),
+        }
+    }
+
     fn negotiated_cipher_suite(&self) -> Option<SupportedCipherSuite> {
         self.common.get_suite()
     }
@@ -399,13 +570,19 @@ impl Connection for ServerConnection {
 
 impl PlaintextSink for ServerConnection {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        Ok(self.send_some_plaintext(buf))
+        self.send_some_plaintext(buf)
     }
 
     fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
         let mut sz = 0;
         for buf in bufs {
-            sz += self.send_some_plaintext(buf);
+            match self.send_some_plaintext(buf) {
+                Ok(len) => sz += len,
+                // Report what was already queued; the error recurs on
+                // the next write.
+                Err(_) if sz > 0 => break,
+                Err(err) => return Err(err),
+            }
         }
         Ok(sz)
     }
@@ -422,16 +599,283 @@ impl fmt::Debug for ServerConnection {
     }
 }
 
+/// Reads a client's ClientHello before a [`ServerConfig`] is chosen
+/// for it.
+///
+/// Feed it TLS data with [`Acceptor::read_tls`] and poll it with
+/// [`Acceptor::accept`] until that returns an [`Accepted`].  Use its
+/// [`ClientHello`] to choose a configuration -- for example, by server
+/// name -- and pass that to [`Accepted::into_connection`] to continue
+/// the handshake as a normal [`ServerConnection`].
+pub struct Acceptor {
+    common: Option<ConnectionCommon>,
+}
+
+impl Acceptor {
+    /// Make a new `Acceptor`.
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            common: Some(ConnectionCommon::new(
+                None,
+                PeerMessageLimits::default(),
+                false,
+            )?),
+        })
+    }
+
+    /// Returns true if the caller should call [`Acceptor::read_tls`]
+    /// as soon as possible.  This is false once a ClientHello has been
+    /// accepted.
+    pub fn wants_read(&self) -> bool {
+        self.common
+            .as_ref()
+            .map(ConnectionCommon::wants_read)
+            .unwrap_or(false)
+    }
+
+    /// Read TLS content from `rd`.  See [`Connection::read_tls`].
+    ///
+    /// This fails once a ClientHello has been accepted: read any more
+    /// data with the [`ServerConnection`] instead.
+    pub fn read_tls(&mut self, rd: &mut dyn io::Read) -> io::Result<usize> {
+        match self.common.as_mut() {
+            Some(common) => common.read_tls(rd),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "acceptor cannot read after accepting a ClientHello",
+            )),
+        }
+    }
+
+    /// Write TLS content to `wr`.  See [`Connection::write_tls`].
+    ///
+    /// Call this after [`Acceptor::accept`] fails, to send the client
+    /// the alert explaining why.
+    pub fn write_tls(&mut self, wr: &mut dyn io::Write) -> io::Result<usize> {
+        match self.common.as_mut() {
+            Some(common) => common.write_tls(wr),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "acceptor cannot write after accepting a ClientHello",
+            )),
+        }
+    }
+
+    /// Process the TLS data read so far.
+    ///
+    /// Returns `Ok(None)` if a complete ClientHello has not been
+    /// received yet: read more data and call this again.  Anything
+    /// other than a ClientHello is an error.
+    ///
+    /// Once this returns an [`Accepted`], the `Acceptor` is spent.
+    pub fn accept(&mut self) -> Result<Option<Accepted>, Error> {
+        let common = match self.common.as_mut() {
+            Some(common) => common,
+            None => {
+                return Err(Error::General(
+                    "acceptor polled after accepting a ClientHello".to_string(),
+                ))
+            }
+        };
+
+        let message = match common.first_handshake_message()? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+
+        if let Err(err) = check::check_message(
+            &message,
+            &[ContentType::Handshake],
+            &[HandshakeType::ClientHello],
+        ) {
+            common.send_fatal_alert(AlertDescription::UnexpectedMessage);
+            return Err(err);
+        }
+
+        let signature_schemes = Accepted::client_hello_payload(&message)
+            .get_sigalgs_extension()
+            .map(|schemes| {
+                schemes
+                    .iter()
+                    .filter(|scheme| !grease::is_grease(scheme.get_u16()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Won't panic because we checked for `None` above.
+        Ok(Some(Accepted {
+            common: self.common.take().unwrap(),
+            message,
+            signature_schemes,
+        }))
+    }
+}
+
+impl fmt::Debug for Acceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Acceptor").finish()
+    }
+}
+
+/// A ClientHello read by an [`Acceptor`], waiting for a
+/// [`ServerConfig`] to be chosen for it.
+pub struct Accepted {
+    common: ConnectionCommon,
+    message: Message,
+    signature_schemes: Vec<SignatureScheme>,
+}
+
+impl Accepted {
+    /// The client's ClientHello.
+    ///
+    /// Unlike the one given to [`ResolvesServerCert::resolve`], its
+    /// signature schemes are not yet reduced to those the server
+    /// supports, since no configuration has been chosen.  If the
+    /// client offered Encrypted Client Hello, this is the outer
+    /// ClientHello.
+    pub fn client_hello(&self) -> ClientHello<'_> {
+        let payload = Self::client_hello_payload(&self.message);
+        let server_name = payload
+            .get_sni_extension()
+            .and_then(|sni| sni.get_single_hostname());
+        let alpn = payload
+            .get_alpn_extension()
+            .map(|protos| {
+                let mut protos = protos.to_slices();
+                protos.retain(|proto| !grease::is_grease_protocol(proto));
+                protos
+            });
+
+        ClientHello::new(server_name, &self.signature_schemes, alpn)
+    }
+
+    /// Continue the handshake with `config`, returning a
+    /// [`ServerConnection`].
+    ///
+    /// The ClientHello has not been processed yet: call
+    /// [`Connection::process_new_packets`] next, as usual.
+    pub fn into_connection(mut self, config: Arc<ServerConfig>) -> Result<ServerConnection, Error> {
+        self.common
+            .set_limits(config.max_fragment_size, config.peer_message_limits)?;
+        self.common
+            .handshake_joiner
+            .frames
+            .push_front(self.message);
+
+        Ok(ServerConnection {
+            common: self.common,
+            state: Some(Box::new(hs::ExpectClientHello::new(config, vec![]))),
+            data: ServerConnectionData::default(),
+        })
+    }
+
+    fn client_hello_payload(message: &Message) -> &ClientHelloPayload {
+        match &message.payload {
+            MessagePayload::Handshake(hsp) => match &hsp.payload {
+                HandshakePayload::ClientHello(ch) => ch,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for Accepted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Accepted").finish()
+    }
+}
+
+/// Stub that implements io::Read and reads early data received
+/// from the client.
+pub struct ReadEarlyData<'a> {
+    early_data: &'a mut EarlyDataState,
+}
+
+impl<'a> ReadEarlyData<'a> {
+    fn new(early_data: &'a mut EarlyDataState) -> Self {
+        ReadEarlyData { early_data }
+    }
+}
+
+impl<'a> io::Read for ReadEarlyData<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.early_data.read(buf)
+    }
+}
+
+enum EarlyDataState {
+    New,
+    Accepted {
+        received: ChunkVecBuffer,
+        left: usize,
+    },
+    Rejected,
+}
+
+impl Default for EarlyDataState {
+    fn default() -> Self {
+        Self::New
+    }
+}
+
+impl EarlyDataState {
+    fn reject(&mut self) {
+        if let Self::New = self {
+            *self = Self::Rejected;
+        }
+    }
+
+    fn accept(&mut self, max_size: usize) {
+        *self = Self::Accepted {
+            received: ChunkVecBuffer::new(None),
+            left: max_size,
+        };
+    }
+
+    fn was_accepted(&self) -> bool {
+        matches!(self, Self::Accepted { .. })
+    }
+
+    fn was_rejected(&self) -> bool {
+        matches!(self, Self::Rejected)
+    }
+
+    /// Buffer received early data.  Returns false if the peer
+    /// sent more than we allowed.
+    fn take_received_plaintext(&mut self, bytes: Payload) -> bool {
+        let available = bytes.0.len();
+        match self {
+            Self::Accepted { received, left } => {
+                if available > *left {
+                    return false;
+                }
+                received.append(bytes.0);
+                *left -= available;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Accepted { received, .. } => received.read(buf),
+            _ => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        }
+    }
+}
+
 #[derive(Default)]
 struct ServerConnectionData {
     sni: Option<webpki::DnsName>,
     received_resumption_data: Option<Vec<u8>>,
     resumption_data: Vec<u8>,
     client_cert_chain: Option<Vec<key::Certificate>>,
-
-    #[allow(dead_code)] // only supported for QUIC currently
-    /// Whether to reject early data even if it would otherwise be accepted
-    reject_early_data: bool,
+    early_data: EarlyDataState,
+    external_psk_identity: Option<Vec<u8>>,
+    early_exporter: Option<Box<EarlyExporter>>,
 }
 
 impl ServerConnectionData {
@@ -501,6 +945,8 @@ pub trait ServerQuicExt {
             ));
         }
 
+        quic::check_suites(&config.cipher_suites)?;
+
         if config.max_early_data_size != 0 && config.max_early_data_size != 0xffff_ffff {
             return Err(Error::General(
                 "QUIC sessions must set a max early data of 0 or 2^32-1".into(),
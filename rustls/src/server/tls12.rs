@@ -1,5 +1,6 @@
 use crate::check::{check_message, inappropriate_message};
 use crate::conn::{ConnectionCommon, ConnectionRandoms, ConnectionSecrets};
+use crate::crypto::ActiveKeyExchange;
 use crate::error::Error;
 use crate::hash_hs::HandshakeHash;
 use crate::key::Certificate;
@@ -9,11 +10,12 @@ use crate::msgs::base::Payload;
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
-use crate::msgs::handshake::{ClientECDHParams, HandshakeMessagePayload, HandshakePayload};
+use crate::msgs::handshake::{ClientDHParams, ClientECDHParams, KeyExchangeAlgorithm};
+use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{NewSessionTicketPayload, SessionID};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
-use crate::suites::Tls12CipherSuite;
+use crate::suites::{PartiallyExtractedSecrets, Tls12CipherSuite};
 use crate::verify;
 use crate::{kx, tls12};
 
@@ -32,11 +34,11 @@ mod client_hello {
     use crate::msgs::enums::{ClientCertificateType, Compression, SignatureScheme};
     use crate::msgs::handshake::{CertificateRequestPayload, Random};
     use crate::msgs::handshake::{
-        CertificateStatus, DigitallySignedStruct, ECDHEServerKeyExchange,
+        CertificateStatus, DHEServerKeyExchange, DigitallySignedStruct, ECDHEServerKeyExchange,
     };
     use crate::msgs::handshake::{ClientExtension, SessionID};
     use crate::msgs::handshake::{ClientHelloPayload, ServerHelloPayload};
-    use crate::msgs::handshake::{ECPointFormatList, ServerECDHParams, SupportedPointFormats};
+    use crate::msgs::handshake::{ServerDHParams, ServerECDHParams};
     use crate::msgs::handshake::{ServerExtension, ServerKeyExchangePayload};
     use crate::sign;
 
@@ -71,24 +73,32 @@ mod client_hello {
                 self.using_ems = true;
             }
 
-            let groups_ext = client_hello
-                .get_namedgroups_extension()
-                .ok_or_else(|| hs::incompatible(&mut cx.common, "client didn't describe groups"))?;
-            let ecpoints_ext = client_hello
-                .get_ecpoints_extension()
-                .ok_or_else(|| {
-                    hs::incompatible(&mut cx.common, "client didn't describe ec points")
-                })?;
+            // Clients which predate RFC7919 can offer DHE without
+            // describing their groups or point formats.
+            let groups_ext = client_hello.get_namedgroups_extension();
+            let ecpoints_ext = client_hello.get_ecpoints_extension();
 
             trace!("namedgroups {:?}", groups_ext);
             trace!("ecpoints {:?}", ecpoints_ext);
 
-            if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
-                cx.common
-                    .send_fatal_alert(AlertDescription::IllegalParameter);
-                return Err(Error::PeerIncompatibleError(
-                    "client didn't support uncompressed ec points".to_string(),
-                ));
+            if self.suite.kx == KeyExchangeAlgorithm::ECDHE {
+                if groups_ext.is_none() {
+                    return Err(hs::incompatible(
+                        &mut cx.common,
+                        "client didn't describe groups",
+                    ));
+                }
+                let ecpoints_ext = ecpoints_ext.ok_or_else(|| {
+                    hs::incompatible(&mut cx.common, "client didn't describe ec points")
+                })?;
+
+                if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::IllegalParameter);
+                    return Err(Error::PeerIncompatibleError(
+                        "client didn't support uncompressed ec points".to_string(),
+                    ));
+                }
             }
 
             // -- If TLS1.3 is enabled, signal the downgrade in the server random
@@ -157,21 +167,14 @@ mod client_hello {
                 ));
             }
 
-            let group = self
-                .config
-                .kx_groups
-                .iter()
-                .find(|skxg| groups_ext.contains(&skxg.name))
-                .cloned()
-                .ok_or_else(|| hs::incompatible(&mut cx.common, "no supported group"))?;
-
-            let ecpoint = ECPointFormatList::supported()
-                .iter()
-                .find(|format| ecpoints_ext.contains(format))
-                .cloned()
-                .ok_or_else(|| hs::incompatible(&mut cx.common, "no supported point format"))?;
-
-            debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
+            let group = tls12::choose_kx_group(
+                &self.config.kx_groups,
+                &self.suite.kx,
+                groups_ext
+                    .map(|groups| groups.as_slice())
+                    .unwrap_or_default(),
+            )
+            .ok_or_else(|| hs::incompatible(&mut cx.common, "no supported group"))?;
 
             let (mut ocsp_response, mut sct_list) =
                 (server_key.get_ocsp(), server_key.get_sct_list());
@@ -179,7 +182,7 @@ mod client_hello {
             // If we're not offered a ticket or a potential connection ID,
             // allocate a connection ID.
             if self.session_id.is_empty() && !ticket_received {
-                self.session_id = SessionID::random()?;
+                self.session_id = SessionID::random(self.config.provider)?;
             }
 
             self.send_ticket = emit_server_hello(
@@ -201,6 +204,7 @@ mod client_hello {
                 emit_cert_status(&mut self.transcript, &mut cx.common, ocsp_response);
             }
             let server_kx = emit_server_kx(
+                &self.config,
                 &mut self.transcript,
                 &mut cx.common,
                 sigschemes,
@@ -269,6 +273,7 @@ mod client_hello {
             )?;
 
             let secrets = ConnectionSecrets::new_resume(
+                self.config.provider,
                 &self.randoms,
                 self.suite,
                 &resumedata.master_secret.0,
@@ -279,7 +284,7 @@ mod client_hello {
                 &secrets.master_secret,
             );
             cx.common
-                .start_encryption_tls12(&secrets);
+                .start_encryption_tls12(&secrets)?;
             cx.data.client_cert_chain = resumedata.client_cert_chain;
 
             if self.send_ticket {
@@ -327,7 +332,6 @@ mod client_hello {
         ep.process_common(
             config,
             cx,
-            suite.into(),
             ocsp_response,
             sct_list,
             hello,
@@ -394,31 +398,31 @@ mod client_hello {
     }
 
     fn emit_server_kx(
+        config: &ServerConfig,
         transcript: &mut HandshakeHash,
         common: &mut ConnectionCommon,
         sigschemes: Vec<SignatureScheme>,
         skxg: &'static kx::SupportedKxGroup,
         signing_key: &dyn sign::SigningKey,
         randoms: &ConnectionRandoms,
-    ) -> Result<kx::KeyExchange, Error> {
-        let kx = kx::KeyExchange::start(skxg).ok_or(Error::FailedToGetRandomBytes)?;
-        let secdh = ServerECDHParams::new(skxg.name, kx.pubkey.as_ref());
-
-        let mut msg = Vec::new();
-        msg.extend(&randoms.client);
-        msg.extend(&randoms.server);
-        secdh.encode(&mut msg);
-
-        let signer = signing_key
-            .choose_scheme(&sigschemes)
-            .ok_or_else(|| Error::General("incompatible signing key".to_string()))?;
-        let sigscheme = signer.get_scheme();
-        let sig = signer.sign(&msg)?;
-
-        let skx = ServerKeyExchangePayload::ECDHE(ECDHEServerKeyExchange {
-            params: secdh,
-            dss: DigitallySignedStruct::new(sigscheme, sig),
-        });
+    ) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+        let kx = config
+            .provider
+            .start_key_exchange(skxg)
+            .ok_or(Error::FailedToGetRandomBytes)?;
+
+        let skx = match skxg.dhe_params() {
+            Some((p, g)) => {
+                let params = ServerDHParams::new(&p, g, kx.pub_key());
+                let dss = sign_server_kx(&sigschemes, signing_key, randoms, &params)?;
+                ServerKeyExchangePayload::DHE(DHEServerKeyExchange { params, dss })
+            }
+            None => {
+                let params = ServerECDHParams::new(skxg.name, kx.pub_key());
+                let dss = sign_server_kx(&sigschemes, signing_key, randoms, &params)?;
+                ServerKeyExchangePayload::ECDHE(ECDHEServerKeyExchange { params, dss })
+            }
+        };
 
         let m = Message {
             version: ProtocolVersion::TLSv1_2,
@@ -433,6 +437,26 @@ mod client_hello {
         Ok(kx)
     }
 
+    /// Sign our key exchange `params`, as sent in a ServerKeyExchange.
+    fn sign_server_kx(
+        sigschemes: &[SignatureScheme],
+        signing_key: &dyn sign::SigningKey,
+        randoms: &ConnectionRandoms,
+        params: &impl Codec,
+    ) -> Result<DigitallySignedStruct, Error> {
+        let mut msg = Vec::new();
+        msg.extend(&randoms.client);
+        msg.extend(&randoms.server);
+        params.encode(&mut msg);
+
+        let signer = signing_key
+            .choose_scheme(sigschemes)
+            .ok_or_else(|| Error::General("incompatible signing key".to_string()))?;
+        let sigscheme = signer.get_scheme();
+        let sig = signer.sign(&msg)?;
+        Ok(DigitallySignedStruct::new(sigscheme, sig))
+    }
+
     fn emit_certificate_req(
         config: &ServerConfig,
         transcript: &mut HandshakeHash,
@@ -444,7 +468,13 @@ mod client_hello {
             return Ok(false);
         }
 
-        let verify_schemes = client_auth.supported_verify_schemes();
+        let supported_schemes = client_auth.supported_verify_schemes();
+        let verify_schemes = config
+            .signature_schemes
+            .iter()
+            .filter(|scheme| supported_schemes.contains(scheme))
+            .copied()
+            .collect();
 
         let names = client_auth
             .client_auth_root_subjects(cx.data.get_sni().as_ref())
@@ -500,7 +530,7 @@ struct ExpectCertificate {
     session_id: SessionID,
     suite: &'static Tls12CipherSuite,
     using_ems: bool,
-    server_kx: kx::KeyExchange,
+    server_kx: Box<dyn ActiveKeyExchange>,
     send_ticket: bool,
 }
 
@@ -574,7 +604,7 @@ struct ExpectClientKx {
     session_id: SessionID,
     suite: &'static Tls12CipherSuite,
     using_ems: bool,
-    server_kx: kx::KeyExchange,
+    server_kx: Box<dyn ActiveKeyExchange>,
     client_cert: Option<Vec<Certificate>>,
     send_ticket: bool,
 }
@@ -590,20 +620,35 @@ impl hs::State for ExpectClientKx {
 
         // Complete key agreement, and set up encryption with the
         // resulting premaster secret.
-        let peer_kx_params =
-            tls12::decode_ecdh_params::<ClientECDHParams>(&mut cx.common, &client_kx.0)?;
-        let kxd = tls12::complete_ecdh(self.server_kx, &peer_kx_params.public.0)?;
+        let kxd = match self.suite.kx {
+            KeyExchangeAlgorithm::DHE => {
+                let peer_kx_params =
+                    tls12::decode_kx_params::<ClientDHParams>(&mut cx.common, &client_kx.0)?;
+                tls12::complete_dhe(self.server_kx, &peer_kx_params.public.0)?
+            }
+            _ => {
+                let peer_kx_params =
+                    tls12::decode_kx_params::<ClientECDHParams>(&mut cx.common, &client_kx.0)?;
+                tls12::complete_ecdh(self.server_kx, &peer_kx_params.public.0)?
+            }
+        };
 
         let secrets = if self.using_ems {
             let handshake_hash = self.transcript.get_current_hash();
             ConnectionSecrets::new_ems(
+                self.config.provider,
                 &self.randoms,
                 &handshake_hash,
                 self.suite,
                 &kxd.shared_secret,
             )
         } else {
-            ConnectionSecrets::new(&self.randoms, self.suite, &kxd.shared_secret)
+            ConnectionSecrets::new(
+                self.config.provider,
+                &self.randoms,
+                self.suite,
+                &kxd.shared_secret,
+            )
         };
         self.config.key_log.log(
             "CLIENT_RANDOM",
@@ -611,7 +656,7 @@ impl hs::State for ExpectClientKx {
             &secrets.master_secret,
         );
         cx.common
-            .start_encryption_tls12(&secrets);
+            .start_encryption_tls12(&secrets)?;
 
         if let Some(client_cert) = self.client_cert {
             Ok(Box::new(ExpectCertificateVerify {
@@ -660,9 +705,13 @@ impl hs::State for ExpectCertificateVerify {
             match self.transcript.take_handshake_buf() {
                 Some(msgs) => {
                     let certs = &self.client_cert;
-                    self.config
-                        .verifier
-                        .verify_tls12_signature(&msgs, &certs[0], sig)
+                    verify::check_signature_scheme(&self.config.signature_schemes, sig).and_then(
+                        |()| {
+                            self.config
+                                .verifier
+                                .verify_tls12_signature(self.config.provider, &msgs, &certs[0], sig)
+                        },
+                    )
                 }
                 None => {
                     // This should be unreachable; the handshake buffer was initialized with
@@ -921,4 +970,18 @@ impl hs::State for ExpectTraffic {
             .export_keying_material(output, label, context);
         Ok(())
     }
+
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        Ok(self.secrets.extract_secrets())
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        _common: &mut ConnectionCommon,
+        _request_peer_update: bool,
+    ) -> Result<(), Error> {
+        Err(Error::General(
+            "traffic key updates are not available in TLS1.2".to_string(),
+        ))
+    }
 }
@@ -1,8 +1,11 @@
-#[cfg(feature = "quic")]
 use crate::check::check_message;
 use crate::check::{inappropriate_handshake_message, inappropriate_message};
 use crate::cipher;
+use crate::compress;
+#[cfg(feature = "quic")]
+use crate::conn::Protocol;
 use crate::conn::{ConnectionCommon, ConnectionRandoms};
+use crate::ech;
 use crate::error::Error;
 use crate::hash_hs::HandshakeHash;
 use crate::key::Certificate;
@@ -10,19 +13,18 @@ use crate::key_schedule::{KeyScheduleTraffic, KeyScheduleTrafficWithClientFinish
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::msgs::codec::Codec;
-use crate::msgs::enums::{AlertDescription, KeyUpdateRequest};
+use crate::msgs::enums::{AlertDescription, CertificateType, KeyUpdateRequest};
 use crate::msgs::enums::{ContentType, HandshakeType, ProtocolVersion};
 use crate::msgs::handshake::HandshakeMessagePayload;
 use crate::msgs::handshake::HandshakePayload;
+use crate::msgs::handshake::NewSessionTicketExtension;
 use crate::msgs::handshake::NewSessionTicketPayloadTLS13;
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
 use crate::rand;
 use crate::server::ServerConfig;
-use crate::suites::Tls13CipherSuite;
+use crate::suites::{PartiallyExtractedSecrets, Tls13CipherSuite};
 use crate::verify;
-#[cfg(feature = "quic")]
-use crate::{conn::Protocol, msgs::handshake::NewSessionTicketExtension};
 
 use super::hs::{self, HandshakeHashOrBuffer, ServerContext};
 
@@ -33,7 +35,10 @@ use ring::constant_time;
 pub(super) use client_hello::CompleteClientHelloHandling;
 
 mod client_hello {
+    use crate::crypto::Digest;
+    use crate::delegated::DelegatedCredential;
     use crate::key_schedule::{KeyScheduleEarly, KeyScheduleHandshake, KeyScheduleNonSecret};
+    use crate::key_schedule::{Prk, PskKind};
     use crate::kx;
     use crate::msgs::base::{Payload, PayloadU8};
     use crate::msgs::ccs::ChangeCipherSpecPayload;
@@ -46,6 +51,7 @@ mod client_hello {
     use crate::msgs::handshake::CertificateRequestPayloadTLS13;
     use crate::msgs::handshake::CertificateStatus;
     use crate::msgs::handshake::ClientHelloPayload;
+    use crate::msgs::handshake::DelegatedCredentialPayload;
     use crate::msgs::handshake::DigitallySignedStruct;
     use crate::msgs::handshake::HelloRetryExtension;
     use crate::msgs::handshake::HelloRetryRequest;
@@ -54,6 +60,7 @@ mod client_hello {
     use crate::msgs::handshake::ServerExtension;
     use crate::msgs::handshake::ServerHelloPayload;
     use crate::msgs::handshake::SessionID;
+    use crate::psk::{self, PskOffer};
     #[cfg(feature = "quic")]
     use crate::quic;
     use crate::server::common::ActiveCertifiedKey;
@@ -61,6 +68,13 @@ mod client_hello {
 
     use super::*;
 
+    #[derive(Clone, Copy, PartialEq)]
+    enum EarlyDataDecision {
+        Disabled,
+        RequestedButRejected,
+        Accepted,
+    }
+
     pub(in crate::server) struct CompleteClientHelloHandling {
         pub(in crate::server) config: Arc<ServerConfig>,
         pub(in crate::server) transcript: HandshakeHash,
@@ -69,9 +83,20 @@ mod client_hello {
         pub(in crate::server) done_retry: bool,
         pub(in crate::server) send_ticket: bool,
         pub(in crate::server) extra_exts: Vec<ServerExtension>,
+        pub(in crate::server) ech: Option<ech::EchAccepted>,
     }
 
     impl CompleteClientHelloHandling {
+        fn binder_hash(&self, client_hello: &Message) -> Digest {
+            let binder_plaintext = match client_hello.payload {
+                MessagePayload::Handshake(ref hmp) => hmp.get_encoding_for_binder_signing(),
+                _ => unreachable!(),
+            };
+
+            self.transcript
+                .get_hash_given(&binder_plaintext)
+        }
+
         fn check_binder(
             &self,
             suite: &'static Tls13CipherSuite,
@@ -79,19 +104,23 @@ mod client_hello {
             psk: &[u8],
             binder: &[u8],
         ) -> bool {
-            let binder_plaintext = match client_hello.payload {
-                MessagePayload::Handshake(ref hmp) => hmp.get_encoding_for_binder_signing(),
-                _ => unreachable!(),
-            };
+            let handshake_hash = self.binder_hash(client_hello);
 
-            let handshake_hash = self
-                .transcript
-                .get_hash_given(&binder_plaintext);
+            let key_schedule =
+                KeyScheduleEarly::new(self.config.provider, suite.hash_algorithm, psk);
+            let real_binder = key_schedule
+                .psk_binder_key_and_sign_verify_data(PskKind::Resumption, &handshake_hash);
 
-            let key_schedule = KeyScheduleEarly::new(suite.hkdf_algorithm, psk);
-            let real_binder =
-                key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+            constant_time::verify_slices_are_equal(real_binder.as_ref(), binder).is_ok()
+        }
 
+        fn check_external_binder(
+            &self,
+            client_hello: &Message,
+            offer: &PskOffer,
+            binder: &[u8],
+        ) -> bool {
+            let real_binder = offer.binder(&self.binder_hash(client_hello));
             constant_time::verify_slices_are_equal(real_binder.as_ref(), binder).is_ok()
         }
 
@@ -115,7 +144,7 @@ mod client_hello {
         pub(in crate::server) fn handle_client_hello(
             mut self,
             cx: &mut ServerContext<'_>,
-            server_key: ActiveCertifiedKey,
+            server_key: Option<ActiveCertifiedKey>,
             chm: &Message,
         ) -> hs::NextStateOrError {
             let client_hello = require_handshake_msg!(
@@ -130,90 +159,17 @@ mod client_hello {
                     .illegal_param("client offered wrong compressions"));
             }
 
-            let groups_ext = client_hello
-                .get_namedgroups_extension()
-                .ok_or_else(|| hs::incompatible(&mut cx.common, "client didn't describe groups"))?;
-
-            let mut sigschemes_ext = client_hello
-                .get_sigalgs_extension()
-                .ok_or_else(|| {
-                    hs::incompatible(&mut cx.common, "client didn't describe sigschemes")
-                })?
-                .clone();
-
-            let tls13_schemes = sign::supported_sign_tls13();
-            sigschemes_ext.retain(|scheme| tls13_schemes.contains(scheme));
+            let early_data_requested = client_hello.early_data_extension_offered();
 
-            let shares_ext = client_hello
-                .get_keyshare_extension()
-                .ok_or_else(|| hs::incompatible(&mut cx.common, "client didn't send keyshares"))?;
-
-            if client_hello.has_keyshare_extension_with_duplicates() {
+            // EarlyData extension is illegal in second ClientHello
+            if self.done_retry && early_data_requested {
                 return Err(cx
                     .common
-                    .illegal_param("client sent duplicate keyshares"));
+                    .illegal_param("client sent EarlyData in second ClientHello"));
             }
 
-            // choose a share that we support
-            let chosen_share = self
-                .config
-                .kx_groups
-                .iter()
-                .find_map(|group| {
-                    shares_ext
-                        .iter()
-                        .find(|share| share.group == group.name)
-                });
-
-            let chosen_share = match chosen_share {
-                Some(s) => s,
-                None => {
-                    // We don't have a suitable key share.  Choose a suitable group and
-                    // send a HelloRetryRequest.
-                    let retry_group_maybe = self
-                        .config
-                        .kx_groups
-                        .iter()
-                        .find(|group| groups_ext.contains(&group.name))
-                        .cloned();
-
-                    self.transcript.add_message(chm);
-
-                    if let Some(group) = retry_group_maybe {
-                        if self.done_retry {
-                            return Err(cx
-                                .common
-                                .illegal_param("did not follow retry request"));
-                        }
-
-                        emit_hello_retry_request(
-                            &mut self.transcript,
-                            self.suite,
-                            &mut cx.common,
-                            group.name,
-                        );
-                        emit_fake_ccs(&mut cx.common);
-                        return Ok(Box::new(hs::ExpectClientHello {
-                            config: self.config,
-                            transcript: HandshakeHashOrBuffer::Hash(self.transcript),
-                            session_id: SessionID::empty(),
-                            using_ems: false,
-                            done_retry: true,
-                            send_ticket: self.send_ticket,
-                            extra_exts: self.extra_exts,
-                        }));
-                    }
-
-                    return Err(hs::incompatible(
-                        &mut cx.common,
-                        "no kx group overlap with client",
-                    ));
-                }
-            };
-
-            let mut chosen_psk_index = None;
-            let mut resumedata = None;
-            if let Some(psk_offer) = client_hello.get_psk() {
+            let psk_offer = client_hello.get_psk();
+            if let Some(psk_offer) = psk_offer {
                 if !client_hello.check_psk_ext_is_last() {
                     return Err(cx
                         .common
@@ -232,7 +188,151 @@ mod client_hello {
                         .common
                         .illegal_param("psk extension mismatched ids/binders"));
                 }
+            }
 
+            // An external PSK is used with the first of our key exchange
+            // modes which the client offered.
+            let external_mode = self
+                .config
+                .psk_key_exchange_modes
+                .iter()
+                .find(|mode| client_hello.psk_mode_offered(**mode))
+                .cloned();
+            let mut external_psk = None;
+            if let (Some(store), Some(psk_offer), Some(_)) =
+                (&self.config.psk_store, psk_offer, external_mode)
+            {
+                for (i, psk_id) in psk_offer.identities.iter().enumerate() {
+                    let offer = match psk::server_find(
+                        self.config.provider,
+                        store.as_ref(),
+                        &psk_id.identity.0,
+                        self.suite,
+                    ) {
+                        Some(offer) => offer,
+                        None => continue,
+                    };
+
+                    if !self.check_external_binder(chm, &offer, &psk_offer.binders[i].0) {
+                        return Err(wrong_binder(cx.common));
+                    }
+
+                    external_psk = Some((i, offer));
+                    break;
+                }
+            }
+
+            // In `psk_ke` mode there is no key exchange, and so no key share.
+            let psk_ke =
+                external_psk.is_some() && external_mode == Some(PSKKeyExchangeMode::PSK_KE);
+
+            let mut sigschemes_ext = match client_hello.get_sigalgs_extension() {
+                Some(sigschemes) => sigschemes.clone(),
+                // The PSK authenticates us, rather than a signature.
+                None if external_psk.is_some() => Vec::new(),
+                None => {
+                    return Err(hs::incompatible(
+                        cx.common,
+                        "client didn't describe sigschemes",
+                    ))
+                }
+            };
+
+            let tls13_schemes = sign::supported_sign_tls13();
+            sigschemes_ext.retain(|scheme| tls13_schemes.contains(scheme));
+
+            let chosen_share = if psk_ke {
+                None
+            } else {
+                let groups_ext = client_hello
+                    .get_namedgroups_extension()
+                    .ok_or_else(|| hs::incompatible(cx.common, "client didn't describe groups"))?;
+
+                let shares_ext = client_hello
+                    .get_keyshare_extension()
+                    .ok_or_else(|| hs::incompatible(cx.common, "client didn't send keyshares"))?;
+
+                if client_hello.has_keyshare_extension_with_duplicates() {
+                    return Err(cx
+                        .common
+                        .illegal_param("client sent duplicate keyshares"));
+                }
+
+                // choose a share that we support
+                let chosen_share = self
+                    .config
+                    .kx_groups
+                    .iter()
+                    .find_map(|group| {
+                        shares_ext
+                            .iter()
+                            .find(|share| share.group == group.name)
+                    });
+
+                match chosen_share {
+                    Some(s) => Some(s),
+                    None => {
+                        // We don't have a suitable key share.  Choose a suitable group and
+                        // send a HelloRetryRequest.
+                        let retry_group_maybe = self
+                            .config
+                            .kx_groups
+                            .iter()
+                            .find(|group| groups_ext.contains(&group.name))
+                            .cloned();
+
+                        self.transcript.add_message(chm);
+
+                        if let Some(group) = retry_group_maybe {
+                            if self.done_retry {
+                                return Err(cx
+                                    .common
+                                    .illegal_param("did not follow retry request"));
+                            }
+
+                            let ech_inner_random = self
+                                .ech
+                                .as_ref()
+                                .map(|_| Random::from(self.randoms.client));
+                            emit_hello_retry_request(
+                                &mut self.transcript,
+                                self.suite,
+                                &mut cx.common,
+                                group.name,
+                                ech_inner_random.as_ref(),
+                            );
+                            emit_fake_ccs(&mut cx.common);
+
+                            // The client's early data (if any) is now useless, and
+                            // must be skipped up to the 2nd ClientHello.
+                            if early_data_requested {
+                                cx.common
+                                    .record_layer
+                                    .set_trial_decryption(self.config.max_early_data_size as usize);
+                            }
+                            return Ok(Box::new(hs::ExpectClientHello {
+                                config: self.config,
+                                transcript: HandshakeHashOrBuffer::Hash(self.transcript),
+                                session_id: SessionID::empty(),
+                                using_ems: false,
+                                done_retry: true,
+                                send_ticket: self.send_ticket,
+                                extra_exts: self.extra_exts,
+                                ech: self.ech,
+                            }));
+                        }
+
+                        return Err(hs::incompatible(
+                            &mut cx.common,
+                            "no kx group overlap with client",
+                        ));
+                    }
+                }
+            };
+
+            let mut chosen_psk_index = None;
+            let mut resumedata = None;
+            if let (Some(psk_offer), false) = (psk_offer, psk_ke) {
                 for (i, psk_id) in psk_offer.identities.iter().enumerate() {
                     let resume = match self
                         .attempt_tls13_ticket_decryption(&psk_id.identity.0)
@@ -249,11 +349,7 @@ mod client_hello {
                         &resume.master_secret.0,
                         &psk_offer.binders[i].0,
                     ) {
-                        cx.common
-                            .send_fatal_alert(AlertDescription::DecryptError);
-                        return Err(Error::PeerMisbehavedError(
-                            "client sent wrong binder".to_string(),
-                        ));
+                        return Err(wrong_binder(cx.common));
                     }
 
                     chosen_psk_index = Some(i);
@@ -276,11 +372,40 @@ mod client_hello {
                 cx.data.client_cert_chain = resume.client_cert_chain.clone();
             }
 
-            let full_handshake = resumedata.is_none();
+            // Resumption is preferred to an external PSK.
+            let external_psk = match (&resumedata, external_psk) {
+                (None, Some((i, offer))) => {
+                    chosen_psk_index = Some(i);
+                    cx.data.external_psk_identity = Some(offer.external_identity().to_vec());
+                    Some(offer)
+                }
+                _ => None,
+            };
+
+            // Only a full handshake uses our certificate.
+            let full_handshake_key = match (&resumedata, &external_psk) {
+                (None, None) => Some(server_key.as_ref().ok_or_else(|| {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::AccessDenied);
+                    Error::General("no server certificate chain resolved".to_string())
+                })?),
+                _ => None,
+            };
+            let (server_cert_type, client_cert_type) = match full_handshake_key {
+                Some(server_key) => choose_cert_types(
+                    cx.common,
+                    client_hello,
+                    server_key,
+                    &self.config,
+                    &mut self.extra_exts,
+                )?,
+                None => (CertificateType::X509, CertificateType::X509),
+            };
+
             self.transcript.add_message(chm);
-            let key_schedule = emit_server_hello(
+            let (key_schedule, client_early_traffic_secret) = emit_server_hello(
                 &mut self.transcript,
-                &self.randoms,
+                &mut self.randoms,
                 self.suite,
                 cx,
                 &client_hello.session_id,
@@ -289,15 +414,20 @@ mod client_hello {
                 resumedata
                     .as_ref()
                     .map(|x| &x.master_secret.0[..]),
+                external_psk.as_ref(),
+                early_data_requested,
+                self.ech.is_some(),
                 &self.config,
             )?;
             if !self.done_retry {
                 emit_fake_ccs(&mut cx.common);
             }
 
-            let (mut ocsp_response, mut sct_list) =
-                (server_key.get_ocsp(), server_key.get_sct_list());
-            emit_encrypted_extensions(
+            let (mut ocsp_response, mut sct_list) = match full_handshake_key {
+                Some(server_key) => (server_key.get_ocsp(), server_key.get_sct_list()),
+                None => (None, None),
+            };
+            let early_data = emit_encrypted_extensions(
                 &mut self.transcript,
                 self.suite,
                 cx,
@@ -305,25 +435,81 @@ mod client_hello {
                 &mut sct_list,
                 client_hello,
                 resumedata.as_ref(),
+                chosen_psk_index,
                 self.extra_exts,
+                self.ech.is_some(),
                 &self.config,
             )?;
 
-            let doing_client_auth = if full_handshake {
+            // For QUIC, early data is carried outside of TLS.  Otherwise we
+            // read it with the client's early traffic key, or skip over it
+            // if we rejected it.
+            let mut expect_early_data = false;
+            if !cx.common.is_quic() {
+                match (early_data, client_early_traffic_secret) {
+                    (EarlyDataDecision::Accepted, Some(secret)) => {
+                        cx.data
+                            .early_data
+                            .accept(self.config.max_early_data_size as usize);
+                        cx.common
+                            .record_layer
+                            .set_message_decrypter(cipher::new_tls13_read(self.suite, &secret)?);
+                        expect_early_data = true;
+                    }
+                    (EarlyDataDecision::RequestedButRejected, _) => {
+                        cx.common
+                            .record_layer
+                            .set_trial_decryption(self.config.max_early_data_size as usize);
+                    }
+                    _ => {}
+                }
+            }
+
+            let doing_client_auth = if let Some(server_key) = full_handshake_key {
                 let client_auth =
                     emit_certificate_req_tls13(&mut self.transcript, cx, &self.config)?;
+                let compressor = compress::choose_compressor(
+                    &self.config.cert_compressors,
+                    client_hello.get_cert_compression_extension(),
+                );
+                // A raw public key is sent alone, in place of the chain.
+                let raw_public_key = match server_cert_type {
+                    CertificateType::RawPublicKey => server_key
+                        .get_raw_public_key()
+                        .map(|spki| vec![Certificate(spki.to_vec())]),
+                    _ => None,
+                };
+                let (cert_chain, ocsp_response, sct_list) = match &raw_public_key {
+                    Some(raw_public_key) => (&raw_public_key[..], None, None),
+                    None => (server_key.get_cert(), ocsp_response, sct_list),
+                };
+                let delegated_credential = server_key
+                    .get_delegated_credential()
+                    .filter(|_| raw_public_key.is_none())
+                    .filter(
+                        |dc| match client_hello.get_delegated_credential_extension() {
+                            Some(dc_schemes) => dc.usable_with(dc_schemes, &sigschemes_ext),
+                            None => false,
+                        },
+                    );
                 emit_certificate_tls13(
                     &mut self.transcript,
                     &mut cx.common,
-                    server_key.get_cert(),
+                    cert_chain,
                     ocsp_response,
                     sct_list,
+                    delegated_credential.map(DelegatedCredential::payload),
+                    compressor.map(|c| c.as_ref()),
                 );
+                let (signing_key, schemes) = match delegated_credential {
+                    Some(dc) => (dc.signing_key(), vec![dc.scheme()]),
+                    None => (server_key.get_key(), sigschemes_ext.clone()),
+                };
                 emit_certificate_verify_tls13(
                     &mut self.transcript,
                     &mut cx.common,
-                    server_key.get_key(),
-                    &sigschemes_ext,
+                    signing_key,
+                    &schemes,
                 )?;
                 client_auth
             } else {
@@ -338,7 +524,7 @@ mod client_hello {
                 cx,
                 key_schedule,
                 &self.config,
-            );
+            )?;
 
             if doing_client_auth {
                 Ok(Box::new(ExpectCertificate {
@@ -347,6 +533,15 @@ mod client_hello {
                     suite: self.suite,
                     key_schedule: key_schedule_traffic,
                     send_ticket: self.send_ticket,
+                    client_cert_type,
+                }))
+            } else if expect_early_data {
+                Ok(Box::new(ExpectEarlyData {
+                    config: self.config,
+                    transcript: self.transcript,
+                    suite: self.suite,
+                    key_schedule: key_schedule_traffic,
+                    send_ticket: self.send_ticket,
                 }))
             } else {
                 Ok(Box::new(ExpectFinished {
@@ -360,35 +555,106 @@ mod client_hello {
         }
     }
 
+    fn wrong_binder(common: &mut ConnectionCommon) -> Error {
+        common.send_fatal_alert(AlertDescription::DecryptError);
+        Error::PeerMisbehavedError("client sent wrong binder".to_string())
+    }
+
+    /// Choose the type of certificate (RFC7250) we send, and the type
+    /// the client should send if we ask for one, from those the client
+    /// offered.  Our choices are added to `exts`, our encrypted extensions.
+    fn choose_cert_types(
+        common: &mut ConnectionCommon,
+        hello: &ClientHelloPayload,
+        server_key: &ActiveCertifiedKey,
+        config: &ServerConfig,
+        exts: &mut Vec<ServerExtension>,
+    ) -> Result<(CertificateType, CertificateType), Error> {
+        let ours = server_key.certificate_types();
+        let server_cert_type = match hello.get_server_cert_types_extension() {
+            Some(offered) => {
+                let typ = offered
+                    .iter()
+                    .find(|typ| ours.contains(typ))
+                    .cloned()
+                    .ok_or_else(|| {
+                        common.send_fatal_alert(AlertDescription::UnsupportedCertificate);
+                        Error::PeerIncompatibleError(
+                            "no server certificate type in common".to_string(),
+                        )
+                    })?;
+                exts.push(ServerExtension::ServerCertType(typ));
+                typ
+            }
+            None if ours.contains(&CertificateType::X509) => CertificateType::X509,
+            None => {
+                return Err(hs::incompatible(
+                    common,
+                    "client does not support raw public keys",
+                ));
+            }
+        };
+
+        let mut client_cert_type = CertificateType::X509;
+        match hello.get_client_cert_types_extension() {
+            Some(offered) if config.verifier.offer_client_auth() => {
+                let accepted = config.verifier.certificate_types();
+                client_cert_type = offered
+                    .iter()
+                    .find(|typ| accepted.contains(typ))
+                    .cloned()
+                    .ok_or_else(|| {
+                        common.send_fatal_alert(AlertDescription::UnsupportedCertificate);
+                        Error::PeerIncompatibleError(
+                            "no client certificate type in common".to_string(),
+                        )
+                    })?;
+                exts.push(ServerExtension::ClientCertType(client_cert_type));
+            }
+            _ => {}
+        }
+
+        Ok((server_cert_type, client_cert_type))
+    }
+
     fn emit_server_hello(
         transcript: &mut HandshakeHash,
-        randoms: &ConnectionRandoms,
+        randoms: &mut ConnectionRandoms,
         suite: &'static Tls13CipherSuite,
         cx: &mut ServerContext<'_>,
         session_id: &SessionID,
-        share: &KeyShareEntry,
+        share: Option<&KeyShareEntry>,
         chosen_psk_idx: Option<usize>,
         resuming_psk: Option<&[u8]>,
+        external_psk: Option<&PskOffer>,
+        early_data_requested: bool,
+        ech_accepted: bool,
         config: &ServerConfig,
-    ) -> Result<KeyScheduleHandshake, Error> {
+    ) -> Result<(KeyScheduleHandshake, Option<Prk>), Error> {
         let mut extensions = Vec::new();
 
-        // Do key exchange
-        let kxr = kx::KeyExchange::choose(share.group, &config.kx_groups)
-            .and_then(kx::KeyExchange::start)
-            .ok_or(Error::FailedToGetRandomBytes)?
-            .complete(&share.payload.0)
-            .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?;
-
-        let kse = KeyShareEntry::new(share.group, kxr.pubkey.as_ref());
-        extensions.push(ServerExtension::KeyShare(kse));
+        // Do key exchange, unless we're in `psk_ke` mode
+        let kxr = match share {
+            Some(share) => {
+                let skxg = kx::KeyExchange::choose(share.group, &config.kx_groups)
+                    .ok_or(Error::FailedToGetRandomBytes)?;
+                let kxr = config
+                    .provider
+                    .respond_key_exchange(skxg, &share.payload.0)?;
+
+                let kse = KeyShareEntry::new(share.group, kxr.pubkey.as_ref());
+                extensions.push(ServerExtension::KeyShare(kse));
+                Some(kxr)
+            }
+            None => None,
+        };
         extensions.push(ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
 
         if let Some(psk_idx) = chosen_psk_idx {
             extensions.push(ServerExtension::PresharedKey(psk_idx as u16));
         }
 
-        let sh = Message {
+        let mut sh = Message {
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
                 typ: HandshakeType::ServerHello,
@@ -405,7 +671,10 @@ mod client_hello {
 
         cx.common.check_aligned_handshake()?;
 
-        #[cfg(feature = "quic")]
+        if ech_accepted {
+            ech::confirm_server_hello(suite, transcript, randoms, &mut sh);
+        }
+
         let client_hello_hash = transcript.get_hash_given(&[]);
 
         trace!("sending server hello {:?}", sh);
@@ -413,27 +682,48 @@ mod client_hello {
         cx.common.send_msg(sh, false);
 
         // Start key schedule
-        let key_schedule = if let Some(psk) = resuming_psk {
-            let early_key_schedule = KeyScheduleEarly::new(suite.hkdf_algorithm, psk);
+        let mut client_early_traffic_secret = None;
+        let early_key_schedule = if let Some(psk) = resuming_psk {
+            let early_key_schedule =
+                KeyScheduleEarly::new(config.provider, suite.hash_algorithm, psk);
+
+            if early_data_requested {
+                let secret = early_key_schedule.client_early_traffic_secret(
+                    &client_hello_hash,
+                    &*config.key_log,
+                    &randoms.client,
+                );
 
-            #[cfg(feature = "quic")]
-            {
-                if cx.common.protocol == Protocol::Quic {
-                    let client_early_traffic_secret = early_key_schedule
-                        .client_early_traffic_secret(
-                            &client_hello_hash,
-                            &*config.key_log,
-                            &randoms.client,
-                        );
-                    // If 0-RTT should be rejected, this will be clobbered by ExtensionProcessing
-                    // before the application can see.
-                    cx.common.quic.early_secret = Some(client_early_traffic_secret);
+                #[cfg(feature = "quic")]
+                {
+                    if cx.common.protocol == Protocol::Quic {
+                        // If 0-RTT should be rejected, this will be clobbered by
+                        // decide_if_early_data_allowed before the application can see.
+                        cx.common.quic.early_secret = Some(quic::ring_prk(&secret));
+                    }
                 }
+
+                client_early_traffic_secret = Some(secret);
+                cx.data.early_exporter = Some(Box::new(early_key_schedule.early_exporter(
+                    &client_hello_hash,
+                    &*config.key_log,
+                    &randoms.client,
+                )));
             }
 
-            early_key_schedule.into_handshake(&kxr.shared_secret)
+            Some(early_key_schedule)
         } else {
-            KeyScheduleNonSecret::new(suite.hkdf_algorithm).into_handshake(&kxr.shared_secret)
+            external_psk.map(PskOffer::key_schedule)
+        };
+
+        let key_schedule = match (early_key_schedule, kxr) {
+            (Some(early_key_schedule), Some(kxr)) => {
+                early_key_schedule.into_handshake(&kxr.shared_secret)
+            }
+            (Some(early_key_schedule), None) => early_key_schedule.into_handshake_for_psk_ke(),
+            (None, Some(kxr)) => KeyScheduleNonSecret::new(config.provider, suite.hash_algorithm)
+                .into_handshake(&kxr.shared_secret),
+            (None, None) => unreachable!("psk_ke without a psk"),
         };
 
         let handshake_hash = transcript.get_current_hash();
@@ -446,20 +736,17 @@ mod client_hello {
         // Encrypt with our own key, decrypt with the peer's key
         cx.common
             .record_layer
-            .set_message_encrypter(cipher::new_tls13_write(suite, &server_key));
+            .set_message_encrypter(cipher::new_tls13_write(suite, &server_key)?);
         cx.common
             .record_layer
-            .set_message_decrypter(cipher::new_tls13_read(suite, &client_key));
+            .set_message_decrypter(cipher::new_tls13_read(suite, &client_key)?);
 
         #[cfg(feature = "quic")]
         {
-            cx.common.quic.hs_secrets = Some(quic::Secrets {
-                client: client_key,
-                server: server_key,
-            });
+            cx.common.quic.hs_secrets = Some(quic::Secrets::new(&client_key, &server_key));
         }
 
-        Ok(key_schedule)
+        Ok((key_schedule, client_early_traffic_secret))
     }
 
     fn emit_fake_ccs(common: &mut ConnectionCommon) {
@@ -478,6 +765,7 @@ mod client_hello {
         suite: &'static Tls13CipherSuite,
         common: &mut ConnectionCommon,
         group: NamedGroup,
+        ech_inner_random: Option<&Random>,
     ) {
         let mut req = HelloRetryRequest {
             legacy_version: ProtocolVersion::TLSv1_2,
@@ -493,6 +781,11 @@ mod client_hello {
                 ProtocolVersion::TLSv1_3,
             ));
 
+        transcript.rollup_for_hrr();
+        if let Some(inner_random) = ech_inner_random {
+            ech::confirm_hello_retry(suite, transcript, inner_random, &mut req);
+        }
+
         let m = Message {
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
@@ -502,7 +795,6 @@ mod client_hello {
         };
 
         trace!("Requesting retry {:?}", m);
-        transcript.rollup_for_hrr();
         transcript.add_message(&m);
         common.send_msg(m, false);
     }
@@ -515,14 +807,15 @@ mod client_hello {
         sct_list: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
+        chosen_psk_idx: Option<usize>,
         extra_exts: Vec<ServerExtension>,
+        ech_accepted: bool,
         config: &ServerConfig,
-    ) -> Result<(), Error> {
+    ) -> Result<EarlyDataDecision, Error> {
         let mut ep = hs::ExtensionProcessing::new();
         ep.process_common(
             config,
             cx,
-            suite.into(),
             ocsp_response,
             sct_list,
             hello,
@@ -530,6 +823,21 @@ mod client_hello {
             extra_exts,
         )?;
 
+        // Tell a client whose ECH offer we could not accept what it
+        // should use next time.
+        if !ech_accepted && hello.get_ech_extension().is_some() && !config.ech_keys.is_empty() {
+            ep.exts
+                .push(ServerExtension::EncryptedClientHello(ech::retry_configs(
+                    &config.ech_keys,
+                )));
+        }
+
+        let early_data =
+            decide_if_early_data_allowed(cx, hello, resumedata, chosen_psk_idx, suite, config);
+        if early_data == EarlyDataDecision::Accepted {
+            ep.exts.push(ServerExtension::EarlyData);
+        }
+
         let ee = Message {
             version: ProtocolVersion::TLSv1_3,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
@@ -541,7 +849,61 @@ mod client_hello {
         trace!("sending encrypted extensions {:?}", ee);
         transcript.add_message(&ee);
         cx.common.send_msg(ee, true);
-        Ok(())
+        Ok(early_data)
+    }
+
+    fn decide_if_early_data_allowed(
+        cx: &mut ServerContext<'_>,
+        hello: &ClientHelloPayload,
+        resumedata: Option<&persist::ServerSessionValue>,
+        chosen_psk_idx: Option<usize>,
+        suite: &'static Tls13CipherSuite,
+        config: &ServerConfig,
+    ) -> EarlyDataDecision {
+        let rejected_or_disabled = match hello.early_data_extension_offered() {
+            true => EarlyDataDecision::RequestedButRejected,
+            false => EarlyDataDecision::Disabled,
+        };
+
+        // "In order to accept early data, the server MUST have accepted a PSK
+        // cipher suite and selected the first key offered in the client's
+        // "pre_shared_key" extension." -- RFC8446, 4.2.10
+        let resume = match (resumedata, chosen_psk_idx) {
+            (Some(resume), Some(0)) => resume,
+            _ => return rejected_or_disabled,
+        };
+
+        // A non-zero max_early_data_size controls whether early data is
+        // allowed at all.  Over TCP we also require stateful resumption, so
+        // that the ticket cannot be replayed.  QUIC servers accept early
+        // data with stateless tickets too, as they always have.
+        let early_data_configured =
+            config.max_early_data_size > 0 && (cx.common.is_quic() || !config.ticketer.enabled());
+
+        // "In order to accept early data, the server [...] MUST verify that
+        // the following values are the same as those associated with the
+        // selected PSK: the TLS version number, the selected cipher suite,
+        // the selected ALPN protocol, if any" -- RFC8446, 4.2.10
+        //
+        // The ALPN protocol was checked in `ExtensionProcessing::process_common`.
+        let early_data_possible = rejected_or_disabled == EarlyDataDecision::RequestedButRejected
+            && Some(resume.version) == cx.common.negotiated_version
+            && resume.cipher_suite == suite.common.suite;
+
+        if early_data_configured && early_data_possible && !cx.data.early_data.was_rejected() {
+            EarlyDataDecision::Accepted
+        } else {
+            #[cfg(feature = "quic")]
+            {
+                if cx.common.is_quic() {
+                    // Clobber value set in emit_server_hello
+                    cx.common.quic.early_secret = None;
+                }
+            }
+
+            cx.data.early_exporter = None;
+            rejected_or_disabled
+        }
     }
 
     fn emit_certificate_req_tls13(
@@ -558,11 +920,17 @@ mod client_hello {
             extensions: Vec::new(),
         };
 
-        let schemes = config
+        let verify_schemes = config
             .verifier
             .supported_verify_schemes();
+        let schemes = config
+            .signature_schemes
+            .iter()
+            .filter(|scheme| verify_schemes.contains(scheme))
+            .copied()
+            .collect();
         cr.extensions
-            .push(CertReqExtension::SignatureAlgorithms(schemes.to_vec()));
+            .push(CertReqExtension::SignatureAlgorithms(schemes));
 
         let names = config
             .verifier
@@ -579,6 +947,13 @@ mod client_hello {
                 .push(CertReqExtension::AuthorityNames(names));
         }
 
+        if !config.cert_decompressors.is_empty() {
+            cr.extensions
+                .push(CertReqExtension::CertificateCompressionAlgorithms(
+                    compress::offered_algorithms(&config.cert_decompressors),
+                ));
+        }
+
         let m = Message {
             version: ProtocolVersion::TLSv1_3,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
@@ -599,6 +974,8 @@ mod client_hello {
         cert_chain: &[Certificate],
         ocsp_response: Option<&[u8]>,
         sct_list: Option<&[u8]>,
+        delegated_credential: Option<&DelegatedCredentialPayload>,
+        compressor: Option<&dyn compress::CertificateCompressor>,
     ) {
         let mut cert_entries = vec![];
         for cert in cert_chain {
@@ -626,15 +1003,20 @@ mod client_hello {
                     .exts
                     .push(CertificateExtension::make_sct(sct_list.to_owned()));
             }
+
+            if let Some(dc) = delegated_credential {
+                end_entity_cert
+                    .exts
+                    .push(CertificateExtension::DelegatedCredential(dc.clone()));
+            }
         }
 
         let cert_body = CertificatePayloadTLS13::new(cert_entries);
         let c = Message {
             version: ProtocolVersion::TLSv1_3,
-            payload: MessagePayload::Handshake(HandshakeMessagePayload {
-                typ: HandshakeType::Certificate,
-                payload: HandshakePayload::CertificateTLS13(cert_body),
-            }),
+            payload: MessagePayload::Handshake(compress::certificate_message(
+                cert_body, compressor,
+            )),
         };
 
         trace!("sending certificate {:?}", c);
@@ -680,7 +1062,7 @@ mod client_hello {
         cx: &mut ServerContext<'_>,
         key_schedule: KeyScheduleHandshake,
         config: &ServerConfig,
-    ) -> KeyScheduleTrafficWithClientFinishedPending {
+    ) -> Result<KeyScheduleTrafficWithClientFinishedPending, Error> {
         let handshake_hash = transcript.get_current_hash();
         let verify_data = key_schedule.sign_server_finish(&handshake_hash);
         let verify_data_payload = Payload::new(verify_data.as_ref());
@@ -708,17 +1090,75 @@ mod client_hello {
             );
         cx.common
             .record_layer
-            .set_message_encrypter(cipher::new_tls13_write(suite, &server_key));
+            .set_message_encrypter(cipher::new_tls13_write(suite, &server_key)?);
 
         #[cfg(feature = "quic")]
         {
-            cx.common.quic.traffic_secrets = Some(quic::Secrets {
-                client: _client_key,
-                server: server_key,
-            });
+            cx.common.quic.traffic_secrets = Some(quic::Secrets::new(&_client_key, &server_key));
         }
 
-        key_schedule_traffic
+        Ok(key_schedule_traffic)
+    }
+}
+
+struct ExpectEarlyData {
+    config: Arc<ServerConfig>,
+    transcript: HandshakeHash,
+    suite: &'static Tls13CipherSuite,
+    key_schedule: KeyScheduleTrafficWithClientFinishedPending,
+    send_ticket: bool,
+}
+
+impl hs::State for ExpectEarlyData {
+    fn handle(mut self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> hs::NextStateOrError {
+        match m.payload {
+            MessagePayload::ApplicationData(payload) => {
+                if cx
+                    .data
+                    .early_data
+                    .take_received_plaintext(payload)
+                {
+                    Ok(self)
+                } else {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::UnexpectedMessage);
+                    Err(Error::PeerMisbehavedError(
+                        "too much early_data received".into(),
+                    ))
+                }
+            }
+            MessagePayload::Handshake(HandshakeMessagePayload {
+                typ: HandshakeType::EndOfEarlyData,
+                payload: HandshakePayload::EndOfEarlyData,
+            }) => {
+                cx.common.check_aligned_handshake()?;
+
+                // Switch to the client's handshake key to read its Finished.
+                cx.common
+                    .record_layer
+                    .set_message_decrypter(cipher::new_tls13_read(
+                        self.suite,
+                        self.key_schedule.handshake_client_key(),
+                    )?);
+
+                self.transcript.add_message(&m);
+                Ok(Box::new(ExpectFinished {
+                    config: self.config,
+                    transcript: self.transcript,
+                    suite: self.suite,
+                    key_schedule: self.key_schedule,
+                    send_ticket: self.send_ticket,
+                }))
+            }
+            MessagePayload::Handshake(ref payload) => Err(inappropriate_handshake_message(
+                payload,
+                &[HandshakeType::EndOfEarlyData],
+            )),
+            _ => Err(inappropriate_message(
+                &m,
+                &[ContentType::ApplicationData, ContentType::Handshake],
+            )),
+        }
     }
 }
 
@@ -728,15 +1168,39 @@ struct ExpectCertificate {
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     send_ticket: bool,
+    client_cert_type: CertificateType,
 }
 
 impl hs::State for ExpectCertificate {
     fn handle(mut self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> hs::NextStateOrError {
-        let certp = require_handshake_msg!(
-            m,
-            HandshakeType::Certificate,
-            HandshakePayload::CertificateTLS13
+        check_message(
+            &m,
+            &[ContentType::Handshake],
+            &[
+                HandshakeType::Certificate,
+                HandshakeType::CompressedCertificate,
+            ],
         )?;
+        let decompressed;
+        let certp = match m.payload {
+            MessagePayload::Handshake(HandshakeMessagePayload {
+                payload: HandshakePayload::CompressedCertificate(ref compressed),
+                ..
+            }) => {
+                decompressed = compress::decompress_certificate(
+                    compressed,
+                    &self.config.cert_decompressors,
+                    self.config.max_decompressed_cert_size,
+                    cx.common,
+                )?;
+                &decompressed
+            }
+            _ => require_handshake_msg!(
+                m,
+                HandshakeType::Certificate,
+                HandshakePayload::CertificateTLS13
+            )?,
+        };
         self.transcript.add_message(&m);
 
         // We don't send any CertificateRequest extensions, so any extensions
@@ -749,6 +1213,14 @@ impl hs::State for ExpectCertificate {
 
         let client_cert = certp.convert();
 
+        // A raw public key is sent alone, in place of the certificate chain.
+        let raw_public_key = self.client_cert_type == CertificateType::RawPublicKey;
+        if raw_public_key && client_cert.len() > 1 {
+            return Err(cx
+                .common
+                .illegal_param("client sent more than one raw public key"));
+        }
+
         let mandatory = self
             .config
             .verifier
@@ -782,13 +1254,20 @@ impl hs::State for ExpectCertificate {
         };
 
         let now = std::time::SystemTime::now();
-        self.config
-            .verifier
-            .verify_client_cert(end_entity, intermediates, cx.data.get_sni().as_ref(), now)
-            .map_err(|err| {
-                hs::incompatible(&mut cx.common, "certificate invalid");
-                err
-            })?;
+        let sni = cx.data.get_sni();
+        if raw_public_key {
+            self.config
+                .verifier
+                .verify_raw_public_key(&end_entity.0, sni.as_ref(), now)
+        } else {
+            self.config
+                .verifier
+                .verify_client_cert(end_entity, intermediates, sni.as_ref(), now)
+        }
+        .map_err(|err| {
+            hs::incompatible(&mut cx.common, "certificate invalid");
+            err
+        })?;
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
@@ -796,6 +1275,7 @@ impl hs::State for ExpectCertificate {
             transcript: self.transcript,
             key_schedule: self.key_schedule,
             client_cert,
+            client_cert_type: self.client_cert_type,
             send_ticket: self.send_ticket,
         }))
     }
@@ -807,6 +1287,7 @@ struct ExpectCertificateVerify {
     suite: &'static Tls13CipherSuite,
     key_schedule: KeyScheduleTrafficWithClientFinishedPending,
     client_cert: Vec<Certificate>,
+    client_cert_type: CertificateType,
     send_ticket: bool,
 }
 
@@ -823,9 +1304,15 @@ impl hs::State for ExpectCertificateVerify {
             let certs = &self.client_cert;
             let msg = verify::construct_tls13_client_verify_message(&handshake_hash);
 
-            self.config
-                .verifier
-                .verify_tls13_signature(&msg, &certs[0], sig)
+            verify::check_signature_scheme(&self.config.signature_schemes, sig).and_then(|()| {
+                if self.client_cert_type == CertificateType::RawPublicKey {
+                    verify::verify_tls13_spki(self.config.provider, &msg, &certs[0].0, sig)
+                } else {
+                    self.config
+                        .verifier
+                        .verify_tls13_signature(self.config.provider, &msg, &certs[0], sig)
+                }
+            })
         };
 
         if let Err(e) = rc {
@@ -889,7 +1376,7 @@ impl ExpectFinished {
         key_schedule: &KeyScheduleTraffic,
         config: &ServerConfig,
     ) -> Result<(), rand::GetRandomFailed> {
-        let nonce = rand::random_vec(32)?;
+        let nonce = rand::random_vec(config.provider, 32)?;
         let plain =
             get_server_session_value(transcript, suite, key_schedule, cx, &nonce).get_encoding();
 
@@ -901,7 +1388,7 @@ impl ExpectFinished {
             };
             (ticket, config.ticketer.lifetime())
         } else {
-            let id = rand::random_vec(32)?;
+            let id = rand::random_vec(config.provider, 32)?;
             let stored = config
                 .session_storage
                 .put(id.clone(), plain);
@@ -913,18 +1400,16 @@ impl ExpectFinished {
             (id, stateful_lifetime)
         };
 
-        let age_add = rand::random_u32()?; // nb, we don't do 0-RTT data, so whatever
-        #[allow(unused_mut)]
+        // nb, we don't check ticket ages: over TCP, 0-RTT is only accepted
+        // with single-use tickets
+        let age_add = rand::random_u32(config.provider)?;
         let mut payload = NewSessionTicketPayloadTLS13::new(lifetime, age_add, nonce, ticket);
-        #[cfg(feature = "quic")]
-        {
-            if config.max_early_data_size > 0 && cx.common.protocol == Protocol::Quic {
-                payload
-                    .exts
-                    .push(NewSessionTicketExtension::EarlyData(
-                        config.max_early_data_size,
-                    ));
-            }
+        if config.max_early_data_size > 0 && (cx.common.is_quic() || !stateless) {
+            payload
+                .exts
+                .push(NewSessionTicketExtension::EarlyData(
+                    config.max_early_data_size,
+                ));
         }
         let m = Message {
             version: ProtocolVersion::TLSv1_3,
@@ -969,7 +1454,7 @@ impl hs::State for ExpectFinished {
         // Install keying to read future messages.
         cx.common
             .record_layer
-            .set_message_decrypter(cipher::new_tls13_read(self.suite, &client_key));
+            .set_message_decrypter(cipher::new_tls13_read(self.suite, &client_key)?);
 
         if self.send_ticket {
             Self::emit_ticket(
@@ -981,6 +1466,9 @@ impl hs::State for ExpectFinished {
             )?;
         }
 
+        cx.common
+            .record_layer
+            .set_confidentiality_limit(self.suite.confidentiality_limit);
         cx.common.start_traffic();
 
         #[cfg(feature = "quic")]
@@ -1027,6 +1515,7 @@ impl ExpectTraffic {
         }
 
         common.check_aligned_handshake()?;
+        common.count_key_update()?;
 
         match kur {
             KeyUpdateRequest::UpdateNotRequested => {}
@@ -1045,10 +1534,31 @@ impl ExpectTraffic {
             .next_client_application_traffic_secret();
         common
             .record_layer
-            .set_message_decrypter(cipher::new_tls13_read(self.suite, &new_read_key));
+            .set_message_decrypter(cipher::new_tls13_read(self.suite, &new_read_key)?);
 
         Ok(())
     }
+
+    /// Sends `msg` (a `KeyUpdate`) and then switches to our next write key.
+    ///
+    /// This also satisfies any pending request from the peer for us to update.
+    fn write_key_update(
+        &mut self,
+        common: &mut ConnectionCommon,
+        msg: Message,
+    ) -> Result<(), Error> {
+        let write_key = self
+            .key_schedule
+            .next_server_application_traffic_secret();
+        let encrypter = cipher::new_tls13_write(self.suite, &write_key)?;
+
+        self.want_write_key_update = false;
+        common.send_msg_encrypt(msg.into());
+        common
+            .record_layer
+            .set_message_encrypter(encrypter);
+        Ok(())
+    }
 }
 
 impl hs::State for ExpectTraffic {
@@ -1089,18 +1599,29 @@ impl hs::State for ExpectTraffic {
             .export_keying_material(output, label, context)
     }
 
-    fn perhaps_write_key_update(&mut self, common: &mut ConnectionCommon) {
-        if self.want_write_key_update {
-            self.want_write_key_update = false;
-            common.send_msg_encrypt(Message::build_key_update_notify().into());
-
-            let write_key = self
-                .key_schedule
-                .next_server_application_traffic_secret();
-            common
-                .record_layer
-                .set_message_encrypter(cipher::new_tls13_write(self.suite, &write_key));
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        self.key_schedule
+            .extract_secrets(self.suite, false)
+    }
+
+    fn perhaps_write_key_update(&mut self, common: &mut ConnectionCommon) -> Result<(), Error> {
+        if self.want_write_key_update || common.record_layer.wants_key_update() {
+            self.write_key_update(common, Message::build_key_update_notify())?;
         }
+        Ok(())
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        common: &mut ConnectionCommon,
+        request_peer_update: bool,
+    ) -> Result<(), Error> {
+        let msg = if request_peer_update {
+            Message::build_key_update_request()
+        } else {
+            Message::build_key_update_notify()
+        };
+        self.write_key_update(common, msg)
     }
 }
 
@@ -1127,4 +1648,20 @@ impl hs::State for ExpectQuicTraffic {
         self.key_schedule
             .export_keying_material(output, label, context)
     }
+
+    fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
+        Err(Error::General(
+            "traffic secrets are managed by QUIC, not TLS".to_string(),
+        ))
+    }
+
+    fn refresh_traffic_keys(
+        &mut self,
+        _common: &mut ConnectionCommon,
+        _request_peer_update: bool,
+    ) -> Result<(), Error> {
+        Err(Error::General(
+            "traffic key updates are managed by QUIC, not TLS".to_string(),
+        ))
+    }
 }
@@ -1,6 +1,7 @@
+use crate::delegated::DelegatedCredential;
 use crate::error::Error;
 use crate::key;
-use crate::msgs::enums::{SignatureAlgorithm, SignatureScheme};
+use crate::msgs::enums::{CertificateType, SignatureAlgorithm, SignatureScheme};
 
 use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair};
 
@@ -29,7 +30,11 @@ pub trait Signer: Send + Sync {
 }
 
 /// A packaged-together certificate chain, matching `SigningKey` and
-/// optional stapled OCSP response and/or SCT list.
+/// optional stapled OCSP response, SCT list and/or delegated credential.
+///
+/// This may instead, or as well, hold the key's raw public key for
+/// peers which ask for one (RFC7250).  In that case the certificate
+/// chain may be empty.
 #[derive(Clone)]
 pub struct CertifiedKey {
     /// The certificate chain.
@@ -46,6 +51,16 @@ pub struct CertifiedKey {
     /// certificate is included on those logs.  This must be
     /// a `SignedCertificateTimestampList` encoding; see RFC6962.
     pub sct_list: Option<Vec<u8>>,
+
+    /// An optional delegated credential (RFC9345) issued by the
+    /// certified key.  This is used in TLS1.3 in place of `key`
+    /// with clients which support it.
+    pub delegated_credential: Option<DelegatedCredential>,
+
+    /// The DER-encoded SubjectPublicKeyInfo of `key`, sent in place of
+    /// the certificate chain in TLS1.3 to peers which negotiate raw
+    /// public keys (RFC7250).
+    pub raw_public_key: Option<Vec<u8>>,
 }
 
 impl CertifiedKey {
@@ -59,7 +74,32 @@ impl CertifiedKey {
             key,
             ocsp: None,
             sct_list: None,
+            delegated_credential: None,
+            raw_public_key: None,
+        }
+    }
+
+    /// Make a new CertifiedKey which only has a raw public key (RFC7250):
+    /// `spki` is the DER-encoded SubjectPublicKeyInfo of `key`.
+    ///
+    /// This can only be used with TLS1.3 peers which support raw public keys.
+    pub fn from_raw_public_key(spki: Vec<u8>, key: Arc<dyn SigningKey>) -> Self {
+        Self {
+            raw_public_key: Some(spki),
+            ..Self::new(Vec::new(), key)
+        }
+    }
+
+    /// The certificate types (RFC7250) we can present for this key.
+    pub(crate) fn certificate_types(&self) -> Vec<CertificateType> {
+        let mut types = Vec::new();
+        if self.raw_public_key.is_some() {
+            types.push(CertificateType::RawPublicKey);
+        }
+        if !self.cert.is_empty() {
+            types.push(CertificateType::X509);
         }
+        types
     }
 
     /// The end-entity certificate.
@@ -148,6 +188,11 @@ pub fn any_ecdsa_type(der: &key::PrivateKey) -> Result<Arc<dyn SigningKey>, Sign
         return Ok(Arc::new(ecdsa_p384));
     }
 
+    #[cfg(feature = "secp521r1")]
+    if let Ok(ecdsa_p521) = EcdsaP521SigningKey::new(der) {
+        return Ok(Arc::new(ecdsa_p521));
+    }
+
     Err(SignError(()))
 }
 
@@ -157,7 +202,10 @@ pub fn any_eddsa_type(der: &key::PrivateKey) -> Result<Arc<dyn SigningKey>, Sign
         return Ok(Arc::new(ed25519));
     }
 
-    // TODO: Add support for Ed448
+    #[cfg(feature = "curve448")]
+    if let Ok(ed448) = Ed448SigningKey::new(der) {
+        return Ok(Arc::new(ed448));
+    }
 
     Err(SignError(()))
 }
@@ -317,6 +365,64 @@ impl Signer for EcdsaSigner {
     }
 }
 
+/// A SigningKey for ECDSA P-521 keys, which ring does not support.
+///
+/// Like `EcdsaSigningKey`, this only signs with one SignatureScheme.
+#[cfg(feature = "secp521r1")]
+struct EcdsaP521SigningKey {
+    key: Arc<p521::ecdsa::SigningKey>,
+}
+
+#[cfg(feature = "secp521r1")]
+impl EcdsaP521SigningKey {
+    /// Make a new `EcdsaP521SigningKey` from a DER encoding in PKCS#8 format.
+    fn new(der: &key::PrivateKey) -> Result<Self, SignError> {
+        use p521::pkcs8::DecodePrivateKey;
+
+        p521::SecretKey::from_pkcs8_der(&der.0)
+            .ok()
+            .and_then(|secret| p521::ecdsa::SigningKey::from_bytes(&secret.to_bytes()).ok())
+            .map(|key| Self { key: Arc::new(key) })
+            .ok_or(SignError(()))
+    }
+}
+
+#[cfg(feature = "secp521r1")]
+impl SigningKey for EcdsaP521SigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        if offered.contains(&SignatureScheme::ECDSA_NISTP521_SHA512) {
+            Some(Box::new(EcdsaP521Signer {
+                key: Arc::clone(&self.key),
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ECDSA
+    }
+}
+
+#[cfg(feature = "secp521r1")]
+struct EcdsaP521Signer {
+    key: Arc<p521::ecdsa::SigningKey>,
+}
+
+#[cfg(feature = "secp521r1")]
+impl Signer for EcdsaP521Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let sig: p521::ecdsa::Signature =
+            p521::ecdsa::signature::Signer::try_sign(self.key.as_ref(), message)
+                .map_err(|_| Error::General("signing failed".into()))?;
+        Ok(sig.to_der().as_bytes().to_vec())
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        SignatureScheme::ECDSA_NISTP521_SHA512
+    }
+}
+
 /// A SigningKey that uses exactly one TLS-level SignatureScheme
 /// and one ring-level signature::SigningAlgorithm.
 ///
@@ -379,16 +485,75 @@ impl Signer for Ed25519Signer {
     }
 }
 
+/// A SigningKey for Ed448 keys, which ring does not support.
+#[cfg(feature = "curve448")]
+struct Ed448SigningKey {
+    key: Arc<openssl::pkey::PKey<openssl::pkey::Private>>,
+}
+
+#[cfg(feature = "curve448")]
+impl Ed448SigningKey {
+    /// Make a new `Ed448SigningKey` from a DER encoding in PKCS#8 format
+    /// (RFC8410 section 7).
+    fn new(der: &key::PrivateKey) -> Result<Self, SignError> {
+        openssl::pkey::PKey::private_key_from_pkcs8(&der.0)
+            .ok()
+            .filter(|key| key.id() == openssl::pkey::Id::ED448)
+            .map(|key| Self { key: Arc::new(key) })
+            .ok_or(SignError(()))
+    }
+}
+
+#[cfg(feature = "curve448")]
+impl SigningKey for Ed448SigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        if offered.contains(&SignatureScheme::ED448) {
+            Some(Box::new(Ed448Signer {
+                key: Arc::clone(&self.key),
+            }))
+        } else {
+            None
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        use crate::msgs::handshake::DecomposedSignatureScheme;
+        SignatureScheme::ED448.sign()
+    }
+}
+
+#[cfg(feature = "curve448")]
+struct Ed448Signer {
+    key: Arc<openssl::pkey::PKey<openssl::pkey::Private>>,
+}
+
+#[cfg(feature = "curve448")]
+impl Signer for Ed448Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        openssl::sign::Signer::new_without_digest(&self.key)
+            .and_then(|mut signer| signer.sign_oneshot_to_vec(message))
+            .map_err(|_| Error::General("signing failed".into()))
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        SignatureScheme::ED448
+    }
+}
+
 /// The set of schemes we support for signatures and
 /// that are allowed for TLS1.3.
 pub fn supported_sign_tls13() -> &'static [SignatureScheme] {
     &[
         SignatureScheme::ECDSA_NISTP384_SHA384,
         SignatureScheme::ECDSA_NISTP256_SHA256,
+        #[cfg(feature = "secp521r1")]
+        SignatureScheme::ECDSA_NISTP521_SHA512,
         SignatureScheme::RSA_PSS_SHA512,
         SignatureScheme::RSA_PSS_SHA384,
         SignatureScheme::RSA_PSS_SHA256,
         SignatureScheme::ED25519,
+        #[cfg(feature = "curve448")]
+        SignatureScheme::ED448,
     ]
 }
 
@@ -1,4 +1,6 @@
 use crate::cipher::{self, Tls12AeadAlgorithm};
+use crate::crypto::{AeadKey, CryptoProvider, HashAlgorithm};
+use crate::error::Error;
 use crate::msgs::enums::ProtocolVersion;
 use crate::msgs::enums::{CipherSuite, SignatureAlgorithm, SignatureScheme};
 use crate::msgs::handshake::DecomposedSignatureScheme;
@@ -19,17 +21,89 @@ pub enum BulkAlgorithm {
 
     /// Chacha20 for confidentiality with poly1305 for authenticity.
     Chacha20Poly1305,
+
+    /// AES with 128-bit keys in CCM mode, with a 16 byte tag.
+    Aes128Ccm,
+
+    /// AES with 256-bit keys in CCM mode, with a 16 byte tag.
+    Aes256Ccm,
+
+    /// AES with 128-bit keys in CCM mode, with an 8 byte tag.
+    Aes128Ccm8,
+
+    /// AES with 256-bit keys in CCM mode, with an 8 byte tag.
+    Aes256Ccm8,
+}
+
+impl BulkAlgorithm {
+    /// The length of this algorithm's keys, in bytes.
+    pub(crate) fn key_len(&self) -> usize {
+        match self {
+            Self::Aes128Gcm | Self::Aes128Ccm | Self::Aes128Ccm8 => 16,
+            Self::Aes256Gcm | Self::Chacha20Poly1305 | Self::Aes256Ccm | Self::Aes256Ccm8 => 32,
+        }
+    }
+}
+
+/// The AEAD with which a TLS1.3 cipher suite protects records.
+///
+/// rustls implements this for [`BulkAlgorithm`], using the connection's
+/// [`CryptoProvider`].  Implement it to define a TLS1.3 cipher suite with
+/// another AEAD: rustls takes care of the record layer, deriving keys and
+/// nonces, and needs only the AEAD itself.
+pub trait Tls13AeadAlgorithm: Send + Sync {
+    /// The length of this AEAD's keys, in bytes.
+    fn key_len(&self) -> usize;
+
+    /// Make a key for protecting records, from `key` which is `key_len()`
+    /// bytes long.  `provider` is that of the connection's config.
+    fn new_key(&self, provider: &dyn CryptoProvider, key: &[u8])
+        -> Result<Box<dyn AeadKey>, Error>;
+
+    /// Can this AEAD be used with `provider`?  Cipher suites using an
+    /// AEAD which cannot are not included in the default cipher suites,
+    /// and cannot be configured.
+    fn supported_by(&self, _provider: &dyn CryptoProvider) -> bool {
+        true
+    }
+
+    /// If this AEAD is one of the [`BulkAlgorithm`]s, which one.
+    ///
+    /// QUIC and [`Connection::extract_secrets`] need to know this, and
+    /// are only available with cipher suites which say.
+    ///
+    /// [`Connection::extract_secrets`]: crate::Connection::extract_secrets
+    fn bulk(&self) -> Option<&BulkAlgorithm> {
+        None
+    }
+}
+
+impl Tls13AeadAlgorithm for BulkAlgorithm {
+    fn key_len(&self) -> usize {
+        Self::key_len(self)
+    }
+
+    fn new_key(
+        &self,
+        provider: &dyn CryptoProvider,
+        key: &[u8],
+    ) -> Result<Box<dyn AeadKey>, Error> {
+        provider.aead_key(self, key)
+    }
+
+    fn supported_by(&self, provider: &dyn CryptoProvider) -> bool {
+        provider.aead_supported(self)
+    }
+
+    fn bulk(&self) -> Option<&BulkAlgorithm> {
+        Some(self)
+    }
 }
 
 /// Common state for cipher suites (both for TLS 1.2 and TLS 1.3)
 pub struct CipherSuiteCommon {
     /// The TLS enumeration naming this cipher suite.
     pub suite: CipherSuite,
-
-    /// How to do bulk encryption.
-    pub bulk: BulkAlgorithm,
-
-    pub(crate) aead_algorithm: &'static ring::aead::Algorithm,
 }
 
 /// A cipher suite supported by rustls.
@@ -44,19 +118,40 @@ pub enum SupportedCipherSuite {
     Tls13(&'static Tls13CipherSuite),
 }
 
-/// A TLS 1.3 cipher suite supported by rustls.
+/// A TLS 1.3 cipher suite.
+///
+/// rustls provides those in [`ALL_CIPHERSUITES`]; others can be defined
+/// with their own [`Tls13AeadAlgorithm`].
 pub struct Tls13CipherSuite {
     /// Common cipher suite fields.
     pub common: CipherSuiteCommon,
-    pub(crate) hkdf_algorithm: ring::hkdf::Algorithm,
+
+    /// The hash function used for the transcript, HKDF and HMAC.
+    pub hash_algorithm: HashAlgorithm,
+
+    /// The AEAD which protects records.
+    pub aead_alg: &'static dyn Tls13AeadAlgorithm,
+
+    /// How many records (or QUIC packets) may be encrypted under one key
+    /// before it must be replaced.
+    ///
+    /// See RFC8446 section 5.5 and RFC9001 section 6.6.  For AES-GCM we use
+    /// the RFC9001 figure for both, as it is the more conservative.  For
+    /// ChaCha20-Poly1305 the sequence number wraps before the limit is reached.
+    /// For AES-CCM we use the RFC9001 figure, which also covers the CCM_8 tag.
+    pub confidentiality_limit: u64,
+
+    /// How many records (or QUIC packets) may fail to decrypt under one
+    /// key before the connection must be closed.
+    ///
+    /// This is only relevant to QUIC: in TLS, the first such failure is fatal.
+    pub integrity_limit: u64,
 }
 
 impl Tls13CipherSuite {
     /// Which hash function to use with this suite.
-    pub fn get_hash(&self) -> &'static ring::digest::Algorithm {
-        self.hkdf_algorithm
-            .hmac_algorithm()
-            .digest_algorithm()
+    pub fn get_hash(&self) -> HashAlgorithm {
+        self.hash_algorithm
     }
 
     /// Can a session using suite self resume from suite prev?
@@ -86,7 +181,7 @@ impl fmt::Debug for Tls13CipherSuite {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Tls13CipherSuite")
             .field("suite", &self.common.suite)
-            .field("bulk", &self.common.bulk)
+            .field("bulk", &self.aead_alg.bulk())
             .finish()
     }
 }
@@ -95,7 +190,9 @@ impl fmt::Debug for Tls13CipherSuite {
 pub struct Tls12CipherSuite {
     /// Common cipher suite fields.
     pub common: CipherSuiteCommon,
-    pub(crate) hmac_algorithm: ring::hmac::Algorithm,
+    /// How to do bulk encryption.
+    pub bulk: BulkAlgorithm,
+    pub(crate) hash_algorithm: HashAlgorithm,
     /// How to exchange/agree keys.
     pub kx: KeyExchangeAlgorithm,
 
@@ -130,8 +227,8 @@ impl Tls12CipherSuite {
     }
 
     /// Which hash function to use with this suite.
-    pub fn get_hash(&self) -> &'static ring::digest::Algorithm {
-        self.hmac_algorithm.digest_algorithm()
+    pub fn get_hash(&self) -> HashAlgorithm {
+        self.hash_algorithm
     }
 }
 
@@ -151,14 +248,14 @@ impl fmt::Debug for Tls12CipherSuite {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Tls12CipherSuite")
             .field("suite", &self.common.suite)
-            .field("bulk", &self.common.bulk)
+            .field("bulk", &self.bulk)
             .finish()
     }
 }
 
 impl SupportedCipherSuite {
     /// Which hash function to use with this suite.
-    pub fn get_hash(&self) -> &'static ring::digest::Algorithm {
+    pub fn get_hash(&self) -> HashAlgorithm {
         match self {
             SupportedCipherSuite::Tls12(inner) => inner.get_hash(),
             SupportedCipherSuite::Tls13(inner) => inner.get_hash(),
@@ -170,6 +267,24 @@ impl SupportedCipherSuite {
         self.common().suite
     }
 
+    /// How this suite does bulk encryption.  This is `None` for TLS1.3
+    /// suites whose AEAD is not one of the [`BulkAlgorithm`]s: see
+    /// [`Tls13AeadAlgorithm::bulk`].
+    pub fn bulk(&self) -> Option<&BulkAlgorithm> {
+        match self {
+            SupportedCipherSuite::Tls12(inner) => Some(&inner.bulk),
+            SupportedCipherSuite::Tls13(inner) => inner.aead_alg.bulk(),
+        }
+    }
+
+    /// Can this suite be used with `provider`?
+    pub fn supported_by(&self, provider: &dyn CryptoProvider) -> bool {
+        match self {
+            SupportedCipherSuite::Tls12(inner) => provider.aead_supported(&inner.bulk),
+            SupportedCipherSuite::Tls13(inner) => inner.aead_alg.supported_by(provider),
+        }
+    }
+
     pub(crate) fn common(&self) -> &CipherSuiteCommon {
         match self {
             SupportedCipherSuite::Tls12(inner) => &inner.common,
@@ -205,8 +320,181 @@ impl SupportedCipherSuite {
     }
 }
 
+/// Secrets for transmitting and receiving data over a TLS session,
+/// as returned by [`Connection::extract_secrets`].
+///
+/// These are enough to configure kernel TLS (kTLS) for a socket, and have
+/// the kernel take over encryption and decryption of records.
+///
+/// [`Connection::extract_secrets`]: crate::Connection::extract_secrets
+pub struct ExtractedSecrets {
+    /// The next sequence number and the secrets for the transmit direction.
+    pub tx: (u64, ConnectionTrafficSecrets),
+
+    /// The next sequence number and the secrets for the receive direction.
+    pub rx: (u64, ConnectionTrafficSecrets),
+}
+
+/// [`ExtractedSecrets`] without the sequence numbers, which are held by
+/// the record layer rather than the handshake state.
+pub(crate) struct PartiallyExtractedSecrets {
+    pub(crate) tx: ConnectionTrafficSecrets,
+    pub(crate) rx: ConnectionTrafficSecrets,
+}
+
+/// The key and IV used to encrypt or decrypt records in one direction of
+/// a TLS session.
+///
+/// The field layout follows the Linux `tls12_crypto_info_*` structures.
+/// For AES-GCM and AES-CCM in TLS1.2 the receive `iv` is zero, because the
+/// peer sends the explicit part of each nonce with every record.
+#[non_exhaustive]
+pub enum ConnectionTrafficSecrets {
+    /// Secrets for the AES_128_GCM AEAD algorithm.
+    Aes128Gcm {
+        /// The AEAD key.
+        key: [u8; 16],
+        /// The implicit first four bytes of the nonce.
+        salt: [u8; 4],
+        /// The remaining eight bytes of the nonce.
+        iv: [u8; 8],
+    },
+
+    /// Secrets for the AES_256_GCM AEAD algorithm.
+    Aes256Gcm {
+        /// The AEAD key.
+        key: [u8; 32],
+        /// The implicit first four bytes of the nonce.
+        salt: [u8; 4],
+        /// The remaining eight bytes of the nonce.
+        iv: [u8; 8],
+    },
+
+    /// Secrets for the CHACHA20_POLY1305 AEAD algorithm.
+    Chacha20Poly1305 {
+        /// The AEAD key.
+        key: [u8; 32],
+        /// The nonce, before the sequence number is mixed in.
+        iv: [u8; 12],
+    },
+
+    /// Secrets for the AES_128_CCM AEAD algorithm.
+    Aes128Ccm {
+        /// The AEAD key.
+        key: [u8; 16],
+        /// The implicit first four bytes of the nonce.
+        salt: [u8; 4],
+        /// The remaining eight bytes of the nonce.
+        iv: [u8; 8],
+    },
+
+    /// Secrets for the AES_256_CCM AEAD algorithm.
+    Aes256Ccm {
+        /// The AEAD key.
+        key: [u8; 32],
+        /// The implicit first four bytes of the nonce.
+        salt: [u8; 4],
+        /// The remaining eight bytes of the nonce.
+        iv: [u8; 8],
+    },
+
+    /// Secrets for the AES_128_CCM_8 AEAD algorithm.
+    Aes128Ccm8 {
+        /// The AEAD key.
+        key: [u8; 16],
+        /// The implicit first four bytes of the nonce.
+        salt: [u8; 4],
+        /// The remaining eight bytes of the nonce.
+        iv: [u8; 8],
+    },
+
+    /// Secrets for the AES_256_CCM_8 AEAD algorithm.
+    Aes256Ccm8 {
+        /// The AEAD key.
+        key: [u8; 32],
+        /// The implicit first four bytes of the nonce.
+        salt: [u8; 4],
+        /// The remaining eight bytes of the nonce.
+        iv: [u8; 8],
+    },
+}
+
+impl ConnectionTrafficSecrets {
+    /// Panics if `key` is the wrong length for `bulk`.
+    pub(crate) fn new(bulk: &BulkAlgorithm, key: &[u8], iv: &[u8; 12]) -> Self {
+        let mut salt = [0u8; 4];
+        salt.copy_from_slice(&iv[..4]);
+        let mut explicit = [0u8; 8];
+        explicit.copy_from_slice(&iv[4..]);
+
+        match bulk {
+            BulkAlgorithm::Aes128Gcm => {
+                let mut k = [0u8; 16];
+                k.copy_from_slice(key);
+                Self::Aes128Gcm {
+                    key: k,
+                    salt,
+                    iv: explicit,
+                }
+            }
+            BulkAlgorithm::Aes256Gcm => {
+                let mut k = [0u8; 32];
+                k.copy_from_slice(key);
+                Self::Aes256Gcm {
+                    key: k,
+                    salt,
+                    iv: explicit,
+                }
+            }
+            BulkAlgorithm::Chacha20Poly1305 => {
+                let mut k = [0u8; 32];
+                k.copy_from_slice(key);
+                Self::Chacha20Poly1305 { key: k, iv: *iv }
+            }
+            BulkAlgorithm::Aes128Ccm => {
+                let mut k = [0u8; 16];
+                k.copy_from_slice(key);
+                Self::Aes128Ccm {
+                    key: k,
+                    salt,
+                    iv: explicit,
+                }
+            }
+            BulkAlgorithm::Aes256Ccm => {
+                let mut k = [0u8; 32];
+                k.copy_from_slice(key);
+                Self::Aes256Ccm {
+                    key: k,
+                    salt,
+                    iv: explicit,
+                }
+            }
+            BulkAlgorithm::Aes128Ccm8 => {
+                let mut k = [0u8; 16];
+                k.copy_from_slice(key);
+                Self::Aes128Ccm8 {
+                    key: k,
+                    salt,
+                    iv: explicit,
+                }
+            }
+            BulkAlgorithm::Aes256Ccm8 => {
+                let mut k = [0u8; 32];
+                k.copy_from_slice(key);
+                Self::Aes256Ccm8 {
+                    key: k,
+                    salt,
+                    iv: explicit,
+                }
+            }
+        }
+    }
+}
+
 static TLS12_ECDSA_SCHEMES: &[SignatureScheme] = &[
     SignatureScheme::ED25519,
+    #[cfg(feature = "curve448")]
+    SignatureScheme::ED448,
     SignatureScheme::ECDSA_NISTP521_SHA512,
     SignatureScheme::ECDSA_NISTP384_SHA384,
     SignatureScheme::ECDSA_NISTP256_SHA256,
@@ -226,15 +514,14 @@ pub static TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
     SupportedCipherSuite::Tls12(&Tls12CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
-            bulk: BulkAlgorithm::Chacha20Poly1305,
-            aead_algorithm: &ring::aead::CHACHA20_POLY1305,
         },
+        bulk: BulkAlgorithm::Chacha20Poly1305,
         kx: KeyExchangeAlgorithm::ECDHE,
         sign: TLS12_ECDSA_SCHEMES,
         fixed_iv_len: 12,
         explicit_nonce_len: 0,
         aead_alg: &cipher::ChaCha20Poly1305,
-        hmac_algorithm: ring::hmac::HMAC_SHA256,
+        hash_algorithm: HashAlgorithm::SHA256,
     });
 
 /// The TLS1.2 ciphersuite TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256
@@ -242,15 +529,14 @@ pub static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
     SupportedCipherSuite::Tls12(&Tls12CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
-            bulk: BulkAlgorithm::Chacha20Poly1305,
-            aead_algorithm: &ring::aead::CHACHA20_POLY1305,
         },
+        bulk: BulkAlgorithm::Chacha20Poly1305,
         kx: KeyExchangeAlgorithm::ECDHE,
         sign: TLS12_RSA_SCHEMES,
         fixed_iv_len: 12,
         explicit_nonce_len: 0,
         aead_alg: &cipher::ChaCha20Poly1305,
-        hmac_algorithm: ring::hmac::HMAC_SHA256,
+        hash_algorithm: HashAlgorithm::SHA256,
     });
 
 /// The TLS1.2 ciphersuite TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
@@ -258,15 +544,14 @@ pub static TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite =
     SupportedCipherSuite::Tls12(&Tls12CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
-            bulk: BulkAlgorithm::Aes128Gcm,
-            aead_algorithm: &ring::aead::AES_128_GCM,
         },
+        bulk: BulkAlgorithm::Aes128Gcm,
         kx: KeyExchangeAlgorithm::ECDHE,
         sign: TLS12_RSA_SCHEMES,
         fixed_iv_len: 4,
         explicit_nonce_len: 8,
         aead_alg: &cipher::AesGcm,
-        hmac_algorithm: ring::hmac::HMAC_SHA256,
+        hash_algorithm: HashAlgorithm::SHA256,
     });
 
 /// The TLS1.2 ciphersuite TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
@@ -274,15 +559,14 @@ pub static TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite =
     SupportedCipherSuite::Tls12(&Tls12CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
-            bulk: BulkAlgorithm::Aes256Gcm,
-            aead_algorithm: &ring::aead::AES_256_GCM,
         },
+        bulk: BulkAlgorithm::Aes256Gcm,
         kx: KeyExchangeAlgorithm::ECDHE,
         sign: TLS12_RSA_SCHEMES,
         fixed_iv_len: 4,
         explicit_nonce_len: 8,
         aead_alg: &cipher::AesGcm,
-        hmac_algorithm: ring::hmac::HMAC_SHA384,
+        hash_algorithm: HashAlgorithm::SHA384,
     });
 
 /// The TLS1.2 ciphersuite TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
@@ -290,15 +574,14 @@ pub static TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite =
     SupportedCipherSuite::Tls12(&Tls12CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
-            bulk: BulkAlgorithm::Aes128Gcm,
-            aead_algorithm: &ring::aead::AES_128_GCM,
         },
+        bulk: BulkAlgorithm::Aes128Gcm,
         kx: KeyExchangeAlgorithm::ECDHE,
         sign: TLS12_ECDSA_SCHEMES,
         fixed_iv_len: 4,
         explicit_nonce_len: 8,
         aead_alg: &cipher::AesGcm,
-        hmac_algorithm: ring::hmac::HMAC_SHA256,
+        hash_algorithm: HashAlgorithm::SHA256,
     });
 
 /// The TLS1.2 ciphersuite TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
@@ -306,15 +589,14 @@ pub static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite =
     SupportedCipherSuite::Tls12(&Tls12CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
-            bulk: BulkAlgorithm::Aes256Gcm,
-            aead_algorithm: &ring::aead::AES_256_GCM,
         },
+        bulk: BulkAlgorithm::Aes256Gcm,
         kx: KeyExchangeAlgorithm::ECDHE,
         sign: TLS12_ECDSA_SCHEMES,
         fixed_iv_len: 4,
         explicit_nonce_len: 8,
         aead_alg: &cipher::AesGcm,
-        hmac_algorithm: ring::hmac::HMAC_SHA384,
+        hash_algorithm: HashAlgorithm::SHA384,
     });
 
 /// The TLS1.3 ciphersuite TLS_CHACHA20_POLY1305_SHA256
@@ -322,10 +604,11 @@ pub static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
     SupportedCipherSuite::Tls13(&Tls13CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
-            bulk: BulkAlgorithm::Chacha20Poly1305,
-            aead_algorithm: &ring::aead::CHACHA20_POLY1305,
         },
-        hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+        hash_algorithm: HashAlgorithm::SHA256,
+        aead_alg: &BulkAlgorithm::Chacha20Poly1305,
+        confidentiality_limit: u64::MAX,
+        integrity_limit: 1 << 36,
     });
 
 /// The TLS1.3 ciphersuite TLS_AES_256_GCM_SHA384
@@ -333,26 +616,159 @@ pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite =
     SupportedCipherSuite::Tls13(&Tls13CipherSuite {
         common: CipherSuiteCommon {
             suite: CipherSuite::TLS13_AES_256_GCM_SHA384,
-            bulk: BulkAlgorithm::Aes256Gcm,
-            aead_algorithm: &ring::aead::AES_256_GCM,
         },
-        hkdf_algorithm: ring::hkdf::HKDF_SHA384,
+        hash_algorithm: HashAlgorithm::SHA384,
+        aead_alg: &BulkAlgorithm::Aes256Gcm,
+        confidentiality_limit: 1 << 23,
+        integrity_limit: 1 << 52,
     });
 
 pub(crate) static TLS13_AES_128_GCM_SHA256_INTERNAL: &Tls13CipherSuite = &Tls13CipherSuite {
     common: CipherSuiteCommon {
         suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
-        bulk: BulkAlgorithm::Aes128Gcm,
-        aead_algorithm: &ring::aead::AES_128_GCM,
     },
-    hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+    hash_algorithm: HashAlgorithm::SHA256,
+    aead_alg: &BulkAlgorithm::Aes128Gcm,
+    confidentiality_limit: 1 << 23,
+    integrity_limit: 1 << 52,
 };
 
 /// The TLS1.3 ciphersuite TLS_AES_128_GCM_SHA256
 pub static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite =
     SupportedCipherSuite::Tls13(TLS13_AES_128_GCM_SHA256_INTERNAL);
 
-/// A list of all the cipher suites supported by rustls.
+/// The TLS1.3 ciphersuite TLS_AES_128_CCM_SHA256.  It is not in
+/// [`DEFAULT_CIPHERSUITES`].
+pub static TLS13_AES_128_CCM_SHA256: SupportedCipherSuite =
+    SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS13_AES_128_CCM_SHA256,
+        },
+        hash_algorithm: HashAlgorithm::SHA256,
+        aead_alg: &BulkAlgorithm::Aes128Ccm,
+        confidentiality_limit: 2_965_820,
+        integrity_limit: 2_965_820,
+    });
+
+/// The TLS1.3 ciphersuite TLS_AES_128_CCM_8_SHA256.  It is not in
+/// [`DEFAULT_CIPHERSUITES`]: its short tag makes forgeries much easier,
+/// so use it only with peers which cannot do better.
+pub static TLS13_AES_128_CCM_8_SHA256: SupportedCipherSuite =
+    SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS13_AES_128_CCM_8_SHA256,
+        },
+        hash_algorithm: HashAlgorithm::SHA256,
+        aead_alg: &BulkAlgorithm::Aes128Ccm8,
+        confidentiality_limit: 2_965_820,
+        integrity_limit: 2_965_820,
+    });
+
+/// The TLS1.2 ciphersuite TLS_ECDHE_ECDSA_WITH_AES_128_CCM.  It is not in
+/// [`DEFAULT_CIPHERSUITES`].
+pub static TLS_ECDHE_ECDSA_WITH_AES_128_CCM: SupportedCipherSuite =
+    SupportedCipherSuite::Tls12(&Tls12CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM,
+        },
+        bulk: BulkAlgorithm::Aes128Ccm,
+        kx: KeyExchangeAlgorithm::ECDHE,
+        sign: TLS12_ECDSA_SCHEMES,
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        aead_alg: &cipher::AesCcm,
+        hash_algorithm: HashAlgorithm::SHA256,
+    });
+
+/// The TLS1.2 ciphersuite TLS_ECDHE_ECDSA_WITH_AES_256_CCM.  It is not in
+/// [`DEFAULT_CIPHERSUITES`].
+pub static TLS_ECDHE_ECDSA_WITH_AES_256_CCM: SupportedCipherSuite =
+    SupportedCipherSuite::Tls12(&Tls12CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM,
+        },
+        bulk: BulkAlgorithm::Aes256Ccm,
+        kx: KeyExchangeAlgorithm::ECDHE,
+        sign: TLS12_ECDSA_SCHEMES,
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        aead_alg: &cipher::AesCcm,
+        hash_algorithm: HashAlgorithm::SHA256,
+    });
+
+/// The TLS1.2 ciphersuite TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8.  It is not in
+/// [`DEFAULT_CIPHERSUITES`].
+pub static TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8: SupportedCipherSuite =
+    SupportedCipherSuite::Tls12(&Tls12CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8,
+        },
+        bulk: BulkAlgorithm::Aes128Ccm8,
+        kx: KeyExchangeAlgorithm::ECDHE,
+        sign: TLS12_ECDSA_SCHEMES,
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        aead_alg: &cipher::AesCcm,
+        hash_algorithm: HashAlgorithm::SHA256,
+    });
+
+/// The TLS1.2 ciphersuite TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8.  It is not in
+/// [`DEFAULT_CIPHERSUITES`].
+pub static TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8: SupportedCipherSuite =
+    SupportedCipherSuite::Tls12(&Tls12CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8,
+        },
+        bulk: BulkAlgorithm::Aes256Ccm8,
+        kx: KeyExchangeAlgorithm::ECDHE,
+        sign: TLS12_ECDSA_SCHEMES,
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        aead_alg: &cipher::AesCcm,
+        hash_algorithm: HashAlgorithm::SHA256,
+    });
+
+/// The TLS1.2 ciphersuite TLS_DHE_RSA_WITH_AES_128_GCM_SHA256, using the
+/// RFC7919 groups.  It is not in [`DEFAULT_CIPHERSUITES`].
+///
+/// This needs the `ffdhe` crate feature.
+#[cfg(feature = "ffdhe")]
+pub static TLS_DHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite =
+    SupportedCipherSuite::Tls12(&Tls12CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
+        },
+        bulk: BulkAlgorithm::Aes128Gcm,
+        kx: KeyExchangeAlgorithm::DHE,
+        sign: TLS12_RSA_SCHEMES,
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        aead_alg: &cipher::AesGcm,
+        hash_algorithm: HashAlgorithm::SHA256,
+    });
+
+/// The TLS1.2 ciphersuite TLS_DHE_RSA_WITH_AES_256_GCM_SHA384, using the
+/// RFC7919 groups.  It is not in [`DEFAULT_CIPHERSUITES`].
+///
+/// This needs the `ffdhe` crate feature.
+#[cfg(feature = "ffdhe")]
+pub static TLS_DHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite =
+    SupportedCipherSuite::Tls12(&Tls12CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+        },
+        bulk: BulkAlgorithm::Aes256Gcm,
+        kx: KeyExchangeAlgorithm::DHE,
+        sign: TLS12_RSA_SCHEMES,
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        aead_alg: &cipher::AesGcm,
+        hash_algorithm: HashAlgorithm::SHA384,
+    });
+
+/// A list of all the cipher suites defined by rustls.  A crypto
+/// provider need not support them all: see
+/// [`SupportedCipherSuite::supported_by`].
 pub static ALL_CIPHERSUITES: &[SupportedCipherSuite] = &[
     // TLS1.3 suites
     TLS13_AES_256_GCM_SHA384,
@@ -365,13 +781,39 @@ pub static ALL_CIPHERSUITES: &[SupportedCipherSuite] = &[
     TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
     TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
     TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    // AES-CCM suites, for constrained peers
+    TLS13_AES_128_CCM_SHA256,
+    TLS13_AES_128_CCM_8_SHA256,
+    TLS_ECDHE_ECDSA_WITH_AES_128_CCM,
+    TLS_ECDHE_ECDSA_WITH_AES_256_CCM,
+    TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8,
+    TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8,
+    // finite-field DHE suites, for legacy peers
+    #[cfg(feature = "ffdhe")]
+    TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+    #[cfg(feature = "ffdhe")]
+    TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
 ];
 
 /// The cipher suite configuration that an application should use by default.
 ///
 /// This will be `ALL_CIPHERSUITES` sans any supported cipher suites that
-/// shouldn't be enabled by most applications.
-pub static DEFAULT_CIPHERSUITES: &[SupportedCipherSuite] = ALL_CIPHERSUITES;
+/// shouldn't be enabled by most applications.  The AES-CCM and DHE suites
+/// are left out: they are only worth using with peers that lack anything
+/// better.
+pub static DEFAULT_CIPHERSUITES: &[SupportedCipherSuite] = &[
+    // TLS1.3 suites
+    TLS13_AES_256_GCM_SHA384,
+    TLS13_AES_128_GCM_SHA256,
+    TLS13_CHACHA20_POLY1305_SHA256,
+    // TLS1.2 suites
+    TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+    TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+];
 
 // These both O(N^2)!
 pub(crate) fn choose_ciphersuite_preferring_client(
@@ -1,8 +1,11 @@
+use crate::crypto::ring::RING;
+use crate::crypto::{AeadKey, CryptoProvider, NONCE_LEN};
 use crate::rand;
 use crate::server::ProducesTickets;
+use crate::suites::BulkAlgorithm;
 use crate::Error;
 
-use ring::aead;
+use std::convert::TryFrom;
 use std::mem;
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::time;
@@ -28,28 +31,31 @@ impl TimeBase {
     }
 }
 
-/// This is a `ProducesTickets` implementation which uses
-/// any *ring* `aead::Algorithm` to encrypt and authentication
-/// the ticket payload.  It does not enforce any lifetime
-/// constraint.
+/// This is a `ProducesTickets` implementation which uses an AEAD
+/// from a `CryptoProvider` to encrypt and authenticate the ticket
+/// payload.  It does not enforce any lifetime constraint.
 struct AeadTicketer {
-    alg: &'static aead::Algorithm,
-    key: aead::LessSafeKey,
+    provider: &'static dyn CryptoProvider,
+    key: Box<dyn AeadKey>,
     lifetime: u32,
 }
 
 impl AeadTicketer {
     /// Make a ticketer with recommended configuration and a random key.
-    fn new() -> Result<Self, rand::GetRandomFailed> {
-        let mut key = [0u8; 32];
-        rand::fill_random(&mut key)?;
+    ///
+    /// This uses ChaCha20-Poly1305, or AES-256-GCM if `provider` lacks it.
+    fn new(provider: &'static dyn CryptoProvider) -> Result<Self, Error> {
+        let alg = match provider.aead_supported(&BulkAlgorithm::Chacha20Poly1305) {
+            true => BulkAlgorithm::Chacha20Poly1305,
+            false => BulkAlgorithm::Aes256Gcm,
+        };
 
-        let alg = &aead::CHACHA20_POLY1305;
-        let key = aead::UnboundKey::new(alg, &key).unwrap();
+        let mut key = vec![0u8; alg.key_len()];
+        rand::fill_random(provider, &mut key)?;
 
         Ok(Self {
-            alg,
-            key: aead::LessSafeKey::new(key),
+            provider,
+            key: provider.aead_key(&alg, &key)?,
             lifetime: 60 * 60 * 12,
         })
     }
@@ -66,46 +72,43 @@ impl ProducesTickets for AeadTicketer {
     /// Encrypt `message` and return the ciphertext.
     fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
         // Random nonce, because a counter is a privacy leak.
-        let mut nonce_buf = [0u8; 12];
-        rand::fill_random(&mut nonce_buf).ok()?;
-        let nonce = ring::aead::Nonce::assume_unique_for_key(nonce_buf);
-        let aad = ring::aead::Aad::empty();
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::fill_random(self.provider, &mut nonce).ok()?;
 
         let mut ciphertext =
-            Vec::with_capacity(nonce_buf.len() + message.len() + self.key.algorithm().tag_len());
-        ciphertext.extend(&nonce_buf);
+            Vec::with_capacity(nonce.len() + message.len() + self.key.tag_len());
+        ciphertext.extend(&nonce);
         ciphertext.extend(message);
         self.key
-            .seal_in_place_separate_tag(nonce, aad, &mut ciphertext[nonce_buf.len()..])
-            .map(|tag| {
-                ciphertext.extend(tag.as_ref());
-                ciphertext
-            })
-            .ok()
+            .seal_in_place(nonce, &[], &mut ciphertext, NONCE_LEN)
+            .ok()?;
+        Some(ciphertext)
     }
 
     /// Decrypt `ciphertext` and recover the original message.
     fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
         // Non-panicking `let (nonce, ciphertext) = ciphertext.split_at(...)`.
-        let nonce = ciphertext.get(..self.alg.nonce_len())?;
+        let nonce = ciphertext.get(..NONCE_LEN)?;
         let ciphertext = ciphertext.get(nonce.len()..)?;
 
         // This won't fail since `nonce` has the required length.
-        let nonce = ring::aead::Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let nonce = <[u8; NONCE_LEN]>::try_from(nonce).ok()?;
 
         let mut out = Vec::from(ciphertext);
 
         let plain_len = self
             .key
-            .open_in_place(nonce, aead::Aad::empty(), &mut out)
-            .ok()?
-            .len();
+            .open_in_place(nonce, &[], &mut out, 0)
+            .ok()?;
         out.truncate(plain_len);
 
         Some(out)
     }
 }
 
+/// Makes a fresh `ProducesTickets` using a `CryptoProvider`.
+type Generator = fn(&'static dyn CryptoProvider) -> Result<Box<dyn ProducesTickets>, Error>;
+
 struct TicketSwitcherState {
     current: Box<dyn ProducesTickets>,
     previous: Option<Box<dyn ProducesTickets>>,
@@ -116,7 +119,8 @@ struct TicketSwitcherState {
 /// 'previous' ticketer.  It creates a new ticketer every so
 /// often, demoting the current ticketer.
 struct TicketSwitcher {
-    generator: fn() -> Result<Box<dyn ProducesTickets>, rand::GetRandomFailed>,
+    provider: &'static dyn CryptoProvider,
+    generator: Generator,
     lifetime: u32,
     state: Mutex<TicketSwitcherState>,
 }
@@ -125,17 +129,19 @@ impl TicketSwitcher {
     /// `lifetime` is in seconds, and is how long the current ticketer
     /// is used to generate new tickets.  Tickets are accepted for no
     /// longer than twice this duration.  `generator` produces a new
-    /// `ProducesTickets` implementation.
+    /// `ProducesTickets` implementation using `provider`.
     fn new(
+        provider: &'static dyn CryptoProvider,
         lifetime: u32,
-        generator: fn() -> Result<Box<dyn ProducesTickets>, rand::GetRandomFailed>,
+        generator: Generator,
     ) -> Result<Self, Error> {
         let now = TimeBase::now()?;
         Ok(Self {
+            provider,
             generator,
             lifetime,
             state: Mutex::new(TicketSwitcherState {
-                current: generator()?,
+                current: generator(provider)?,
                 previous: None,
                 next_switch_time: now.as_secs() + u64::from(lifetime),
             }),
@@ -152,10 +158,13 @@ impl TicketSwitcher {
         &self,
         now: TimeBase,
         state: &mut MutexGuard<TicketSwitcherState>,
-    ) -> Result<(), rand::GetRandomFailed> {
+    ) -> Result<(), Error> {
         let now = now.as_secs();
         if now > state.next_switch_time {
-            state.previous = Some(mem::replace(&mut state.current, (self.generator)()?));
+            state.previous = Some(mem::replace(
+                &mut state.current,
+                (self.generator)(self.provider)?,
+            ));
             state.next_switch_time = now + u64::from(self.lifetime);
         }
         Ok(())
@@ -201,17 +210,32 @@ impl ProducesTickets for TicketSwitcher {
 /// A concrete, safe ticket creation mechanism.
 pub struct Ticketer {}
 
-fn generate_inner() -> Result<Box<dyn ProducesTickets>, rand::GetRandomFailed> {
-    Ok(Box::new(AeadTicketer::new()?))
+fn generate_inner(
+    provider: &'static dyn CryptoProvider,
+) -> Result<Box<dyn ProducesTickets>, Error> {
+    Ok(Box::new(AeadTicketer::new(provider)?))
 }
 
 impl Ticketer {
     /// Make the recommended Ticketer.  This produces tickets
     /// with a 12 hour life and randomly generated keys.
     ///
-    /// The encryption mechanism used in Chacha20Poly1305.
+    /// The encryption mechanism used in Chacha20Poly1305, from the
+    /// default crypto provider.
     pub fn new() -> Result<Arc<dyn ProducesTickets>, Error> {
-        Ok(Arc::new(TicketSwitcher::new(6 * 60 * 60, generate_inner)?))
+        Self::new_with_provider(&RING)
+    }
+
+    /// Like [`Ticketer::new`], but the keys and encryption come from
+    /// `provider`.  If it lacks Chacha20Poly1305, AES-256-GCM is used.
+    pub fn new_with_provider(
+        provider: &'static dyn CryptoProvider,
+    ) -> Result<Arc<dyn ProducesTickets>, Error> {
+        Ok(Arc::new(TicketSwitcher::new(
+            provider,
+            6 * 60 * 60,
+            generate_inner,
+        )?))
     }
 }
 
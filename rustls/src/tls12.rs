@@ -1,20 +1,22 @@
 use crate::conn::ConnectionCommon;
-use crate::kx;
+use crate::crypto::{ActiveKeyExchange, KeyExchangeResult};
+use crate::kx::SupportedKxGroup;
 use crate::msgs::codec::{Codec, Reader};
-use crate::msgs::enums::{AlertDescription, ContentType};
+use crate::msgs::enums::{AlertDescription, ContentType, NamedGroup};
+use crate::msgs::handshake::KeyExchangeAlgorithm;
 use crate::Error;
 
-pub(crate) fn decode_ecdh_params<T: Codec>(
+pub(crate) fn decode_kx_params<T: Codec>(
     conn: &mut ConnectionCommon,
     kx_params: &[u8],
 ) -> Result<T, Error> {
-    decode_ecdh_params_::<T>(kx_params).ok_or_else(|| {
+    decode_kx_params_::<T>(kx_params).ok_or_else(|| {
         conn.send_fatal_alert(AlertDescription::DecodeError);
         Error::CorruptMessagePayload(ContentType::Handshake)
     })
 }
 
-fn decode_ecdh_params_<T: Codec>(kx_params: &[u8]) -> Option<T> {
+fn decode_kx_params_<T: Codec>(kx_params: &[u8]) -> Option<T> {
     let mut rd = Reader::init(kx_params);
     let ecdh_params = T::read(&mut rd)?;
     match rd.any_left() {
@@ -24,30 +26,117 @@ fn decode_ecdh_params_<T: Codec>(kx_params: &[u8]) -> Option<T> {
 }
 
 pub(crate) fn complete_ecdh(
-    mine: kx::KeyExchange,
+    mine: Box<dyn ActiveKeyExchange>,
     peer_pub_key: &[u8],
-) -> Result<kx::KeyExchangeResult, Error> {
-    mine.complete(peer_pub_key)
-        .ok_or_else(|| Error::PeerMisbehavedError("key agreement failed".to_string()))
+) -> Result<KeyExchangeResult, Error> {
+    let pubkey = mine.pub_key().to_vec();
+    let shared_secret = mine
+        .complete(peer_pub_key)
+        .ok_or_else(|| Error::PeerMisbehavedError("key agreement failed".to_string()))?;
+    Ok(KeyExchangeResult {
+        pubkey,
+        shared_secret,
+    })
+}
+
+/// Like `complete_ecdh`, but for finite-field Diffie-Hellman, where the
+/// premaster secret has its leading zero bytes stripped (RFC5246
+/// section 8.1.2).
+pub(crate) fn complete_dhe(
+    mine: Box<dyn ActiveKeyExchange>,
+    peer_pub_key: &[u8],
+) -> Result<KeyExchangeResult, Error> {
+    let mut kxd = complete_ecdh(mine, peer_pub_key)?;
+    let zeros = kxd
+        .shared_secret
+        .iter()
+        .take_while(|b| **b == 0)
+        .count();
+    kxd.shared_secret.drain(..zeros);
+    Ok(kxd)
+}
+
+/// Choose the group for the TLS1.2 key exchange `kxa`, from `ours` in
+/// order of preference, given the groups the client described.
+///
+/// Clients which predate RFC7919 offer DHE without naming any
+/// finite-field groups.  We give those our most preferred one.
+pub(crate) fn choose_kx_group(
+    ours: &[&'static SupportedKxGroup],
+    kxa: &KeyExchangeAlgorithm,
+    theirs: &[NamedGroup],
+) -> Option<&'static SupportedKxGroup> {
+    let mut usable = ours
+        .iter()
+        .filter(|skxg| skxg.usable_for_tls12(kxa));
+    let names_ffdhe = theirs.iter().any(|name| {
+        matches!(
+            name,
+            NamedGroup::FFDHE2048 | NamedGroup::FFDHE3072 | NamedGroup::FFDHE4096
+        )
+    });
+
+    match *kxa == KeyExchangeAlgorithm::DHE && !names_ffdhe {
+        true => usable.next(),
+        false => usable.find(|skxg| theirs.contains(&skxg.name)),
+    }
+    .cloned()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::ring::RING;
+    use crate::crypto::CryptoProvider;
+    use crate::kx;
     use crate::msgs::handshake::{ClientECDHParams, ServerECDHParams};
 
     #[test]
     fn server_ecdhe_remaining_bytes() {
-        let key = kx::KeyExchange::start(&kx::X25519).unwrap();
-        let server_params = ServerECDHParams::new(key.group(), key.pubkey.as_ref());
+        let key = RING
+            .start_key_exchange(&kx::X25519)
+            .unwrap();
+        let server_params = ServerECDHParams::new(key.group(), key.pub_key());
         let mut server_buf = Vec::new();
         server_params.encode(&mut server_buf);
         server_buf.push(34);
-        assert!(decode_ecdh_params_::<ServerECDHParams>(&server_buf).is_none());
+        assert!(decode_kx_params_::<ServerECDHParams>(&server_buf).is_none());
     }
 
     #[test]
     fn client_ecdhe_invalid() {
-        assert!(decode_ecdh_params_::<ClientECDHParams>(&[34]).is_none());
+        assert!(decode_kx_params_::<ClientECDHParams>(&[34]).is_none());
+    }
+
+    #[cfg(feature = "ffdhe")]
+    #[test]
+    fn dhe_group_choice() {
+        let ours = [&kx::X25519, &kx::FFDHE3072, &kx::FFDHE2048];
+        let dhe = KeyExchangeAlgorithm::DHE;
+
+        // a client naming no finite-field groups gets our favourite
+        assert_eq!(
+            choose_kx_group(&ours, &dhe, &[]).map(|skxg| skxg.name),
+            Some(NamedGroup::FFDHE3072)
+        );
+        assert_eq!(
+            choose_kx_group(&ours, &dhe, &[NamedGroup::X25519]).map(|skxg| skxg.name),
+            Some(NamedGroup::FFDHE3072)
+        );
+
+        // otherwise we must use one it names
+        assert_eq!(
+            choose_kx_group(&ours, &dhe, &[NamedGroup::FFDHE2048]).map(|skxg| skxg.name),
+            Some(NamedGroup::FFDHE2048)
+        );
+        assert!(choose_kx_group(&ours, &dhe, &[NamedGroup::FFDHE4096]).is_none());
+
+        // and finite-field groups are never used for ECDHE
+        assert!(choose_kx_group(
+            &ours,
+            &KeyExchangeAlgorithm::ECDHE,
+            &[NamedGroup::FFDHE2048]
+        )
+        .is_none());
     }
 }
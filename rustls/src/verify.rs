@@ -1,19 +1,66 @@
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use crate::anchors::{OwnedTrustAnchor, RootCertStore};
 use crate::client::ServerName;
+use crate::crypto::ring::RING;
+use crate::crypto::{CryptoProvider, Digest};
 use crate::error::Error;
-use crate::error::WebPkiOp;
+use crate::error::{OcspError, WebPkiError, WebPkiOp};
 use crate::key::Certificate;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
-use crate::msgs::enums::SignatureScheme;
+use crate::msgs::enums::{CertificateType, SignatureScheme};
 use crate::msgs::handshake::{DigitallySignedStruct, DistinguishedNames};
-
-use ring::digest::Digest;
+use crate::ocsp;
+#[cfg(feature = "secp521r1")]
+use crate::x509::SECP521R1;
+use crate::x509::{bit_string_bytes, wrap_in_sequence, DerReader};
+#[cfg(feature = "curve448")]
+use crate::x509::ID_ED448;
+use crate::x509::{ID_EC_PUBLIC_KEY, ID_ED25519, RSA_ENCRYPTION};
+use crate::x509::{SECP256R1, SECP384R1};
+
+use ring::io::der;
+use ring::signature;
 use std::convert::TryFrom;
 
+/// A list of all the signature schemes defined by rustls, in preference
+/// order.  A crypto provider need not support them all: see
+/// [`CryptoProvider::signature_schemes`].
+pub static ALL_SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    #[cfg(feature = "secp521r1")]
+    SignatureScheme::ECDSA_NISTP521_SHA512,
+    SignatureScheme::ED25519,
+    #[cfg(feature = "curve448")]
+    SignatureScheme::ED448,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+/// Check the peer signed `dss` with one of the `enabled` schemes.
+///
+/// This is done before asking a verifier to check the signature, so
+/// the configured policy applies to custom verifiers too.
+pub(crate) fn check_signature_scheme(
+    enabled: &[SignatureScheme],
+    dss: &DigitallySignedStruct,
+) -> Result<(), Error> {
+    match enabled.contains(&dss.scheme) {
+        true => Ok(()),
+        false => Err(Error::PeerMisbehavedError(format!(
+            "peer signed with disabled scheme {:?}",
+            dss.scheme
+        ))),
+    }
+}
+
 type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
 
 /// Which signature verification mechanisms we support.  No particular
@@ -120,15 +167,16 @@ pub trait ServerCertVerifier: Send + Sync {
     /// SignatureSchemes such as `SignatureScheme::ECDSA_NISTP256_SHA256` are not
     /// in fact bound to the specific curve implied in their name.
     ///
-    /// This trait method has a default implementation that uses webpki to verify
-    /// the signature.
+    /// This trait method has a default implementation that verifies the
+    /// signature with `provider`, the crypto provider of the connection.
     fn verify_tls12_signature(
         &self,
+        provider: &dyn CryptoProvider,
         message: &[u8],
         cert: &Certificate,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_signed_struct(message, cert, dss)
+        verify_signed_struct(provider, message, cert, dss)
     }
 
     /// Verify a signature allegedly by the given server certificate.
@@ -140,15 +188,16 @@ pub trait ServerCertVerifier: Send + Sync {
     /// must only validate signatures using public keys on the right curve --
     /// rustls does not enforce this requirement for you.
     ///
-    /// This trait method has a default implementation that uses webpki to verify
-    /// the signature.
+    /// This trait method has a default implementation that verifies the
+    /// signature with `provider`, the crypto provider of the connection.
     fn verify_tls13_signature(
         &self,
+        provider: &dyn CryptoProvider,
         message: &[u8],
         cert: &Certificate,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_tls13(message, cert, dss)
+        verify_tls13(provider, message, cert, dss)
     }
 
     /// Return the list of SignatureSchemes that this verifier will handle,
@@ -171,6 +220,34 @@ pub trait ServerCertVerifier: Send + Sync {
     fn request_scts(&self) -> bool {
         true
     }
+
+    /// Return the types of certificate (RFC7250) this verifier accepts
+    /// from servers, most preferred first.
+    ///
+    /// Include `CertificateType::RawPublicKey` to ask TLS1.3 servers for
+    /// a raw public key, which is checked by `verify_raw_public_key`.
+    ///
+    /// The default implementation accepts only X.509 certificate chains.
+    fn certificate_types(&self) -> Vec<CertificateType> {
+        vec![CertificateType::X509]
+    }
+
+    /// Verify the server's raw public key `spki`, a DER-encoded
+    /// SubjectPublicKeyInfo, is trusted for `server_name`.
+    ///
+    /// This is only called if the server chose to send a raw public key
+    /// from those allowed by `certificate_types`.  rustls checks the
+    /// server's handshake signature against `spki` itself.
+    ///
+    /// The default implementation rejects all raw public keys.
+    fn verify_raw_public_key(
+        &self,
+        _spki: &[u8],
+        _server_name: &ServerName,
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Err(Error::UntrustedRawPublicKey)
+    }
 }
 
 /// A type which encapsuates a string that is a syntactically valid DNS name.
@@ -245,15 +322,16 @@ pub trait ClientCertVerifier: Send + Sync {
     /// SignatureSchemes such as `SignatureScheme::ECDSA_NISTP256_SHA256` are not
     /// in fact bound to the specific curve implied in their name.
     ///
-    /// This trait method has a default implementation that uses webpki to verify
-    /// the signature.
+    /// This trait method has a default implementation that verifies the
+    /// signature with `provider`, the crypto provider of the connection.
     fn verify_tls12_signature(
         &self,
+        provider: &dyn CryptoProvider,
         message: &[u8],
         cert: &Certificate,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_signed_struct(message, cert, dss)
+        verify_signed_struct(provider, message, cert, dss)
     }
 
 
@@ -266,15 +344,16 @@ pub trait ClientCertVerifier: Send + Sync {
     /// must only validate signatures using public keys on the right curve --
     /// rustls does not enforce this requirement for you.
     ///
-    /// This trait method has a default implementation that uses webpki to verify
-    /// the signature.
+    /// This trait method has a default implementation that verifies the
+    /// signature with `provider`, the crypto provider of the connection.
     fn verify_tls13_signature(
         &self,
+        provider: &dyn CryptoProvider,
         message: &[u8],
         cert: &Certificate,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_tls13(message, cert, dss)
+        verify_tls13(provider, message, cert, dss)
     }
 
     /// Return the list of SignatureSchemes that this verifier will handle,
@@ -287,6 +366,36 @@ pub trait ClientCertVerifier: Send + Sync {
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
         WebPkiVerifier::verification_schemes()
     }
+
+    /// Return the types of certificate (RFC7250) this verifier accepts
+    /// from clients, most preferred first.
+    ///
+    /// TLS1.3 clients which offer a raw public key send one if this
+    /// includes `CertificateType::RawPublicKey`; it is checked by
+    /// `verify_raw_public_key`.
+    ///
+    /// The default implementation accepts only X.509 certificate chains.
+    fn certificate_types(&self) -> Vec<CertificateType> {
+        vec![CertificateType::X509]
+    }
+
+    /// Verify the client's raw public key `spki`, a DER-encoded
+    /// SubjectPublicKeyInfo, is trusted.
+    ///
+    /// `sni` is the server name quoted by the client in its ClientHello; it has
+    /// been validated as a proper DNS name but is otherwise untrusted.
+    ///
+    /// rustls checks the client's handshake signature against `spki` itself.
+    ///
+    /// The default implementation rejects all raw public keys.
+    fn verify_raw_public_key(
+        &self,
+        _spki: &[u8],
+        _sni: Option<&DnsName>,
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        Err(Error::UntrustedRawPublicKey)
+    }
 }
 
 impl ServerCertVerifier for WebPkiVerifier {
@@ -294,7 +403,8 @@ impl ServerCertVerifier for WebPkiVerifier {
     /// - Signed by a  trusted `RootCertStore` CA
     /// - Not Expired
     /// - Valid for DNS entry
-    /// - OCSP data is present
+    /// - Not revoked, according to the stapled OCSP response and the
+    ///   verifier's `RevocationPolicy`
     fn verify_server_cert(
         &self,
         end_entity: &Certificate,
@@ -320,10 +430,7 @@ impl ServerCertVerifier for WebPkiVerifier {
             .map(|_| cert)?;
 
         verify_scts(end_entity, now, scts, self.ct_logs)?;
-
-        if !ocsp_response.is_empty() {
-            trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
-        }
+        self.check_revocation(end_entity, intermediates, ocsp_response, now)?;
 
         cert.verify_is_valid_for_dns_name(dns_name.0.as_ref())
             .map_err(|e| Error::WebPkiError(e.into(), WebPkiOp::ValidateForDnsName))
@@ -331,13 +438,45 @@ impl ServerCertVerifier for WebPkiVerifier {
     }
 }
 
+/// How `WebPkiVerifier` treats the revocation status of server
+/// certificates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevocationPolicy {
+    /// Don't check revocation.  Stapled OCSP responses are ignored.
+    Ignore,
+
+    /// Validate the OCSP response stapled by the server, if any, and
+    /// reject the certificate if it is revoked or its status is unknown.
+    ///
+    /// A response is required if the certificate asks for one with the
+    /// TLS Feature ("must-staple") extension.
+    CheckStapled,
+
+    /// Like `CheckStapled`, but reject every certificate that comes
+    /// without a stapled OCSP response.
+    RequireStapled,
+}
+
+impl Default for RevocationPolicy {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
 /// Default `ServerCertVerifier`, see the trait impl for more information.
 #[allow(unreachable_pub)]
 pub struct WebPkiVerifier {
     roots: RootCertStore,
     ct_logs: &'static [&'static sct::Log<'static>],
+    revocation_policy: RevocationPolicy,
+    ocsp_max_age: Duration,
+    provider: &'static dyn CryptoProvider,
 }
 
+/// How long an OCSP response without a nextUpdate time is trusted
+/// by default.
+pub const DEFAULT_OCSP_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
 #[allow(unreachable_pub)]
 impl WebPkiVerifier {
     /// Constructs a new `WebPkiVerifier`.
@@ -347,24 +486,86 @@ impl WebPkiVerifier {
     /// `ct_logs` is the list of logs that are trusted for Certificate
     /// Transparency. Currently CT log enforcement is opportunistic; see
     /// <https://github.com/ctz/rustls/issues/479>.
+    ///
+    /// Revocation is not checked; see `with_revocation_policy`.
     pub fn new(roots: RootCertStore, ct_logs: &'static [&'static sct::Log<'static>]) -> Self {
-        Self { roots, ct_logs }
+        Self {
+            roots,
+            ct_logs,
+            revocation_policy: RevocationPolicy::default(),
+            ocsp_max_age: DEFAULT_OCSP_MAX_AGE,
+            provider: &RING,
+        }
+    }
+
+    /// Check the revocation status of server certificates according
+    /// to `policy`.
+    pub fn with_revocation_policy(mut self, policy: RevocationPolicy) -> Self {
+        self.revocation_policy = policy;
+        self
+    }
+
+    /// Accept OCSP responses which don't say when newer information
+    /// will be available for at most `max_age` after they were produced.
+    ///
+    /// The default is `DEFAULT_OCSP_MAX_AGE`.
+    pub fn with_ocsp_max_age(mut self, max_age: Duration) -> Self {
+        self.ocsp_max_age = max_age;
+        self
+    }
+
+    /// Verify the signatures of OCSP responses with `provider`.
+    ///
+    /// The default is the default crypto provider.  Certificate chains
+    /// are always validated by webpki, whatever the provider.
+    pub fn with_crypto_provider(mut self, provider: &'static dyn CryptoProvider) -> Self {
+        self.provider = provider;
+        self
     }
 
     /// Returns the signature verification methods supported by
     /// webpki.
     pub fn verification_schemes() -> Vec<SignatureScheme> {
-        vec![
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ED25519,
-            SignatureScheme::RSA_PSS_SHA512,
-            SignatureScheme::RSA_PSS_SHA384,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA256,
-        ]
+        ALL_SIGNATURE_SCHEMES.to_vec()
+    }
+
+    fn check_revocation(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<(), Error> {
+        if self.revocation_policy == RevocationPolicy::Ignore {
+            if !ocsp_response.is_empty() {
+                trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+            }
+            return Ok(());
+        }
+
+        if ocsp_response.is_empty() {
+            let required = self.revocation_policy == RevocationPolicy::RequireStapled
+                || ocsp::requires_stapled_response(end_entity).map_err(Error::OcspError)?;
+            return match required {
+                true => Err(Error::OcspError(OcspError::Missing)),
+                false => Ok(()),
+            };
+        }
+
+        let now = now
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|_| Error::FailedToGetCurrentTime)?
+            .as_secs();
+        ocsp::verify_response(
+            self.provider,
+            ocsp_response,
+            end_entity,
+            intermediates,
+            &self.roots,
+            now,
+            self.ocsp_max_age.as_secs(),
+        )
+        .map_err(Error::OcspError)
     }
 }
 
@@ -445,6 +646,107 @@ impl ClientCertVerifier for AllowAnyAuthenticatedClient {
     }
 }
 
+/// A verifier which trusts a fixed set of raw public keys (RFC7250),
+/// rather than certificate chains.
+///
+/// This can verify servers, or clients: in that case client authentication
+/// is mandatory.  Only TLS1.3 peers which support raw public keys can be
+/// verified.
+pub struct PinnedPublicKeys {
+    spkis: Vec<Vec<u8>>,
+}
+
+impl PinnedPublicKeys {
+    /// Construct a new `PinnedPublicKeys`, trusting the keys whose
+    /// DER-encoded SubjectPublicKeyInfos are `spkis`.
+    pub fn new(spkis: Vec<Vec<u8>>) -> Arc<Self> {
+        Arc::new(Self { spkis })
+    }
+
+    fn check(&self, spki: &[u8]) -> Result<(), Error> {
+        if self
+            .spkis
+            .iter()
+            .any(|pinned| pinned.as_slice() == spki)
+        {
+            Ok(())
+        } else {
+            warn!("Peer's raw public key is not pinned");
+            Err(Error::UntrustedRawPublicKey)
+        }
+    }
+
+    fn no_certificates() -> Error {
+        Error::PeerIncompatibleError("peer sent a certificate, not a raw public key".to_string())
+    }
+}
+
+impl ServerCertVerifier for PinnedPublicKeys {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Err(Self::no_certificates())
+    }
+
+    fn request_scts(&self) -> bool {
+        false
+    }
+
+    fn certificate_types(&self) -> Vec<CertificateType> {
+        vec![CertificateType::RawPublicKey]
+    }
+
+    fn verify_raw_public_key(
+        &self,
+        spki: &[u8],
+        _server_name: &ServerName,
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.check(spki)
+            .map(|()| ServerCertVerified::assertion())
+    }
+}
+
+impl ClientCertVerifier for PinnedPublicKeys {
+    fn client_auth_mandatory(&self, _sni: Option<&DnsName>) -> Option<bool> {
+        Some(true)
+    }
+
+    fn client_auth_root_subjects(&self, _sni: Option<&DnsName>) -> Option<DistinguishedNames> {
+        Some(DistinguishedNames::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _sni: Option<&DnsName>,
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        Err(Self::no_certificates())
+    }
+
+    fn certificate_types(&self) -> Vec<CertificateType> {
+        vec![CertificateType::RawPublicKey]
+    }
+
+    fn verify_raw_public_key(
+        &self,
+        spki: &[u8],
+        _sni: Option<&DnsName>,
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        self.check(spki)
+            .map(|()| ClientCertVerified::assertion())
+    }
+}
+
 /// A `ClientCertVerifier` that will allow both anonymous and authenticated
 /// clients, without any name checking.
 ///
@@ -522,95 +824,17 @@ impl ClientCertVerifier for NoClientAuth {
     }
 }
 
-static ECDSA_SHA256: SignatureAlgorithms =
-    &[&webpki::ECDSA_P256_SHA256, &webpki::ECDSA_P384_SHA256];
-
-static ECDSA_SHA384: SignatureAlgorithms =
-    &[&webpki::ECDSA_P256_SHA384, &webpki::ECDSA_P384_SHA384];
-
-static ED25519: SignatureAlgorithms = &[&webpki::ED25519];
-
-static RSA_SHA256: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA256];
-static RSA_SHA384: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA384];
-static RSA_SHA512: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA512];
-static RSA_PSS_SHA256: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY];
-static RSA_PSS_SHA384: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY];
-static RSA_PSS_SHA512: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY];
-
-fn convert_scheme(scheme: SignatureScheme) -> Result<SignatureAlgorithms, Error> {
-    match scheme {
-        // nb. for TLS1.2 the curve is not fixed by SignatureScheme.
-        SignatureScheme::ECDSA_NISTP256_SHA256 => Ok(ECDSA_SHA256),
-        SignatureScheme::ECDSA_NISTP384_SHA384 => Ok(ECDSA_SHA384),
-
-        SignatureScheme::ED25519 => Ok(ED25519),
-
-        SignatureScheme::RSA_PKCS1_SHA256 => Ok(RSA_SHA256),
-        SignatureScheme::RSA_PKCS1_SHA384 => Ok(RSA_SHA384),
-        SignatureScheme::RSA_PKCS1_SHA512 => Ok(RSA_SHA512),
-
-        SignatureScheme::RSA_PSS_SHA256 => Ok(RSA_PSS_SHA256),
-        SignatureScheme::RSA_PSS_SHA384 => Ok(RSA_PSS_SHA384),
-        SignatureScheme::RSA_PSS_SHA512 => Ok(RSA_PSS_SHA512),
-
-        _ => {
-            let error_msg = format!("received unadvertised sig scheme {:?}", scheme);
-            Err(Error::PeerMisbehavedError(error_msg))
-        }
-    }
-}
-
-fn verify_sig_using_any_alg(
-    cert: &webpki::EndEntityCert,
-    algs: SignatureAlgorithms,
-    message: &[u8],
-    sig: &[u8],
-) -> Result<(), webpki::Error> {
-    // TLS doesn't itself give us enough info to map to a single webpki::SignatureAlgorithm.
-    // Therefore, convert_algs maps to several and we try them all.
-    for alg in algs {
-        match cert.verify_signature(alg, message, sig) {
-            Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey) => continue,
-            res => return res,
-        }
-    }
-
-    Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
-}
-
 fn verify_signed_struct(
+    provider: &dyn CryptoProvider,
     message: &[u8],
     cert: &Certificate,
     dss: &DigitallySignedStruct,
 ) -> Result<HandshakeSignatureValid, Error> {
-    let possible_algs = convert_scheme(dss.scheme)?;
-    let cert = webpki::EndEntityCert::try_from(cert.0.as_ref())
-        .map_err(|e| Error::WebPkiError(e.into(), WebPkiOp::ParseEndEntity))?;
-
-    verify_sig_using_any_alg(&cert, possible_algs, message, &dss.sig.0)
-        .map_err(|e| Error::WebPkiError(e.into(), WebPkiOp::VerifySignature))
+    provider
+        .verify_tls12_signature(dss.scheme, &end_entity_spki(cert)?, message, &dss.sig.0)
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
-fn convert_alg_tls13(
-    scheme: SignatureScheme,
-) -> Result<&'static webpki::SignatureAlgorithm, Error> {
-    use crate::msgs::enums::SignatureScheme::*;
-
-    match scheme {
-        ECDSA_NISTP256_SHA256 => Ok(&webpki::ECDSA_P256_SHA256),
-        ECDSA_NISTP384_SHA384 => Ok(&webpki::ECDSA_P384_SHA384),
-        ED25519 => Ok(&webpki::ED25519),
-        RSA_PSS_SHA256 => Ok(&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
-        RSA_PSS_SHA384 => Ok(&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY),
-        RSA_PSS_SHA512 => Ok(&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY),
-        _ => {
-            let error_msg = format!("received unsupported sig scheme {:?}", scheme);
-            Err(Error::PeerMisbehavedError(error_msg))
-        }
-    }
-}
-
 /// Constructs the signature message specified in section 4.4.3 of RFC8446.
 pub(crate) fn construct_tls13_client_verify_message(handshake_hash: &Digest) -> Vec<u8> {
     construct_tls13_verify_message(handshake_hash, b"TLS 1.3, client CertificateVerify\x00")
@@ -633,21 +857,179 @@ fn construct_tls13_verify_message(
 }
 
 fn verify_tls13(
+    provider: &dyn CryptoProvider,
     msg: &[u8],
     cert: &Certificate,
     dss: &DigitallySignedStruct,
 ) -> Result<HandshakeSignatureValid, Error> {
-    let alg = convert_alg_tls13(dss.scheme)?;
+    provider
+        .verify_signature(dss.scheme, &end_entity_spki(cert)?, msg, &dss.sig.0)
+        .map(|_| HandshakeSignatureValid::assertion())
+}
 
+/// The DER-encoded SubjectPublicKeyInfo of the certificate `cert`.
+fn end_entity_spki(cert: &Certificate) -> Result<Vec<u8>, Error> {
+    let cert = ocsp::Cert::parse_der(&cert.0).ok_or(Error::WebPkiError(
+        WebPkiError::BadEncoding,
+        WebPkiOp::ParseEndEntity,
+    ))?;
 
-    let cert = webpki::EndEntityCert::try_from(cert.0.as_ref())
-        .map_err(|e| Error::WebPkiError(e.into(), WebPkiOp::ParseEndEntity))?;
+    let mut spki = cert.spki.to_vec();
+    wrap_in_sequence(&mut spki);
+    Ok(spki)
+}
 
-    cert.verify_signature(alg, msg, &dss.sig.0)
-        .map_err(|e| Error::WebPkiError(e.into(), WebPkiOp::VerifySignature))
+/// Verify a TLS1.3 signature made by the key in `spki`, a DER-encoded
+/// SubjectPublicKeyInfo, rather than by a certificate.  This is done
+/// by the crypto `provider`.
+///
+/// Like `verify_tls13`, this requires ECDSA keys to be on the curve
+/// named by the signature scheme.
+pub(crate) fn verify_tls13_spki(
+    provider: &dyn CryptoProvider,
+    msg: &[u8],
+    spki: &[u8],
+    dss: &DigitallySignedStruct,
+) -> Result<HandshakeSignatureValid, Error> {
+    provider
+        .verify_signature(dss.scheme, spki, msg, &dss.sig.0)
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
+/// The default crypto provider's `verify_signature`, or with `tls12`
+/// its `verify_tls12_signature`.
+pub(crate) fn verify_spki_signature(
+    scheme: SignatureScheme,
+    spki: &[u8],
+    message: &[u8],
+    signature: &[u8],
+    tls12: bool,
+) -> Result<(), Error> {
+    let bad_encoding = || Error::WebPkiError(WebPkiError::BadEncoding, WebPkiOp::VerifySignature);
+
+    let mut outer = DerReader::new(spki);
+    let mut spki = DerReader::new(
+        outer
+            .read(der::Tag::Sequence as u8)
+            .filter(|_| outer.is_empty())
+            .ok_or_else(bad_encoding)?,
+    );
+    let mut key_alg = DerReader::new(
+        spki.read(der::Tag::Sequence as u8)
+            .ok_or_else(bad_encoding)?,
+    );
+    let key = spki
+        .read(der::Tag::BitString as u8)
+        .and_then(bit_string_bytes)
+        .ok_or_else(bad_encoding)?;
+    if !spki.is_empty() {
+        return Err(bad_encoding());
+    }
+
+    let key_type = key_alg
+        .read(der::Tag::OID as u8)
+        .ok_or_else(bad_encoding)?;
+    let curve = key_alg
+        .read_optional(der::Tag::OID as u8)
+        .ok_or_else(bad_encoding)?;
+
+    // In TLS1.2 the ECDSA schemes only name the hash function.
+    let alg: &'static dyn signature::VerificationAlgorithm = match (scheme, key_type, curve) {
+        (SignatureScheme::ECDSA_NISTP256_SHA256, ID_EC_PUBLIC_KEY, Some(SECP256R1)) => {
+            &signature::ECDSA_P256_SHA256_ASN1
+        }
+        (SignatureScheme::ECDSA_NISTP256_SHA256, ID_EC_PUBLIC_KEY, Some(SECP384R1)) if tls12 => {
+            &signature::ECDSA_P384_SHA256_ASN1
+        }
+        (SignatureScheme::ECDSA_NISTP384_SHA384, ID_EC_PUBLIC_KEY, Some(SECP384R1)) => {
+            &signature::ECDSA_P384_SHA384_ASN1
+        }
+        (SignatureScheme::ECDSA_NISTP384_SHA384, ID_EC_PUBLIC_KEY, Some(SECP256R1)) if tls12 => {
+            &signature::ECDSA_P256_SHA384_ASN1
+        }
+        #[cfg(feature = "secp521r1")]
+        (SignatureScheme::ECDSA_NISTP521_SHA512, ID_EC_PUBLIC_KEY, Some(SECP521R1)) => {
+            return verify_p521(message, key, signature);
+        }
+        (SignatureScheme::ED25519, ID_ED25519, None) => &signature::ED25519,
+        #[cfg(feature = "curve448")]
+        (SignatureScheme::ED448, ID_ED448, None) => {
+            return verify_ed448(message, key, signature);
+        }
+        (SignatureScheme::RSA_PSS_SHA256, RSA_ENCRYPTION, _) => {
+            &signature::RSA_PSS_2048_8192_SHA256
+        }
+        (SignatureScheme::RSA_PSS_SHA384, RSA_ENCRYPTION, _) => {
+            &signature::RSA_PSS_2048_8192_SHA384
+        }
+        (SignatureScheme::RSA_PSS_SHA512, RSA_ENCRYPTION, _) => {
+            &signature::RSA_PSS_2048_8192_SHA512
+        }
+        (SignatureScheme::RSA_PKCS1_SHA256, RSA_ENCRYPTION, _) if tls12 => {
+            &signature::RSA_PKCS1_2048_8192_SHA256
+        }
+        (SignatureScheme::RSA_PKCS1_SHA384, RSA_ENCRYPTION, _) if tls12 => {
+            &signature::RSA_PKCS1_2048_8192_SHA384
+        }
+        (SignatureScheme::RSA_PKCS1_SHA512, RSA_ENCRYPTION, _) if tls12 => {
+            &signature::RSA_PKCS1_2048_8192_SHA512
+        }
+        _ => {
+            return Err(Error::WebPkiError(
+                WebPkiError::UnsupportedSignatureAlgorithmForPublicKey,
+                WebPkiOp::VerifySignature,
+            ));
+        }
+    };
+
+    signature::UnparsedPublicKey::new(alg, key)
+        .verify(message, signature)
+        .map_err(|_| {
+            Error::WebPkiError(
+                WebPkiError::InvalidSignatureForPublicKey,
+                WebPkiOp::VerifySignature,
+            )
+        })
+}
+
+/// Verify an ECDSA P-521 signature `sig` (using SHA-512) over `msg`,
+/// made by the SEC1-encoded public key `key`.
+#[cfg(feature = "secp521r1")]
+fn verify_p521(msg: &[u8], key: &[u8], sig: &[u8]) -> Result<(), Error> {
+    use p521::ecdsa::signature::Verifier;
+
+    let key = p521::ecdsa::VerifyingKey::from_sec1_bytes(key)
+        .map_err(|_| Error::WebPkiError(WebPkiError::BadEncoding, WebPkiOp::VerifySignature))?;
+
+    p521::ecdsa::Signature::from_der(sig)
+        .and_then(|sig| key.verify(msg, &sig))
+        .map_err(|_| {
+            Error::WebPkiError(
+                WebPkiError::InvalidSignatureForPublicKey,
+                WebPkiOp::VerifySignature,
+            )
+        })
+}
+
+/// Verify an Ed448 signature `sig` over `msg`, made by the raw public
+/// key `key`.
+#[cfg(feature = "curve448")]
+fn verify_ed448(msg: &[u8], key: &[u8], sig: &[u8]) -> Result<(), Error> {
+    use openssl::pkey::{Id, PKey};
+    use openssl::sign::Verifier;
+
+    let key = PKey::public_key_from_raw_bytes(key, Id::ED448)
+        .map_err(|_| Error::WebPkiError(WebPkiError::BadEncoding, WebPkiOp::VerifySignature))?;
+
+    match Verifier::new_without_digest(&key).and_then(|mut v| v.verify_oneshot(sig, msg)) {
+        Ok(true) => Ok(()),
+        _ => Err(Error::WebPkiError(
+            WebPkiError::InvalidSignatureForPublicKey,
+            WebPkiOp::VerifySignature,
+        )),
+    }
+}
+
 fn unix_time_millis(now: SystemTime) -> Result<u64, Error> {
     now.duration_since(std::time::UNIX_EPOCH)
         .map(|dur| dur.as_secs())
@@ -2,6 +2,18 @@
 
 use ring::io::der;
 
+// Object identifiers of public key types, as the contents of their
+// DER encoding.
+pub(crate) const RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+pub(crate) const ID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+pub(crate) const SECP256R1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+pub(crate) const SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+#[cfg(feature = "secp521r1")]
+pub(crate) const SECP521R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x23];
+pub(crate) const ID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+#[cfg(feature = "curve448")]
+pub(crate) const ID_ED448: &[u8] = &[0x2b, 0x65, 0x71];
+
 fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
     let len = bytes.len();
 
@@ -25,6 +37,144 @@ pub(crate) fn wrap_in_sequence(bytes: &mut Vec<u8>) {
     bytes.insert(0, der::Tag::Sequence as u8);
 }
 
+/// A cursor over DER-encoded values, for the structures that webpki
+/// parses internally but does not expose to us.
+///
+/// Every method returns `None` if the input is malformed.
+#[derive(Clone, Copy)]
+pub(crate) struct DerReader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { rest: bytes }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    /// Is the next value's tag `tag`?
+    pub(crate) fn peek(&self, tag: u8) -> bool {
+        self.rest.first() == Some(&tag)
+    }
+
+    /// Read the next value, whatever its tag.  Returns its tag, its
+    /// contents and its entire encoding.
+    pub(crate) fn read_any(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+        let tag = *self.rest.first()?;
+
+        // Nothing we parse uses high tag numbers.
+        if tag & 0x1f == 0x1f {
+            return None;
+        }
+
+        let first = *self.rest.get(1)?;
+        let (len, header_len) = match first {
+            0x00..=0x7f => (first as usize, 2),
+            0x81..=0x84 => {
+                let count = (first & 0x7f) as usize;
+                let bytes = self.rest.get(2..2 + count)?;
+
+                // DER demands the shortest encoding of the length.
+                if bytes[0] == 0 {
+                    return None;
+                }
+
+                let len = bytes
+                    .iter()
+                    .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+                if len < 0x80 {
+                    return None;
+                }
+                (len, 2 + count)
+            }
+            _ => return None,
+        };
+
+        let encoding = self
+            .rest
+            .get(..header_len.checked_add(len)?)?;
+        self.rest = &self.rest[encoding.len()..];
+        Some((tag, &encoding[header_len..], encoding))
+    }
+
+    /// Read the next value, which must have tag `tag`, and return its
+    /// contents.
+    pub(crate) fn read(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.read_with_encoding(tag)
+            .map(|(contents, _)| contents)
+    }
+
+    /// Like `read`, but also returns the entire encoding of the value.
+    pub(crate) fn read_with_encoding(&mut self, tag: u8) -> Option<(&'a [u8], &'a [u8])> {
+        let mut copy = *self;
+        match copy.read_any()? {
+            (actual, contents, encoding) if actual == tag => {
+                *self = copy;
+                Some((contents, encoding))
+            }
+            _ => None,
+        }
+    }
+
+    /// Read the next value if it has tag `tag`, returning its contents.
+    pub(crate) fn read_optional(&mut self, tag: u8) -> Option<Option<&'a [u8]>> {
+        match self.peek(tag) {
+            true => self.read(tag).map(Some),
+            false => Some(None),
+        }
+    }
+}
+
+/// Return the contents of a BIT STRING (given its contents, including
+/// the leading unused-bits count) as bytes.
+pub(crate) fn bit_string_bytes(contents: &[u8]) -> Option<&[u8]> {
+    match contents.split_first() {
+        Some((0, bytes)) => Some(bytes),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_der_reader() {
+    let mut long = vec![0x04, 0x81, 0x80];
+    long.resize(0x83, 0x11);
+    long.extend_from_slice(&[0x05, 0x00]);
+
+    let mut rd = DerReader::new(&long);
+    assert!(rd.peek(0x04));
+    assert_eq!(rd.read(0x05), None);
+    let (contents, encoding) = rd.read_with_encoding(0x04).unwrap();
+    assert_eq!(contents.len(), 0x80);
+    assert_eq!(encoding.len(), 0x83);
+    assert_eq!(rd.read_optional(0x04), Some(None));
+    assert_eq!(rd.read_optional(0x05), Some(Some(&[][..])));
+    assert!(rd.is_empty());
+}
+
+#[test]
+fn test_der_reader_rejects_bad_lengths() {
+    // non-minimal length encodings
+    assert_eq!(DerReader::new(&[0x04, 0x81, 0x01, 0x00]).read_any(), None);
+    assert_eq!(DerReader::new(&[0x04, 0x82, 0x00, 0x80]).read_any(), None);
+    // indefinite length
+    assert_eq!(DerReader::new(&[0x30, 0x80, 0x00, 0x00]).read_any(), None);
+    // truncated
+    assert_eq!(DerReader::new(&[0x04, 0x02, 0x00]).read_any(), None);
+    assert_eq!(DerReader::new(&[0x04]).read_any(), None);
+    // high tag number
+    assert_eq!(DerReader::new(&[0x1f, 0x01, 0x00]).read_any(), None);
+}
+
+#[test]
+fn test_bit_string_bytes() {
+    assert_eq!(bit_string_bytes(&[0x00, 0x12]), Some(&[0x12][..]));
+    assert_eq!(bit_string_bytes(&[0x01, 0x12]), None);
+    assert_eq!(bit_string_bytes(&[]), None);
+}
+
 #[test]
 fn test_empty() {
     let mut val = Vec::new();
@@ -4,23 +4,37 @@ use std::env;
 use std::fmt;
 use std::io::{self, IoSlice, Read, Write};
 use std::mem;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use rustls;
 
 use rustls::config_builder;
-use rustls::internal::msgs::{codec::Codec, persist::ClientSessionValue};
+use rustls::config_builder_with_provider;
+use rustls::crypto::ring::RING;
+use rustls::crypto::{ActiveKeyExchange, AeadKey, CryptoProvider, Digest};
+use rustls::crypto::{GetRandomFailed, HashAlgorithm, HashContext};
+use rustls::internal::msgs::{base::PayloadU8, codec::Codec, persist::ClientSessionValue};
 #[cfg(feature = "quic")]
 use rustls::quic::{self, ClientQuicExt, QuicExt, ServerQuicExt};
 use rustls::sign;
+use rustls::BulkAlgorithm;
 use rustls::ClientHello;
 use rustls::Connection;
+use rustls::DelegatedCredential;
 use rustls::Error;
 use rustls::KeyLog;
+use rustls::{Accepted, Acceptor};
+use rustls::{CertificateCompressionAlgorithm, CertificateCompressor, CertificateDecompressor};
 use rustls::{CipherSuite, ProtocolVersion, SignatureScheme};
+use rustls::{CipherSuiteCommon, Tls13AeadAlgorithm, Tls13CipherSuite};
 use rustls::{ClientConfig, ClientConnection, ResolvesClientCert};
+use rustls::{CompressionFailed, DecompressionFailed};
+use rustls::{EchConfig, EchKey};
+use rustls::{ExternalPsk, PSKKeyExchangeMode, PskMemoryStore};
+use rustls::{OcspError, RevocationPolicy};
 use rustls::{ResolvesServerCert, ServerConfig, ServerConnection};
 use rustls::{Stream, StreamOwned};
 use rustls::{SupportedCipherSuite, ALL_CIPHERSUITES};
@@ -28,6 +42,8 @@ use rustls::{WebPkiError, WebPkiOp};
 
 #[cfg(feature = "dangerous_configuration")]
 use rustls::ClientCertVerified;
+#[cfg(feature = "static-x25519")]
+use rustls::EchStatus;
 
 #[allow(dead_code)]
 mod common;
@@ -261,6 +277,445 @@ fn config_builder_for_server_rejects_incompatible_cipher_suites() {
     );
 }
 
+#[test]
+fn config_builder_for_client_rejects_empty_signature_schemes() {
+    assert_eq!(
+        config_builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .with_signature_schemes(&[])
+            .for_client()
+            .err(),
+        Some(Error::General("no signature schemes configured".into()))
+    );
+}
+
+#[test]
+fn config_builder_for_server_rejects_signature_schemes_unusable_with_suite() {
+    assert_eq!(
+        config_builder()
+            .with_cipher_suites(&[
+                rustls::cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                rustls::cipher_suite::TLS13_AES_128_GCM_SHA256,
+            ])
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .with_signature_schemes(&[SignatureScheme::RSA_PSS_SHA256])
+            .for_server()
+            .err(),
+        Some(Error::General(
+            "no signature schemes configured for TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256".into()
+        ))
+    );
+
+    assert_eq!(
+        config_builder()
+            .with_cipher_suites(&[rustls::cipher_suite::TLS13_AES_128_GCM_SHA256])
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .with_signature_schemes(&[SignatureScheme::RSA_PKCS1_SHA256])
+            .for_server()
+            .err(),
+        Some(Error::General(
+            "no signature schemes configured for TLS13_AES_128_GCM_SHA256".into()
+        ))
+    );
+}
+
+#[test]
+fn config_builder_for_server_rejects_unsupported_signature_scheme() {
+    assert_eq!(
+        config_builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .with_signature_schemes(&[
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ECDSA_SHA1_Legacy,
+            ])
+            .for_server()
+            .err(),
+        Some(Error::General(
+            "ECDSA_SHA1_Legacy not supported by the crypto provider".into()
+        ))
+    );
+}
+
+#[test]
+fn config_builder_rejects_key_unusable_with_signature_schemes() {
+    let builder = || {
+        config_builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .with_signature_schemes(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+    };
+    let expected =
+        Error::General("private key cannot sign with any enabled signature scheme".into());
+
+    let kt = KeyType::RSA;
+    let err = builder()
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(kt.get_chain(), kt.get_key())
+        .err();
+    assert_eq!(err, Some(expected.clone()));
+
+    let err = builder()
+        .for_client()
+        .unwrap()
+        .with_root_certificates(get_client_root_store(kt), &[])
+        .with_single_cert(kt.get_client_chain(), kt.get_client_key())
+        .err();
+    assert_eq!(err, Some(expected));
+
+    let kt = KeyType::ECDSA;
+    assert!(builder()
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(kt.get_chain(), kt.get_key())
+        .is_ok());
+}
+
+/// A provider which defers to *ring*, but lacks ChaCha20-Poly1305 and
+/// most key exchange groups, and counts the AEAD keys it makes.  It uses
+/// the default HKDF implementation.
+struct RestrictedProvider {
+    aead_keys: AtomicUsize,
+}
+
+static RESTRICTED: RestrictedProvider = RestrictedProvider {
+    aead_keys: AtomicUsize::new(0),
+};
+
+static RESTRICTED_KX_GROUPS: [&rustls::SupportedKxGroup; 2] =
+    [&rustls::kx_group::X25519, &rustls::kx_group::SECP256R1];
+
+impl CryptoProvider for RestrictedProvider {
+    fn fill_random(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+        RING.fill_random(buf)
+    }
+
+    fn start_hash(&self, alg: HashAlgorithm) -> Box<dyn HashContext> {
+        RING.start_hash(alg)
+    }
+
+    fn hmac_sign(&self, alg: HashAlgorithm, key: &[u8], data: &[&[u8]]) -> Digest {
+        RING.hmac_sign(alg, key, data)
+    }
+
+    fn aead_supported(&self, alg: &BulkAlgorithm) -> bool {
+        *alg != BulkAlgorithm::Chacha20Poly1305
+    }
+
+    fn aead_key(&self, alg: &BulkAlgorithm, key: &[u8]) -> Result<Box<dyn AeadKey>, Error> {
+        self.aead_keys
+            .fetch_add(1, Ordering::SeqCst);
+        RING.aead_key(alg, key)
+    }
+
+    fn kx_groups(&self) -> &[&'static rustls::SupportedKxGroup] {
+        &RESTRICTED_KX_GROUPS
+    }
+
+    fn start_key_exchange(
+        &self,
+        group: &'static rustls::SupportedKxGroup,
+    ) -> Option<Box<dyn ActiveKeyExchange>> {
+        RING.start_key_exchange(group)
+    }
+
+    fn load_private_key(
+        &self,
+        key: &rustls::PrivateKey,
+    ) -> Result<Arc<dyn sign::SigningKey>, sign::SignError> {
+        RING.load_private_key(key)
+    }
+
+    fn signature_schemes(&self) -> &[SignatureScheme] {
+        RING.signature_schemes()
+    }
+
+    fn verify_signature(
+        &self,
+        scheme: SignatureScheme,
+        spki: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error> {
+        RING.verify_signature(scheme, spki, message, signature)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        scheme: SignatureScheme,
+        spki: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error> {
+        RING.verify_tls12_signature(scheme, spki, message, signature)
+    }
+}
+
+#[test]
+fn config_builder_with_provider_restricts_defaults() {
+    let client_config = finish_client_config(
+        KeyType::RSA,
+        config_builder_with_provider(&RESTRICTED)
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .for_client()
+            .unwrap(),
+    );
+    let server_config = finish_server_config(
+        KeyType::RSA,
+        config_builder()
+            .with_cipher_suites(&[rustls::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256])
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .for_server()
+            .unwrap(),
+    );
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatibleError(
+            "no ciphersuites in common".into()
+        )))
+    );
+}
+
+#[test]
+fn config_builder_with_provider_rejects_unsupported_algorithms() {
+    assert_eq!(
+        config_builder_with_provider(&RESTRICTED)
+            .with_cipher_suites(&[rustls::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256])
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .for_server()
+            .err(),
+        Some(Error::General(
+            "TLS13_CHACHA20_POLY1305_SHA256 not supported by the crypto provider".into()
+        ))
+    );
+
+    assert_eq!(
+        config_builder_with_provider(&RESTRICTED)
+            .with_safe_default_cipher_suites()
+            .with_kx_groups(&[&rustls::kx_group::SECP384R1])
+            .with_safe_default_protocol_versions()
+            .for_client()
+            .err(),
+        Some(Error::General(
+            "secp384r1 not supported by the crypto provider".into()
+        ))
+    );
+}
+
+#[test]
+fn handshake_with_custom_provider() {
+    for version in rustls::ALL_VERSIONS {
+        let server_config = finish_server_config(
+            KeyType::ECDSA,
+            config_builder_with_provider(&RESTRICTED)
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[version])
+                .for_server()
+                .unwrap(),
+        );
+        let client_config = make_client_config(KeyType::ECDSA);
+
+        let before = RESTRICTED
+            .aead_keys
+            .load(Ordering::SeqCst);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(
+            RESTRICTED
+                .aead_keys
+                .load(Ordering::SeqCst)
+                > before
+        );
+
+        assert_eq!(client.protocol_version(), Some(version.version));
+        let suite = server
+            .negotiated_cipher_suite()
+            .unwrap();
+        assert!(![
+            rustls::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+            rustls::cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+        ]
+        .contains(&suite));
+
+        server
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        check_read(&mut client.reader(), b"hello");
+    }
+}
+
+/// AES-128-GCM from the config's crypto provider, standing in for an AEAD
+/// which rustls does not know.  It counts the keys it makes.
+struct CountingAes128Gcm {
+    keys: AtomicUsize,
+}
+
+impl Tls13AeadAlgorithm for CountingAes128Gcm {
+    fn key_len(&self) -> usize {
+        16
+    }
+
+    fn new_key(
+        &self,
+        provider: &dyn CryptoProvider,
+        key: &[u8],
+    ) -> Result<Box<dyn AeadKey>, Error> {
+        self.keys.fetch_add(1, Ordering::SeqCst);
+        provider.aead_key(&BulkAlgorithm::Aes128Gcm, key)
+    }
+}
+
+static COUNTING_AES_128_GCM: CountingAes128Gcm = CountingAes128Gcm {
+    keys: AtomicUsize::new(0),
+};
+
+static CUSTOM_TLS13_SUITE: SupportedCipherSuite = SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::Unknown(0xff01),
+    },
+    hash_algorithm: HashAlgorithm::SHA256,
+    aead_alg: &COUNTING_AES_128_GCM,
+    confidentiality_limit: 1 << 23,
+    integrity_limit: 1 << 52,
+});
+
+// This key starts wanting an update after 0x1000 records.
+static SHORT_LIVED_TLS13_SUITE: SupportedCipherSuite =
+    SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::Unknown(0xff02),
+        },
+        hash_algorithm: HashAlgorithm::SHA256,
+        aead_alg: &COUNTING_AES_128_GCM,
+        confidentiality_limit: 0x1_1000,
+        integrity_limit: 1 << 52,
+    });
+
+/// AES-128-GCM from the config's crypto provider, which can be told
+/// to stop making keys.
+struct FlakyAes128Gcm {
+    fail: AtomicBool,
+}
+
+impl Tls13AeadAlgorithm for FlakyAes128Gcm {
+    fn key_len(&self) -> usize {
+        16
+    }
+
+    fn new_key(
+        &self,
+        provider: &dyn CryptoProvider,
+        key: &[u8],
+    ) -> Result<Box<dyn AeadKey>, Error> {
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(Error::General("no more keys".to_string()));
+        }
+        provider.aead_key(&BulkAlgorithm::Aes128Gcm, key)
+    }
+}
+
+static FLAKY_AES_128_GCM: FlakyAes128Gcm = FlakyAes128Gcm {
+    fail: AtomicBool::new(false),
+};
+
+// Like SHORT_LIVED_TLS13_SUITE, but its key updates can be made to fail.
+static FLAKY_SHORT_LIVED_TLS13_SUITE: SupportedCipherSuite =
+    SupportedCipherSuite::Tls13(&Tls13CipherSuite {
+        common: CipherSuiteCommon {
+            suite: CipherSuite::Unknown(0xff03),
+        },
+        hash_algorithm: HashAlgorithm::SHA256,
+        aead_alg: &FLAKY_AES_128_GCM,
+        confidentiality_limit: 0x1_1000,
+        integrity_limit: 1 << 52,
+    });
+
+fn custom_suite_config_builder() -> rustls::ConfigWantsKxGroups {
+    config_builder().with_cipher_suites(&[CUSTOM_TLS13_SUITE])
+}
+
+#[test]
+fn handshake_with_user_defined_tls13_suite() {
+    let client_config = finish_client_config(
+        KeyType::RSA,
+        custom_suite_config_builder()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .for_client()
+            .unwrap(),
+    );
+    let server_config = finish_server_config(
+        KeyType::RSA,
+        custom_suite_config_builder()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .for_server()
+            .unwrap(),
+    );
+
+    let before = COUNTING_AES_128_GCM
+        .keys
+        .load(Ordering::SeqCst);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.negotiated_cipher_suite(), Some(CUSTOM_TLS13_SUITE));
+    assert_eq!(server.negotiated_cipher_suite(), Some(CUSTOM_TLS13_SUITE));
+    assert_eq!(CUSTOM_TLS13_SUITE.bulk(), None);
+    assert_eq!(
+        rustls::cipher_suite::TLS13_AES_128_GCM_SHA256.bulk(),
+        Some(&BulkAlgorithm::Aes128Gcm)
+    );
+    assert!(
+        COUNTING_AES_128_GCM
+            .keys
+            .load(Ordering::SeqCst)
+            >= before + 4
+    );
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+
+    server
+        .writer()
+        .write_all(b"goodbye")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"goodbye");
+
+    assert_eq!(
+        client.extract_secrets().err(),
+        Some(Error::General(
+            "cannot extract secrets for this cipher suite".into()
+        ))
+    );
+}
+
 #[test]
 fn buffered_client_data_sent() {
     let server_config = Arc::new(make_server_config(KeyType::RSA));
@@ -653,71 +1108,1178 @@ fn server_cert_resolve_reduces_sigalgs_for_ecdsa_ciphersuite() {
         vec![
             SignatureScheme::ECDSA_NISTP384_SHA384,
             SignatureScheme::ECDSA_NISTP256_SHA256,
+            #[cfg(feature = "secp521r1")]
+            SignatureScheme::ECDSA_NISTP521_SHA512,
             SignatureScheme::ED25519,
+            #[cfg(feature = "curve448")]
+            SignatureScheme::ED448,
         ],
     );
 }
 
-struct ServerCheckNoSNI {}
+/// A compliance profile which forbids Ed25519 and RSA-PKCS1.
+static NO_ED25519_OR_PKCS1: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+];
 
-impl ResolvesServerCert for ServerCheckNoSNI {
-    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
-        assert!(client_hello.server_name().is_none());
+#[test]
+fn client_advertises_only_enabled_signature_schemes() {
+    let client_config =
+        make_client_config_with_signature_schemes(KeyType::RSA, NO_ED25519_OR_PKCS1);
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+        expected_sigalgs: Some(NO_ED25519_OR_PKCS1.to_vec()),
+        ..Default::default()
+    });
 
-        None
-    }
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert!(err.is_err());
 }
 
+/// The same profile, preferring RSA.
+static RSA_FIRST_NO_ED25519_OR_PKCS1: &[SignatureScheme] = &[
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+];
+
 #[test]
-fn client_with_sni_disabled_does_not_send_sni() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let mut server_config = make_server_config(*kt);
-        server_config.cert_resolver = Arc::new(ServerCheckNoSNI {});
-        let server_config = Arc::new(server_config);
+fn client_advertises_signature_schemes_in_preference_order() {
+    let client_config =
+        make_client_config_with_signature_schemes(KeyType::RSA, RSA_FIRST_NO_ED25519_OR_PKCS1);
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+        expected_sigalgs: Some(RSA_FIRST_NO_ED25519_OR_PKCS1.to_vec()),
+        ..Default::default()
+    });
 
-        for version in rustls::ALL_VERSIONS {
-            let mut client_config = make_client_config_with_versions(*kt, &[version]);
-            client_config.enable_sni = false;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert!(err.is_err());
+}
 
-            let mut client =
+#[test]
+fn server_requests_signature_schemes_in_preference_order() {
+    let kt = KeyType::RSA;
+    let server_config = Arc::new(make_server_config_with_signature_schemes_and_client_auth(
+        kt,
+        RSA_FIRST_NO_ED25519_OR_PKCS1,
+    ));
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve {
+            query_count: AtomicUsize::new(0),
+            expect_queries: 1,
+            expected_sigschemes: Some(RSA_FIRST_NO_ED25519_OR_PKCS1.to_vec()),
+        });
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+        );
+    }
+}
+
+#[test]
+fn signature_scheme_profile_allows_rsa_and_ecdsa() {
+    for kt in &[KeyType::RSA, KeyType::ECDSA] {
+        let client_config = make_client_config_with_signature_schemes(*kt, NO_ED25519_OR_PKCS1);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(*kt));
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn signature_scheme_profile_refuses_ed25519_server() {
+    let client_config =
+        make_client_config_with_signature_schemes(KeyType::ED25519, NO_ED25519_OR_PKCS1);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::ED25519));
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert!(err.is_err());
+}
+
+/// A key which signs with its preferred scheme, whatever the peer offered.
+struct IgnoresOfferedSchemes(Arc<dyn sign::SigningKey>);
+
+impl sign::SigningKey for IgnoresOfferedSchemes {
+    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        self.0
+            .choose_scheme(rustls::ALL_SIGNATURE_SCHEMES)
+    }
+
+    fn algorithm(&self) -> rustls::internal::msgs::enums::SignatureAlgorithm {
+        self.0.algorithm()
+    }
+}
+
+#[test]
+fn client_rejects_signature_with_disabled_scheme() {
+    let kt = KeyType::ED25519;
+    let key = sign::any_supported_type(&kt.get_key()).unwrap();
+    let certified_key =
+        sign::CertifiedKey::new(kt.get_chain(), Arc::new(IgnoresOfferedSchemes(key)));
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(AlwaysResolves(Arc::new(certified_key)));
+
+    let client_config = make_client_config_with_signature_schemes(kt, NO_ED25519_OR_PKCS1);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Client(Error::PeerMisbehavedError(
+            "peer signed with disabled scheme ED25519".into()
+        )))
+    );
+}
+
+struct ServerCheckNoSNI {}
+
+impl ResolvesServerCert for ServerCheckNoSNI {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(client_hello.server_name().is_none());
+
+        None
+    }
+}
+
+#[test]
+fn client_with_sni_disabled_does_not_send_sni() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckNoSNI {});
+        let server_config = Arc::new(server_config);
+
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(*kt, &[version]);
+            client_config.enable_sni = false;
+
+            let mut client =
                 ClientConnection::new(Arc::new(client_config), dns_name("value-not-sent")).unwrap();
             let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(err.is_err(), true);
-        }
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(err.is_err(), true);
+        }
+    }
+}
+
+#[test]
+fn client_checks_server_certificate_with_given_name() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let mut client = ClientConnection::new(
+                Arc::new(client_config),
+                dns_name("not-the-right-hostname.com"),
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::WebPkiError(
+                    WebPkiError::CertNotValidForName,
+                    WebPkiOp::ValidateForDnsName,
+                )))
+            );
+        }
+    }
+}
+
+#[test]
+fn client_accepts_good_stapled_ocsp_response() {
+    for kt in ALL_KEY_TYPES.iter() {
+        for status in &["good", "delegated"] {
+            let server_config = Arc::new(make_server_config_with_ocsp(
+                *kt,
+                kt.get_chain(),
+                kt.get_ocsp_response(status),
+            ));
+
+            for version in rustls::ALL_VERSIONS {
+                let client_config = make_client_config_with_revocation_policy(
+                    *kt,
+                    version,
+                    RevocationPolicy::RequireStapled,
+                );
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+                do_handshake(&mut client, &mut server);
+            }
+        }
+    }
+}
+
+#[test]
+fn client_rejects_revoked_server_certificate() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config_with_ocsp(
+            *kt,
+            kt.get_chain(),
+            kt.get_ocsp_response("revoked"),
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_revocation_policy(
+                *kt,
+                version,
+                RevocationPolicy::CheckStapled,
+            );
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::OcspError(
+                    OcspError::CertRevoked
+                )))
+            );
+
+            transfer(&mut client, &mut server);
+            assert_eq!(
+                server.process_new_packets(),
+                Err(Error::AlertReceived(AlertDescription::CertificateRevoked))
+            );
+        }
+    }
+}
+
+#[test]
+fn client_ignores_revocation_by_default() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config_with_ocsp(
+            *kt,
+            kt.get_chain(),
+            kt.get_ocsp_response("revoked"),
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+        }
+    }
+}
+
+#[test]
+fn client_enforces_must_staple() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config_with_ocsp(
+            *kt,
+            kt.get_must_staple_chain(),
+            vec![],
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_revocation_policy(
+                *kt,
+                version,
+                RevocationPolicy::CheckStapled,
+            );
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::OcspError(OcspError::Missing)))
+            );
+
+            let client_config =
+                make_client_config_with_revocation_policy(*kt, version, RevocationPolicy::Ignore);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+        }
+    }
+}
+
+#[test]
+fn client_requires_stapled_ocsp_response_if_configured() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_revocation_policy(
+                *kt,
+                version,
+                RevocationPolicy::CheckStapled,
+            );
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+
+            let client_config = make_client_config_with_revocation_policy(
+                *kt,
+                version,
+                RevocationPolicy::RequireStapled,
+            );
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::OcspError(OcspError::Missing)))
+            );
+        }
+    }
+}
+
+/// A toy certificate compression algorithm, which reverses its input.
+/// It counts how many times it has been used in each direction.
+#[derive(Default)]
+struct ReverseCompression {
+    compressed: AtomicUsize,
+    decompressed: AtomicUsize,
+}
+
+impl ReverseCompression {
+    fn counts(&self) -> (usize, usize) {
+        (
+            self.compressed.load(Ordering::SeqCst),
+            self.decompressed.load(Ordering::SeqCst),
+        )
+    }
+}
+
+impl CertificateCompressor for ReverseCompression {
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertificateCompressionAlgorithm::Unknown(0xff00)
+    }
+
+    fn compress(&self, input: &[u8]) -> Result<Vec<u8>, CompressionFailed> {
+        self.compressed
+            .fetch_add(1, Ordering::SeqCst);
+        Ok(input.iter().rev().cloned().collect())
+    }
+}
+
+impl CertificateDecompressor for ReverseCompression {
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertificateCompressionAlgorithm::Unknown(0xff00)
+    }
+
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressionFailed> {
+        self.decompressed
+            .fetch_add(1, Ordering::SeqCst);
+        if input.len() != output.len() {
+            return Err(DecompressionFailed);
+        }
+        for (o, i) in output
+            .iter_mut()
+            .zip(input.iter().rev())
+        {
+            *o = *i;
+        }
+        Ok(())
+    }
+}
+
+/// A certificate decompressor which always fails.
+struct BrokenDecompression;
+
+impl CertificateDecompressor for BrokenDecompression {
+    fn algorithm(&self) -> CertificateCompressionAlgorithm {
+        CertificateCompressionAlgorithm::Unknown(0xff00)
+    }
+
+    fn decompress(&self, _input: &[u8], _output: &mut [u8]) -> Result<(), DecompressionFailed> {
+        Err(DecompressionFailed)
+    }
+}
+
+#[test]
+fn server_sends_compressed_certificate() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let compression = Arc::new(ReverseCompression::default());
+
+        let server_config =
+            make_server_config_with_cert_compressors(*kt, vec![compression.clone()]);
+        let client_config =
+            make_client_config_with_cert_decompressors(*kt, vec![compression.clone()]);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(compression.counts(), (1, 1));
+        assert_eq!(client.peer_certificates(), Some(&kt.get_chain()[..]));
+    }
+}
+
+#[test]
+fn client_sends_compressed_certificate() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let compression = Arc::new(ReverseCompression::default());
+
+        let mut server_config = make_server_config_with_mandatory_client_auth(*kt);
+        server_config.cert_decompressors = vec![compression.clone()];
+        let mut client_config = make_client_config_with_auth(*kt);
+        client_config.cert_compressors = vec![compression.clone()];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(compression.counts(), (1, 1));
+        assert_eq!(server.peer_certificates(), Some(&kt.get_client_chain()[..]));
+    }
+}
+
+#[test]
+fn certificate_compression_needs_common_algorithm_and_tls13() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let compression = Arc::new(ReverseCompression::default());
+
+        let server_config = Arc::new(make_server_config_with_cert_compressors(
+            *kt,
+            vec![compression.clone()],
+        ));
+
+        // client doesn't offer any algorithm
+        let client_config = Arc::new(make_client_config(*kt));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+
+        // client only does TLS1.2
+        let mut client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
+        client_config.cert_decompressors = vec![compression.clone()];
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(compression.counts(), (0, 0));
+    }
+}
+
+#[test]
+fn client_limits_decompressed_certificate_size() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let compression = Arc::new(ReverseCompression::default());
+
+        let server_config =
+            make_server_config_with_cert_compressors(*kt, vec![compression.clone()]);
+        let mut client_config =
+            make_client_config_with_cert_decompressors(*kt, vec![compression.clone()]);
+        client_config.max_decompressed_cert_size = 100;
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        let err = do_handshake_until_both_error(&mut client, &mut server);
+        assert_eq!(
+            err,
+            Err(vec![
+                ErrorFromPeer::Client(Error::CertificateDecompressionFailed),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate)),
+            ])
+        );
+        assert_eq!(compression.counts(), (1, 0));
+    }
+}
+
+#[test]
+fn client_rejects_undecompressable_certificate() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = make_server_config_with_cert_compressors(
+            *kt,
+            vec![Arc::new(ReverseCompression::default())],
+        );
+        let client_config =
+            make_client_config_with_cert_decompressors(*kt, vec![Arc::new(BrokenDecompression)]);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        let err = do_handshake_until_both_error(&mut client, &mut server);
+        assert_eq!(
+            err,
+            Err(vec![
+                ErrorFromPeer::Client(Error::CertificateDecompressionFailed),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate)),
+            ])
+        );
+    }
+}
+
+#[cfg(feature = "static-x25519")]
+#[test]
+fn client_and_server_accept_ech() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let key = make_ech_key(1, 0x11);
+        let mut server_config = make_server_config(*kt);
+        server_config.ech_keys = vec![make_ech_key(2, 0x22), key.clone()];
+        let client_config = make_ech_client_config(*kt, &key);
+
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), dns_name("second.testserver.com"))
+                .unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        assert_eq!(client.ech_status(), EchStatus::Offered);
+
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.ech_status(), EchStatus::Accepted);
+        assert_eq!(Some("second.testserver.com"), server.sni_hostname());
+        send_and_check(&mut client, &mut server, b"hello");
+        send_and_check(&mut server, &mut client, b"world");
+    }
+}
+
+#[cfg(feature = "static-x25519")]
+#[test]
+fn client_and_server_accept_ech_after_hello_retry() {
+    let kt = KeyType::RSA;
+    let key = make_ech_key(1, 0x11);
+    let mut server_config = make_server_config_with_kx_groups(kt, &[&rustls::kx_group::SECP384R1]);
+    server_config.ech_keys = vec![key.clone()];
+    let mut client_config = make_client_config_with_kx_groups(
+        kt,
+        &[&rustls::kx_group::X25519, &rustls::kx_group::SECP384R1],
+    );
+    client_config.ech_config = Some(EchConfig::new(&key.config_list()).unwrap());
+
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), dns_name("second.testserver.com")).unwrap();
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.ech_status(), EchStatus::Accepted);
+    assert_eq!(Some("second.testserver.com"), server.sni_hostname());
+    send_and_check(&mut client, &mut server, b"hello");
+    send_and_check(&mut server, &mut client, b"world");
+}
+
+#[cfg(feature = "static-x25519")]
+#[test]
+fn client_fails_when_server_rejects_ech() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_key = make_ech_key(1, 0x11);
+        let mut server_config = make_server_config(*kt);
+        server_config.ech_keys = vec![server_key.clone()];
+        let client_config = make_ech_client_config(*kt, &make_ech_key(1, 0x22));
+
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), dns_name("second.testserver.com"))
+                .unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        let err = do_handshake_until_both_error(&mut client, &mut server);
+        assert_eq!(
+            err,
+            Err(vec![
+                ErrorFromPeer::Client(Error::EncryptedClientHelloRejected {
+                    retry_configs: Some(server_key.config_list()),
+                }),
+                ErrorFromPeer::Server(Error::AlertReceived(
+                    AlertDescription::EncryptedClientHelloRequired
+                )),
+            ])
+        );
+        assert_eq!(client.ech_status(), EchStatus::Rejected);
+
+        // The server saw only the public name.
+        assert_eq!(Some("testserver.com"), server.sni_hostname());
+        assert!(EchConfig::new(&server_key.config_list()).is_ok());
+    }
+}
+
+#[cfg(feature = "static-x25519")]
+#[test]
+fn client_fails_when_server_does_not_support_ech() {
+    let kt = KeyType::RSA;
+    let client_config = make_ech_client_config(kt, &make_ech_key(1, 0x11));
+
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), dns_name("second.testserver.com")).unwrap();
+    let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Client(Error::EncryptedClientHelloRejected {
+            retry_configs: None
+        }))
+    );
+    assert_eq!(client.ech_status(), EchStatus::Rejected);
+    assert_eq!(Some("testserver.com"), server.sni_hostname());
+}
+
+#[cfg(feature = "static-x25519")]
+#[test]
+fn client_with_ech_requires_tls13() {
+    let kt = KeyType::RSA;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    client_config.ech_config = Some(EchConfig::new(&make_ech_key(1, 0x11).config_list()).unwrap());
+
+    assert!(ClientConnection::new(Arc::new(client_config), dns_name("localhost")).is_err());
+}
+
+#[test]
+fn ech_config_rejects_invalid_lists() {
+    assert_eq!(EchConfig::new(&[]).unwrap_err(), Error::InvalidEchConfig);
+    assert_eq!(
+        EchConfig::new(&[0x00, 0x04, 0xfe, 0x0d, 0x00, 0x00]).unwrap_err(),
+        Error::InvalidEchConfig
+    );
+}
+
+#[cfg(feature = "static-x25519")]
+#[test]
+fn ech_key_rejects_invalid_inputs() {
+    let mut list = make_ech_key(1, 0x11).config_list();
+    list.push(0x00);
+    assert_eq!(EchConfig::new(&list).unwrap_err(), Error::InvalidEchConfig);
+
+    assert!(EchKey::new(1, "not a dns name!", &[0x11; 32]).is_err());
+    assert!(EchKey::new(1, "testserver.com", &[0x11; 31]).is_err());
+}
+
+#[cfg(not(feature = "static-x25519"))]
+#[test]
+fn ech_key_needs_static_x25519() {
+    assert!(EchKey::new(1, "testserver.com", &[0x11; 32]).is_err());
+}
+
+/// A key which cannot sign anything.  A server using this can only
+/// complete a TLS1.3 handshake with a delegated credential.
+struct NoSigningKey;
+
+impl sign::SigningKey for NoSigningKey {
+    fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        None
+    }
+
+    fn algorithm(&self) -> rustls::internal::msgs::enums::SignatureAlgorithm {
+        rustls::internal::msgs::enums::SignatureAlgorithm::Anonymous
+    }
+}
+
+const ONE_DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[test]
+fn server_signs_with_delegated_credential() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let dc = make_delegated_credential(*kt, ONE_DAY).unwrap();
+        let server_config =
+            make_server_config_with_delegated_credential(*kt, Arc::new(NoSigningKey), dc);
+        let mut client_config = make_client_config(*kt);
+        client_config.enable_delegated_credentials = true;
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+        assert_eq!(
+            client.peer_certificates(),
+            Some(&kt.get_delegation_chain()[..])
+        );
+    }
+}
+
+#[test]
+fn delegated_credential_needs_client_support_and_tls13() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let dc = make_delegated_credential(*kt, ONE_DAY).unwrap();
+        let end_entity_key = sign::any_supported_type(&kt.get_key()).unwrap();
+        let server_config = Arc::new(make_server_config_with_delegated_credential(
+            *kt,
+            end_entity_key,
+            dc.clone(),
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            for enabled in &[false, true] {
+                let mut client_config = make_client_config_with_versions(*kt, &[version]);
+                client_config.enable_delegated_credentials = *enabled;
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+                do_handshake(&mut client, &mut server);
+            }
+        }
+
+        // the server must not use the credential if the client didn't offer
+        let server_config =
+            make_server_config_with_delegated_credential(*kt, Arc::new(NoSigningKey), dc);
+        let client_config = make_client_config(*kt);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::PeerIncompatibleError(
+                "no overlapping sigschemes".to_string()
+            )))
+        );
+    }
+}
+
+#[test]
+fn client_rejects_expired_delegated_credential() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let dc = make_delegated_credential(*kt, Duration::from_secs(0)).unwrap();
+        let server_config =
+            make_server_config_with_delegated_credential(*kt, Arc::new(NoSigningKey), dc);
+        let mut client_config = make_client_config(*kt);
+        client_config.enable_delegated_credentials = true;
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Client(Error::InvalidDelegatedCredential),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::IllegalParameter)),
+            ])
+        );
+    }
+}
+
+#[test]
+fn client_rejects_forged_delegated_credential() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut encoding = make_delegated_credential(*kt, ONE_DAY)
+            .unwrap()
+            .encoding();
+        *encoding.last_mut().unwrap() ^= 0x01;
+        let (key, _) = kt.get_delegated_credential_key();
+        let dc =
+            DelegatedCredential::from_encoding(&encoding, sign::any_supported_type(&key).unwrap())
+                .unwrap();
+
+        let server_config =
+            make_server_config_with_delegated_credential(*kt, Arc::new(NoSigningKey), dc);
+        let mut client_config = make_client_config(*kt);
+        client_config.enable_delegated_credentials = true;
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Client(Error::InvalidDelegatedCredential),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::IllegalParameter)),
+            ])
+        );
+    }
+}
+
+#[test]
+fn delegated_credential_issuance_is_checked() {
+    for kt in ALL_KEY_TYPES.iter() {
+        // too long-lived
+        assert_eq!(
+            make_delegated_credential(*kt, 8 * ONE_DAY).err(),
+            Some(Error::InvalidDelegatedCredential)
+        );
+
+        // certificate lacks the DelegationUsage extension
+        let end_entity_key = sign::any_supported_type(&kt.get_key()).unwrap();
+        let (key, public_key) = kt.get_delegated_credential_key();
+        let key = sign::any_supported_type(&key).unwrap();
+        assert_eq!(
+            DelegatedCredential::new(
+                &kt.get_chain()[0],
+                end_entity_key.as_ref(),
+                public_key.clone(),
+                key.clone(),
+                ONE_DAY,
+                RING.signature_schemes()
+            )
+            .err(),
+            Some(Error::InvalidDelegatedCredential)
+        );
+
+        let dc_chain = kt.get_delegation_chain();
+        let dc = make_delegated_credential(*kt, ONE_DAY).unwrap();
+
+        // public key does not match the credential's key
+        assert_eq!(
+            DelegatedCredential::new(
+                &dc_chain[0],
+                end_entity_key.as_ref(),
+                kt.get_spki(),
+                key.clone(),
+                ONE_DAY,
+                RING.signature_schemes()
+            )
+            .err(),
+            Some(Error::InvalidDelegatedCredential)
+        );
+
+        // the credential's scheme is not allowed
+        let without_dc_scheme = RING
+            .signature_schemes()
+            .iter()
+            .filter(|scheme| **scheme != dc.scheme())
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            DelegatedCredential::new(
+                &dc_chain[0],
+                end_entity_key.as_ref(),
+                public_key,
+                key.clone(),
+                ONE_DAY,
+                &without_dc_scheme
+            )
+            .err(),
+            Some(Error::InvalidDelegatedCredential)
+        );
+
+        // loading the encoding
+        let encoding = dc.encoding();
+        let loaded = DelegatedCredential::from_encoding(&encoding, key.clone()).unwrap();
+        assert_eq!(loaded.encoding(), encoding);
+        assert_eq!(loaded.scheme(), dc.scheme());
+
+        let mut trailing = encoding.clone();
+        trailing.push(0);
+        assert_eq!(
+            DelegatedCredential::from_encoding(&trailing, key.clone()).err(),
+            Some(Error::InvalidDelegatedCredential)
+        );
+        assert_eq!(
+            DelegatedCredential::from_encoding(&encoding, Arc::new(NoSigningKey)).err(),
+            Some(Error::InvalidDelegatedCredential)
+        );
+    }
+}
+
+#[test]
+fn delegated_credential_issuance_follows_signature_schemes() {
+    let kt = KeyType::RSA;
+    let end_entity_key = sign::any_supported_type(&kt.get_key()).unwrap();
+    let (key, public_key) = kt.get_delegated_credential_key();
+    let key = sign::any_supported_type(&key).unwrap();
+    let issue = |schemes: &[SignatureScheme]| {
+        DelegatedCredential::new(
+            &kt.get_delegation_chain()[0],
+            end_entity_key.as_ref(),
+            public_key.clone(),
+            key.clone(),
+            ONE_DAY,
+            schemes,
+        )
+    };
+
+    // the end-entity key signs with the only RSA scheme allowed
+    let dc = issue(&[
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::RSA_PSS_SHA384,
+    ])
+    .unwrap();
+    let encoding = dc.encoding();
+    let algorithm = 4 + 2 + 3 + public_key.len();
+    assert_eq!(&encoding[algorithm..algorithm + 2], &[0x08, 0x05]);
+
+    // PKCS#1 signatures are not allowed in TLS1.3
+    assert_eq!(
+        issue(&[
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ])
+        .err(),
+        Some(Error::InvalidDelegatedCredential)
+    );
+}
+
+#[test]
+fn raw_public_keys_authenticate_both_peers() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_raw_public_key_client_config(*kt, kt.get_spki());
+        let server_config = make_raw_public_key_server_config(*kt, kt.get_client_spki());
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client.peer_certificates(),
+            Some(&[rustls::Certificate(kt.get_spki())][..])
+        );
+        assert_eq!(
+            server.peer_certificates(),
+            Some(&[rustls::Certificate(kt.get_client_spki())][..])
+        );
+    }
+}
+
+#[test]
+fn raw_public_keys_must_be_pinned() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_raw_public_key_client_config(*kt, kt.get_client_spki());
+        let server_config = make_raw_public_key_server_config(*kt, kt.get_client_spki());
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Client(Error::UntrustedRawPublicKey),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate)),
+            ])
+        );
+
+        let client_config = make_raw_public_key_client_config(*kt, kt.get_spki());
+        let server_config = make_raw_public_key_server_config(*kt, kt.get_spki());
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::UntrustedRawPublicKey))
+        );
+    }
+}
+
+#[test]
+fn raw_public_keys_need_peer_support_and_tls13() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_raw_public_key_server_config(*kt, kt.get_client_spki()));
+
+        let client_config = make_client_config(*kt);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::PeerIncompatibleError(
+                "client does not support raw public keys".to_string()
+            )))
+        );
+
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::PeerIncompatibleError(
+                "no certificate chain for TLS1.2 client".to_string()
+            )))
+        );
+
+        let client_config = make_raw_public_key_client_config(*kt, kt.get_spki());
+        let server_config = make_server_config(*kt);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Server(Error::PeerIncompatibleError(
+                    "no server certificate type in common".to_string()
+                )),
+                ErrorFromPeer::Client(Error::AlertReceived(
+                    AlertDescription::UnsupportedCertificate
+                )),
+            ])
+        );
+    }
+}
+
+#[test]
+fn server_with_chain_and_raw_public_key_serves_both() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let key = sign::any_supported_type(&kt.get_key()).unwrap();
+        let mut certified_key = sign::CertifiedKey::new(kt.get_chain(), key);
+        certified_key.raw_public_key = Some(kt.get_spki());
+        let server_config = Arc::new(
+            rustls::server_config_builder_with_safe_defaults()
+                .with_no_client_auth()
+                .with_cert_resolver(Arc::new(AlwaysResolves(Arc::new(certified_key)))),
+        );
+
+        let client_config = make_client_config(*kt);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client
+                .peer_certificates()
+                .map(<[_]>::to_vec),
+            Some(kt.get_chain())
+        );
+
+        // a client which also has a raw public key, but the server
+        // does not ask for client auth.
+        let client_config = make_raw_public_key_client_config(*kt, kt.get_spki());
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client.peer_certificates(),
+            Some(&[rustls::Certificate(kt.get_spki())][..])
+        );
+        assert_eq!(server.peer_certificates(), None);
+    }
+}
+
+#[test]
+fn external_psks_authenticate_both_peers() {
+    for modes in &[
+        &[PSKKeyExchangeMode::PSK_DHE_KE][..],
+        &[PSKKeyExchangeMode::PSK_KE][..],
+        &[PSKKeyExchangeMode::PSK_KE, PSKKeyExchangeMode::PSK_DHE_KE][..],
+    ] {
+        let client_config = make_psk_client_config(
+            &supported_cipher_suites(),
+            vec![make_psk(b"other", 1), make_psk(b"ours", 2)],
+            modes,
+        );
+        let server_config = make_psk_server_config(vec![make_psk(b"ours", 2)], modes);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(client.external_psk_identity(), None);
+
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.external_psk_identity(), Some(&b"ours"[..]));
+        assert_eq!(server.external_psk_identity(), Some(&b"ours"[..]));
+        assert_eq!(client.peer_certificates(), None);
+        assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+        server
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        check_read(&mut client.reader(), b"hello");
+    }
+}
+
+#[test]
+fn imported_psks_can_be_used_with_sha384() {
+    let suite = find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384);
+    let psk = || ExternalPsk::imported(b"ours".to_vec(), vec![3; 32], b"app".to_vec()).unwrap();
+    let client_config =
+        make_psk_client_config(&[suite], vec![psk()], &[PSKKeyExchangeMode::PSK_DHE_KE]);
+    let server_config = make_psk_server_config(vec![psk()], &[PSKKeyExchangeMode::PSK_DHE_KE]);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.negotiated_cipher_suite(), Some(suite));
+    assert_eq!(client.external_psk_identity(), Some(&b"ours"[..]));
+    assert_eq!(server.external_psk_identity(), Some(&b"ours"[..]));
+}
+
+#[test]
+fn external_psk_with_wrong_secret_fails() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    let client_config = make_psk_client_config(
+        &supported_cipher_suites(),
+        vec![make_psk(b"ours", 1)],
+        &[PSKKeyExchangeMode::PSK_DHE_KE],
+    );
+    let server_config = make_psk_server_config(
+        vec![make_psk(b"ours", 2)],
+        &[PSKKeyExchangeMode::PSK_DHE_KE],
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_both_error(&mut client, &mut server),
+        Err(vec![
+            ErrorFromPeer::Server(Error::PeerMisbehavedError(
+                "client sent wrong binder".to_string()
+            )),
+            ErrorFromPeer::Client(Error::AlertReceived(AlertDescription::DecryptError)),
+        ])
+    );
+}
+
+#[test]
+fn external_psk_only_server_needs_a_psk() {
+    let server_config = Arc::new(make_psk_server_config(
+        vec![make_psk(b"ours", 1)],
+        &[PSKKeyExchangeMode::PSK_DHE_KE],
+    ));
+
+    for client_config in [
+        make_client_config(KeyType::RSA),
+        make_client_config_with_versions(KeyType::RSA, &[&rustls::version::TLS12]),
+        make_psk_client_config(
+            &supported_cipher_suites(),
+            vec![make_psk(b"ours", 1)],
+            &[PSKKeyExchangeMode::PSK_KE],
+        ),
+    ] {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::General(
+                "no server certificate chain resolved".to_string()
+            )))
+        );
     }
 }
 
 #[test]
-fn client_checks_server_certificate_with_given_name() {
+fn server_with_chain_and_external_psks_serves_both() {
     for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
+        let mut server_config = make_server_config(*kt);
+        server_config.psk_store = Some(PskMemoryStore::new(vec![make_psk(b"ours", 1)]));
+        let server_config = Arc::new(server_config);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let mut client = ClientConnection::new(
-                Arc::new(client_config),
-                dns_name("not-the-right-hostname.com"),
-            )
-            .unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let client_config = make_client_config(*kt);
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client
+                .peer_certificates()
+                .map(<[_]>::to_vec),
+            Some(kt.get_chain())
+        );
+        assert_eq!(server.external_psk_identity(), None);
 
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::WebPkiError(
-                    WebPkiError::CertNotValidForName,
-                    WebPkiOp::ValidateForDnsName,
-                )))
-            );
-        }
+        let mut client_config = make_client_config(*kt);
+        client_config.psk_store = Some(PskMemoryStore::new(vec![make_psk(b"ours", 1)]));
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.peer_certificates(), None);
+        assert_eq!(client.external_psk_identity(), Some(&b"ours"[..]));
+        assert_eq!(server.external_psk_identity(), Some(&b"ours"[..]));
     }
 }
 
+#[test]
+fn external_psk_sessions_can_be_resumed() {
+    let client_config = Arc::new(make_psk_client_config(
+        &supported_cipher_suites(),
+        vec![make_psk(b"ours", 1)],
+        &[PSKKeyExchangeMode::PSK_DHE_KE],
+    ));
+    let server_config = Arc::new(make_psk_server_config(
+        vec![make_psk(b"ours", 1)],
+        &[PSKKeyExchangeMode::PSK_DHE_KE],
+    ));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    server.set_resumption_data(b"hello");
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.external_psk_identity(), Some(&b"ours"[..]));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.received_resumption_data(), Some(&b"hello"[..]));
+    assert_eq!(client.external_psk_identity(), None);
+    assert_eq!(server.external_psk_identity(), None);
+}
+
 struct ClientCheckCertResolve {
     query_count: AtomicUsize,
     expect_queries: usize,
+    expected_sigschemes: Option<Vec<SignatureScheme>>,
 }
 
 impl ClientCheckCertResolve {
@@ -725,6 +2287,7 @@ impl ClientCheckCertResolve {
         ClientCheckCertResolve {
             query_count: AtomicUsize::new(0),
             expect_queries,
+            expected_sigschemes: None,
         }
     }
 }
@@ -755,6 +2318,10 @@ impl ResolvesClientCert for ClientCheckCertResolve {
             panic!("no signature schemes shared by server");
         }
 
+        if let Some(expected_sigschemes) = &self.expected_sigschemes {
+            assert_eq!(sigschemes, &expected_sigschemes[..]);
+        }
+
         None
     }
 
@@ -2085,122 +3652,424 @@ fn sni_resolver_works() {
 }
 
 #[test]
-fn sni_resolver_rejects_wrong_names() {
-    let kt = KeyType::RSA;
-    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn sni_resolver_rejects_wrong_names() {
+    let kt = KeyType::RSA;
+    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::General(
+            "The server certificate is not valid for the given name".into()
+        )),
+        resolver.add(
+            "not-localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::General("Bad DNS name".into())),
+        resolver.add(
+            "not ascii 🦀",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+}
+
+#[test]
+fn sni_resolver_rejects_bad_certs() {
+    let kt = KeyType::RSA;
+    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Err(Error::General(
+            "No end-entity certificate in certificate chain".into()
+        )),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(vec![], signing_key.clone())
+        )
+    );
+
+    let bad_chain = vec![rustls::Certificate(vec![0xa0])];
+    assert_eq!(
+        Err(Error::General(
+            "End-entity certificate in certificate chain is syntactically invalid".into()
+        )),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(bad_chain, signing_key.clone())
+        )
+    );
+}
+
+fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
+    let mut client_secret = [0u8; 64];
+    let mut server_secret = [0u8; 64];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(
+        Ok(()),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Ok(()),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+
+    assert_eq!(
+        Ok(()),
+        client.export_keying_material(&mut client_secret, b"label", None)
+    );
+    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
+    assert_eq!(
+        Ok(()),
+        server.export_keying_material(&mut server_secret, b"label", None)
+    );
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+}
+
+#[test]
+fn test_tls12_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
+        let server_config = make_server_config(*kt);
+
+        do_exporter_test(client_config, server_config);
+    }
+}
+
+#[test]
+fn test_tls13_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
+        let server_config = make_server_config(*kt);
+
+        do_exporter_test(client_config, server_config);
+    }
+}
+
+/// Sends `data` from `send` to `recv`, checking it arrives intact.
+/// Returns the number of TLS bytes that were needed to carry it.
+fn send_and_check(send: &mut dyn Connection, recv: &mut dyn Connection, data: &[u8]) -> usize {
+    send.writer().write_all(data).unwrap();
+    let sz = transfer(send, recv);
+    recv.process_new_packets().unwrap();
+    check_read(&mut recv.reader(), data);
+    sz
+}
+
+#[test]
+fn refresh_traffic_keys_fails_before_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    assert_eq!(
+        client.refresh_traffic_keys(false),
+        Err(Error::HandshakeNotComplete)
+    );
+    assert_eq!(
+        server.refresh_traffic_keys(true),
+        Err(Error::HandshakeNotComplete)
+    );
+}
+
+#[test]
+fn refresh_traffic_keys_fails_for_tls12() {
+    let client_config = make_client_config_with_versions(KeyType::RSA, &[&rustls::version::TLS12]);
+    let server_config = make_server_config(KeyType::RSA);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert!(matches!(
+        client.refresh_traffic_keys(false),
+        Err(Error::General(_))
+    ));
+    assert!(matches!(
+        server.refresh_traffic_keys(false),
+        Err(Error::General(_))
+    ));
+
+    // the connection is still usable
+    send_and_check(&mut client, &mut server, b"hello");
+    send_and_check(&mut server, &mut client, b"world");
+}
+
+#[test]
+fn refresh_traffic_keys_works_for_tls13() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
+        let server_config = make_server_config(*kt);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        let plain_sz = send_and_check(&mut server, &mut client, b"hello");
+
+        // client updates on its own: the server reads with new keys, but
+        // keeps writing with its existing ones
+        client
+            .refresh_traffic_keys(false)
+            .unwrap();
+        assert!(send_and_check(&mut client, &mut server, b"hello") > plain_sz);
+        assert_eq!(send_and_check(&mut server, &mut client, b"hello"), plain_sz);
+
+        // same again from the server side
+        server
+            .refresh_traffic_keys(false)
+            .unwrap();
+        assert!(send_and_check(&mut server, &mut client, b"hello") > plain_sz);
+        assert_eq!(send_and_check(&mut client, &mut server, b"hello"), plain_sz);
+    }
+}
+
+#[test]
+fn refresh_traffic_keys_can_request_peer_update() {
+    let client_config = make_client_config_with_versions(KeyType::RSA, &[&rustls::version::TLS13]);
+    let server_config = make_server_config(KeyType::RSA);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    let plain_sz = send_and_check(&mut server, &mut client, b"hello");
+
+    // the server answers the request with its own KeyUpdate, sent
+    // ahead of its next data
+    client
+        .refresh_traffic_keys(true)
+        .unwrap();
+    send_and_check(&mut client, &mut server, b"hello");
+    assert!(send_and_check(&mut server, &mut client, b"hello") > plain_sz);
+    assert_eq!(send_and_check(&mut server, &mut client, b"hello"), plain_sz);
+
+    // and the client does likewise
+    server
+        .refresh_traffic_keys(true)
+        .unwrap();
+    send_and_check(&mut server, &mut client, b"hello");
+    assert!(send_and_check(&mut client, &mut server, b"hello") > plain_sz);
+    assert_eq!(send_and_check(&mut client, &mut server, b"hello"), plain_sz);
+}
+
+/// A handshaken TLS1.3 pair using `suite`, where the client sends tiny
+/// records without a buffer limit: so one write can use up a key.
+fn make_pair_for_tls13_suite_with_tiny_records(
+    suite: SupportedCipherSuite,
+) -> (ClientConnection, ServerConnection) {
+    let mut client_config = finish_client_config(
+        KeyType::RSA,
+        config_builder()
+            .with_cipher_suites(&[suite])
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .for_client()
+            .unwrap(),
+    );
+    client_config.max_fragment_size = Some(32);
+    let server_config = finish_server_config(
+        KeyType::RSA,
+        config_builder()
+            .with_cipher_suites(&[suite])
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .for_server()
+            .unwrap(),
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    client.set_buffer_limit(None);
+    (client, server)
+}
+
+#[test]
+fn failed_key_update_reports_data_already_queued() {
+    let (mut client, _server) =
+        make_pair_for_tls13_suite_with_tiny_records(FLAKY_SHORT_LIVED_TLS13_SUITE);
+    FLAKY_AES_128_GCM
+        .fail
+        .store(true, Ordering::SeqCst);
+
+    let data = vec![0x5a; 0x1_2000 * 32];
+    let sent = client.writer().write(&data).unwrap();
+    assert!(sent > 0 && sent < data.len());
+    assert!(client
+        .writer()
+        .write(&data[sent..])
+        .is_err());
+}
 
-    assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
-    assert_eq!(
-        Err(Error::General(
-            "The server certificate is not valid for the given name".into()
-        )),
-        resolver.add(
-            "not-localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
-    assert_eq!(
-        Err(Error::General("Bad DNS name".into())),
-        resolver.add(
-            "not ascii 🦀",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+#[test]
+fn key_update_is_sent_within_a_single_write() {
+    let (mut client, mut server) =
+        make_pair_for_tls13_suite_with_tiny_records(SHORT_LIVED_TLS13_SUITE);
+
+    // one write of more records than a single key may protect
+    let before = COUNTING_AES_128_GCM
+        .keys
+        .load(Ordering::SeqCst);
+    let data = vec![0x5a; 0x1_2000 * 32];
+    client
+        .writer()
+        .write_all(&data)
+        .unwrap();
+    let mut tls = Vec::new();
+    while client.wants_write() {
+        client.write_tls(&mut tls).unwrap();
+    }
+
+    let mut received = Vec::new();
+    for chunk in tls.chunks(4096) {
+        server
+            .read_tls(&mut &chunk[..])
+            .unwrap();
+        server.process_new_packets().unwrap();
+        server
+            .reader()
+            .read_to_end(&mut received)
+            .ok();
+    }
+    assert_eq!(received, data);
+    assert!(
+        COUNTING_AES_128_GCM
+            .keys
+            .load(Ordering::SeqCst)
+            >= before + 2 * 16
     );
 }
 
 #[test]
-fn sni_resolver_rejects_bad_certs() {
-    let kt = KeyType::RSA;
-    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn client_rejects_too_many_key_updates() {
+    let client_config = make_client_config_with_versions(KeyType::RSA, &[&rustls::version::TLS13]);
+    let server_config = make_server_config(KeyType::RSA);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
 
-    assert_eq!(
-        Err(Error::General(
-            "No end-entity certificate in certificate chain".into()
-        )),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(vec![], signing_key.clone())
-        )
-    );
+    for _ in 0..32 {
+        server
+            .refresh_traffic_keys(false)
+            .unwrap();
+    }
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
 
-    let bad_chain = vec![rustls::Certificate(vec![0xa0])];
+    server
+        .refresh_traffic_keys(false)
+        .unwrap();
+    transfer(&mut server, &mut client);
     assert_eq!(
-        Err(Error::General(
-            "End-entity certificate in certificate chain is syntactically invalid".into()
-        )),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(bad_chain, signing_key.clone())
-        )
+        client.process_new_packets().err(),
+        Some(Error::PeerSentTooManyKeyUpdates)
     );
 }
 
-fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
-    let mut client_secret = [0u8; 64];
-    let mut server_secret = [0u8; 64];
-
+#[test]
+fn key_update_limit_is_reset_by_application_data() {
+    let client_config = make_client_config_with_versions(KeyType::RSA, &[&rustls::version::TLS13]);
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config
+        .peer_message_limits
+        .key_updates = 2;
     let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-
-    assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
-    );
-    assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
-    );
     do_handshake(&mut client, &mut server);
 
-    assert_eq!(
-        Ok(()),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
-    );
-    assert_eq!(
-        Ok(()),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
-    );
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+    for _ in 0..2 {
+        client
+            .refresh_traffic_keys(false)
+            .unwrap();
+    }
+    send_and_check(&mut client, &mut server, b"hello");
 
+    for _ in 0..3 {
+        client
+            .refresh_traffic_keys(false)
+            .unwrap();
+    }
+    transfer(&mut client, &mut server);
     assert_eq!(
-        Ok(()),
-        client.export_keying_material(&mut client_secret, b"label", None)
-    );
-    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
-    assert_eq!(
-        Ok(()),
-        server.export_keying_material(&mut server_secret, b"label", None)
+        server.process_new_packets().err(),
+        Some(Error::PeerSentTooManyKeyUpdates)
     );
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 }
 
 #[test]
-fn test_tls12_exporter() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
-        let server_config = make_server_config(*kt);
+fn server_rejects_too_many_warning_alerts() {
+    use rustls::internal::msgs::enums::{AlertDescription, AlertLevel};
+    use rustls::internal::msgs::message::{Message, PlainMessage};
 
-        do_exporter_test(client_config, server_config);
+    let (_, mut server) = make_pair(KeyType::RSA);
+    let warning = PlainMessage::from(Message::build_alert(
+        AlertLevel::Warning,
+        AlertDescription::UserCanceled,
+    ))
+    .into_unencrypted_opaque()
+    .encode();
+
+    for _ in 0..4 {
+        server
+            .read_tls(&mut warning.as_slice())
+            .unwrap();
+        server.process_new_packets().unwrap();
     }
+
+    server
+        .read_tls(&mut warning.as_slice())
+        .unwrap();
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::PeerSentTooManyWarningAlerts)
+    );
 }
 
 #[test]
-fn test_tls13_exporter() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
-        let server_config = make_server_config(*kt);
+fn server_rejects_too_many_empty_records() {
+    use rustls::internal::msgs::base::Payload;
+    use rustls::internal::msgs::enums::ContentType;
+    use rustls::internal::msgs::message::OpaqueMessage;
 
-        do_exporter_test(client_config, server_config);
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config
+        .peer_message_limits
+        .empty_records = 8;
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+
+    let empty = OpaqueMessage {
+        typ: ContentType::Handshake,
+        version: ProtocolVersion::TLSv1_2,
+        payload: Payload::new(Vec::new()),
+    }
+    .encode();
+
+    for _ in 0..8 {
+        server
+            .read_tls(&mut empty.as_slice())
+            .unwrap();
+        server.process_new_packets().unwrap();
     }
+
+    server
+        .read_tls(&mut empty.as_slice())
+        .unwrap();
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::PeerSentTooManyEmptyRecords)
+    );
 }
 
 fn do_suite_test(
@@ -2252,21 +4121,11 @@ fn do_suite_test(
     assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
 }
 
-fn find_suite(suite: CipherSuite) -> SupportedCipherSuite {
-    for scs in ALL_CIPHERSUITES.iter().copied() {
-        if scs.suite() == suite {
-            return scs;
-        }
-    }
-
-    panic!("find_suite given unsupported suite");
-}
-
-static TEST_CIPHERSUITES: [(
+static TEST_CIPHERSUITES: &[(
     &'static rustls::SupportedProtocolVersion,
     KeyType,
     CipherSuite,
-); 9] = [
+)] = &[
     (
         &rustls::version::TLS13,
         KeyType::RSA,
@@ -2312,6 +4171,48 @@ static TEST_CIPHERSUITES: [(
         KeyType::RSA,
         CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
     ),
+    (
+        &rustls::version::TLS13,
+        KeyType::RSA,
+        CipherSuite::TLS13_AES_128_CCM_SHA256,
+    ),
+    (
+        &rustls::version::TLS13,
+        KeyType::RSA,
+        CipherSuite::TLS13_AES_128_CCM_8_SHA256,
+    ),
+    (
+        &rustls::version::TLS12,
+        KeyType::ECDSA,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM,
+    ),
+    (
+        &rustls::version::TLS12,
+        KeyType::ECDSA,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM,
+    ),
+    (
+        &rustls::version::TLS12,
+        KeyType::ECDSA,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8,
+    ),
+    (
+        &rustls::version::TLS12,
+        KeyType::ECDSA,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8,
+    ),
+    #[cfg(feature = "ffdhe")]
+    (
+        &rustls::version::TLS12,
+        KeyType::RSA,
+        CipherSuite::TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+    ),
+    #[cfg(feature = "ffdhe")]
+    (
+        &rustls::version::TLS12,
+        KeyType::RSA,
+        CipherSuite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
+    ),
 ];
 
 #[test]
@@ -2331,11 +4232,63 @@ fn all_suites_covered() {
     assert_eq!(ALL_CIPHERSUITES.len(), TEST_CIPHERSUITES.len());
 }
 
+#[cfg(feature = "aes-ccm")]
+#[test]
+fn ccm_suites_are_not_default() {
+    // the DHE suites are left out of the defaults too
+    for suite in ALL_CIPHERSUITES {
+        let name = format!("{:?}", suite.suite());
+        let is_default = !name.contains("_CCM") && !name.contains("_DHE_");
+        assert_eq!(rustls::DEFAULT_CIPHERSUITES.contains(suite), is_default);
+    }
+
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config(KeyType::ECDSA),
+        finish_server_config(
+            KeyType::ECDSA,
+            config_builder()
+                .with_cipher_suites(&[
+                    rustls::cipher_suite::TLS13_AES_128_CCM_SHA256,
+                    rustls::cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM,
+                ])
+                .with_safe_default_kx_groups()
+                .with_safe_default_protocol_versions()
+                .for_server()
+                .unwrap(),
+        ),
+    );
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatibleError(
+            "no ciphersuites in common".into()
+        )))
+    );
+}
+
+#[cfg(not(feature = "aes-ccm"))]
+#[test]
+fn ccm_suites_need_aes_ccm_feature() {
+    assert_eq!(
+        config_builder()
+            .with_cipher_suites(&[rustls::cipher_suite::TLS13_AES_128_CCM_SHA256])
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .for_server()
+            .err(),
+        Some(Error::General(
+            "TLS13_AES_128_CCM_SHA256 not supported by the crypto provider".into()
+        ))
+    );
+}
+
 #[test]
 fn negotiated_ciphersuite_client() {
     for item in TEST_CIPHERSUITES.iter() {
         let (version, kt, suite) = *item;
         let scs = find_suite(suite);
+        if !scs.supported_by(&RING) {
+            continue;
+        }
         let client_config = finish_client_config(
             kt,
             rustls::config_builder()
@@ -2346,27 +4299,234 @@ fn negotiated_ciphersuite_client() {
                 .unwrap(),
         );
 
-        do_suite_test(client_config, make_server_config(kt), scs, version.version);
+        do_suite_test(
+            client_config,
+            make_server_config_with_suites(kt, &supported_cipher_suites()),
+            scs,
+            version.version,
+        );
+    }
+}
+
+#[test]
+fn negotiated_ciphersuite_server() {
+    for item in TEST_CIPHERSUITES.iter() {
+        let (version, kt, suite) = *item;
+        let scs = find_suite(suite);
+        if !scs.supported_by(&RING) {
+            continue;
+        }
+        let server_config = finish_server_config(
+            kt,
+            rustls::config_builder()
+                .with_cipher_suites(&[scs])
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[version])
+                .for_server()
+                .unwrap(),
+        );
+
+        do_suite_test(
+            make_client_config_with_suites(kt, &supported_cipher_suites()),
+            server_config,
+            scs,
+            version.version,
+        );
+    }
+}
+
+/// Checks that the secrets one peer sends with are those the other
+/// receives with.
+fn assert_secrets_agree(
+    version: &'static rustls::SupportedProtocolVersion,
+    tx: &(u64, rustls::ConnectionTrafficSecrets),
+    rx: &(u64, rustls::ConnectionTrafficSecrets),
+) {
+    use rustls::ConnectionTrafficSecrets::*;
+
+    assert_eq!(tx.0, rx.0);
+    match (&tx.1, &rx.1) {
+        (
+            Aes128Gcm { key, salt, iv },
+            Aes128Gcm {
+                key: rx_key,
+                salt: rx_salt,
+                iv: rx_iv,
+            },
+        ) => {
+            assert_eq!((key, salt), (rx_key, rx_salt));
+            assert!(version == &rustls::version::TLS12 || iv == rx_iv);
+        }
+        (
+            Aes256Gcm { key, salt, iv },
+            Aes256Gcm {
+                key: rx_key,
+                salt: rx_salt,
+                iv: rx_iv,
+            },
+        ) => {
+            assert_eq!((key, salt), (rx_key, rx_salt));
+            assert!(version == &rustls::version::TLS12 || iv == rx_iv);
+        }
+        (
+            Chacha20Poly1305 { key, iv },
+            Chacha20Poly1305 {
+                key: rx_key,
+                iv: rx_iv,
+            },
+        ) => {
+            assert_eq!((key, iv), (rx_key, rx_iv));
+        }
+        (
+            Aes128Ccm { key, salt, iv },
+            Aes128Ccm {
+                key: rx_key,
+                salt: rx_salt,
+                iv: rx_iv,
+            },
+        ) => {
+            assert_eq!((key, salt), (rx_key, rx_salt));
+            assert!(version == &rustls::version::TLS12 || iv == rx_iv);
+        }
+        (
+            Aes256Ccm { key, salt, iv },
+            Aes256Ccm {
+                key: rx_key,
+                salt: rx_salt,
+                iv: rx_iv,
+            },
+        ) => {
+            assert_eq!((key, salt), (rx_key, rx_salt));
+            assert!(version == &rustls::version::TLS12 || iv == rx_iv);
+        }
+        (
+            Aes128Ccm8 { key, salt, iv },
+            Aes128Ccm8 {
+                key: rx_key,
+                salt: rx_salt,
+                iv: rx_iv,
+            },
+        ) => {
+            assert_eq!((key, salt), (rx_key, rx_salt));
+            assert!(version == &rustls::version::TLS12 || iv == rx_iv);
+        }
+        (
+            Aes256Ccm8 { key, salt, iv },
+            Aes256Ccm8 {
+                key: rx_key,
+                salt: rx_salt,
+                iv: rx_iv,
+            },
+        ) => {
+            assert_eq!((key, salt), (rx_key, rx_salt));
+            assert!(version == &rustls::version::TLS12 || iv == rx_iv);
+        }
+        _ => panic!("peers extracted secrets for different algorithms"),
+    }
+}
+
+#[test]
+fn extract_secrets_fails_before_handshake() {
+    let (client, server) = make_pair(KeyType::RSA);
+    assert!(matches!(
+        client.extract_secrets(),
+        Err(Error::HandshakeNotComplete)
+    ));
+    assert!(matches!(
+        server.extract_secrets(),
+        Err(Error::HandshakeNotComplete)
+    ));
+}
+
+#[test]
+fn extract_secrets_agree_between_peers() {
+    for item in TEST_CIPHERSUITES.iter() {
+        let (version, kt, suite) = *item;
+        if !find_suite(suite).supported_by(&RING) {
+            continue;
+        }
+        let (mut client, mut server) = make_pair_for_suite(version, kt, suite);
+        do_handshake(&mut client, &mut server);
+
+        send_and_check(&mut client, &mut server, b"hello");
+        send_and_check(&mut server, &mut client, b"world");
+        send_and_check(&mut server, &mut client, b"again");
+
+        let client = client.extract_secrets().unwrap();
+        let server = server.extract_secrets().unwrap();
+        assert_secrets_agree(version, &client.tx, &server.rx);
+        assert_secrets_agree(version, &server.tx, &client.rx);
     }
 }
 
 #[test]
-fn negotiated_ciphersuite_server() {
-    for item in TEST_CIPHERSUITES.iter() {
-        let (version, kt, suite) = *item;
-        let scs = find_suite(suite);
-        let server_config = finish_server_config(
-            kt,
-            rustls::config_builder()
-                .with_cipher_suites(&[scs])
-                .with_safe_default_kx_groups()
-                .with_protocol_versions(&[version])
-                .for_server()
-                .unwrap(),
-        );
+fn extracted_tls13_secrets_decrypt_records() {
+    let (mut client, mut server) = make_pair_for_suite(
+        &rustls::version::TLS13,
+        KeyType::RSA,
+        CipherSuite::TLS13_AES_128_GCM_SHA256,
+    );
+    do_handshake(&mut client, &mut server);
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut record = Vec::new();
+    client.write_tls(&mut record).unwrap();
 
-        do_suite_test(make_client_config(kt), server_config, scs, version.version);
+    let (seq, secrets) = client.extract_secrets().unwrap().tx;
+    let (key, salt, iv) = match secrets {
+        rustls::ConnectionTrafficSecrets::Aes128Gcm { key, salt, iv } => (key, salt, iv),
+        _ => panic!("unexpected algorithm"),
+    };
+
+    let mut nonce = [0u8; 12];
+    nonce[..4].copy_from_slice(&salt);
+    nonce[4..].copy_from_slice(&iv);
+    for (n, s) in nonce[4..]
+        .iter_mut()
+        .zip((seq - 1).to_be_bytes().iter())
+    {
+        *n ^= s;
     }
+
+    let key = ring::aead::LessSafeKey::new(
+        ring::aead::UnboundKey::new(&ring::aead::AES_128_GCM, &key).unwrap(),
+    );
+    let (header, payload) = record.split_at_mut(5);
+    let plain = key
+        .open_in_place(
+            ring::aead::Nonce::assume_unique_for_key(nonce),
+            ring::aead::Aad::from(&header[..]),
+            payload,
+        )
+        .unwrap();
+    assert_eq!(plain, b"hello\x17");
+}
+
+#[test]
+fn extract_secrets_requires_flushed_buffers() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    do_handshake(&mut client, &mut server);
+
+    // written, but not yet sent
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    assert!(matches!(client.extract_secrets(), Err(Error::General(_))));
+
+    // received, but not yet read
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    do_handshake(&mut client, &mut server);
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(matches!(server.extract_secrets(), Err(Error::General(_))));
 }
 
 #[derive(Debug, PartialEq)]
@@ -2950,6 +5110,295 @@ fn early_data_is_available_on_resumption() {
     assert_eq!(err, Err(ErrorFromPeer::Server(Error::DecryptError)));
 }
 
+#[test]
+fn early_data_can_be_read_by_server() {
+    let (client_config, server_config) = make_early_data_configs(128);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(server.early_data().is_none());
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .bytes_left(),
+        128
+    );
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    let mut buf = [0u8; 5];
+    assert_eq!(
+        server
+            .early_data()
+            .unwrap()
+            .read(&mut buf)
+            .unwrap(),
+        5
+    );
+    assert_eq!(&buf, b"hello");
+
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+    assert!(!server.is_handshaking());
+    assert_eq!(
+        server
+            .reader()
+            .read(&mut buf)
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::WouldBlock
+    );
+}
+
+#[test]
+fn early_data_over_limit_is_refused_by_server() {
+    let (client_config, server_config) = make_early_data_configs(128);
+    let storage = server_config.session_storage.clone();
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    // The client was told 128 bytes are fine, but the server now only wants 4.
+    let mut server_config = (*server_config).clone();
+    server_config.max_early_data_size = 4;
+    server_config.session_storage = storage;
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Server(Error::PeerMisbehavedError(
+            "too much early_data received".into()
+        )))
+    );
+}
+
+#[test]
+fn early_data_rejected_by_server_is_skipped() {
+    let (client_config, server_config) = make_early_data_configs(128);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    server.reject_early_data();
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    assert!(!client.is_early_data_accepted());
+    assert!(server.early_data().is_none());
+    assert!(!server.is_handshaking());
+}
+
+#[test]
+fn early_data_not_offered_if_alpn_no_longer_configured() {
+    let (client_config, server_config) = make_early_data_configs_with_alpn(&[b"h2"], &[b"h2"]);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config.clone()), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+
+    let mut client_config = client_config;
+    client_config.alpn_protocols = vec![b"h3".to_vec()];
+    let (mut client, _) = make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    assert!(client.early_data().is_none());
+}
+
+#[test]
+fn early_data_rejected_if_server_changes_alpn() {
+    let (client_config, server_config) =
+        make_early_data_configs_with_alpn(&[b"h2", b"h3"], &[b"h2"]);
+    let client_config = Arc::new(client_config);
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config.clone()));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+
+    // the server now prefers another protocol, sharing the same sessions
+    let mut server_config = server_config;
+    server_config.alpn_protocols = vec![b"h3".to_vec(), b"h2".to_vec()];
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config));
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    assert!(!client.is_early_data_accepted());
+    assert!(server.early_data().is_none());
+    assert_eq!(client.alpn_protocol(), Some(&b"h3"[..]));
+    assert_eq!(server.alpn_protocol(), Some(&b"h3"[..]));
+}
+
+#[test]
+fn client_rejects_early_data_accepted_with_different_alpn() {
+    let (mut client_config, server_config) =
+        make_early_data_configs_with_alpn(&[b"h2", b"h3"], &[b"h2"]);
+    let storage = Arc::new(ClientStorage::new());
+    client_config.session_storage = storage.clone();
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    // make the client believe the session used another protocol
+    let session_key = storage
+        .last_put_key
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap();
+    let session_value_bytes = storage
+        .storage
+        .get(&session_key)
+        .unwrap();
+    let mut session_value = ClientSessionValue::read_bytes(&session_value_bytes).unwrap();
+    assert_eq!(
+        session_value
+            .alpn
+            .as_ref()
+            .map(|p| &p.0[..]),
+        Some(&b"h2"[..])
+    );
+    session_value.alpn = Some(PayloadU8::new(b"h3".to_vec()));
+    storage
+        .storage
+        .put(session_key, session_value.get_encoding());
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerMisbehavedError(
+            "server accepted early data with different ALPN".into()
+        )))
+    );
+}
+
+#[test]
+fn early_exporter_agrees_between_peers() {
+    let (client_config, server_config) = make_early_data_configs(128);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let mut buf = [0u8; 32];
+    assert!(client
+        .export_early_keying_material(&mut buf, b"label", None)
+        .is_err());
+    assert!(server
+        .export_early_keying_material(&mut buf, b"label", None)
+        .is_err());
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let mut client_early = [0u8; 32];
+    client
+        .export_early_keying_material(&mut client_early, b"label", Some(b"context"))
+        .unwrap();
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    let mut server_early = [0u8; 32];
+    server
+        .export_early_keying_material(&mut server_early, b"label", Some(b"context"))
+        .unwrap();
+    assert_eq!(client_early, server_early);
+
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
+    client
+        .export_early_keying_material(&mut buf, b"label", Some(b"context"))
+        .unwrap();
+    assert_eq!(buf, client_early);
+
+    // the early exporter is distinct from the usual one
+    client
+        .export_keying_material(&mut buf, b"label", Some(b"context"))
+        .unwrap();
+    assert_ne!(buf, client_early);
+}
+
+#[test]
+fn early_exporter_unavailable_if_early_data_rejected() {
+    let (client_config, server_config) = make_early_data_configs(128);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    server.reject_early_data();
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    let mut buf = [0u8; 32];
+    assert!(client
+        .export_early_keying_material(&mut buf, b"label", None)
+        .is_ok());
+
+    do_handshake(&mut client, &mut server);
+    assert!(client
+        .export_early_keying_material(&mut buf, b"label", None)
+        .is_err());
+    assert!(server
+        .export_early_keying_material(&mut buf, b"label", None)
+        .is_err());
+}
+
+#[test]
+fn early_data_not_offered_with_stateless_tickets() {
+    let (client_config, mut server_config) = make_early_data_configs(128);
+    server_config.ticketer = rustls::Ticketer::new().unwrap();
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.early_data().is_none());
+    do_handshake(&mut client, &mut server);
+    assert!(server.early_data().is_none());
+}
+
 #[cfg(feature = "quic")]
 mod test_quic {
     use super::*;
@@ -3134,19 +5583,68 @@ mod test_quic {
         )
         .unwrap();
 
-        let mut server =
-            ServerConnection::new_quic(server_config, quic::Version::V1, server_params.into())
-                .unwrap();
+        let mut server =
+            ServerConnection::new_quic(server_config, quic::Version::V1, server_params.into())
+                .unwrap();
+
+        step(&mut client, &mut server).unwrap();
+        step(&mut server, &mut client)
+            .unwrap()
+            .unwrap();
+        assert!(step(&mut server, &mut client).is_err());
+        assert_eq!(
+            client.alert(),
+            Some(rustls::internal::msgs::enums::AlertDescription::BadCertificate)
+        );
+    }
+
+    #[test]
+    fn test_quic_zero_rtt_with_stateless_tickets() {
+        let kt = KeyType::RSA;
+        let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+        client_config.enable_early_data = true;
+        let client_config = Arc::new(client_config);
+        let mut server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+        server_config.max_early_data_size = 0xffffffff;
+        server_config.ticketer = rustls::Ticketer::new().unwrap();
+        let server_config = Arc::new(server_config);
+        let client_params = &b"client params"[..];
+        let server_params = &b"server params"[..];
+
+        let connect = || {
+            let client = ClientConnection::new_quic(
+                Arc::clone(&client_config),
+                quic::Version::V1,
+                dns_name("localhost"),
+                client_params.into(),
+            )
+            .unwrap();
+            let server = ServerConnection::new_quic(
+                Arc::clone(&server_config),
+                quic::Version::V1,
+                server_params.into(),
+            )
+            .unwrap();
+            (client, server)
+        };
+
+        let (mut client, mut server) = connect();
+        while client.is_handshaking() || server.is_handshaking() {
+            step(&mut client, &mut server).unwrap();
+            step(&mut server, &mut client).unwrap();
+        }
+        // receive the ticket
+        step(&mut server, &mut client).unwrap();
 
+        let (mut client, mut server) = connect();
         step(&mut client, &mut server).unwrap();
-        step(&mut server, &mut client)
-            .unwrap()
-            .unwrap();
-        assert!(step(&mut server, &mut client).is_err());
-        assert_eq!(
-            client.alert(),
-            Some(rustls::internal::msgs::enums::AlertDescription::BadCertificate)
-        );
+        assert!(client.zero_rtt_keys().is_some());
+        assert!(server.zero_rtt_keys().is_some());
+        while client.is_handshaking() || server.is_handshaking() {
+            step(&mut server, &mut client).unwrap();
+            step(&mut client, &mut server).unwrap();
+        }
+        assert!(client.is_early_data_accepted());
     }
 
     #[test]
@@ -3220,6 +5718,66 @@ mod test_quic {
         .is_err());
     }
 
+    #[test]
+    fn test_quic_rejects_user_defined_suites() {
+        let client_config = finish_client_config(
+            KeyType::ED25519,
+            custom_suite_config_builder()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[&rustls::version::TLS13])
+                .for_client()
+                .unwrap(),
+        );
+        assert_eq!(
+            ClientConnection::new_quic(
+                Arc::new(client_config),
+                quic::Version::V1,
+                dns_name("localhost"),
+                b"client params".to_vec(),
+            )
+            .err(),
+            Some(Error::General(
+                "Unknown(65281) cannot be used with QUIC".into()
+            ))
+        );
+
+        let server_config = finish_server_config(
+            KeyType::ED25519,
+            custom_suite_config_builder()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[&rustls::version::TLS13])
+                .for_server()
+                .unwrap(),
+        );
+        assert!(ServerConnection::new_quic(
+            Arc::new(server_config),
+            quic::Version::V1,
+            b"server params".to_vec(),
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "aes-ccm")]
+    #[test]
+    fn test_quic_rejects_ccm_suites() {
+        let client_config = make_client_config_with_suites(
+            KeyType::ED25519,
+            &[rustls::cipher_suite::TLS13_AES_128_CCM_SHA256],
+        );
+        assert_eq!(
+            ClientConnection::new_quic(
+                Arc::new(client_config),
+                quic::Version::V1,
+                dns_name("localhost"),
+                b"client params".to_vec(),
+            )
+            .err(),
+            Some(Error::General(
+                "TLS13_AES_128_CCM_SHA256 cannot be used with QUIC".into()
+            ))
+        );
+    }
+
     #[test]
     fn test_quic_invalid_early_data_size() {
         let mut server_config =
@@ -3286,7 +5844,7 @@ mod test_quic {
                 payload: HandshakePayload::ClientHello(ClientHelloPayload {
                     client_version: ProtocolVersion::TLSv1_3,
                     random,
-                    session_id: SessionID::random().unwrap(),
+                    session_id: SessionID::random(&rustls::crypto::ring::RING).unwrap(),
                     cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
                     compression_methods: vec![Compression::Null],
                     extensions: vec![
@@ -3354,7 +5912,7 @@ mod test_quic {
                 payload: HandshakePayload::ClientHello(ClientHelloPayload {
                     client_version: ProtocolVersion::TLSv1_2,
                     random: random.clone(),
-                    session_id: SessionID::random().unwrap(),
+                    session_id: SessionID::random(&rustls::crypto::ring::RING).unwrap(),
                     cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
                     compression_methods: vec![Compression::Null],
                     extensions: vec![
@@ -3369,90 +5927,427 @@ mod test_quic {
             }),
         };
 
-        let buf = PlainMessage::from(client_hello)
-            .into_unencrypted_opaque()
-            .encode();
-        server
-            .read_tls(&mut buf.as_slice())
+        let buf = PlainMessage::from(client_hello)
+            .into_unencrypted_opaque()
+            .encode();
+        server
+            .read_tls(&mut buf.as_slice())
+            .unwrap();
+        assert_eq!(
+            server.process_new_packets().err(),
+            Some(Error::PeerIncompatibleError(
+                "Server requires TLS1.3, but client omitted versions ext".into(),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_quic_exporter() {
+        for &kt in ALL_KEY_TYPES.iter() {
+            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+            let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+
+            do_exporter_test(client_config, server_config);
+        }
+    }
+
+    #[test]
+    fn test_quic_refresh_traffic_keys_fails() {
+        let kt = KeyType::RSA;
+        let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+        let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+
+        let mut client = ClientConnection::new_quic(
+            Arc::new(client_config),
+            quic::Version::V1,
+            dns_name("localhost"),
+            b"client params".to_vec(),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new_quic(
+            Arc::new(server_config),
+            quic::Version::V1,
+            b"server params".to_vec(),
+        )
+        .unwrap();
+
+        step(&mut client, &mut server).unwrap();
+        step(&mut server, &mut client).unwrap();
+        step(&mut client, &mut server).unwrap();
+        step(&mut server, &mut client).unwrap();
+        step(&mut client, &mut server).unwrap();
+        assert!(!client.is_handshaking());
+        assert!(!server.is_handshaking());
+
+        assert!(matches!(
+            client.refresh_traffic_keys(false),
+            Err(Error::General(_))
+        ));
+        assert!(matches!(
+            server.refresh_traffic_keys(true),
+            Err(Error::General(_))
+        ));
+    }
+} // mod test_quic
+
+#[test]
+fn test_client_does_not_offer_sha1() {
+    use rustls::internal::msgs::{
+        codec::Reader, enums::HandshakeType, handshake::HandshakePayload, message::MessagePayload,
+        message::OpaqueMessage,
+    };
+
+    for kt in ALL_KEY_TYPES.iter() {
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
+
+            assert!(client.wants_write());
+            let mut buf = [0u8; 262144];
+            let sz = client
+                .write_tls(&mut buf.as_mut())
+                .unwrap();
+            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+            let msg = Message::try_from(msg.into_plain_message()).unwrap();
+            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+
+            let client_hello = match msg.payload {
+                MessagePayload::Handshake(hs) => match hs.payload {
+                    HandshakePayload::ClientHello(ch) => ch,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+
+            let sigalgs = client_hello
+                .get_sigalgs_extension()
+                .unwrap();
+            assert_eq!(
+                sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
+                false,
+                "sha1 unexpectedly offered"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_client_config_keyshare() {
+    let client_config =
+        make_client_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP384R1]);
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP384R1]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+}
+
+#[test]
+fn test_client_config_keyshare_mismatch() {
+    let client_config =
+        make_client_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP384R1]);
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::X25519]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+}
+
+#[cfg(feature = "secp521r1")]
+#[test]
+fn test_client_config_keyshare_secp521r1() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = finish_client_config(
+            KeyType::RSA,
+            config_builder()
+                .with_safe_default_cipher_suites()
+                .with_kx_groups(&[&rustls::kx_group::SECP521R1])
+                .with_protocol_versions(&[version])
+                .for_client()
+                .unwrap(),
+        );
+        let server_config =
+            make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP521R1]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server).unwrap();
+        send_and_check(&mut client, &mut server, b"hello");
+    }
+}
+
+#[cfg(feature = "curve448")]
+#[test]
+fn test_client_config_keyshare_x448() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = finish_client_config(
+            KeyType::RSA,
+            config_builder()
+                .with_safe_default_cipher_suites()
+                .with_kx_groups(&[&rustls::kx_group::X448])
+                .with_protocol_versions(&[version])
+                .for_client()
+                .unwrap(),
+        );
+        let server_config =
+            make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::X448]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server).unwrap();
+        send_and_check(&mut client, &mut server, b"hello");
+    }
+}
+
+#[cfg(feature = "mlkem")]
+#[test]
+fn test_client_config_keyshare_x25519mlkem768() {
+    let client_config =
+        make_client_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::X25519MLKEM768]);
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::X25519MLKEM768]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    send_and_check(&mut client, &mut server, b"hello");
+}
+
+#[cfg(feature = "mlkem")]
+#[test]
+fn test_client_retries_with_x25519mlkem768_share() {
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::RSA,
+        &[&rustls::kx_group::X25519, &rustls::kx_group::X25519MLKEM768],
+    );
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::X25519MLKEM768]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    // client sends hello with an x25519 share only
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert!(wrlen < 1000);
+    }
+
+    // server sends HRR
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert!(wrlen < 100);
+    }
+
+    // client retries with a (much larger) hybrid share
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert!(wrlen > 1216);
+    }
+
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+    send_and_check(&mut client, &mut server, b"hello");
+}
+
+#[cfg(feature = "mlkem")]
+#[test]
+fn test_x25519mlkem768_is_not_used_for_tls12() {
+    // a TLS1.2 server skips the hybrid group in favour of one it can use
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::RSA,
+        &[&rustls::kx_group::X25519MLKEM768, &rustls::kx_group::X25519],
+    );
+    let server_config = make_server_config_with_versions(KeyType::RSA, &[&rustls::version::TLS12]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_2));
+
+    // and fails if that is all the client offers
+    let client_config = finish_client_config(
+        KeyType::RSA,
+        config_builder()
+            .with_safe_default_cipher_suites()
+            .with_kx_groups(&[&rustls::kx_group::X25519MLKEM768])
+            .with_protocol_versions(&[&rustls::version::TLS12])
+            .for_client()
+            .unwrap(),
+    );
+    let server_config = make_server_config(KeyType::RSA);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatibleError(
+            "no supported group".into()
+        )))
+    );
+}
+
+#[cfg(feature = "ffdhe")]
+#[test]
+fn test_client_config_keyshare_ffdhe() {
+    for group in &[
+        &rustls::kx_group::FFDHE2048,
+        &rustls::kx_group::FFDHE3072,
+        &rustls::kx_group::FFDHE4096,
+    ] {
+        let client_config = make_client_config_with_kx_groups(KeyType::RSA, &[group]);
+        let server_config = make_server_config_with_kx_groups(KeyType::RSA, &[group]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server).unwrap();
+        assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+        send_and_check(&mut client, &mut server, b"hello");
+    }
+}
+
+#[cfg(feature = "ffdhe")]
+#[test]
+fn test_tls12_dhe_uses_group_named_by_client() {
+    // the client can only complete the handshake in ffdhe3072
+    let client_config = make_tls12_dhe_client_config(
+        &[rustls::cipher_suite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256],
+        &[&rustls::kx_group::X25519, &rustls::kx_group::FFDHE3072],
+    );
+    let server_config = make_dhe_server_config(&[&rustls::kx_group::FFDHE2048, &rustls::kx_group::FFDHE3072]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+    assert_eq!(
+        client.negotiated_cipher_suite(),
+        Some(rustls::cipher_suite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256)
+    );
+    send_and_check(&mut client, &mut server, b"hello");
+}
+
+#[cfg(feature = "ffdhe")]
+#[test]
+fn test_tls12_dhe_is_not_chosen_without_common_group() {
+    // the client prefers DHE, but names only ffdhe4096
+    let client_config = make_tls12_dhe_client_config(
+        &[
+            rustls::cipher_suite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
+            rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        ],
+        &[&rustls::kx_group::X25519, &rustls::kx_group::FFDHE4096],
+    );
+    let server_config = make_dhe_server_config(&[&rustls::kx_group::X25519, &rustls::kx_group::FFDHE2048]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+    assert_eq!(
+        client.negotiated_cipher_suite(),
+        Some(rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256)
+    );
+}
+
+/// Load a certificate chain from one of the extra test-ca files
+/// (eg. "ecdsa", "end.p521.fullchain") that have no `KeyType`.
+#[cfg(any(feature = "secp521r1", feature = "curve448"))]
+fn load_chain(keytype: &str, path: &str) -> Vec<rustls::Certificate> {
+    rustls_pemfile::certs(&mut io::BufReader::new(bytes_for(keytype, path)))
+        .unwrap()
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect()
+}
+
+/// Like `load_chain`, but for a PKCS#8 private key.
+#[cfg(any(feature = "secp521r1", feature = "curve448"))]
+fn load_key(keytype: &str, path: &str) -> rustls::PrivateKey {
+    let keys =
+        rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(bytes_for(keytype, path)))
             .unwrap();
-        assert_eq!(
-            server.process_new_packets().err(),
-            Some(Error::PeerIncompatibleError(
-                "Server requires TLS1.3, but client omitted versions ext".into(),
-            )),
+    rustls::PrivateKey(keys[0].clone())
+}
+
+/// Do a handshake in each protocol version, where the server and
+/// (optionally) the client authenticate with the given keys.
+#[cfg(any(feature = "secp521r1", feature = "curve448"))]
+fn check_handshake_with_extra_keys(
+    keytype: &str,
+    server_files: (&str, &str),
+    client_files: Option<(&str, &str)>,
+) {
+    for version in rustls::ALL_VERSIONS {
+        let mut root_store = rustls::RootCertStore::empty();
+        root_store.add_parsable_certificates(
+            &rustls_pemfile::certs(&mut io::BufReader::new(bytes_for(keytype, "ca.cert"))).unwrap(),
         );
-    }
+        let client_builder = config_builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[version])
+            .for_client()
+            .unwrap()
+            .with_root_certificates(root_store.clone(), &[]);
+        let client_config = match client_files {
+            Some((chain, key)) => client_builder
+                .with_single_cert(load_chain(keytype, chain), load_key(keytype, key))
+                .unwrap(),
+            None => client_builder.with_no_client_auth(),
+        };
 
-    #[test]
-    fn test_quic_exporter() {
-        for &kt in ALL_KEY_TYPES.iter() {
-            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-            let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+        let server_builder = rustls::server_config_builder_with_safe_defaults();
+        let server_builder = match client_files {
+            Some(_) => server_builder
+                .with_client_cert_verifier(rustls::AllowAnyAuthenticatedClient::new(root_store)),
+            None => server_builder.with_no_client_auth(),
+        };
+        let server_config = server_builder
+            .with_single_cert(
+                load_chain(keytype, server_files.0),
+                load_key(keytype, server_files.1),
+            )
+            .unwrap();
 
-            do_exporter_test(client_config, server_config);
-        }
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server).unwrap();
+        assert_eq!(server.peer_certificates().is_some(), client_files.is_some());
+        send_and_check(&mut client, &mut server, b"hello");
     }
-} // mod test_quic
+}
 
+#[cfg(feature = "secp521r1")]
 #[test]
-fn test_client_does_not_offer_sha1() {
-    use rustls::internal::msgs::{
-        codec::Reader, enums::HandshakeType, handshake::HandshakePayload, message::MessagePayload,
-        message::OpaqueMessage,
-    };
-
-    for kt in ALL_KEY_TYPES.iter() {
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
-
-            assert!(client.wants_write());
-            let mut buf = [0u8; 262144];
-            let sz = client
-                .write_tls(&mut buf.as_mut())
-                .unwrap();
-            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
-            let msg = Message::try_from(msg.into_plain_message()).unwrap();
-            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+fn server_signs_with_ecdsa_p521() {
+    check_handshake_with_extra_keys("ecdsa", ("end.p521.fullchain", "end.p521.key"), None);
+}
 
-            let client_hello = match msg.payload {
-                MessagePayload::Handshake(hs) => match hs.payload {
-                    HandshakePayload::ClientHello(ch) => ch,
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
-            };
+#[cfg(feature = "secp521r1")]
+#[test]
+fn client_signs_with_ecdsa_p521() {
+    check_handshake_with_extra_keys(
+        "ecdsa",
+        ("end.fullchain", "end.key"),
+        Some(("client.p521.fullchain", "client.p521.key")),
+    );
+}
 
-            let sigalgs = client_hello
-                .get_sigalgs_extension()
-                .unwrap();
-            assert_eq!(
-                sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
-                false,
-                "sha1 unexpectedly offered"
-            );
-        }
-    }
+#[cfg(feature = "curve448")]
+#[test]
+fn server_signs_with_ed448() {
+    check_handshake_with_extra_keys("eddsa", ("end.ed448.fullchain", "end.ed448.key"), None);
 }
 
+#[cfg(feature = "curve448")]
 #[test]
-fn test_client_config_keyshare() {
-    let client_config =
-        make_client_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP384R1]);
-    let server_config =
-        make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP384R1]);
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-    do_handshake_until_error(&mut client, &mut server).unwrap();
+fn client_signs_with_ed448() {
+    check_handshake_with_extra_keys(
+        "eddsa",
+        ("end.fullchain", "end.key"),
+        Some(("client.ed448.fullchain", "client.ed448.key")),
+    );
 }
 
+#[cfg(feature = "curve448")]
 #[test]
-fn test_client_config_keyshare_mismatch() {
-    let client_config =
-        make_client_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP384R1]);
-    let server_config =
-        make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::X25519]);
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+fn client_rejects_bad_ed448_signature() {
+    let server_config = rustls::server_config_builder_with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            load_chain("eddsa", "end.ed448.fullchain"),
+            load_key("eddsa", "client.ed448.key"),
+        )
+        .unwrap();
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::ED25519), server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::WebPkiError(
+            WebPkiError::InvalidSignatureForPublicKey,
+            WebPkiOp::VerifySignature,
+        )))
+    );
 }
 
 #[test]
@@ -3672,8 +6567,8 @@ fn connection_types_are_not_huge() {
 }
 
 use rustls::internal::msgs::{
-    handshake::ClientExtension, handshake::HandshakePayload, message::Message,
-    message::MessagePayload,
+    handshake::ClientExtension, handshake::ClientHelloPayload, handshake::HandshakePayload,
+    message::Message, message::MessagePayload,
 };
 
 #[test]
@@ -3750,3 +6645,382 @@ fn test_server_rejects_clients_without_any_kx_group_overlap() {
         ))
     );
 }
+
+fn offer_fallback_scsv(msg: &mut Message) {
+    if let MessagePayload::Handshake(hs) = &mut msg.payload {
+        if let HandshakePayload::ClientHello(ch) = &mut hs.payload {
+            ch.cipher_suites
+                .push(CipherSuite::TLS_FALLBACK_SCSV);
+        }
+    }
+}
+
+#[test]
+fn test_server_rejects_inappropriate_fallback() {
+    use rustls::internal::msgs::enums::AlertDescription;
+
+    let client_config = make_client_config_with_versions(KeyType::RSA, &[&rustls::version::TLS12]);
+    let server_config = make_server_config(KeyType::RSA);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    transfer_altered(&mut client, offer_fallback_scsv, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerIncompatibleError(
+            "client fell back to TLS1.2, but TLS1.3 is available".into()
+        ))
+    );
+
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(
+            AlertDescription::InappropriateFallback
+        ))
+    );
+}
+
+#[test]
+fn test_server_accepts_fallback_scsv_at_best_version() {
+    for (client_version, server_versions) in &[
+        (&rustls::version::TLS12, vec![&rustls::version::TLS12]),
+        (
+            &rustls::version::TLS13,
+            vec![&rustls::version::TLS12, &rustls::version::TLS13],
+        ),
+    ] {
+        let client_config = make_client_config_with_versions(KeyType::RSA, &[client_version]);
+        let server_config = make_server_config_with_versions(KeyType::RSA, server_versions);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+        // nb. altering the ClientHello means the handshake can't complete
+        transfer_altered(&mut client, offer_fallback_scsv, &mut server);
+        server.process_new_packets().unwrap();
+        assert_eq!(server.protocol_version(), Some(client_version.version));
+    }
+}
+
+#[test]
+fn test_server_signals_tls12_downgrade_in_random() {
+    const SENTINEL: &[u8] = b"DOWNGRD\x01";
+
+    for (server_versions, expect_sentinel) in &[
+        (vec![&rustls::version::TLS12], false),
+        (vec![&rustls::version::TLS12, &rustls::version::TLS13], true),
+    ] {
+        let client_config =
+            make_client_config_with_versions(KeyType::RSA, &[&rustls::version::TLS12]);
+        let server_config = make_server_config_with_versions(KeyType::RSA, server_versions);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+        let server_random = Mutex::new(None);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer_altered(
+            &mut server,
+            |msg: &mut Message| {
+                if let MessagePayload::Handshake(hs) = &msg.payload {
+                    if let HandshakePayload::ServerHello(sh) = &hs.payload {
+                        *server_random.lock().unwrap() = Some(sh.random.0);
+                    }
+                }
+            },
+            &mut client,
+        );
+
+        let server_random = server_random
+            .into_inner()
+            .unwrap()
+            .unwrap();
+        assert_eq!(&server_random[24..] == SENTINEL, *expect_sentinel);
+    }
+}
+
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn capture_client_hello(
+    client: &mut ClientConnection,
+    server: &mut ServerConnection,
+) -> ClientHelloPayload {
+    let hello = Mutex::new(None);
+    transfer_altered(
+        client,
+        |msg: &mut Message| {
+            if let MessagePayload::Handshake(hs) = &msg.payload {
+                if let HandshakePayload::ClientHello(ch) = &hs.payload {
+                    *hello.lock().unwrap() = Some(ch.get_encoding());
+                }
+            }
+        },
+        server,
+    );
+    let hello = hello.into_inner().unwrap().unwrap();
+    ClientHelloPayload::read_bytes(&hello).unwrap()
+}
+
+#[test]
+fn test_client_sends_grease_when_enabled() {
+    use rustls::internal::msgs::enums::PSKKeyExchangeMode;
+
+    for enable_grease in &[false, true] {
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.enable_grease = *enable_grease;
+        client_config.alpn_protocols = vec![b"h2".to_vec()];
+        let mut server_config = make_server_config(KeyType::RSA);
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+        let ch = capture_client_hello(&mut client, &mut server);
+
+        assert_eq!(is_grease(ch.cipher_suites[0].get_u16()), *enable_grease);
+        let grease_exts = ch
+            .extensions
+            .iter()
+            .filter(|ext| is_grease(ext.get_type().get_u16()))
+            .count();
+        assert_eq!(grease_exts, if *enable_grease { 2 } else { 0 });
+
+        for ext in &ch.extensions {
+            let first_is_grease = match ext {
+                ClientExtension::SupportedVersions(versions) => is_grease(versions[0].get_u16()),
+                ClientExtension::NamedGroups(groups) => is_grease(groups[0].get_u16()),
+                ClientExtension::SignatureAlgorithms(schemes) => is_grease(schemes[0].get_u16()),
+                ClientExtension::KeyShare(shares) => {
+                    assert_eq!(shares.len(), if *enable_grease { 2 } else { 1 });
+                    is_grease(shares[0].group.get_u16())
+                }
+                ClientExtension::Protocols(protocols) => {
+                    protocols[0].0.len() == 2
+                        && is_grease(u16::from_be_bytes([protocols[0].0[0], protocols[0].0[1]]))
+                }
+                ClientExtension::PresharedKeyModes(modes) => {
+                    modes[0] != PSKKeyExchangeMode::PSK_DHE_KE
+                }
+                _ => continue,
+            };
+            assert_eq!(first_is_grease, *enable_grease, "in {:?}", ext);
+        }
+
+        server.process_new_packets().unwrap();
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(server.alpn_protocol(), Some(&b"h2"[..]));
+    }
+}
+
+#[test]
+fn test_server_ignores_grease() {
+    for kt in ALL_KEY_TYPES.iter() {
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(*kt, &[version]);
+            client_config.enable_grease = true;
+            client_config.alpn_protocols = vec![b"h2".to_vec()];
+            let client_config = Arc::new(client_config);
+            let mut server_config = make_server_config(*kt);
+            server_config.alpn_protocols = vec![b"h2".to_vec()];
+            let server_config = Arc::new(server_config);
+
+            // Once for a full handshake, and again to resume.
+            for _ in 0..2 {
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&client_config, &server_config);
+                do_handshake(&mut client, &mut server);
+                assert_eq!(client.protocol_version(), Some(version.version));
+                assert_eq!(server.alpn_protocol(), Some(&b"h2"[..]));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_grease_client_handles_hello_retry_request() {
+    let mut client_config = make_client_config_with_kx_groups(
+        KeyType::RSA,
+        &[&rustls::kx_group::X25519, &rustls::kx_group::SECP384R1],
+    );
+    client_config.enable_grease = true;
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::RSA, &[&rustls::kx_group::SECP384R1]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    let first = capture_client_hello(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    let second = capture_client_hello(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+
+    assert!(is_grease(first.cipher_suites[0].get_u16()));
+    assert_eq!(first.cipher_suites, second.cipher_suites);
+    assert_eq!(
+        first.get_namedgroups_extension(),
+        second.get_namedgroups_extension()
+    );
+    assert_eq!(
+        second
+            .get_keyshare_extension()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_client_hello_is_padded_past_problem_lengths() {
+    use rustls::internal::msgs::enums::ExtensionType;
+
+    let server_config = Arc::new(make_server_config(KeyType::RSA));
+    let mut padded = 0;
+
+    for alpn_len in (0..255).step_by(3) {
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.alpn_protocols = vec![vec![b'a'; alpn_len + 1], vec![b'b'; alpn_len + 1]];
+        let client_config = Arc::new(client_config);
+
+        // Once for a full handshake, and again to resume, when the
+        // padding must be covered by the PSK binder.
+        for _ in 0..2 {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            let ch = capture_client_hello(&mut client, &mut server);
+            let len = 4 + ch.get_encoding().len();
+            assert!(!(256..512).contains(&len), "hello of {} bytes", len);
+            if let Some(ClientExtension::Padding(padding)) =
+                ch.find_extension(ExtensionType::Padding)
+            {
+                assert!((256..512).contains(&(len - 4 - padding)));
+                padded += 1;
+            }
+
+            server.process_new_packets().unwrap();
+            do_handshake(&mut client, &mut server);
+        }
+    }
+
+    assert!(padded > 0);
+}
+
+/// Feed everything `client` wants to write into `acceptor`.
+fn transfer_to_acceptor(client: &mut ClientConnection, acceptor: &mut Acceptor) {
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let mut rd = &buf[..];
+    while !rd.is_empty() {
+        acceptor.read_tls(&mut rd).unwrap();
+    }
+}
+
+#[test]
+fn test_acceptor_chooses_config_from_client_hello() {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.alpn_protocols = vec![b"tenant".to_vec()];
+    let mut client = ClientConnection::new(Arc::new(client_config), dns_name("localhost")).unwrap();
+
+    let mut acceptor = Acceptor::new().unwrap();
+    assert!(acceptor.wants_read());
+    assert!(acceptor.accept().unwrap().is_none());
+
+    transfer_to_acceptor(&mut client, &mut acceptor);
+    let accepted: Accepted = acceptor.accept().unwrap().unwrap();
+    assert!(!acceptor.wants_read());
+    assert!(acceptor
+        .read_tls(&mut &b"more"[..])
+        .is_err());
+    assert!(acceptor
+        .write_tls(&mut Vec::new())
+        .is_err());
+    assert!(acceptor.accept().is_err());
+
+    let hello = accepted.client_hello();
+    assert_eq!(hello.server_name(), Some("localhost"));
+    assert_eq!(hello.alpn(), Some(&[&b"tenant"[..]][..]));
+    assert!(hello
+        .signature_schemes()
+        .contains(&SignatureScheme::RSA_PSS_SHA256));
+
+    let server_config = match hello.server_name() {
+        Some("localhost") => {
+            let mut config =
+                make_server_config_with_versions(KeyType::RSA, &[&rustls::version::TLS12]);
+            config.alpn_protocols = vec![b"tenant".to_vec()];
+            config
+        }
+        _ => make_server_config(KeyType::RSA),
+    };
+    let mut server = accepted
+        .into_connection(Arc::new(server_config))
+        .unwrap();
+
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.sni_hostname(), Some("localhost"));
+    assert_eq!(server.alpn_protocol(), Some(&b"tenant"[..]));
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_2));
+}
+
+#[test]
+fn test_acceptor_waits_for_complete_client_hello() {
+    let mut client = ClientConnection::new(
+        Arc::new(make_client_config(KeyType::ECDSA)),
+        dns_name("localhost"),
+    )
+    .unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::new().unwrap();
+    let (first, last) = buf.split_at(buf.len() - 1);
+    for byte in first.chunks(100) {
+        acceptor
+            .read_tls(&mut &byte[..])
+            .unwrap();
+        assert!(acceptor.accept().unwrap().is_none());
+    }
+    acceptor
+        .read_tls(&mut &last[..])
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+
+    let mut server = accepted
+        .into_connection(Arc::new(make_server_config(KeyType::ECDSA)))
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+}
+
+#[test]
+fn test_acceptor_rejects_non_client_hello() {
+    let mut acceptor = Acceptor::new().unwrap();
+    // an application data record
+    acceptor
+        .read_tls(&mut &[0x17, 0x03, 0x03, 0x00, 0x01, 0x00][..])
+        .unwrap();
+    assert!(matches!(
+        acceptor.accept(),
+        Err(Error::InappropriateMessage { .. })
+    ));
+    assert!(acceptor.accept().is_err());
+
+    let mut alert = Vec::new();
+    acceptor.write_tls(&mut alert).unwrap();
+    assert_eq!(alert[0], 0x15);
+    assert_eq!(&alert[alert.len() - 2..], &[0x02, 0x0a]);
+
+    // the server's half of a handshake
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::new().unwrap();
+    acceptor
+        .read_tls(&mut &buf[..])
+        .unwrap();
+    assert!(matches!(
+        acceptor.accept(),
+        Err(Error::InappropriateHandshakeMessage { .. })
+    ));
+}
@@ -1,26 +1,37 @@
 use std::convert::{TryFrom, TryInto};
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
 use rustls::{
     self, client_config_builder_with_safe_defaults, server_config_builder_with_safe_defaults,
 };
+use rustls::crypto::ring::RING;
+use rustls::crypto::CryptoProvider;
+use rustls::sign;
 use rustls_pemfile;
 
 use rustls::internal::msgs::codec::Reader;
 use rustls::internal::msgs::message::{Message, OpaqueMessage, PlainMessage};
 use rustls::Connection;
+use rustls::DelegatedCredential;
 use rustls::Error;
-use rustls::{AllowAnyAuthenticatedClient, RootCertStore};
+use rustls::{AllowAnyAuthenticatedClient, PinnedPublicKeys, RevocationPolicy, RootCertStore};
 use rustls::{Certificate, PrivateKey};
+use rustls::{CertificateCompressor, CertificateDecompressor};
 use rustls::{ClientConfig, ClientConnection};
-use rustls::{ServerConfig, ServerConnection};
+use rustls::{ExternalPsk, PSKKeyExchangeMode, PskMemoryStore};
+use rustls::{CipherSuite, SignatureScheme, SupportedCipherSuite, ALL_CIPHERSUITES};
+use rustls::{ClientHello, ResolvesServerCert, ServerConfig, ServerConnection};
+
+#[cfg(feature = "static-x25519")]
+use rustls::{EchConfig, EchKey};
 
 #[cfg(feature = "dangerous_configuration")]
 use rustls::{
     internal::msgs::handshake::DigitallySignedStruct, ClientCertVerified, ClientCertVerifier,
     DistinguishedNames, HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
-    SignatureScheme, WebPkiVerifier,
+    WebPkiVerifier,
 };
 
 macro_rules! embed_files {
@@ -53,34 +64,71 @@ embed_files! {
     (ECDSA_CLIENT_CHAIN, "ecdsa", "client.chain");
     (ECDSA_CLIENT_FULLCHAIN, "ecdsa", "client.fullchain");
     (ECDSA_CLIENT_KEY, "ecdsa", "client.key");
+    (ECDSA_CLIENT_P521_FULLCHAIN, "ecdsa", "client.p521.fullchain");
+    (ECDSA_CLIENT_P521_KEY, "ecdsa", "client.p521.key");
     (ECDSA_CLIENT_REQ, "ecdsa", "client.req");
+    (ECDSA_CLIENT_SPKI, "ecdsa", "client.spki");
+    (ECDSA_DC_KEY, "ecdsa", "dc.key");
+    (ECDSA_DC_SPKI, "ecdsa", "dc.spki");
     (ECDSA_END_CERT, "ecdsa", "end.cert");
     (ECDSA_END_CHAIN, "ecdsa", "end.chain");
+    (ECDSA_END_DELEGATED_OCSP, "ecdsa", "end.delegated.ocsp");
+    (ECDSA_END_DELEGATION_CERT, "ecdsa", "end.delegation.cert");
+    (ECDSA_END_DELEGATION_FULLCHAIN, "ecdsa", "end.delegation.fullchain");
     (ECDSA_END_FULLCHAIN, "ecdsa", "end.fullchain");
     (ECDSA_END_KEY, "ecdsa", "end.key");
+    (ECDSA_END_MUSTSTAPLE_CERT, "ecdsa", "end.muststaple.cert");
+    (ECDSA_END_MUSTSTAPLE_FULLCHAIN, "ecdsa", "end.muststaple.fullchain");
+    (ECDSA_END_OCSP, "ecdsa", "end.ocsp");
+    (ECDSA_END_P521_FULLCHAIN, "ecdsa", "end.p521.fullchain");
+    (ECDSA_END_P521_KEY, "ecdsa", "end.p521.key");
     (ECDSA_END_REQ, "ecdsa", "end.req");
+    (ECDSA_END_REVOKED_OCSP, "ecdsa", "end.revoked.ocsp");
+    (ECDSA_END_SPKI, "ecdsa", "end.spki");
     (ECDSA_INTER_CERT, "ecdsa", "inter.cert");
     (ECDSA_INTER_KEY, "ecdsa", "inter.key");
     (ECDSA_INTER_REQ, "ecdsa", "inter.req");
+    (ECDSA_OCSP_CERT, "ecdsa", "ocsp.cert");
+    (ECDSA_OCSP_KEY, "ecdsa", "ocsp.key");
+    (ECDSA_OCSP_REQ, "ecdsa", "ocsp.req");
     (ECDSA_NISTP256_PEM, "ecdsa", "nistp256.pem");
     (ECDSA_NISTP384_PEM, "ecdsa", "nistp384.pem");
+    (ECDSA_NISTP521_PEM, "ecdsa", "nistp521.pem");
 
     (EDDSA_CA_CERT, "eddsa", "ca.cert");
     (EDDSA_CA_DER, "eddsa", "ca.der");
     (EDDSA_CA_KEY, "eddsa", "ca.key");
     (EDDSA_CLIENT_CERT, "eddsa", "client.cert");
     (EDDSA_CLIENT_CHAIN, "eddsa", "client.chain");
+    (EDDSA_CLIENT_ED448_FULLCHAIN, "eddsa", "client.ed448.fullchain");
+    (EDDSA_CLIENT_ED448_KEY, "eddsa", "client.ed448.key");
     (EDDSA_CLIENT_FULLCHAIN, "eddsa", "client.fullchain");
     (EDDSA_CLIENT_KEY, "eddsa", "client.key");
     (EDDSA_CLIENT_REQ, "eddsa", "client.req");
+    (EDDSA_CLIENT_SPKI, "eddsa", "client.spki");
+    (EDDSA_DC_KEY, "eddsa", "dc.key");
+    (EDDSA_DC_SPKI, "eddsa", "dc.spki");
     (EDDSA_END_CERT, "eddsa", "end.cert");
     (EDDSA_END_CHAIN, "eddsa", "end.chain");
+    (EDDSA_END_DELEGATED_OCSP, "eddsa", "end.delegated.ocsp");
+    (EDDSA_END_DELEGATION_CERT, "eddsa", "end.delegation.cert");
+    (EDDSA_END_DELEGATION_FULLCHAIN, "eddsa", "end.delegation.fullchain");
+    (EDDSA_END_ED448_FULLCHAIN, "eddsa", "end.ed448.fullchain");
+    (EDDSA_END_ED448_KEY, "eddsa", "end.ed448.key");
     (EDDSA_END_FULLCHAIN, "eddsa", "end.fullchain");
     (EDDSA_END_KEY, "eddsa", "end.key");
+    (EDDSA_END_MUSTSTAPLE_CERT, "eddsa", "end.muststaple.cert");
+    (EDDSA_END_MUSTSTAPLE_FULLCHAIN, "eddsa", "end.muststaple.fullchain");
+    (EDDSA_END_OCSP, "eddsa", "end.ocsp");
     (EDDSA_END_REQ, "eddsa", "end.req");
+    (EDDSA_END_REVOKED_OCSP, "eddsa", "end.revoked.ocsp");
+    (EDDSA_END_SPKI, "eddsa", "end.spki");
     (EDDSA_INTER_CERT, "eddsa", "inter.cert");
     (EDDSA_INTER_KEY, "eddsa", "inter.key");
     (EDDSA_INTER_REQ, "eddsa", "inter.req");
+    (EDDSA_OCSP_CERT, "eddsa", "ocsp.cert");
+    (EDDSA_OCSP_KEY, "eddsa", "ocsp.key");
+    (EDDSA_OCSP_REQ, "eddsa", "ocsp.req");
 
     (RSA_CA_CERT, "rsa", "ca.cert");
     (RSA_CA_DER, "rsa", "ca.der");
@@ -90,16 +138,30 @@ embed_files! {
     (RSA_CLIENT_FULLCHAIN, "rsa", "client.fullchain");
     (RSA_CLIENT_KEY, "rsa", "client.key");
     (RSA_CLIENT_REQ, "rsa", "client.req");
+    (RSA_CLIENT_SPKI, "rsa", "client.spki");
     (RSA_CLIENT_RSA, "rsa", "client.rsa");
+    (RSA_DC_KEY, "rsa", "dc.key");
+    (RSA_DC_SPKI, "rsa", "dc.spki");
     (RSA_END_CERT, "rsa", "end.cert");
     (RSA_END_CHAIN, "rsa", "end.chain");
+    (RSA_END_DELEGATED_OCSP, "rsa", "end.delegated.ocsp");
+    (RSA_END_DELEGATION_CERT, "rsa", "end.delegation.cert");
+    (RSA_END_DELEGATION_FULLCHAIN, "rsa", "end.delegation.fullchain");
     (RSA_END_FULLCHAIN, "rsa", "end.fullchain");
     (RSA_END_KEY, "rsa", "end.key");
+    (RSA_END_MUSTSTAPLE_CERT, "rsa", "end.muststaple.cert");
+    (RSA_END_MUSTSTAPLE_FULLCHAIN, "rsa", "end.muststaple.fullchain");
+    (RSA_END_OCSP, "rsa", "end.ocsp");
     (RSA_END_REQ, "rsa", "end.req");
+    (RSA_END_REVOKED_OCSP, "rsa", "end.revoked.ocsp");
+    (RSA_END_SPKI, "rsa", "end.spki");
     (RSA_END_RSA, "rsa", "end.rsa");
     (RSA_INTER_CERT, "rsa", "inter.cert");
     (RSA_INTER_KEY, "rsa", "inter.key");
     (RSA_INTER_REQ, "rsa", "inter.req");
+    (RSA_OCSP_CERT, "rsa", "ocsp.cert");
+    (RSA_OCSP_KEY, "rsa", "ocsp.key");
+    (RSA_OCSP_REQ, "rsa", "ocsp.req");
 }
 
 pub fn transfer(left: &mut dyn Connection, right: &mut dyn Connection) -> usize {
@@ -203,6 +265,63 @@ impl KeyType {
         )
     }
 
+    pub fn get_must_staple_chain(&self) -> Vec<Certificate> {
+        rustls_pemfile::certs(&mut io::BufReader::new(
+            self.bytes_for("end.muststaple.fullchain"),
+        ))
+        .unwrap()
+        .iter()
+        .map(|v| Certificate(v.clone()))
+        .collect()
+    }
+
+    /// Returns a stapled OCSP response for the end-entity certificate.
+    /// `status` is "good", "revoked" or "delegated" (good, but signed
+    /// by a delegated responder).
+    pub fn get_ocsp_response(&self, status: &str) -> Vec<u8> {
+        match status {
+            "good" => self.bytes_for("end.ocsp"),
+            "revoked" => self.bytes_for("end.revoked.ocsp"),
+            "delegated" => self.bytes_for("end.delegated.ocsp"),
+            _ => panic!("unknown OCSP status {}", status),
+        }
+        .to_vec()
+    }
+
+    /// Returns a variant of the end-entity chain whose certificate may
+    /// issue delegated credentials.
+    pub fn get_delegation_chain(&self) -> Vec<Certificate> {
+        rustls_pemfile::certs(&mut io::BufReader::new(
+            self.bytes_for("end.delegation.fullchain"),
+        ))
+        .unwrap()
+        .iter()
+        .map(|v| Certificate(v.clone()))
+        .collect()
+    }
+
+    /// Returns a key for a delegated credential, and its DER-encoded
+    /// SubjectPublicKeyInfo.
+    pub fn get_delegated_credential_key(&self) -> (PrivateKey, Vec<u8>) {
+        let key =
+            rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(self.bytes_for("dc.key")))
+                .unwrap()[0]
+                .clone();
+        (PrivateKey(key), self.bytes_for("dc.spki").to_vec())
+    }
+
+    /// Returns the DER-encoded SubjectPublicKeyInfo of the key from
+    /// `get_key`, for use as a raw public key.
+    pub fn get_spki(&self) -> Vec<u8> {
+        self.bytes_for("end.spki").to_vec()
+    }
+
+    /// Returns the DER-encoded SubjectPublicKeyInfo of the key from
+    /// `get_client_key`, for use as a raw public key.
+    pub fn get_client_spki(&self) -> Vec<u8> {
+        self.bytes_for("client.spki").to_vec()
+    }
+
     pub fn get_client_chain(&self) -> Vec<Certificate> {
         rustls_pemfile::certs(&mut io::BufReader::new(self.bytes_for("client.fullchain")))
             .unwrap()
@@ -211,7 +330,7 @@ impl KeyType {
             .collect()
     }
 
-    fn get_client_key(&self) -> PrivateKey {
+    pub fn get_client_key(&self) -> PrivateKey {
         PrivateKey(
             rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(
                 self.bytes_for("client.key"),
@@ -222,6 +341,25 @@ impl KeyType {
     }
 }
 
+/// The members of `ALL_CIPHERSUITES` which the default provider supports.
+pub fn supported_cipher_suites() -> Vec<SupportedCipherSuite> {
+    ALL_CIPHERSUITES
+        .iter()
+        .copied()
+        .filter(|scs| scs.supported_by(&RING))
+        .collect()
+}
+
+pub fn find_suite(suite: CipherSuite) -> SupportedCipherSuite {
+    for scs in ALL_CIPHERSUITES.iter().copied() {
+        if scs.suite() == suite {
+            return scs;
+        }
+    }
+
+    panic!("find_suite given unsupported suite");
+}
+
 pub fn finish_server_config(kt: KeyType, conf: rustls::ConfigWantsClientVerifier) -> ServerConfig {
     conf.with_no_client_auth()
         .with_single_cert(kt.get_chain(), kt.get_key())
@@ -262,6 +400,21 @@ pub fn make_server_config_with_kx_groups(
     )
 }
 
+pub fn make_server_config_with_suites(
+    kt: KeyType,
+    suites: &[rustls::SupportedCipherSuite],
+) -> ServerConfig {
+    finish_server_config(
+        kt,
+        rustls::config_builder()
+            .with_cipher_suites(suites)
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .for_server()
+            .unwrap(),
+    )
+}
+
 pub fn get_client_root_store(kt: KeyType) -> RootCertStore {
     let roots = kt.get_chain();
     let mut client_auth_roots = RootCertStore::empty();
@@ -326,6 +479,19 @@ pub fn make_client_config_with_kx_groups(
     finish_client_config(kt, builder)
 }
 
+pub fn make_client_config_with_suites(
+    kt: KeyType,
+    suites: &[rustls::SupportedCipherSuite],
+) -> ClientConfig {
+    let builder = rustls::config_builder()
+        .with_cipher_suites(suites)
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()
+        .for_client()
+        .unwrap();
+    finish_client_config(kt, builder)
+}
+
 pub fn make_client_config_with_versions(
     kt: KeyType,
     versions: &[&'static rustls::SupportedProtocolVersion],
@@ -356,6 +522,234 @@ pub fn make_client_config_with_versions_with_auth(
     finish_client_config_with_creds(kt, builder)
 }
 
+pub fn make_early_data_configs(max_early_data_size: u32) -> (Arc<ClientConfig>, ServerConfig) {
+    let kt = KeyType::RSA;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.enable_early_data = true;
+
+    let mut server_config = make_server_config(kt);
+    server_config.max_early_data_size = max_early_data_size;
+
+    (Arc::new(client_config), server_config)
+}
+
+pub fn make_early_data_configs_with_alpn(
+    client_alpn: &[&[u8]],
+    server_alpn: &[&[u8]],
+) -> (ClientConfig, ServerConfig) {
+    let (client_config, mut server_config) = make_early_data_configs(128);
+    let mut client_config = (*client_config).clone();
+    client_config.alpn_protocols = client_alpn
+        .iter()
+        .map(|p| p.to_vec())
+        .collect();
+    server_config.alpn_protocols = server_alpn
+        .iter()
+        .map(|p| p.to_vec())
+        .collect();
+    (client_config, server_config)
+}
+
+pub fn make_server_config_with_ocsp(
+    kt: KeyType,
+    chain: Vec<Certificate>,
+    ocsp: Vec<u8>,
+) -> ServerConfig {
+    server_config_builder_with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert_with_ocsp_and_sct(chain, kt.get_key(), ocsp, vec![])
+        .unwrap()
+}
+
+pub fn make_client_config_with_revocation_policy(
+    kt: KeyType,
+    version: &'static rustls::SupportedProtocolVersion,
+    policy: RevocationPolicy,
+) -> ClientConfig {
+    let builder = rustls::config_builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[version])
+        .for_client()
+        .unwrap()
+        .with_revocation_policy(policy);
+    finish_client_config(kt, builder)
+}
+
+pub fn make_server_config_with_cert_compressors(
+    kt: KeyType,
+    compressors: Vec<Arc<dyn CertificateCompressor>>,
+) -> ServerConfig {
+    let mut server_config = make_server_config(kt);
+    server_config.cert_compressors = compressors;
+    server_config
+}
+
+pub fn make_client_config_with_cert_decompressors(
+    kt: KeyType,
+    decompressors: Vec<Arc<dyn CertificateDecompressor>>,
+) -> ClientConfig {
+    let mut client_config = make_client_config(kt);
+    client_config.cert_decompressors = decompressors;
+    client_config
+}
+
+#[cfg(feature = "static-x25519")]
+pub fn make_ech_key(config_id: u8, private_key: u8) -> EchKey {
+    EchKey::new(config_id, "testserver.com", &[private_key; 32]).unwrap()
+}
+
+#[cfg(feature = "static-x25519")]
+pub fn make_ech_client_config(kt: KeyType, key: &EchKey) -> ClientConfig {
+    let mut client_config = make_client_config(kt);
+    client_config.ech_config = Some(EchConfig::new(&key.config_list()).unwrap());
+    client_config
+}
+
+/// A resolver which always supplies the same `CertifiedKey`.
+pub struct AlwaysResolves(pub Arc<sign::CertifiedKey>);
+
+impl ResolvesServerCert for AlwaysResolves {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+pub fn make_delegated_credential(
+    kt: KeyType,
+    valid_for: Duration,
+) -> Result<DelegatedCredential, Error> {
+    let end_entity_key = sign::any_supported_type(&kt.get_key()).unwrap();
+    let (key, public_key) = kt.get_delegated_credential_key();
+    DelegatedCredential::new(
+        &kt.get_delegation_chain()[0],
+        end_entity_key.as_ref(),
+        public_key,
+        sign::any_supported_type(&key).unwrap(),
+        valid_for,
+        RING.signature_schemes(),
+    )
+}
+
+pub fn make_server_config_with_delegated_credential(
+    kt: KeyType,
+    key: Arc<dyn sign::SigningKey>,
+    dc: DelegatedCredential,
+) -> ServerConfig {
+    let mut certified_key = sign::CertifiedKey::new(kt.get_delegation_chain(), key);
+    certified_key.delegated_credential = Some(dc);
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(AlwaysResolves(Arc::new(certified_key)));
+    server_config
+}
+
+pub fn make_raw_public_key_client_config(kt: KeyType, pinned: Vec<u8>) -> ClientConfig {
+    client_config_builder_with_safe_defaults()
+        .with_pinned_public_keys(vec![pinned])
+        .with_raw_public_key(kt.get_client_spki(), kt.get_client_key())
+        .unwrap()
+}
+
+pub fn make_raw_public_key_server_config(kt: KeyType, pinned: Vec<u8>) -> ServerConfig {
+    server_config_builder_with_safe_defaults()
+        .with_client_cert_verifier(PinnedPublicKeys::new(vec![pinned]))
+        .with_raw_public_key(kt.get_spki(), kt.get_key())
+        .unwrap()
+}
+
+pub fn make_psk(identity: &[u8], secret: u8) -> ExternalPsk {
+    ExternalPsk::new(identity.to_vec(), vec![secret; 32]).unwrap()
+}
+
+pub fn make_psk_client_config(
+    suites: &[rustls::SupportedCipherSuite],
+    psks: Vec<ExternalPsk>,
+    modes: &[PSKKeyExchangeMode],
+) -> ClientConfig {
+    let mut config = rustls::config_builder()
+        .with_cipher_suites(suites)
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()
+        .for_client()
+        .unwrap()
+        .with_root_certificates(RootCertStore::empty(), &[])
+        .with_no_client_auth();
+    config.psk_store = Some(PskMemoryStore::new(psks));
+    config.psk_key_exchange_modes = modes.to_vec();
+    config
+}
+
+pub fn make_psk_server_config(
+    psks: Vec<ExternalPsk>,
+    modes: &[PSKKeyExchangeMode],
+) -> ServerConfig {
+    let mut config = server_config_builder_with_safe_defaults()
+        .with_no_client_auth()
+        .with_external_psks(PskMemoryStore::new(psks));
+    config.psk_key_exchange_modes = modes.to_vec();
+    config
+}
+
+pub fn make_server_config_with_signature_schemes_and_client_auth(
+    kt: KeyType,
+    schemes: &[SignatureScheme],
+) -> ServerConfig {
+    rustls::config_builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()
+        .with_signature_schemes(schemes)
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(get_client_root_store(kt)))
+        .with_single_cert(kt.get_chain(), kt.get_key())
+        .unwrap()
+}
+
+pub fn make_client_config_with_signature_schemes(
+    kt: KeyType,
+    schemes: &[SignatureScheme],
+) -> ClientConfig {
+    let builder = rustls::config_builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .with_signature_schemes(schemes)
+        .for_client()
+        .unwrap();
+    finish_client_config(kt, builder)
+}
+
+#[cfg(feature = "ffdhe")]
+pub fn make_tls12_dhe_client_config(
+    suites: &[rustls::SupportedCipherSuite],
+    kx_groups: &[&'static rustls::SupportedKxGroup],
+) -> ClientConfig {
+    finish_client_config(
+        KeyType::RSA,
+        rustls::config_builder()
+            .with_cipher_suites(suites)
+            .with_kx_groups(kx_groups)
+            .with_protocol_versions(&[&rustls::version::TLS12])
+            .for_client()
+            .unwrap(),
+    )
+}
+
+#[cfg(feature = "ffdhe")]
+pub fn make_dhe_server_config(kx_groups: &[&'static rustls::SupportedKxGroup]) -> ServerConfig {
+    finish_server_config(
+        KeyType::RSA,
+        rustls::config_builder()
+            .with_cipher_suites(&supported_cipher_suites())
+            .with_kx_groups(kx_groups)
+            .with_safe_default_protocol_versions()
+            .for_server()
+            .unwrap(),
+    )
+}
+
 pub fn make_pair(kt: KeyType) -> (ClientConnection, ServerConnection) {
     make_pair_for_configs(make_client_config(kt), make_server_config(kt))
 }
@@ -377,6 +771,26 @@ pub fn make_pair_for_arc_configs(
     )
 }
 
+pub fn make_pair_for_suite(
+    version: &'static rustls::SupportedProtocolVersion,
+    kt: KeyType,
+    suite: CipherSuite,
+) -> (ClientConnection, ServerConnection) {
+    let client_config = finish_client_config(
+        kt,
+        rustls::config_builder()
+            .with_cipher_suites(&[find_suite(suite)])
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[version])
+            .for_client()
+            .unwrap(),
+    );
+    make_pair_for_configs(
+        client_config,
+        make_server_config_with_suites(kt, &supported_cipher_suites()),
+    )
+}
+
 pub fn do_handshake(
     client: &mut ClientConnection,
     server: &mut ServerConnection,
@@ -425,6 +839,7 @@ impl ServerCertVerifier for MockServerVerifier {
 
     fn verify_tls12_signature(
         &self,
+        _provider: &dyn CryptoProvider,
         message: &[u8],
         cert: &Certificate,
         dss: &DigitallySignedStruct,
@@ -442,6 +857,7 @@ impl ServerCertVerifier for MockServerVerifier {
 
     fn verify_tls13_signature(
         &self,
+        _provider: &dyn CryptoProvider,
         message: &[u8],
         cert: &Certificate,
         dss: &DigitallySignedStruct,
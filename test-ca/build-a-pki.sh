@@ -47,9 +47,18 @@ openssl rsa \
           -in rsa/client.key \
           -out rsa/client.rsa
 
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout rsa/ocsp.key \
+          -out rsa/ocsp.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown RSA OCSP responder"
+
 # ecdsa
 openssl ecparam -name prime256v1 -out ecdsa/nistp256.pem
 openssl ecparam -name secp384r1 -out ecdsa/nistp384.pem
+openssl ecparam -name secp521r1 -out ecdsa/nistp521.pem
 
 openssl req -nodes \
           -x509 \
@@ -88,10 +97,37 @@ openssl req -nodes \
           -days 2000 \
           -subj "/CN=ponytown client"
 
+openssl req -nodes \
+          -newkey ec:ecdsa/nistp256.pem \
+          -keyout ecdsa/ocsp.key \
+          -out ecdsa/ocsp.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown ECDSA OCSP responder"
+
+# P-521 end-entity and client keys, issued by the ECDSA intermediate
+openssl req -nodes \
+          -newkey ec:ecdsa/nistp521.pem \
+          -keyout ecdsa/end.p521.key \
+          -out ecdsa/end.p521.req \
+          -sha256 \
+          -batch \
+          -days 2000 \
+          -subj "/CN=testserver.com"
+
+openssl req -nodes \
+          -newkey ec:ecdsa/nistp521.pem \
+          -keyout ecdsa/client.p521.key \
+          -out ecdsa/client.p521.req \
+          -sha256 \
+          -batch \
+          -days 2000 \
+          -subj "/CN=ponytown client"
+
 # eddsa
 
-# TODO: add support for Ed448
-# openssl genpkey -algorithm Ed448 -out eddsa/ca.key
+# webpki cannot verify Ed448 signatures on certificates, so Ed448 is only
+# used for the end.ed448 and client.ed448 keys below.
 openssl genpkey -algorithm Ed25519 -out eddsa/ca.key
 
 openssl req -nodes \
@@ -123,8 +159,6 @@ openssl req -nodes \
           -batch \
           -subj "/CN=testserver.com"
 
-# TODO: add support for Ed448
-# openssl genpkey -algorithm Ed448 -out eddsa/client.key
 openssl genpkey -algorithm Ed25519 -out eddsa/client.key
 
 openssl req -nodes \
@@ -135,6 +169,40 @@ openssl req -nodes \
           -batch \
           -subj "/CN=ponytown client"
 
+openssl genpkey -algorithm Ed25519 -out eddsa/ocsp.key
+
+openssl req -nodes \
+          -new \
+          -key eddsa/ocsp.key \
+          -out eddsa/ocsp.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown EdDSA OCSP responder"
+
+# Ed448 end-entity and client keys, issued by the EdDSA intermediate
+openssl genpkey -algorithm Ed448 -out eddsa/end.ed448.key
+
+openssl req -nodes \
+          -new \
+          -key eddsa/end.ed448.key \
+          -out eddsa/end.ed448.req \
+          -batch \
+          -subj "/CN=testserver.com"
+
+openssl genpkey -algorithm Ed448 -out eddsa/client.ed448.key
+
+openssl req -nodes \
+          -new \
+          -key eddsa/client.ed448.key \
+          -out eddsa/client.ed448.req \
+          -batch \
+          -subj "/CN=ponytown client"
+
+# keys for delegated credentials (RFC9345) issued by each end-entity
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out rsa/dc.key
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-384 -out ecdsa/dc.key
+openssl genpkey -algorithm Ed25519 -out eddsa/dc.key
+
 for kt in rsa ecdsa eddsa ; do
   openssl x509 -req \
             -in $kt/inter.req \
@@ -173,4 +241,129 @@ for kt in rsa ecdsa eddsa ; do
   cat $kt/client.cert $kt/inter.cert $kt/ca.cert > $kt/client.fullchain
 
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
+
+  # a must-staple variant of the end-entity certificate, and stapled
+  # OCSP responses for end.cert: good and revoked (both signed by the
+  # issuer), and good signed by a delegated responder.
+  openssl x509 -req \
+            -in $kt/end.req \
+            -out $kt/end.muststaple.cert \
+            -CA $kt/inter.cert \
+            -CAkey $kt/inter.key \
+            -sha256 \
+            -days 2000 \
+            -set_serial 457 \
+            -extensions v3_end_must_staple -extfile openssl.cnf
+
+  cat $kt/end.muststaple.cert $kt/inter.cert $kt/ca.cert > $kt/end.muststaple.fullchain
+
+  # a variant of the end-entity certificate which may issue delegated
+  # credentials.
+  openssl x509 -req \
+            -in $kt/end.req \
+            -out $kt/end.delegation.cert \
+            -CA $kt/inter.cert \
+            -CAkey $kt/inter.key \
+            -sha256 \
+            -days 2000 \
+            -set_serial 458 \
+            -extensions v3_end_delegation -extfile openssl.cnf
+
+  cat $kt/end.delegation.cert $kt/inter.cert $kt/ca.cert > $kt/end.delegation.fullchain
+
+  openssl pkey -in $kt/dc.key -pubout -outform DER -out $kt/dc.spki
+
+  # raw public keys (RFC7250)
+  openssl pkey -in $kt/end.key -pubout -outform DER -out $kt/end.spki
+  openssl pkey -in $kt/client.key -pubout -outform DER -out $kt/client.spki
+
+  openssl x509 -req \
+            -in $kt/ocsp.req \
+            -out $kt/ocsp.cert \
+            -CA $kt/inter.cert \
+            -CAkey $kt/inter.key \
+            -sha256 \
+            -days 2000 \
+            -set_serial 1011 \
+            -extensions v3_ocsp -extfile openssl.cnf
+
+  openssl ocsp -issuer $kt/inter.cert \
+            -cert $kt/end.cert \
+            -no_nonce \
+            -reqout $kt/end.ocspreq
+
+  expiry=$(openssl x509 -in $kt/end.cert -noout -enddate | cut -d= -f2)
+  expiry=$(date -u -d "$expiry" +%y%m%d%H%M%SZ)
+  printf 'V\t%s\t\t01C8\tunknown\t/CN=testserver.com\n' $expiry > $kt/good.index
+  printf 'R\t%s\t%s\t01C8\tunknown\t/CN=testserver.com\n' $expiry $(date -u +%y%m%d%H%M%SZ) > $kt/revoked.index
+
+  openssl ocsp -index $kt/good.index \
+            -CA $kt/inter.cert \
+            -rsigner $kt/inter.cert \
+            -rkey $kt/inter.key \
+            -reqin $kt/end.ocspreq \
+            -ndays 2000 \
+            -respout $kt/end.ocsp
+
+  openssl ocsp -index $kt/revoked.index \
+            -CA $kt/inter.cert \
+            -rsigner $kt/inter.cert \
+            -rkey $kt/inter.key \
+            -reqin $kt/end.ocspreq \
+            -ndays 2000 \
+            -respout $kt/end.revoked.ocsp
+
+  openssl ocsp -index $kt/good.index \
+            -CA $kt/inter.cert \
+            -rsigner $kt/ocsp.cert \
+            -rkey $kt/ocsp.key \
+            -reqin $kt/end.ocspreq \
+            -ndays 2000 \
+            -respout $kt/end.delegated.ocsp
+
+  rm $kt/end.ocspreq $kt/good.index $kt/revoked.index
 done
+
+openssl x509 -req \
+          -in ecdsa/end.p521.req \
+          -out ecdsa/end.p521.cert \
+          -CA ecdsa/inter.cert \
+          -CAkey ecdsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 459 \
+          -extensions v3_end -extfile openssl.cnf
+
+openssl x509 -req \
+          -in ecdsa/client.p521.req \
+          -out ecdsa/client.p521.cert \
+          -CA ecdsa/inter.cert \
+          -CAkey ecdsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 790 \
+          -extensions v3_client -extfile openssl.cnf
+
+cat ecdsa/end.p521.cert ecdsa/inter.cert ecdsa/ca.cert > ecdsa/end.p521.fullchain
+cat ecdsa/client.p521.cert ecdsa/inter.cert ecdsa/ca.cert > ecdsa/client.p521.fullchain
+
+openssl x509 -req \
+          -in eddsa/end.ed448.req \
+          -out eddsa/end.ed448.cert \
+          -CA eddsa/inter.cert \
+          -CAkey eddsa/inter.key \
+          -days 2000 \
+          -set_serial 460 \
+          -extensions v3_end -extfile openssl.cnf
+
+openssl x509 -req \
+          -in eddsa/client.ed448.req \
+          -out eddsa/client.ed448.cert \
+          -CA eddsa/inter.cert \
+          -CAkey eddsa/inter.key \
+          -days 2000 \
+          -set_serial 791 \
+          -extensions v3_client -extfile openssl.cnf
+
+cat eddsa/end.ed448.cert eddsa/inter.cert eddsa/ca.cert > eddsa/end.ed448.fullchain
+cat eddsa/client.ed448.cert eddsa/inter.cert eddsa/ca.cert > eddsa/client.ed448.fullchain